@@ -206,7 +206,7 @@ impl Gatewayd {
     }
 
     pub async fn list_active_channels(&self) -> Result<Vec<ChannelInfo>> {
-        let channels = cmd!(self, "lightning", "list-active-channels")
+        let channels = cmd!(self, "lightning", "list-active-channels", "--json")
             .out_json()
             .await?;
         let channels = channels
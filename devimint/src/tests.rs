@@ -18,7 +18,7 @@ use fedimint_core::{Amount, BitcoinHash, PeerId};
 use fedimint_ln_client::cli::LnInvoiceResponse;
 use fedimint_logging::LOG_DEVIMINT;
 use hex::ToHex;
-use ln_gateway::rpc::GatewayInfo;
+use ln_gateway::rpc::{GatewayInfo, GatewayStatus};
 use serde_json::json;
 use tokio::net::TcpStream;
 use tokio::time::timeout;
@@ -1716,7 +1716,7 @@ pub async fn gw_reboot_test(dev_fed: DevFed, process_mgr: &ProcessManager) -> Re
             let cln_value = new_cln_cmd.out_json().await.map_err(ControlFlow::Continue)?;
             let reboot_info: GatewayInfo = serde_json::from_value(cln_value).context("json invalid").map_err(ControlFlow::Break)?;
 
-            if reboot_info.gateway_state == "Running" {
+            if matches!(reboot_info.gateway_state, GatewayStatus::Connected { .. }) {
                 info!(target: LOG_DEVIMINT, "CLN Gateway restarted, with auto-rejoin to federation");
                 // Assert that the gateway info is the same as before the reboot
                 if cln_info != reboot_info {
@@ -1737,7 +1737,7 @@ pub async fn gw_reboot_test(dev_fed: DevFed, process_mgr: &ProcessManager) -> Re
             let lnd_value = new_lnd_cmd.out_json().await.map_err(ControlFlow::Continue)?;
             let reboot_info: GatewayInfo = serde_json::from_value(lnd_value).context("json invalid").map_err(ControlFlow::Break)?;
 
-            if reboot_info.gateway_state == "Running" {
+            if matches!(reboot_info.gateway_state, GatewayStatus::Connected { .. }) {
                 info!(target: LOG_DEVIMINT, "LND Gateway restarted, with auto-rejoin to federation");
                 // Assert that the gateway info is the same as before the reboot
                 assert_eq!(lnd_info, reboot_info);
@@ -1,18 +1,19 @@
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::fmt::{self, Debug, Display};
 use std::num::NonZeroUsize;
 use std::ops::Add;
 use std::pin::Pin;
+use std::result;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
-use std::{cmp, result};
 
 use anyhow::anyhow;
 use bitcoin::hashes::sha256;
 use bitcoin::secp256k1;
 use fedimint_core::admin_client::{
-    ConfigGenConnectionsRequest, ConfigGenParamsRequest, ConfigGenParamsResponse, PeerServerParams,
-    ServerStatus,
+    CompactDbRequest, ConfigGenConnectionsRequest, ConfigGenParamsRequest, ConfigGenParamsResponse,
+    CreateInviteCodeRequest, DbPrefixStats, MempoolSummary, PeerServerParams, ServerStatus,
+    SessionTiming, SetLogFilterRequest, SetupParamsValidation, UpgradeStatus,
 };
 use fedimint_core::backup::ClientBackupSnapshot;
 use fedimint_core::config::ClientConfig;
@@ -21,27 +22,36 @@ use fedimint_core::core::{Decoder, DynOutputOutcome, ModuleInstanceId, OutputOut
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::endpoint_constants::{
     ADD_CONFIG_GEN_PEER_ENDPOINT, AUDIT_ENDPOINT, AUTH_ENDPOINT, AWAIT_OUTPUT_OUTCOME_ENDPOINT,
-    AWAIT_SESSION_OUTCOME_ENDPOINT, AWAIT_TRANSACTION_ENDPOINT, BACKUP_ENDPOINT,
-    CONFIG_GEN_PEERS_ENDPOINT, CONSENSUS_CONFIG_GEN_PARAMS_ENDPOINT,
-    DEFAULT_CONFIG_GEN_PARAMS_ENDPOINT, GUARDIAN_CONFIG_BACKUP_ENDPOINT, RECOVER_ENDPOINT,
-    RESTART_FEDERATION_SETUP_ENDPOINT, RUN_DKG_ENDPOINT, SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT,
-    SESSION_COUNT_ENDPOINT, SESSION_STATUS_ENDPOINT, SET_CONFIG_GEN_CONNECTIONS_ENDPOINT,
-    SET_CONFIG_GEN_PARAMS_ENDPOINT, SET_PASSWORD_ENDPOINT, START_CONSENSUS_ENDPOINT,
-    STATUS_ENDPOINT, SUBMIT_TRANSACTION_ENDPOINT, VERIFIED_CONFIGS_ENDPOINT,
+    AWAIT_SESSION_OUTCOME_ENDPOINT, AWAIT_TRANSACTION_ENDPOINT, BACKUP_DATABASE_ENDPOINT,
+    BACKUP_ENDPOINT, CANCEL_UPGRADE_ENDPOINT, COMPACT_DB_ENDPOINT, CONFIG_GEN_PEERS_ENDPOINT,
+    CONSENSUS_CONFIG_GEN_PARAMS_ENDPOINT, CREATE_INVITE_CODE_ENDPOINT, DB_STATS_ENDPOINT,
+    DEFAULT_CONFIG_GEN_PARAMS_ENDPOINT, FEDERATION_STATUS_ENDPOINT,
+    GUARDIAN_CONFIG_BACKUP_ENDPOINT, MEMPOOL_ENDPOINT, PEER_ENDPOINTS_ENDPOINT,
+    PROPOSE_FEDERATION_META_ENDPOINT, RECOVER_ENDPOINT, RESTART_FEDERATION_SETUP_ENDPOINT,
+    ROTATE_TLS_CERT_ENDPOINT, RUN_DKG_ENDPOINT, SCHEDULE_UPGRADE_ENDPOINT,
+    SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT, SESSION_COUNT_ENDPOINT, SESSION_STATUS_ENDPOINT,
+    SESSION_TIMING_ENDPOINT, SET_CONFIG_GEN_CONNECTIONS_ENDPOINT, SET_CONFIG_GEN_PARAMS_ENDPOINT,
+    SET_LOG_FILTER_ENDPOINT, SET_PASSWORD_ENDPOINT, START_CONSENSUS_ENDPOINT, STATUS_ENDPOINT,
+    SUBMIT_TRANSACTION_ENDPOINT, TRANSACTION_STATUS_ENDPOINT, UPDATE_PEER_ENDPOINTS_ENDPOINT,
+    UPGRADE_STATUS_ENDPOINT, VALIDATE_SETUP_PARAMS_ENDPOINT, VERIFIED_CONFIGS_ENDPOINT,
     VERIFY_CONFIG_HASH_ENDPOINT, VERSION_ENDPOINT,
 };
+use fedimint_core::epoch::{FederationMeta, FederationMetaRecord, PeerEndpoints};
 use fedimint_core::fmt_utils::{AbbreviateDebug, AbbreviateJson};
 use fedimint_core::invite_code::InviteCode;
 use fedimint_core::module::audit::AuditSummary;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::{
-    ApiAuth, ApiRequestErased, ApiVersion, SerdeModuleEncoding, SupportedApiVersionsSummary,
+    ApiAuth, ApiError, ApiRequestErased, ApiVersion, SerdeModuleEncoding,
+    SupportedApiVersionsSummary,
 };
 use fedimint_core::session_outcome::{AcceptedItem, SessionOutcome, SessionStatus};
 use fedimint_core::task::jit::JitTryAnyhow;
 use fedimint_core::task::{MaybeSend, MaybeSync};
 use fedimint_core::time::now;
-use fedimint_core::transaction::{SerdeTransaction, Transaction, TransactionSubmissionOutcome};
+use fedimint_core::transaction::{
+    SerdeTransaction, Transaction, TransactionStatus, TransactionSubmissionOutcome,
+};
 use fedimint_core::util::SafeUrl;
 use fedimint_core::{
     apply, async_trait_maybe_send, dyn_newtype_define, runtime, NumPeersExt, OutPoint, PeerId,
@@ -62,13 +72,25 @@ use thiserror::Error;
 use tokio::sync::{Mutex, OnceCell, RwLock};
 use tracing::{debug, error, instrument, trace, warn};
 
+use crate::cache::ResponseCache;
+use crate::metrics::{self, InFlightRequest};
+use crate::peer_score::PeerScores;
 use crate::query::{
-    DiscoverApiVersionSet, QueryStep, QueryStrategy, ThresholdConsensus, UnionResponsesSingle,
+    DiscoverApiVersionSet, FastestResponders, PeerSelection, QueryStep, QueryStrategy,
+    ThresholdConsensus, UnionResponsesSingle,
+};
+use crate::transport::{
+    ClientConnection as _, ClientTransport as _, DirectTransport, DynClientConnection,
+    DynClientTransport,
 };
 
 pub type PeerResult<T> = Result<T, PeerError>;
 pub type JsonRpcResult<T> = Result<T, JsonRpcClientError>;
 pub type FederationResult<T> = Result<T, FederationError>;
+
+/// How long [`FederationApiExt::request_current_consensus`] waits for the
+/// fastest responders before it also queries the next best peer
+const CONSENSUS_EXPAND_AFTER: Duration = Duration::from_secs(1);
 pub type SerdeOutputOutcome = SerdeModuleEncoding<DynOutputOutcome>;
 
 /// An API request error when calling a single federation peer
@@ -85,31 +107,40 @@ pub enum PeerError {
 }
 
 impl PeerError {
+    /// Classifies the error, so callers can handle it without matching on raw
+    /// JSON-RPC error codes
+    pub fn api_error(&self) -> FederationApiError {
+        match self {
+            PeerError::ResponseDeserialization(e) => {
+                FederationApiError::InvalidResponse(e.to_string())
+            }
+            PeerError::InvalidPeerId { peer_id } => {
+                FederationApiError::Client(format!("Invalid peer id: {peer_id}"))
+            }
+            PeerError::Rpc(e) => FederationApiError::from_rpc_error(e),
+            PeerError::InvalidResponse(e) => FederationApiError::InvalidResponse(e.clone()),
+        }
+    }
+
     /// Report errors that are worth reporting
     ///
     /// The goal here is to avoid spamming logs with errors that happen commonly
     /// for all sorts of expected reasons, while printing ones that suggest
     /// there's a problem.
     pub fn report_if_important(&self, peer_id: PeerId) {
-        let important = match self {
-            PeerError::ResponseDeserialization(_) => true,
-            PeerError::InvalidPeerId { peer_id: _ } => true,
-            PeerError::Rpc(rpc_e) => match rpc_e {
-                // TODO: Does this cover all retryable cases?
-                JsonRpcClientError::Transport(_) => false,
-                JsonRpcClientError::MaxSlotsExceeded => true,
-                JsonRpcClientError::RequestTimeout => false,
-                JsonRpcClientError::RestartNeeded(_) => true,
-                JsonRpcClientError::Call(_) => true,
-                JsonRpcClientError::ParseError(_) => true,
-                JsonRpcClientError::InvalidSubscriptionId => true,
-                JsonRpcClientError::InvalidRequestId(_) => true,
-                JsonRpcClientError::Custom(_) => true,
-                JsonRpcClientError::HttpNotImplemented => true,
-                JsonRpcClientError::EmptyBatchRequest(_) => true,
-                JsonRpcClientError::RegisterMethod(_) => true,
-            },
-            PeerError::InvalidResponse(_) => true,
+        let important = match self.api_error() {
+            FederationApiError::Timeout | FederationApiError::Connection(_) => false,
+            // The guardian explicitly asked us to retry later
+            FederationApiError::Unavailable(_) => false,
+            // Expected from guardians with session pruning enabled
+            FederationApiError::Pruned(_) => false,
+            FederationApiError::NotFoundYet(_)
+            | FederationApiError::Unauthorized
+            | FederationApiError::BadRequest(_)
+            | FederationApiError::PeerVersionMismatch(_)
+            | FederationApiError::InvalidResponse(_)
+            | FederationApiError::Server { .. }
+            | FederationApiError::Client(_) => true,
         };
 
         trace!(target: LOG_CLIENT_NET_API, error = %self, "PeerError");
@@ -120,6 +151,82 @@ impl PeerError {
     }
 }
 
+/// JSON-RPC 2.0 error code of a method the server does not know
+const METHOD_NOT_FOUND_CODE: i32 = -32601;
+
+/// Typed classification of the error a single guardian returned
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum FederationApiError {
+    /// The requested data does not exist yet, e.g. a future session
+    #[error("Not found yet: {0}")]
+    NotFoundYet(String),
+    /// The guardian deleted the requested data, e.g. a pruned session, so
+    /// retrying with the same guardian will not help
+    #[error("Pruned: {0}")]
+    Pruned(String),
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+    /// The guardian is overloaded and asked us to retry later
+    #[error("Unavailable: {0}")]
+    Unavailable(String),
+    /// The guardian or the client timed out the request
+    #[error("Request timed out")]
+    Timeout,
+    #[error("Connection error: {0}")]
+    Connection(String),
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+    /// The guardian runs a version that does not support the method
+    #[error("Method not supported by peer: {0}")]
+    PeerVersionMismatch(String),
+    #[error("Server error {code}: {message}")]
+    Server { code: i32, message: String },
+    /// The request could not be sent because of a bug or misuse on our side
+    #[error("Client error: {0}")]
+    Client(String),
+}
+
+impl FederationApiError {
+    pub fn from_rpc_error(error: &JsonRpcClientError) -> Self {
+        match error {
+            JsonRpcClientError::Call(e) => {
+                let message = e.message().to_owned();
+
+                match e.code() {
+                    ApiError::NOT_FOUND => FederationApiError::NotFoundYet(message),
+                    ApiError::GONE => FederationApiError::Pruned(message),
+                    ApiError::UNAUTHORIZED => FederationApiError::Unauthorized,
+                    ApiError::BAD_REQUEST => FederationApiError::BadRequest(message),
+                    ApiError::UNAVAILABLE => FederationApiError::Unavailable(message),
+                    ApiError::TIMEOUT => FederationApiError::Timeout,
+                    METHOD_NOT_FOUND_CODE => FederationApiError::PeerVersionMismatch(message),
+                    code => FederationApiError::Server { code, message },
+                }
+            }
+            JsonRpcClientError::RequestTimeout => FederationApiError::Timeout,
+            JsonRpcClientError::Transport(e) => FederationApiError::Connection(e.to_string()),
+            JsonRpcClientError::RestartNeeded(e) => FederationApiError::Connection(e.to_string()),
+            JsonRpcClientError::ParseError(e) => FederationApiError::InvalidResponse(e.to_string()),
+            JsonRpcClientError::InvalidRequestId(e) => {
+                FederationApiError::InvalidResponse(e.to_string())
+            }
+            e @ (JsonRpcClientError::MaxSlotsExceeded
+            | JsonRpcClientError::InvalidSubscriptionId
+            | JsonRpcClientError::Custom(_)
+            | JsonRpcClientError::HttpNotImplemented
+            | JsonRpcClientError::EmptyBatchRequest(_)
+            | JsonRpcClientError::RegisterMethod(_)) => FederationApiError::Client(e.to_string()),
+        }
+    }
+
+    /// Whether the guardian asked us to send the same request again later
+    pub fn is_retry_later(&self) -> bool {
+        matches!(self, FederationApiError::Unavailable(_))
+    }
+}
+
 /// An API request error when calling an entire federation
 ///
 /// Generally all Federation errors are retriable.
@@ -156,6 +263,16 @@ impl Display for FederationError {
     }
 }
 
+/// General error of a [`FederationError`] if the query deadline passed before
+/// the query strategy returned a result
+#[derive(Debug, Clone, Error)]
+#[error("Query deadline of {deadline:?} exceeded without a response from peers {pending_peers:?}")]
+pub struct QueryTimeout {
+    pub deadline: Duration,
+    /// Peers we were still waiting for when the deadline passed
+    pub pending_peers: BTreeSet<PeerId>,
+}
+
 impl FederationError {
     pub fn general(
         method: impl Into<String>,
@@ -203,6 +320,11 @@ impl FederationError {
     pub fn get_peer_errors(&self) -> impl Iterator<Item = (PeerId, &PeerError)> {
         self.peers.iter().map(|(peer, error)| (*peer, error))
     }
+
+    /// Get the peers that never answered if the query deadline passed.
+    pub fn get_timeout(&self) -> Option<&QueryTimeout> {
+        self.general.as_ref()?.downcast_ref()
+    }
 }
 
 type OutputOutcomeResult<O> = result::Result<O, OutputOutcomeError>;
@@ -275,6 +397,18 @@ pub trait IRawFederationApi: Debug + MaybeSend + MaybeSync {
 
     fn with_module(&self, id: ModuleInstanceId) -> DynModuleApi;
 
+    /// Latency and error rates of the peers, used to route queries to the
+    /// fastest responders. `None` if the implementation does not track them.
+    fn peer_scores(&self) -> Option<&PeerScores> {
+        None
+    }
+
+    /// Cache of verified responses to queries for immutable data. `None` if
+    /// the implementation does not cache responses.
+    fn response_cache(&self) -> Option<&ResponseCache> {
+        None
+    }
+
     /// Make request to a specific federation peer by `peer_id`
     async fn request_raw(
         &self,
@@ -282,6 +416,23 @@ pub trait IRawFederationApi: Debug + MaybeSend + MaybeSync {
         method: &str,
         params: &[Value],
     ) -> result::Result<Value, JsonRpcClientError>;
+
+    /// Make several requests to a specific federation peer, in a single round
+    /// trip if the implementation supports batching. The results are in the
+    /// order of the requests.
+    async fn request_raw_batch(
+        &self,
+        peer_id: PeerId,
+        requests: &[(String, Vec<Value>)],
+    ) -> result::Result<Vec<JsonRpcResult<Value>>, JsonRpcClientError> {
+        let mut results = Vec::with_capacity(requests.len());
+
+        for (method, params) in requests {
+            results.push(self.request_raw(peer_id, method, params).await);
+        }
+
+        Ok(results)
+    }
 }
 
 /// Set of api versions for each component (core + modules)
@@ -311,14 +462,80 @@ pub trait FederationApiExt: IRawFederationApi {
                 .await
         };
 
-        if let Some(timeout) = timeout {
+        let start = now();
+
+        let result = if let Some(timeout) = timeout {
             match fedimint_core::runtime::timeout(timeout, request).await {
                 Ok(result) => result,
                 Err(_timeout) => Err(JsonRpcClientError::RequestTimeout),
             }
         } else {
             request.await
+        };
+
+        if let Some(scores) = self.peer_scores() {
+            // An error returned by the endpoint itself still means the peer
+            // is reachable and responsive
+            let success = matches!(result, Ok(_) | Err(JsonRpcClientError::Call(_)));
+
+            scores.record(
+                peer_id,
+                now().duration_since(start).unwrap_or_default(),
+                success,
+            );
         }
+
+        result
+    }
+
+    /// Calls `method` with every element of `params` on a single peer in one
+    /// batch, e.g. to fetch the outcomes of many outputs with a single round
+    /// trip. The results are in the order of `params`.
+    async fn request_single_peer_batch<Ret>(
+        &self,
+        timeout: Option<Duration>,
+        method: String,
+        params: Vec<ApiRequestErased>,
+        peer_id: PeerId,
+    ) -> PeerResult<Vec<PeerResult<Ret>>>
+    where
+        Ret: serde::de::DeserializeOwned,
+    {
+        let requests = params
+            .iter()
+            .map(|params| (method.clone(), vec![params.to_json()]))
+            .collect::<Vec<_>>();
+
+        let request = async { self.request_raw_batch(peer_id, &requests).await };
+
+        let start = now();
+
+        let result = if let Some(timeout) = timeout {
+            match fedimint_core::runtime::timeout(timeout, request).await {
+                Ok(result) => result,
+                Err(_timeout) => Err(JsonRpcClientError::RequestTimeout),
+            }
+        } else {
+            request.await
+        };
+
+        if let Some(scores) = self.peer_scores() {
+            scores.record(
+                peer_id,
+                now().duration_since(start).unwrap_or_default(),
+                result.is_ok(),
+            );
+        }
+
+        Ok(result?
+            .into_iter()
+            .map(|result| {
+                result.map_err(PeerError::Rpc).and_then(|value| {
+                    serde_json::from_value(value)
+                        .map_err(|e| PeerError::ResponseDeserialization(e.into()))
+                })
+            })
+            .collect())
     }
 
     /// Like [`Self::request_single_peer`], but API more like
@@ -352,48 +569,147 @@ pub trait FederationApiExt: IRawFederationApi {
         params: ApiRequestErased,
     ) -> FederationResult<FedRet> {
         let timeout = strategy.request_timeout();
+        let deadline = strategy
+            .query_deadline()
+            .map(|deadline| (deadline, now() + deadline));
 
         #[cfg(not(target_family = "wasm"))]
         let mut futures = FuturesUnordered::<Pin<Box<dyn Future<Output = _> + Send>>>::new();
         #[cfg(target_family = "wasm")]
         let mut futures = FuturesUnordered::<Pin<Box<dyn Future<Output = _>>>>::new();
 
-        let peers = self.all_peers();
+        let request_peer = {
+            let method = &method;
+            let params = &params;
 
-        for peer_id in peers {
-            futures.push(Box::pin(async {
-                let request = async {
-                    self.request_raw(*peer_id, &method, &[params.to_json()])
-                        .await
-                        .map(AbbreviateDebug)
-                };
+            move |peer: PeerId, delay: Duration| {
+                // Counts the request as started even if the future is never
+                // polled before it is cancelled
+                let in_flight = InFlightRequest::start();
 
-                let result = if let Some(timeout) = timeout {
-                    match fedimint_core::runtime::timeout(timeout, request).await {
-                        Ok(result) => result,
-                        Err(_timeout) => Err(JsonRpcClientError::RequestTimeout),
+                async move {
+                    // Note: we need to sleep inside the request future, so that
+                    // `futures` is being polled continuously
+                    if !delay.is_zero() {
+                        runtime::sleep(delay).await;
                     }
-                } else {
-                    request.await
-                };
 
-                PeerResponse {
-                    peer: *peer_id,
-                    result,
+                    let result = self
+                        .request_single_peer(timeout, method.clone(), params.clone(), peer)
+                        .await
+                        .map(AbbreviateDebug);
+
+                    in_flight.complete();
+
+                    PeerResponse { peer, result }
                 }
-            }));
+            }
+        };
+
+        // Peers we have not sent the request to yet, best scoring first
+        let mut unqueried_peers = VecDeque::new();
+        let mut expansion = None;
+
+        let initial_peers = match strategy.peer_selection() {
+            PeerSelection::All => self.all_peers().iter().copied().collect::<Vec<_>>(),
+            PeerSelection::FastestResponders {
+                count,
+                expand_after,
+            } => {
+                let ranked = match self.peer_scores() {
+                    Some(scores) => scores.ranked(self.all_peers().iter().copied()),
+                    None => self.all_peers().iter().copied().collect(),
+                };
+
+                unqueried_peers.extend(ranked.iter().skip(count).copied());
+                expansion = Some((expand_after, now() + expand_after));
+
+                ranked.into_iter().take(count).collect()
+            }
+        };
+
+        // Peers we sent a request to and are waiting for a response from
+        let mut pending_peers = initial_peers.iter().copied().collect::<BTreeSet<_>>();
+
+        for peer in initial_peers {
+            futures.push(Box::pin(request_peer(peer, Duration::ZERO)));
         }
 
-        let mut peer_delay_ms = BTreeMap::new();
+        let retry_policy = strategy.retry_policy();
+        let mut retries = BTreeMap::<PeerId, u32>::new();
 
         // Delegates the response handling to the `QueryStrategy` with an exponential
         // back-off with every new set of requests
-        let max_delay_ms = 1000;
-        loop {
-            let response = futures.next().await;
+        let result = loop {
+            // Without outstanding requests waiting for the next expansion
+            // would only delay the query
+            if futures.is_empty() {
+                if let Some(peer) = unqueried_peers.pop_front() {
+                    pending_peers.insert(peer);
+                    futures.push(Box::pin(request_peer(peer, Duration::ZERO)));
+                }
+            }
+
+            let expansion_at = expansion
+                .filter(|_| !unqueried_peers.is_empty())
+                .map(|(_, expansion_at)| expansion_at);
+
+            let wake_at = [deadline.map(|(_, deadline_at)| deadline_at), expansion_at]
+                .into_iter()
+                .flatten()
+                .min();
+
+            let response = match wake_at {
+                Some(wake_at) => {
+                    let remaining = wake_at.duration_since(now()).unwrap_or_default();
+
+                    match runtime::timeout(remaining, futures.next()).await {
+                        Ok(response) => response,
+                        Err(_elapsed) if Some(wake_at) == expansion_at => {
+                            let (expand_after, _) =
+                                expansion.expect("Expansion time is only set with an expansion");
+
+                            if let Some(peer) = unqueried_peers.pop_front() {
+                                debug!(target: LOG_CLIENT_NET_API, %peer, method, "Expanding query to the next best peer");
+                                pending_peers.insert(peer);
+                                futures.push(Box::pin(request_peer(peer, Duration::ZERO)));
+                            }
+
+                            expansion = Some((expand_after, now() + expand_after));
+
+                            continue;
+                        }
+                        Err(_elapsed) => {
+                            let (deadline, _) =
+                                deadline.expect("Wake up time is either expansion or deadline");
+
+                            break Err(FederationError {
+                                method: method.clone(),
+                                params: params.params.clone(),
+                                general: Some(
+                                    QueryTimeout {
+                                        deadline,
+                                        pending_peers: pending_peers.clone(),
+                                    }
+                                    .into(),
+                                ),
+                                peers: pending_peers
+                                    .iter()
+                                    .map(|peer| {
+                                        (*peer, PeerError::Rpc(JsonRpcClientError::RequestTimeout))
+                                    })
+                                    .collect(),
+                            });
+                        }
+                    }
+                }
+                None => futures.next().await,
+            };
             trace!(target: LOG_CLIENT_NET_API, ?response, method, params = ?AbbreviateDebug(params.to_json()), "Received peer response");
             match response {
                 Some(PeerResponse { peer, result }) => {
+                    pending_peers.remove(&peer);
+
                     let result: PeerResult<PeerRet> =
                         result.map_err(PeerError::Rpc).and_then(|o| {
                             serde_json::from_value::<PeerRet>(o.0)
@@ -410,51 +726,103 @@ pub trait FederationApiExt: IRawFederationApi {
                     );
                     match strategy_step {
                         QueryStep::Retry(peers) => {
+                            let exhausted_peer = peers.iter().copied().find(|peer| {
+                                !retry_policy.allows(retries.get(peer).copied().unwrap_or(0) + 1)
+                            });
+
+                            if let Some(peer) = exhausted_peer {
+                                break Err(FederationError {
+                                    method: method.clone(),
+                                    params: params.params.clone(),
+                                    general: Some(anyhow!(
+                                        "Retried peer {peer} {} times without a result",
+                                        retries.get(&peer).copied().unwrap_or(0)
+                                    )),
+                                    peers: BTreeMap::new(),
+                                });
+                            }
+
                             for retry_peer in peers {
-                                let mut delay_ms =
-                                    peer_delay_ms.get(&retry_peer).copied().unwrap_or(10);
-                                delay_ms = cmp::min(max_delay_ms, delay_ms * 2);
-                                peer_delay_ms.insert(retry_peer, delay_ms);
-
-                                futures.push(Box::pin({
-                                    let method = &method;
-                                    let params = &params;
-                                    async move {
-                                        // Note: we need to sleep inside the retrying future,
-                                        // so that `futures` is being polled continuously
-                                        runtime::sleep(Duration::from_millis(delay_ms)).await;
-                                        PeerResponse {
-                                            peer: retry_peer,
-                                            result: self
-                                                .request_raw(
-                                                    retry_peer,
-                                                    method,
-                                                    &[params.to_json()],
-                                                )
-                                                .await
-                                                .map(AbbreviateDebug),
-                                        }
-                                    }
-                                }));
+                                let attempt = retries.entry(retry_peer).or_default();
+                                *attempt += 1;
+
+                                pending_peers.insert(retry_peer);
+
+                                futures.push(Box::pin(request_peer(
+                                    retry_peer,
+                                    retry_policy.delay(*attempt),
+                                )));
                             }
                         }
                         QueryStep::Continue => {}
                         QueryStep::Failure { general, peers } => {
-                            return Err(FederationError {
+                            break Err(FederationError {
                                 method: method.clone(),
                                 params: params.params.clone(),
                                 general,
                                 peers,
-                            })
+                            });
                         }
-                        QueryStep::Success(response) => return Ok(response),
+                        QueryStep::Success(response) => break Ok(response),
                     }
                 }
                 None => {
                     panic!("Query strategy ran out of peers to query without returning a result");
                 }
             }
+        };
+
+        // The strategy does not need the responses of the stragglers anymore,
+        // so we cancel their requests right away instead of letting them run
+        // until the futures are dropped at the end of the caller's scope
+        if !futures.is_empty() {
+            debug!(
+                target: LOG_CLIENT_NET_API,
+                method,
+                cancelled = futures.len(),
+                "Cancelling outstanding requests of finished query"
+            );
         }
+
+        drop(futures);
+        metrics::record_query();
+
+        result
+    }
+
+    /// Like [`Self::request_with_strategy`], but returns a cached response if
+    /// the same request succeeded before and caches the response otherwise.
+    /// The response expires after `ttl` or never if `None`.
+    ///
+    /// Only use this for immutable data and strategies that verify the
+    /// responses, see [`crate::cache`].
+    async fn request_with_strategy_cached<PeerRet, FedRet>(
+        &self,
+        strategy: impl QueryStrategy<PeerRet, FedRet> + MaybeSend,
+        method: String,
+        params: ApiRequestErased,
+        ttl: Option<Duration>,
+    ) -> FederationResult<FedRet>
+    where
+        PeerRet: serde::de::DeserializeOwned,
+        FedRet: Debug + Clone + MaybeSend + MaybeSync + 'static,
+    {
+        let Some(cache) = self.response_cache() else {
+            return self.request_with_strategy(strategy, method, params).await;
+        };
+
+        if let Some(response) = cache.get(&method, &params) {
+            trace!(target: LOG_CLIENT_NET_API, method, "Returning cached response");
+            return Ok(response);
+        }
+
+        let response = self
+            .request_with_strategy(strategy, method.clone(), params.clone())
+            .await?;
+
+        cache.insert(&method, &params, response.clone(), ttl);
+
+        Ok(response)
     }
 
     async fn request_current_consensus<Ret>(
@@ -465,8 +833,14 @@ pub trait FederationApiExt: IRawFederationApi {
     where
         Ret: serde::de::DeserializeOwned + Eq + Debug + Clone + MaybeSend,
     {
+        let num_peers = self.all_peers().to_num_peers();
+
         self.request_with_strategy(
-            ThresholdConsensus::new(self.all_peers().total()),
+            FastestResponders::new(
+                ThresholdConsensus::new(num_peers),
+                num_peers.threshold(),
+                CONSENSUS_EXPAND_AFTER,
+            ),
             method,
             params,
         )
@@ -563,6 +937,26 @@ impl DynGlobalApi {
         GlobalFederationApiWithCache::new(WsFederationApi::from_config(config)).into()
     }
 
+    /// Like [`Self::from_config`], but connects to the guardians with
+    /// `transport`, e.g. through a SOCKS5 proxy
+    pub fn from_config_with_transport(
+        config: &ClientConfig,
+        self_peer_id: Option<PeerId>,
+        transport: DynClientTransport,
+    ) -> Self {
+        GlobalFederationApiWithCache::new(WsFederationApi::new_with_transport(
+            config
+                .global
+                .api_endpoints
+                .iter()
+                .map(|(id, peer)| (*id, peer.url.clone()))
+                .collect(),
+            self_peer_id,
+            transport,
+        ))
+        .into()
+    }
+
     pub fn from_config_admin(config: &ClientConfig, self_peer_id: PeerId) -> Self {
         GlobalFederationApiWithCache::new(
             WsFederationApi::from_config(config).with_self_peer_id(self_peer_id),
@@ -627,6 +1021,14 @@ pub trait IGlobalFederationApi: IRawFederationApi {
 
     async fn await_transaction(&self, txid: TransactionId) -> FederationResult<TransactionId>;
 
+    /// Lifecycle stage of a transaction as seen by a single guardian, the
+    /// guardians may disagree until the transaction is ordered
+    async fn transaction_status(
+        &self,
+        txid: TransactionId,
+        peer_id: PeerId,
+    ) -> FederationResult<TransactionStatus>;
+
     /// Fetches the server consensus hash if enough peers agree on it
     async fn server_config_consensus_hash(&self) -> FederationResult<sha256::Hash>;
 
@@ -691,6 +1093,14 @@ pub trait IGlobalFederationApi: IRawFederationApi {
         auth: ApiAuth,
     ) -> FederationResult<()>;
 
+    /// Checks proposed config gen params for errors and likely
+    /// misconfigurations without generating any keys
+    async fn validate_setup_params(
+        &self,
+        requested: ConfigGenParamsRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<SetupParamsValidation>;
+
     /// Returns the consensus config gen params, followers will delegate this
     /// call to the leader.  Once this endpoint returns successfully we can run
     /// DKG.
@@ -725,6 +1135,10 @@ pub trait IGlobalFederationApi: IRawFederationApi {
     /// Returns the status of the server
     async fn status(&self) -> FederationResult<StatusResponse>;
 
+    /// Returns the connection status and consensus lag of all peers as seen by
+    /// the guardian
+    async fn federation_status(&self) -> FederationResult<FederationStatus>;
+
     /// Show an audit across all modules
     async fn audit(&self, auth: ApiAuth) -> FederationResult<AuditSummary>;
 
@@ -732,6 +1146,77 @@ pub trait IGlobalFederationApi: IRawFederationApi {
     async fn guardian_config_backup(&self, auth: ApiAuth)
         -> FederationResult<GuardianConfigBackup>;
 
+    /// Take an encrypted snapshot of the guardian database without pausing
+    /// consensus
+    async fn backup_database(&self, auth: ApiAuth) -> FederationResult<GuardianDatabaseBackup>;
+
+    /// Announce new p2p and/or api endpoints of the guardian to its peers
+    async fn update_peer_endpoints(
+        &self,
+        endpoints: PeerEndpoints,
+        auth: ApiAuth,
+    ) -> FederationResult<()>;
+
+    /// Returns the endpoints guardians announced after the federation was set
+    /// up
+    async fn peer_endpoints(&self) -> FederationResult<BTreeMap<PeerId, PeerEndpoints>>;
+
+    /// Replace the guardian's TLS certificate for the p2p connections
+    async fn rotate_tls_cert(&self, auth: ApiAuth) -> FederationResult<()>;
+
+    /// Number and size of the guardian's database entries per record type and
+    /// module
+    async fn db_stats(&self, auth: ApiAuth) -> FederationResult<Vec<DbPrefixStats>>;
+
+    /// Compact the guardian's database to reclaim disk space
+    async fn compact_db(&self, request: CompactDbRequest, auth: ApiAuth) -> FederationResult<()>;
+
+    /// Change the guardian's log filter at runtime, returns the directives of
+    /// the new filter
+    async fn set_log_filter(
+        &self,
+        request: SetLogFilterRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<String>;
+
+    /// Create an invite code for a subset of the guardians, optionally
+    /// restricted to an expiry and a number of uses
+    async fn create_invite_code(
+        &self,
+        request: CreateInviteCodeRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<InviteCode>;
+
+    /// The federation meta approved by a threshold of guardians
+    async fn federation_meta(&self) -> FederationResult<Option<FederationMetaRecord>>;
+
+    /// Approve the federation meta, which becomes active once a threshold of
+    /// guardians approved the identical meta
+    async fn propose_federation_meta(
+        &self,
+        meta: FederationMeta,
+        auth: ApiAuth,
+    ) -> FederationResult<()>;
+
+    /// The federation meta approved by guardians that is not active yet
+    async fn federation_meta_proposals(&self)
+        -> FederationResult<BTreeMap<PeerId, FederationMeta>>;
+
+    /// Votes to halt consensus after the given session for an upgrade
+    async fn schedule_upgrade(&self, session_index: u64, auth: ApiAuth) -> FederationResult<()>;
+
+    /// Withdraws the guardian's vote for an upgrade
+    async fn cancel_upgrade(&self, auth: ApiAuth) -> FederationResult<()>;
+
+    /// The guardians' votes on upcoming upgrades
+    async fn upgrade_status(&self) -> FederationResult<UpgradeStatus>;
+
+    /// Summary of the consensus items the guardian has not proposed yet
+    async fn mempool(&self, auth: ApiAuth) -> FederationResult<MempoolSummary>;
+
+    /// Time the guardian spent in the stages of the most recent sessions
+    async fn session_timing(&self, auth: ApiAuth) -> FederationResult<Vec<SessionTiming>>;
+
     /// Check auth credentials
     async fn auth(&self, auth: ApiAuth) -> FederationResult<()>;
 
@@ -811,9 +1296,19 @@ where
         decoders: &ModuleDecoderRegistry,
     ) -> anyhow::Result<SessionOutcome> {
         debug!(block_index, "Awaiting block's outcome from Federation");
-        self.request_current_consensus::<SerdeModuleEncoding<SessionOutcome>>(
+        let num_peers = self.all_peers().to_num_peers();
+
+        // The outcome of a session never changes once a threshold of guardians
+        // agreed on it
+        self.request_with_strategy_cached::<_, SerdeModuleEncoding<SessionOutcome>>(
+            FastestResponders::new(
+                ThresholdConsensus::new(num_peers),
+                num_peers.threshold(),
+                CONSENSUS_EXPAND_AFTER,
+            ),
             AWAIT_SESSION_OUTCOME_ENDPOINT.to_string(),
             ApiRequestErased::new(block_index),
+            None,
         )
         .await?
         .try_into_inner(decoders)
@@ -853,6 +1348,14 @@ where
         self.inner.with_module(id)
     }
 
+    fn peer_scores(&self) -> Option<&PeerScores> {
+        self.inner.peer_scores()
+    }
+
+    fn response_cache(&self) -> Option<&ResponseCache> {
+        self.inner.response_cache()
+    }
+
     /// Make request to a specific federation peer by `peer_id`
     async fn request_raw(
         &self,
@@ -862,6 +1365,14 @@ where
     ) -> result::Result<Value, JsonRpcClientError> {
         self.inner.request_raw(peer_id, method, params).await
     }
+
+    async fn request_raw_batch(
+        &self,
+        peer_id: PeerId,
+        requests: &[(String, Vec<Value>)],
+    ) -> result::Result<Vec<JsonRpcResult<Value>>, JsonRpcClientError> {
+        self.inner.request_raw_batch(peer_id, requests).await
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -956,6 +1467,20 @@ where
         .await
     }
 
+    async fn transaction_status(
+        &self,
+        txid: TransactionId,
+        peer_id: PeerId,
+    ) -> FederationResult<TransactionStatus> {
+        self.request_single_peer_federation(
+            None,
+            TRANSACTION_STATUS_ENDPOINT.to_owned(),
+            ApiRequestErased::new(txid),
+            peer_id,
+        )
+        .await
+    }
+
     async fn server_config_consensus_hash(&self) -> FederationResult<sha256::Hash> {
         self.request_current_consensus(
             SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT.to_owned(),
@@ -975,7 +1500,9 @@ where
     ) -> FederationResult<Vec<ClientBackupSnapshot>> {
         Ok(self
             .request_with_strategy(
-                UnionResponsesSingle::<Option<ClientBackupSnapshot>>::new(self.all_peers().total()),
+                UnionResponsesSingle::<Option<ClientBackupSnapshot>>::new(
+                    self.all_peers().to_num_peers(),
+                ),
                 RECOVER_ENDPOINT.to_owned(),
                 ApiRequestErased::new(id),
             )
@@ -1058,6 +1585,19 @@ where
         .await
     }
 
+    async fn validate_setup_params(
+        &self,
+        requested: ConfigGenParamsRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<SetupParamsValidation> {
+        self.request_admin(
+            VALIDATE_SETUP_PARAMS_ENDPOINT,
+            ApiRequestErased::new(requested),
+            auth,
+        )
+        .await
+    }
+
     async fn consensus_config_gen_params(&self) -> FederationResult<ConfigGenParamsResponse> {
         self.request_admin_no_auth(
             CONSENSUS_CONFIG_GEN_PARAMS_ENDPOINT,
@@ -1101,6 +1641,11 @@ where
             .await
     }
 
+    async fn federation_status(&self) -> FederationResult<FederationStatus> {
+        self.request_admin_no_auth(FEDERATION_STATUS_ENDPOINT, ApiRequestErased::default())
+            .await
+    }
+
     async fn audit(&self, auth: ApiAuth) -> FederationResult<AuditSummary> {
         self.request_admin(AUDIT_ENDPOINT, ApiRequestErased::default(), auth)
             .await
@@ -1118,6 +1663,130 @@ where
         .await
     }
 
+    async fn backup_database(&self, auth: ApiAuth) -> FederationResult<GuardianDatabaseBackup> {
+        self.request_admin(BACKUP_DATABASE_ENDPOINT, ApiRequestErased::default(), auth)
+            .await
+    }
+
+    async fn update_peer_endpoints(
+        &self,
+        endpoints: PeerEndpoints,
+        auth: ApiAuth,
+    ) -> FederationResult<()> {
+        self.request_admin(
+            UPDATE_PEER_ENDPOINTS_ENDPOINT,
+            ApiRequestErased::new(endpoints),
+            auth,
+        )
+        .await
+    }
+
+    async fn peer_endpoints(&self) -> FederationResult<BTreeMap<PeerId, PeerEndpoints>> {
+        self.request_admin_no_auth(PEER_ENDPOINTS_ENDPOINT, ApiRequestErased::default())
+            .await
+    }
+
+    async fn rotate_tls_cert(&self, auth: ApiAuth) -> FederationResult<()> {
+        self.request_admin(ROTATE_TLS_CERT_ENDPOINT, ApiRequestErased::default(), auth)
+            .await
+    }
+
+    async fn db_stats(&self, auth: ApiAuth) -> FederationResult<Vec<DbPrefixStats>> {
+        self.request_admin(DB_STATS_ENDPOINT, ApiRequestErased::default(), auth)
+            .await
+    }
+
+    async fn compact_db(&self, request: CompactDbRequest, auth: ApiAuth) -> FederationResult<()> {
+        self.request_admin(COMPACT_DB_ENDPOINT, ApiRequestErased::new(request), auth)
+            .await
+    }
+
+    async fn set_log_filter(
+        &self,
+        request: SetLogFilterRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<String> {
+        self.request_admin(
+            SET_LOG_FILTER_ENDPOINT,
+            ApiRequestErased::new(request),
+            auth,
+        )
+        .await
+    }
+
+    async fn create_invite_code(
+        &self,
+        request: CreateInviteCodeRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<InviteCode> {
+        self.request_admin(
+            CREATE_INVITE_CODE_ENDPOINT,
+            ApiRequestErased::new(request),
+            auth,
+        )
+        .await
+    }
+
+    async fn federation_meta(&self) -> FederationResult<Option<FederationMetaRecord>> {
+        self.request_current_consensus(
+            FEDERATION_META_ENDPOINT.to_owned(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
+    async fn propose_federation_meta(
+        &self,
+        meta: FederationMeta,
+        auth: ApiAuth,
+    ) -> FederationResult<()> {
+        self.request_admin(
+            PROPOSE_FEDERATION_META_ENDPOINT,
+            ApiRequestErased::new(meta),
+            auth,
+        )
+        .await
+    }
+
+    async fn federation_meta_proposals(
+        &self,
+    ) -> FederationResult<BTreeMap<PeerId, FederationMeta>> {
+        self.request_admin_no_auth(
+            FEDERATION_META_PROPOSALS_ENDPOINT,
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
+    async fn schedule_upgrade(&self, session_index: u64, auth: ApiAuth) -> FederationResult<()> {
+        self.request_admin(
+            SCHEDULE_UPGRADE_ENDPOINT,
+            ApiRequestErased::new(session_index),
+            auth,
+        )
+        .await
+    }
+
+    async fn cancel_upgrade(&self, auth: ApiAuth) -> FederationResult<()> {
+        self.request_admin(CANCEL_UPGRADE_ENDPOINT, ApiRequestErased::default(), auth)
+            .await
+    }
+
+    async fn upgrade_status(&self) -> FederationResult<UpgradeStatus> {
+        self.request_admin_no_auth(UPGRADE_STATUS_ENDPOINT, ApiRequestErased::default())
+            .await
+    }
+
+    async fn mempool(&self, auth: ApiAuth) -> FederationResult<MempoolSummary> {
+        self.request_admin(MEMPOOL_ENDPOINT, ApiRequestErased::default(), auth)
+            .await
+    }
+
+    async fn session_timing(&self, auth: ApiAuth) -> FederationResult<Vec<SessionTiming>> {
+        self.request_admin(SESSION_TIMING_ENDPOINT, ApiRequestErased::default(), auth)
+            .await
+    }
+
     async fn auth(&self, auth: ApiAuth) -> FederationResult<()> {
         self.request_admin(AUTH_ENDPOINT, ApiRequestErased::default(), auth)
             .await
@@ -1137,11 +1806,13 @@ where
 /// equal results from at least `min_eq_results` of them. Peers that return
 /// differing results are returned as a peer faults list.
 #[derive(Debug, Clone)]
-pub struct WsFederationApi<C = WsClient> {
+pub struct WsFederationApi {
     peer_ids: BTreeSet<PeerId>,
     self_peer_id: Option<PeerId>,
-    peers: Arc<Vec<FederationPeer<C>>>,
+    peers: Arc<Vec<FederationPeer>>,
     module_id: Option<ModuleInstanceId>,
+    scores: PeerScores,
+    cache: ResponseCache,
 }
 
 /// Some data shared/preserved between [`FederationPeerClient`] and
@@ -1193,20 +1864,19 @@ impl FederationPeerClientShared {
 /// The client in [`FederationPeer`], that takes care of reconnecting by
 /// starting background Jit task
 #[derive(Debug)]
-struct FederationPeerClient<C> {
-    client: JitTryAnyhow<C>,
+struct FederationPeerClient {
+    client: JitTryAnyhow<DynClientConnection>,
+    transport: DynClientTransport,
     shared: Arc<tokio::sync::Mutex<FederationPeerClientShared>>,
 }
 
-impl<C> FederationPeerClient<C>
-where
-    C: JsonRpcClient + 'static,
-{
-    pub fn new(peer_id: PeerId, url: SafeUrl) -> Self {
+impl FederationPeerClient {
+    pub fn new(peer_id: PeerId, url: SafeUrl, transport: DynClientTransport) -> Self {
         let shared: Arc<_> = tokio::sync::Mutex::new(FederationPeerClientShared::new()).into();
 
         Self {
-            client: Self::new_jit_client(peer_id, url, shared.clone()),
+            client: Self::new_jit_client(peer_id, url, transport.clone(), shared.clone()),
+            transport,
             shared,
         }
     }
@@ -1214,8 +1884,9 @@ where
     fn new_jit_client(
         peer_id: PeerId,
         url: SafeUrl,
+        transport: DynClientTransport,
         shared: Arc<Mutex<FederationPeerClientShared>>,
-    ) -> JitTryAnyhow<C> {
+    ) -> JitTryAnyhow<DynClientConnection> {
         JitTryAnyhow::new_try(move || async move {
             shared.lock().await.wait_and_inc_reconnect().await;
 
@@ -1224,7 +1895,7 @@ where
                 peer_id = %peer_id,
                 url = %url,
                 "Connecting to peer");
-            let res = C::connect(&url).await;
+            let res = transport.connect(&url).await;
 
             match &res {
                 Ok(_) => {
@@ -1248,23 +1919,24 @@ where
     }
 
     pub async fn reconnect(&mut self, peer_id: PeerId, url: SafeUrl) {
-        self.client = Self::new_jit_client(peer_id, url, self.shared.clone());
+        self.client =
+            Self::new_jit_client(peer_id, url, self.transport.clone(), self.shared.clone());
     }
 }
 
 #[derive(Debug)]
-struct FederationPeer<C> {
+struct FederationPeer {
     url: SafeUrl,
     peer_id: PeerId,
-    client: RwLock<FederationPeerClient<C>>,
+    client: RwLock<FederationPeerClient>,
 }
-impl<C: JsonRpcClient + Debug + 'static> IModuleFederationApi for WsFederationApi<C> {}
+impl IModuleFederationApi for WsFederationApi {}
 
 /// Implementation of API calls over websockets
 ///
 /// Can function as either the global or module API
 #[apply(async_trait_maybe_send!)]
-impl<C: JsonRpcClient + Debug + 'static> IRawFederationApi for WsFederationApi<C> {
+impl IRawFederationApi for WsFederationApi {
     fn all_peers(&self) -> &BTreeSet<PeerId> {
         &self.peer_ids
     }
@@ -1279,28 +1951,47 @@ impl<C: JsonRpcClient + Debug + 'static> IRawFederationApi for WsFederationApi<C
             peers: self.peers.clone(),
             module_id: Some(id),
             self_peer_id: self.self_peer_id,
+            scores: self.scores.clone(),
+            // Method names are only unique within a module
+            cache: ResponseCache::default(),
         }
         .into()
     }
 
+    fn peer_scores(&self) -> Option<&PeerScores> {
+        Some(&self.scores)
+    }
+
+    fn response_cache(&self) -> Option<&ResponseCache> {
+        Some(&self.cache)
+    }
+
     async fn request_raw(
         &self,
         peer_id: PeerId,
         method: &str,
         params: &[Value],
     ) -> JsonRpcResult<Value> {
-        let peer = self
-            .peers
-            .iter()
-            .find(|m| m.peer_id == peer_id)
-            .ok_or_else(|| JsonRpcClientError::Custom(format!("Invalid peer_id: {peer_id}")))?;
+        let peer = self.peer(peer_id)?;
+        let method = self.method_name(method);
 
-        let method = match self.module_id {
-            None => method.to_string(),
-            Some(id) => format!("module_{id}_{method}"),
-        };
         peer.request(&method, params).await
     }
+
+    async fn request_raw_batch(
+        &self,
+        peer_id: PeerId,
+        requests: &[(String, Vec<Value>)],
+    ) -> JsonRpcResult<Vec<JsonRpcResult<Value>>> {
+        let peer = self.peer(peer_id)?;
+
+        let requests = requests
+            .iter()
+            .map(|(method, params)| (self.method_name(method), params.clone()))
+            .collect::<Vec<_>>();
+
+        peer.batch_request(&requests).await
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -1331,10 +2022,10 @@ impl JsonRpcClient for WsClient {
     }
 }
 
-impl WsFederationApi<WsClient> {
+impl WsFederationApi {
     /// Creates a new API client
     pub fn new(peers: Vec<(PeerId, SafeUrl)>) -> Self {
-        Self::new_with_client(peers, None)
+        Self::new_with_client::<WsClient>(peers, None)
     }
 
     /// Creates a new API client from a client config
@@ -1357,16 +2048,44 @@ impl WsFederationApi<WsClient> {
     }
 }
 
-impl<C> WsFederationApi<C>
-where
-    C: JsonRpcClient + 'static,
-{
+impl WsFederationApi {
     pub fn peers(&self) -> Vec<PeerId> {
         self.peers.iter().map(|peer| peer.peer_id).collect()
     }
 
-    /// Creates a new API client
-    pub fn new_with_client(peers: Vec<(PeerId, SafeUrl)>, self_peer_id: Option<PeerId>) -> Self {
+    fn peer(&self, peer_id: PeerId) -> JsonRpcResult<&FederationPeer> {
+        self.peers
+            .iter()
+            .find(|m| m.peer_id == peer_id)
+            .ok_or_else(|| JsonRpcClientError::Custom(format!("Invalid peer_id: {peer_id}")))
+    }
+
+    fn method_name(&self, method: &str) -> String {
+        match self.module_id {
+            None => method.to_string(),
+            Some(id) => format!("module_{id}_{method}"),
+        }
+    }
+
+    /// Creates a new API client connecting to the peers directly with `C`
+    pub fn new_with_client<C: JsonRpcClient + Debug + 'static>(
+        peers: Vec<(PeerId, SafeUrl)>,
+        self_peer_id: Option<PeerId>,
+    ) -> Self {
+        Self::new_with_transport(
+            peers,
+            self_peer_id,
+            Arc::new(DirectTransport::<C>::default()),
+        )
+    }
+
+    /// Creates a new API client connecting to the peers with `transport`, e.g.
+    /// through a SOCKS5 proxy
+    pub fn new_with_transport(
+        peers: Vec<(PeerId, SafeUrl)>,
+        self_peer_id: Option<PeerId>,
+        transport: DynClientTransport,
+    ) -> Self {
         WsFederationApi {
             peer_ids: peers.iter().map(|m| m.0).collect(),
             self_peer_id,
@@ -1382,13 +2101,19 @@ where
 
                         FederationPeer {
                             peer_id,
-                            client: RwLock::new(FederationPeerClient::new(peer_id, url.clone())),
+                            client: RwLock::new(FederationPeerClient::new(
+                                peer_id,
+                                url.clone(),
+                                transport.clone(),
+                            )),
                             url,
                         }
                     })
                     .collect(),
             ),
             module_id: None,
+            scores: PeerScores::default(),
+            cache: ResponseCache::default(),
         }
     }
 }
@@ -1399,18 +2124,28 @@ pub struct PeerResponse<R> {
     pub result: JsonRpcResult<R>,
 }
 
-impl<C> FederationPeer<C>
-where
-    C: JsonRpcClient + 'static,
-{
+impl FederationPeer {
     #[instrument(level = "trace", fields(peer = %self.peer_id, %method), skip_all)]
     pub async fn request(&self, method: &str, params: &[Value]) -> JsonRpcResult<Value> {
+        self.connection().await?.request(method, params).await
+    }
+
+    #[instrument(level = "trace", fields(peer = %self.peer_id, requests = requests.len()), skip_all)]
+    pub async fn batch_request(
+        &self,
+        requests: &[(String, Vec<Value>)],
+    ) -> JsonRpcResult<Vec<JsonRpcResult<Value>>> {
+        self.connection().await?.batch_request(requests).await
+    }
+
+    /// Returns the connection to the peer, reconnecting once if it was lost
+    async fn connection(&self) -> JsonRpcResult<DynClientConnection> {
         for attempts in 0.. {
             debug_assert!(attempts <= 1);
             let rclient = self.client.read().await;
             match rclient.client.get_try().await {
                 Ok(client) if client.is_connected() => {
-                    return client.request::<_, _>(method, params).await;
+                    return Ok(client.clone());
                 }
                 Err(e) => {
                     // Strategies using timeouts often depend on failing requests returning quickly,
@@ -1447,8 +2182,6 @@ where
     }
 }
 
-impl<C: JsonRpcClient> WsFederationApi<C> {}
-
 /// The status of a server, including how it views its peers
 #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub struct FederationStatus {
@@ -1459,15 +2192,26 @@ pub struct FederationStatus {
     /// This should always be 0 if everything is okay, so a monitoring tool
     /// should generate an alert if this is not the case.
     pub peers_flagged: u64,
+    /// Number of peers whose latest state hash differs from ours. This should
+    /// always be 0, otherwise the consensus state of a guardian diverged.
+    #[serde(default)]
+    pub peers_diverged: u64,
 }
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PeerStatus {
     pub last_contribution: Option<u64>,
+    /// Number of sessions since the peer last contributed to the consensus
+    #[serde(default)]
+    pub session_lag: Option<u64>,
     pub connection_status: PeerConnectionStatus,
     /// Indicates that this peer needs attention from the operator since
     /// it has not contributed to the consensus in a long time
     pub flagged: bool,
+    /// Latest session after which the peer announced a consensus state that
+    /// differs from ours
+    #[serde(default)]
+    pub state_divergence: Option<u64>,
 }
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
@@ -1492,6 +2236,14 @@ pub struct GuardianConfigBackup {
     pub tar_archive_bytes: Vec<u8>,
 }
 
+/// Result of a `backup_database` request
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GuardianDatabaseBackup {
+    /// Path on the guardian host the encrypted snapshot is being written to in
+    /// the background
+    pub path: String,
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr as _;
@@ -1503,7 +2255,11 @@ mod tests {
     use jsonrpsee_core::traits::ToRpcParams;
     use serde::de::DeserializeOwned;
 
+    use fedimint_core::NumPeers;
+
     use super::*;
+    use crate::metrics::query_metrics;
+    use crate::query::{FilterMap, WithTimeouts};
 
     type Result<T = ()> = std::result::Result<T, JsonRpcClientError>;
 
@@ -1559,6 +2315,144 @@ mod tests {
         }
     }
 
+    #[derive(Debug)]
+    struct HangingPeersApi {
+        peers: BTreeSet<PeerId>,
+    }
+
+    #[apply(async_trait_maybe_send!)]
+    impl IRawFederationApi for HangingPeersApi {
+        fn all_peers(&self) -> &BTreeSet<PeerId> {
+            &self.peers
+        }
+
+        fn self_peer(&self) -> Option<PeerId> {
+            None
+        }
+
+        fn with_module(&self, _id: ModuleInstanceId) -> DynModuleApi {
+            unimplemented!()
+        }
+
+        async fn request_raw(
+            &self,
+            peer_id: PeerId,
+            _method: &str,
+            _params: &[Value],
+        ) -> result::Result<Value, JsonRpcClientError> {
+            // Only the first peer ever answers
+            if peer_id == PeerId::from(0) {
+                Ok(Value::from(42))
+            } else {
+                std::future::pending().await
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn query_deadline_reports_pending_peers() {
+        let api = HangingPeersApi {
+            peers: (0..4).map(PeerId::from).collect(),
+        };
+
+        let error = api
+            .request_with_strategy(
+                WithTimeouts::new(ThresholdConsensus::<u64>::new(NumPeers::from(4)))
+                    .with_query_deadline(Duration::from_millis(100)),
+                "test".to_string(),
+                ApiRequestErased::default(),
+            )
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error.get_timeout().map(|timeout| &timeout.pending_peers),
+            Some(&(1..4).map(PeerId::from).collect())
+        );
+        assert_eq!(error.get_peer_errors().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn finished_query_cancels_outstanding_requests() {
+        let api = HangingPeersApi {
+            peers: (0..4).map(PeerId::from).collect(),
+        };
+
+        let before = query_metrics();
+
+        let response = api
+            .request_with_strategy(
+                FilterMap::new(|response: u64| Ok(response), NumPeers::from(4)),
+                "test".to_string(),
+                ApiRequestErased::default(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response, 42);
+
+        // Other tests of this process may run queries concurrently
+        let after = query_metrics();
+        assert!(after.queries > before.queries);
+        assert!(after.requests_cancelled >= before.requests_cancelled + 3);
+    }
+
+    #[tokio::test]
+    async fn batch_results_are_in_request_order() {
+        let api = HangingPeersApi {
+            peers: (0..4).map(PeerId::from).collect(),
+        };
+
+        let results = api
+            .request_single_peer_batch::<String>(
+                None,
+                "test".to_string(),
+                vec![ApiRequestErased::default(); 3],
+                PeerId::from(0),
+            )
+            .await
+            .unwrap();
+
+        // Every call fails to deserialize on its own without failing the batch
+        assert_eq!(results.len(), 3);
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, Err(PeerError::ResponseDeserialization(_)))));
+
+        let results = api
+            .request_single_peer_batch::<u64>(
+                None,
+                "test".to_string(),
+                vec![ApiRequestErased::default(); 3],
+                PeerId::from(0),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            results.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![42, 42, 42]
+        );
+    }
+
+    #[test]
+    fn distinguishes_pruned_from_not_found_yet() {
+        let classify = |code| {
+            FederationApiError::from_rpc_error(&JsonRpcClientError::Call(
+                jsonrpsee_types::ErrorObject::owned(code, "session 3", None::<()>),
+            ))
+        };
+
+        assert_eq!(
+            classify(ApiError::NOT_FOUND),
+            FederationApiError::NotFoundYet("session 3".to_string())
+        );
+        assert_eq!(
+            classify(ApiError::GONE),
+            FederationApiError::Pruned("session 3".to_string())
+        );
+    }
+
     #[test]
     fn converts_invite_code() {
         let connect = InviteCode::new(
@@ -0,0 +1,138 @@
+//! Client side cache of responses to queries for immutable data
+//!
+//! Some data never changes once the federation returned it, e.g. the outcome
+//! of a completed session. Caching it spares light clients from downloading
+//! the same history again, see
+//! [`crate::api::FederationApiExt::request_with_strategy_cached`].
+//!
+//! Responses are only inserted after the query strategy accepted them, so the
+//! strategies of cached queries have to verify the responses, e.g. by checking
+//! the threshold signature of the federation or by requiring a threshold of
+//! identical responses. A cached response is returned without contacting the
+//! federation again until it expires.
+
+use std::any::Any;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use fedimint_core::maybe_add_send_sync;
+use fedimint_core::module::ApiRequestErased;
+use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::time::now;
+
+/// Number of responses an API keeps by default
+pub const DEFAULT_RESPONSE_CACHE_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    method: String,
+    params: String,
+}
+
+impl CacheKey {
+    fn new(method: &str, params: &ApiRequestErased) -> Self {
+        CacheKey {
+            method: method.to_string(),
+            params: params.to_json().to_string(),
+        }
+    }
+}
+
+struct CacheEntry {
+    value: Box<maybe_add_send_sync!(dyn Any)>,
+    expires_at: Option<SystemTime>,
+}
+
+/// LRU cache of verified responses keyed by the method and parameters of the
+/// request
+#[derive(Clone)]
+pub struct ResponseCache(Arc<Mutex<lru::LruCache<CacheKey, CacheEntry>>>);
+
+impl std::fmt::Debug for ResponseCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResponseCache")
+            .field("len", &self.0.lock().expect("Cache lock poisoned").len())
+            .finish()
+    }
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        Self::new(NonZeroUsize::new(DEFAULT_RESPONSE_CACHE_CAPACITY).expect("Capacity is non-zero"))
+    }
+}
+
+impl ResponseCache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        ResponseCache(Arc::new(Mutex::new(lru::LruCache::new(capacity))))
+    }
+
+    /// Returns the cached response if it has not expired yet and was cached as
+    /// a `T`
+    pub fn get<T: Clone + 'static>(&self, method: &str, params: &ApiRequestErased) -> Option<T> {
+        let key = CacheKey::new(method, params);
+        let mut cache = self.0.lock().expect("Cache lock poisoned");
+
+        let expired = cache
+            .peek(&key)?
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= now());
+
+        if expired {
+            cache.pop(&key);
+            return None;
+        }
+
+        cache.get(&key)?.value.downcast_ref::<T>().cloned()
+    }
+
+    /// Caches a verified response, it expires after `ttl` or never if `None`
+    pub fn insert<T: MaybeSend + MaybeSync + 'static>(
+        &self,
+        method: &str,
+        params: &ApiRequestErased,
+        value: T,
+        ttl: Option<Duration>,
+    ) {
+        self.0.lock().expect("Cache lock poisoned").put(
+            CacheKey::new(method, params),
+            CacheEntry {
+                value: Box::new(value),
+                expires_at: ttl.map(|ttl| now() + ttl),
+            },
+        );
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().expect("Cache lock poisoned").clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fedimint_core::module::ApiRequestErased;
+
+    use super::ResponseCache;
+
+    #[test]
+    fn returns_cached_response_until_it_expires() {
+        let cache = ResponseCache::default();
+        let params = ApiRequestErased::new(5u64);
+
+        cache.insert("forever", &params, 42u64, None);
+        cache.insert("expired", &params, 42u64, Some(Duration::ZERO));
+
+        assert_eq!(cache.get::<u64>("forever", &params), Some(42));
+        assert_eq!(
+            cache.get::<u64>("forever", &ApiRequestErased::new(6u64)),
+            None
+        );
+        assert_eq!(cache.get::<u64>("expired", &params), None);
+
+        // A response is only returned as the type it was cached as
+        assert_eq!(cache.get::<String>("forever", &params), None);
+    }
+}
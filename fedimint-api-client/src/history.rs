@@ -0,0 +1,222 @@
+//! Offline verification of the consensus history of a federation
+//!
+//! Every completed session is signed by a threshold of the guardians with
+//! their broadcast keys. Given these public keys, an auditor can verify an
+//! exported range of signed session outcomes without contacting the
+//! federation.
+
+use std::collections::BTreeMap;
+
+use fedimint_core::secp256k1::{self, schnorr, PublicKey};
+use fedimint_core::session_outcome::{
+    broadcast_signature_message, SessionOutcome, SignedSessionOutcome,
+};
+use fedimint_core::{NumPeersExt, PeerId};
+use thiserror::Error;
+
+use crate::progress::{NoProgress, ProgressEvent, ProgressOperation, ProgressReporter};
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum HistoryVerificationError {
+    #[error("Session {session_index} is signed by {signatures} instead of {threshold} guardians")]
+    WrongNumberOfSignatures {
+        session_index: u64,
+        signatures: usize,
+        threshold: usize,
+    },
+    #[error("Session {session_index} is signed by {peer}, which is not a guardian")]
+    UnknownSigner { session_index: u64, peer: PeerId },
+    #[error("Session {session_index} has an invalid signature of {peer}")]
+    InvalidSignature { session_index: u64, peer: PeerId },
+}
+
+/// Verifies the signatures of consecutive sessions starting at
+/// `first_session_index` with the broadcast public keys of the guardians and
+/// returns the verified session outcomes by their index
+pub fn verify_signed_history(
+    public_keys: &BTreeMap<PeerId, PublicKey>,
+    first_session_index: u64,
+    signed_session_outcomes: impl IntoIterator<Item = SignedSessionOutcome>,
+) -> Result<BTreeMap<u64, SessionOutcome>, HistoryVerificationError> {
+    verify_signed_history_with_progress(
+        public_keys,
+        first_session_index,
+        signed_session_outcomes,
+        &NoProgress,
+    )
+}
+
+/// Like [`verify_signed_history`], but reports every verified session to
+/// `progress`
+pub fn verify_signed_history_with_progress(
+    public_keys: &BTreeMap<PeerId, PublicKey>,
+    first_session_index: u64,
+    signed_session_outcomes: impl IntoIterator<Item = SignedSessionOutcome>,
+    progress: &dyn ProgressReporter,
+) -> Result<BTreeMap<u64, SessionOutcome>, HistoryVerificationError> {
+    let signed_session_outcomes = signed_session_outcomes.into_iter();
+
+    let total = match signed_session_outcomes.size_hint() {
+        (lower, Some(upper)) if lower == upper => Some(upper as u64),
+        _ => None,
+    };
+
+    (first_session_index..)
+        .zip(signed_session_outcomes)
+        .enumerate()
+        .map(|(verified, (session_index, signed_session_outcome))| {
+            verify_signed_session_outcome(public_keys, session_index, &signed_session_outcome)?;
+
+            progress.report(ProgressEvent {
+                operation: ProgressOperation::VerifyHistory,
+                completed: verified as u64 + 1,
+                total,
+            });
+
+            Ok((session_index, signed_session_outcome.session_outcome))
+        })
+        .collect()
+}
+
+/// Verifies that a threshold of the guardians signed the header of the session
+pub fn verify_signed_session_outcome(
+    public_keys: &BTreeMap<PeerId, PublicKey>,
+    session_index: u64,
+    signed_session_outcome: &SignedSessionOutcome,
+) -> Result<(), HistoryVerificationError> {
+    // The guardians include exactly a threshold of signatures
+    if signed_session_outcome.signatures.len() != public_keys.threshold() {
+        return Err(HistoryVerificationError::WrongNumberOfSignatures {
+            session_index,
+            signatures: signed_session_outcome.signatures.len(),
+            threshold: public_keys.threshold(),
+        });
+    }
+
+    let message = broadcast_signature_message(
+        public_keys,
+        &signed_session_outcome.session_outcome.header(session_index),
+    );
+
+    for (peer, signature) in &signed_session_outcome.signatures {
+        let public_key = public_keys
+            .get(peer)
+            .ok_or(HistoryVerificationError::UnknownSigner {
+                session_index,
+                peer: *peer,
+            })?;
+
+        let valid = schnorr::Signature::from_slice(&signature.0).is_ok_and(|signature| {
+            secp256k1::SECP256K1
+                .verify_schnorr(&signature, &message, &public_key.x_only_public_key().0)
+                .is_ok()
+        });
+
+        if !valid {
+            return Err(HistoryVerificationError::InvalidSignature {
+                session_index,
+                peer: *peer,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    use fedimint_core::secp256k1::{self, KeyPair};
+    use fedimint_core::session_outcome::{
+        broadcast_signature_message, SchnorrSignature, SessionOutcome, SignedSessionOutcome,
+    };
+    use fedimint_core::PeerId;
+
+    use super::{
+        verify_signed_history, verify_signed_history_with_progress, HistoryVerificationError,
+    };
+    use crate::progress::ProgressEvent;
+
+    #[test]
+    fn verifies_threshold_signed_sessions() {
+        let key_pairs = (0..4)
+            .map(|peer| {
+                (
+                    PeerId::from(peer),
+                    KeyPair::new(secp256k1::SECP256K1, &mut rand::thread_rng()),
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let public_keys = key_pairs
+            .iter()
+            .map(|(peer, key_pair)| (*peer, key_pair.public_key()))
+            .collect::<BTreeMap<_, _>>();
+
+        let sign = |session_index: u64, signers: &[u16]| {
+            let session_outcome = SessionOutcome { items: vec![] };
+            let message =
+                broadcast_signature_message(&public_keys, &session_outcome.header(session_index));
+
+            SignedSessionOutcome {
+                signatures: signers
+                    .iter()
+                    .map(|peer| {
+                        let signature = secp256k1::SECP256K1
+                            .sign_schnorr(&message, &key_pairs[&PeerId::from(*peer)]);
+
+                        (
+                            PeerId::from(*peer),
+                            SchnorrSignature(signature.as_ref().to_owned()),
+                        )
+                    })
+                    .collect(),
+                session_outcome,
+            }
+        };
+
+        let verified = verify_signed_history(
+            &public_keys,
+            7,
+            vec![sign(7, &[0, 1, 2]), sign(8, &[1, 2, 3])],
+        )
+        .unwrap();
+
+        assert_eq!(verified.keys().copied().collect::<Vec<_>>(), vec![7, 8]);
+
+        let events = Mutex::new(vec![]);
+
+        verify_signed_history_with_progress(
+            &public_keys,
+            7,
+            vec![sign(7, &[0, 1, 2]), sign(8, &[1, 2, 3])],
+            &|event: ProgressEvent| events.lock().unwrap().push((event.completed, event.total)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            events.into_inner().unwrap(),
+            vec![(1, Some(2)), (2, Some(2))]
+        );
+
+        // The signatures commit to the index of the session
+        assert_eq!(
+            verify_signed_history(&public_keys, 7, vec![sign(8, &[0, 1, 2])]),
+            Err(HistoryVerificationError::InvalidSignature {
+                session_index: 7,
+                peer: PeerId::from(0)
+            })
+        );
+
+        assert_eq!(
+            verify_signed_history(&public_keys, 7, vec![sign(7, &[0, 1])]),
+            Err(HistoryVerificationError::WrongNumberOfSignatures {
+                session_index: 7,
+                signatures: 2,
+                threshold: 3
+            })
+        );
+    }
+}
@@ -1,18 +1,34 @@
+use std::collections::BTreeMap;
 use std::time::Duration;
 
 use anyhow::{bail, Context as _};
 use api::{DynGlobalApi, FederationApiExt as _, WsFederationApi};
-use fedimint_core::config::ClientConfig;
+use fedimint_core::config::{ClientConfig, PeerUrl};
 use fedimint_core::encoding::Encodable as _;
 use fedimint_core::endpoint_constants::CLIENT_CONFIG_ENDPOINT;
 use fedimint_core::invite_code::InviteCode;
 use fedimint_core::module::ApiRequestErased;
+use fedimint_core::{NumPeers, PeerId};
 use query::FilterMap;
 use tracing::debug;
 
 pub mod api;
+/// Cache of verified responses to immutable queries
+pub mod cache;
+/// Offline verification of signed session outcomes
+pub mod history;
+/// Counters of the query driver
+pub mod metrics;
+/// Latency and reliability scores of the guardians
+pub mod peer_score;
+/// Health probing of a federation before joining it
+pub mod probe;
+/// Progress events of long running operations
+pub mod progress;
 /// Client query system
 pub mod query;
+/// Transports to connect to the guardians with
+pub mod transport;
 
 /// Tries to download the client config from the federation,
 /// attempts to retry teb times before giving up.
@@ -37,6 +53,38 @@ pub async fn download_from_invite_code(invite_code: &InviteCode) -> anyhow::Resu
 pub async fn try_download_client_config(invite_code: &InviteCode) -> anyhow::Result<ClientConfig> {
     // we have to download the api endpoints first
     let federation_id = invite_code.federation_id();
+    let api_endpoints = download_api_endpoints(invite_code).await?;
+
+    // now we can build an api for all guardians and download the client config
+    let api_endpoints = api_endpoints
+        .into_iter()
+        .map(|(peer, url)| (peer, url.url))
+        .collect();
+
+    // Guardians count the downloads of scoped invite codes, so we only send it
+    // with the actual download
+    let params = match invite_code.scope() {
+        Some(_) => ApiRequestErased::new(invite_code),
+        None => ApiRequestErased::default(),
+    };
+
+    let client_config = WsFederationApi::new(api_endpoints)
+        .request_current_consensus::<ClientConfig>(CLIENT_CONFIG_ENDPOINT.to_owned(), params)
+        .await?;
+
+    if client_config.calculate_federation_id() != federation_id {
+        bail!("Obtained client config has different federation id");
+    }
+
+    Ok(client_config)
+}
+
+/// Downloads the api endpoints of all guardians from the guardians of the
+/// invite code and verifies them against the federation id
+async fn download_api_endpoints(
+    invite_code: &InviteCode,
+) -> anyhow::Result<BTreeMap<PeerId, PeerUrl>> {
+    let federation_id = invite_code.federation_id();
 
     let query_strategy = FilterMap::new(
         move |cfg: ClientConfig| {
@@ -46,33 +94,16 @@ pub async fn try_download_client_config(invite_code: &InviteCode) -> anyhow::Res
 
             Ok(cfg.global.api_endpoints)
         },
-        1,
+        // The response is verified against the federation id, so the first
+        // guardian of the invite code that answers is sufficient
+        NumPeers::from(1),
     );
 
-    let api_endpoints = DynGlobalApi::from_invite_code(invite_code)
+    Ok(DynGlobalApi::from_invite_code(invite_code)
         .request_with_strategy(
             query_strategy,
             CLIENT_CONFIG_ENDPOINT.to_owned(),
             ApiRequestErased::default(),
         )
-        .await?;
-
-    // now we can build an api for all guardians and download the client config
-    let api_endpoints = api_endpoints
-        .into_iter()
-        .map(|(peer, url)| (peer, url.url))
-        .collect();
-
-    let client_config = WsFederationApi::new(api_endpoints)
-        .request_current_consensus::<ClientConfig>(
-            CLIENT_CONFIG_ENDPOINT.to_owned(),
-            ApiRequestErased::default(),
-        )
-        .await?;
-
-    if client_config.calculate_federation_id() != federation_id {
-        bail!("Obtained client config has different federation id");
-    }
-
-    Ok(client_config)
+        .await?)
 }
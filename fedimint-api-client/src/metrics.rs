@@ -0,0 +1,66 @@
+//! Counters of the requests sent by the query driver
+//!
+//! Every query sends its request to several peers concurrently and cancels
+//! the requests that are still outstanding as soon as its strategy returns a
+//! result. The counters are process wide and meant for debugging, e.g. to see
+//! how much load a strategy causes on the guardians.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static QUERIES: AtomicU64 = AtomicU64::new(0);
+static REQUESTS_STARTED: AtomicU64 = AtomicU64::new(0);
+static REQUESTS_COMPLETED: AtomicU64 = AtomicU64::new(0);
+static REQUESTS_CANCELLED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryMetrics {
+    /// Queries that returned a result or an error
+    pub queries: u64,
+    /// Requests to single peers, including retries
+    pub requests_started: u64,
+    /// Requests that returned a response or an error
+    pub requests_completed: u64,
+    /// Requests that were still outstanding when their query returned
+    pub requests_cancelled: u64,
+}
+
+/// Returns the counters of all queries of this process so far
+pub fn query_metrics() -> QueryMetrics {
+    QueryMetrics {
+        queries: QUERIES.load(Ordering::Relaxed),
+        requests_started: REQUESTS_STARTED.load(Ordering::Relaxed),
+        requests_completed: REQUESTS_COMPLETED.load(Ordering::Relaxed),
+        requests_cancelled: REQUESTS_CANCELLED.load(Ordering::Relaxed),
+    }
+}
+
+pub(crate) fn record_query() {
+    QUERIES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a request as cancelled if it is dropped before it completes
+pub(crate) struct InFlightRequest {
+    completed: bool,
+}
+
+impl InFlightRequest {
+    pub(crate) fn start() -> Self {
+        REQUESTS_STARTED.fetch_add(1, Ordering::Relaxed);
+
+        InFlightRequest { completed: false }
+    }
+
+    pub(crate) fn complete(mut self) {
+        self.completed = true;
+    }
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        if self.completed {
+            REQUESTS_COMPLETED.fetch_add(1, Ordering::Relaxed);
+        } else {
+            REQUESTS_CANCELLED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Scores of the guardians based on how fast and reliably they answered our
+//! recent requests
+//!
+//! Queries that only need a subset of the guardians to answer can be sent to
+//! the best scoring ones first, see [`crate::query::FastestResponders`].
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use fedimint_core::PeerId;
+
+/// Weight of the latest request in the moving averages
+const SCORE_SMOOTHING: f64 = 0.2;
+
+/// Latency we add for a peer that fails every request, so a fast but
+/// unreliable peer ranks below a slow but reliable one
+const ERROR_PENALTY_MS: f64 = 10_000.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PeerScore {
+    /// Moving average of the response latency in milliseconds
+    pub latency_ms: f64,
+    /// Moving average of the share of failed requests, between 0 and 1
+    pub error_rate: f64,
+    pub requests: u64,
+}
+
+impl PeerScore {
+    /// Expected time until the peer returns a usable response, lower is
+    /// better
+    pub fn cost(&self) -> f64 {
+        self.latency_ms + self.error_rate * ERROR_PENALTY_MS
+    }
+
+    fn record(&mut self, latency: Duration, success: bool) {
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        let error = if success { 0.0 } else { 1.0 };
+
+        if self.requests == 0 {
+            self.latency_ms = latency_ms;
+            self.error_rate = error;
+        } else {
+            self.latency_ms += SCORE_SMOOTHING * (latency_ms - self.latency_ms);
+            self.error_rate += SCORE_SMOOTHING * (error - self.error_rate);
+        }
+
+        self.requests += 1;
+    }
+}
+
+/// Scores of all peers, shared by the global and module APIs of a federation
+#[derive(Debug, Clone, Default)]
+pub struct PeerScores(Arc<Mutex<BTreeMap<PeerId, PeerScore>>>);
+
+impl PeerScores {
+    pub fn record(&self, peer: PeerId, latency: Duration, success: bool) {
+        self.0
+            .lock()
+            .expect("Peer scores lock poisoned")
+            .entry(peer)
+            .or_default()
+            .record(latency, success);
+    }
+
+    pub fn get(&self, peer: PeerId) -> Option<PeerScore> {
+        self.0
+            .lock()
+            .expect("Peer scores lock poisoned")
+            .get(&peer)
+            .copied()
+    }
+
+    /// Orders the peers from the best to the worst score. Peers we have not
+    /// sent any requests to yet come first, so every peer gets a score.
+    pub fn ranked(&self, peers: impl IntoIterator<Item = PeerId>) -> Vec<PeerId> {
+        let scores = self.0.lock().expect("Peer scores lock poisoned");
+
+        let mut peers = peers.into_iter().collect::<Vec<_>>();
+
+        peers.sort_by(|a, b| {
+            let cost_a = scores.get(a).map_or(0.0, PeerScore::cost);
+            let cost_b = scores.get(b).map_or(0.0, PeerScore::cost);
+
+            cost_a.total_cmp(&cost_b)
+        });
+
+        peers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fedimint_core::PeerId;
+
+    use super::PeerScores;
+
+    #[test]
+    fn ranks_fast_and_reliable_peers_first() {
+        let scores = PeerScores::default();
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+
+        scores.record(peers[0], Duration::from_millis(300), true);
+        scores.record(peers[1], Duration::from_millis(50), true);
+        scores.record(peers[2], Duration::from_millis(10), false);
+
+        assert_eq!(
+            scores.ranked(peers.clone()),
+            vec![peers[3], peers[1], peers[0], peers[2]]
+        );
+
+        // A single slow response does not outweigh the history of a peer
+        scores.record(peers[1], Duration::from_millis(1000), true);
+
+        assert_eq!(scores.get(peers[1]).unwrap().latency_ms, 240.0);
+        assert_eq!(scores.get(peers[1]).unwrap().requests, 2);
+    }
+}
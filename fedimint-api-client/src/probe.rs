@@ -0,0 +1,212 @@
+//! Health probing of a federation before joining it
+//!
+//! Wallets can show the user how healthy a federation is before they deposit
+//! any funds: how many guardians are reachable, how fast they respond, which
+//! API versions they support and whether they agree on the consensus height.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use fedimint_core::config::FederationId;
+use fedimint_core::endpoint_constants::{SESSION_COUNT_ENDPOINT, VERSION_ENDPOINT};
+use fedimint_core::invite_code::InviteCode;
+use fedimint_core::module::{ApiRequestErased, SupportedApiVersionsSummary};
+use fedimint_core::time::now;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{NumPeersExt, PeerId};
+use futures::future::join_all;
+use serde::Serialize;
+
+use crate::api::{FederationApiExt as _, WsFederationApi};
+use crate::download_api_endpoints;
+
+/// How long we wait for a single guardian to answer a probe request
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FederationHealthReport {
+    pub federation_id: FederationId,
+    pub peers: BTreeMap<PeerId, PeerHealth>,
+    /// Highest session count reported by any guardian
+    pub session_count: Option<u64>,
+    /// Number of sessions the slowest reachable guardian is behind the
+    /// fastest one
+    pub session_count_skew: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerHealth {
+    pub name: String,
+    pub url: SafeUrl,
+    /// Round-trip time of the version request, `None` if unreachable
+    pub latency: Option<Duration>,
+    pub api_versions: Option<SupportedApiVersionsSummary>,
+    pub session_count: Option<u64>,
+    /// The first error the guardian returned, if any
+    pub error: Option<String>,
+}
+
+impl PeerHealth {
+    pub fn is_reachable(&self) -> bool {
+        self.latency.is_some()
+    }
+}
+
+impl FederationHealthReport {
+    fn new(federation_id: FederationId, peers: BTreeMap<PeerId, PeerHealth>) -> Self {
+        let session_counts = peers
+            .values()
+            .filter_map(|peer| peer.session_count)
+            .collect::<Vec<_>>();
+
+        let max = session_counts.iter().max().copied();
+        let min = session_counts.iter().min().copied();
+
+        FederationHealthReport {
+            federation_id,
+            peers,
+            session_count: max,
+            session_count_skew: max.zip(min).map(|(max, min)| max - min),
+        }
+    }
+
+    pub fn reachable_peers(&self) -> usize {
+        self.peers
+            .values()
+            .filter(|peer| peer.is_reachable())
+            .count()
+    }
+
+    /// Whether enough guardians are reachable for the federation to process
+    /// transactions
+    pub fn is_operational(&self) -> bool {
+        self.peers.threshold() <= self.reachable_peers()
+    }
+}
+
+/// Contacts all guardians of the federation concurrently and reports which of
+/// them are reachable, their latency, API versions and session count
+///
+/// Fails only if the api endpoints of the guardians can not be downloaded via
+/// the invite code, unreachable guardians are part of the report.
+pub async fn probe_federation(invite_code: &InviteCode) -> anyhow::Result<FederationHealthReport> {
+    let api_endpoints = download_api_endpoints(invite_code).await?;
+
+    let api = WsFederationApi::new(
+        api_endpoints
+            .iter()
+            .map(|(peer, url)| (*peer, url.url.clone()))
+            .collect(),
+    );
+
+    let peers = join_all(api_endpoints.into_iter().map(|(peer, url)| {
+        let api = &api;
+
+        async move { (peer, probe_peer(api, peer, url.name, url.url).await) }
+    }))
+    .await
+    .into_iter()
+    .collect();
+
+    Ok(FederationHealthReport::new(
+        invite_code.federation_id(),
+        peers,
+    ))
+}
+
+async fn probe_peer(api: &WsFederationApi, peer: PeerId, name: String, url: SafeUrl) -> PeerHealth {
+    let mut health = PeerHealth {
+        name,
+        url,
+        latency: None,
+        api_versions: None,
+        session_count: None,
+        error: None,
+    };
+
+    let start = now();
+
+    let api_versions = api
+        .request_single_peer(
+            Some(PROBE_TIMEOUT),
+            VERSION_ENDPOINT.to_owned(),
+            ApiRequestErased::default(),
+            peer,
+        )
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|value| {
+            Ok(serde_json::from_value::<SupportedApiVersionsSummary>(
+                value,
+            )?)
+        });
+
+    match api_versions {
+        Ok(api_versions) => {
+            health.latency = Some(now().duration_since(start).unwrap_or_default());
+            health.api_versions = Some(api_versions);
+        }
+        Err(error) => {
+            health.error = Some(error.to_string());
+            return health;
+        }
+    }
+
+    let session_count = api
+        .request_single_peer(
+            Some(PROBE_TIMEOUT),
+            SESSION_COUNT_ENDPOINT.to_owned(),
+            ApiRequestErased::default(),
+            peer,
+        )
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|value| Ok(serde_json::from_value::<u64>(value)?));
+
+    match session_count {
+        Ok(session_count) => health.session_count = Some(session_count),
+        Err(error) => health.error = Some(error.to_string()),
+    }
+
+    health
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use fedimint_core::config::FederationId;
+    use fedimint_core::util::SafeUrl;
+    use fedimint_core::PeerId;
+
+    use super::{FederationHealthReport, PeerHealth};
+
+    #[test]
+    fn reports_session_count_skew_of_reachable_peers() {
+        let peer = |session_count: Option<u64>| PeerHealth {
+            name: "guardian".to_owned(),
+            url: SafeUrl::from_str("ws://127.0.0.1:5000").unwrap(),
+            latency: session_count.map(|_| Duration::from_millis(100)),
+            api_versions: None,
+            session_count,
+            error: None,
+        };
+
+        let report = FederationHealthReport::new(
+            FederationId::dummy(),
+            BTreeMap::from([
+                (PeerId::from(0), peer(Some(10))),
+                (PeerId::from(1), peer(Some(12))),
+                (PeerId::from(2), peer(Some(12))),
+                (PeerId::from(3), peer(None)),
+            ]),
+        );
+
+        assert_eq!(report.session_count, Some(12));
+        assert_eq!(report.session_count_skew, Some(2));
+        assert_eq!(report.reachable_peers(), 3);
+        assert!(report.is_operational());
+    }
+}
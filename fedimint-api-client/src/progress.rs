@@ -0,0 +1,54 @@
+//! Progress of long running operations
+//!
+//! Operations like verifying a long consensus history or recovering the
+//! client modules can take minutes. Wallets pass a [`ProgressReporter`] to
+//! them to show an accurate progress bar instead of a spinner.
+
+use std::sync::Arc;
+
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::task::{MaybeSend, MaybeSync};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressOperation {
+    /// Verifying the signatures of the consensus history, see
+    /// [`crate::history::verify_signed_history_with_progress`]
+    VerifyHistory,
+    /// Recovering the state of a client module from the consensus history
+    RecoverModule(ModuleInstanceId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressEvent {
+    pub operation: ProgressOperation,
+    pub completed: u64,
+    /// `None` if the operation does not know how much work is left
+    pub total: Option<u64>,
+}
+
+/// Receives the progress events of long running operations
+///
+/// Reporters are called from the task doing the work, so they should return
+/// quickly, e.g. by forwarding the event over a channel.
+pub trait ProgressReporter: MaybeSend + MaybeSync {
+    fn report(&self, event: ProgressEvent);
+}
+
+pub type DynProgressReporter = Arc<dyn ProgressReporter>;
+
+impl<F> ProgressReporter for F
+where
+    F: Fn(ProgressEvent) + MaybeSend + MaybeSync,
+{
+    fn report(&self, event: ProgressEvent) {
+        self(event);
+    }
+}
+
+/// Discards all progress events
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl ProgressReporter for NoProgress {
+    fn report(&self, _event: ProgressEvent) {}
+}
@@ -9,7 +9,7 @@ use fedimint_core::module::{
 };
 use fedimint_core::task::{MaybeSend, MaybeSync};
 use fedimint_core::time::now;
-use fedimint_core::{maybe_add_send_sync, PeerId};
+use fedimint_core::{maybe_add_send_sync, NumPeers, NumPeersExt, PeerId};
 use itertools::Itertools;
 
 use crate::api::{self, ApiVersionSet, PeerError, PeerResult};
@@ -25,9 +25,219 @@ pub trait QueryStrategy<IR, OR = IR> {
     fn request_timeout(&self) -> Option<Duration> {
         None
     }
+    /// Time after which the whole query fails with a [`api::QueryTimeout`] if
+    /// the strategy has not returned yet, including all retries
+    fn query_deadline(&self) -> Option<Duration> {
+        None
+    }
+    /// Which of the peers the query is sent to initially
+    fn peer_selection(&self) -> PeerSelection {
+        PeerSelection::All
+    }
+    /// How long to wait before retrying a peer after [`QueryStep::Retry`]
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
     fn process(&mut self, peer_id: PeerId, response: api::PeerResult<IR>) -> QueryStep<OR>;
 }
 
+/// Bounds the time of every single request and of the whole query of another
+/// strategy, so a hung peer can't stall the query indefinitely
+pub struct WithTimeouts<S> {
+    inner: S,
+    request_timeout: Option<Duration>,
+    query_deadline: Option<Duration>,
+}
+
+impl<S> WithTimeouts<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            request_timeout: None,
+            query_deadline: None,
+        }
+    }
+
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_query_deadline(mut self, deadline: Duration) -> Self {
+        self.query_deadline = Some(deadline);
+        self
+    }
+}
+
+impl<IR, OR, S: QueryStrategy<IR, OR>> QueryStrategy<IR, OR> for WithTimeouts<S> {
+    fn request_timeout(&self) -> Option<Duration> {
+        self.request_timeout.or(self.inner.request_timeout())
+    }
+
+    fn query_deadline(&self) -> Option<Duration> {
+        self.query_deadline.or(self.inner.query_deadline())
+    }
+
+    fn peer_selection(&self) -> PeerSelection {
+        self.inner.peer_selection()
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.inner.retry_policy()
+    }
+
+    fn process(&mut self, peer_id: PeerId, response: api::PeerResult<IR>) -> QueryStep<OR> {
+        self.inner.process(peer_id, response)
+    }
+}
+
+/// Peers a query is sent to before the strategy has processed any response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerSelection {
+    /// Send the request to every peer at once
+    All,
+    /// Send the request to the `count` best scoring peers first and add the
+    /// next best peer whenever `expand_after` passes without a result or all
+    /// requests sent so far have been answered
+    FastestResponders {
+        count: usize,
+        expand_after: Duration,
+    },
+}
+
+/// Sends the queries of another strategy to the peers that answered fastest
+/// and most reliably in the past, see [`crate::peer_score::PeerScores`].
+///
+/// This avoids the load on the remaining guardians for queries that only need
+/// responses from a subset of them, while slow or faulty peers in that subset
+/// only delay the query by `expand_after`. All peers are queried eventually,
+/// so the wrapped strategy behaves as if it had queried all of them.
+pub struct FastestResponders<S> {
+    inner: S,
+    count: usize,
+    expand_after: Duration,
+}
+
+impl<S> FastestResponders<S> {
+    pub fn new(inner: S, count: usize, expand_after: Duration) -> Self {
+        assert!(count > 0);
+
+        Self {
+            inner,
+            count,
+            expand_after,
+        }
+    }
+}
+
+impl<IR, OR, S: QueryStrategy<IR, OR>> QueryStrategy<IR, OR> for FastestResponders<S> {
+    fn request_timeout(&self) -> Option<Duration> {
+        self.inner.request_timeout()
+    }
+
+    fn query_deadline(&self) -> Option<Duration> {
+        self.inner.query_deadline()
+    }
+
+    fn peer_selection(&self) -> PeerSelection {
+        PeerSelection::FastestResponders {
+            count: self.count,
+            expand_after: self.expand_after,
+        }
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.inner.retry_policy()
+    }
+
+    fn process(&mut self, peer_id: PeerId, response: api::PeerResult<IR>) -> QueryStep<OR> {
+        self.inner.process(peer_id, response)
+    }
+}
+
+/// Exponential backoff between the retries of a peer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Delay before the first retry
+    pub base: Duration,
+    /// Factor the delay grows by with every retry of the same peer
+    pub multiplier: f64,
+    /// Upper bound of the delay
+    pub max: Duration,
+    /// Share of the delay, between 0 and 1, that is randomly subtracted from
+    /// it, so retries of many clients do not hit the peers at the same time
+    pub jitter: f64,
+    /// Number of retries of a single peer after which the query fails, or
+    /// `None` to retry indefinitely
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            base: Duration::from_millis(20),
+            multiplier: 2.0,
+            max: Duration::from_secs(1),
+            jitter: 0.0,
+            max_attempts: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the `attempt`-th retry of a peer, starting at one
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+
+        let delay =
+            (self.base.as_secs_f64() * self.multiplier.powi(exponent)).min(self.max.as_secs_f64());
+
+        let jitter = self.jitter.clamp(0.0, 1.0) * rand::random::<f64>();
+
+        Duration::from_secs_f64(delay * (1.0 - jitter))
+    }
+
+    /// Whether the budget allows the `attempt`-th retry of a peer
+    pub fn allows(&self, attempt: u32) -> bool {
+        self.max_attempts
+            .map_or(true, |max_attempts| attempt <= max_attempts)
+    }
+}
+
+/// Overrides the [`RetryPolicy`] of another strategy
+pub struct WithRetryPolicy<S> {
+    inner: S,
+    policy: RetryPolicy,
+}
+
+impl<S> WithRetryPolicy<S> {
+    pub fn new(inner: S, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+}
+
+impl<IR, OR, S: QueryStrategy<IR, OR>> QueryStrategy<IR, OR> for WithRetryPolicy<S> {
+    fn request_timeout(&self) -> Option<Duration> {
+        self.inner.request_timeout()
+    }
+
+    fn query_deadline(&self) -> Option<Duration> {
+        self.inner.query_deadline()
+    }
+
+    fn peer_selection(&self) -> PeerSelection {
+        self.inner.peer_selection()
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.policy
+    }
+
+    fn process(&mut self, peer_id: PeerId, response: api::PeerResult<IR>) -> QueryStep<OR> {
+        self.inner.process(peer_id, response)
+    }
+}
+
 /// Results from the strategy handling a response from a peer
 ///
 /// Note that the implementation driving the [`QueryStrategy`] returning
@@ -78,6 +288,11 @@ impl ErrorStrategy {
     }
 
     pub fn process<R>(&mut self, peer: PeerId, error: PeerError) -> QueryStep<R> {
+        // An overloaded guardian will answer later, so its error is not final
+        if error.api_error().is_retry_later() {
+            return QueryStep::Retry(BTreeSet::from([peer]));
+        }
+
         assert!(self.errors.insert(peer, error).is_none());
 
         if self.errors.len() == self.threshold {
@@ -108,13 +323,11 @@ impl<R, T> FilterMap<R, T> {
     /// a signature)
     pub fn new(
         filter_map: impl Fn(R) -> anyhow::Result<T> + MaybeSend + MaybeSync + 'static,
-        total_peers: usize,
+        num_peers: NumPeers,
     ) -> Self {
-        let max_evil = (total_peers - 1) / 3;
-
         Self {
             filter_map: Box::new(filter_map),
-            error_strategy: ErrorStrategy::new(max_evil + 1),
+            error_strategy: ErrorStrategy::new(num_peers.one_honest()),
         }
     }
 }
@@ -146,16 +359,13 @@ pub struct FilterMapThreshold<R, T> {
 impl<R, T> FilterMapThreshold<R, T> {
     pub fn new(
         verifier: impl Fn(PeerId, R) -> anyhow::Result<T> + MaybeSend + MaybeSync + 'static,
-        total_peers: usize,
+        num_peers: NumPeers,
     ) -> Self {
-        let max_evil = (total_peers - 1) / 3;
-        let threshold = total_peers - max_evil;
-
         Self {
             filter_map: Box::new(verifier),
-            error_strategy: ErrorStrategy::new(max_evil + 1),
+            error_strategy: ErrorStrategy::new(num_peers.one_honest()),
             filtered_responses: BTreeMap::new(),
-            threshold,
+            threshold: num_peers.threshold(),
         }
     }
 }
@@ -191,15 +401,14 @@ pub struct ThresholdConsensus<R> {
 }
 
 impl<R> ThresholdConsensus<R> {
-    pub fn new(total_peers: usize) -> Self {
-        let max_evil = (total_peers - 1) / 3;
-        let threshold = total_peers - max_evil;
-
+    /// Requires identical responses from a threshold of the `num_peers`
+    /// guardians, so at least one of them is honest
+    pub fn new(num_peers: NumPeers) -> Self {
         Self {
-            error_strategy: ErrorStrategy::new(max_evil + 1),
+            error_strategy: ErrorStrategy::new(num_peers.one_honest()),
             responses: BTreeMap::new(),
             retry: BTreeSet::new(),
-            threshold,
+            threshold: num_peers.threshold(),
         }
     }
 }
@@ -255,14 +464,11 @@ pub struct UnionResponses<R> {
 }
 
 impl<R> UnionResponses<R> {
-    pub fn new(total_peers: usize) -> Self {
-        let max_evil = (total_peers - 1) / 3;
-        let threshold = total_peers - max_evil;
-
+    pub fn new(num_peers: NumPeers) -> Self {
         Self {
-            error_strategy: ErrorStrategy::new(max_evil + 1),
+            error_strategy: ErrorStrategy::new(num_peers.one_honest()),
             responses: HashMap::new(),
-            threshold,
+            threshold: num_peers.threshold(),
         }
     }
 }
@@ -310,15 +516,12 @@ pub struct UnionResponsesSingle<R> {
 }
 
 impl<R> UnionResponsesSingle<R> {
-    pub fn new(total_peers: usize) -> Self {
-        let max_evil = (total_peers - 1) / 3;
-        let threshold = total_peers - max_evil;
-
+    pub fn new(num_peers: NumPeers) -> Self {
         Self {
-            error_strategy: ErrorStrategy::new(max_evil + 1),
+            error_strategy: ErrorStrategy::new(num_peers.one_honest()),
             responses: HashSet::new(),
             union: vec![],
-            threshold,
+            threshold: num_peers.threshold(),
         }
     }
 }
@@ -687,3 +890,102 @@ fn discover_common_api_versions_set(
             .collect(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    use fedimint_core::module::ApiError;
+    use fedimint_core::{NumPeers, PeerId};
+
+    use super::{QueryStep, QueryStrategy, RetryPolicy, ThresholdConsensus};
+    use crate::api::PeerError;
+
+    #[test]
+    fn retry_policy_backs_off_exponentially() {
+        let policy = RetryPolicy {
+            base: Duration::from_millis(100),
+            multiplier: 3.0,
+            max: Duration::from_secs(1),
+            jitter: 0.0,
+            max_attempts: Some(3),
+        };
+
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(300));
+        assert_eq!(policy.delay(3), Duration::from_millis(900));
+        assert_eq!(policy.delay(4), Duration::from_secs(1));
+
+        assert!(policy.allows(3));
+        assert!(!policy.allows(4));
+
+        let jittered = RetryPolicy {
+            jitter: 0.5,
+            ..policy
+        };
+
+        for attempt in 1..5 {
+            assert!(jittered.delay(attempt) <= policy.delay(attempt));
+            assert!(jittered.delay(attempt) >= policy.delay(attempt) / 2);
+        }
+    }
+
+    #[test]
+    fn threshold_consensus_derives_threshold_from_num_peers() {
+        let mut strategy = ThresholdConsensus::<u64>::new(NumPeers::from(4));
+
+        assert!(matches!(
+            strategy.process(PeerId::from(0), Ok(1)),
+            QueryStep::Continue
+        ));
+        assert!(matches!(
+            strategy.process(PeerId::from(1), Ok(1)),
+            QueryStep::Continue
+        ));
+        assert!(matches!(
+            strategy.process(PeerId::from(2), Ok(1)),
+            QueryStep::Success(1)
+        ));
+
+        // A single faulty guardian out of four can't fail the query
+        let mut strategy = ThresholdConsensus::<u64>::new(NumPeers::from(4));
+
+        assert!(matches!(
+            strategy.process(
+                PeerId::from(0),
+                Err(PeerError::InvalidResponse("faulty".to_string()))
+            ),
+            QueryStep::Continue
+        ));
+        assert!(matches!(
+            strategy.process(
+                PeerId::from(1),
+                Err(PeerError::InvalidResponse("faulty".to_string()))
+            ),
+            QueryStep::Failure { .. }
+        ));
+    }
+
+    #[test]
+    fn overloaded_peer_is_retried_instead_of_failing() {
+        let mut strategy = ThresholdConsensus::<u64>::new(NumPeers::from(4));
+
+        let mempool_full = || {
+            PeerError::Rpc(jsonrpsee_core::client::Error::Call(
+                jsonrpsee_types::ErrorObject::owned(
+                    ApiError::UNAVAILABLE,
+                    "Mempool is full",
+                    None::<()>,
+                ),
+            ))
+        };
+
+        for peer in 0..4 {
+            assert!(matches!(
+                strategy.process(PeerId::from(peer), Err(mempool_full())),
+                QueryStep::Retry(peers) if peers == BTreeSet::from([PeerId::from(peer)])
+            ));
+        }
+    }
+}
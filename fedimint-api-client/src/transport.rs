@@ -0,0 +1,271 @@
+//! Transports the API client connects to the guardians with
+//!
+//! By default the client connects to every guardian directly with a websocket.
+//! Wallets in restrictive network environments can instead connect through a
+//! SOCKS5 proxy like Tor, or fall back to plain HTTP requests. Requests for
+//! results that are not available yet block on the guardian, so over HTTP they
+//! become long polls.
+
+use std::fmt::{self, Debug};
+use std::marker::PhantomData;
+use std::sync::Arc;
+#[cfg(not(target_family = "wasm"))]
+use std::time::Duration;
+
+use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{apply, async_trait_maybe_send};
+use fedimint_logging::LOG_CLIENT_NET_API;
+use jsonrpsee_core::client::{ClientT, Error as JsonRpcClientError};
+use jsonrpsee_core::params::BatchRequestBuilder;
+use serde_json::Value;
+use tracing::debug;
+
+use crate::api::{JsonRpcClient, JsonRpcResult};
+
+/// An established connection to a single guardian
+#[apply(async_trait_maybe_send!)]
+pub trait ClientConnection: Debug + MaybeSend + MaybeSync {
+    async fn request(&self, method: &str, params: &[Value]) -> JsonRpcResult<Value>;
+
+    /// Sends all requests in a single JSON-RPC batch and returns their
+    /// results in the order of the requests
+    async fn batch_request(
+        &self,
+        requests: &[(String, Vec<Value>)],
+    ) -> JsonRpcResult<Vec<JsonRpcResult<Value>>>;
+
+    /// Whether requests can still be sent, otherwise the client reconnects
+    fn is_connected(&self) -> bool;
+}
+
+pub type DynClientConnection = Arc<dyn ClientConnection>;
+
+#[apply(async_trait_maybe_send!)]
+impl<C: JsonRpcClient + Debug> ClientConnection for C {
+    async fn request(&self, method: &str, params: &[Value]) -> JsonRpcResult<Value> {
+        ClientT::request(self, method, params).await
+    }
+
+    async fn batch_request(
+        &self,
+        requests: &[(String, Vec<Value>)],
+    ) -> JsonRpcResult<Vec<JsonRpcResult<Value>>> {
+        send_batch(self, requests).await
+    }
+
+    fn is_connected(&self) -> bool {
+        JsonRpcClient::is_connected(self)
+    }
+}
+
+async fn send_batch(
+    client: &(impl ClientT + MaybeSync),
+    requests: &[(String, Vec<Value>)],
+) -> JsonRpcResult<Vec<JsonRpcResult<Value>>> {
+    let mut batch = BatchRequestBuilder::new();
+
+    for (method, params) in requests {
+        batch
+            .insert(method.as_str(), params.as_slice())
+            .map_err(JsonRpcClientError::ParseError)?;
+    }
+
+    Ok(client
+        .batch_request::<Value>(batch)
+        .await?
+        .into_iter()
+        .map(|result| result.map_err(|e| JsonRpcClientError::Call(e.into_owned())))
+        .collect())
+}
+
+/// Establishes the connections to the guardians, selected when the API is
+/// created with [`crate::api::WsFederationApi::new_with_transport`]
+#[apply(async_trait_maybe_send!)]
+pub trait ClientTransport: Debug + MaybeSend + MaybeSync + 'static {
+    async fn connect(&self, url: &SafeUrl) -> JsonRpcResult<DynClientConnection>;
+}
+
+pub type DynClientTransport = Arc<dyn ClientTransport>;
+
+/// Connects to the guardians directly with the client `C`
+pub struct DirectTransport<C>(PhantomData<fn() -> C>);
+
+impl<C> Debug for DirectTransport<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DirectTransport")
+    }
+}
+
+impl<C> Default for DirectTransport<C> {
+    fn default() -> Self {
+        DirectTransport(PhantomData)
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<C: JsonRpcClient + Debug + 'static> ClientTransport for DirectTransport<C> {
+    async fn connect(&self, url: &SafeUrl) -> JsonRpcResult<DynClientConnection> {
+        Ok(Arc::new(C::connect(url).await?))
+    }
+}
+
+/// Connects to the guardians with websockets tunneled through a SOCKS5 proxy,
+/// e.g. a local Tor daemon. The proxy resolves the host names, so guardians can
+/// be reached via their onion services.
+///
+/// TLS through the proxy is not supported yet, so only `ws://` urls can be
+/// reached. Onion services don't need it, their address authenticates them.
+#[cfg(not(target_family = "wasm"))]
+#[derive(Debug, Clone)]
+pub struct Socks5Transport {
+    proxy: std::net::SocketAddr,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Socks5Transport {
+    pub fn new(proxy: std::net::SocketAddr) -> Self {
+        Socks5Transport { proxy }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[apply(async_trait_maybe_send!)]
+impl ClientTransport for Socks5Transport {
+    async fn connect(&self, url: &SafeUrl) -> JsonRpcResult<DynClientConnection> {
+        use tokio_util::compat::TokioAsyncReadCompatExt as _;
+
+        if url.scheme() != "ws" {
+            return Err(JsonRpcClientError::Transport(anyhow::format_err!(
+                "Only ws:// urls can be reached through a SOCKS5 proxy"
+            )));
+        }
+
+        let host = url
+            .host_str()
+            .ok_or_else(|| JsonRpcClientError::Transport(anyhow::format_err!("Url has no host")))?
+            .to_owned();
+
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| JsonRpcClientError::Transport(anyhow::format_err!("Url has no port")))?;
+
+        let stream = tokio_socks::tcp::Socks5Stream::connect(self.proxy, (host.as_str(), port))
+            .await
+            .map_err(|e| JsonRpcClientError::Transport(e.into()))?;
+
+        let (sender, receiver) =
+            jsonrpsee_client_transport::ws::WsTransportClientBuilder::default()
+                .build_with_stream(url.clone().to_unsafe(), stream.compat())
+                .await
+                .map_err(|e| JsonRpcClientError::Transport(e.into()))?;
+
+        let client = jsonrpsee_core::client::ClientBuilder::default()
+            .max_concurrent_requests(u16::MAX as usize)
+            .build_with_tokio(sender, receiver);
+
+        Ok(Arc::new(client))
+    }
+}
+
+/// Sends every request as a separate HTTP request to the guardians, for
+/// networks that block websockets
+#[cfg(not(target_family = "wasm"))]
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    request_timeout: Duration,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Default for HttpTransport {
+    fn default() -> Self {
+        // Awaiting the outcome of a transaction or session can take minutes
+        HttpTransport {
+            request_timeout: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl HttpTransport {
+    pub fn with_request_timeout(self, request_timeout: Duration) -> Self {
+        HttpTransport { request_timeout }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[apply(async_trait_maybe_send!)]
+impl ClientTransport for HttpTransport {
+    async fn connect(&self, url: &SafeUrl) -> JsonRpcResult<DynClientConnection> {
+        let mut url = url.clone().to_unsafe();
+
+        let scheme = match url.scheme() {
+            "ws" => "http",
+            "wss" => "https",
+            scheme => scheme,
+        }
+        .to_owned();
+
+        url.set_scheme(&scheme).map_err(|()| {
+            JsonRpcClientError::Transport(anyhow::format_err!("Invalid url scheme {scheme}"))
+        })?;
+
+        let client = jsonrpsee_http_client::HttpClientBuilder::default()
+            .request_timeout(self.request_timeout)
+            .build(url.as_str())?;
+
+        Ok(Arc::new(HttpConnection(client)))
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[derive(Debug)]
+struct HttpConnection(jsonrpsee_http_client::HttpClient);
+
+#[cfg(not(target_family = "wasm"))]
+#[apply(async_trait_maybe_send!)]
+impl ClientConnection for HttpConnection {
+    async fn request(&self, method: &str, params: &[Value]) -> JsonRpcResult<Value> {
+        self.0.request(method, params).await
+    }
+
+    async fn batch_request(
+        &self,
+        requests: &[(String, Vec<Value>)],
+    ) -> JsonRpcResult<Vec<JsonRpcResult<Value>>> {
+        send_batch(&self.0, requests).await
+    }
+
+    fn is_connected(&self) -> bool {
+        // Every request opens its own connection
+        true
+    }
+}
+
+/// Connects with `fallback` if connecting with `primary` fails, e.g. with
+/// [`HttpTransport`] if websockets are blocked
+#[derive(Debug, Clone)]
+pub struct FallbackTransport {
+    primary: DynClientTransport,
+    fallback: DynClientTransport,
+}
+
+impl FallbackTransport {
+    pub fn new(primary: DynClientTransport, fallback: DynClientTransport) -> Self {
+        FallbackTransport { primary, fallback }
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl ClientTransport for FallbackTransport {
+    async fn connect(&self, url: &SafeUrl) -> JsonRpcResult<DynClientConnection> {
+        match self.primary.connect(url).await {
+            Ok(connection) => Ok(connection),
+            Err(err) => {
+                debug!(target: LOG_CLIENT_NET_API, %url, %err, "Connecting with fallback transport");
+
+                self.fallback.connect(url).await
+            }
+        }
+    }
+}
@@ -200,6 +200,9 @@ pub enum ClientCmd {
     },
     /// Returns the client config
     Config,
+    /// Show the federation meta approved by the guardians, like the
+    /// federation's name and terms of service
+    FederationMeta,
 }
 
 pub async fn handle_command(
@@ -657,6 +660,10 @@ pub async fn handle_command(
             let config = client.get_config_json();
             Ok(serde_json::to_value(config).expect("Client config is serializable"))
         }
+        ClientCmd::FederationMeta => {
+            let federation_meta = client.api().federation_meta().await?;
+            Ok(serde_json::to_value(federation_meta).expect("Federation meta is serializable"))
+        }
     }
 }
 
@@ -74,4 +74,8 @@ where
     ) -> <Locked<DB> as fedimint_core::db::IRawDatabase>::Transaction<'_> {
         self.inner.begin_transaction().await
     }
+
+    async fn compact_prefix(&self, key_prefix: &[u8]) -> anyhow::Result<()> {
+        self.inner.compact_prefix(key_prefix).await
+    }
 }
@@ -2,6 +2,9 @@
 // and db
 pub const FM_CLIENT_DIR_ENV: &str = "FM_CLIENT_DIR";
 
+// Env variable to select the database backend of the client
+pub const FM_CLIENT_DB_BACKEND_ENV: &str = "FM_CLIENT_DB_BACKEND";
+
 // Env variable to set the peer id of the guardian
 pub const FM_OUR_ID_ENV: &str = "FM_OUR_ID";
 
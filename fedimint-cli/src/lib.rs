@@ -4,7 +4,7 @@ pub mod envs;
 mod utils;
 
 use core::fmt;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
@@ -16,7 +16,7 @@ use std::{fs, result};
 
 use anyhow::format_err;
 use bip39::Mnemonic;
-use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use db_locked::LockedBuilder;
 use fedimint_aead::{encrypted_read, encrypted_write, get_encryption_key};
 use fedimint_api_client::api::{
@@ -27,11 +27,15 @@ use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitRegistry};
 use fedimint_client::module::ClientModule as _;
 use fedimint_client::secret::{get_default_client_secret, RootSecretStrategy};
 use fedimint_client::{AdminCreds, Client, ClientBuilder, ClientHandleArc};
-use fedimint_core::admin_client::{ConfigGenConnectionsRequest, ConfigGenParamsRequest};
+use fedimint_core::admin_client::{
+    CompactDbRequest, ConfigGenConnectionsRequest, ConfigGenParamsRequest, CreateInviteCodeRequest,
+    SetLogFilterRequest,
+};
 use fedimint_core::config::{
     ClientConfig, FederationId, FederationIdPrefix, ServerModuleConfigGenParamsRegistry,
 };
-use fedimint_core::db::{Database, DatabaseValue};
+use fedimint_core::db::{Database, DatabaseValue, IRawDatabaseExt};
+use fedimint_core::epoch::{FederationMeta, PeerEndpoints};
 use fedimint_core::invite_code::InviteCode;
 use fedimint_core::module::{ApiAuth, ApiRequestErased};
 use fedimint_core::util::{handle_version_hash_command, retry, ConstantBackoff, SafeUrl};
@@ -52,7 +56,7 @@ use tracing::{debug, info};
 use utils::parse_peer_id;
 
 use crate::client::ClientCmd;
-use crate::envs::{FM_CLIENT_DIR_ENV, FM_OUR_ID_ENV, FM_PASSWORD_ENV};
+use crate::envs::{FM_CLIENT_DB_BACKEND_ENV, FM_CLIENT_DIR_ENV, FM_OUR_ID_ENV, FM_PASSWORD_ENV};
 
 /// Type of output the cli produces
 #[derive(Serialize)]
@@ -190,6 +194,11 @@ struct Opts {
     #[arg(long = "data-dir", env = FM_CLIENT_DIR_ENV)]
     data_dir: Option<PathBuf>,
 
+    /// Database backend, switching from `rocksdb` to `sqlite` migrates the
+    /// existing database
+    #[arg(long, env = FM_CLIENT_DB_BACKEND_ENV, value_enum, default_value = "rocksdb")]
+    db_backend: DbBackend,
+
     /// Peer id of the guardian
     #[arg(env = FM_OUR_ID_ENV, long, value_parser = parse_peer_id)]
     our_id: Option<PeerId>,
@@ -207,6 +216,13 @@ struct Opts {
     command: Command,
 }
 
+/// Storage engine of the client database
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DbBackend {
+    Rocksdb,
+    Sqlite,
+}
+
 impl Opts {
     fn data_dir(&self) -> CliResult<&PathBuf> {
         self.data_dir
@@ -236,18 +252,41 @@ impl Opts {
         Ok(ApiAuth(password))
     }
 
-    async fn load_rocks_db(&self) -> CliResult<Database> {
-        debug!(target: LOG_CLIENT, "Loading client database");
-        let db_path = self.data_dir_create().await?.join("client.db");
+    async fn load_db(&self) -> CliResult<Database> {
+        debug!(target: LOG_CLIENT, backend = ?self.db_backend, "Loading client database");
+        let data_dir = self.data_dir_create().await?;
+        let db_path = data_dir.join("client.db");
         let lock_path = db_path.with_extension("db.lock");
-        Ok(LockedBuilder::new(&lock_path)
+        let locked = LockedBuilder::new(&lock_path)
             .await
-            .map_err_cli_msg("could not lock database")?
-            .with_db(
-                fedimint_rocksdb::RocksDb::open(db_path)
+            .map_err_cli_msg("could not lock database")?;
+
+        Ok(match self.db_backend {
+            DbBackend::Rocksdb => locked
+                .with_db(
+                    fedimint_rocksdb::RocksDb::open(db_path)
+                        .map_err_cli_msg("could not open database")?,
+                )
+                .into(),
+            DbBackend::Sqlite => locked
+                .with_db(
+                    fedimint_sqlite::open_or_migrate(data_dir.join("client.sqlite"), || {
+                        if !db_path.exists() {
+                            return Ok(None);
+                        }
+
+                        info!("Migrating the RocksDB client database to SQLite");
+
+                        Ok(Some(
+                            fedimint_rocksdb::RocksDbReadOnly::open_read_only(&db_path)?
+                                .into_database(),
+                        ))
+                    })
+                    .await
                     .map_err_cli_msg("could not open database")?,
-            )
-            .into())
+                )
+                .into(),
+        })
     }
 }
 
@@ -301,12 +340,114 @@ enum AdminCmd {
     /// Show the status according to the `status` endpoint
     Status,
 
+    /// Show the connection status and consensus lag of all peers according to
+    /// the `federation_status` endpoint
+    FederationStatus,
+
     /// Show an audit across all modules
     Audit,
 
     /// Download guardian config to back it up
     GuardianConfigBackup,
 
+    /// Write an encrypted snapshot of the guardian database into the backup
+    /// directory configured on the guardian
+    BackupDatabase,
+
+    /// Announce new endpoints of this guardian to the other guardians, e.g.
+    /// after moving it to a different host
+    UpdatePeerEndpoints {
+        /// New address other guardians connect to for consensus
+        #[arg(long)]
+        p2p_url: Option<SafeUrl>,
+        /// New address of the guardian's api
+        #[arg(long)]
+        api_url: Option<SafeUrl>,
+    },
+
+    /// Show the endpoints guardians announced after the federation was set up
+    PeerEndpoints,
+
+    /// Replace the TLS certificate of this guardian for the p2p connections.
+    /// Peers keep accepting the previous certificate for a grace period.
+    RotateTlsCert,
+
+    /// Create an invite code pointing at the given guardians. If it expires
+    /// or has a limited number of uses the guardians reject config downloads
+    /// with it afterwards.
+    CreateInviteCode {
+        /// Guardians whose API URLs are embedded, by default as many as needed
+        /// to always reach an honest one
+        #[arg(long = "peer")]
+        peers: Vec<PeerId>,
+        /// Number of seconds the invite code stays valid
+        #[arg(long)]
+        expires_in_secs: Option<u64>,
+        /// Number of config downloads every guardian serves for the invite
+        /// code
+        #[arg(long)]
+        max_uses: Option<u64>,
+    },
+
+    /// Approve the federation meta shown to users. It becomes active once a
+    /// threshold of guardians approved the identical meta, so all of them
+    /// have to pass the same arguments.
+    ProposeFederationMeta {
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long)]
+        icon_url: Option<SafeUrl>,
+        #[arg(long)]
+        welcome_message: Option<String>,
+        /// Terms of service users have to accept
+        #[arg(long)]
+        tos_url: Option<SafeUrl>,
+    },
+
+    /// Show the federation meta approved by guardians that is not active yet
+    FederationMetaProposals,
+
+    /// Vote to halt consensus after the given session for an upgrade. All
+    /// guardians halt once a threshold of them voted for the same session.
+    ScheduleUpgrade {
+        /// Index of the last session before the upgrade
+        session_index: u64,
+    },
+
+    /// Withdraw this guardian's vote for an upgrade
+    CancelUpgrade,
+
+    /// Show the guardians' votes on upcoming upgrades
+    UpgradeStatus,
+
+    /// Summarize the consensus items this guardian has queued but not proposed
+    /// yet
+    Mempool,
+
+    /// Show the time the guardian spent in the stages of the most recent
+    /// sessions
+    SessionTiming,
+
+    /// Show the number and size of the database entries of the guardian per
+    /// record type and module
+    DbStats,
+
+    /// Compact the database of the guardian to reclaim disk space
+    CompactDb {
+        /// Hex encoded key prefix as shown by `db-stats`, compacts the whole
+        /// database if none is given
+        #[arg(long = "prefix")]
+        prefixes: Vec<String>,
+    },
+
+    /// Change the log filter of the guardian until it is restarted
+    SetLogFilter {
+        /// Filter directives added to the ones the guardian was started with,
+        /// e.g. `fm::consensus=debug`. Restores the initial filter if empty.
+        #[arg(default_value = "")]
+        directives: String,
+    },
+
     Dkg(DkgAdminArgs),
 }
 
@@ -333,6 +474,17 @@ enum DkgAdminCmd {
     WsStatus,
     SetPassword,
     GetDefaultConfigGenParams,
+    /// Check the params for errors and likely misconfigurations without
+    /// setting them
+    ValidateSetupParams {
+        /// Guardian-defined key-value pairs that will be passed to the client
+        /// Must be a valid JSON object (Map<String, String>)
+        #[clap(long)]
+        meta_json: String,
+        /// The params (if leader) or just the local params (if follower)
+        #[clap(long)]
+        modules_json: String,
+    },
     SetConfigGenParams {
         /// Guardian-defined key-value pairs that will be passed to the client
         /// Must be a valid JSON object (Map<String, String>)
@@ -548,7 +700,7 @@ impl FedimintCli {
     }
 
     async fn make_client_builder(&self, cli: &Opts) -> CliResult<ClientBuilder> {
-        let db = cli.load_rocks_db().await?;
+        let db = cli.load_db().await?;
         let mut client_builder = Client::builder(db);
         client_builder.with_module_inits(self.module_inits.clone());
         client_builder.with_primary_module(1);
@@ -662,7 +814,7 @@ impl FedimintCli {
     async fn handle_command(&mut self, cli: Opts) -> CliOutputResult {
         match cli.command.clone() {
             Command::InviteCode { peer } => {
-                let db = cli.load_rocks_db().await?;
+                let db = cli.load_db().await?;
                 let client_config = Client::get_config_from_db(&db)
                     .await
                     .ok_or_cli_msg("client config code not found")?;
@@ -737,6 +889,17 @@ impl FedimintCli {
                     serde_json::to_value(status).map_err_cli_msg("invalid response")?,
                 ))
             }
+            Command::Admin(AdminCmd::FederationStatus) => {
+                let client = self.client_open(&cli).await?;
+
+                let federation_status = cli
+                    .admin_client(client.get_config())?
+                    .federation_status()
+                    .await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(federation_status).map_err_cli_msg("invalid response")?,
+                ))
+            }
             Command::Admin(AdminCmd::GuardianConfigBackup) => {
                 let client = self.client_open(&cli).await?;
 
@@ -749,6 +912,178 @@ impl FedimintCli {
                         .map_err_cli_msg("invalid response")?,
                 ))
             }
+            Command::Admin(AdminCmd::BackupDatabase) => {
+                let client = self.client_open(&cli).await?;
+
+                let database_backup = cli
+                    .admin_client(client.get_config())?
+                    .backup_database(cli.auth()?)
+                    .await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(database_backup).map_err_cli_msg("invalid response")?,
+                ))
+            }
+            Command::Admin(AdminCmd::UpdatePeerEndpoints { p2p_url, api_url }) => {
+                if p2p_url.is_none() && api_url.is_none() {
+                    return Err(CliError {
+                        error: "At least one of --p2p-url and --api-url is required".to_string(),
+                    });
+                }
+
+                let client = self.client_open(&cli).await?;
+
+                cli.admin_client(client.get_config())?
+                    .update_peer_endpoints(PeerEndpoints { p2p_url, api_url }, cli.auth()?)
+                    .await?;
+                Ok(CliOutput::Raw(serde_json::Value::Null))
+            }
+            Command::Admin(AdminCmd::PeerEndpoints) => {
+                let client = self.client_open(&cli).await?;
+
+                let peer_endpoints = cli
+                    .admin_client(client.get_config())?
+                    .peer_endpoints()
+                    .await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(peer_endpoints).map_err_cli_msg("invalid response")?,
+                ))
+            }
+            Command::Admin(AdminCmd::RotateTlsCert) => {
+                let client = self.client_open(&cli).await?;
+
+                cli.admin_client(client.get_config())?
+                    .rotate_tls_cert(cli.auth()?)
+                    .await?;
+                Ok(CliOutput::Raw(serde_json::Value::Null))
+            }
+            Command::Admin(AdminCmd::CreateInviteCode {
+                peers,
+                expires_in_secs,
+                max_uses,
+            }) => {
+                let client = self.client_open(&cli).await?;
+
+                let request = CreateInviteCodeRequest {
+                    peers: peers.into_iter().collect::<BTreeSet<PeerId>>(),
+                    expires_at: expires_in_secs
+                        .map(|secs| fedimint_core::time::duration_since_epoch().as_secs() + secs),
+                    max_uses,
+                };
+
+                let invite_code = cli
+                    .admin_client(client.get_config())?
+                    .create_invite_code(request, cli.auth()?)
+                    .await?;
+                Ok(CliOutput::InviteCode { invite_code })
+            }
+            Command::Admin(AdminCmd::ProposeFederationMeta {
+                name,
+                icon_url,
+                welcome_message,
+                tos_url,
+            }) => {
+                let client = self.client_open(&cli).await?;
+
+                let meta = FederationMeta {
+                    name,
+                    icon_url,
+                    welcome_message,
+                    tos_url,
+                };
+
+                cli.admin_client(client.get_config())?
+                    .propose_federation_meta(meta, cli.auth()?)
+                    .await?;
+                Ok(CliOutput::Raw(serde_json::Value::Null))
+            }
+            Command::Admin(AdminCmd::FederationMetaProposals) => {
+                let client = self.client_open(&cli).await?;
+
+                let proposals = cli
+                    .admin_client(client.get_config())?
+                    .federation_meta_proposals()
+                    .await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(proposals).map_err_cli_msg("invalid response")?,
+                ))
+            }
+            Command::Admin(AdminCmd::ScheduleUpgrade { session_index }) => {
+                let client = self.client_open(&cli).await?;
+
+                cli.admin_client(client.get_config())?
+                    .schedule_upgrade(session_index, cli.auth()?)
+                    .await?;
+                Ok(CliOutput::Raw(serde_json::Value::Null))
+            }
+            Command::Admin(AdminCmd::CancelUpgrade) => {
+                let client = self.client_open(&cli).await?;
+
+                cli.admin_client(client.get_config())?
+                    .cancel_upgrade(cli.auth()?)
+                    .await?;
+                Ok(CliOutput::Raw(serde_json::Value::Null))
+            }
+            Command::Admin(AdminCmd::UpgradeStatus) => {
+                let client = self.client_open(&cli).await?;
+
+                let upgrade_status = cli
+                    .admin_client(client.get_config())?
+                    .upgrade_status()
+                    .await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(upgrade_status).map_err_cli_msg("invalid response")?,
+                ))
+            }
+            Command::Admin(AdminCmd::Mempool) => {
+                let client = self.client_open(&cli).await?;
+
+                let mempool = cli
+                    .admin_client(client.get_config())?
+                    .mempool(cli.auth()?)
+                    .await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(mempool).map_err_cli_msg("invalid response")?,
+                ))
+            }
+            Command::Admin(AdminCmd::SessionTiming) => {
+                let client = self.client_open(&cli).await?;
+
+                let session_timing = cli
+                    .admin_client(client.get_config())?
+                    .session_timing(cli.auth()?)
+                    .await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(session_timing).map_err_cli_msg("invalid response")?,
+                ))
+            }
+            Command::Admin(AdminCmd::DbStats) => {
+                let client = self.client_open(&cli).await?;
+
+                let db_stats = cli
+                    .admin_client(client.get_config())?
+                    .db_stats(cli.auth()?)
+                    .await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(db_stats).map_err_cli_msg("invalid response")?,
+                ))
+            }
+            Command::Admin(AdminCmd::CompactDb { prefixes }) => {
+                let client = self.client_open(&cli).await?;
+
+                cli.admin_client(client.get_config())?
+                    .compact_db(CompactDbRequest { prefixes }, cli.auth()?)
+                    .await?;
+                Ok(CliOutput::Raw(serde_json::Value::Null))
+            }
+            Command::Admin(AdminCmd::SetLogFilter { directives }) => {
+                let client = self.client_open(&cli).await?;
+
+                let directives = cli
+                    .admin_client(client.get_config())?
+                    .set_log_filter(SetLogFilterRequest { directives }, cli.auth()?)
+                    .await?;
+                Ok(CliOutput::Raw(serde_json::Value::String(directives)))
+            }
             Command::Admin(AdminCmd::Dkg(dkg_args)) => {
                 self.handle_admin_dkg_command(cli, dkg_args).await
             }
@@ -949,6 +1284,20 @@ impl FedimintCli {
                     serde_json::to_value(default_params).map_err_cli_msg("invalid response")?,
                 ))
             }
+            DkgAdminCmd::ValidateSetupParams {
+                meta_json,
+                modules_json,
+            } => {
+                let meta: BTreeMap<String, String> =
+                    serde_json::from_str(meta_json).map_err_cli_msg("Invalid JSON")?;
+                let modules: ServerModuleConfigGenParamsRegistry =
+                    serde_json::from_str(modules_json).map_err_cli_msg("Invalid JSON")?;
+                let params = ConfigGenParamsRequest { meta, modules };
+                let validation = client.validate_setup_params(params, cli.auth()?).await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(validation).map_err_cli_msg("invalid response")?,
+                ))
+            }
             DkgAdminCmd::SetConfigGenParams {
                 meta_json,
                 modules_json,
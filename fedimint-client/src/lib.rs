@@ -84,6 +84,8 @@ use db::{
 };
 use envs::get_discover_api_version_timeout;
 use fedimint_api_client::api::{ApiVersionSet, DynGlobalApi, DynModuleApi, IGlobalFederationApi};
+use fedimint_api_client::progress::{DynProgressReporter, ProgressEvent, ProgressOperation};
+use fedimint_api_client::transport::DynClientTransport;
 use fedimint_core::config::{
     ClientConfig, ClientModuleConfig, FederationId, JsonClientConfig, JsonWithKind,
     ModuleInitRegistry,
@@ -1651,6 +1653,7 @@ impl Client {
             ModuleInstanceId,
             watch::Receiver<RecoveryProgress>,
         >,
+        progress_reporter: Option<DynProgressReporter>,
     ) {
         let db = self.db.clone();
         self.task_group
@@ -1660,6 +1663,7 @@ impl Client {
                     recovery_sender,
                     module_recoveries,
                     module_recovery_progress_receivers,
+                    progress_reporter,
                 )
                 .await
             });
@@ -1676,6 +1680,7 @@ impl Client {
             ModuleInstanceId,
             watch::Receiver<RecoveryProgress>,
         >,
+        progress_reporter: Option<DynProgressReporter>,
     ) {
         debug!(target:LOG_CLIENT_RECOVERY, num_modules=%module_recovery_progress_receivers.len(), "Staring module recoveries");
         let mut completed_stream = Vec::new();
@@ -1740,6 +1745,16 @@ impl Client {
             .await;
             dbtx.commit_tx().await;
 
+            if let Some(progress_reporter) = &progress_reporter {
+                if !progress.is_none() {
+                    progress_reporter.report(ProgressEvent {
+                        operation: ProgressOperation::RecoverModule(module_instance_id),
+                        completed: progress.complete.into(),
+                        total: Some(progress.total.into()),
+                    });
+                }
+            }
+
             recovery_sender.send_modify(|v| {
                 v.insert(module_instance_id, progress);
             });
@@ -1787,6 +1802,8 @@ pub struct ClientBuilder {
     admin_creds: Option<AdminCreds>,
     db_no_decoders: Database,
     meta_service: Arc<MetaService>,
+    transport: Option<DynClientTransport>,
+    progress_reporter: Option<DynProgressReporter>,
     stopped: bool,
 }
 
@@ -1800,6 +1817,8 @@ impl ClientBuilder {
             db_no_decoders: db,
             stopped: false,
             meta_service,
+            transport: None,
+            progress_reporter: None,
         }
     }
 
@@ -1812,6 +1831,8 @@ impl ClientBuilder {
             stopped: false,
             // non unique
             meta_service: client.meta_service.clone(),
+            transport: None,
+            progress_reporter: None,
         }
     }
 
@@ -1849,6 +1870,29 @@ impl ClientBuilder {
         self.meta_service = meta_service;
     }
 
+    /// Connect to the guardians with `transport` instead of direct websocket
+    /// connections, e.g. through Tor
+    pub fn with_transport(&mut self, transport: DynClientTransport) {
+        self.transport = Some(transport);
+    }
+
+    /// Report the progress of the module recoveries to `progress_reporter`
+    pub fn with_progress_reporter(&mut self, progress_reporter: DynProgressReporter) {
+        self.progress_reporter = Some(progress_reporter);
+    }
+
+    fn federation_api(&self, config: &ClientConfig) -> DynGlobalApi {
+        let self_peer_id = self.admin_creds.as_ref().map(|creds| creds.peer_id);
+
+        match (&self.transport, self_peer_id) {
+            (Some(transport), self_peer_id) => {
+                DynGlobalApi::from_config_with_transport(config, self_peer_id, transport.clone())
+            }
+            (None, Some(self_peer_id)) => DynGlobalApi::from_config_admin(config, self_peer_id),
+            (None, None) => DynGlobalApi::from_config(config),
+        }
+    }
+
     async fn migrate_database(&self, db: &Database) -> anyhow::Result<()> {
         // Only apply the client database migrations if the database has been
         // initialized.
@@ -2019,7 +2063,7 @@ impl ClientBuilder {
         root_secret: &DerivableSecret,
         config: &ClientConfig,
     ) -> anyhow::Result<Option<ClientBackup>> {
-        let api = DynGlobalApi::from_config(config);
+        let api = self.federation_api(config);
         Client::download_backup_from_federation_static(
             &api,
             &Self::federation_root_secret(root_secret, config),
@@ -2095,11 +2139,7 @@ impl ClientBuilder {
         let config = Self::config_decoded(config, &decoders)?;
         let fed_id = config.calculate_federation_id();
         let db = self.db_no_decoders.with_decoders(decoders.clone());
-        let api = if let Some(admin_creds) = self.admin_creds.as_ref() {
-            DynGlobalApi::from_config_admin(&config, admin_creds.peer_id)
-        } else {
-            DynGlobalApi::from_config(&config)
-        };
+        let api = self.federation_api(&config);
         let task_group = TaskGroup::new();
 
         // Migrate the database before interacting with it in case any on-disk data
@@ -2355,6 +2395,7 @@ impl ClientBuilder {
                     client_recovery_progress_sender,
                     module_recoveries,
                     module_recovery_progress_receivers,
+                    self.progress_reporter,
                 )
                 .await;
         }
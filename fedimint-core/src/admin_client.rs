@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
 
 use fedimint_core::util::SafeUrl;
@@ -7,6 +7,7 @@ use serde::{Deserialize, Serialize};
 use tokio_rustls::rustls::Certificate as RustlsCertificate;
 
 use crate::config::ServerModuleConfigGenParamsRegistry;
+use crate::core::ModuleInstanceId;
 use crate::PeerId;
 
 /// The state of the server returned via APIs
@@ -31,6 +32,101 @@ pub enum ServerStatus {
     /// Restarted setup. All peers need to sync on this state before continuing
     /// to `SharingConfigGenParams`
     SetupRestarted,
+    /// Consensus halted at the session the guardians scheduled an upgrade for
+    AwaitingUpgrade,
+}
+
+/// Consensus items a guardian has queued but not proposed yet
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct MempoolSummary {
+    pub items: usize,
+    /// Size of all items in their consensus encoding
+    pub bytes: usize,
+    pub transactions: usize,
+    /// Milliseconds since the oldest item was queued
+    pub oldest_age_ms: Option<u64>,
+    pub modules: BTreeMap<ModuleInstanceId, MempoolModuleSummary>,
+}
+
+/// Contribution of a single module to the [`MempoolSummary`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct MempoolModuleSummary {
+    /// Module consensus items
+    pub consensus_items: usize,
+    /// Inputs of queued transactions
+    pub inputs: usize,
+    /// Outputs of queued transactions
+    pub outputs: usize,
+}
+
+/// Votes of the guardians on the session after which all of them halt to
+/// upgrade their software
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct UpgradeStatus {
+    /// Session index each guardian voted for, if it has not cancelled its vote
+    pub votes: BTreeMap<PeerId, u64>,
+    /// Earliest upcoming session a threshold of guardians voted for
+    pub scheduled_session: Option<u64>,
+}
+
+/// Parameters of an invite code created by a guardian
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct CreateInviteCodeRequest {
+    /// Guardians whose API URLs are embedded, if empty as many guardians as
+    /// needed to always reach an honest one are chosen
+    pub peers: BTreeSet<PeerId>,
+    /// Unix time in seconds after which the guardians reject config downloads
+    /// with the invite code
+    pub expires_at: Option<u64>,
+    /// Number of config downloads every guardian serves for the invite code
+    pub max_uses: Option<u64>,
+}
+
+/// Milliseconds the consensus engine spent in the stages of a session. The
+/// stages can overlap since items are proposed while others are processed.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct SessionTiming {
+    pub session_index: u64,
+    pub total_ms: u64,
+    /// Building the batches proposed to the atomic broadcast
+    pub proposal_ms: u64,
+    /// Time not spent processing or committing, mostly waiting for the
+    /// atomic broadcast to order batches
+    pub broadcast_ms: u64,
+    /// Validating and applying ordered items, summed up over items that were
+    /// processed concurrently
+    pub processing_ms: u64,
+    /// Auditing and committing the resulting database transactions
+    pub commit_ms: u64,
+}
+
+/// Number and size of the database entries with a key prefix
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct DbPrefixStats {
+    /// Hex encoded key prefix, which can be passed to `compact_db`
+    pub prefix: String,
+    /// Record type or module stored under the prefix
+    pub name: String,
+    pub entries: u64,
+    /// Combined size of the keys and values, which differs from the size on
+    /// disk due to compression and entries not compacted yet
+    pub size_bytes: u64,
+}
+
+/// Sent by admin user to the API
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct CompactDbRequest {
+    /// Hex encoded key prefixes to compact, the whole database if empty
+    pub prefixes: Vec<String>,
+}
+
+/// Sent by admin user to the API
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct SetLogFilterRequest {
+    /// `EnvFilter` directives applied on top of the ones the guardian was
+    /// started with, e.g. `fm::consensus=debug`. Empty to restore the initial
+    /// filter.
+    pub directives: String,
 }
 
 #[cfg(target_family = "wasm")]
@@ -93,6 +189,29 @@ pub struct ConfigGenParamsRequest {
     pub modules: ServerModuleConfigGenParamsRegistry,
 }
 
+/// Problems with proposed config gen params, found before any keys are
+/// generated
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct SetupParamsValidation {
+    /// Problems that would make the setup fail
+    pub errors: Vec<SetupParamsIssue>,
+    /// Params that are valid but likely a misconfiguration
+    pub warnings: Vec<SetupParamsIssue>,
+}
+
+impl SetupParamsValidation {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct SetupParamsIssue {
+    /// The module whose params the issue is about, `None` for the federation
+    pub module: Option<ModuleInstanceId>,
+    pub message: String,
+}
+
 mod serde_tls_cert {
     use std::borrow::Cow;
 
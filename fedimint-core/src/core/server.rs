@@ -99,6 +99,9 @@ pub trait IServerModule: Debug {
         module_instance_id: ModuleInstanceId,
     );
 
+    /// Checks the invariants of the module state, see [`ServerModule::verify`]
+    async fn verify(&self, dbtx: &mut DatabaseTransaction<'_>) -> Vec<String>;
+
     /// Returns a list of custom API endpoints defined by the module. These are
     /// made available both to users as well as to other modules. They thus
     /// should be deterministic, only dependant on their input and the
@@ -239,6 +242,10 @@ where
         <Self as ServerModule>::audit(self, dbtx, audit, module_instance_id).await;
     }
 
+    async fn verify(&self, dbtx: &mut DatabaseTransaction<'_>) -> Vec<String> {
+        <Self as ServerModule>::verify(self, dbtx).await
+    }
+
     fn api_endpoints(&self) -> Vec<ApiEndpoint<DynServerModule>> {
         <Self as ServerModule>::api_endpoints(self)
             .into_iter()
@@ -167,6 +167,12 @@ pub trait IRawDatabase: Debug + MaybeSend + MaybeSync + 'static {
 
     /// Start a database transaction
     async fn begin_transaction<'a>(&'a self) -> Self::Transaction<'a>;
+
+    /// Compact the storage of all entries with the prefix to reclaim disk
+    /// space, not supported by every implementation
+    async fn compact_prefix(&self, _key_prefix: &[u8]) -> Result<()> {
+        bail!("Compaction is not supported by this database")
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -179,6 +185,10 @@ where
     async fn begin_transaction<'a>(&'a self) -> Self::Transaction<'a> {
         (**self).begin_transaction().await
     }
+
+    async fn compact_prefix(&self, key_prefix: &[u8]) -> Result<()> {
+        (**self).compact_prefix(key_prefix).await
+    }
 }
 
 /// An extension trait with convenience operations on [`IRawDatabase`]
@@ -212,9 +222,15 @@ pub trait IDatabase: Debug + MaybeSend + MaybeSync + 'static {
     async fn register(&self, key: &[u8]);
     /// Notify about `key` update (creation, modification, deletion)
     async fn notify(&self, key: &[u8]);
+    /// Notify everyone waiting for any key, e.g. after another process
+    /// modified the database
+    async fn notify_all(&self);
 
     /// The prefix len of this database instance
     fn prefix_len(&self) -> usize;
+
+    /// Compact the storage of all entries with the prefix
+    async fn compact_prefix(&self, key_prefix: &[u8]) -> Result<()>;
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -231,10 +247,17 @@ where
     async fn notify(&self, key: &[u8]) {
         (**self).notify(key).await;
     }
+    async fn notify_all(&self) {
+        (**self).notify_all().await;
+    }
 
     fn prefix_len(&self) -> usize {
         (**self).prefix_len()
     }
+
+    async fn compact_prefix(&self, key_prefix: &[u8]) -> Result<()> {
+        (**self).compact_prefix(key_prefix).await
+    }
 }
 
 /// Base functionality around [`IRawDatabase`] to make it a [`IDatabase`]
@@ -265,10 +288,17 @@ impl<RawDatabase: IRawDatabase + MaybeSend + 'static> IDatabase for BaseDatabase
     async fn notify(&self, key: &[u8]) {
         self.notifications.notify(key).await;
     }
+    async fn notify_all(&self) {
+        self.notifications.notify_all();
+    }
 
     fn prefix_len(&self) -> usize {
         0
     }
+
+    async fn compact_prefix(&self, key_prefix: &[u8]) -> Result<()> {
+        self.raw.compact_prefix(key_prefix).await
+    }
 }
 
 /// A public-facing newtype over `IDatabase`
@@ -367,6 +397,19 @@ impl Database {
         Ok(())
     }
 
+    /// Compact the storage of all entries with the prefix to reclaim disk
+    /// space, while the database stays in use
+    pub async fn compact_prefix(&self, key_prefix: &[u8]) -> Result<()> {
+        self.inner.compact_prefix(key_prefix).await
+    }
+
+    /// Wakes up everyone waiting for a key to change. Only required if the
+    /// database is modified by another process, e.g. when following the
+    /// database of a guardian from a read-only replica.
+    pub async fn notify_all(&self) {
+        self.inner.notify_all().await;
+    }
+
     /// Begin a new committable database transaction
     pub async fn begin_transaction<'s, 'tx>(&'s self) -> DatabaseTransaction<'tx, Committable>
     where
@@ -535,7 +578,7 @@ impl Database {
     }
 }
 
-fn module_instance_id_to_byte_prefix(module_instance_id: u16) -> Vec<u8> {
+pub fn module_instance_id_to_byte_prefix(module_instance_id: u16) -> Vec<u8> {
     let mut prefix = vec![MODULE_GLOBAL_PREFIX];
     module_instance_id
         .consensus_encode(&mut prefix)
@@ -587,9 +630,19 @@ where
         self.inner.notify(&self.get_full_key(key)).await;
     }
 
+    async fn notify_all(&self) {
+        self.inner.notify_all().await;
+    }
+
     fn prefix_len(&self) -> usize {
         self.inner.prefix_len() + self.prefix.len()
     }
+
+    async fn compact_prefix(&self, key_prefix: &[u8]) -> Result<()> {
+        self.inner
+            .compact_prefix(&self.get_full_key(key_prefix))
+            .await
+    }
 }
 
 /// A database transactions that wraps an `inner` one and adds a prefix to all
@@ -64,6 +64,13 @@ impl Notifications {
         self.buckets[slot_index_for_key(key)].notify_waiters();
     }
 
+    /// Notify the waiters of all keys.
+    pub fn notify_all(&self) {
+        for bucket in &self.buckets {
+            bucket.notify_waiters();
+        }
+    }
+
     /// Notifies the waiters about the notifications recorded in NotifyQueue.
     pub fn submit_queue(&self, queue: &NotifyQueue) {
         for bucket in queue.buckets.iter_ones() {
@@ -144,6 +151,24 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_notify_all() {
+        let notifs = Notifications::new();
+        let key1 = 1;
+        let key2 = 2;
+        let sub1 = notifs.register(key1);
+        let sub2 = notifs.register(key2);
+        notifs.notify_all();
+        assert!(
+            future_returns_shortly(sub1).await.is_some(),
+            "should notify"
+        );
+        assert!(
+            future_returns_shortly(sub2).await.is_some(),
+            "should notify"
+        );
+    }
+
     #[tokio::test]
     async fn test_notify_queue() {
         let notifs = Notifications::new();
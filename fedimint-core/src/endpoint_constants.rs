@@ -4,6 +4,7 @@ pub const GUARDIAN_CONFIG_BACKUP_ENDPOINT: &str = "download_guardian_backup";
 pub const AUTH_ENDPOINT: &str = "auth";
 pub const AWAIT_OUTPUT_OUTCOME_ENDPOINT: &str = "await_output_outcome";
 pub const BACKUP_ENDPOINT: &str = "backup";
+pub const BACKUP_DATABASE_ENDPOINT: &str = "backup_database";
 pub const CLIENT_CONFIG_ENDPOINT: &str = "client_config";
 pub const SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT: &str = "server_config_consensus_hash";
 pub const SESSION_COUNT_ENDPOINT: &str = "session_count";
@@ -29,5 +30,26 @@ pub const VERIFIED_CONFIGS_ENDPOINT: &str = "verified_configs";
 pub const VERSION_ENDPOINT: &str = "version";
 pub const AWAIT_TRANSACTION_ENDPOINT: &str = "await_transaction";
 pub const INVITE_CODE_ENDPOINT: &str = "invite_code";
+pub const FEDERATION_STATUS_ENDPOINT: &str = "federation_status";
 pub const FEDERATION_ID_ENDPOINT: &str = "federation_id";
 pub const RESTART_FEDERATION_SETUP_ENDPOINT: &str = "restart_federation_setup";
+pub const CHECKPOINT_SIGNATURE_ENDPOINT: &str = "checkpoint_signature";
+pub const LATEST_CHECKPOINT_ENDPOINT: &str = "latest_checkpoint";
+pub const DOWNLOAD_CHECKPOINT_ENDPOINT: &str = "download_checkpoint";
+pub const UPDATE_PEER_ENDPOINTS_ENDPOINT: &str = "update_peer_endpoints";
+pub const PEER_ENDPOINTS_ENDPOINT: &str = "peer_endpoints";
+pub const ROTATE_TLS_CERT_ENDPOINT: &str = "rotate_tls_cert";
+pub const TRANSACTION_STATUS_ENDPOINT: &str = "transaction_status";
+pub const MEMPOOL_ENDPOINT: &str = "mempool";
+pub const SESSION_TIMING_ENDPOINT: &str = "session_timing";
+pub const SCHEDULE_UPGRADE_ENDPOINT: &str = "schedule_upgrade";
+pub const CANCEL_UPGRADE_ENDPOINT: &str = "cancel_upgrade";
+pub const UPGRADE_STATUS_ENDPOINT: &str = "upgrade_status";
+pub const PROPOSE_FEDERATION_META_ENDPOINT: &str = "propose_federation_meta";
+pub const FEDERATION_META_PROPOSALS_ENDPOINT: &str = "federation_meta_proposals";
+pub const FEDERATION_META_ENDPOINT: &str = "federation_meta";
+pub const CREATE_INVITE_CODE_ENDPOINT: &str = "create_invite_code";
+pub const VALIDATE_SETUP_PARAMS_ENDPOINT: &str = "validate_setup_params";
+pub const DB_STATS_ENDPOINT: &str = "db_stats";
+pub const COMPACT_DB_ENDPOINT: &str = "compact_db";
+pub const SET_LOG_FILTER_ENDPOINT: &str = "set_log_filter";
@@ -1,7 +1,12 @@
+use anyhow::ensure;
+use bitcoin_hashes::sha256;
 use fedimint_core::core::DynModuleConsensusItem as ModuleConsensusItem;
 use fedimint_core::encoding::{Decodable, Encodable};
+use serde::{Deserialize, Serialize};
 
+use crate::session_outcome::SchnorrSignature;
 use crate::transaction::Transaction;
+use crate::util::SafeUrl;
 
 /// All the items that may be produced during a consensus epoch
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
@@ -10,8 +15,124 @@ pub enum ConsensusItem {
     Transaction(Transaction),
     /// Any data that modules require consensus on
     Module(ModuleConsensusItem),
+    /// New addresses of the guardian that submitted the item
+    PeerEndpoints(PeerEndpoints),
+    /// New TLS certificate of the guardian that submitted the item
+    TlsCertRotation(TlsCertRotation),
+    /// Consensus state of the guardian that submitted the item after a session
+    StateHash(SessionStateHash),
+    /// Vote of the submitting guardian on when to halt for an upgrade
+    UpgradeVote(UpgradeVote),
+    /// Federation metadata the submitting guardian approves of
+    FederationMeta(FederationMeta),
     /// Allows us to add new items in the future without crashing old clients
     /// that try to interpret the session log.
     #[encodable_default]
     Default { variant: u64, bytes: Vec<u8> },
 }
+
+/// Addresses under which a guardian can be reached that differ from the ones
+/// in the federation config. Since consensus items are attributed to their
+/// submitter by the atomic broadcast, a guardian can only change its own
+/// addresses.
+#[derive(
+    Debug, Clone, Default, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize,
+)]
+pub struct PeerEndpoints {
+    /// Address for the p2p connections between guardians
+    pub p2p_url: Option<SafeUrl>,
+    /// Address of the guardian's API
+    pub api_url: Option<SafeUrl>,
+}
+
+impl PeerEndpoints {
+    /// Returns the endpoints after applying the non-empty fields of `update`
+    pub fn merge(&self, update: PeerEndpoints) -> PeerEndpoints {
+        PeerEndpoints {
+            p2p_url: update.p2p_url.or_else(|| self.p2p_url.clone()),
+            api_url: update.api_url.or_else(|| self.api_url.clone()),
+        }
+    }
+}
+
+/// Replaces the TLS certificate a guardian uses for the p2p connections
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct TlsCertRotation {
+    /// DER encoded certificate
+    pub cert: Vec<u8>,
+    /// Signature over the certificate by the guardian's broadcast key
+    pub signature: SchnorrSignature,
+}
+
+/// Hash over the consensus state a guardian arrived at after processing the
+/// session with `session_index`. Guardians that processed the same items have
+/// to arrive at the same state, so differing hashes reveal a divergence.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct SessionStateHash {
+    pub session_index: u64,
+    pub state_hash: sha256::Hash,
+}
+
+/// Once a threshold of guardians voted for the same session all guardians halt
+/// after completing it, so they can upgrade their software at the same point
+/// of the consensus history
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub enum UpgradeVote {
+    /// Halt after the session with the given index
+    Schedule { session_index: u64 },
+    /// Withdraw the previous vote
+    Cancel,
+}
+
+/// Maximum length of the federation name in bytes, which is short enough to be
+/// embedded in invite codes
+pub const FEDERATION_NAME_MAX_LEN: usize = 64;
+
+/// Maximum length of the welcome message in bytes
+pub const WELCOME_MESSAGE_MAX_LEN: usize = 1024;
+
+/// Information about the federation shown to users, e.g. before they join it.
+/// It becomes active once a threshold of guardians approved the identical
+/// metadata by submitting it.
+#[derive(
+    Debug, Clone, Default, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize,
+)]
+pub struct FederationMeta {
+    pub name: Option<String>,
+    pub icon_url: Option<SafeUrl>,
+    pub welcome_message: Option<String>,
+    /// Terms of service users have to accept
+    pub tos_url: Option<SafeUrl>,
+}
+
+impl FederationMeta {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(name) = &self.name {
+            ensure!(!name.is_empty(), "The name must not be empty");
+            ensure!(
+                name.len() <= FEDERATION_NAME_MAX_LEN,
+                "The name must not be longer than {FEDERATION_NAME_MAX_LEN} bytes"
+            );
+        }
+
+        if let Some(welcome_message) = &self.welcome_message {
+            ensure!(
+                welcome_message.len() <= WELCOME_MESSAGE_MAX_LEN,
+                "The welcome message must not be longer than {WELCOME_MESSAGE_MAX_LEN} bytes"
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The active federation metadata
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub struct FederationMetaRecord {
+    pub meta: FederationMeta,
+    /// Incremented every time the guardians approve new metadata
+    pub revision: u64,
+    /// Session in which the metadata was approved. Its signed session outcome
+    /// contains the approvals of the guardians.
+    pub session_index: u64,
+}
@@ -12,6 +12,7 @@ use serde::{Deserialize, Serialize};
 use crate::config::FederationId;
 use crate::encoding::{Decodable, DecodeError, Encodable};
 use crate::module::registry::ModuleDecoderRegistry;
+use crate::session_outcome::SchnorrSignature;
 use crate::util::SafeUrl;
 use crate::{NumPeersExt as _, PeerId};
 
@@ -83,6 +84,31 @@ impl InviteCode {
         InviteCode(code_vec)
     }
 
+    /// Constructs an [`InviteCode`] containing the URLs of all given guardians
+    ///
+    /// # Panics
+    /// If no guardian is given
+    pub fn new_with_peers(
+        peer_to_url_map: &BTreeMap<PeerId, SafeUrl>,
+        federation_id: FederationId,
+    ) -> Self {
+        assert!(
+            !peer_to_url_map.is_empty(),
+            "An invite code needs at least one guardian"
+        );
+
+        let mut code_vec: Vec<InviteCodeData> = peer_to_url_map
+            .iter()
+            .map(|(peer, url)| InviteCodeData::Api {
+                url: url.clone(),
+                peer: *peer,
+            })
+            .collect();
+        code_vec.push(InviteCodeData::FederationId(federation_id));
+
+        InviteCode(code_vec)
+    }
+
     /// Returns the API URL of one of the guardians.
     pub fn url(&self) -> SafeUrl {
         self.0
@@ -128,12 +154,49 @@ impl InviteCode {
             })
             .expect("Ensured by constructor")
     }
+
+    /// Restricts the config downloads with this invite code to the given scope
+    pub fn with_scope(mut self, scope: SignedInviteScope) -> Self {
+        self.0
+            .retain(|data| !matches!(data, InviteCodeData::Scope(_)));
+        self.0.push(InviteCodeData::Scope(scope));
+
+        self
+    }
+
+    /// Returns the restrictions on the config downloads with this invite code
+    pub fn scope(&self) -> Option<&SignedInviteScope> {
+        self.0.iter().find_map(|data| match data {
+            InviteCodeData::Scope(scope) => Some(scope),
+            _ => None,
+        })
+    }
+
+    /// Embeds the name of the federation, so it can be shown before the config
+    /// is downloaded
+    pub fn with_federation_name(mut self, name: String) -> Self {
+        self.0
+            .retain(|data| !matches!(data, InviteCodeData::FederationName(_)));
+        self.0.push(InviteCodeData::FederationName(name));
+
+        self
+    }
+
+    /// Returns the name of the federation if it was embedded. Unlike the
+    /// config it is not authenticated by the federation id, so the name in
+    /// the federation meta should be preferred once the client joined.
+    pub fn federation_name(&self) -> Option<String> {
+        self.0.iter().find_map(|data| match data {
+            InviteCodeData::FederationName(name) => Some(name.clone()),
+            _ => None,
+        })
+    }
 }
 
-/// Data that can be encoded in the invite code. Currently we always just use
-/// one `Api` and one `FederationId` variant in an invite code, but more can be
-/// added in the future while still keeping the invite code readable for older
-/// clients, which will just ignore the new fields.
+/// Data that can be encoded in the invite code. Every invite code contains at
+/// least one `Api` and one `FederationId` variant, more can be added in the
+/// future while still keeping the invite code readable for older clients,
+/// which will just ignore the new fields.
 #[derive(Clone, Debug, Eq, PartialEq, Encodable, Decodable)]
 enum InviteCodeData {
     /// API endpoint of one of the guardians
@@ -145,11 +208,39 @@ enum InviteCodeData {
     },
     /// Authentication id for the federation
     FederationId(FederationId),
+    /// Name of the federation approved by the guardians
+    FederationName(String),
+    /// Restrictions on the config downloads with the invite code
+    Scope(SignedInviteScope),
     /// Unknown invite code fields to be defined in the future
     #[encodable_default]
     Default { variant: u64, bytes: Vec<u8> },
 }
 
+/// Restrictions on the config downloads with an invite code. Every guardian
+/// enforces them for the clients that send the invite code along with their
+/// config download, but since the client config is not secret they can not
+/// stop clients that download it without the invite code.
+#[derive(Clone, Debug, Eq, PartialEq, Encodable, Decodable)]
+pub struct InviteScope {
+    /// Random id the guardians count the config downloads of the invite by
+    pub id: u64,
+    /// Guardian that created the invite code
+    pub issuer: PeerId,
+    /// Unix time in seconds after which the config downloads are rejected
+    pub expires_at: Option<u64>,
+    /// Number of config downloads every guardian serves
+    pub max_uses: Option<u64>,
+}
+
+/// Scope of an invite code signed by the broadcast key of its issuer, so other
+/// guardians can verify the scope was not changed
+#[derive(Clone, Debug, Eq, PartialEq, Encodable, Decodable)]
+pub struct SignedInviteScope {
+    pub scope: InviteScope,
+    pub signature: SchnorrSignature,
+}
+
 /// We can represent client invite code as a bech32 string for compactness and
 /// error-checking
 ///
@@ -210,7 +301,8 @@ mod tests {
     use std::str::FromStr;
 
     use crate::config::FederationId;
-    use crate::invite_code::InviteCode;
+    use crate::invite_code::{InviteCode, InviteScope, SignedInviteScope};
+    use crate::session_outcome::SchnorrSignature;
 
     #[test]
     fn test_invite_code_to_from_string() {
@@ -234,4 +326,48 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_invite_code_federation_name() {
+        let invite_code = InviteCode::new(
+            "wss://fedimintd.mplsfed.foo/".parse().expect("valid url"),
+            crate::PeerId(0),
+            FederationId::dummy(),
+        );
+
+        assert_eq!(invite_code.federation_name(), None);
+
+        let invite_code = invite_code
+            .with_federation_name("Old".to_string())
+            .with_federation_name("Mpls Fed".to_string());
+
+        let decoded = InviteCode::from_str(&invite_code.to_string()).expect("valid invite code");
+
+        assert_eq!(decoded, invite_code);
+        assert_eq!(decoded.federation_name(), Some("Mpls Fed".to_string()));
+    }
+
+    #[test]
+    fn test_invite_code_scope() {
+        let scope = SignedInviteScope {
+            scope: InviteScope {
+                id: 42,
+                issuer: crate::PeerId(1),
+                expires_at: Some(1_700_000_000),
+                max_uses: None,
+            },
+            signature: SchnorrSignature([7; 64]),
+        };
+
+        let invite_code = InviteCode::new(
+            "wss://fedimintd.mplsfed.foo/".parse().expect("valid url"),
+            crate::PeerId(0),
+            FederationId::dummy(),
+        )
+        .with_scope(scope.clone());
+
+        let decoded = InviteCode::from_str(&invite_code.to_string()).expect("valid invite code");
+
+        assert_eq!(decoded.scope(), Some(&scope));
+    }
 }
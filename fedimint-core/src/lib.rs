@@ -378,6 +378,10 @@ pub trait NumPeersExt {
     fn threshold(&self) -> usize {
         self.total() - self.max_evil()
     }
+
+    fn to_num_peers(&self) -> NumPeers {
+        NumPeers::from(self.total())
+    }
 }
 
 impl PeerId {
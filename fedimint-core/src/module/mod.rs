@@ -144,24 +144,44 @@ pub struct ApiError {
 }
 
 impl ApiError {
+    pub const BAD_REQUEST: i32 = 400;
+    pub const UNAUTHORIZED: i32 = 401;
+    pub const NOT_FOUND: i32 = 404;
+    /// The requested data existed but the guardian no longer stores it
+    pub const GONE: i32 = 410;
+    pub const SERVER_ERROR: i32 = 500;
+    pub const UNAVAILABLE: i32 = 503;
+    /// The request did not complete within the timeout of the guardian
+    pub const TIMEOUT: i32 = -32000;
+
     pub fn new(code: i32, message: String) -> Self {
         Self { code, message }
     }
 
     pub fn not_found(message: String) -> Self {
-        Self::new(404, message)
+        Self::new(Self::NOT_FOUND, message)
+    }
+
+    pub fn gone(message: String) -> Self {
+        Self::new(Self::GONE, message)
     }
 
     pub fn bad_request(message: String) -> Self {
-        Self::new(400, message)
+        Self::new(Self::BAD_REQUEST, message)
     }
 
     pub fn unauthorized() -> Self {
-        Self::new(401, "Invalid authorization".to_string())
+        Self::new(Self::UNAUTHORIZED, "Invalid authorization".to_string())
     }
 
     pub fn server_error(message: String) -> Self {
-        Self::new(500, message)
+        Self::new(Self::SERVER_ERROR, message)
+    }
+
+    /// The guardian can not accept more transactions right now, the client
+    /// should retry later
+    pub fn mempool_full() -> Self {
+        Self::new(Self::UNAVAILABLE, "Mempool is full".to_string())
     }
 }
 
@@ -520,6 +540,9 @@ pub trait IServerModuleInit: IDynCommonModuleInit {
 
     fn validate_params(&self, params: &ConfigGenModuleParams) -> anyhow::Result<()>;
 
+    /// Warnings about params that are valid but likely a misconfiguration
+    fn params_warnings(&self, params: &ConfigGenModuleParams) -> anyhow::Result<Vec<String>>;
+
     fn trusted_dealer_gen(
         &self,
         peers: &[PeerId],
@@ -544,6 +567,10 @@ pub trait IServerModuleInit: IDynCommonModuleInit {
     /// database before the module is initialized. The migrations map is
     /// indexed on the from version.
     fn get_database_migrations(&self) -> BTreeMap<DatabaseVersion, ServerMigrationFn>;
+
+    /// Database key prefixes of the module that are written outside of
+    /// consensus and therefore differ between guardians
+    fn local_db_prefixes(&self) -> Vec<u8>;
 }
 
 dyn_newtype_define!(
@@ -662,6 +689,12 @@ pub trait ServerModuleInit: ModuleInit + Sized {
         params.to_typed::<Self::Params>()
     }
 
+    /// Warnings about params that are valid but likely a misconfiguration,
+    /// shown to the guardians before the setup is run
+    fn params_warnings(&self, _params: &Self::Params) -> Vec<String> {
+        vec![]
+    }
+
     fn trusted_dealer_gen(
         &self,
         peers: &[PeerId],
@@ -688,6 +721,14 @@ pub trait ServerModuleInit: ModuleInit + Sized {
     fn get_database_migrations(&self) -> BTreeMap<DatabaseVersion, ServerMigrationFn> {
         BTreeMap::new()
     }
+
+    /// Database key prefixes of the module that every guardian writes on its
+    /// own, like data submitted via the module's API or the guardian's own
+    /// signature shares. They are excluded from the consensus state that state
+    /// hashes and checkpoints commit to.
+    fn local_db_prefixes(&self) -> Vec<u8> {
+        vec![]
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -730,6 +771,11 @@ where
         Ok(())
     }
 
+    fn params_warnings(&self, params: &ConfigGenModuleParams) -> anyhow::Result<Vec<String>> {
+        let params = <Self as ServerModuleInit>::parse_params(self, params)?;
+        Ok(<Self as ServerModuleInit>::params_warnings(self, &params))
+    }
+
     fn trusted_dealer_gen(
         &self,
         peers: &[PeerId],
@@ -766,6 +812,10 @@ where
     fn get_database_migrations(&self) -> BTreeMap<DatabaseVersion, ServerMigrationFn> {
         <Self as ServerModuleInit>::get_database_migrations(self)
     }
+
+    fn local_db_prefixes(&self) -> Vec<u8> {
+        <Self as ServerModuleInit>::local_db_prefixes(self)
+    }
 }
 
 /// Module associated types required by both client and server
@@ -900,6 +950,13 @@ pub trait ServerModule: Debug + Sized {
         module_instance_id: ModuleInstanceId,
     );
 
+    /// Checks the invariants of the module state in the database and returns
+    /// a description of every violated one. Used to verify the database of a
+    /// guardian that is not running, e.g. after a migration.
+    async fn verify(&self, _dbtx: &mut DatabaseTransaction<'_>) -> Vec<String> {
+        vec![]
+    }
+
     /// Returns a list of custom API endpoints defined by the module. These are
     /// made available both to users as well as to other modules. They thus
     /// should be deterministic, only dependant on their input and the
@@ -62,21 +62,64 @@ mod r#impl {
     }
 
     pub async fn sleep(duration: Duration) {
-        // nosemgrep: ban-tokio-sleep
-        tokio::time::sleep(duration).await;
+        match crate::time::subscribe_test_clock() {
+            // nosemgrep: ban-tokio-sleep
+            None => tokio::time::sleep(duration).await,
+            Some(clock) => sleep_on_test_clock(duration, clock).await,
+        }
     }
 
     pub async fn sleep_until(deadline: Instant) {
-        tokio::time::sleep_until(deadline).await;
+        match crate::time::subscribe_test_clock() {
+            None => tokio::time::sleep_until(deadline).await,
+            Some(clock) => {
+                // nosemgrep: ban-instant-now
+                sleep_on_test_clock(deadline.saturating_duration_since(Instant::now()), clock)
+                    .await;
+            }
+        }
     }
 
     pub async fn timeout<T>(duration: Duration, future: T) -> Result<T::Output, Elapsed>
     where
         T: Future,
     {
-        tokio::time::timeout(duration, future)
-            .await
-            .map_err(|_| Elapsed)
+        match crate::time::subscribe_test_clock() {
+            None => tokio::time::timeout(duration, future)
+                .await
+                .map_err(|_| Elapsed),
+            Some(clock) => {
+                tokio::select! {
+                    biased;
+                    output = future => Ok(output),
+                    () = sleep_on_test_clock(duration, clock) => Err(Elapsed),
+                }
+            }
+        }
+    }
+
+    /// Sleeps until `duration` has passed on the test clock, which runs ahead
+    /// of the real time by how far it is advanced during the sleep
+    async fn sleep_on_test_clock(
+        duration: Duration,
+        mut clock: tokio::sync::watch::Receiver<Duration>,
+    ) {
+        // nosemgrep: ban-instant-now
+        let start = Instant::now();
+        let start_offset = *clock.borrow_and_update();
+
+        loop {
+            let advanced = clock.borrow_and_update().saturating_sub(start_offset);
+            let Some(remaining) = duration.checked_sub(advanced) else {
+                return;
+            };
+
+            tokio::select! {
+                // nosemgrep: ban-tokio-sleep
+                () = tokio::time::sleep_until(start + remaining) => return,
+                Ok(()) = clock.changed() => {}
+            }
+        }
     }
 }
 
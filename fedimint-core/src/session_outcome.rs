@@ -1,5 +1,9 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
 use bitcoin::hashes::{sha256, Hash};
 use parity_scale_codec::{Decode, Encode};
+use secp256k1::Message;
 
 use crate::encoding::{Decodable, Encodable};
 use crate::epoch::ConsensusItem;
@@ -62,6 +66,28 @@ pub struct SignedSessionOutcome {
     pub signatures: std::collections::BTreeMap<PeerId, SchnorrSignature>,
 }
 
+/// The message the guardians sign with their broadcast keys. It is tagged
+/// with the hash of all broadcast public keys of the federation, so a
+/// signature can't be replayed in another federation.
+pub fn broadcast_signature_message(
+    public_keys: &BTreeMap<PeerId, secp256k1::PublicKey>,
+    message: &[u8],
+) -> Message {
+    let mut engine = sha256::HashEngine::default();
+
+    let public_key_tag = public_keys.consensus_hash::<sha256::Hash>();
+
+    engine
+        .write_all(public_key_tag.as_ref())
+        .expect("Writing to a hash engine can not fail");
+
+    engine
+        .write_all(message)
+        .expect("Writing to a hash engine can not fail");
+
+    Message::from(sha256::Hash::from_engine(engine))
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
 pub enum SessionStatus {
     Initial,
@@ -1,10 +1,13 @@
 // nosemgrep: ban-system-time-now
 use std::time::SystemTime;
 
+#[cfg(not(target_family = "wasm"))]
+pub use self::test_clock::{advance_test_clock, enable_test_clock};
+
 #[cfg(not(target_family = "wasm"))]
 pub fn now() -> SystemTime {
     // nosemgrep: ban-system-time-now
-    SystemTime::now()
+    SystemTime::now() + test_clock::offset()
 }
 
 #[cfg(target_family = "wasm")]
@@ -19,3 +22,50 @@ pub fn duration_since_epoch() -> std::time::Duration {
         .duration_since(SystemTime::UNIX_EPOCH)
         .expect("time to work")
 }
+
+/// Clock that tests can move forward to skip over expiries and timeouts
+/// without waiting for them.
+///
+/// Once enabled, [`super::now`] and the sleeps and timeouts of
+/// [`crate::runtime`] run ahead of the system time by how far the clock was
+/// advanced. The clock is global to the process, so it must only be used by
+/// tests that run in a process of their own.
+#[cfg(not(target_family = "wasm"))]
+mod test_clock {
+    use std::sync::OnceLock;
+    use std::time::Duration;
+
+    use tokio::sync::watch;
+
+    static OFFSET: OnceLock<watch::Sender<Duration>> = OnceLock::new();
+
+    fn sender() -> &'static watch::Sender<Duration> {
+        OFFSET.get_or_init(|| watch::channel(Duration::ZERO).0)
+    }
+
+    /// Makes the sleeps and timeouts started from now on follow the test
+    /// clock, which has to happen before the code under test starts any
+    pub fn enable_test_clock() {
+        sender();
+    }
+
+    /// Moves the clock forward by `duration`, waking all sleeps and timeouts
+    /// that expire within it
+    pub fn advance_test_clock(duration: Duration) {
+        sender().send_modify(|offset| *offset += duration);
+    }
+
+    pub(crate) fn offset() -> Duration {
+        OFFSET
+            .get()
+            .map_or(Duration::ZERO, |offset| *offset.borrow())
+    }
+
+    /// Returns a receiver of the offset if the test clock is enabled
+    pub(crate) fn subscribe() -> Option<watch::Receiver<Duration>> {
+        OFFSET.get().map(watch::Sender::subscribe)
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub(crate) use self::test_clock::subscribe as subscribe_test_clock;
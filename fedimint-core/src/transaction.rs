@@ -4,6 +4,7 @@ use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::SerdeModuleEncoding;
 use fedimint_core::{Amount, TransactionId};
 use secp256k1_zkp::schnorr;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::config::ALEPH_BFT_UNIT_BYTE_LIMIT;
@@ -150,3 +151,20 @@ pub enum TransactionError {
 
 #[derive(Debug, Encodable, Decodable, Clone, Eq, PartialEq)]
 pub struct TransactionSubmissionOutcome(pub Result<TransactionId, TransactionError>);
+
+/// Lifecycle stage of a transaction as seen by a single guardian
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    /// The guardian has not seen the transaction recently
+    Unknown,
+    /// The transaction was submitted to the guardian and waits to be ordered
+    Queued,
+    /// The transaction was accepted in the session that is currently running
+    Included { session_index: u64 },
+    /// The session that accepted the transaction is complete. The session
+    /// index is unknown if the guardian synced from a checkpoint after it.
+    Accepted { session_index: Option<u64> },
+    /// The transaction was ordered but turned out to be invalid
+    Rejected { session_index: u64, reason: String },
+}
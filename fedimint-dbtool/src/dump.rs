@@ -2,6 +2,7 @@ use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 use anyhow::Context;
+use bitcoin_hashes::sha256;
 use erased_serde::Serialize;
 use fedimint_client::db::ClientConfigKeyPrefix;
 use fedimint_client::module::init::ClientModuleInitRegistry;
@@ -11,12 +12,17 @@ use fedimint_core::db::{
     Database, DatabaseVersionKey, IDatabaseTransactionOpsCore, IDatabaseTransactionOpsCoreTyped,
 };
 use fedimint_core::encoding::Encodable;
+use fedimint_core::epoch::{FederationMeta, FederationMetaRecord, PeerEndpoints, SessionStateHash};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::push_db_pair_items_no_serde;
 use fedimint_rocksdb::RocksDbReadOnly;
 use fedimint_server::config::io::read_server_config;
 use fedimint_server::config::ServerConfig;
+use fedimint_server::consensus::checkpoint::{
+    CheckpointChunk, CheckpointHeader, SignedCheckpointHeader,
+};
 use fedimint_server::consensus::db as ConsensusRange;
+use fedimint_server::consensus::tls_rotation::PeerTlsCert;
 use futures::StreamExt;
 use ln_gateway::Gateway;
 use strum::IntoEnumIterator;
@@ -293,6 +299,150 @@ impl DatabaseDump {
                         "Aleph Units"
                     );
                 }
+                ConsensusRange::DbKeyPrefix::DbBackup => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::DbBackupPrefix,
+                        ConsensusRange::DbBackupKey,
+                        String,
+                        consensus,
+                        "Database Backups"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::CheckpointHeader => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::CheckpointHeaderPrefix,
+                        ConsensusRange::CheckpointHeaderKey,
+                        CheckpointHeader,
+                        consensus,
+                        "Checkpoint Header"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::CheckpointChunk => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::CheckpointChunkPrefix,
+                        ConsensusRange::CheckpointChunkKey,
+                        CheckpointChunk,
+                        consensus,
+                        "Checkpoint Chunks"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::SignedCheckpointHeader => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::SignedCheckpointHeaderPrefix,
+                        ConsensusRange::SignedCheckpointHeaderKey,
+                        SignedCheckpointHeader,
+                        consensus,
+                        "Signed Checkpoint Headers"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::PeerEndpoints => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::PeerEndpointsPrefix,
+                        ConsensusRange::PeerEndpointsKey,
+                        PeerEndpoints,
+                        consensus,
+                        "Peer Endpoints"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::PeerTlsCert => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::PeerTlsCertPrefix,
+                        ConsensusRange::PeerTlsCertKey,
+                        PeerTlsCert,
+                        consensus,
+                        "Peer TLS Certificates"
+                    );
+                }
+                // The private key is not dumped on purpose
+                ConsensusRange::DbKeyPrefix::OwnTlsKey => {}
+                ConsensusRange::DbKeyPrefix::TransactionSession => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::TransactionSessionPrefix,
+                        ConsensusRange::TransactionSessionKey,
+                        u64,
+                        consensus,
+                        "Transaction Sessions"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::RejectedTransaction => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::RejectedTransactionPrefix,
+                        ConsensusRange::RejectedTransactionKey,
+                        ConsensusRange::RejectedTransaction,
+                        consensus,
+                        "Rejected Transactions"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::OwnStateHash => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::OwnStateHashPrefix,
+                        ConsensusRange::OwnStateHashKey,
+                        sha256::Hash,
+                        consensus,
+                        "Own State Hashes"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::PeerStateHash => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::PeerStateHashPrefix,
+                        ConsensusRange::PeerStateHashKey,
+                        SessionStateHash,
+                        consensus,
+                        "Peer State Hashes"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::UpgradeVote => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::UpgradeVotePrefix,
+                        ConsensusRange::UpgradeVoteKey,
+                        u64,
+                        consensus,
+                        "Upgrade Votes"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::FederationMetaProposal => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::FederationMetaProposalPrefix,
+                        ConsensusRange::FederationMetaProposalKey,
+                        FederationMeta,
+                        consensus,
+                        "Federation Meta Proposals"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::FederationMeta => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::FederationMetaPrefix,
+                        ConsensusRange::FederationMetaKey,
+                        FederationMetaRecord,
+                        consensus,
+                        "Federation Meta"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::InviteUses => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::InviteUsesPrefix,
+                        ConsensusRange::InviteUsesKey,
+                        u64,
+                        consensus,
+                        "Invite Uses"
+                    );
+                }
+                // The config gen state is encrypted and contains private keys
+                ConsensusRange::DbKeyPrefix::ConfigGenState => {}
                 // Module is a global prefix for all module data
                 ConsensusRange::DbKeyPrefix::Module => {}
             }
@@ -0,0 +1,144 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::bail;
+use clap::Args;
+use fedimint_core::Amount;
+use fedimint_dummy_client::{DummyClientInit, DummyClientModule};
+use fedimint_dummy_common::config::DummyGenParams;
+use fedimint_dummy_server::DummyInit;
+use fedimint_ln_client::{LightningClientInit, MockGatewayConnection};
+use fedimint_ln_common::config::LightningGenParams;
+use fedimint_ln_server::LightningInit;
+use fedimint_mint_client::MintClientInit;
+use fedimint_mint_common::config::MintGenParams;
+use fedimint_mint_server::MintInit;
+use fedimint_testing::fixtures::Fixtures;
+use fedimint_testing::gateway::DEFAULT_GATEWAY_PASSWORD;
+use fedimint_testing::load::{LoadGenerator, LoadMix};
+use fedimint_wallet_client::WalletClientInit;
+use fedimint_wallet_common::config::WalletGenParams;
+use fedimint_wallet_server::WalletInit;
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::MetricEvent;
+
+#[derive(Args, Clone)]
+pub struct InProcessLoadTestArgs {
+    #[arg(
+        long,
+        default_value = "spend=2,reissue=2,ln_payment=1,peg_in=1",
+        help = "Relative weights of the operations, as <operation>=<weight> separated by commas"
+    )]
+    mix: LoadMix,
+
+    #[arg(long, default_value = "10", help = "Operations started per second")]
+    rate: f64,
+
+    #[arg(
+        long,
+        default_value = "60",
+        help = "For how many seconds to start operations"
+    )]
+    test_duration_secs: u64,
+
+    #[arg(
+        long,
+        default_value = "100",
+        help = "How many operations can be in flight before new ones are skipped"
+    )]
+    max_in_flight: usize,
+
+    #[arg(
+        long,
+        default_value = "10000sat",
+        help = "Amount moved by every operation"
+    )]
+    operation_amount: Amount,
+
+    #[arg(
+        long,
+        default_value = "10000000sat",
+        help = "E-cash each user starts with"
+    )]
+    initial_balance: Amount,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Seed of the operations drawn from the mix"
+    )]
+    seed: u64,
+}
+
+/// Starts a federation with mint, wallet and lightning modules and a gateway
+/// in this process and runs a [`LoadGenerator`] through `users` clients of it
+pub async fn run_in_process_load_test(
+    args: InProcessLoadTestArgs,
+    users: u16,
+    event_sender: mpsc::UnboundedSender<MetricEvent>,
+) -> anyhow::Result<()> {
+    let fixtures = Fixtures::new_primary(MintClientInit, MintInit, MintGenParams::default())
+        .with_module(DummyClientInit, DummyInit, DummyGenParams::default());
+    let wallet_params = WalletGenParams::regtest(fixtures.bitcoin_server());
+    let finality_delay = wallet_params.consensus.finality_delay;
+    let wallet_client = WalletClientInit::new(fixtures.bitcoin_client());
+    let ln_params = LightningGenParams::regtest(fixtures.bitcoin_server());
+    let fixtures = fixtures
+        .with_module(wallet_client, WalletInit, wallet_params)
+        .with_module(
+            LightningClientInit {
+                gateway_conn: Arc::new(MockGatewayConnection),
+            },
+            LightningInit,
+            ln_params,
+        );
+
+    info!("Starting federation and gateway");
+    let fed = fixtures.new_default_fed().await;
+    let mut gateway = fixtures
+        .new_gateway(0, Some(DEFAULT_GATEWAY_PASSWORD.to_string()))
+        .await;
+    gateway.connect_fed(&fed).await;
+
+    info!("Funding {users} users with {}", args.initial_balance);
+    let mut clients = vec![];
+    for _ in 0..users {
+        let client = fed.new_client().await;
+        let (op, outpoint) = client
+            .get_first_module::<DummyClientModule>()
+            .print_money(args.initial_balance)
+            .await?;
+        client.await_primary_module_output(op, outpoint).await?;
+        clients.push(client);
+    }
+
+    let report = LoadGenerator::new(clients, args.mix)
+        .rate(args.rate)
+        .duration(Duration::from_secs(args.test_duration_secs))
+        .max_in_flight(args.max_in_flight)
+        .amount(args.operation_amount)
+        .seed(args.seed)
+        .peg_ins(fixtures.bitcoin(), finality_delay.into())
+        .ln_payments(gateway.gateway.gateway_id)
+        .run()
+        .await?;
+
+    eprintln!("{report}");
+
+    for (operation, stats) in &report.operations {
+        for latency in stats.latencies() {
+            event_sender.send(MetricEvent {
+                name: operation.to_string(),
+                duration: *latency,
+            })?;
+        }
+    }
+
+    if report.failed() > 0 {
+        bail!("{} operations failed", report.failed());
+    }
+
+    Ok(())
+}
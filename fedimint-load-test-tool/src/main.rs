@@ -34,7 +34,9 @@ use tracing::{debug, info, warn};
 use crate::common::{
     build_client, do_spend_notes, get_invite_code_cli, remint_denomination, try_get_notes_cli,
 };
+use crate::in_process::{run_in_process_load_test, InProcessLoadTestArgs};
 pub mod common;
+pub mod in_process;
 
 #[derive(Parser, Clone)]
 #[command(version)]
@@ -104,6 +106,11 @@ enum Command {
     /// we can keep making the payments in a loop
     #[command()]
     LnCircularLoadTest(LnCircularLoadTestArgs),
+    /// Run a load test against a federation and gateway started in this
+    /// process, where the users start a mix of peg-ins, spends, reissues and
+    /// LN payments at a target rate
+    #[command()]
+    InProcessLoadTest(InProcessLoadTestArgs),
 }
 
 #[derive(Args, Clone)]
@@ -377,6 +384,14 @@ async fn main() -> anyhow::Result<()> {
             )
             .await?
         }
+        Command::InProcessLoadTest(args) => {
+            let f: BoxFuture<_> = Box::pin(run_in_process_load_test(
+                args,
+                opts.users,
+                event_sender.clone(),
+            ));
+            vec![f]
+        }
     };
 
     let result = futures::future::join_all(futures).await;
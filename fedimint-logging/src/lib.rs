@@ -11,11 +11,13 @@
 //! side.
 
 use std::fs::File;
+use std::sync::OnceLock;
 use std::{env, io};
 
+use anyhow::Context;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
-use tracing_subscriber::{EnvFilter, Layer};
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
 
 pub const LOG_BLOCKCHAIN: &str = "fm::net::blockchain";
 pub const LOG_CONSENSUS: &str = "fm::consensus";
@@ -45,6 +47,10 @@ pub const LOG_CLIENT_MODULE_META: &str = "fm::client::module::meta";
 pub const LOG_CLIENT_MODULE_MINT: &str = "fm::client::module::mint";
 pub const LOG_CLIENT_MODULE_LN: &str = "fm::client::module::ln";
 
+/// Env variable with the filter directives for the spans exported via OTLP,
+/// defaults to `info`
+pub const FM_OTLP_FILTER_ENV: &str = "FM_OTLP_FILTER";
+
 /// Consolidates the setup of server tracing into a helper
 #[derive(Default)]
 pub struct TracingSetup {
@@ -56,9 +62,25 @@ pub struct TracingSetup {
     with_jaeger: bool,
     #[cfg(feature = "telemetry")]
     with_chrome: bool,
+    #[cfg(feature = "telemetry")]
+    otlp_endpoint: Option<String>,
     with_file: Option<File>,
+    with_json: bool,
 }
 
+/// Filter of the log output that can be changed while running
+struct LogFilter {
+    /// Directives the logging was initialized with
+    base: String,
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+static LOG_FILTER: OnceLock<LogFilter> = OnceLock::new();
+
+/// Provider of the OTLP exporter, kept to flush the pending spans on shutdown
+#[cfg(feature = "telemetry")]
+static OTLP_PROVIDER: OnceLock<opentelemetry_sdk::trace::TracerProvider> = OnceLock::new();
+
 impl TracingSetup {
     /// Setup a console server for tokio logging <https://docs.rs/console-subscriber>
     #[cfg(feature = "telemetry")]
@@ -81,11 +103,26 @@ impl TracingSetup {
         self
     }
 
+    /// Export spans to an OpenTelemetry collector via OTLP over gRPC, e.g. at
+    /// `http://localhost:4317`. The service name and other resource attributes
+    /// are read from `OTEL_SERVICE_NAME` and `OTEL_RESOURCE_ATTRIBUTES`.
+    #[cfg(feature = "telemetry")]
+    pub fn with_otlp(&mut self, endpoint: Option<String>) -> &mut Self {
+        self.otlp_endpoint = endpoint;
+        self
+    }
+
     pub fn with_file(&mut self, file: Option<File>) -> &mut Self {
         self.with_file = file;
         self
     }
 
+    /// Write the log output as one JSON object per line
+    pub fn with_json(&mut self, enabled: bool) -> &mut Self {
+        self.with_json = enabled;
+        self
+    }
+
     /// Sets the log level applied to most modules. Some overly chatty modules
     /// are muted even if this is set to a lower log level, use the `RUST_LOG`
     /// environment variable to override.
@@ -109,7 +146,7 @@ impl TracingSetup {
         use tracing_subscriber::fmt::writer::{BoxMakeWriter, Tee};
 
         let var = env::var(tracing_subscriber::EnvFilter::DEFAULT_ENV).unwrap_or_default();
-        let filter_layer = EnvFilter::builder().parse(format!(
+        let directives = format!(
             // We prefix everything with a default general log level and
             // good per-module specific default. User provided RUST_LOG
             // can override one or both
@@ -120,7 +157,9 @@ impl TracingSetup {
             "AlephBFT-=error",
             var,
             self.extra_directives.as_deref().unwrap_or(""),
-        ))?;
+        );
+        let (filter_layer, filter_handle) =
+            reload::Layer::new(EnvFilter::builder().parse(&directives)?);
 
         let fmt_writer = if let Some(file) = self.with_file.take() {
             BoxMakeWriter::new(Tee::new(io::stderr, file))
@@ -130,8 +169,14 @@ impl TracingSetup {
 
         let fmt_layer = tracing_subscriber::fmt::layer()
             .with_thread_names(false) // can be enabled for debugging
-            .with_writer(fmt_writer)
-            .with_filter(filter_layer);
+            .with_writer(fmt_writer);
+
+        let fmt_layer = if self.with_json {
+            fmt_layer.json().boxed()
+        } else {
+            fmt_layer.boxed()
+        }
+        .with_filter(filter_layer);
 
         let console_opt = || -> Option<Box<dyn Layer<_> + Send + Sync + 'static>> {
             #[cfg(feature = "telemetry")]
@@ -162,6 +207,40 @@ impl TracingSetup {
             None
         };
 
+        let otlp_layer_opt =
+            || -> anyhow::Result<Option<Box<dyn Layer<_> + Send + Sync + 'static>>> {
+                #[cfg(feature = "telemetry")]
+                if let Some(endpoint) = &self.otlp_endpoint {
+                    use opentelemetry_otlp::WithExportConfig;
+
+                    let tracer = opentelemetry_otlp::new_pipeline()
+                        .tracing()
+                        .with_exporter(
+                            opentelemetry_otlp::new_exporter()
+                                .tonic()
+                                .with_endpoint(endpoint),
+                        )
+                        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+
+                    if let Some(provider) = tracer.provider() {
+                        let _ = OTLP_PROVIDER.set(provider);
+                    }
+
+                    // Exporting every debug span of every guardian would
+                    // overwhelm most collectors, so we filter separately
+                    let filter = EnvFilter::builder()
+                        .parse(env::var(FM_OTLP_FILTER_ENV).unwrap_or_else(|_| "info".into()))?;
+
+                    return Ok(Some(
+                        tracing_opentelemetry::layer()
+                            .with_tracer(tracer)
+                            .with_filter(filter)
+                            .boxed(),
+                    ));
+                }
+                Ok(None)
+            };
+
         let chrome_layer_opt = || -> Option<Box<dyn Layer<_> + Send + Sync + 'static>> {
             #[cfg(feature = "telemetry")]
             if self.with_chrome {
@@ -181,13 +260,49 @@ impl TracingSetup {
             .with(fmt_layer)
             .with(console_opt())
             .with(telemetry_layer_opt())
+            .with(otlp_layer_opt()?)
             .with(chrome_layer_opt())
             .try_init()?;
+
+        // Only the first initialization succeeds, so there can't be a filter yet
+        let _ = LOG_FILTER.set(LogFilter {
+            base: directives,
+            handle: filter_handle,
+        });
+
         Ok(())
     }
 }
 
+/// Replaces the filter directives added at runtime, e.g. `fm::consensus=debug`
+/// to debug consensus. The directives are applied on top of the ones the
+/// logging was initialized with, so an empty string restores the initial
+/// filter. Returns the directives of the new filter.
+pub fn set_log_filter(directives: &str) -> anyhow::Result<String> {
+    let filter = LOG_FILTER
+        .get()
+        .context("Logging was not initialized with a TracingSetup")?;
+
+    let directives = format!("{},{directives}", filter.base);
+
+    filter
+        .handle
+        .reload(EnvFilter::builder().parse(&directives)?)?;
+
+    Ok(directives)
+}
+
 pub fn shutdown() {
     #[cfg(feature = "telemetry")]
     opentelemetry::global::shutdown_tracer_provider();
+
+    #[cfg(feature = "telemetry")]
+    if let Some(provider) = OTLP_PROVIDER.get() {
+        // The batch exporter would drop the spans of the last batch otherwise
+        for result in provider.force_flush() {
+            if let Err(error) = result {
+                eprintln!("Failed to export spans: {error}");
+            }
+        }
+    }
 }
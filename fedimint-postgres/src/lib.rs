@@ -0,0 +1,517 @@
+#![allow(where_clauses_object_safety)] // https://github.com/dtolnay/async-trait/issues/228
+
+//! Postgres backed database for gateways and guardians that keep their state
+//! in a managed Postgres instance, e.g. to rely on its replication
+//!
+//! Every database lives in its own key-value table, so many databases can
+//! share one Postgres instance. Transactions work like the ones of the SQLite
+//! backend: every transaction reads from its own snapshot of the table and
+//! buffers its writes in memory. On commit the buffered writes are applied
+//! while holding an exclusive lock on the table, failing if any of the written
+//! keys was modified since the snapshot was taken, which matches the
+//! optimistic transactions of the RocksDB backend.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{ensure, Context, Result};
+use async_trait::async_trait;
+use fedimint_core::db::{
+    IDatabaseTransactionOps, IDatabaseTransactionOpsCore, IRawDatabase, IRawDatabaseTransaction,
+    PrefixStream,
+};
+use futures::stream;
+pub use tokio_postgres;
+use tokio_postgres::{Client, NoTls};
+use tracing::warn;
+
+pub struct PostgresDb {
+    /// Connection URI, which usually contains the credentials
+    uri: String,
+    table: String,
+    /// Idle connections reused by new transactions
+    connections: Arc<Mutex<Vec<Client>>>,
+}
+
+/// Writes of a transaction that were not committed yet, `None` marks a removed
+/// entry
+type WriteSet = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+pub struct PostgresDbTransaction<'a> {
+    db: &'a PostgresDb,
+    /// Connection holding the read transaction of our snapshot, only `None`
+    /// while being dropped
+    client: Option<Client>,
+    writes: WriteSet,
+    savepoint: WriteSet,
+}
+
+/// Postgres truncates longer identifiers
+const MAX_NAME_LEN: usize = 60;
+
+/// Returns the table the database called `name` is stored in
+fn table_name(name: &str) -> Result<String> {
+    ensure!(
+        !name.is_empty() && name.len() <= MAX_NAME_LEN,
+        "Database name {name} must have between 1 and {MAX_NAME_LEN} characters"
+    );
+    ensure!(
+        name.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'),
+        "Database name {name} may only contain lowercase letters, digits and underscores"
+    );
+
+    Ok(format!("kv_{name}"))
+}
+
+impl PostgresDb {
+    /// Opens the database called `name` in the Postgres instance at `uri`,
+    /// creating its table if it does not exist yet
+    pub async fn open(uri: impl Into<String>, name: &str) -> Result<PostgresDb> {
+        let db = PostgresDb {
+            uri: uri.into(),
+            table: table_name(name)?,
+            connections: Arc::new(Mutex::new(vec![])),
+        };
+
+        let client = db.connect().await?;
+
+        client
+            .batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    key BYTEA PRIMARY KEY,
+                    value BYTEA NOT NULL
+                );",
+                db.table
+            ))
+            .await?;
+
+        db.release(client);
+
+        Ok(db)
+    }
+
+    async fn connect(&self) -> Result<Client> {
+        let (client, connection) = tokio_postgres::connect(&self.uri, NoTls)
+            .await
+            .context("Unable to connect to Postgres")?;
+
+        fedimint_core::runtime::spawn("postgres connection", async move {
+            if let Err(e) = connection.await {
+                warn!("Postgres connection failed: {e}");
+            }
+        });
+
+        Ok(client)
+    }
+
+    async fn acquire(&self) -> Result<Client> {
+        let idle = self.connections.lock().expect("Lock poisoned").pop();
+
+        match idle {
+            Some(client) if !client.is_closed() => Ok(client),
+            _ => self.connect().await,
+        }
+    }
+
+    fn release(&self, client: Client) {
+        self.connections.lock().expect("Lock poisoned").push(client);
+    }
+}
+
+impl fmt::Debug for PostgresDb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PostgresDb")
+            .field("table", &self.table)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> fmt::Debug for PostgresDbTransaction<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PostgresDbTransaction")
+    }
+}
+
+// Will return None if there is no next prefix (i.e prefix is already the last
+// possible/max one)
+fn next_prefix(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut next_prefix = prefix.to_vec();
+
+    while let Some(last) = next_prefix.pop() {
+        if last < u8::MAX {
+            next_prefix.push(last + 1);
+            return Some(next_prefix);
+        }
+    }
+
+    None
+}
+
+#[async_trait]
+impl IRawDatabase for PostgresDb {
+    type Transaction<'a> = PostgresDbTransaction<'a>;
+    async fn begin_transaction<'a>(&'a self) -> PostgresDbTransaction<'a> {
+        let client = async {
+            let client = self.acquire().await?;
+
+            // The snapshot is taken by the first query of a transaction
+            client
+                .batch_execute("BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY; SELECT 1;")
+                .await?;
+
+            anyhow::Ok(client)
+        }
+        .await
+        .expect("Starting a Postgres transaction failed");
+
+        PostgresDbTransaction {
+            db: self,
+            client: Some(client),
+            writes: WriteSet::new(),
+            savepoint: WriteSet::new(),
+        }
+    }
+
+    async fn compact_prefix(&self, _key_prefix: &[u8]) -> Result<()> {
+        let client = self.acquire().await?;
+        client
+            .batch_execute(&format!("VACUUM {}", self.table))
+            .await?;
+        self.release(client);
+
+        Ok(())
+    }
+}
+
+impl<'a> PostgresDbTransaction<'a> {
+    fn client(&self) -> &Client {
+        self.client.as_ref().expect("Client is only taken on drop")
+    }
+
+    /// Reads a value from our snapshot, ignoring our own writes
+    async fn snapshot_get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .client()
+            .query_opt(
+                &format!("SELECT value FROM {} WHERE key = $1", self.db.table),
+                &[&key],
+            )
+            .await?
+            .map(|row| row.get(0)))
+    }
+
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.writes.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => self.snapshot_get(key).await,
+        }
+    }
+
+    /// Returns the entries with the prefix in ascending order of their keys,
+    /// including our own writes
+    async fn find_by_prefix(&self, key_prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let upper = next_prefix(key_prefix);
+
+        let rows = match &upper {
+            Some(upper) => {
+                self.client()
+                    .query(
+                        &format!(
+                            "SELECT key, value FROM {} WHERE key >= $1 AND key < $2 ORDER BY key",
+                            self.db.table
+                        ),
+                        &[&key_prefix, upper],
+                    )
+                    .await?
+            }
+            None => {
+                self.client()
+                    .query(
+                        &format!(
+                            "SELECT key, value FROM {} WHERE key >= $1 ORDER BY key",
+                            self.db.table
+                        ),
+                        &[&key_prefix],
+                    )
+                    .await?
+            }
+        };
+
+        let mut entries = rows
+            .into_iter()
+            .map(|row| (row.get(0), Some(row.get(1))))
+            .collect::<WriteSet>();
+
+        let upper_bound = match upper {
+            Some(upper) => Bound::Excluded(upper),
+            None => Bound::Unbounded,
+        };
+
+        for (key, value) in self
+            .writes
+            .range((Bound::Included(key_prefix.to_vec()), upper_bound))
+        {
+            entries.insert(key.clone(), value.clone());
+        }
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|value| (key, value)))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl<'a> IDatabaseTransactionOpsCore for PostgresDbTransaction<'a> {
+    async fn raw_insert_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        let old_value = self.get(key).await?;
+        self.writes.insert(key.to_vec(), Some(value.to_vec()));
+        Ok(old_value)
+    }
+
+    async fn raw_get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get(key).await
+    }
+
+    async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let old_value = self.get(key).await?;
+        self.writes.insert(key.to_vec(), None);
+        Ok(old_value)
+    }
+
+    async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> Result<PrefixStream<'_>> {
+        let entries = self.find_by_prefix(key_prefix).await?;
+        Ok(Box::pin(stream::iter(entries)))
+    }
+
+    async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> anyhow::Result<()> {
+        let entries = self.find_by_prefix(key_prefix).await?;
+
+        for (key, _) in entries {
+            self.writes.insert(key, None);
+        }
+
+        Ok(())
+    }
+
+    async fn raw_find_by_prefix_sorted_descending(
+        &mut self,
+        key_prefix: &[u8],
+    ) -> Result<PrefixStream<'_>> {
+        let mut entries = self.find_by_prefix(key_prefix).await?;
+        entries.reverse();
+        Ok(Box::pin(stream::iter(entries)))
+    }
+}
+
+#[async_trait]
+impl<'a> IDatabaseTransactionOps for PostgresDbTransaction<'a> {
+    async fn rollback_tx_to_savepoint(&mut self) -> Result<()> {
+        self.writes = self.savepoint.clone();
+        Ok(())
+    }
+
+    async fn set_tx_savepoint(&mut self) -> Result<()> {
+        self.savepoint = self.writes.clone();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'a> IRawDatabaseTransaction for PostgresDbTransaction<'a> {
+    async fn commit_tx(mut self) -> Result<()> {
+        let writes = std::mem::take(&mut self.writes);
+
+        if writes.is_empty() {
+            return Ok(());
+        }
+
+        let mut snapshot_values = Vec::with_capacity(writes.len());
+        for key in writes.keys() {
+            snapshot_values.push(self.snapshot_get(key).await?);
+        }
+
+        let table = &self.db.table;
+        let client = self.client();
+
+        // End our snapshot and wait for all other writers, readers are not
+        // blocked by the lock
+        client
+            .batch_execute(&format!(
+                "COMMIT; BEGIN; LOCK TABLE {table} IN EXCLUSIVE MODE;"
+            ))
+            .await?;
+
+        for ((key, value), snapshot_value) in writes.iter().zip(snapshot_values) {
+            let current_value: Option<Vec<u8>> = client
+                .query_opt(&format!("SELECT value FROM {table} WHERE key = $1"), &[key])
+                .await?
+                .map(|row| row.get(0));
+
+            ensure!(current_value == snapshot_value, "write-write conflict");
+
+            match value {
+                Some(value) => {
+                    client
+                        .execute(
+                            &format!(
+                                "INSERT INTO {table} (key, value) VALUES ($1, $2)
+                                ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value"
+                            ),
+                            &[key, value],
+                        )
+                        .await?
+                }
+                None => {
+                    client
+                        .execute(&format!("DELETE FROM {table} WHERE key = $1"), &[key])
+                        .await?
+                }
+            };
+        }
+
+        client.batch_execute("COMMIT").await?;
+
+        Ok(())
+    }
+}
+
+impl<'a> Drop for PostgresDbTransaction<'a> {
+    fn drop(&mut self) {
+        let Some(client) = self.client.take() else {
+            return;
+        };
+
+        let connections = self.db.connections.clone();
+
+        // Ends our snapshot or discards a failed commit, releasing its lock,
+        // before the connection is reused
+        fedimint_core::runtime::spawn("postgres rollback", async move {
+            if client.batch_execute("ROLLBACK").await.is_ok() {
+                connections.lock().expect("Lock poisoned").push(client);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod fedimint_postgres_tests {
+    use fedimint_core::db::Database;
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+
+    use super::*;
+
+    /// Postgres instance the database tests run against, they are skipped if
+    /// it is not set
+    const FM_TEST_POSTGRES_URI_ENV: &str = "FM_TEST_POSTGRES_URI";
+
+    async fn open_test_db(name: &str) -> Option<Database> {
+        let Ok(uri) = std::env::var(FM_TEST_POSTGRES_URI_ENV) else {
+            return None;
+        };
+
+        let db = PostgresDb::open(uri, name).await.unwrap();
+
+        // Previous runs leave their entries behind
+        let client = db.acquire().await.unwrap();
+        client
+            .batch_execute(&format!("TRUNCATE {}", db.table))
+            .await
+            .unwrap();
+        db.release(client);
+
+        Some(Database::new(db, ModuleDecoderRegistry::default()))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_insert_elements() {
+        let Some(db) = open_test_db("test_insert_elements").await else {
+            return;
+        };
+        fedimint_core::db::verify_insert_elements(db).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_remove_existing() {
+        let Some(db) = open_test_db("test_remove_existing").await else {
+            return;
+        };
+        fedimint_core::db::verify_remove_existing(db).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_read_own_writes() {
+        let Some(db) = open_test_db("test_read_own_writes").await else {
+            return;
+        };
+        fedimint_core::db::verify_read_own_writes(db).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_prevent_dirty_reads() {
+        let Some(db) = open_test_db("test_prevent_dirty_reads").await else {
+            return;
+        };
+        fedimint_core::db::verify_prevent_dirty_reads(db).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_find_by_prefix() {
+        let Some(db) = open_test_db("test_find_by_prefix").await else {
+            return;
+        };
+        fedimint_core::db::verify_find_by_prefix(db).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_snapshot_isolation() {
+        let Some(db) = open_test_db("test_snapshot_isolation").await else {
+            return;
+        };
+        fedimint_core::db::verify_snapshot_isolation(db).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_rollback_to_savepoint() {
+        let Some(db) = open_test_db("test_rollback_to_savepoint").await else {
+            return;
+        };
+        fedimint_core::db::verify_rollback_to_savepoint(db).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_write_conflict() {
+        let Some(db) = open_test_db("test_write_conflict").await else {
+            return;
+        };
+        fedimint_core::db::expect_write_conflict(db).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_remove_by_prefix() {
+        let Some(db) = open_test_db("test_remove_by_prefix").await else {
+            return;
+        };
+        fedimint_core::db::verify_remove_by_prefix(db).await;
+    }
+
+    #[test]
+    fn test_next_prefix() {
+        assert_eq!(next_prefix(&[1, 2, 3]).unwrap(), vec![1, 2, 4]);
+        assert_eq!(next_prefix(&[1, 2, 255]).unwrap(), vec![1, 3]);
+        assert!(next_prefix(&[255, 255, 255]).is_none());
+        assert!(next_prefix(&[]).is_none());
+    }
+
+    #[test]
+    fn test_table_name() {
+        assert_eq!(table_name("client_abc123").unwrap(), "kv_client_abc123");
+        assert!(table_name("").is_err());
+        assert!(table_name(&"a".repeat(MAX_NAME_LEN + 1)).is_err());
+        assert!(table_name("kv; DROP TABLE kv").is_err());
+        assert!(table_name("Upper").is_err());
+    }
+}
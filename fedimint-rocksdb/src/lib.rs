@@ -5,6 +5,7 @@ pub mod envs;
 use std::fmt;
 use std::path::Path;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
@@ -85,8 +86,8 @@ fn get_default_options() -> anyhow::Result<rocksdb::Options> {
     Ok(opts)
 }
 
-#[derive(Debug)]
-pub struct RocksDbReadOnly(rocksdb::DB);
+#[derive(Debug, Clone)]
+pub struct RocksDbReadOnly(Arc<rocksdb::DB>);
 
 pub struct RocksDbReadOnlyTransaction<'a>(&'a rocksdb::DB);
 
@@ -94,7 +95,28 @@ impl RocksDbReadOnly {
     pub fn open_read_only(db_path: impl AsRef<Path>) -> anyhow::Result<RocksDbReadOnly> {
         let opts = get_default_options()?;
         let db = rocksdb::DB::open_for_read_only(&opts, db_path, false)?;
-        Ok(RocksDbReadOnly(db))
+        Ok(RocksDbReadOnly(Arc::new(db)))
+    }
+
+    /// Opens a secondary instance of the database at `db_path` that is still
+    /// written to by another process. The secondary instance keeps its own
+    /// info log in `secondary_path` and only sees the writes of the primary
+    /// up to the last call of [`Self::catch_up_with_primary`].
+    pub fn open_secondary(
+        db_path: impl AsRef<Path>,
+        secondary_path: impl AsRef<Path>,
+    ) -> anyhow::Result<RocksDbReadOnly> {
+        let mut opts = get_default_options()?;
+        // Required for secondary instances to see all files of the primary
+        opts.set_max_open_files(-1);
+        let db = rocksdb::DB::open_as_secondary(&opts, db_path.as_ref(), secondary_path.as_ref())?;
+        Ok(RocksDbReadOnly(Arc::new(db)))
+    }
+
+    /// Makes the writes the primary committed since the last call visible to
+    /// a secondary instance
+    pub fn catch_up_with_primary(&self) -> anyhow::Result<()> {
+        Ok(self.0.try_catch_up_with_primary()?)
     }
 }
 
@@ -154,6 +176,19 @@ impl IRawDatabase for RocksDb {
 
         rocksdb_tx
     }
+
+    async fn compact_prefix(&self, key_prefix: &[u8]) -> Result<()> {
+        fedimint_core::runtime::block_in_place(|| {
+            if key_prefix.is_empty() {
+                self.0.compact_range(None::<&[u8]>, None::<&[u8]>);
+            } else {
+                self.0
+                    .compact_range(Some(key_prefix), next_prefix(key_prefix).as_deref());
+            }
+        });
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -393,7 +428,8 @@ impl<'a> IDatabaseTransactionOps for RocksDbReadOnlyTransaction<'a> {
 #[async_trait]
 impl<'a> IRawDatabaseTransaction for RocksDbReadOnlyTransaction<'a> {
     async fn commit_tx(self) -> Result<()> {
-        panic!("Cannot commit a read only transaction");
+        // Writes already fail, so there is nothing to commit
+        Ok(())
     }
 }
 
@@ -0,0 +1,397 @@
+//! Webhook notifications about critical events of the guardian
+//!
+//! If a guardian configures [`AlertConfig`] in its local config, a background
+//! task watches the connections to its peers, the progress of consensus and
+//! the free disk space, and POSTs an [`AlertEvent`] to every configured webhook
+//! once one of them crosses its threshold. Panics of API handlers are reported
+//! as soon as they are caught. Besides the structured event every request
+//! carries a human readable `text` field, which is the message format of Slack
+//! and Matrix webhooks.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use fedimint_api_client::api::PeerConnectionStatus;
+use fedimint_core::db::Database;
+use fedimint_core::task::{sleep, TaskGroup};
+use fedimint_core::time::duration_since_epoch;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::PeerId;
+use fedimint_logging::LOG_CONSENSUS;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::consensus::engine::get_finished_session_count_static;
+
+/// How often the monitor checks the state of the guardian
+const ALERT_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long we wait for a webhook to accept an event
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Events that have not been delivered yet, further events are dropped
+const ALERT_QUEUE_SIZE: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertConfig {
+    /// URLs the events are POSTed to as JSON
+    pub webhooks: Vec<SafeUrl>,
+    /// Seconds a peer has to be disconnected before we alert
+    pub peer_offline_secs: u64,
+    /// Seconds without a completed session after which we consider consensus
+    /// to be stalled
+    pub consensus_stall_secs: u64,
+    /// Directory on the file system to watch the free space of, usually the
+    /// data dir. The disk space is not checked if not set.
+    pub disk_path: Option<PathBuf>,
+    /// Alert once less than this many bytes are available at `disk_path`
+    pub min_free_disk_bytes: u64,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        AlertConfig {
+            webhooks: vec![],
+            peer_offline_secs: 600,
+            consensus_stall_secs: 1800,
+            disk_path: None,
+            min_free_disk_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AlertEvent {
+    PeerOffline {
+        peer: PeerId,
+        offline_secs: u64,
+    },
+    ConsensusStalled {
+        /// Index of the session that has not been completed
+        session_index: u64,
+        stalled_secs: u64,
+    },
+    DiskLow {
+        path: PathBuf,
+        available_bytes: u64,
+    },
+    ApiPanic {
+        path: String,
+    },
+}
+
+impl fmt::Display for AlertEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertEvent::PeerOffline { peer, offline_secs } => {
+                write!(f, "Peer {peer} has been offline for {offline_secs}s")
+            }
+            AlertEvent::ConsensusStalled {
+                session_index,
+                stalled_secs,
+            } => write!(
+                f,
+                "Consensus is stalled, session {session_index} has not completed for {stalled_secs}s"
+            ),
+            AlertEvent::DiskLow {
+                path,
+                available_bytes,
+            } => write!(
+                f,
+                "Only {available_bytes} bytes of disk space are left at {}",
+                path.display()
+            ),
+            AlertEvent::ApiPanic { path } => write!(f, "API handler {path} panicked"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AlertPayload<'a> {
+    text: String,
+    guardian: PeerId,
+    timestamp: u64,
+    event: &'a AlertEvent,
+}
+
+static ALERT_SENDER: OnceLock<async_channel::Sender<AlertEvent>> = OnceLock::new();
+
+/// Sends the event to the configured webhooks, does nothing if alerting is
+/// not enabled
+pub fn report(event: AlertEvent) {
+    if let Some(sender) = ALERT_SENDER.get() {
+        // Alerts are best effort, we rather drop them than block the caller
+        let _ = sender.try_send(event);
+    }
+}
+
+/// Spawns the tasks watching the guardian and delivering the events
+pub fn spawn_alert_tasks(
+    task_group: &TaskGroup,
+    config: AlertConfig,
+    identity: PeerId,
+    db: Database,
+    connection_status: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStatus>>>,
+) {
+    if config.webhooks.is_empty() {
+        warn!(target: LOG_CONSENSUS, "Alerting is configured without any webhooks");
+        return;
+    }
+
+    let (sender, receiver) = async_channel::bounded(ALERT_QUEUE_SIZE);
+
+    if ALERT_SENDER.set(sender).is_err() {
+        warn!(target: LOG_CONSENSUS, "Alerting has already been started");
+        return;
+    }
+
+    info!(target: LOG_CONSENSUS, "Starting alert webhooks");
+
+    let webhooks = config.webhooks.clone();
+
+    task_group.spawn_cancellable("alert webhooks", async move {
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .expect("Failed to build http client");
+
+        while let Ok(event) = receiver.recv().await {
+            warn!(target: LOG_CONSENSUS, %event, "Sending alert");
+
+            let payload = AlertPayload {
+                text: event.to_string(),
+                guardian: identity,
+                timestamp: duration_since_epoch().as_secs(),
+                event: &event,
+            };
+
+            for webhook in &webhooks {
+                if let Err(e) = send_alert(&client, webhook, &payload).await {
+                    // The url of a webhook usually contains a secret token
+                    warn!(
+                        target: LOG_CONSENSUS,
+                        host = ?webhook.host_str(),
+                        "Failed to send alert: {e}"
+                    );
+                }
+            }
+        }
+    });
+
+    task_group.spawn_cancellable("alert monitor", async move {
+        let mut monitor = AlertMonitor::new(config);
+
+        loop {
+            let now = Instant::now();
+
+            let statuses = connection_status.read().await.clone();
+            let session_count =
+                get_finished_session_count_static(&mut db.begin_transaction_nc().await).await;
+
+            let mut events = monitor.check_peers(&statuses, now);
+            events.extend(monitor.check_sessions(session_count, now));
+
+            if let Some(path) = monitor.config.disk_path.clone() {
+                match fs2::available_space(&path) {
+                    Ok(available_bytes) => events.extend(monitor.check_disk(available_bytes)),
+                    Err(e) => {
+                        warn!(target: LOG_CONSENSUS, path = %path.display(), "Failed to check disk space: {e}");
+                    }
+                }
+            }
+
+            for event in events {
+                report(event);
+            }
+
+            sleep(ALERT_CHECK_INTERVAL).await;
+        }
+    });
+}
+
+async fn send_alert(
+    client: &reqwest::Client,
+    webhook: &SafeUrl,
+    payload: &AlertPayload<'_>,
+) -> anyhow::Result<()> {
+    let response = client
+        .post(webhook.clone().to_unsafe())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(payload)?)
+        .send()
+        .await
+        .map_err(reqwest::Error::without_url)?;
+
+    anyhow::ensure!(
+        response.status().is_success(),
+        "Webhook responded with status {}",
+        response.status()
+    );
+
+    Ok(())
+}
+
+/// Tracks the state of the guardian between two checks, so every threshold
+/// crossing is only reported once
+struct AlertMonitor {
+    config: AlertConfig,
+    /// Since when a peer is disconnected and whether we alerted already
+    offline_since: BTreeMap<PeerId, (Instant, bool)>,
+    /// Last finished session count and when it changed
+    last_session: Option<(u64, Instant)>,
+    stall_alerted: bool,
+    disk_alerted: bool,
+}
+
+impl AlertMonitor {
+    fn new(config: AlertConfig) -> Self {
+        AlertMonitor {
+            config,
+            offline_since: BTreeMap::new(),
+            last_session: None,
+            stall_alerted: false,
+            disk_alerted: false,
+        }
+    }
+
+    fn check_peers(
+        &mut self,
+        statuses: &BTreeMap<PeerId, PeerConnectionStatus>,
+        now: Instant,
+    ) -> Vec<AlertEvent> {
+        let mut events = vec![];
+
+        for (peer, status) in statuses {
+            if *status == PeerConnectionStatus::Connected {
+                self.offline_since.remove(peer);
+                continue;
+            }
+
+            let (since, alerted) = self.offline_since.entry(*peer).or_insert((now, false));
+            let offline_secs = now.duration_since(*since).as_secs();
+
+            if !*alerted && offline_secs >= self.config.peer_offline_secs {
+                *alerted = true;
+                events.push(AlertEvent::PeerOffline {
+                    peer: *peer,
+                    offline_secs,
+                });
+            }
+        }
+
+        events
+    }
+
+    fn check_sessions(&mut self, session_count: u64, now: Instant) -> Vec<AlertEvent> {
+        match self.last_session {
+            Some((last_count, since)) if last_count == session_count => {
+                let stalled_secs = now.duration_since(since).as_secs();
+
+                if !self.stall_alerted && stalled_secs >= self.config.consensus_stall_secs {
+                    self.stall_alerted = true;
+                    return vec![AlertEvent::ConsensusStalled {
+                        session_index: session_count,
+                        stalled_secs,
+                    }];
+                }
+            }
+            _ => {
+                self.last_session = Some((session_count, now));
+                self.stall_alerted = false;
+            }
+        }
+
+        vec![]
+    }
+
+    fn check_disk(&mut self, available_bytes: u64) -> Vec<AlertEvent> {
+        if self.config.min_free_disk_bytes <= available_bytes {
+            self.disk_alerted = false;
+            return vec![];
+        }
+
+        if self.disk_alerted {
+            return vec![];
+        }
+
+        self.disk_alerted = true;
+
+        vec![AlertEvent::DiskLow {
+            path: self.config.disk_path.clone().unwrap_or_default(),
+            available_bytes,
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::{Duration, Instant};
+
+    use fedimint_api_client::api::PeerConnectionStatus;
+    use fedimint_core::PeerId;
+
+    use super::{AlertConfig, AlertEvent, AlertMonitor};
+
+    #[test]
+    fn test_alert_monitor_reports_once() {
+        let mut monitor = AlertMonitor::new(AlertConfig {
+            peer_offline_secs: 60,
+            consensus_stall_secs: 120,
+            min_free_disk_bytes: 100,
+            ..AlertConfig::default()
+        });
+
+        let start = Instant::now();
+        let peer = PeerId::from(1);
+        let disconnected = BTreeMap::from([(peer, PeerConnectionStatus::Disconnected)]);
+        let connected = BTreeMap::from([(peer, PeerConnectionStatus::Connected)]);
+
+        assert!(monitor.check_peers(&disconnected, start).is_empty());
+        assert_eq!(
+            monitor.check_peers(&disconnected, start + Duration::from_secs(60)),
+            vec![AlertEvent::PeerOffline {
+                peer,
+                offline_secs: 60
+            }]
+        );
+        assert!(monitor
+            .check_peers(&disconnected, start + Duration::from_secs(90))
+            .is_empty());
+
+        // A reconnect resets the offline time
+        assert!(monitor
+            .check_peers(&connected, start + Duration::from_secs(100))
+            .is_empty());
+        assert!(monitor
+            .check_peers(&disconnected, start + Duration::from_secs(110))
+            .is_empty());
+
+        assert!(monitor.check_sessions(5, start).is_empty());
+        assert!(monitor
+            .check_sessions(6, start + Duration::from_secs(100))
+            .is_empty());
+        assert_eq!(
+            monitor.check_sessions(6, start + Duration::from_secs(220)),
+            vec![AlertEvent::ConsensusStalled {
+                session_index: 6,
+                stalled_secs: 120
+            }]
+        );
+        assert!(monitor
+            .check_sessions(6, start + Duration::from_secs(300))
+            .is_empty());
+
+        assert!(monitor.check_disk(100).is_empty());
+        assert_eq!(monitor.check_disk(99).len(), 1);
+        assert!(monitor.check_disk(50).is_empty());
+        assert!(monitor.check_disk(200).is_empty());
+        assert_eq!(monitor.check_disk(10).len(), 1);
+    }
+}
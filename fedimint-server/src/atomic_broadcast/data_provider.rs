@@ -1,4 +1,5 @@
 use std::collections::BTreeSet;
+use std::time::Instant;
 
 use fedimint_core::config::ALEPH_BFT_UNIT_BYTE_LIMIT;
 use fedimint_core::encoding::Encodable;
@@ -7,6 +8,9 @@ use fedimint_core::session_outcome::SchnorrSignature;
 use fedimint_core::TransactionId;
 use tokio::sync::watch;
 
+use crate::consensus::mempool::MempoolTracker;
+use crate::consensus::profiling::{SessionProfiler, SessionStage};
+use crate::metrics::SUBMISSION_QUEUE_DEPTH;
 use crate::LOG_CONSENSUS;
 
 #[derive(
@@ -30,6 +34,8 @@ impl UnitData {
 
 pub struct DataProvider {
     mempool_item_receiver: async_channel::Receiver<ConsensusItem>,
+    mempool: MempoolTracker,
+    profiler: SessionProfiler,
     signature_receiver: watch::Receiver<Option<SchnorrSignature>>,
     submitted_transactions: BTreeSet<TransactionId>,
     leftover_item: Option<ConsensusItem>,
@@ -38,10 +44,14 @@ pub struct DataProvider {
 impl DataProvider {
     pub fn new(
         mempool_item_receiver: async_channel::Receiver<ConsensusItem>,
+        mempool: MempoolTracker,
+        profiler: SessionProfiler,
         signature_receiver: watch::Receiver<Option<SchnorrSignature>>,
     ) -> Self {
         Self {
             mempool_item_receiver,
+            mempool,
+            profiler,
             signature_receiver,
             submitted_transactions: BTreeSet::new(),
             leftover_item: None,
@@ -57,6 +67,8 @@ impl aleph_bft::DataProvider<UnitData> for DataProvider {
             return Some(UnitData::Signature(signature));
         }
 
+        let start = Instant::now();
+
         // the length of a vector is encoded in at most 9 bytes
         let mut n_bytes = 9;
         let mut items = Vec::new();
@@ -75,6 +87,8 @@ impl aleph_bft::DataProvider<UnitData> for DataProvider {
         // if the channel is empty we want to return the batch immediately in order to
         // not delay the creation of our next unit, even if the batch is empty
         while let Ok(item) = self.mempool_item_receiver.try_recv() {
+            self.mempool.remove(&item);
+
             if let ConsensusItem::Transaction(transaction) = &item {
                 if !self.submitted_transactions.insert(transaction.tx_hash()) {
                     continue;
@@ -92,10 +106,15 @@ impl aleph_bft::DataProvider<UnitData> for DataProvider {
             }
         }
 
+        SUBMISSION_QUEUE_DEPTH.set(self.mempool_item_receiver.len() as i64);
+
         let bytes = items.consensus_encode_to_vec();
 
         assert!(bytes.len() <= ALEPH_BFT_UNIT_BYTE_LIMIT);
 
+        self.profiler
+            .record(SessionStage::Proposal, start.elapsed());
+
         return Some(UnitData::Batch(bytes));
     }
 }
@@ -1,32 +1,39 @@
 use std::collections::BTreeMap;
-use std::io::Write;
+use std::sync::Arc;
 
 use aleph_bft::Keychain as KeychainTrait;
-use fedimint_core::encoding::Encodable;
-use fedimint_core::session_outcome::SchnorrSignature;
-use fedimint_core::{secp256k1, BitcoinHash, NumPeersExt, PeerId};
-use secp256k1::hashes::sha256;
-use secp256k1::{schnorr, KeyPair, Message, PublicKey};
-
+use anyhow::Context;
+use fedimint_core::session_outcome::{broadcast_signature_message, SchnorrSignature};
+use fedimint_core::{secp256k1, NumPeers, NumPeersExt, PeerId};
+use fedimint_logging::LOG_CONSENSUS;
+use secp256k1::{schnorr, Message, PublicKey};
+use tokio::sync::watch;
+use tracing::{error, warn};
+
+use crate::config::key_store::DynKeyStore;
 use crate::config::ServerConfig;
 
+/// How often signing is attempted before giving up, since keys held by an HSM
+/// can fail to sign transiently
+const SIGN_ATTEMPTS: usize = 3;
+
 #[derive(Clone, Debug)]
 pub struct Keychain {
     peer_id: PeerId,
     public_keys: BTreeMap<PeerId, PublicKey>,
-    keypair: KeyPair,
+    key_store: DynKeyStore,
+    /// Set once signing a message of the atomic broadcast failed
+    signing_failure: Arc<watch::Sender<Option<String>>>,
 }
 
 impl Keychain {
-    pub fn new(cfg: &ServerConfig) -> Self {
-        Keychain {
+    pub fn new(cfg: &ServerConfig) -> anyhow::Result<Self> {
+        Ok(Keychain {
             peer_id: cfg.local.identity,
             public_keys: cfg.consensus.broadcast_public_keys.clone(),
-            keypair: cfg
-                .private
-                .broadcast_secret_key
-                .keypair(secp256k1::SECP256K1),
-        }
+            key_store: cfg.key_store()?,
+            signing_failure: Arc::new(watch::channel(None).0),
+        })
     }
 
     pub fn peer_id(&self) -> PeerId {
@@ -37,26 +44,49 @@ impl Keychain {
         self.public_keys.total()
     }
 
+    pub fn num_peers(&self) -> NumPeers {
+        self.public_keys.to_num_peers()
+    }
+
     pub fn threshold(&self) -> usize {
         self.public_keys.threshold()
     }
 
     fn tagged_hash(&self, message: &[u8]) -> Message {
-        let mut engine = sha256::HashEngine::default();
-
-        let public_key_tag = self.public_keys.consensus_hash::<sha256::Hash>();
-
-        engine
-            .write_all(public_key_tag.as_ref())
-            .expect("Writing to a hash engine can not fail");
+        broadcast_signature_message(&self.public_keys, message)
+    }
 
-        engine
-            .write_all(message)
-            .expect("Writing to a hash engine can not fail");
+    /// Signs the message with our broadcast secret key, retrying if the key
+    /// store fails
+    pub fn try_sign(&self, message: &[u8]) -> anyhow::Result<SchnorrSignature> {
+        let message = self.tagged_hash(message);
+
+        let mut attempt = 1;
+        loop {
+            match self.key_store.sign_schnorr(&message) {
+                Ok(signature) => return Ok(SchnorrSignature(signature.as_ref().to_owned())),
+                Err(e) if attempt < SIGN_ATTEMPTS => {
+                    warn!(target: LOG_CONSENSUS, attempt, "Failed to sign with our broadcast secret key: {e:?}");
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(e).context("Failed to sign with our broadcast secret key");
+                }
+            }
+        }
+    }
 
-        let hash = sha256::Hash::from_engine(engine);
+    /// Resolves with the error once signing a message of the atomic broadcast
+    /// failed, after which our units are rejected by our peers and the
+    /// session has to be aborted
+    pub async fn signing_failure(&self) -> String {
+        let mut failure = self.signing_failure.subscribe();
 
-        Message::from(hash)
+        match failure.wait_for(Option::is_some).await {
+            Ok(failure) => failure.clone().unwrap_or_default(),
+            // We hold the sender, so the channel can not be closed
+            Err(_) => std::future::pending().await,
+        }
     }
 }
 
@@ -75,12 +105,16 @@ impl aleph_bft::Keychain for Keychain {
     }
 
     fn sign(&self, message: &[u8]) -> Self::Signature {
-        SchnorrSignature(
-            self.keypair
-                .sign_schnorr(self.tagged_hash(message))
-                .as_ref()
-                .to_owned(),
-        )
+        // Aleph BFT expects signing to be infallible, so we report the failure
+        // to the consensus engine, which aborts the session. The invalid
+        // signature is rejected by our peers in the meantime.
+        self.try_sign(message).unwrap_or_else(|e| {
+            error!(target: LOG_CONSENSUS, "Aborting the session: {e:?}");
+
+            self.signing_failure.send_replace(Some(format!("{e:?}")));
+
+            SchnorrSignature([0; 64])
+        })
     }
 
     fn verify(
@@ -0,0 +1,255 @@
+//! Periodic encrypted database backups
+//!
+//! If a guardian configures [`DbBackupConfig`] in its local config, a
+//! background task takes an encrypted snapshot of the database every
+//! [`DbBackupConfig::interval_secs`] and stores it in a local directory or an
+//! S3-compatible object store. After every successful backup, backups that are
+//! no longer covered by the [`DbBackupRetention`] policy are deleted.
+
+pub mod s3;
+
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::task::{sleep, TaskGroup};
+use fedimint_core::time::duration_since_epoch;
+use fedimint_logging::LOG_DB;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::backup::s3::{S3Client, S3Config};
+use crate::consensus::db::{DbBackupKey, DbBackupPrefix};
+use crate::snapshot::{DbSnapshot, EncryptedDbSnapshot, DB_SNAPSHOT_EXT};
+
+/// How long to wait before retrying after a failed backup
+const BACKUP_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DbBackupConfig {
+    /// Seconds between two consecutive backups
+    pub interval_secs: u64,
+    pub retention: DbBackupRetention,
+    pub target: DbBackupTarget,
+}
+
+/// Determines which backups are deleted after a new backup was stored. The
+/// most recent backup is never deleted.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DbBackupRetention {
+    /// Number of most recent backups to keep
+    pub keep_last: usize,
+    /// Backups older than this are deleted even if they are within
+    /// `keep_last`
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DbBackupTarget {
+    /// Write backups as files into a local directory
+    Directory { path: PathBuf },
+    /// Upload backups to an S3-compatible object store
+    S3(S3Config),
+}
+
+impl DbBackupRetention {
+    /// Returns the timestamps of the backups that should be deleted given the
+    /// timestamps of all existing backups sorted in descending order
+    pub fn backups_to_prune(&self, timestamps_desc: &[u64], now: u64) -> Vec<u64> {
+        timestamps_desc
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(index, timestamp)| {
+                *index >= self.keep_last.max(1)
+                    || self
+                        .max_age_secs
+                        .is_some_and(|max_age| now.saturating_sub(**timestamp) > max_age)
+            })
+            .map(|(_, timestamp)| *timestamp)
+            .collect()
+    }
+}
+
+enum DbBackupStore {
+    Directory(PathBuf),
+    S3(S3Client),
+}
+
+impl DbBackupStore {
+    fn new(target: DbBackupTarget) -> anyhow::Result<DbBackupStore> {
+        Ok(match target {
+            DbBackupTarget::Directory { path } => DbBackupStore::Directory(path),
+            DbBackupTarget::S3(config) => DbBackupStore::S3(S3Client::from_env(config)?),
+        })
+    }
+
+    /// Stores the snapshot and returns its location once it is durable
+    async fn store(&self, snapshot: EncryptedDbSnapshot, timestamp: u64) -> anyhow::Result<String> {
+        match self {
+            DbBackupStore::Directory(path) => {
+                let path = path.clone();
+                let written =
+                    tokio::task::spawn_blocking(move || snapshot.write_to_dir(&path, timestamp))
+                        .await??;
+                Ok(written.display().to_string())
+            }
+            DbBackupStore::S3(client) => {
+                let key = client.object_key(&format!("{timestamp}.{DB_SNAPSHOT_EXT}"));
+                client.put_object(&key, snapshot.to_bytes()).await?;
+                Ok(key)
+            }
+        }
+    }
+
+    async fn delete(&self, location: &str) -> anyhow::Result<()> {
+        match self {
+            DbBackupStore::Directory(_) => match tokio::fs::remove_file(location).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            },
+            DbBackupStore::S3(client) => client.delete_object(location).await,
+        }
+    }
+}
+
+/// Spawns the task taking scheduled backups of `db` encrypted with `password`
+pub fn spawn_db_backup_task(
+    task_group: &TaskGroup,
+    db: Database,
+    config: DbBackupConfig,
+    password: String,
+) {
+    task_group.spawn_cancellable("db backup scheduler", async move {
+        let store = match DbBackupStore::new(config.target.clone()) {
+            Ok(store) => store,
+            Err(e) => {
+                warn!(target: LOG_DB, "Scheduled database backups are disabled: {e:?}");
+                return;
+            }
+        };
+
+        info!(target: LOG_DB, "Starting scheduled database backups");
+
+        loop {
+            let now = duration_since_epoch().as_secs();
+
+            let next_backup = last_backup_timestamp(&db)
+                .await
+                .map_or(now, |last| last.saturating_add(config.interval_secs));
+
+            if now < next_backup {
+                sleep(Duration::from_secs(next_backup - now)).await;
+                continue;
+            }
+
+            if let Err(e) = backup(&db, &store, &password).await {
+                warn!(target: LOG_DB, "Scheduled database backup failed: {e:?}");
+                sleep(BACKUP_RETRY_DELAY).await;
+                continue;
+            }
+
+            prune(&db, &store, &config.retention).await;
+        }
+    });
+}
+
+async fn last_backup_timestamp(db: &Database) -> Option<u64> {
+    db.begin_transaction_nc()
+        .await
+        .find_by_prefix_sorted_descending(&DbBackupPrefix)
+        .await
+        .next()
+        .await
+        .map(|(key, _)| key.0)
+}
+
+async fn backup(db: &Database, store: &DbBackupStore, password: &str) -> anyhow::Result<()> {
+    let snapshot = DbSnapshot::take(db).await?;
+    let timestamp = snapshot.timestamp;
+
+    let encrypted = snapshot.encrypt_blocking(password.to_owned()).await?;
+    let location = store.store(encrypted, timestamp).await?;
+
+    let mut dbtx = db.begin_transaction().await;
+    dbtx.insert_entry(&DbBackupKey(timestamp), &location).await;
+    dbtx.commit_tx_result().await?;
+
+    info!(target: LOG_DB, %location, "Stored scheduled database backup");
+
+    Ok(())
+}
+
+async fn prune(db: &Database, store: &DbBackupStore, retention: &DbBackupRetention) {
+    let backups = db
+        .begin_transaction_nc()
+        .await
+        .find_by_prefix_sorted_descending(&DbBackupPrefix)
+        .await
+        .map(|(key, location)| (key.0, location))
+        .collect::<Vec<_>>()
+        .await;
+
+    let timestamps = backups
+        .iter()
+        .map(|(timestamp, _)| *timestamp)
+        .collect::<Vec<_>>();
+
+    let prune = retention.backups_to_prune(&timestamps, duration_since_epoch().as_secs());
+
+    for (timestamp, location) in backups.iter().filter(|(t, _)| prune.contains(t)) {
+        // If deleting fails we keep the record so we retry after the next backup
+        if let Err(e) = store.delete(location).await {
+            warn!(target: LOG_DB, %location, "Failed to delete old database backup: {e:?}");
+            continue;
+        }
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.remove_entry(&DbBackupKey(*timestamp)).await;
+        dbtx.commit_tx().await;
+
+        info!(target: LOG_DB, %location, "Deleted old database backup");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DbBackupRetention;
+
+    #[test]
+    fn test_backups_to_prune() {
+        let retention = DbBackupRetention {
+            keep_last: 2,
+            max_age_secs: None,
+        };
+
+        assert_eq!(retention.backups_to_prune(&[], 100), Vec::<u64>::new());
+        assert_eq!(
+            retention.backups_to_prune(&[50, 40], 100),
+            Vec::<u64>::new()
+        );
+        assert_eq!(
+            retention.backups_to_prune(&[50, 40, 30, 20], 100),
+            vec![30, 20]
+        );
+
+        let retention = DbBackupRetention {
+            keep_last: 10,
+            max_age_secs: Some(55),
+        };
+
+        assert_eq!(retention.backups_to_prune(&[50, 40, 30], 100), vec![40, 30]);
+
+        // The most recent backup is kept regardless of the policy
+        let retention = DbBackupRetention {
+            keep_last: 0,
+            max_age_secs: Some(0),
+        };
+
+        assert_eq!(retention.backups_to_prune(&[50, 40], 100), vec![40]);
+    }
+}
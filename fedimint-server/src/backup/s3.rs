@@ -0,0 +1,203 @@
+//! Minimal client to store and delete objects in S3-compatible object stores
+//!
+//! Requests are authenticated with AWS signature version 4 and use path-style
+//! addressing (`{endpoint}/{bucket}/{key}`), which is supported by AWS as well
+//! as self-hosted implementations like MinIO or Garage.
+use std::env;
+
+use anyhow::{bail, Context};
+use bitcoin_hashes::{sha256, Hash, Hmac, HmacEngine};
+use fedimint_core::time::duration_since_epoch;
+use fedimint_core::util::SafeUrl;
+use serde::{Deserialize, Serialize};
+
+use crate::envs::{FM_DB_BACKUP_S3_ACCESS_KEY_ID_ENV, FM_DB_BACKUP_S3_SECRET_ACCESS_KEY_ENV};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct S3Config {
+    /// Base url of the object store, e.g. `https://s3.us-east-1.amazonaws.com`
+    pub endpoint: SafeUrl,
+    pub bucket: String,
+    pub region: String,
+    /// Prepended to the name of every object we store, e.g. `guardian-0/`
+    #[serde(default)]
+    pub key_prefix: String,
+}
+
+/// Credentials are read from the environment so they never end up in the
+/// plaintext local config
+#[derive(Debug)]
+pub struct S3Client {
+    config: S3Config,
+    access_key_id: String,
+    secret_access_key: String,
+    client: reqwest::Client,
+}
+
+impl S3Client {
+    pub fn from_env(config: S3Config) -> anyhow::Result<S3Client> {
+        Ok(S3Client {
+            config,
+            access_key_id: env::var(FM_DB_BACKUP_S3_ACCESS_KEY_ID_ENV)
+                .with_context(|| format!("{FM_DB_BACKUP_S3_ACCESS_KEY_ID_ENV} is not set"))?,
+            secret_access_key: env::var(FM_DB_BACKUP_S3_SECRET_ACCESS_KEY_ENV)
+                .with_context(|| format!("{FM_DB_BACKUP_S3_SECRET_ACCESS_KEY_ENV} is not set"))?,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Returns the full key an object with `name` is stored under
+    pub fn object_key(&self, name: &str) -> String {
+        format!("{}{name}", self.config.key_prefix)
+    }
+
+    pub async fn put_object(&self, key: &str, body: Vec<u8>) -> anyhow::Result<()> {
+        self.send(reqwest::Method::PUT, key, body).await
+    }
+
+    pub async fn delete_object(&self, key: &str) -> anyhow::Result<()> {
+        self.send(reqwest::Method::DELETE, key, vec![]).await
+    }
+
+    async fn send(&self, method: reqwest::Method, key: &str, body: Vec<u8>) -> anyhow::Result<()> {
+        let url = self
+            .config
+            .endpoint
+            .join(&format!("{}/{key}", self.config.bucket))?;
+
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => bail!("S3 endpoint has no host"),
+        };
+
+        let payload_hash = sha256::Hash::hash(&body).to_string();
+        let (date, timestamp) = amz_date(duration_since_epoch().as_secs());
+
+        let canonical_request = format!(
+            "{method}\n{path}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{timestamp}\n\n{SIGNED_HEADERS}\n{payload_hash}",
+            path = url.path(),
+        );
+
+        let scope = format!("{date}/{}/s3/aws4_request", self.config.region);
+
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+            sha256::Hash::hash(canonical_request.as_bytes())
+        );
+
+        let signing_key = signing_key(&self.secret_access_key, &date, &self.config.region, "s3");
+        let signature = hmac(&signing_key, string_to_sign.as_bytes());
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={SIGNED_HEADERS}, Signature={signature}",
+            self.access_key_id
+        );
+
+        let response = self
+            .client
+            .request(method, url.to_unsafe())
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", timestamp)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            bail!(
+                "S3 request failed with status {}: {}",
+                response.status(),
+                response.text().await.unwrap_or_default()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+pub(crate) fn hmac(key: &[u8], data: &[u8]) -> Hmac<sha256::Hash> {
+    let mut engine = HmacEngine::<sha256::Hash>::new(key);
+    bitcoin_hashes::HashEngine::input(&mut engine, data);
+    Hmac::from_engine(engine)
+}
+
+pub(crate) fn signing_key(
+    secret_access_key: &str,
+    date: &str,
+    region: &str,
+    service: &str,
+) -> [u8; 32] {
+    let key = hmac(
+        format!("AWS4{secret_access_key}").as_bytes(),
+        date.as_bytes(),
+    );
+    let key = hmac(&key.to_byte_array(), region.as_bytes());
+    let key = hmac(&key.to_byte_array(), service.as_bytes());
+
+    hmac(&key.to_byte_array(), b"aws4_request").to_byte_array()
+}
+
+/// Formats a unix timestamp as the `YYYYMMDD` date and `YYYYMMDDTHHMMSSZ`
+/// timestamp used in signature version 4
+pub(crate) fn amz_date(unix_secs: u64) -> (String, String) {
+    let days = (unix_secs / 86400) as i64;
+    let secs_of_day = unix_secs % 86400;
+
+    // Converts days since the unix epoch into a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let date = format!("{year:04}{month:02}{day:02}");
+    let timestamp = format!(
+        "{date}T{:02}{:02}{:02}Z",
+        secs_of_day / 3600,
+        (secs_of_day % 3600) / 60,
+        secs_of_day % 60
+    );
+
+    (date, timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amz_date() {
+        assert_eq!(
+            amz_date(1_369_353_600),
+            ("20130524".to_string(), "20130524T000000Z".to_string())
+        );
+        assert_eq!(
+            amz_date(1_709_251_199),
+            ("20240229".to_string(), "20240229T235959Z".to_string())
+        );
+    }
+
+    #[test]
+    fn test_signing_key() {
+        // Example from the AWS signature version 4 documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20150830",
+            "us-east-1",
+            "iam",
+        );
+
+        assert_eq!(
+            hex::encode(key),
+            "c4afb1cc5771d871763a393e44b703571b55cc28424d1a5e86da6ed3c154a4b9"
+        );
+    }
+}
@@ -3,39 +3,46 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
+use anyhow::Context;
 use async_trait::async_trait;
 use bitcoin_hashes::sha256;
+use fedimint_aead::{decrypt, encrypt, get_encryption_key, random_salt};
 use fedimint_api_client::api::{DynGlobalApi, StatusResponse};
 use fedimint_core::admin_client::{
     ConfigGenConnectionsRequest, ConfigGenParamsConsensus, ConfigGenParamsRequest,
-    ConfigGenParamsResponse, PeerServerParams, ServerStatus,
+    ConfigGenParamsResponse, PeerServerParams, ServerStatus, SetupParamsIssue,
+    SetupParamsValidation,
 };
 use fedimint_core::config::{
     ConfigGenModuleParams, ServerModuleConfigGenParamsRegistry, ServerModuleInitRegistry,
 };
 use fedimint_core::core::ModuleInstanceId;
-use fedimint_core::db::Database;
-use fedimint_core::encoding::Encodable;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::endpoint_constants::{
     ADD_CONFIG_GEN_PEER_ENDPOINT, AUTH_ENDPOINT, CONFIG_GEN_PEERS_ENDPOINT,
     CONSENSUS_CONFIG_GEN_PARAMS_ENDPOINT, DEFAULT_CONFIG_GEN_PARAMS_ENDPOINT,
     RESTART_FEDERATION_SETUP_ENDPOINT, RUN_DKG_ENDPOINT, SET_CONFIG_GEN_CONNECTIONS_ENDPOINT,
     SET_CONFIG_GEN_PARAMS_ENDPOINT, SET_PASSWORD_ENDPOINT, START_CONSENSUS_ENDPOINT,
-    STATUS_ENDPOINT, VERIFIED_CONFIGS_ENDPOINT, VERIFY_CONFIG_HASH_ENDPOINT,
+    STATUS_ENDPOINT, VALIDATE_SETUP_PARAMS_ENDPOINT, VERIFIED_CONFIGS_ENDPOINT,
+    VERIFY_CONFIG_HASH_ENDPOINT,
 };
 use fedimint_core::module::{
     api_endpoint, ApiAuth, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased, ApiVersion,
 };
 use fedimint_core::task::{sleep, TaskGroup};
 use fedimint_core::util::SafeUrl;
-use fedimint_core::PeerId;
+use fedimint_core::{NumPeersExt, PeerId};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::Sender;
 use tokio::sync::{Mutex, MutexGuard};
 use tokio_rustls::rustls;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
+use crate::config::headless::HeadlessSetup;
 use crate::config::{gen_cert_and_key, ConfigGenParams, ServerConfig};
+use crate::consensus::db::ConfigGenStateKey;
 use crate::envs::FM_PEER_ID_SORT_BY_URL_ENV;
 use crate::net::api::{check_auth, ApiResult, HasApiContext};
 use crate::net::peers::DelayCalculator;
@@ -45,7 +52,7 @@ use crate::net::peers::DelayCalculator;
 pub struct ConfigGenApi {
     /// In-memory state machine
     state: Arc<Mutex<ConfigGenState>>,
-    /// DB not really used
+    /// Persists the state machine so an interrupted setup can be resumed
     db: Database,
     /// Tracks when the config is generated
     config_generated_tx: Sender<ServerConfig>,
@@ -74,18 +81,75 @@ impl ConfigGenApi {
         config_gen_api
     }
 
-    // Sets the auth and decryption key derived from the password
+    // Sets the auth and decryption key derived from the password, resuming a
+    // setup that was interrupted by a restart
     pub async fn set_password(&self, auth: ApiAuth) -> ApiResult<()> {
-        let mut state = self.require_status(ServerStatus::AwaitingPassword).await?;
-        state.auth = Some(auth);
-        state.status = ServerStatus::SharingConfigGenParams;
-        info!(
-            target: fedimint_logging::LOG_NET_PEER_DKG,
-            "Set password for config gen"
-        );
+        let resumed = {
+            let mut state = self.require_status(ServerStatus::AwaitingPassword).await?;
+
+            let persisted = match self
+                .db
+                .begin_transaction_nc()
+                .await
+                .get_value(&ConfigGenStateKey)
+                .await
+            {
+                Some(encrypted) => Some(encrypted.decrypt(&auth).map_err(|_| {
+                    ApiError::bad_request(
+                        "Password does not match the interrupted setup".to_string(),
+                    )
+                })?),
+                None => None,
+            };
+
+            state.auth = Some(auth);
+            state.status = ServerStatus::SharingConfigGenParams;
+            info!(
+                target: fedimint_logging::LOG_NET_PEER_DKG,
+                "Set password for config gen"
+            );
+
+            let resumed = persisted.is_some();
+
+            if let Some(persisted) = persisted {
+                state.restore(persisted);
+            }
+
+            self.persist_state(&state).await?;
+
+            resumed
+        };
+
+        if resumed {
+            // The leader needs to learn our status again
+            if let Err(e) = self.update_leader().await {
+                warn!(
+                    target: fedimint_logging::LOG_NET_PEER_DKG,
+                    "Unable to update the leader after resuming: {:?}", e
+                );
+            }
+        }
+
         Ok(())
     }
 
+    /// Stores the state encrypted with our password, so the setup can be
+    /// resumed if we are restarted
+    async fn persist_state(&self, state: &ConfigGenState) -> ApiResult<()> {
+        let Some(auth) = state.auth.as_ref() else {
+            return Ok(());
+        };
+
+        let encrypted = EncryptedConfigGenState::encrypt(&state.persisted(), auth)
+            .map_err(|e| ApiError::server_error(format!("Unable to encrypt state: {e}")))?;
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(&ConfigGenStateKey, &encrypted).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| ApiError::server_error(format!("Unable to persist state: {e}")))
+    }
+
     async fn require_status(&self, status: ServerStatus) -> ApiResult<MutexGuard<ConfigGenState>> {
         let state = self.state.lock().await;
         if state.status != status {
@@ -115,6 +179,7 @@ impl ConfigGenApi {
                 .require_status(ServerStatus::SharingConfigGenParams)
                 .await?;
             state.set_request(request)?;
+            self.persist_state(&state).await?;
         }
         self.update_leader().await?;
         Ok(())
@@ -140,7 +205,7 @@ impl ConfigGenApi {
         let mut state = self.state.lock().await;
         state.peers.insert(peer.api_url.clone(), peer);
         info!(target: fedimint_logging::LOG_NET_PEER_DKG, "New peer added to config gen");
-        Ok(())
+        self.persist_state(&state).await
     }
 
     /// Returns the peers that have called `add_config_gen_peer` on the leader
@@ -168,7 +233,41 @@ impl ConfigGenApi {
             target: fedimint_logging::LOG_NET_PEER_DKG,
             "Set params for config gen"
         );
-        Ok(())
+        self.persist_state(&state).await
+    }
+
+    /// Checks proposed params for errors and likely misconfigurations before
+    /// the setup is run
+    pub async fn validate_setup_params(
+        &self,
+        request: ConfigGenParamsRequest,
+    ) -> ApiResult<SetupParamsValidation> {
+        let state = self.state.lock().await.clone();
+        let leader_url = state
+            .local
+            .as_ref()
+            .and_then(|local| local.leader_api_url.clone());
+
+        // Followers only learn about the other guardians from the leader
+        let num_peers = match leader_url {
+            Some(leader_url) => DynGlobalApi::from_pre_peer_id_admin_endpoint(leader_url)
+                .consensus_config_gen_params()
+                .await
+                .map(|response| response.consensus.peers.len())
+                .ok(),
+            None => Some(state.get_peer_info().len()),
+        };
+
+        let mut validation = state.validate_setup_params(&request, num_peers.unwrap_or(0));
+
+        if num_peers.is_none() {
+            validation.warnings.push(SetupParamsIssue {
+                module: None,
+                message: "Unable to get the guardians from the leader".to_string(),
+            });
+        }
+
+        Ok(validation)
     }
 
     async fn get_requested_params(&self) -> ApiResult<ConfigGenParamsRequest> {
@@ -226,6 +325,7 @@ impl ConfigGenApi {
                 target: fedimint_logging::LOG_NET_PEER_DKG,
                 "Update config gen status to 'Ready for config gen'"
             );
+            self.persist_state(&state).await?;
             // Create a WSClient for the leader
             state.local.clone().and_then(|local| {
                 local
@@ -302,6 +402,7 @@ impl ConfigGenApi {
                         );
                     }
                 }
+                self_clone.persist_state(&state).await?;
             }
             self_clone.update_leader().await
         });
@@ -352,6 +453,7 @@ impl ConfigGenApi {
                 target: fedimint_logging::LOG_NET_PEER_DKG,
                 "Update config gen status to 'Verified configs'"
             );
+            self.persist_state(&state).await?;
         }
 
         self.update_leader().await?;
@@ -399,6 +501,7 @@ impl ConfigGenApi {
                 target: fedimint_logging::LOG_NET_PEER_DKG,
                 "Update config gen status to 'Setup restarted'"
             );
+            self.persist_state(&state).await?;
             // Create a WSClient for the leader
             state.local.clone().and_then(|local| {
                 local
@@ -423,6 +526,7 @@ impl ConfigGenApi {
             {
                 let mut state = self_clone.state.lock().await;
                 state.reset();
+                clear_persisted_state(&self_clone.db).await;
             }
             self_clone.update_leader().await
         });
@@ -509,6 +613,11 @@ pub struct ConfigGenSettings {
     pub max_connections: u32,
     /// Registry for config gen
     pub registry: ServerModuleInitRegistry,
+    /// Runs the setup without any interactive API calls if set
+    pub headless_setup: Option<HeadlessSetup>,
+    /// Writes the password into the data dir after the setup, so it does not
+    /// have to be provided on every start
+    pub write_plaintext_password: bool,
 }
 
 /// State held by the API after receiving a `ConfigGenConnectionsRequest`
@@ -532,11 +641,13 @@ pub struct ConfigGenState {
 }
 
 /// Our local connection info
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct ConfigGenLocalConnection {
     /// Our TLS private key
+    #[serde(with = "crate::config::serde_tls_key")]
     tls_private: rustls::PrivateKey,
     /// Our TLS public cert
+    #[serde(with = "crate::config::serde_tls_cert")]
     tls_cert: rustls::Certificate,
     /// Our guardian name
     our_name: String,
@@ -670,6 +781,88 @@ impl ConfigGenState {
         Ok(ConfigGenParams { local, consensus })
     }
 
+    /// Validates the params without generating any keys, using the defaults
+    /// for missing module params like [`Self::get_config_gen_params`]
+    fn validate_setup_params(
+        &self,
+        request: &ConfigGenParamsRequest,
+        num_peers: usize,
+    ) -> SetupParamsValidation {
+        let mut validation = SetupParamsValidation::default();
+        let default_params = &self.settings.default_params.modules;
+
+        for (id, _, _) in request.modules.iter_modules() {
+            if default_params.get(id).is_none() {
+                validation.warnings.push(SetupParamsIssue {
+                    module: Some(id),
+                    message: "The module is not part of the setup and will be ignored".to_string(),
+                });
+            }
+        }
+
+        for (id, kind, default) in default_params.iter_modules() {
+            let params = request.modules.get(id).unwrap_or(default);
+            let module = self.settings.registry.get(kind).expect("Module exists");
+
+            match module.params_warnings(params) {
+                Ok(warnings) => validation
+                    .warnings
+                    .extend(warnings.into_iter().map(|message| SetupParamsIssue {
+                        module: Some(id),
+                        message,
+                    })),
+                Err(e) => validation.errors.push(SetupParamsIssue {
+                    module: Some(id),
+                    message: itertools::join(e.chain(), ": "),
+                }),
+            }
+        }
+
+        validation
+            .warnings
+            .extend(
+                peer_count_warnings(num_peers)
+                    .into_iter()
+                    .map(|message| SetupParamsIssue {
+                        module: None,
+                        message,
+                    }),
+            );
+
+        validation
+    }
+
+    fn persisted(&self) -> PersistedConfigGenState {
+        PersistedConfigGenState {
+            local: self.local.clone(),
+            peers: self.peers.clone(),
+            requested_params: self.requested_params.clone(),
+            status: self.status.clone(),
+            config: self.config.clone(),
+        }
+    }
+
+    /// Restores the state of an interrupted setup. The DKG and the restart of
+    /// the setup can not be resumed since our peers' messages were lost, so
+    /// they have to be restarted.
+    fn restore(&mut self, persisted: PersistedConfigGenState) {
+        self.local = persisted.local;
+        self.peers = persisted.peers;
+        self.requested_params = persisted.requested_params;
+        self.config = persisted.config;
+        self.status = match persisted.status {
+            ServerStatus::ReadyForConfigGen | ServerStatus::SetupRestarted => {
+                ServerStatus::ConfigGenFailed
+            }
+            status => status,
+        };
+
+        info!(
+            target: fedimint_logging::LOG_NET_PEER_DKG,
+            "Resumed config gen with status {:?}", self.status
+        );
+    }
+
     fn reset(&mut self) {
         self.config = None;
         self.peers = Default::default();
@@ -685,6 +878,83 @@ impl ConfigGenState {
     }
 }
 
+/// Warns about federation sizes that do not tolerate any faulty guardian or
+/// that tolerate no more faulty guardians than a smaller federation
+fn peer_count_warnings(num_peers: usize) -> Vec<String> {
+    if num_peers == 0 {
+        return vec![];
+    }
+
+    let peers = (0..num_peers)
+        .map(|peer| PeerId::from(peer as u16))
+        .collect::<Vec<PeerId>>();
+
+    let max_evil = peers.max_evil();
+
+    if max_evil == 0 {
+        return vec![format!(
+            "With {num_peers} guardians and a threshold of {} the federation halts if a single \
+             guardian is offline",
+            peers.threshold()
+        )];
+    }
+
+    if num_peers != 3 * max_evil + 1 {
+        return vec![format!(
+            "{num_peers} guardians tolerate {max_evil} faulty guardians, the same as {} guardians",
+            3 * max_evil + 1
+        )];
+    }
+
+    vec![]
+}
+
+/// The parts of the [`ConfigGenState`] that survive a restart, the settings are
+/// configured locally again and the password is entered again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PersistedConfigGenState {
+    local: Option<ConfigGenLocalConnection>,
+    peers: BTreeMap<SafeUrl, PeerServerParams>,
+    requested_params: Option<ConfigGenParamsRequest>,
+    status: ServerStatus,
+    config: Option<ServerConfig>,
+}
+
+/// The [`ConfigGenState`] of an ongoing setup, encrypted with the guardian
+/// password since it contains our TLS key and possibly our private config.
+/// This is local state that is not part of consensus.
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct EncryptedConfigGenState {
+    pub salt: String,
+    pub ciphertext: Vec<u8>,
+}
+
+impl EncryptedConfigGenState {
+    fn encrypt(state: &PersistedConfigGenState, auth: &ApiAuth) -> anyhow::Result<Self> {
+        let salt = random_salt();
+        let encryption_key = get_encryption_key(&auth.0, &salt)?;
+
+        Ok(EncryptedConfigGenState {
+            ciphertext: encrypt(serde_json::to_vec(state)?, &encryption_key)?,
+            salt,
+        })
+    }
+
+    fn decrypt(mut self, auth: &ApiAuth) -> anyhow::Result<PersistedConfigGenState> {
+        let encryption_key = get_encryption_key(&auth.0, &self.salt)?;
+        let plaintext = decrypt(&mut self.ciphertext, &encryption_key)?;
+
+        serde_json::from_slice(plaintext).context("Invalid config gen state")
+    }
+}
+
+/// Removes the persisted state once the setup was restarted or completed
+pub async fn clear_persisted_state(db: &Database) {
+    let mut dbtx = db.begin_transaction().await;
+    dbtx.remove_entry(&ConfigGenStateKey).await;
+    dbtx.commit_tx().await;
+}
+
 #[async_trait]
 impl HasApiContext<ConfigGenApi> for ConfigGenApi {
     async fn context(
@@ -772,6 +1042,14 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConfigGenApi>> {
                 config.consensus_config_gen_params(&request).await
             }
         },
+        api_endpoint! {
+            VALIDATE_SETUP_PARAMS_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |config: &ConfigGenApi, context, params: ConfigGenParamsRequest| -> SetupParamsValidation {
+                check_auth(context)?;
+                config.validate_setup_params(params).await
+            }
+        },
         api_endpoint! {
             RUN_DKG_ENDPOINT,
             ApiVersion::new(0, 0),
@@ -864,9 +1142,15 @@ mod tests {
     use itertools::Itertools;
     use tracing::info;
 
-    use crate::config::api::{ConfigGenConnectionsRequest, ConfigGenSettings};
+    use crate::config::api::{
+        peer_count_warnings, ConfigGenConnectionsRequest, ConfigGenLocalConnection,
+        ConfigGenSettings, EncryptedConfigGenState, PersistedConfigGenState,
+    };
     use crate::config::io::{read_server_config, PLAINTEXT_PASSWORD};
-    use crate::config::{DynServerModuleInit, ServerConfig, DEFAULT_MAX_CLIENT_CONNECTIONS};
+    use crate::config::secrets::FileSecretsBackend;
+    use crate::config::{
+        gen_cert_and_key, DynServerModuleInit, ServerConfig, DEFAULT_MAX_CLIENT_CONNECTIONS,
+    };
     use crate::fedimint_core::module::ServerModuleInit;
 
     /// Helper in config API tests for simulating a guardian's client and server
@@ -911,6 +1195,8 @@ mod tests {
                 registry: ServerModuleInitRegistry::from(vec![DynServerModuleInit::from(
                     DummyInit,
                 )]),
+                headless_setup: None,
+                write_plaintext_password: true,
             };
 
             let dir = data_dir.join(name_suffix.to_string());
@@ -920,8 +1206,12 @@ mod tests {
             let settings_clone = settings.clone();
 
             spawn("fedimint server", async move {
+                let secrets = FileSecretsBackend::new(dir_clone.clone());
+
                 crate::run(
                     dir_clone,
+                    None,
+                    &secrets,
                     settings_clone,
                     db,
                     "dummyversionhash".to_owned(),
@@ -1052,6 +1342,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_peer_count_warnings() {
+        assert!(peer_count_warnings(0).is_empty());
+        assert_eq!(peer_count_warnings(1).len(), 1);
+        assert_eq!(peer_count_warnings(3).len(), 1);
+        assert!(peer_count_warnings(4).is_empty());
+        assert_eq!(peer_count_warnings(5).len(), 1);
+        assert_eq!(peer_count_warnings(6).len(), 1);
+        assert!(peer_count_warnings(7).is_empty());
+    }
+
+    #[test]
+    fn test_encrypted_config_gen_state() {
+        let (tls_cert, tls_private) = gen_cert_and_key("peer0").unwrap();
+
+        let state = PersistedConfigGenState {
+            local: Some(ConfigGenLocalConnection {
+                tls_private,
+                tls_cert: tls_cert.clone(),
+                our_name: "peer0".to_string(),
+                leader_api_url: None,
+            }),
+            peers: BTreeMap::new(),
+            requested_params: None,
+            status: ServerStatus::VerifiedConfigs,
+            config: None,
+        };
+
+        let auth = ApiAuth("password".to_string());
+        let encrypted = EncryptedConfigGenState::encrypt(&state, &auth).unwrap();
+
+        assert!(encrypted
+            .clone()
+            .decrypt(&ApiAuth("wrong password".to_string()))
+            .is_err());
+
+        let decrypted = encrypted.decrypt(&auth).unwrap();
+
+        assert_eq!(decrypted.status, ServerStatus::VerifiedConfigs);
+        assert_eq!(decrypted.local.unwrap().tls_cert, tls_cert);
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_config_api() {
         const PEER_NUM: u16 = 4;
@@ -11,7 +11,7 @@ use bls12_381::Scalar;
 use fedimint_core::config::{
     DkgError, DkgGroup, DkgMessage, DkgPeerMsg, DkgResult, ISupportedDkgMessage,
 };
-use fedimint_core::core::{Decoder, ModuleInstanceId, ModuleKind};
+use fedimint_core::core::{Decoder, ModuleInstanceId, ModuleKind, MODULE_INSTANCE_ID_GLOBAL};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::PeerHandle;
@@ -32,6 +32,21 @@ use threshold_crypto::{
     G1Affine, G1Projective, G2Affine, G2Projective, PublicKeySet, SecretKeyShare,
 };
 
+use crate::multiplexed::{LaneConfig, LaneOverflowPolicy, MessagePriority, PriorityLanesConfig};
+
+/// Lanes of the DKG multiplexer, no lane may drop messages since every DKG
+/// message is required to complete the key generation
+pub const DKG_PRIORITY_LANES: PriorityLanesConfig = PriorityLanesConfig {
+    critical: LaneConfig {
+        buffer: 1000,
+        overflow: LaneOverflowPolicy::Backpressure,
+    },
+    bulk: LaneConfig {
+        buffer: 100,
+        overflow: LaneOverflowPolicy::Backpressure,
+    },
+};
+
 struct Dkg<G> {
     gen_g: G,
     peers: Vec<PeerId>,
@@ -243,6 +258,17 @@ pub fn scalar(peer: &PeerId) -> Scalar {
     Scalar::from(peer.to_usize() as u64 + 1)
 }
 
+/// The module DKGs exchange large polynomial commitments, e.g. one per amount
+/// tier of the mint, so they are queued behind the small messages of the
+/// global key exchange and the completion confirmations
+pub fn dkg_message_priority(key: &(ModuleInstanceId, String)) -> MessagePriority {
+    if key.0 == MODULE_INSTANCE_ID_GLOBAL {
+        MessagePriority::Critical
+    } else {
+        MessagePriority::Bulk
+    }
+}
+
 pub struct DkgRunner<T> {
     peers: Vec<PeerId>,
     our_id: PeerId,
@@ -599,13 +625,77 @@ impl<'a> PeerHandleOps for PeerHandle<'a> {
 mod tests {
     use std::collections::{HashMap, VecDeque};
 
+    use fedimint_core::config::DkgPeerMsg;
+    use fedimint_core::core::MODULE_INSTANCE_ID_GLOBAL;
+    use fedimint_core::net::peers::fake::make_fake_peer_connection;
+    use fedimint_core::net::peers::IMuxPeerConnections;
+    use fedimint_core::task::TaskGroup;
     use fedimint_core::PeerId;
     use rand::rngs::OsRng;
     use threshold_crypto::{G1Projective, G2Projective};
 
     use crate::config::distributedgen::{
-        evaluate_polynomial_g2, scalar, Dkg, DkgGroup, DkgKeys, DkgStep, ThresholdKeys,
+        dkg_message_priority, evaluate_polynomial_g2, scalar, Dkg, DkgGroup, DkgKeys, DkgStep,
+        ThresholdKeys, DKG_PRIORITY_LANES,
     };
+    use crate::multiplexed::{MessagePriority, PeerConnectionMultiplexer};
+
+    #[test_log::test(tokio::test)]
+    async fn test_dkg_priority_lanes() {
+        const NUM_MSGS: usize = 500;
+
+        let task_group = TaskGroup::new();
+        let peer1 = PeerId::from(0);
+        let peer2 = PeerId::from(1);
+
+        let module_key = (0, "tbs".to_string());
+        let global_key = (MODULE_INSTANCE_ID_GLOBAL, "DKG DONE".to_string());
+
+        assert_eq!(dkg_message_priority(&module_key), MessagePriority::Bulk);
+        assert_eq!(dkg_message_priority(&global_key), MessagePriority::Critical);
+
+        let (conn1, conn2) =
+            make_fake_peer_connection(peer1, peer2, 1000, task_group.make_handle());
+        let conn1 = PeerConnectionMultiplexer::with_priority_lanes(
+            conn1,
+            dkg_message_priority,
+            DKG_PRIORITY_LANES,
+        )
+        .into_dyn();
+        let conn2 = PeerConnectionMultiplexer::new(conn2).into_dyn();
+
+        {
+            let module_key = module_key.clone();
+            let global_key = global_key.clone();
+            task_group.spawn("dkg-sender", move |_| async move {
+                // Flood the bulk lane beyond its buffer before the confirmation
+                for _ in 0..NUM_MSGS {
+                    conn1
+                        .send(&[peer2], module_key.clone(), DkgPeerMsg::Done)
+                        .await
+                        .unwrap();
+                }
+                conn1
+                    .send(&[peer2], global_key, DkgPeerMsg::Done)
+                    .await
+                    .unwrap();
+            });
+        }
+
+        // Unlike the default bulk lane, the DKG lanes must not drop messages
+        for _ in 0..NUM_MSGS {
+            assert!(matches!(
+                conn2.receive(module_key.clone()).await.unwrap(),
+                (peer, DkgPeerMsg::Done) if peer == peer1
+            ));
+        }
+        assert!(matches!(
+            conn2.receive(global_key).await.unwrap(),
+            (peer, DkgPeerMsg::Done) if peer == peer1
+        ));
+
+        task_group.join_all(None).await.expect("no failures");
+    }
 
     #[test_log::test]
     fn test_dkg() {
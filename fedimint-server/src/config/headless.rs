@@ -0,0 +1,272 @@
+//! Runs the config gen ceremony from a declarative setup file
+//!
+//! Instead of a guardian operating the setup UI, every guardian is started
+//! with a setup file describing its role in the ceremony. The guardian then
+//! drives its own [`ConfigGenApi`] through the same steps the UI would take, so
+//! a federation can be spun up reproducibly, e.g. in CI. The leader waits until
+//! the expected number of guardians joined before running the DKG, and the
+//! configs are accepted without a manual comparison of the verification
+//! hashes.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, ensure, Context};
+use fedimint_core::admin_client::{ConfigGenConnectionsRequest, ServerStatus};
+use fedimint_core::config::ServerModuleConfigGenParamsRegistry;
+use fedimint_core::module::{ApiAuth, ApiError};
+use fedimint_core::task::sleep;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{NumPeersExt, PeerId};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::config::api::{ConfigGenApi, ConfigGenSettings};
+
+/// How often we poll for the progress of the other guardians
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Declarative setup of a guardian, read from a JSON file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeadlessSetup {
+    /// Our guardian name
+    pub our_name: String,
+    /// API URL of the leader, `None` if we are the leader
+    pub leader_api_url: Option<SafeUrl>,
+    /// Password for the admin API and our encrypted config
+    pub password: ApiAuth,
+    /// Number of guardians in the federation, including us
+    pub num_peers: usize,
+    /// Expected threshold of the federation, which follows from the number of
+    /// guardians. Only used to catch mistakes in the setup file.
+    #[serde(default)]
+    pub threshold: Option<usize>,
+    /// Meta values of the federation, only used by the leader
+    #[serde(default)]
+    pub meta: BTreeMap<String, String>,
+    /// Module params, the defaults are used if not set. The consensus params
+    /// are only used by the leader.
+    #[serde(default)]
+    pub modules: Option<ServerModuleConfigGenParamsRegistry>,
+    /// Overrides the bind address for P2P communication
+    #[serde(default)]
+    pub p2p_bind: Option<SocketAddr>,
+    /// Overrides the bind address for API communication
+    #[serde(default)]
+    pub api_bind: Option<SocketAddr>,
+    /// Overrides the URL for our P2P connection
+    #[serde(default)]
+    pub p2p_url: Option<SafeUrl>,
+    /// Overrides the URL for our API connection
+    #[serde(default)]
+    pub api_url: Option<SafeUrl>,
+}
+
+impl HeadlessSetup {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Unable to open setup file {}", path.display()))?;
+
+        let setup: HeadlessSetup =
+            serde_json::from_reader(file).context("Unable to parse setup file")?;
+
+        setup.validate()?;
+
+        Ok(setup)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            0 < self.num_peers,
+            "The federation needs at least one guardian"
+        );
+
+        if let Some(threshold) = self.threshold {
+            let peers = (0..self.num_peers)
+                .map(|peer| PeerId::from(peer as u16))
+                .collect::<Vec<PeerId>>();
+
+            ensure!(
+                threshold == peers.threshold(),
+                "A federation of {} guardians has a threshold of {}",
+                self.num_peers,
+                peers.threshold()
+            );
+        }
+
+        Ok(())
+    }
+
+    fn is_leader(&self) -> bool {
+        self.leader_api_url.is_none()
+    }
+}
+
+impl ConfigGenSettings {
+    /// Runs config gen headlessly with the setup, overriding our addresses
+    pub fn with_headless_setup(mut self, setup: HeadlessSetup) -> Self {
+        self.p2p_bind = setup.p2p_bind.unwrap_or(self.p2p_bind);
+        self.api_bind = setup.api_bind.unwrap_or(self.api_bind);
+        self.p2p_url = setup.p2p_url.clone().unwrap_or(self.p2p_url);
+        self.api_url = setup.api_url.clone().unwrap_or(self.api_url);
+        self.headless_setup = Some(setup);
+        self
+    }
+}
+
+fn api_error(error: ApiError) -> anyhow::Error {
+    anyhow!(error.message)
+}
+
+/// Drives the config gen API through the ceremony until the consensus is
+/// started
+pub async fn run_headless_setup(api: ConfigGenApi, setup: HeadlessSetup) -> anyhow::Result<()> {
+    api.set_password(setup.password.clone())
+        .await
+        .map_err(api_error)?;
+
+    match api.server_status().await {
+        ServerStatus::SharingConfigGenParams => {
+            share_config_gen_params(&api, &setup).await?;
+            run_dkg(&api, &setup).await?;
+        }
+        // We were restarted after the DKG of an interrupted setup
+        ServerStatus::VerifyingConfigs | ServerStatus::VerifiedConfigs => {}
+        status => bail!("Unable to resume the setup in status {status:?}"),
+    }
+
+    for (peer, hash) in api.verify_config_hash().await.map_err(api_error)? {
+        info!(
+            target: fedimint_logging::LOG_NET_PEER_DKG,
+            "Verification hash for peer {peer}: {hash}"
+        );
+    }
+
+    api.verified_configs().await.map_err(api_error)?;
+    api.start_consensus().await.map_err(api_error)?;
+
+    Ok(())
+}
+
+async fn share_config_gen_params(api: &ConfigGenApi, setup: &HeadlessSetup) -> anyhow::Result<()> {
+    let connections = ConfigGenConnectionsRequest {
+        our_name: setup.our_name.clone(),
+        leader_api_url: setup.leader_api_url.clone(),
+    };
+
+    // The leader might not be up yet
+    while let Err(e) = api.set_config_gen_connections(connections.clone()).await {
+        info!(
+            target: fedimint_logging::LOG_NET_PEER_DKG,
+            "Waiting for the leader: {}", e.message
+        );
+        sleep(POLL_INTERVAL).await;
+    }
+
+    let mut params = api.default_config_gen_params().await.map_err(api_error)?;
+
+    if let Some(modules) = setup.modules.clone() {
+        params.modules = modules;
+    }
+
+    params.meta.extend(setup.meta.clone());
+
+    // Followers can only validate their params once the leader set its params
+    while let Err(e) = api.set_config_gen_params(params.clone()).await {
+        ensure!(
+            !setup.is_leader(),
+            "Invalid config gen params: {}",
+            e.message
+        );
+
+        info!(
+            target: fedimint_logging::LOG_NET_PEER_DKG,
+            "Waiting for the leader's params: {}", e.message
+        );
+        sleep(POLL_INTERVAL).await;
+    }
+
+    Ok(())
+}
+
+async fn run_dkg(api: &ConfigGenApi, setup: &HeadlessSetup) -> anyhow::Result<()> {
+    // The leader waits for all followers to join and be ready, the followers
+    // wait for the leader to start the DKG
+    if setup.is_leader() {
+        loop {
+            let peers = api.config_gen_peers().await.map_err(api_error)?;
+
+            // We are the only guardian that is not ready yet
+            let ready = peers
+                .iter()
+                .filter(|peer| peer.status == Some(ServerStatus::ReadyForConfigGen))
+                .count();
+
+            if peers.len() == setup.num_peers && ready + 1 == setup.num_peers {
+                break;
+            }
+
+            ensure!(
+                peers.len() <= setup.num_peers,
+                "More guardians than expected joined the setup"
+            );
+
+            info!(
+                target: fedimint_logging::LOG_NET_PEER_DKG,
+                "Waiting for guardians, {} of {} joined and {} are ready",
+                peers.len(),
+                setup.num_peers,
+                ready
+            );
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    api.run_dkg().await.map_err(api_error)?;
+
+    loop {
+        match api.server_status().await {
+            ServerStatus::VerifyingConfigs => return Ok(()),
+            ServerStatus::ConfigGenFailed => bail!("The DKG failed"),
+            _ => sleep(POLL_INTERVAL).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeadlessSetup;
+
+    #[test]
+    fn test_validate_headless_setup() {
+        let setup: HeadlessSetup = serde_json::from_str(
+            r#"{
+                "our_name": "peer1",
+                "leader_api_url": "ws://127.0.0.1:8174",
+                "password": "pass",
+                "num_peers": 4,
+                "threshold": 3
+            }"#,
+        )
+        .unwrap();
+
+        assert!(setup.validate().is_ok());
+
+        let wrong_threshold = HeadlessSetup {
+            threshold: Some(4),
+            ..setup.clone()
+        };
+
+        assert!(wrong_threshold.validate().is_err());
+
+        let no_peers = HeadlessSetup {
+            num_peers: 0,
+            threshold: None,
+            ..setup
+        };
+
+        assert!(no_peers.validate().is_err());
+    }
+}
@@ -3,12 +3,16 @@ use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
-use fedimint_aead::{encrypted_read, encrypted_write, get_encryption_key, LessSafeKey};
+use anyhow::Context;
+use fedimint_aead::{
+    decrypt, encrypt, encrypted_read, encrypted_write, get_encryption_key, random_salt, LessSafeKey,
+};
 use fedimint_core::config::ServerModuleInitRegistry;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use crate::config::ServerConfig;
+use crate::config::secrets::SecretsBackend;
+use crate::config::{ServerConfig, ServerConfigPrivate};
 
 /// Client configuration file
 pub const CLIENT_CONFIG: &str = "client";
@@ -49,11 +53,71 @@ pub fn read_server_config(password: &str, path: PathBuf) -> anyhow::Result<Serve
     let salt = fs::read_to_string(path.join(SALT_FILE))?;
     let key = get_encryption_key(password, &salt)?;
 
-    Ok(ServerConfig {
+    let private = encrypted_json_read(&key, path.join(PRIVATE_CONFIG))?;
+
+    assemble_server_config(path, private)
+}
+
+/// Reads the server config like [`read_server_config`], but fetches the salt
+/// and the encrypted private config from the secrets backend
+pub async fn read_server_config_from(
+    secrets: &dyn SecretsBackend,
+    password: &str,
+    path: PathBuf,
+) -> anyhow::Result<ServerConfig> {
+    let key = secrets_encryption_key(secrets, password).await?;
+
+    let mut encrypted = hex::decode(
+        secrets
+            .read(&private_config_secret())
+            .await?
+            .context("The private config is missing")?,
+    )?;
+
+    let private = serde_json::from_slice(decrypt(&mut encrypted, &key)?)?;
+
+    assemble_server_config(path, private)
+}
+
+/// Returns true if the secrets backend contains a private config
+pub async fn has_private_config(secrets: &dyn SecretsBackend) -> anyhow::Result<bool> {
+    Ok(secrets.read(&private_config_secret()).await?.is_some())
+}
+
+/// Name of the encrypted private config in a secrets backend, which matches
+/// its file name in the data dir
+fn private_config_secret() -> String {
+    format!("{PRIVATE_CONFIG}.{ENCRYPTED_EXT}")
+}
+
+async fn secrets_encryption_key(
+    secrets: &dyn SecretsBackend,
+    password: &str,
+) -> anyhow::Result<LessSafeKey> {
+    let salt = secrets
+        .read(SALT_FILE)
+        .await?
+        .context("The salt is missing")?;
+
+    get_encryption_key(password, &String::from_utf8(salt)?)
+}
+
+/// Combines the private config with the plaintext configs and opens the key
+/// store
+fn assemble_server_config(
+    path: PathBuf,
+    private: ServerConfigPrivate,
+) -> anyhow::Result<ServerConfig> {
+    let mut cfg = ServerConfig {
         consensus: plaintext_json_read(path.join(CONSENSUS_CONFIG))?,
         local: plaintext_json_read(path.join(LOCAL_CONFIG))?,
-        private: encrypted_json_read(&key, path.join(PRIVATE_CONFIG))?,
-    })
+        private,
+        key_store: None,
+    };
+
+    cfg.key_store = Some(cfg.open_key_store()?);
+
+    Ok(cfg)
 }
 
 /// Reads a plaintext json file into a struct
@@ -82,6 +146,50 @@ pub fn write_server_config(
     let salt = fs::read_to_string(path.join(SALT_FILE))?;
     let key = get_encryption_key(password, &salt)?;
 
+    write_plaintext_configs(server, &path, module_config_gens)?;
+    encrypted_json_write(&server.private, &key, path.join(PRIVATE_CONFIG))
+}
+
+/// Writes the server config like [`write_server_config`], but stores a new
+/// salt and the encrypted private config in the secrets backend
+pub async fn write_server_config_to(
+    secrets: &dyn SecretsBackend,
+    server: &ServerConfig,
+    path: PathBuf,
+    password: &str,
+    module_config_gens: &ServerModuleInitRegistry,
+) -> anyhow::Result<()> {
+    write_plaintext_configs(server, &path, module_config_gens)?;
+
+    secrets.write(SALT_FILE, random_salt().into_bytes()).await?;
+
+    write_private_config(secrets, &server.private, password).await
+}
+
+/// Replaces the private config in the secrets backend, e.g. after moving its
+/// keys into an HSM
+pub async fn write_private_config(
+    secrets: &dyn SecretsBackend,
+    private: &ServerConfigPrivate,
+    password: &str,
+) -> anyhow::Result<()> {
+    let key = secrets_encryption_key(secrets, password).await?;
+
+    let encrypted = encrypt(serde_json::to_vec(private)?, &key)?;
+
+    secrets
+        .write(
+            &private_config_secret(),
+            hex::encode(encrypted).into_bytes(),
+        )
+        .await
+}
+
+fn write_plaintext_configs(
+    server: &ServerConfig,
+    path: &Path,
+    module_config_gens: &ServerModuleInitRegistry,
+) -> anyhow::Result<()> {
     let client_config = server.consensus.to_client_config(module_config_gens)?;
     plaintext_json_write(&server.local, path.join(LOCAL_CONFIG))?;
     plaintext_json_write(&server.consensus, path.join(CONSENSUS_CONFIG))?;
@@ -89,8 +197,7 @@ pub fn write_server_config(
         &server.get_invite_code(),
         &path.join(CLIENT_INVITE_CODE_FILE),
     )?;
-    plaintext_json_write(&client_config, path.join(CLIENT_CONFIG))?;
-    encrypted_json_write(&server.private, &key, path.join(PRIVATE_CONFIG))
+    plaintext_json_write(&client_config, path.join(CLIENT_CONFIG))
 }
 
 /// Writes struct into a plaintext json file
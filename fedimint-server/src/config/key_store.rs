@@ -0,0 +1,175 @@
+//! Storage of the guardian's TLS and broadcast secret keys
+//!
+//! By default both keys are part of the encrypted private config. Institutional
+//! guardians can instead keep them in an HSM accessed via PKCS#11, in which
+//! case the private config only contains the [`Pkcs11Config`] and the keys
+//! never leave the HSM.
+
+use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Context;
+use fedimint_core::secp256k1;
+use secp256k1::hashes::{sha256, Hash};
+use secp256k1::{schnorr, KeyPair, Message, PublicKey};
+use serde::{Deserialize, Serialize};
+use tokio_rustls::rustls;
+
+use crate::config::ServerConfigPrivate;
+
+/// Message signed when opening a key store to check it holds our broadcast
+/// secret key
+const KEY_STORE_CHECK_MESSAGE: &[u8] = b"fedimint-key-store-check";
+
+/// Holds the secret keys of a guardian and signs with them
+pub trait KeyStore: Debug + Send + Sync {
+    /// Signs a message of the atomic broadcast with our broadcast secret key
+    fn sign_schnorr(&self, message: &Message) -> anyhow::Result<schnorr::Signature>;
+
+    /// Key we authenticate ourselves with in TLS handshakes with our peers
+    fn tls_signing_key(&self) -> Arc<dyn rustls::sign::SigningKey>;
+}
+
+pub type DynKeyStore = Arc<dyn KeyStore>;
+
+/// Keys held by an HSM accessed via PKCS#11
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pkcs11Config {
+    /// Path of the PKCS#11 module provided by the HSM vendor
+    pub module: PathBuf,
+    /// Label of the token holding our keys
+    pub token_label: String,
+    /// PIN of the token user
+    pub pin: String,
+    /// Label of the NIST P-256 private key of our TLS certificate
+    pub tls_key_label: String,
+    /// Label of our secp256k1 broadcast secret key
+    pub broadcast_key_label: String,
+    /// Vendor defined mechanism creating BIP-340 Schnorr signatures, since
+    /// PKCS#11 does not standardize one
+    pub schnorr_mechanism: u64,
+}
+
+/// Keys kept in the encrypted private config
+pub struct PrivateConfigKeyStore {
+    broadcast_keypair: KeyPair,
+    tls_key: Arc<dyn rustls::sign::SigningKey>,
+}
+
+impl PrivateConfigKeyStore {
+    pub fn new(private: &ServerConfigPrivate) -> anyhow::Result<PrivateConfigKeyStore> {
+        let broadcast_secret_key = private
+            .broadcast_secret_key
+            .context("The private config contains no broadcast secret key")?;
+
+        let tls_key = private
+            .tls_key
+            .as_ref()
+            .context("The private config contains no TLS key")?;
+
+        Ok(PrivateConfigKeyStore {
+            broadcast_keypair: broadcast_secret_key.keypair(secp256k1::SECP256K1),
+            tls_key: rustls::sign::any_supported_type(tls_key).context("Unsupported TLS key")?,
+        })
+    }
+}
+
+impl Debug for PrivateConfigKeyStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PrivateConfigKeyStore")
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyStore for PrivateConfigKeyStore {
+    fn sign_schnorr(&self, message: &Message) -> anyhow::Result<schnorr::Signature> {
+        Ok(self.broadcast_keypair.sign_schnorr(*message))
+    }
+
+    fn tls_signing_key(&self) -> Arc<dyn rustls::sign::SigningKey> {
+        self.tls_key.clone()
+    }
+}
+
+/// Opens the key store configured in the private config and checks that it
+/// holds the secret key for our broadcast public key
+pub fn open_key_store(
+    private: &ServerConfigPrivate,
+    broadcast_public_key: &PublicKey,
+) -> anyhow::Result<DynKeyStore> {
+    let key_store: DynKeyStore = match &private.pkcs11 {
+        Some(config) => open_pkcs11_key_store(config)?,
+        None => Arc::new(PrivateConfigKeyStore::new(private)?),
+    };
+
+    verify_broadcast_key(key_store.as_ref(), broadcast_public_key)?;
+
+    Ok(key_store)
+}
+
+#[cfg(feature = "pkcs11")]
+fn open_pkcs11_key_store(config: &Pkcs11Config) -> anyhow::Result<DynKeyStore> {
+    Ok(Arc::new(super::pkcs11::Pkcs11KeyStore::open(config)?))
+}
+
+#[cfg(not(feature = "pkcs11"))]
+fn open_pkcs11_key_store(_config: &Pkcs11Config) -> anyhow::Result<DynKeyStore> {
+    anyhow::bail!("The keys are held by an HSM, but the server was built without PKCS#11 support")
+}
+
+/// Checks that the key store signs with the secret key of the public key
+pub fn verify_broadcast_key(
+    key_store: &dyn KeyStore,
+    public_key: &PublicKey,
+) -> anyhow::Result<()> {
+    let message = Message::from(sha256::Hash::hash(KEY_STORE_CHECK_MESSAGE));
+
+    let signature = key_store.sign_schnorr(&message)?;
+
+    secp256k1::SECP256K1
+        .verify_schnorr(&signature, &message, &public_key.x_only_public_key().0)
+        .context("Broadcast secret key doesn't match corresponding public key")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::module::ApiAuth;
+    use fedimint_core::secp256k1::{self, SecretKey};
+    use rand::rngs::OsRng;
+
+    use super::{verify_broadcast_key, PrivateConfigKeyStore};
+    use crate::config::{gen_cert_and_key, ServerConfigPrivate};
+
+    #[test]
+    fn test_verify_broadcast_key() {
+        let secret_key = SecretKey::new(&mut OsRng);
+        let other_key = SecretKey::new(&mut OsRng);
+
+        let private = ServerConfigPrivate {
+            api_auth: ApiAuth("pass".to_string()),
+            tls_key: Some(gen_cert_and_key("peer").unwrap().1),
+            broadcast_secret_key: Some(secret_key),
+            pkcs11: None,
+            modules: BTreeMap::new(),
+        };
+
+        let key_store = PrivateConfigKeyStore::new(&private).unwrap();
+
+        let public_key = secret_key.public_key(secp256k1::SECP256K1);
+        let other_public_key = other_key.public_key(secp256k1::SECP256K1);
+
+        assert!(verify_broadcast_key(&key_store, &public_key).is_ok());
+        assert!(verify_broadcast_key(&key_store, &other_public_key).is_err());
+
+        let without_keys = ServerConfigPrivate {
+            tls_key: None,
+            broadcast_secret_key: None,
+            ..private
+        };
+
+        assert!(PrivateConfigKeyStore::new(&without_keys).is_err());
+    }
+}
@@ -1,9 +1,11 @@
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::env;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::{bail, format_err};
+use anyhow::{bail, ensure, format_err, Context};
 use fedimint_core::admin_client::ConfigGenParamsConsensus;
 pub use fedimint_core::config::{
     serde_binary_human_readable, ClientConfig, DkgError, DkgPeerMsg, DkgResult, FederationId,
@@ -23,26 +25,41 @@ use fedimint_core::{secp256k1, timing, PeerId};
 use fedimint_logging::{LOG_NET_PEER, LOG_NET_PEER_DKG};
 use futures::future::join_all;
 use rand::rngs::OsRng;
-use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use secp256k1::{PublicKey, SecretKey};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tokio_rustls::rustls;
 use tracing::{error, info};
 
+use crate::alerts::AlertConfig;
+use crate::backup::DbBackupConfig;
 use crate::config::api::ConfigGenParamsLocal;
-use crate::config::distributedgen::{DkgRunner, PeerHandleOps};
+use crate::config::distributedgen::{
+    dkg_message_priority, DkgRunner, PeerHandleOps, DKG_PRIORITY_LANES,
+};
+use crate::config::key_store::{
+    open_key_store, verify_broadcast_key, DynKeyStore, Pkcs11Config, PrivateConfigKeyStore,
+};
+use crate::consensus::proposals::ModuleProposalConfig;
+use crate::consensus::pruning::SessionPruningPolicy;
+use crate::consensus::SubmissionQueueConfig;
 use crate::envs::FM_MAX_CLIENT_CONNECTIONS_ENV;
 use crate::fedimint_core::encoding::Encodable;
 use crate::fedimint_core::NumPeersExt;
 use crate::multiplexed::PeerConnectionMultiplexer;
-use crate::net::connect::{dns_sanitize, Connector, TlsConfig};
-use crate::net::peers::{DelayCalculator, NetworkConfig};
+use crate::net::connect::{dns_sanitize, Connector, TlsConfig, TlsPrivateKey};
+use crate::net::peers::{DelayCalculator, NetworkConfig, PeerRateLimitConfig, PeerReconnectConfig};
 use crate::net::peers_reliable::ReconnectPeerConnectionsReliable;
 use crate::TlsTcpConnector;
 
 pub mod api;
 pub mod distributedgen;
+pub mod headless;
 pub mod io;
+pub mod key_store;
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+pub mod secrets;
 
 /// The default maximum open connections the API can handle
 const DEFAULT_MAX_CLIENT_CONNECTIONS: u32 = 1000;
@@ -70,6 +87,11 @@ pub struct ServerConfig {
     /// Contains all configuration that will be encrypted such as private key
     /// material
     pub private: ServerConfigPrivate,
+    /// Key store holding our TLS and broadcast secret keys, opened when the
+    /// config is read from disk. The keys of the private config are used if
+    /// not set.
+    #[serde(skip)]
+    pub key_store: Option<DynKeyStore>,
 }
 
 impl ServerConfig {
@@ -105,11 +127,21 @@ impl ServerConfig {
 pub struct ServerConfigPrivate {
     /// Secret API auth string
     pub api_auth: ApiAuth,
-    /// Secret key for TLS communication, required for peer authentication
-    #[serde(with = "serde_tls_key")]
-    pub tls_key: rustls::PrivateKey,
-    /// Secret key for the atomic broadcast to sign messages
-    pub broadcast_secret_key: SecretKey,
+    /// Secret key for TLS communication, required for peer authentication.
+    /// Not set if the key is held by an HSM.
+    #[serde(
+        default,
+        with = "serde_tls_key::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tls_key: Option<rustls::PrivateKey>,
+    /// Secret key for the atomic broadcast to sign messages. Not set if the
+    /// key is held by an HSM.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broadcast_secret_key: Option<SecretKey>,
+    /// HSM holding our TLS and broadcast secret keys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pkcs11: Option<Pkcs11Config>,
     /// Secret material from modules
     pub modules: BTreeMap<ModuleInstanceId, JsonWithKind>,
 }
@@ -163,6 +195,37 @@ pub struct ServerConfigLocal {
     /// keep the session time constant these two have to behave inversely
     /// proportional.
     pub broadcast_round_delay_ms: u16,
+    /// Directory encrypted database snapshots are written to when requested
+    /// via the `backup_database` endpoint
+    #[serde(default)]
+    pub db_backup_dir: Option<PathBuf>,
+    /// Schedule for periodic encrypted database backups, disabled if not set
+    #[serde(default)]
+    pub db_backup_schedule: Option<DbBackupConfig>,
+    /// Delete the outcomes of old sessions once a signed checkpoint covers
+    /// them, keeps the full history if not set
+    ///
+    /// Clients recovering from the session history and peers that can not
+    /// fast-sync need the pruned sessions, so they fail to catch up if every
+    /// guardian of the federation enables this
+    #[serde(default)]
+    pub session_pruning: Option<SessionPruningPolicy>,
+    /// Backoff and circuit breaker for the connections to our peers
+    #[serde(default)]
+    pub peer_reconnect: PeerReconnectConfig,
+    /// Caps on the message size and rate we accept from our peers
+    #[serde(default)]
+    pub peer_rate_limit: PeerRateLimitConfig,
+    /// Size and overflow policy of the queue of submitted transactions
+    #[serde(default)]
+    pub submission_queue: SubmissionQueueConfig,
+    /// Rate limits, priorities and buffers for the consensus items proposed by
+    /// the modules of a kind, the default applies to kinds not listed
+    #[serde(default)]
+    pub module_proposals: BTreeMap<ModuleKind, ModuleProposalConfig>,
+    /// Webhooks notified about critical events, disabled if not set
+    #[serde(default)]
+    pub alerts: Option<AlertConfig>,
     /// Non-consensus, non-private configuration from modules
     pub modules: BTreeMap<ModuleInstanceId, JsonWithKind>,
 }
@@ -230,8 +293,9 @@ impl ServerConfig {
     ) -> Self {
         let private = ServerConfigPrivate {
             api_auth: params.local.api_auth.clone(),
-            tls_key: params.local.our_private_key.clone(),
-            broadcast_secret_key,
+            tls_key: Some(params.local.our_private_key.clone()),
+            broadcast_secret_key: Some(broadcast_secret_key),
+            pkcs11: None,
             modules: Default::default(),
         };
         let local = ServerConfigLocal {
@@ -245,6 +309,14 @@ impl ServerConfig {
             } else {
                 DEFAULT_BROADCAST_ROUND_DELAY_MS
             },
+            db_backup_dir: None,
+            db_backup_schedule: None,
+            session_pruning: None,
+            peer_reconnect: PeerReconnectConfig::default(),
+            peer_rate_limit: PeerRateLimitConfig::default(),
+            submission_queue: SubmissionQueueConfig::default(),
+            module_proposals: BTreeMap::new(),
+            alerts: None,
             modules: Default::default(),
         };
         let consensus = ServerConfigConsensus {
@@ -271,6 +343,7 @@ impl ServerConfig {
             consensus,
             local,
             private,
+            key_store: None,
         };
         cfg.add_modules(modules);
         cfg
@@ -375,12 +448,10 @@ impl ServerConfig {
     ) -> anyhow::Result<()> {
         let peers = self.local.p2p_endpoints.clone();
         let consensus = self.consensus.clone();
-        let private = self.private.clone();
-
-        let my_public_key = private.broadcast_secret_key.public_key(&Secp256k1::new());
 
-        if Some(&my_public_key) != consensus.broadcast_public_keys.get(identity) {
-            bail!("Broadcast secret key doesn't match corresponding public key");
+        match consensus.broadcast_public_keys.get(identity) {
+            Some(public_key) => verify_broadcast_key(self.key_store()?.as_ref(), public_key)?,
+            None => bail!("Broadcast secret key doesn't match corresponding public key"),
         }
         if peers.keys().max().copied().map(|id| id.to_usize()) != Some(peers.len() - 1) {
             bail!("Peer ids are not indexed from 0");
@@ -472,7 +543,12 @@ impl ServerConfig {
             task_group,
         )
         .await;
-        let connections = PeerConnectionMultiplexer::new(server_conn).into_dyn();
+        let connections = PeerConnectionMultiplexer::with_priority_lanes(
+            server_conn,
+            dkg_message_priority,
+            DKG_PRIORITY_LANES,
+        )
+        .into_dyn();
 
         let peers = &params.peer_ids();
         let our_id = &params.local.our_id;
@@ -616,17 +692,46 @@ impl ServerConfig {
         }
     }
 
-    pub fn tls_config(&self) -> TlsConfig {
-        TlsConfig {
-            our_private_key: self.private.tls_key.clone(),
+    /// The key store holding our TLS and broadcast secret keys. Keys held by
+    /// an HSM require opening the key store with
+    /// [`ServerConfig::open_key_store`] first.
+    pub fn key_store(&self) -> anyhow::Result<DynKeyStore> {
+        if let Some(key_store) = &self.key_store {
+            return Ok(key_store.clone());
+        }
+
+        ensure!(
+            self.private.pkcs11.is_none(),
+            "Keys held by an HSM require opening the key store first"
+        );
+
+        Ok(Arc::new(PrivateConfigKeyStore::new(&self.private)?))
+    }
+
+    /// Opens the key store configured in the private config, which can be
+    /// expensive if the keys are held by an HSM
+    pub fn open_key_store(&self) -> anyhow::Result<DynKeyStore> {
+        let public_key = self
+            .consensus
+            .broadcast_public_keys
+            .get(&self.local.identity)
+            .context("Our broadcast public key is missing from the config")?;
+
+        open_key_store(&self.private, public_key)
+    }
+
+    pub fn tls_config(&self) -> anyhow::Result<TlsConfig> {
+        Ok(TlsConfig {
+            our_private_key: TlsPrivateKey::KeyStore(self.key_store()?),
             peer_certs: self.consensus.tls_certs.clone(),
+            previous_peer_certs: BTreeMap::new(),
             peer_names: self
                 .local
                 .p2p_endpoints
                 .iter()
                 .map(|(id, endpoint)| (*id, endpoint.name.to_string()))
                 .collect(),
-        }
+        })
     }
 
     pub fn get_incoming_count(&self) -> u16 {
@@ -653,8 +758,9 @@ impl ConfigGenParams {
 
     pub fn tls_config(&self) -> TlsConfig {
         TlsConfig {
-            our_private_key: self.local.our_private_key.clone(),
+            our_private_key: self.local.our_private_key.clone().into(),
             peer_certs: self.tls_certs(),
+            previous_peer_certs: BTreeMap::new(),
             peer_names: self
                 .p2p_urls()
                 .into_iter()
@@ -818,4 +924,62 @@ mod serde_tls_key {
         let bytes = Vec::from_hex(hex_str.as_ref()).map_err(serde::de::Error::custom)?;
         Ok(rustls::PrivateKey(bytes))
     }
+
+    pub mod option {
+        use std::borrow::Cow;
+
+        use hex::{FromHex, ToHex};
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+        use tokio_rustls::rustls;
+
+        pub fn serialize<S>(
+            key: &Option<rustls::PrivateKey>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            let hex_str = key.as_ref().map(|key| key.0.encode_hex::<String>());
+            Serialize::serialize(&hex_str, serializer)
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<rustls::PrivateKey>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let hex_str: Option<Cow<str>> = Deserialize::deserialize(deserializer)?;
+            hex_str
+                .map(|hex_str| {
+                    let bytes =
+                        Vec::from_hex(hex_str.as_ref()).map_err(serde::de::Error::custom)?;
+                    Ok(rustls::PrivateKey(bytes))
+                })
+                .transpose()
+        }
+    }
+}
+
+mod serde_tls_cert {
+    use std::borrow::Cow;
+
+    use hex::{FromHex, ToHex};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use tokio_rustls::rustls;
+
+    pub fn serialize<S>(cert: &rustls::Certificate, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let hex_str = cert.0.encode_hex::<String>();
+        Serialize::serialize(&hex_str, serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<rustls::Certificate, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let hex_str: Cow<str> = Deserialize::deserialize(deserializer)?;
+        let bytes = Vec::from_hex(hex_str.as_ref()).map_err(serde::de::Error::custom)?;
+        Ok(rustls::Certificate(bytes))
+    }
 }
@@ -0,0 +1,215 @@
+//! [`KeyStore`] backed by an HSM accessed via PKCS#11
+//!
+//! The TLS key is a NIST P-256 key signing with the standard `CKM_ECDSA_SHA256`
+//! mechanism. PKCS#11 does not standardize BIP-340 Schnorr signatures over
+//! secp256k1, so the broadcast key signs with the vendor defined mechanism from
+//! the [`Pkcs11Config`].
+
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{ensure, Context};
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::mechanism::vendor_defined::VendorDefinedMechanism;
+use cryptoki::mechanism::{Mechanism, MechanismType};
+use cryptoki::object::{Attribute, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::types::AuthPin;
+use fedimint_core::secp256k1::{schnorr, Message};
+use tokio_rustls::rustls;
+
+use crate::config::key_store::{KeyStore, Pkcs11Config};
+
+pub struct Pkcs11KeyStore {
+    tls_key: Pkcs11Key,
+    broadcast_key: Pkcs11Key,
+    schnorr_mechanism: MechanismType,
+}
+
+/// Handle of a private key on the token, sharing the session with the other
+/// keys
+#[derive(Clone)]
+struct Pkcs11Key {
+    session: Arc<Mutex<Session>>,
+    handle: ObjectHandle,
+}
+
+impl Pkcs11KeyStore {
+    pub fn open(config: &Pkcs11Config) -> anyhow::Result<Pkcs11KeyStore> {
+        let pkcs11 = Pkcs11::new(&config.module).with_context(|| {
+            format!(
+                "Unable to load the PKCS#11 module {}",
+                config.module.display()
+            )
+        })?;
+
+        pkcs11.initialize(CInitializeArgs::OsThreads)?;
+
+        let slot = pkcs11
+            .get_slots_with_token()?
+            .into_iter()
+            .find(|slot| {
+                pkcs11
+                    .get_token_info(*slot)
+                    .is_ok_and(|info| info.label() == config.token_label)
+            })
+            .with_context(|| format!("No token with the label {}", config.token_label))?;
+
+        let session = pkcs11.open_ro_session(slot)?;
+
+        session.login(UserType::User, Some(&AuthPin::new(config.pin.clone())))?;
+
+        let tls_key = find_private_key(&session, &config.tls_key_label)?;
+        let broadcast_key = find_private_key(&session, &config.broadcast_key_label)?;
+
+        let session = Arc::new(Mutex::new(session));
+
+        Ok(Pkcs11KeyStore {
+            tls_key: Pkcs11Key {
+                session: session.clone(),
+                handle: tls_key,
+            },
+            broadcast_key: Pkcs11Key {
+                session,
+                handle: broadcast_key,
+            },
+            schnorr_mechanism: MechanismType::new_vendor_defined(config.schnorr_mechanism)?,
+        })
+    }
+}
+
+fn find_private_key(session: &Session, label: &str) -> anyhow::Result<ObjectHandle> {
+    let keys = session.find_objects(&[
+        Attribute::Class(ObjectClass::PRIVATE_KEY),
+        Attribute::Label(label.as_bytes().to_vec()),
+    ])?;
+
+    ensure!(
+        keys.len() == 1,
+        "Expected one private key with the label {label}, found {}",
+        keys.len()
+    );
+
+    Ok(keys[0])
+}
+
+impl Pkcs11Key {
+    fn sign_with(&self, mechanism: &Mechanism, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let session = self.session.lock().expect("Lock poisoned");
+
+        Ok(session.sign(mechanism, self.handle, data)?)
+    }
+}
+
+impl Debug for Pkcs11KeyStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pkcs11KeyStore").finish_non_exhaustive()
+    }
+}
+
+impl KeyStore for Pkcs11KeyStore {
+    fn sign_schnorr(&self, message: &Message) -> anyhow::Result<schnorr::Signature> {
+        let mechanism = Mechanism::VendorDefined(VendorDefinedMechanism::new::<()>(
+            self.schnorr_mechanism,
+            None,
+        ));
+
+        let signature = self.broadcast_key.sign_with(&mechanism, message.as_ref())?;
+
+        schnorr::Signature::from_slice(&signature).context("The HSM returned an invalid signature")
+    }
+
+    fn tls_signing_key(&self) -> Arc<dyn rustls::sign::SigningKey> {
+        Arc::new(self.tls_key.clone())
+    }
+}
+
+impl rustls::sign::SigningKey for Pkcs11Key {
+    fn choose_scheme(
+        &self,
+        offered: &[rustls::SignatureScheme],
+    ) -> Option<Box<dyn rustls::sign::Signer>> {
+        if offered.contains(&rustls::SignatureScheme::ECDSA_NISTP256_SHA256) {
+            Some(Box::new(self.clone()))
+        } else {
+            None
+        }
+    }
+
+    fn algorithm(&self) -> rustls::SignatureAlgorithm {
+        rustls::SignatureAlgorithm::ECDSA
+    }
+}
+
+impl rustls::sign::Signer for Pkcs11Key {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+        self.sign_with(&Mechanism::EcdsaSha256, message)
+            .and_then(|signature| ecdsa_signature_to_der(&signature))
+            .map_err(|e| rustls::Error::General(format!("HSM signing failed: {e}")))
+    }
+
+    fn scheme(&self) -> rustls::SignatureScheme {
+        rustls::SignatureScheme::ECDSA_NISTP256_SHA256
+    }
+}
+
+/// Converts a P-256 signature from the `r || s` encoding of PKCS#11 into the
+/// ASN.1 DER encoding used by TLS
+fn ecdsa_signature_to_der(signature: &[u8]) -> anyhow::Result<Vec<u8>> {
+    ensure!(
+        signature.len() == 64,
+        "Expected a signature of 64 bytes, got {}",
+        signature.len()
+    );
+
+    let (r, s) = signature.split_at(32);
+    let (r, s) = (der_integer(r), der_integer(s));
+
+    // Both integers are at most 35 bytes long, so the length fits into a byte
+    let mut der = vec![0x30, (r.len() + s.len()) as u8];
+    der.extend(r);
+    der.extend(s);
+
+    Ok(der)
+}
+
+/// Encodes big endian bytes as a positive ASN.1 DER integer
+fn der_integer(bytes: &[u8]) -> Vec<u8> {
+    let start = bytes
+        .iter()
+        .position(|byte| *byte != 0)
+        .unwrap_or(bytes.len() - 1);
+
+    let bytes = &bytes[start..];
+
+    // A set high bit would make the integer negative
+    let padding: &[u8] = if bytes[0] & 0x80 != 0 { &[0x00] } else { &[] };
+
+    [
+        &[0x02, (padding.len() + bytes.len()) as u8][..],
+        padding,
+        bytes,
+    ]
+    .concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ecdsa_signature_to_der;
+
+    #[test]
+    fn test_ecdsa_signature_to_der() {
+        let mut signature = [0x00; 64];
+        signature[31] = 0x01;
+        signature[32..].fill(0x80);
+
+        let der = ecdsa_signature_to_der(&signature).unwrap();
+
+        let mut expected = vec![0x30, 38, 0x02, 0x01, 0x01, 0x02, 33, 0x00];
+        expected.extend([0x80; 32]);
+
+        assert_eq!(der, expected);
+
+        assert!(ecdsa_signature_to_der(&signature[1..]).is_err());
+    }
+}
@@ -0,0 +1,445 @@
+//! Storage of the salt and the encrypted private config
+//!
+//! By default both are files in the data dir, but they can also be kept in a
+//! remote secrets store, so a compromised host does not leak the encrypted
+//! private config. The backend is selected by a URI:
+//!
+//! * `file:///path/to/dir` stores the secrets as files in the directory
+//! * `vault+https://vault.example.com:8200/<mount>/<path>` stores them in the
+//!   KV version 2 secrets engine of HashiCorp Vault, authenticated with the
+//!   token in `VAULT_TOKEN`
+//! * `aws-secretsmanager://<region>/<prefix>` stores them in AWS Secrets
+//!   Manager, authenticated with the credentials in
+//!   `FM_SECRETS_AWS_ACCESS_KEY_ID` and `FM_SECRETS_AWS_SECRET_ACCESS_KEY`
+
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{env, fs};
+
+use anyhow::{bail, ensure, Context};
+use async_trait::async_trait;
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::time::duration_since_epoch;
+use serde::Deserialize;
+use serde_json::json;
+use url::Url;
+
+use crate::backup::s3::{amz_date, hmac, signing_key};
+use crate::envs::{
+    FM_SECRETS_AWS_ACCESS_KEY_ID_ENV, FM_SECRETS_AWS_SECRET_ACCESS_KEY_ENV, VAULT_TOKEN_ENV,
+};
+
+/// Stores secrets by name
+#[async_trait]
+pub trait SecretsBackend: Debug + Send + Sync {
+    /// Reads a secret, returns `None` if it does not exist
+    async fn read(&self, name: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Writes a secret, replacing any previous value
+    async fn write(&self, name: &str, value: Vec<u8>) -> anyhow::Result<()>;
+}
+
+pub type DynSecretsBackend = Arc<dyn SecretsBackend>;
+
+/// Opens the secrets backend for the URI, or the data dir if none is given
+pub fn open_secrets_backend(
+    uri: Option<&str>,
+    data_dir: &Path,
+) -> anyhow::Result<DynSecretsBackend> {
+    let Some(uri) = uri else {
+        return Ok(Arc::new(FileSecretsBackend::new(data_dir.to_owned())));
+    };
+
+    let uri = Url::parse(uri).context("Invalid secrets backend URI")?;
+
+    Ok(match uri.scheme() {
+        "file" => Arc::new(FileSecretsBackend::new(PathBuf::from(uri.path()))),
+        "vault+http" | "vault+https" => Arc::new(VaultSecretsBackend::new(
+            &uri,
+            env::var(VAULT_TOKEN_ENV).with_context(|| format!("{VAULT_TOKEN_ENV} is not set"))?,
+        )?),
+        "aws-secretsmanager" => Arc::new(AwsSecretsBackend::from_env(&uri)?),
+        scheme => bail!("Unsupported secrets backend {scheme}"),
+    })
+}
+
+/// Secrets stored as files in a directory
+#[derive(Debug)]
+pub struct FileSecretsBackend {
+    dir: PathBuf,
+}
+
+impl FileSecretsBackend {
+    pub fn new(dir: PathBuf) -> FileSecretsBackend {
+        FileSecretsBackend { dir }
+    }
+}
+
+#[async_trait]
+impl SecretsBackend for FileSecretsBackend {
+    async fn read(&self, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match fs::read(self.dir.join(name)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn write(&self, name: &str, value: Vec<u8>) -> anyhow::Result<()> {
+        // Write a new file first, so we never leave a partially written secret
+        let new_file = self.dir.join(format!("{name}.new"));
+
+        fs::write(&new_file, value)?;
+        fs::rename(new_file, self.dir.join(name))?;
+
+        Ok(())
+    }
+}
+
+/// Secrets stored in the KV version 2 secrets engine of HashiCorp Vault, every
+/// secret as a hex encoded `value` below a common path
+pub struct VaultSecretsBackend {
+    /// Data URL of the common path, e.g.
+    /// `https://vault.example.com:8200/v1/secret/data/fedimint/guardian-0`
+    url: String,
+    token: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct VaultResponse {
+    data: VaultSecretVersion,
+}
+
+#[derive(Deserialize)]
+struct VaultSecretVersion {
+    data: VaultSecret,
+}
+
+#[derive(Deserialize)]
+struct VaultSecret {
+    value: String,
+}
+
+impl VaultSecretsBackend {
+    pub fn new(uri: &Url, token: String) -> anyhow::Result<VaultSecretsBackend> {
+        let scheme = uri
+            .scheme()
+            .strip_prefix("vault+")
+            .context("Not a Vault URI")?;
+
+        let host = uri.host_str().context("The Vault URI has no host")?;
+
+        let port = uri
+            .port()
+            .map(|port| format!(":{port}"))
+            .unwrap_or_default();
+
+        let mut segments = uri
+            .path_segments()
+            .into_iter()
+            .flatten()
+            .filter(|segment| !segment.is_empty());
+
+        let mount = segments
+            .next()
+            .context("The Vault URI has no secrets engine mount")?;
+
+        let path = segments.collect::<Vec<_>>().join("/");
+
+        ensure!(!path.is_empty(), "The Vault URI has no secret path");
+
+        Ok(VaultSecretsBackend {
+            url: format!("{scheme}://{host}{port}/v1/{mount}/data/{path}"),
+            token,
+            client: reqwest::Client::new(),
+        })
+    }
+}
+
+impl Debug for VaultSecretsBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VaultSecretsBackend")
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl SecretsBackend for VaultSecretsBackend {
+    async fn read(&self, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let response = self
+            .client
+            .get(format!("{}/{name}", self.url))
+            .header("X-Vault-Token", &self.token)
+            .send()
+            .await?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        if !response.status().is_success() {
+            bail!("Vault request failed with status {}", response.status());
+        }
+
+        let response: VaultResponse = serde_json::from_slice(&response.bytes().await?)?;
+
+        Ok(Some(hex::decode(response.data.data.value)?))
+    }
+
+    async fn write(&self, name: &str, value: Vec<u8>) -> anyhow::Result<()> {
+        let body = json!({ "data": { "value": hex::encode(value) } });
+
+        let response = self
+            .client
+            .post(format!("{}/{name}", self.url))
+            .header("X-Vault-Token", &self.token)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            bail!("Vault request failed with status {}", response.status());
+        }
+
+        Ok(())
+    }
+}
+
+/// Secrets stored in AWS Secrets Manager as hex encoded secret strings named
+/// `{prefix}/{name}`
+pub struct AwsSecretsBackend {
+    region: String,
+    prefix: String,
+    access_key_id: String,
+    secret_access_key: String,
+    client: reqwest::Client,
+}
+
+/// Headers included in the signature of every request
+const AWS_SIGNED_HEADERS: &str = "content-type;host;x-amz-date;x-amz-target";
+
+#[derive(Deserialize)]
+struct AwsSecretValue {
+    #[serde(rename = "SecretString")]
+    secret_string: String,
+}
+
+#[derive(Deserialize)]
+struct AwsError {
+    #[serde(rename = "__type")]
+    error_type: String,
+}
+
+impl AwsSecretsBackend {
+    /// Credentials are read from the environment so they never end up in the
+    /// plaintext local config
+    pub fn from_env(uri: &Url) -> anyhow::Result<AwsSecretsBackend> {
+        let region = uri
+            .host_str()
+            .context("The AWS Secrets Manager URI has no region")?;
+
+        let prefix = uri.path().trim_matches('/');
+
+        ensure!(
+            !prefix.is_empty(),
+            "The AWS Secrets Manager URI has no prefix"
+        );
+
+        Ok(AwsSecretsBackend {
+            region: region.to_owned(),
+            prefix: prefix.to_owned(),
+            access_key_id: env::var(FM_SECRETS_AWS_ACCESS_KEY_ID_ENV)
+                .with_context(|| format!("{FM_SECRETS_AWS_ACCESS_KEY_ID_ENV} is not set"))?,
+            secret_access_key: env::var(FM_SECRETS_AWS_SECRET_ACCESS_KEY_ENV)
+                .with_context(|| format!("{FM_SECRETS_AWS_SECRET_ACCESS_KEY_ENV} is not set"))?,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    fn secret_id(&self, name: &str) -> String {
+        format!("{}/{name}", self.prefix)
+    }
+
+    /// Calls an action of the Secrets Manager JSON API, returning the response
+    /// body on success and the error type otherwise
+    async fn call(
+        &self,
+        action: &str,
+        body: serde_json::Value,
+    ) -> anyhow::Result<Result<Vec<u8>, String>> {
+        let host = format!("secretsmanager.{}.amazonaws.com", self.region);
+        let target = format!("secretsmanager.{action}");
+        let body = body.to_string();
+
+        let (date, timestamp) = amz_date(duration_since_epoch().as_secs());
+
+        let canonical_request = format!(
+            "POST\n/\n\ncontent-type:application/x-amz-json-1.1\nhost:{host}\nx-amz-date:{timestamp}\nx-amz-target:{target}\n\n{AWS_SIGNED_HEADERS}\n{}",
+            sha256::Hash::hash(body.as_bytes())
+        );
+
+        let scope = format!("{date}/{}/secretsmanager/aws4_request", self.region);
+
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+            sha256::Hash::hash(canonical_request.as_bytes())
+        );
+
+        let signing_key = signing_key(
+            &self.secret_access_key,
+            &date,
+            &self.region,
+            "secretsmanager",
+        );
+        let signature = hmac(&signing_key, string_to_sign.as_bytes());
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={AWS_SIGNED_HEADERS}, Signature={signature}",
+            self.access_key_id
+        );
+
+        let response = self
+            .client
+            .post(format!("https://{host}/"))
+            .header("content-type", "application/x-amz-json-1.1")
+            .header("x-amz-date", timestamp)
+            .header("x-amz-target", target)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await?;
+
+        let success = response.status().is_success();
+        let status = response.status();
+        let bytes = response.bytes().await?.to_vec();
+
+        if success {
+            return Ok(Ok(bytes));
+        }
+
+        match serde_json::from_slice::<AwsError>(&bytes) {
+            Ok(error) => Ok(Err(error.error_type)),
+            Err(_) => bail!("AWS Secrets Manager request failed with status {status}"),
+        }
+    }
+}
+
+impl Debug for AwsSecretsBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AwsSecretsBackend")
+            .field("region", &self.region)
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Error types are sometimes prefixed with a namespace
+fn is_aws_error(error_type: &str, expected: &str) -> bool {
+    error_type.rsplit('#').next() == Some(expected)
+}
+
+#[async_trait]
+impl SecretsBackend for AwsSecretsBackend {
+    async fn read(&self, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let response = self
+            .call(
+                "GetSecretValue",
+                json!({ "SecretId": self.secret_id(name) }),
+            )
+            .await?;
+
+        match response {
+            Ok(bytes) => {
+                let value: AwsSecretValue = serde_json::from_slice(&bytes)?;
+                Ok(Some(hex::decode(value.secret_string)?))
+            }
+            Err(error) if is_aws_error(&error, "ResourceNotFoundException") => Ok(None),
+            Err(error) => bail!("AWS Secrets Manager request failed: {error}"),
+        }
+    }
+
+    async fn write(&self, name: &str, value: Vec<u8>) -> anyhow::Result<()> {
+        let value = hex::encode(value);
+
+        let response = self
+            .call(
+                "PutSecretValue",
+                json!({ "SecretId": self.secret_id(name), "SecretString": value }),
+            )
+            .await?;
+
+        let response = match response {
+            Err(error) if is_aws_error(&error, "ResourceNotFoundException") => {
+                self.call(
+                    "CreateSecret",
+                    json!({ "Name": self.secret_id(name), "SecretString": value }),
+                )
+                .await?
+            }
+            response => response,
+        };
+
+        if let Err(error) = response {
+            bail!("AWS Secrets Manager request failed: {error}");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::{is_aws_error, FileSecretsBackend, SecretsBackend, VaultSecretsBackend};
+
+    #[tokio::test]
+    async fn test_file_secrets_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = FileSecretsBackend::new(dir.path().to_owned());
+
+        assert_eq!(backend.read("secret").await.unwrap(), None);
+
+        backend.write("secret", vec![1]).await.unwrap();
+        backend.write("secret", vec![2]).await.unwrap();
+
+        assert_eq!(backend.read("secret").await.unwrap(), Some(vec![2]));
+    }
+
+    #[test]
+    fn test_vault_url() {
+        let uri =
+            Url::parse("vault+https://vault.example.com:8200/secret/fedimint/guardian-0").unwrap();
+
+        let backend = VaultSecretsBackend::new(&uri, "token".to_string()).unwrap();
+
+        assert_eq!(
+            backend.url,
+            "https://vault.example.com:8200/v1/secret/data/fedimint/guardian-0"
+        );
+
+        let no_path = Url::parse("vault+https://vault.example.com/secret").unwrap();
+
+        assert!(VaultSecretsBackend::new(&no_path, "token".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_is_aws_error() {
+        assert!(is_aws_error(
+            "ResourceNotFoundException",
+            "ResourceNotFoundException"
+        ));
+        assert!(is_aws_error(
+            "com.amazonaws.secretsmanager#ResourceNotFoundException",
+            "ResourceNotFoundException"
+        ));
+        assert!(!is_aws_error(
+            "AccessDeniedException",
+            "ResourceNotFoundException"
+        ));
+    }
+}
@@ -2,16 +2,21 @@
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use anyhow::{anyhow, Result};
+use async_channel::TrySendError;
 use async_trait::async_trait;
 use bitcoin_hashes::sha256;
 use fedimint_aead::{encrypt, get_encryption_key, random_salt};
 use fedimint_api_client::api::{
-    FederationStatus, GuardianConfigBackup, PeerConnectionStatus, PeerStatus, StatusResponse,
+    FederationStatus, GuardianConfigBackup, GuardianDatabaseBackup, PeerConnectionStatus,
+    PeerStatus, StatusResponse,
+};
+use fedimint_core::admin_client::{
+    CompactDbRequest, CreateInviteCodeRequest, DbPrefixStats, MempoolSummary, ServerStatus,
+    SessionTiming, SetLogFilterRequest, UpgradeStatus,
 };
-use fedimint_core::admin_client::ServerStatus;
 use fedimint_core::backup::{ClientBackupKey, ClientBackupSnapshot};
 use fedimint_core::config::ClientConfig;
 use fedimint_core::core::backup::{SignedBackupRequest, BACKUP_REQUEST_MAX_PAYLOAD_SIZE_BYTES};
@@ -21,13 +26,22 @@ use fedimint_core::db::{
 };
 use fedimint_core::endpoint_constants::{
     AUDIT_ENDPOINT, AUTH_ENDPOINT, AWAIT_OUTPUT_OUTCOME_ENDPOINT, AWAIT_SESSION_OUTCOME_ENDPOINT,
-    AWAIT_SIGNED_SESSION_OUTCOME_ENDPOINT, AWAIT_TRANSACTION_ENDPOINT, BACKUP_ENDPOINT,
-    CLIENT_CONFIG_ENDPOINT, FEDERATION_ID_ENDPOINT, GUARDIAN_CONFIG_BACKUP_ENDPOINT,
-    INVITE_CODE_ENDPOINT, MODULES_CONFIG_JSON_ENDPOINT, RECOVER_ENDPOINT,
-    SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT, SESSION_COUNT_ENDPOINT, SESSION_STATUS_ENDPOINT,
-    SHUTDOWN_ENDPOINT, STATUS_ENDPOINT, SUBMIT_TRANSACTION_ENDPOINT, VERSION_ENDPOINT,
+    AWAIT_SIGNED_SESSION_OUTCOME_ENDPOINT, AWAIT_TRANSACTION_ENDPOINT, BACKUP_DATABASE_ENDPOINT,
+    BACKUP_ENDPOINT, CANCEL_UPGRADE_ENDPOINT, CHECKPOINT_SIGNATURE_ENDPOINT,
+    CLIENT_CONFIG_ENDPOINT, COMPACT_DB_ENDPOINT, DB_STATS_ENDPOINT, DOWNLOAD_CHECKPOINT_ENDPOINT,
+    FEDERATION_ID_ENDPOINT, FEDERATION_STATUS_ENDPOINT, GUARDIAN_CONFIG_BACKUP_ENDPOINT,
+    INVITE_CODE_ENDPOINT, LATEST_CHECKPOINT_ENDPOINT, MEMPOOL_ENDPOINT,
+    MODULES_CONFIG_JSON_ENDPOINT, PEER_ENDPOINTS_ENDPOINT, RECOVER_ENDPOINT,
+    ROTATE_TLS_CERT_ENDPOINT, SCHEDULE_UPGRADE_ENDPOINT, SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT,
+    SESSION_COUNT_ENDPOINT, SESSION_STATUS_ENDPOINT, SESSION_TIMING_ENDPOINT,
+    SET_LOG_FILTER_ENDPOINT, SHUTDOWN_ENDPOINT, STATUS_ENDPOINT, SUBMIT_TRANSACTION_ENDPOINT,
+    TRANSACTION_STATUS_ENDPOINT, UPDATE_PEER_ENDPOINTS_ENDPOINT, UPGRADE_STATUS_ENDPOINT,
+    VERSION_ENDPOINT,
+};
+use fedimint_core::epoch::{
+    ConsensusItem, FederationMeta, FederationMetaRecord, PeerEndpoints, UpgradeVote,
 };
-use fedimint_core::epoch::ConsensusItem;
+use fedimint_core::invite_code::InviteCode;
 use fedimint_core::module::audit::{Audit, AuditSummary};
 use fedimint_core::module::registry::ServerModuleRegistry;
 use fedimint_core::module::{
@@ -36,26 +50,52 @@ use fedimint_core::module::{
 };
 use fedimint_core::secp256k1::{PublicKey, SECP256K1};
 use fedimint_core::server::DynServerModule;
-use fedimint_core::session_outcome::{SessionOutcome, SessionStatus, SignedSessionOutcome};
+use fedimint_core::session_outcome::{
+    SchnorrSignature, SessionOutcome, SessionStatus, SignedSessionOutcome,
+};
+use fedimint_core::task::TaskGroup;
 use fedimint_core::transaction::{
-    SerdeTransaction, Transaction, TransactionError, TransactionSubmissionOutcome,
+    SerdeTransaction, Transaction, TransactionError, TransactionStatus,
+    TransactionSubmissionOutcome,
 };
-use fedimint_core::{OutPoint, PeerId, TransactionId};
+use fedimint_core::{NumPeersExt, OutPoint, PeerId, TransactionId};
 use fedimint_logging::LOG_NET_API;
 use futures::StreamExt;
 use tokio::sync::{watch, RwLock};
-use tracing::{debug, info};
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
 
+use crate::atomic_broadcast::Keychain;
 use crate::config::io::{
     CONSENSUS_CONFIG, ENCRYPTED_EXT, JSON_EXT, LOCAL_CONFIG, PRIVATE_CONFIG, SALT_FILE,
 };
 use crate::config::{JsonWithKind, ServerConfig};
-use crate::consensus::db::{AcceptedItemPrefix, AcceptedTransactionKey, SignedSessionOutcomeKey};
+use crate::consensus::checkpoint::{
+    CheckpointChunk, DownloadCheckpointRequest, SignedCheckpointHeader,
+};
+use crate::consensus::db::{
+    AcceptedItemPrefix, AcceptedTransactionKey, CheckpointChunkKey, CheckpointHeaderKey,
+    FederationMetaKey, PeerEndpointsPrefix, RejectedTransactionKey, SignedCheckpointHeaderKey,
+    SignedSessionOutcomeKey, TransactionSessionKey,
+};
+use crate::consensus::db_maintenance::{compact_db, db_stats};
+use crate::consensus::dedup::RecentTransactions;
 use crate::consensus::engine::get_finished_session_count_static;
+use crate::consensus::federation_meta::federation_meta_proposals;
+use crate::consensus::invite::{create_invite_code, redeem_invite_code};
+use crate::consensus::mempool::MempoolTracker;
+use crate::consensus::profiling::SessionProfiler;
+use crate::consensus::tls_rotation::generate_tls_cert_rotation;
 use crate::consensus::transaction::process_transaction_with_dbtx;
+use crate::consensus::upgrade::upgrade_status;
+use crate::consensus::{api_endpoints, SubmissionOverflowPolicy};
 use crate::fedimint_core::encoding::Encodable;
-use crate::metrics::{BACKUP_WRITE_SIZE_BYTES, STORED_BACKUPS_COUNT};
+use crate::metrics::{
+    BACKUP_WRITE_SIZE_BYTES, STORED_BACKUPS_COUNT, SUBMISSION_DEDUP_HITS_COUNT,
+    SUBMISSION_QUEUE_DEPTH, SUBMISSION_QUEUE_REJECTED_COUNT,
+};
 use crate::net::api::{check_auth, ApiResult, HasApiContext};
+use crate::snapshot::{DbSnapshot, DB_SNAPSHOT_EXT};
 
 #[derive(Clone)]
 pub struct ConsensusApi {
@@ -69,10 +109,21 @@ pub struct ConsensusApi {
     pub client_cfg: ClientConfig,
     /// For sending API events to consensus such as transactions
     pub submission_sender: async_channel::Sender<ConsensusItem>,
+    /// Transactions we queued recently, so retries are not proposed again
+    pub recent_transactions: Arc<Mutex<RecentTransactions>>,
+    /// Summary of the items in the submission queue
+    pub mempool: MempoolTracker,
+    pub session_profiler: SessionProfiler,
     pub shutdown_sender: watch::Sender<Option<u64>>,
+    /// Set once consensus halted for a scheduled upgrade
+    pub upgrade_receiver: watch::Receiver<Option<u64>>,
     pub connection_status_channels: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStatus>>>,
     pub last_ci_by_peer: Arc<RwLock<BTreeMap<PeerId, u64>>>,
+    /// Latest session after which a peer's state hash differed from ours
+    pub state_divergence_by_peer: Arc<RwLock<BTreeMap<PeerId, u64>>>,
     pub supported_api_versions: SupportedApiVersionsSummary,
+    /// Background work started by API requests, e.g. database backups
+    pub task_group: TaskGroup,
 }
 
 impl ConsensusApi {
@@ -80,12 +131,12 @@ impl ConsensusApi {
         &self.supported_api_versions
     }
 
-    // we want to return an error if and only if the submitted transaction is
-    // invalid and will be rejected if we were to submit it to consensus
+    // we want to return an inner error if and only if the submitted transaction
+    // is invalid and will be rejected if we were to submit it to consensus
     pub async fn submit_transaction(
         &self,
         transaction: Transaction,
-    ) -> Result<TransactionId, TransactionError> {
+    ) -> ApiResult<Result<TransactionId, TransactionError>> {
         let txid = transaction.tx_hash();
 
         debug!(target: LOG_NET_API, %txid, "Received a submitted transaction");
@@ -99,20 +150,279 @@ impl ConsensusApi {
             .is_some()
         {
             debug!(target: LOG_NET_API, %txid, "Transaction already accepted");
-            return Ok(txid);
+            return Ok(Ok(txid));
+        }
+
+        if self
+            .recent_transactions
+            .lock()
+            .expect("Lock poisoned")
+            .contains(&txid, Instant::now())
+        {
+            debug!(target: LOG_NET_API, %txid, "Transaction already queued");
+            SUBMISSION_DEDUP_HITS_COUNT.inc();
+            return Ok(Ok(txid));
         }
 
         // We ignore any writes, as we only verify if the transaction is valid here
         dbtx.ignore_uncommitted();
 
-        process_transaction_with_dbtx(self.modules.clone(), &mut dbtx, transaction.clone()).await?;
+        if let Err(e) =
+            process_transaction_with_dbtx(self.modules.clone(), &mut dbtx, transaction.clone())
+                .await
+        {
+            return Ok(Err(e));
+        }
+
+        self.enqueue_transaction(transaction).await?;
+
+        self.recent_transactions
+            .lock()
+            .expect("Lock poisoned")
+            .insert(txid, Instant::now());
+
+        Ok(Ok(txid))
+    }
+
+    async fn enqueue_transaction(&self, transaction: Transaction) -> ApiResult<()> {
+        let item = ConsensusItem::Transaction(transaction);
+
+        match self.cfg.local.submission_queue.overflow {
+            SubmissionOverflowPolicy::Backpressure => self.submit_item(item).await,
+            SubmissionOverflowPolicy::Reject => {
+                self.mempool.insert(&item);
+
+                let result = match self.submission_sender.try_send(item) {
+                    Ok(()) => Ok(()),
+                    Err(TrySendError::Full(item)) => {
+                        warn!(target: LOG_NET_API, "Submission queue is full, rejecting transaction");
+                        SUBMISSION_QUEUE_REJECTED_COUNT.inc();
+                        self.mempool.remove(&item);
+                        Err(ApiError::mempool_full())
+                    }
+                    Err(TrySendError::Closed(item)) => {
+                        self.mempool.remove(&item);
+                        Err(ApiError::server_error(
+                            "Consensus is shutting down".to_string(),
+                        ))
+                    }
+                };
+
+                SUBMISSION_QUEUE_DEPTH.set(self.submission_sender.len() as i64);
+
+                result
+            }
+        }
+    }
+
+    /// Sends the item into the submission queue, waiting for space if the
+    /// queue is full
+    async fn submit_item(&self, item: ConsensusItem) -> ApiResult<()> {
+        self.mempool.insert(&item);
+
+        if let Err(e) = self.submission_sender.send(item).await {
+            self.mempool.remove(&e.0);
+            return Err(ApiError::server_error(
+                "Consensus is shutting down".to_string(),
+            ));
+        }
+
+        SUBMISSION_QUEUE_DEPTH.set(self.submission_sender.len() as i64);
+
+        Ok(())
+    }
+
+    pub fn mempool_summary(&self) -> MempoolSummary {
+        self.mempool.summary(Instant::now())
+    }
+
+    /// Announces new endpoints of this guardian to its peers via consensus
+    pub async fn update_peer_endpoints(&self, endpoints: PeerEndpoints) -> ApiResult<()> {
+        if endpoints.p2p_url.is_none() && endpoints.api_url.is_none() {
+            return Err(ApiError::bad_request(
+                "At least one endpoint has to be updated".to_string(),
+            ));
+        }
 
-        self.submission_sender
-            .send(ConsensusItem::Transaction(transaction))
+        self.submit_item(ConsensusItem::PeerEndpoints(endpoints))
             .await
-            .ok();
+    }
 
-        Ok(txid)
+    /// Announces a new TLS certificate for the p2p connections via consensus
+    pub async fn rotate_tls_cert(&self) -> ApiResult<()> {
+        let item = generate_tls_cert_rotation(&self.cfg, &self.db)
+            .await
+            .map_err(|e| ApiError::server_error(e.to_string()))?;
+
+        self.submit_item(item).await
+    }
+
+    pub async fn db_stats(&self) -> ApiResult<Vec<DbPrefixStats>> {
+        db_stats(&self.db, &self.modules)
+            .await
+            .map_err(|e| ApiError::server_error(e.to_string()))
+    }
+
+    pub async fn compact_db(&self, request: CompactDbRequest) -> ApiResult<()> {
+        compact_db(&self.db, &request.prefixes)
+            .await
+            .map_err(|e| ApiError::bad_request(e.to_string()))
+    }
+
+    /// Changes the log filter of the guardian until it is restarted, returns
+    /// the directives of the new filter
+    pub fn set_log_filter(&self, request: SetLogFilterRequest) -> ApiResult<String> {
+        let directives = fedimint_logging::set_log_filter(&request.directives)
+            .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+        info!(target: LOG_NET_API, %directives, "Changed the log filter");
+
+        Ok(directives)
+    }
+
+    /// Approves the federation meta via consensus. It becomes active once a
+    /// threshold of guardians approved the identical meta.
+    pub async fn propose_federation_meta(&self, meta: FederationMeta) -> ApiResult<()> {
+        meta.validate()
+            .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+        self.submit_item(ConsensusItem::FederationMeta(meta)).await
+    }
+
+    pub async fn federation_meta(&self) -> Option<FederationMetaRecord> {
+        self.db
+            .begin_transaction_nc()
+            .await
+            .get_value(&FederationMetaKey)
+            .await
+    }
+
+    pub async fn federation_meta_proposals(&self) -> BTreeMap<PeerId, FederationMeta> {
+        federation_meta_proposals(&mut self.db.begin_transaction_nc().await).await
+    }
+
+    /// Our invite code with the name of the federation embedded, if the
+    /// guardians approved one
+    pub async fn invite_code(&self) -> InviteCode {
+        self.with_federation_name(self.cfg.get_invite_code()).await
+    }
+
+    async fn with_federation_name(&self, invite_code: InviteCode) -> InviteCode {
+        match self
+            .federation_meta()
+            .await
+            .and_then(|record| record.meta.name)
+        {
+            Some(name) => invite_code.with_federation_name(name),
+            None => invite_code,
+        }
+    }
+
+    /// Creates an invite code for the requested guardians, optionally
+    /// restricted to an expiry and a number of uses
+    pub async fn create_invite_code(&self, request: CreateInviteCodeRequest) -> ApiResult<String> {
+        let api_endpoints = api_endpoints(&self.cfg, &self.db)
+            .await
+            .into_iter()
+            .collect();
+
+        let invite_code = create_invite_code(&self.cfg, &api_endpoints, request)
+            .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+        Ok(self.with_federation_name(invite_code).await.to_string())
+    }
+
+    /// Votes via consensus to halt after the given session for an upgrade
+    pub async fn schedule_upgrade(&self, session_index: u64) -> ApiResult<()> {
+        if session_index < self.session_count().await {
+            return Err(ApiError::bad_request(
+                "The session has already been completed".to_string(),
+            ));
+        }
+
+        self.submit_item(ConsensusItem::UpgradeVote(UpgradeVote::Schedule {
+            session_index,
+        }))
+        .await
+    }
+
+    /// Withdraws our vote for an upgrade via consensus
+    pub async fn cancel_upgrade(&self) -> ApiResult<()> {
+        if !self
+            .upgrade_status()
+            .await
+            .votes
+            .contains_key(&self.cfg.local.identity)
+        {
+            return Err(ApiError::bad_request(
+                "We have not voted for an upgrade".to_string(),
+            ));
+        }
+
+        self.submit_item(ConsensusItem::UpgradeVote(UpgradeVote::Cancel))
+            .await
+    }
+
+    pub async fn upgrade_status(&self) -> UpgradeStatus {
+        let mut dbtx = self.db.begin_transaction_nc().await;
+
+        let session_count = get_finished_session_count_static(&mut dbtx).await;
+
+        upgrade_status(
+            &mut dbtx,
+            self.cfg.consensus.broadcast_public_keys.threshold(),
+            session_count,
+        )
+        .await
+    }
+
+    pub async fn peer_endpoints(&self) -> BTreeMap<PeerId, PeerEndpoints> {
+        self.db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&PeerEndpointsPrefix)
+            .await
+            .map(|(key, endpoints)| (key.0, endpoints))
+            .collect()
+            .await
+    }
+
+    pub async fn transaction_status(&self, txid: TransactionId) -> TransactionStatus {
+        let mut dbtx = self.db.begin_transaction_nc().await;
+
+        if dbtx
+            .get_value(&AcceptedTransactionKey(txid))
+            .await
+            .is_some()
+        {
+            let session_index = dbtx.get_value(&TransactionSessionKey(txid)).await;
+            let session_count = get_finished_session_count_static(&mut dbtx).await;
+
+            return match session_index {
+                Some(session_index) if session_count <= session_index => {
+                    TransactionStatus::Included { session_index }
+                }
+                session_index => TransactionStatus::Accepted { session_index },
+            };
+        }
+
+        // A rejected transaction might have been resubmitted after the rejection
+        if self
+            .recent_transactions
+            .lock()
+            .expect("Lock poisoned")
+            .contains(&txid, Instant::now())
+        {
+            return TransactionStatus::Queued;
+        }
+
+        match dbtx.get_value(&RejectedTransactionKey(txid)).await {
+            Some(rejected) => TransactionStatus::Rejected {
+                session_index: rejected.session_index,
+                reason: rejected.reason,
+            },
+            None => TransactionStatus::Unknown,
+        }
     }
 
     pub async fn await_transaction(
@@ -153,37 +463,112 @@ impl ConsensusApi {
         get_finished_session_count_static(&mut self.db.begin_transaction_nc().await).await
     }
 
-    pub async fn await_signed_session_outcome(&self, index: u64) -> SignedSessionOutcome {
-        self.db
+    pub async fn await_signed_session_outcome(
+        &self,
+        index: u64,
+    ) -> ApiResult<SignedSessionOutcome> {
+        let mut dbtx = self.db.begin_transaction_nc().await;
+
+        // After fast-syncing from a checkpoint or pruning we do not have the
+        // outcomes of all past sessions, so we must not wait for them
+        if index < get_finished_session_count_static(&mut dbtx).await {
+            return dbtx
+                .get_value(&SignedSessionOutcomeKey(index))
+                .await
+                .ok_or_else(|| session_outcome_pruned(index));
+        }
+
+        Ok(self
+            .db
             .wait_key_check(&SignedSessionOutcomeKey(index), std::convert::identity)
             .await
-            .0
+            .0)
     }
 
-    pub async fn session_status(&self, session_index: u64) -> SessionStatus {
+    pub async fn session_status(&self, session_index: u64) -> ApiResult<SessionStatus> {
         let mut dbtx = self.db.begin_transaction_nc().await;
 
-        match session_index.cmp(&get_finished_session_count_static(&mut dbtx).await) {
-            Ordering::Greater => SessionStatus::Initial,
-            Ordering::Equal => SessionStatus::Pending(
-                dbtx.find_by_prefix(&AcceptedItemPrefix)
-                    .await
-                    .map(|entry| entry.1)
-                    .collect()
-                    .await,
-            ),
-            Ordering::Less => SessionStatus::Complete(
-                dbtx.get_value(&SignedSessionOutcomeKey(session_index))
-                    .await
-                    .expect("There are no gaps in session outcomes")
-                    .session_outcome,
-            ),
-        }
+        Ok(
+            match session_index.cmp(&get_finished_session_count_static(&mut dbtx).await) {
+                Ordering::Greater => SessionStatus::Initial,
+                Ordering::Equal => SessionStatus::Pending(
+                    dbtx.find_by_prefix(&AcceptedItemPrefix)
+                        .await
+                        .map(|entry| entry.1)
+                        .collect()
+                        .await,
+                ),
+                Ordering::Less => SessionStatus::Complete(
+                    dbtx.get_value(&SignedSessionOutcomeKey(session_index))
+                        .await
+                        .ok_or_else(|| session_outcome_pruned(session_index))?
+                        .session_outcome,
+                ),
+            },
+        )
+    }
+
+    /// Signs the header of our latest checkpoint if it was created after the
+    /// session with `session_index`
+    pub async fn checkpoint_signature(
+        &self,
+        session_index: u64,
+    ) -> ApiResult<Option<SchnorrSignature>> {
+        let Some(header) = self
+            .db
+            .begin_transaction_nc()
+            .await
+            .get_value(&CheckpointHeaderKey)
+            .await
+            .filter(|header| header.session_index == session_index)
+        else {
+            return Ok(None);
+        };
+
+        Keychain::new(&self.cfg)
+            .and_then(|keychain| keychain.try_sign(&header.signing_message()))
+            .map(Some)
+            .map_err(|e| ApiError::server_error(e.to_string()))
+    }
+
+    /// Returns our latest checkpoint if it has been signed by a threshold of
+    /// guardians and we can still serve its chunks
+    pub async fn latest_checkpoint(&self) -> Option<SignedCheckpointHeader> {
+        let mut dbtx = self.db.begin_transaction_nc().await;
+
+        let header = dbtx.get_value(&CheckpointHeaderKey).await?;
+
+        dbtx.get_value(&SignedCheckpointHeaderKey(header.session_index))
+            .await
+    }
+
+    pub async fn download_checkpoint(
+        &self,
+        request: DownloadCheckpointRequest,
+    ) -> ApiResult<CheckpointChunk> {
+        let mut dbtx = self.db.begin_transaction_nc().await;
+
+        dbtx.get_value(&CheckpointHeaderKey)
+            .await
+            .filter(|header| header.session_index == request.session_index)
+            .ok_or_else(|| {
+                ApiError::not_found(format!(
+                    "Checkpoint for session {} is not available",
+                    request.session_index
+                ))
+            })?;
+
+        dbtx.get_value(&CheckpointChunkKey(request.chunk_index))
+            .await
+            .ok_or_else(|| {
+                ApiError::not_found(format!("Checkpoint has no chunk {}", request.chunk_index))
+            })
     }
 
     pub async fn get_federation_status(&self) -> ApiResult<FederationStatus> {
         let peers_connection_status = self.connection_status_channels.read().await.clone();
         let last_ci_by_peer = self.last_ci_by_peer.read().await.clone();
+        let state_divergence_by_peer = self.state_divergence_by_peer.read().await.clone();
         let session_count = self.session_count().await;
 
         let status_by_peer = peers_connection_status
@@ -191,11 +576,15 @@ impl ConsensusApi {
             .map(|(peer, connection_status)| {
                 let last_contribution = last_ci_by_peer.get(&peer).cloned();
                 let flagged = last_contribution.unwrap_or(0) + 1 < session_count;
+                let session_lag =
+                    last_contribution.map(|session| session_count.saturating_sub(session));
 
                 let consensus_status = PeerStatus {
                     connection_status,
                     last_contribution,
+                    session_lag,
                     flagged,
+                    state_divergence: state_divergence_by_peer.get(&peer).copied(),
                 };
 
                 (peer, consensus_status)
@@ -207,6 +596,11 @@ impl ConsensusApi {
             .filter(|status| status.flagged)
             .count() as u64;
 
+        let peers_diverged = status_by_peer
+            .values()
+            .filter(|status| status.state_divergence.is_some())
+            .count() as u64;
+
         let peers_online = status_by_peer
             .values()
             .filter(|status| status.connection_status == PeerConnectionStatus::Connected)
@@ -222,6 +616,7 @@ impl ConsensusApi {
             peers_online,
             peers_offline,
             peers_flagged,
+            peers_diverged,
             status_by_peer,
         })
     }
@@ -312,6 +707,36 @@ impl ConsensusApi {
         Ok(GuardianConfigBackup { tar_archive_bytes })
     }
 
+    /// Takes a consistent snapshot of the database from a single read
+    /// transaction, so consensus keeps running while the backup is made. The
+    /// snapshot is encrypted with the guardian password.
+    ///
+    /// The snapshot is encrypted and written to the configured backup
+    /// directory in the background since large databases would exceed the API
+    /// request timeout. The task is part of the task group, so shutdown waits
+    /// for the backup to be written.
+    async fn backup_database(&self, password: String) -> ApiResult<GuardianDatabaseBackup> {
+        let dir = self.cfg.local.db_backup_dir.clone().ok_or_else(|| {
+            ApiError::bad_request("No database backup directory configured".into())
+        })?;
+
+        let snapshot = DbSnapshot::take(&self.db)
+            .await
+            .map_err(|e| ApiError::server_error(e.to_string()))?;
+
+        let path = dir.join(format!("{}.{DB_SNAPSHOT_EXT}", snapshot.timestamp));
+
+        self.task_group.spawn("backup database", |_| async move {
+            if let Err(e) = snapshot.write_encrypted(password, dir).await {
+                error!(target: LOG_NET_API, "Failed to write database backup: {e:?}");
+            }
+        });
+
+        Ok(GuardianDatabaseBackup {
+            path: path.display().to_string(),
+        })
+    }
+
     async fn handle_backup_request<'s, 'dbtx, 'a>(
         &'s self,
         dbtx: &'dbtx mut DatabaseTransaction<'a>,
@@ -400,6 +825,14 @@ impl HasApiContext<DynServerModule> for ConsensusApi {
     }
 }
 
+/// Unlike a session that has not finished yet, which the client can wait for,
+/// a pruned session will never become available from this guardian
+fn session_outcome_pruned(index: u64) -> ApiError {
+    ApiError::gone(format!(
+        "Outcome of session {index} is not available, it has been pruned or skipped by fast-sync"
+    ))
+}
+
 pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
     vec![
         api_endpoint! {
@@ -419,7 +852,7 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
 
                 // we return an inner error if and only if the submitted transaction is
                 // invalid and will be rejected if we were to submit it to consensus
-                Ok((&TransactionSubmissionOutcome(fedimint.submit_transaction(transaction).await)).into())
+                Ok((&TransactionSubmissionOutcome(fedimint.submit_transaction(transaction).await?)).into())
             }
         },
         api_endpoint! {
@@ -451,7 +884,15 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
             INVITE_CODE_ENDPOINT,
             ApiVersion::new(0, 0),
             async |fedimint: &ConsensusApi, _context,  _v: ()| -> String {
-                Ok(fedimint.cfg.get_invite_code().to_string())
+                Ok(fedimint.invite_code().await.to_string())
+            }
+        },
+        api_endpoint! {
+            CREATE_INVITE_CODE_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, context, request: CreateInviteCodeRequest| -> String {
+                check_auth(context)?;
+                fedimint.create_invite_code(request).await
             }
         },
         api_endpoint! {
@@ -464,7 +905,13 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
         api_endpoint! {
             CLIENT_CONFIG_ENDPOINT,
             ApiVersion::new(0, 0),
-            async |fedimint: &ConsensusApi, _context, _v: ()| -> ClientConfig {
+            async |fedimint: &ConsensusApi, context, invite_code: Option<InviteCode>| -> ClientConfig {
+                if let Some(invite_code) = invite_code {
+                    redeem_invite_code(&mut context.dbtx().into_nc(), &fedimint.cfg, &invite_code)
+                        .await
+                        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+                }
+
                 Ok(fedimint.client_cfg.clone())
             }
         },
@@ -479,12 +926,25 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
             STATUS_ENDPOINT,
             ApiVersion::new(0, 0),
             async |fedimint: &ConsensusApi, _context, _v: ()| -> StatusResponse {
+                let server = if fedimint.upgrade_receiver.borrow().is_some() {
+                    ServerStatus::AwaitingUpgrade
+                } else {
+                    ServerStatus::ConsensusRunning
+                };
+
                 Ok(StatusResponse {
-                    server: ServerStatus::ConsensusRunning,
+                    server,
                     federation: Some(fedimint.get_federation_status().await?)
                 })
             }
         },
+        api_endpoint! {
+            FEDERATION_STATUS_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, _context, _v: ()| -> FederationStatus {
+                fedimint.get_federation_status().await
+            }
+        },
         api_endpoint! {
             SESSION_COUNT_ENDPOINT,
             ApiVersion::new(0, 0),
@@ -496,21 +956,42 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
             AWAIT_SESSION_OUTCOME_ENDPOINT,
             ApiVersion::new(0, 0),
             async |fedimint: &ConsensusApi, _context, index: u64| -> SerdeModuleEncoding<SessionOutcome> {
-                Ok((&fedimint.await_signed_session_outcome(index).await.session_outcome).into())
+                Ok((&fedimint.await_signed_session_outcome(index).await?.session_outcome).into())
             }
         },
         api_endpoint! {
             AWAIT_SIGNED_SESSION_OUTCOME_ENDPOINT,
             ApiVersion::new(0, 0),
             async |fedimint: &ConsensusApi, _context, index: u64| -> SerdeModuleEncoding<SignedSessionOutcome> {
-                Ok((&fedimint.await_signed_session_outcome(index).await).into())
+                Ok((&fedimint.await_signed_session_outcome(index).await?).into())
             }
         },
         api_endpoint! {
             SESSION_STATUS_ENDPOINT,
             ApiVersion::new(0, 1),
             async |fedimint: &ConsensusApi, _context, index: u64| -> SerdeModuleEncoding<SessionStatus> {
-                Ok((&fedimint.session_status(index).await).into())
+                Ok((&fedimint.session_status(index).await?).into())
+            }
+        },
+        api_endpoint! {
+            CHECKPOINT_SIGNATURE_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, _context, index: u64| -> Option<SerdeModuleEncoding<SchnorrSignature>> {
+                Ok(fedimint.checkpoint_signature(index).await?.as_ref().map(Into::into))
+            }
+        },
+        api_endpoint! {
+            LATEST_CHECKPOINT_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, _context, _v: ()| -> Option<SerdeModuleEncoding<SignedCheckpointHeader>> {
+                Ok(fedimint.latest_checkpoint().await.as_ref().map(Into::into))
+            }
+        },
+        api_endpoint! {
+            DOWNLOAD_CHECKPOINT_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, _context, request: DownloadCheckpointRequest| -> SerdeModuleEncoding<CheckpointChunk> {
+                Ok((&fedimint.download_checkpoint(request).await?).into())
             }
         },
         api_endpoint! {
@@ -522,6 +1003,121 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
                 Ok(())
             }
         },
+        api_endpoint! {
+            PROPOSE_FEDERATION_META_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, context, meta: FederationMeta| -> () {
+                check_auth(context)?;
+                fedimint.propose_federation_meta(meta).await
+            }
+        },
+        api_endpoint! {
+            FEDERATION_META_PROPOSALS_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, _context, _v: ()| -> BTreeMap<PeerId, FederationMeta> {
+                Ok(fedimint.federation_meta_proposals().await)
+            }
+        },
+        api_endpoint! {
+            FEDERATION_META_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, _context, _v: ()| -> Option<FederationMetaRecord> {
+                Ok(fedimint.federation_meta().await)
+            }
+        },
+        api_endpoint! {
+            SCHEDULE_UPGRADE_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, context, session_index: u64| -> () {
+                check_auth(context)?;
+                fedimint.schedule_upgrade(session_index).await
+            }
+        },
+        api_endpoint! {
+            CANCEL_UPGRADE_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, context, _v: ()| -> () {
+                check_auth(context)?;
+                fedimint.cancel_upgrade().await
+            }
+        },
+        api_endpoint! {
+            UPGRADE_STATUS_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, _context, _v: ()| -> UpgradeStatus {
+                Ok(fedimint.upgrade_status().await)
+            }
+        },
+        api_endpoint! {
+            TRANSACTION_STATUS_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, _context, txid: TransactionId| -> TransactionStatus {
+                Ok(fedimint.transaction_status(txid).await)
+            }
+        },
+        api_endpoint! {
+            MEMPOOL_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, context, _v: ()| -> MempoolSummary {
+                check_auth(context)?;
+                Ok(fedimint.mempool_summary())
+            }
+        },
+        api_endpoint! {
+            SESSION_TIMING_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, context, _v: ()| -> Vec<SessionTiming> {
+                check_auth(context)?;
+                Ok(fedimint.session_profiler.recent_sessions())
+            }
+        },
+        api_endpoint! {
+            UPDATE_PEER_ENDPOINTS_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, context, endpoints: PeerEndpoints| -> () {
+                check_auth(context)?;
+                fedimint.update_peer_endpoints(endpoints).await
+            }
+        },
+        api_endpoint! {
+            PEER_ENDPOINTS_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, _context, _v: ()| -> BTreeMap<PeerId, PeerEndpoints> {
+                Ok(fedimint.peer_endpoints().await)
+            }
+        },
+        api_endpoint! {
+            ROTATE_TLS_CERT_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, context, _v: ()| -> () {
+                check_auth(context)?;
+                fedimint.rotate_tls_cert().await
+            }
+        },
+        api_endpoint! {
+            DB_STATS_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, context, _v: ()| -> Vec<DbPrefixStats> {
+                check_auth(context)?;
+                fedimint.db_stats().await
+            }
+        },
+        api_endpoint! {
+            COMPACT_DB_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, context, request: CompactDbRequest| -> () {
+                check_auth(context)?;
+                fedimint.compact_db(request).await
+            }
+        },
+        api_endpoint! {
+            SET_LOG_FILTER_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, context, request: SetLogFilterRequest| -> String {
+                check_auth(context)?;
+                fedimint.set_log_filter(request)
+            }
+        },
         api_endpoint! {
             AUDIT_ENDPOINT,
             ApiVersion::new(0, 0),
@@ -539,6 +1135,15 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
                 Ok(fedimint.get_guardian_config_backup(password).await?)
             }
         },
+        api_endpoint! {
+            BACKUP_DATABASE_ENDPOINT,
+            ApiVersion::new(0, 2),
+            async |fedimint: &ConsensusApi, context, _v: ()| -> GuardianDatabaseBackup {
+                check_auth(context)?;
+                let password = context.request_auth().expect("Auth was checked before").0;
+                fedimint.backup_database(password).await
+            }
+        },
         api_endpoint! {
             BACKUP_ENDPOINT,
             ApiVersion::new(0, 0),
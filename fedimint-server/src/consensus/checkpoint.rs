@@ -0,0 +1,815 @@
+//! Checkpoints of the consensus state for fast-syncing guardians
+//!
+//! Every [`checkpoint_interval`] sessions every guardian copies the consensus
+//! relevant part of its database into a checkpoint and signs its header. Since
+//! all guardians process the same consensus items the checkpoints are
+//! identical, hence a threshold of signatures on a header proves that it
+//! commits to the state of the federation after that session. A guardian that
+//! has been offline for a long time can download the latest signed checkpoint
+//! from any peer on startup instead of replaying every session it missed.
+//!
+//! Only the global [`CHECKPOINT_DB_PREFIXES`] are part of a checkpoint, minus
+//! the [`LocalModulePrefixes`] the modules write outside of consensus. A
+//! guardian keeps its local module state when it applies a checkpoint.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use aleph_bft::Keychain as KeychainTrait;
+use anyhow::{anyhow, bail, ensure};
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use fedimint_api_client::api::{DynGlobalApi, FederationApiExt};
+use fedimint_api_client::query::{FilterMap, FilterMapThreshold};
+use fedimint_core::config::ServerModuleInitRegistry;
+use fedimint_core::db::{
+    module_instance_id_to_byte_prefix, Database, DatabaseTransaction, IDatabaseTransactionOpsCore,
+    IDatabaseTransactionOpsCoreTyped,
+};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::endpoint_constants::{
+    AWAIT_SIGNED_SESSION_OUTCOME_ENDPOINT, CHECKPOINT_SIGNATURE_ENDPOINT,
+    DOWNLOAD_CHECKPOINT_ENDPOINT, LATEST_CHECKPOINT_ENDPOINT,
+};
+use fedimint_core::envs::is_running_in_test_env;
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::{ApiRequestErased, SerdeModuleEncoding};
+use fedimint_core::runtime::timeout;
+use fedimint_core::session_outcome::{SchnorrSignature, SignedSessionOutcome};
+use fedimint_core::task::sleep;
+use fedimint_core::PeerId;
+use fedimint_logging::LOG_CONSENSUS;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::atomic_broadcast::{to_node_index, Keychain};
+use crate::config::ServerConfig;
+use crate::consensus::db::{
+    AcceptedItemPrefix, AlephUnitsPrefix, CheckpointChunkKey, CheckpointChunkPrefix,
+    CheckpointHeaderKey, DbKeyPrefix, SignedCheckpointHeaderKey, SignedSessionOutcomeKey,
+};
+use crate::consensus::engine::get_finished_session_count_static;
+
+/// Database prefixes holding the state that all guardians agree on
+pub const CHECKPOINT_DB_PREFIXES: [DbKeyPrefix; 7] = [
+    DbKeyPrefix::AcceptedTransaction,
+    DbKeyPrefix::PeerEndpoints,
+    DbKeyPrefix::PeerTlsCert,
+    DbKeyPrefix::UpgradeVote,
+    DbKeyPrefix::FederationMetaProposal,
+    DbKeyPrefix::FederationMeta,
+    DbKeyPrefix::Module,
+];
+
+/// Raw database prefixes of the module state that every guardian writes on its
+/// own, as returned by `local_db_prefixes` of the module inits. Entries under
+/// these prefixes are not part of the consensus state.
+#[derive(Debug, Clone, Default)]
+pub struct LocalModulePrefixes(Vec<Vec<u8>>);
+
+impl LocalModulePrefixes {
+    pub fn new(cfg: &ServerConfig, module_init_registry: &ServerModuleInitRegistry) -> Self {
+        let mut prefixes = vec![];
+
+        for (module_instance_id, module_cfg) in &cfg.consensus.modules {
+            if let Some(module_init) = module_init_registry.get(&module_cfg.kind) {
+                for prefix in module_init.local_db_prefixes() {
+                    let mut raw_prefix = module_instance_id_to_byte_prefix(*module_instance_id);
+
+                    raw_prefix.push(prefix);
+
+                    prefixes.push(raw_prefix);
+                }
+            }
+        }
+
+        LocalModulePrefixes(prefixes)
+    }
+
+    /// Whether the raw database key belongs to the state all guardians agree
+    /// on
+    pub fn is_consensus_key(&self, key: &[u8]) -> bool {
+        CHECKPOINT_DB_PREFIXES
+            .iter()
+            .any(|prefix| key.first() == Some(&(*prefix as u8)))
+            && !self.0.iter().any(|prefix| key.starts_with(prefix))
+    }
+}
+
+/// Maximum number of database entries per chunk, which bounds the size of a
+/// single `download_checkpoint` response
+const CHECKPOINT_CHUNK_ENTRIES: usize = 1000;
+
+/// Separates checkpoint signatures from signatures on session headers
+const CHECKPOINT_SIGNATURE_TAG: &[u8] = b"fedimint-checkpoint";
+
+/// How long we wait for our peers to tell us about their latest checkpoint
+const FAST_SYNC_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Number of sessions between two checkpoints. This has to be the same for all
+/// guardians, otherwise their checkpoints will never be threshold signed.
+pub fn checkpoint_interval() -> u64 {
+    if is_running_in_test_env() {
+        10
+    } else {
+        1000
+    }
+}
+
+/// Commits to the consensus state after the session with `session_index`
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct CheckpointHeader {
+    pub session_index: u64,
+    /// Hash over all encoded chunks in order
+    pub state_hash: sha256::Hash,
+    pub chunk_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct SignedCheckpointHeader {
+    pub header: CheckpointHeader,
+    pub signatures: BTreeMap<PeerId, SchnorrSignature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct CheckpointChunk {
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadCheckpointRequest {
+    pub session_index: u64,
+    pub chunk_index: u64,
+}
+
+impl CheckpointHeader {
+    pub fn signing_message(&self) -> Vec<u8> {
+        let mut message = CHECKPOINT_SIGNATURE_TAG.to_vec();
+
+        message.append(&mut self.consensus_encode_to_vec());
+
+        message
+    }
+
+    /// Checks that the chunks are the ones this header commits to
+    pub fn verify_chunks(&self, chunks: &[CheckpointChunk]) -> bool {
+        self.chunk_count == chunks.len() as u64 && self.state_hash == state_hash(chunks)
+    }
+}
+
+impl SignedCheckpointHeader {
+    pub fn verify(&self, keychain: &Keychain) -> bool {
+        let message = self.header.signing_message();
+
+        self.signatures.len() >= keychain.threshold()
+            && self
+                .signatures
+                .iter()
+                .all(|(peer, signature)| keychain.verify(&message, signature, to_node_index(*peer)))
+    }
+}
+
+fn state_hash(chunks: &[CheckpointChunk]) -> sha256::Hash {
+    let mut engine = sha256::HashEngine::default();
+
+    for chunk in chunks {
+        engine.input(&chunk.consensus_encode_to_vec());
+    }
+
+    sha256::Hash::from_engine(engine)
+}
+
+/// Hash over the current consensus state, which equals the state hash of a
+/// checkpoint created from it
+pub async fn consensus_state_hash(
+    db: &Database,
+    local_prefixes: &LocalModulePrefixes,
+) -> sha256::Hash {
+    consensus_state_hash_dbtx(&mut db.begin_transaction_nc().await, local_prefixes).await
+}
+
+/// Hash over the consensus state as seen by `dbtx`, the hashing itself runs on
+/// a blocking thread
+pub async fn consensus_state_hash_dbtx(
+    dbtx: &mut DatabaseTransaction<'_>,
+    local_prefixes: &LocalModulePrefixes,
+) -> sha256::Hash {
+    let chunks = consensus_state_chunks(dbtx, local_prefixes).await;
+
+    tokio::task::spawn_blocking(move || state_hash(&chunks))
+        .await
+        .expect("Hashing the consensus state panicked")
+}
+
+async fn consensus_state_chunks(
+    dbtx: &mut DatabaseTransaction<'_>,
+    local_prefixes: &LocalModulePrefixes,
+) -> Vec<CheckpointChunk> {
+    let mut entries = vec![];
+
+    for prefix in CHECKPOINT_DB_PREFIXES {
+        entries.extend(
+            dbtx.raw_find_by_prefix(&[prefix as u8])
+                .await
+                .expect("Reading from the database failed")
+                .filter(|(key, _)| std::future::ready(local_prefixes.is_consensus_key(key)))
+                .collect::<Vec<_>>()
+                .await,
+        );
+    }
+
+    entries
+        .chunks(CHECKPOINT_CHUNK_ENTRIES)
+        .map(|entries| CheckpointChunk {
+            entries: entries.to_vec(),
+        })
+        .collect()
+}
+
+/// Copies the consensus state into a new checkpoint, replacing the previous one
+pub async fn create_checkpoint(
+    db: &Database,
+    local_prefixes: &LocalModulePrefixes,
+    session_index: u64,
+) -> CheckpointHeader {
+    let mut dbtx = db.begin_transaction().await;
+
+    let chunks = consensus_state_chunks(&mut dbtx.to_ref_nc(), local_prefixes).await;
+
+    let header = CheckpointHeader {
+        session_index,
+        state_hash: state_hash(&chunks),
+        chunk_count: chunks.len() as u64,
+    };
+
+    store_checkpoint(&mut dbtx.to_ref_nc(), &header, &chunks).await;
+
+    dbtx.commit_tx_result()
+        .await
+        .expect("This is the only place where we write checkpoints during consensus");
+
+    info!(target: LOG_CONSENSUS, session_index, "Created checkpoint");
+
+    header
+}
+
+async fn store_checkpoint(
+    dbtx: &mut DatabaseTransaction<'_>,
+    header: &CheckpointHeader,
+    chunks: &[CheckpointChunk],
+) {
+    dbtx.remove_by_prefix(&CheckpointChunkPrefix).await;
+
+    for (chunk_index, chunk) in chunks.iter().enumerate() {
+        dbtx.insert_entry(&CheckpointChunkKey(chunk_index as u64), chunk)
+            .await;
+    }
+
+    dbtx.insert_entry(&CheckpointHeaderKey, header).await;
+}
+
+/// Collects the signatures of our peers on our latest checkpoint until we
+/// have a threshold or the checkpoint has been replaced by a newer one
+pub async fn collect_checkpoint_signatures(
+    db: Database,
+    federation_api: DynGlobalApi,
+    keychain: Keychain,
+    header: CheckpointHeader,
+) {
+    let message = header.signing_message();
+    let num_peers = keychain.num_peers();
+
+    let verifier = move |peer: PeerId,
+                         response: Option<SerdeModuleEncoding<SchnorrSignature>>|
+          -> anyhow::Result<SchnorrSignature> {
+        let signature = response
+            .ok_or_else(|| anyhow!("Peer has no matching checkpoint yet"))?
+            .try_into_inner(&ModuleDecoderRegistry::default())?;
+
+        ensure!(
+            keychain.verify(&message, &signature, to_node_index(peer)),
+            "Invalid checkpoint signature, the peer's consensus state differs from ours"
+        );
+
+        Ok(signature)
+    };
+
+    loop {
+        let latest = db
+            .begin_transaction_nc()
+            .await
+            .get_value(&CheckpointHeaderKey)
+            .await;
+
+        if latest.as_ref() != Some(&header) {
+            return;
+        }
+
+        let result = federation_api
+            .request_with_strategy(
+                FilterMapThreshold::new(verifier.clone(), num_peers),
+                CHECKPOINT_SIGNATURE_ENDPOINT.to_string(),
+                ApiRequestErased::new(header.session_index),
+            )
+            .await;
+
+        match result {
+            Ok(signatures) => {
+                let mut dbtx = db.begin_transaction().await;
+
+                dbtx.insert_entry(
+                    &SignedCheckpointHeaderKey(header.session_index),
+                    &SignedCheckpointHeader {
+                        header: header.clone(),
+                        signatures,
+                    },
+                )
+                .await;
+
+                dbtx.commit_tx().await;
+
+                info!(target: LOG_CONSENSUS, session_index = header.session_index, "Checkpoint has been signed by a threshold of peers");
+
+                return;
+            }
+            Err(error) => {
+                warn!(target: LOG_CONSENSUS, "Could not collect checkpoint signatures: {error}");
+            }
+        }
+
+        sleep(Duration::from_secs(10)).await;
+    }
+}
+
+/// Replaces our consensus state with the latest threshold signed checkpoint of
+/// our peers if we are at least one checkpoint interval behind it. This needs
+/// to run before the modules are initialized.
+pub async fn fast_sync(
+    cfg: &ServerConfig,
+    db: &Database,
+    decoders: &ModuleDecoderRegistry,
+    local_prefixes: &LocalModulePrefixes,
+) -> anyhow::Result<()> {
+    let session_count =
+        get_finished_session_count_static(&mut db.begin_transaction_nc().await).await;
+
+    let keychain = Keychain::new(cfg)?;
+
+    // Our own api is not running yet so we only ask our peers
+    let federation_api = DynGlobalApi::from_endpoints(
+        cfg.consensus
+            .api_endpoints
+            .iter()
+            .filter(|(peer, _)| **peer != cfg.local.identity)
+            .map(|(peer, url)| (*peer, url.url.clone()))
+            .collect(),
+    );
+
+    let verifier_keychain = keychain.clone();
+
+    let filter_map = move |response: Option<SerdeModuleEncoding<SignedCheckpointHeader>>|
+          -> anyhow::Result<SignedCheckpointHeader> {
+        let signed_header = response
+            .ok_or_else(|| anyhow!("Peer has no signed checkpoint"))?
+            .try_into_inner(&ModuleDecoderRegistry::default())?;
+
+        ensure!(
+            signed_header.verify(&verifier_keychain),
+            "Invalid checkpoint signatures"
+        );
+
+        Ok(signed_header)
+    };
+
+    let signed_header = match timeout(
+        FAST_SYNC_TIMEOUT,
+        federation_api.request_with_strategy(
+            FilterMap::new(filter_map, keychain.num_peers()),
+            LATEST_CHECKPOINT_ENDPOINT.to_string(),
+            ApiRequestErased::default(),
+        ),
+    )
+    .await
+    {
+        Ok(Ok(signed_header)) => signed_header,
+        Ok(Err(error)) => {
+            info!(target: LOG_CONSENSUS, "No checkpoint to fast-sync from: {error}");
+            return Ok(());
+        }
+        Err(..) => {
+            warn!(target: LOG_CONSENSUS, "Timed out requesting the latest checkpoint from our peers");
+            return Ok(());
+        }
+    };
+
+    let session_index = signed_header.header.session_index;
+
+    if session_index < session_count + checkpoint_interval() {
+        return Ok(());
+    }
+
+    info!(
+        target: LOG_CONSENSUS,
+        session_count,
+        session_index,
+        "Fast-syncing from checkpoint"
+    );
+
+    // If the peers replace the checkpoint while we download it we just replay
+    // the sessions instead
+    let chunks = match download_checkpoint(&federation_api, &signed_header).await {
+        Ok(chunks) => chunks,
+        Err(error) => {
+            warn!(target: LOG_CONSENSUS, "Could not fast-sync from checkpoint: {error}");
+            return Ok(());
+        }
+    };
+
+    let signed_session_outcome =
+        download_signed_session_outcome(&federation_api, &keychain, decoders, session_index)
+            .await?;
+
+    apply_checkpoint(
+        db,
+        local_prefixes,
+        &signed_header,
+        &chunks,
+        &signed_session_outcome,
+    )
+    .await?;
+
+    info!(target: LOG_CONSENSUS, session_index, "Fast-synced from checkpoint");
+
+    Ok(())
+}
+
+/// Downloads all chunks of the checkpoint from the peers that signed it
+async fn download_checkpoint(
+    federation_api: &DynGlobalApi,
+    signed_header: &SignedCheckpointHeader,
+) -> anyhow::Result<Vec<CheckpointChunk>> {
+    for peer in signed_header.signatures.keys() {
+        match download_checkpoint_from_peer(federation_api, &signed_header.header, *peer).await {
+            Ok(chunks) => return Ok(chunks),
+            Err(error) => {
+                warn!(target: LOG_CONSENSUS, %peer, "Failed to download checkpoint: {error}");
+            }
+        }
+    }
+
+    bail!("Could not download the checkpoint from any peer")
+}
+
+async fn download_checkpoint_from_peer(
+    federation_api: &DynGlobalApi,
+    header: &CheckpointHeader,
+    peer: PeerId,
+) -> anyhow::Result<Vec<CheckpointChunk>> {
+    let mut chunks = vec![];
+
+    for chunk_index in 0..header.chunk_count {
+        let chunk = federation_api
+            .request_single_peer_federation::<SerdeModuleEncoding<CheckpointChunk>>(
+                None,
+                DOWNLOAD_CHECKPOINT_ENDPOINT.to_string(),
+                ApiRequestErased::new(DownloadCheckpointRequest {
+                    session_index: header.session_index,
+                    chunk_index,
+                }),
+                peer,
+            )
+            .await?
+            .try_into_inner(&ModuleDecoderRegistry::default())?;
+
+        chunks.push(chunk);
+    }
+
+    ensure!(
+        header.verify_chunks(&chunks),
+        "Checkpoint does not match the signed header"
+    );
+
+    Ok(chunks)
+}
+
+async fn download_signed_session_outcome(
+    federation_api: &DynGlobalApi,
+    keychain: &Keychain,
+    decoders: &ModuleDecoderRegistry,
+    session_index: u64,
+) -> anyhow::Result<SignedSessionOutcome> {
+    let keychain = keychain.clone();
+    let decoders = decoders.clone();
+    let num_peers = keychain.num_peers();
+
+    let filter_map = move |response: SerdeModuleEncoding<SignedSessionOutcome>|
+          -> anyhow::Result<SignedSessionOutcome> {
+        let signed_session_outcome = response.try_into_inner(&decoders)?;
+
+        let header = signed_session_outcome.session_outcome.header(session_index);
+
+        ensure!(
+            signed_session_outcome.signatures.len() == keychain.threshold()
+                && signed_session_outcome
+                    .signatures
+                    .iter()
+                    .all(|(peer, signature)| keychain.verify(
+                        &header,
+                        signature,
+                        to_node_index(*peer)
+                    )),
+            "Invalid signatures"
+        );
+
+        Ok(signed_session_outcome)
+    };
+
+    // The signatures are verified before the outcome is cached
+    Ok(federation_api
+        .request_with_strategy_cached(
+            FilterMap::new(filter_map, num_peers),
+            AWAIT_SIGNED_SESSION_OUTCOME_ENDPOINT.to_string(),
+            ApiRequestErased::new(session_index),
+            None,
+        )
+        .await?)
+}
+
+/// Atomically replaces the consensus state with the checkpoint and discards
+/// the state of the session we were in. Afterwards the signed session outcome
+/// of the checkpoint's session is our latest one, so consensus continues with
+/// the session following it. Our local module state is left untouched.
+async fn apply_checkpoint(
+    db: &Database,
+    local_prefixes: &LocalModulePrefixes,
+    signed_header: &SignedCheckpointHeader,
+    chunks: &[CheckpointChunk],
+    signed_session_outcome: &SignedSessionOutcome,
+) -> anyhow::Result<()> {
+    ensure!(
+        signed_header.header.verify_chunks(chunks),
+        "Checkpoint does not match the signed header"
+    );
+
+    ensure!(
+        chunks
+            .iter()
+            .flat_map(|chunk| &chunk.entries)
+            .all(|(key, _)| local_prefixes.is_consensus_key(key)),
+        "The checkpoint contains a key outside of the consensus state"
+    );
+
+    let session_index = signed_header.header.session_index;
+
+    let mut dbtx = db.begin_transaction().await;
+
+    for prefix in CHECKPOINT_DB_PREFIXES {
+        let keys = dbtx
+            .raw_find_by_prefix(&[prefix as u8])
+            .await?
+            .map(|(key, _)| key)
+            .filter(|key| std::future::ready(local_prefixes.is_consensus_key(key)))
+            .collect::<Vec<_>>()
+            .await;
+
+        for key in keys {
+            dbtx.raw_remove_entry(&key).await?;
+        }
+    }
+
+    dbtx.remove_by_prefix(&AcceptedItemPrefix).await;
+    dbtx.remove_by_prefix(&AlephUnitsPrefix).await;
+
+    for chunk in chunks {
+        for (key, value) in &chunk.entries {
+            dbtx.raw_insert_bytes(key, value).await?;
+        }
+    }
+
+    // We keep the checkpoint so we can serve it to other peers ourselves
+    store_checkpoint(&mut dbtx.to_ref_nc(), &signed_header.header, chunks).await;
+
+    dbtx.insert_entry(&SignedCheckpointHeaderKey(session_index), signed_header)
+        .await;
+
+    dbtx.insert_entry(
+        &SignedSessionOutcomeKey(session_index),
+        signed_session_outcome,
+    )
+    .await;
+
+    dbtx.commit_tx_result().await
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::IRawDatabaseExt;
+    use fedimint_core::session_outcome::SessionOutcome;
+
+    use super::*;
+
+    async fn insert_raw(db: &Database, entries: Vec<(Vec<u8>, Vec<u8>)>) {
+        let mut dbtx = db.begin_transaction().await;
+
+        for (key, value) in entries {
+            dbtx.raw_insert_bytes(&key, &value).await.unwrap();
+        }
+
+        dbtx.commit_tx().await;
+    }
+
+    async fn read_raw(db: &Database, prefix: u8) -> Vec<(Vec<u8>, Vec<u8>)> {
+        db.begin_transaction_nc()
+            .await
+            .raw_find_by_prefix(&[prefix])
+            .await
+            .unwrap()
+            .collect()
+            .await
+    }
+
+    fn module_key(module_instance_id: u16, key: &[u8]) -> Vec<u8> {
+        let mut raw_key = module_instance_id_to_byte_prefix(module_instance_id);
+
+        raw_key.extend_from_slice(key);
+
+        raw_key
+    }
+
+    #[tokio::test]
+    async fn local_module_state_does_not_affect_state_hash() {
+        let local_prefixes = LocalModulePrefixes(vec![module_key(0, &[0x42])]);
+
+        let db = MemDatabase::new().into_database();
+        let other_db = MemDatabase::new().into_database();
+
+        insert_raw(
+            &db,
+            vec![
+                (module_key(0, &[0x40, 0x01]), vec![0x02]),
+                (module_key(0, &[0x42, 0x03]), vec![0x04]),
+            ],
+        )
+        .await;
+
+        insert_raw(
+            &other_db,
+            vec![
+                (module_key(0, &[0x40, 0x01]), vec![0x02]),
+                (module_key(0, &[0x42, 0x03]), vec![0x05]),
+                (module_key(0, &[0x42, 0x06]), vec![0x07]),
+            ],
+        )
+        .await;
+
+        assert_eq!(
+            consensus_state_hash(&db, &local_prefixes).await,
+            consensus_state_hash(&other_db, &local_prefixes).await
+        );
+
+        // The same prefix of another module instance is consensus state
+        insert_raw(&other_db, vec![(module_key(1, &[0x42, 0x03]), vec![0x08])]).await;
+
+        assert_ne!(
+            consensus_state_hash(&db, &local_prefixes).await,
+            consensus_state_hash(&other_db, &local_prefixes).await
+        );
+    }
+
+    #[tokio::test]
+    async fn checkpoint_roundtrip() {
+        let local_prefixes = LocalModulePrefixes(vec![module_key(0, &[0x42])]);
+
+        let db = MemDatabase::new().into_database();
+
+        insert_raw(
+            &db,
+            vec![
+                (
+                    vec![DbKeyPrefix::AcceptedTransaction as u8, 0x01],
+                    vec![0x02],
+                ),
+                (vec![DbKeyPrefix::Module as u8, 0x00, 0x03], vec![0x04]),
+                (vec![DbKeyPrefix::AcceptedItem as u8, 0x05], vec![0x06]),
+                (module_key(0, &[0x42, 0x0c]), vec![0x0d]),
+            ],
+        )
+        .await;
+
+        let header = create_checkpoint(&db, &local_prefixes, 9).await;
+
+        let chunks = vec![db
+            .begin_transaction_nc()
+            .await
+            .get_value(&CheckpointChunkKey(0))
+            .await
+            .unwrap()];
+
+        assert_eq!(header.chunk_count, 1);
+        assert_eq!(chunks[0].entries.len(), 2);
+        assert!(header.verify_chunks(&chunks));
+        assert_eq!(
+            db.begin_transaction_nc()
+                .await
+                .get_value(&CheckpointHeaderKey)
+                .await,
+            Some(header.clone())
+        );
+
+        let mut tampered = chunks.clone();
+        tampered[0].entries[0].1 = vec![0x07];
+        assert!(!header.verify_chunks(&tampered));
+
+        let signed_header = SignedCheckpointHeader {
+            header,
+            signatures: BTreeMap::new(),
+        };
+
+        let signed_session_outcome = SignedSessionOutcome {
+            session_outcome: SessionOutcome { items: vec![] },
+            signatures: BTreeMap::new(),
+        };
+
+        let synced_db = MemDatabase::new().into_database();
+
+        insert_raw(
+            &synced_db,
+            vec![
+                (
+                    vec![DbKeyPrefix::AcceptedTransaction as u8, 0x08],
+                    vec![0x09],
+                ),
+                (vec![DbKeyPrefix::AcceptedItem as u8, 0x0a], vec![0x0b]),
+                (module_key(0, &[0x42, 0x0e]), vec![0x0f]),
+            ],
+        )
+        .await;
+
+        assert!(apply_checkpoint(
+            &synced_db,
+            &local_prefixes,
+            &signed_header,
+            &tampered,
+            &signed_session_outcome
+        )
+        .await
+        .is_err());
+
+        let mut with_local_state = chunks.clone();
+        with_local_state[0]
+            .entries
+            .push((module_key(0, &[0x42, 0x0c]), vec![0x0d]));
+        assert!(apply_checkpoint(
+            &synced_db,
+            &local_prefixes,
+            &signed_header,
+            &with_local_state,
+            &signed_session_outcome
+        )
+        .await
+        .is_err());
+
+        apply_checkpoint(
+            &synced_db,
+            &local_prefixes,
+            &signed_header,
+            &chunks,
+            &signed_session_outcome,
+        )
+        .await
+        .unwrap();
+
+        for prefix in CHECKPOINT_DB_PREFIXES {
+            if !matches!(prefix, DbKeyPrefix::Module) {
+                assert_eq!(
+                    read_raw(&synced_db, prefix as u8).await,
+                    read_raw(&db, prefix as u8).await
+                );
+            }
+        }
+
+        assert_eq!(
+            consensus_state_hash(&synced_db, &local_prefixes).await,
+            signed_header.header.state_hash
+        );
+
+        // We keep our own local module state instead of taking the peer's
+        assert_eq!(
+            read_raw(&synced_db, DbKeyPrefix::Module as u8).await,
+            vec![
+                (vec![DbKeyPrefix::Module as u8, 0x00, 0x03], vec![0x04]),
+                (module_key(0, &[0x42, 0x0e]), vec![0x0f]),
+            ]
+        );
+
+        assert!(read_raw(&synced_db, DbKeyPrefix::AcceptedItem as u8)
+            .await
+            .is_empty());
+
+        assert_eq!(
+            get_finished_session_count_static(&mut synced_db.begin_transaction_nc().await).await,
+            10
+        );
+    }
+}
@@ -1,14 +1,20 @@
 use std::collections::BTreeMap;
 use std::fmt::Debug;
 
+use bitcoin_hashes::sha256;
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::db::{DatabaseVersion, ServerMigrationFn, MODULE_GLOBAL_PREFIX};
 use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::epoch::{FederationMeta, FederationMetaRecord, PeerEndpoints, SessionStateHash};
 use fedimint_core::session_outcome::{AcceptedItem, SignedSessionOutcome};
-use fedimint_core::{impl_db_lookup, impl_db_record, TransactionId};
+use fedimint_core::{impl_db_lookup, impl_db_record, PeerId, TransactionId};
 use serde::Serialize;
 use strum_macros::EnumIter;
 
+use crate::config::api::EncryptedConfigGenState;
+use crate::consensus::checkpoint::{CheckpointChunk, CheckpointHeader, SignedCheckpointHeader};
+use crate::consensus::tls_rotation::{OwnTlsKey, PeerTlsCert};
+
 pub const GLOBAL_DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
 
 #[repr(u8)]
@@ -18,6 +24,22 @@ pub enum DbKeyPrefix {
     AcceptedTransaction = 0x02,
     SignedSessionOutcome = 0x04,
     AlephUnits = 0x05,
+    DbBackup = 0x06,
+    CheckpointHeader = 0x07,
+    CheckpointChunk = 0x08,
+    SignedCheckpointHeader = 0x09,
+    PeerEndpoints = 0x0a,
+    PeerTlsCert = 0x0b,
+    OwnTlsKey = 0x0c,
+    TransactionSession = 0x0d,
+    RejectedTransaction = 0x0e,
+    OwnStateHash = 0x0f,
+    PeerStateHash = 0x10,
+    UpgradeVote = 0x11,
+    FederationMetaProposal = 0x12,
+    FederationMeta = 0x13,
+    InviteUses = 0x14,
+    ConfigGenState = 0x15,
     Module = MODULE_GLOBAL_PREFIX,
 }
 
@@ -89,6 +111,275 @@ impl_db_record!(
 );
 impl_db_lookup!(key = AlephUnitsKey, query_prefix = AlephUnitsPrefix);
 
+/// Timestamp of a scheduled database backup, the value is the location the
+/// backup was stored at
+#[derive(Debug, Encodable, Decodable)]
+pub struct DbBackupKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct DbBackupPrefix;
+
+impl_db_record!(
+    key = DbBackupKey,
+    value = String,
+    db_prefix = DbKeyPrefix::DbBackup,
+    notify_on_modify = false,
+);
+impl_db_lookup!(key = DbBackupKey, query_prefix = DbBackupPrefix);
+
+/// Header of the latest checkpoint we created
+#[derive(Debug, Encodable, Decodable)]
+pub struct CheckpointHeaderKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct CheckpointHeaderPrefix;
+
+impl_db_record!(
+    key = CheckpointHeaderKey,
+    value = CheckpointHeader,
+    db_prefix = DbKeyPrefix::CheckpointHeader,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = CheckpointHeaderKey,
+    query_prefix = CheckpointHeaderPrefix
+);
+
+/// Chunk of the latest checkpoint we created by index
+#[derive(Debug, Encodable, Decodable)]
+pub struct CheckpointChunkKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct CheckpointChunkPrefix;
+
+impl_db_record!(
+    key = CheckpointChunkKey,
+    value = CheckpointChunk,
+    db_prefix = DbKeyPrefix::CheckpointChunk,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = CheckpointChunkKey,
+    query_prefix = CheckpointChunkPrefix
+);
+
+/// Threshold signed header of a checkpoint by session index
+#[derive(Debug, Encodable, Decodable)]
+pub struct SignedCheckpointHeaderKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct SignedCheckpointHeaderPrefix;
+
+impl_db_record!(
+    key = SignedCheckpointHeaderKey,
+    value = SignedCheckpointHeader,
+    db_prefix = DbKeyPrefix::SignedCheckpointHeader,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = SignedCheckpointHeaderKey,
+    query_prefix = SignedCheckpointHeaderPrefix
+);
+
+/// Addresses a peer announced via consensus that override the ones in the
+/// config
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerEndpointsKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerEndpointsPrefix;
+
+impl_db_record!(
+    key = PeerEndpointsKey,
+    value = PeerEndpoints,
+    db_prefix = DbKeyPrefix::PeerEndpoints,
+    notify_on_modify = false,
+);
+impl_db_lookup!(key = PeerEndpointsKey, query_prefix = PeerEndpointsPrefix);
+
+/// TLS certificate a peer rotated to via consensus
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerTlsCertKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerTlsCertPrefix;
+
+impl_db_record!(
+    key = PeerTlsCertKey,
+    value = PeerTlsCert,
+    db_prefix = DbKeyPrefix::PeerTlsCert,
+    notify_on_modify = false,
+);
+impl_db_lookup!(key = PeerTlsCertKey, query_prefix = PeerTlsCertPrefix);
+
+/// Private keys of TLS certificates we generated for a rotation by the hash
+/// of the certificate
+#[derive(Debug, Encodable, Decodable)]
+pub struct OwnTlsKeyKey(pub sha256::Hash);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct OwnTlsKeyPrefix;
+
+impl_db_record!(
+    key = OwnTlsKeyKey,
+    value = OwnTlsKey,
+    db_prefix = DbKeyPrefix::OwnTlsKey,
+    notify_on_modify = false,
+);
+impl_db_lookup!(key = OwnTlsKeyKey, query_prefix = OwnTlsKeyPrefix);
+
+/// Index of the session a transaction was accepted in
+#[derive(Debug, Encodable, Decodable)]
+pub struct TransactionSessionKey(pub TransactionId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct TransactionSessionPrefix;
+
+impl_db_record!(
+    key = TransactionSessionKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::TransactionSession,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = TransactionSessionKey,
+    query_prefix = TransactionSessionPrefix
+);
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct RejectedTransaction {
+    pub session_index: u64,
+    pub reason: String,
+}
+
+/// Transactions that were ordered by consensus but failed to process
+#[derive(Debug, Encodable, Decodable)]
+pub struct RejectedTransactionKey(pub TransactionId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct RejectedTransactionPrefix;
+
+impl_db_record!(
+    key = RejectedTransactionKey,
+    value = RejectedTransaction,
+    db_prefix = DbKeyPrefix::RejectedTransaction,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = RejectedTransactionKey,
+    query_prefix = RejectedTransactionPrefix
+);
+
+/// Hashes over our own consensus state after the most recent sessions. This is
+/// local state that is not part of consensus.
+#[derive(Debug, Encodable, Decodable)]
+pub struct OwnStateHashKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct OwnStateHashPrefix;
+
+impl_db_record!(
+    key = OwnStateHashKey,
+    value = sha256::Hash,
+    db_prefix = DbKeyPrefix::OwnStateHash,
+    notify_on_modify = false,
+);
+impl_db_lookup!(key = OwnStateHashKey, query_prefix = OwnStateHashPrefix);
+
+/// Latest state hash a peer announced via consensus
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerStateHashKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerStateHashPrefix;
+
+impl_db_record!(
+    key = PeerStateHashKey,
+    value = SessionStateHash,
+    db_prefix = DbKeyPrefix::PeerStateHash,
+    notify_on_modify = false,
+);
+impl_db_lookup!(key = PeerStateHashKey, query_prefix = PeerStateHashPrefix);
+
+/// Session index after which a peer voted to halt for an upgrade
+#[derive(Debug, Encodable, Decodable)]
+pub struct UpgradeVoteKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct UpgradeVotePrefix;
+
+impl_db_record!(
+    key = UpgradeVoteKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::UpgradeVote,
+    notify_on_modify = false,
+);
+impl_db_lookup!(key = UpgradeVoteKey, query_prefix = UpgradeVotePrefix);
+
+/// Federation metadata a peer approved that has not become active yet
+#[derive(Debug, Encodable, Decodable)]
+pub struct FederationMetaProposalKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FederationMetaProposalPrefix;
+
+impl_db_record!(
+    key = FederationMetaProposalKey,
+    value = FederationMeta,
+    db_prefix = DbKeyPrefix::FederationMetaProposal,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = FederationMetaProposalKey,
+    query_prefix = FederationMetaProposalPrefix
+);
+
+/// Federation metadata approved by a threshold of peers
+#[derive(Debug, Encodable, Decodable)]
+pub struct FederationMetaKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FederationMetaPrefix;
+
+impl_db_record!(
+    key = FederationMetaKey,
+    value = FederationMetaRecord,
+    db_prefix = DbKeyPrefix::FederationMeta,
+    notify_on_modify = true,
+);
+impl_db_lookup!(key = FederationMetaKey, query_prefix = FederationMetaPrefix);
+
+/// Number of config downloads we served for a scoped invite code by its id.
+/// This is local state that is not part of consensus.
+#[derive(Debug, Encodable, Decodable)]
+pub struct InviteUsesKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct InviteUsesPrefix;
+
+impl_db_record!(
+    key = InviteUsesKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::InviteUses,
+    notify_on_modify = false,
+);
+impl_db_lookup!(key = InviteUsesKey, query_prefix = InviteUsesPrefix);
+
+/// State of an ongoing config gen, so it can be resumed after a restart
+#[derive(Debug, Encodable, Decodable)]
+pub struct ConfigGenStateKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ConfigGenStatePrefix;
+
+impl_db_record!(
+    key = ConfigGenStateKey,
+    value = EncryptedConfigGenState,
+    db_prefix = DbKeyPrefix::ConfigGenState,
+    notify_on_modify = false,
+);
+impl_db_lookup!(key = ConfigGenStateKey, query_prefix = ConfigGenStatePrefix);
+
 pub fn get_global_database_migrations() -> BTreeMap<DatabaseVersion, ServerMigrationFn> {
     BTreeMap::new()
 }
@@ -286,6 +577,28 @@ mod fedimint_migration_tests {
                             );
                             info!(target: LOG_DB, "Validated AlephUnits");
                         }
+                        // Backup records were introduced after the v0 snapshot
+                        DbKeyPrefix::DbBackup => {}
+                        // Checkpoints were introduced after the v0 snapshot
+                        DbKeyPrefix::CheckpointHeader
+                        | DbKeyPrefix::CheckpointChunk
+                        | DbKeyPrefix::SignedCheckpointHeader => {}
+                        // Peer endpoint announcements were introduced after the v0 snapshot
+                        DbKeyPrefix::PeerEndpoints => {}
+                        // TLS certificate rotations were introduced after the v0 snapshot
+                        DbKeyPrefix::PeerTlsCert | DbKeyPrefix::OwnTlsKey => {}
+                        // Transaction status indexes were introduced after the v0 snapshot
+                        DbKeyPrefix::TransactionSession | DbKeyPrefix::RejectedTransaction => {}
+                        // State hashes were introduced after the v0 snapshot
+                        DbKeyPrefix::OwnStateHash | DbKeyPrefix::PeerStateHash => {}
+                        // Upgrade votes were introduced after the v0 snapshot
+                        DbKeyPrefix::UpgradeVote => {}
+                        // Federation meta was introduced after the v0 snapshot
+                        DbKeyPrefix::FederationMetaProposal | DbKeyPrefix::FederationMeta => {}
+                        // Invite uses were introduced after the v0 snapshot
+                        DbKeyPrefix::InviteUses => {}
+                        // Config gen state was introduced after the v0 snapshot
+                        DbKeyPrefix::ConfigGenState => {}
                         // Module prefix is reserved for modules, no migration testing is needed
                         DbKeyPrefix::Module => {}
                     }
@@ -0,0 +1,138 @@
+//! Inspection and compaction of the database while the guardian is running
+//!
+//! Operators can look up which record types take up space via the `db_stats`
+//! endpoint and reclaim the space of deleted entries, e.g. after pruning old
+//! session outcomes, via the `compact_db` endpoint without stopping the
+//! guardian.
+
+use fedimint_core::admin_client::DbPrefixStats;
+use fedimint_core::db::{module_instance_id_to_byte_prefix, Database, IDatabaseTransactionOpsCore};
+use fedimint_core::module::registry::ServerModuleRegistry;
+use fedimint_logging::LOG_CONSENSUS;
+use futures::StreamExt;
+use strum::IntoEnumIterator;
+use tracing::info;
+
+use crate::consensus::db::DbKeyPrefix;
+
+/// Counts the entries and their size per global record type and per module
+pub async fn db_stats(
+    db: &Database,
+    modules: &ServerModuleRegistry,
+) -> anyhow::Result<Vec<DbPrefixStats>> {
+    let mut prefixes = DbKeyPrefix::iter()
+        .filter(|prefix| !matches!(prefix, DbKeyPrefix::Module))
+        .map(|prefix| (vec![prefix.clone() as u8], prefix.to_string()))
+        .collect::<Vec<_>>();
+
+    for (module_instance_id, kind, _) in modules.iter_modules() {
+        prefixes.push((
+            module_instance_id_to_byte_prefix(module_instance_id),
+            format!("Module {module_instance_id} ({kind})"),
+        ));
+    }
+
+    let mut dbtx = db.begin_transaction_nc().await;
+    let mut stats = vec![];
+
+    for (prefix, name) in prefixes {
+        let (entries, size_bytes) = dbtx
+            .raw_find_by_prefix(&prefix)
+            .await?
+            .fold((0, 0), |(entries, size), (key, value)| async move {
+                (entries + 1, size + (key.len() + value.len()) as u64)
+            })
+            .await;
+
+        stats.push(DbPrefixStats {
+            prefix: hex::encode(prefix),
+            name,
+            entries,
+            size_bytes,
+        });
+    }
+
+    Ok(stats)
+}
+
+/// Compacts the storage of the hex encoded key prefixes, or of the whole
+/// database if none are given
+pub async fn compact_db(db: &Database, prefixes: &[String]) -> anyhow::Result<()> {
+    let prefixes = if prefixes.is_empty() {
+        vec![vec![]]
+    } else {
+        prefixes
+            .iter()
+            .map(hex::decode)
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    for prefix in prefixes {
+        info!(
+            target: LOG_CONSENSUS,
+            prefix = %hex::encode(&prefix),
+            "Compacting database"
+        );
+
+        db.compact_prefix(&prefix).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{IDatabaseTransactionOpsCore, IRawDatabaseExt};
+    use fedimint_core::module::registry::ServerModuleRegistry;
+
+    use super::{compact_db, db_stats};
+    use crate::consensus::db::DbKeyPrefix;
+
+    #[tokio::test]
+    async fn test_db_stats() {
+        let db = MemDatabase::new().into_database();
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_insert_bytes(&[DbKeyPrefix::AcceptedItem as u8, 0x01], &[0x02; 10])
+            .await
+            .unwrap();
+        dbtx.raw_insert_bytes(&[DbKeyPrefix::AcceptedItem as u8, 0x02], &[0x02; 20])
+            .await
+            .unwrap();
+        dbtx.raw_insert_bytes(&[DbKeyPrefix::InviteUses as u8, 0x01], &[])
+            .await
+            .unwrap();
+        dbtx.commit_tx().await;
+
+        let stats = db_stats(&db, &ServerModuleRegistry::default())
+            .await
+            .unwrap();
+
+        let accepted_items = stats
+            .iter()
+            .find(|stats| stats.name == "AcceptedItem")
+            .unwrap();
+
+        assert_eq!(accepted_items.prefix, "01");
+        assert_eq!(accepted_items.entries, 2);
+        assert_eq!(accepted_items.size_bytes, 34);
+
+        let invite_uses = stats
+            .iter()
+            .find(|stats| stats.name == "InviteUses")
+            .unwrap();
+
+        assert_eq!(invite_uses.entries, 1);
+        assert_eq!(invite_uses.size_bytes, 2);
+
+        assert_eq!(stats.iter().map(|stats| stats.entries).sum::<u64>(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_compact_db_rejects_invalid_prefix() {
+        let db = MemDatabase::new().into_database();
+
+        assert!(compact_db(&db, &["zz".to_string()]).await.is_err());
+    }
+}
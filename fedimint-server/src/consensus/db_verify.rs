@@ -0,0 +1,278 @@
+//! Offline integrity verification of the guardian database
+//!
+//! Before and after a migration, or when restoring a backup, an operator can
+//! check that every entry of the database can still be decoded with the
+//! decoders of the configured modules and that the state of the modules is
+//! consistent. The guardian must not be running while the database is
+//! verified.
+
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+use fedimint_core::config::ServerModuleInitRegistry;
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{
+    Database, DatabaseKey, DatabaseRecord, DatabaseValue, DatabaseVersionKey, DatabaseVersionKeyV0,
+    DbKeyPrefix as CoreDbKeyPrefix, IDatabaseTransactionOpsCore,
+};
+use fedimint_core::encoding::Decodable;
+use fedimint_core::module::audit::Audit;
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::task::TaskGroup;
+use futures::StreamExt;
+use serde::Serialize;
+use strum::IntoEnumIterator;
+
+use crate::config::ServerConfig;
+use crate::consensus::db::{
+    AcceptedItemKey, AcceptedTransactionKey, AlephUnitsKey, CheckpointChunkKey,
+    CheckpointHeaderKey, ConfigGenStateKey, DbBackupKey, DbKeyPrefix, FederationMetaKey,
+    FederationMetaProposalKey, InviteUsesKey, OwnStateHashKey, OwnTlsKeyKey, PeerEndpointsKey,
+    PeerStateHashKey, PeerTlsCertKey, RejectedTransactionKey, SignedCheckpointHeaderKey,
+    SignedSessionOutcomeKey, TransactionSessionKey, UpgradeVoteKey,
+};
+use crate::consensus::init_modules;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DbVerifyReport {
+    /// Number of entries per global record type and per module
+    pub entries: BTreeMap<String, u64>,
+    pub problems: Vec<DbProblem>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DbProblem {
+    /// Hex encoded key of the entry or the module the problem was found in
+    pub location: String,
+    pub message: String,
+}
+
+impl DbVerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    fn problem(&mut self, location: impl Into<String>, message: impl ToString) {
+        self.problems.push(DbProblem {
+            location: location.into(),
+            message: message.to_string(),
+        });
+    }
+}
+
+/// Walks all entries of the database, checks that they can be decoded and
+/// verifies the invariants of the module states
+pub async fn verify_db(
+    cfg: &ServerConfig,
+    db: &Database,
+    module_init_registry: &ServerModuleInitRegistry,
+    task_group: &TaskGroup,
+) -> anyhow::Result<DbVerifyReport> {
+    let decoders = module_init_registry.decoders_strict(cfg.iter_module_instances())?;
+
+    let db = db.with_decoders(decoders.clone());
+
+    let mut report = verify_entries(&db, &decoders).await?;
+
+    let modules = init_modules(cfg, &db, module_init_registry, task_group).await?;
+
+    // Modules may compact their state while being audited, which we discard
+    let mut dbtx = db.begin_transaction_nc().await;
+    dbtx.ignore_uncommitted();
+
+    let mut audit = Audit::default();
+
+    for (module_instance_id, kind, module) in modules.iter_modules() {
+        let location = format!("Module {module_instance_id} ({kind})");
+
+        for violation in module
+            .verify(&mut dbtx.to_ref_with_prefix_module_id(module_instance_id))
+            .await
+        {
+            report.problem(location.clone(), violation);
+        }
+
+        module
+            .audit(
+                &mut dbtx.to_ref_with_prefix_module_id(module_instance_id),
+                &mut audit,
+                module_instance_id,
+            )
+            .await;
+    }
+
+    if audit.net_assets().milli_sat < 0 {
+        report.problem(
+            "Audit",
+            format!("The federation has negative net assets: {audit}"),
+        );
+    }
+
+    Ok(report)
+}
+
+async fn verify_entries(
+    db: &Database,
+    decoders: &ModuleDecoderRegistry,
+) -> anyhow::Result<DbVerifyReport> {
+    let entries = db
+        .begin_transaction_nc()
+        .await
+        .raw_find_by_prefix(&[])
+        .await?
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut report = DbVerifyReport::default();
+
+    for (key, value) in entries {
+        let location = hex::encode(&key);
+
+        // The database versions are written by `fedimint-core` for the global
+        // state as well as for every module
+        if key[0] == CoreDbKeyPrefix::DatabaseVersion as u8 {
+            *report
+                .entries
+                .entry("DatabaseVersion".to_string())
+                .or_default() += 1;
+
+            let result = if key.len() == 1 {
+                decode::<DatabaseVersionKeyV0>(&key, &value, decoders)
+            } else {
+                decode::<DatabaseVersionKey>(&key, &value, decoders)
+            };
+
+            if let Err(e) = result {
+                report.problem(location, e);
+            }
+
+            continue;
+        }
+
+        let Some(prefix) = DbKeyPrefix::iter().find(|prefix| prefix.clone() as u8 == key[0]) else {
+            report.problem(location, "Unknown key prefix");
+            continue;
+        };
+
+        let result = match prefix {
+            DbKeyPrefix::AcceptedItem => decode::<AcceptedItemKey>(&key, &value, decoders),
+            DbKeyPrefix::AcceptedTransaction => {
+                decode::<AcceptedTransactionKey>(&key, &value, decoders)
+            }
+            DbKeyPrefix::SignedSessionOutcome => {
+                decode::<SignedSessionOutcomeKey>(&key, &value, decoders)
+            }
+            DbKeyPrefix::AlephUnits => decode::<AlephUnitsKey>(&key, &value, decoders),
+            DbKeyPrefix::DbBackup => decode::<DbBackupKey>(&key, &value, decoders),
+            DbKeyPrefix::CheckpointHeader => decode::<CheckpointHeaderKey>(&key, &value, decoders),
+            DbKeyPrefix::CheckpointChunk => decode::<CheckpointChunkKey>(&key, &value, decoders),
+            DbKeyPrefix::SignedCheckpointHeader => {
+                decode::<SignedCheckpointHeaderKey>(&key, &value, decoders)
+            }
+            DbKeyPrefix::PeerEndpoints => decode::<PeerEndpointsKey>(&key, &value, decoders),
+            DbKeyPrefix::PeerTlsCert => decode::<PeerTlsCertKey>(&key, &value, decoders),
+            DbKeyPrefix::OwnTlsKey => decode::<OwnTlsKeyKey>(&key, &value, decoders),
+            DbKeyPrefix::TransactionSession => {
+                decode::<TransactionSessionKey>(&key, &value, decoders)
+            }
+            DbKeyPrefix::RejectedTransaction => {
+                decode::<RejectedTransactionKey>(&key, &value, decoders)
+            }
+            DbKeyPrefix::OwnStateHash => decode::<OwnStateHashKey>(&key, &value, decoders),
+            DbKeyPrefix::PeerStateHash => decode::<PeerStateHashKey>(&key, &value, decoders),
+            DbKeyPrefix::UpgradeVote => decode::<UpgradeVoteKey>(&key, &value, decoders),
+            DbKeyPrefix::FederationMetaProposal => {
+                decode::<FederationMetaProposalKey>(&key, &value, decoders)
+            }
+            DbKeyPrefix::FederationMeta => decode::<FederationMetaKey>(&key, &value, decoders),
+            DbKeyPrefix::InviteUses => decode::<InviteUsesKey>(&key, &value, decoders),
+            DbKeyPrefix::ConfigGenState => decode::<ConfigGenStateKey>(&key, &value, decoders),
+            DbKeyPrefix::Module => {
+                // The records of a module are only known to the module itself, so
+                // we only check that they belong to a configured module
+                match ModuleInstanceId::consensus_decode(
+                    &mut Cursor::new(&key[1..]),
+                    &ModuleDecoderRegistry::default(),
+                ) {
+                    Ok(module_instance_id) => {
+                        *report
+                            .entries
+                            .entry(format!("Module {module_instance_id}"))
+                            .or_default() += 1;
+
+                        if decoders.get(module_instance_id).is_none() {
+                            report.problem(location, "Entry of an unknown module");
+                        }
+                    }
+                    Err(e) => report.problem(location, e),
+                }
+
+                continue;
+            }
+        };
+
+        *report.entries.entry(prefix.to_string()).or_default() += 1;
+
+        if let Err(e) = result {
+            report.problem(location, e);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Decodes an entry of a global record type
+fn decode<K>(key: &[u8], value: &[u8], decoders: &ModuleDecoderRegistry) -> anyhow::Result<()>
+where
+    K: DatabaseRecord + DatabaseKey,
+{
+    K::from_bytes(key, decoders)?;
+    K::Value::from_bytes(value, decoders)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{
+        DatabaseVersion, DatabaseVersionKey, IDatabaseTransactionOpsCore,
+        IDatabaseTransactionOpsCoreTyped, IRawDatabaseExt,
+    };
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+
+    use super::verify_entries;
+    use crate::consensus::db::{DbKeyPrefix, InviteUsesKey};
+
+    #[tokio::test]
+    async fn test_verify_entries() {
+        let db = MemDatabase::new().into_database();
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&InviteUsesKey(0), &1).await;
+        dbtx.insert_entry(&DatabaseVersionKey(0), &DatabaseVersion(1))
+            .await;
+        dbtx.raw_insert_bytes(&[DbKeyPrefix::OwnStateHash as u8, 0x01], &[0x02])
+            .await
+            .unwrap();
+        dbtx.raw_insert_bytes(&[0x03, 0x01], &[]).await.unwrap();
+        dbtx.commit_tx().await;
+
+        let report = verify_entries(&db, &ModuleDecoderRegistry::default())
+            .await
+            .unwrap();
+
+        assert_eq!(report.entries.get("InviteUses"), Some(&1));
+        assert_eq!(report.entries.get("OwnStateHash"), Some(&1));
+        assert_eq!(report.entries.get("DatabaseVersion"), Some(&1));
+
+        assert_eq!(
+            report
+                .problems
+                .iter()
+                .map(|problem| problem.location.as_str())
+                .collect::<Vec<_>>(),
+            vec!["0301", "0f01"]
+        );
+    }
+}
@@ -32,6 +32,28 @@ impl<'ci> fmt::Debug for FmtDbgConsensusItem<'ci> {
                     f.write_fmt(format_args!("\n    Output: {output}")).unwrap();
                 }
             }
+            ConsensusItem::PeerEndpoints(endpoints) => {
+                f.write_fmt(format_args!(
+                    "Peer endpoints: p2p={:?} api={:?}",
+                    endpoints.p2p_url.as_ref().map(ToString::to_string),
+                    endpoints.api_url.as_ref().map(ToString::to_string),
+                ))?;
+            }
+            ConsensusItem::TlsCertRotation(_) => {
+                f.write_str("TLS certificate rotation")?;
+            }
+            ConsensusItem::StateHash(state_hash) => {
+                f.write_fmt(format_args!(
+                    "State hash: session={} hash={}",
+                    state_hash.session_index, state_hash.state_hash,
+                ))?;
+            }
+            ConsensusItem::UpgradeVote(vote) => {
+                f.write_fmt(format_args!("Upgrade vote: {vote:?}"))?;
+            }
+            ConsensusItem::FederationMeta(meta) => {
+                f.write_fmt(format_args!("Federation meta: {:?}", meta.name))?;
+            }
             ConsensusItem::Default { variant, .. } => {
                 f.write_fmt(format_args!("Unknown CI variant: {variant}"))?;
             }
@@ -0,0 +1,122 @@
+//! Deduplication of submitted transactions
+//!
+//! Clients retry submitting a transaction until they see it accepted. Without
+//! deduplication every retry would be queued and proposed again, wasting the
+//! limited space in our units. We therefore remember the transactions we
+//! queued recently and skip resubmissions until they expire from the cache.
+//! Expiry ensures a transaction is proposed again if it got lost, e.g. since
+//! we restarted before it was ordered.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use fedimint_core::TransactionId;
+use tokio::time::Instant;
+
+/// Maximum number of transactions the cache remembers
+pub const RECENT_TRANSACTIONS_CAPACITY: usize = 10_000;
+
+/// Time after which a resubmitted transaction is queued again
+pub const RECENT_TRANSACTIONS_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct RecentTransactions {
+    capacity: usize,
+    ttl: Duration,
+    queued_at: HashMap<TransactionId, Instant>,
+    order: VecDeque<(TransactionId, Instant)>,
+}
+
+impl Default for RecentTransactions {
+    fn default() -> Self {
+        RecentTransactions::new(RECENT_TRANSACTIONS_CAPACITY, RECENT_TRANSACTIONS_TTL)
+    }
+}
+
+impl RecentTransactions {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        RecentTransactions {
+            capacity,
+            ttl,
+            queued_at: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Returns true if the transaction was queued within the ttl
+    pub fn contains(&mut self, txid: &TransactionId, now: Instant) -> bool {
+        self.expire(now);
+
+        self.queued_at.contains_key(txid)
+    }
+
+    /// Records that the transaction was queued at `now`
+    pub fn insert(&mut self, txid: TransactionId, now: Instant) {
+        self.expire(now);
+
+        if self.queued_at.contains_key(&txid) {
+            return;
+        }
+
+        self.queued_at.insert(txid, now);
+        self.order.push_back((txid, now));
+
+        while self.capacity < self.order.len() {
+            if let Some((txid, _)) = self.order.pop_front() {
+                self.queued_at.remove(&txid);
+            }
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((txid, queued_at)) = self.order.front().copied() {
+            if now.duration_since(queued_at) < self.ttl {
+                break;
+            }
+
+            self.order.pop_front();
+            self.queued_at.remove(&txid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bitcoin_hashes::Hash;
+    use fedimint_core::TransactionId;
+    use tokio::time::Instant;
+
+    use super::RecentTransactions;
+
+    fn txid(byte: u8) -> TransactionId {
+        TransactionId::from_byte_array([byte; 32])
+    }
+
+    #[test]
+    fn test_recent_transactions() {
+        let mut recent = RecentTransactions::new(2, Duration::from_secs(10));
+        let start = Instant::now();
+
+        assert!(!recent.contains(&txid(0), start));
+
+        recent.insert(txid(0), start);
+        recent.insert(txid(1), start + Duration::from_secs(5));
+
+        assert!(recent.contains(&txid(0), start + Duration::from_secs(9)));
+        assert!(recent.contains(&txid(1), start + Duration::from_secs(9)));
+
+        // transactions expire after the ttl
+        assert!(!recent.contains(&txid(0), start + Duration::from_secs(10)));
+        assert!(recent.contains(&txid(1), start + Duration::from_secs(10)));
+
+        // the oldest transaction is evicted once the capacity is exceeded
+        recent.insert(txid(2), start + Duration::from_secs(11));
+        recent.insert(txid(3), start + Duration::from_secs(12));
+
+        assert!(!recent.contains(&txid(1), start + Duration::from_secs(12)));
+        assert!(recent.contains(&txid(2), start + Duration::from_secs(12)));
+        assert!(recent.contains(&txid(3), start + Duration::from_secs(12)));
+    }
+}
@@ -5,14 +5,17 @@ use std::time::Duration;
 
 use aleph_bft::Keychain as KeychainTrait;
 use anyhow::{anyhow, bail};
-use async_channel::Receiver;
+use async_channel::{Receiver, Sender};
+use bitcoin_hashes::sha256;
 use fedimint_api_client::api::{DynGlobalApi, FederationApiExt, PeerConnectionStatus};
 use fedimint_api_client::query::FilterMap;
 use fedimint_core::core::MODULE_INSTANCE_ID_GLOBAL;
-use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::db::{
+    Committable, Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped,
+};
 use fedimint_core::encoding::Decodable;
 use fedimint_core::endpoint_constants::AWAIT_SIGNED_SESSION_OUTCOME_ENDPOINT;
-use fedimint_core::epoch::ConsensusItem;
+use fedimint_core::epoch::{ConsensusItem, PeerEndpoints, SessionStateHash};
 use fedimint_core::fmt_utils::OptStacktrace;
 use fedimint_core::module::audit::Audit;
 use fedimint_core::module::registry::{ModuleDecoderRegistry, ServerModuleRegistry};
@@ -23,11 +26,11 @@ use fedimint_core::session_outcome::{
 };
 use fedimint_core::task::{sleep, TaskGroup, TaskHandle};
 use fedimint_core::timing::TimeReporter;
-use fedimint_core::{timing, PeerId};
+use fedimint_core::{timing, PeerId, TransactionId};
 use futures::StreamExt;
 use rand::Rng;
-use tokio::sync::{watch, RwLock};
-use tracing::{debug, info, instrument, warn, Level};
+use tokio::sync::{oneshot, watch, RwLock};
+use tracing::{debug, error, info, instrument, warn, Level};
 
 use crate::atomic_broadcast::backup::{BackupReader, BackupWriter};
 use crate::atomic_broadcast::data_provider::{DataProvider, UnitData};
@@ -36,37 +39,62 @@ use crate::atomic_broadcast::network::Network;
 use crate::atomic_broadcast::spawner::Spawner;
 use crate::atomic_broadcast::{to_node_index, Keychain, Message};
 use crate::config::ServerConfig;
+use crate::consensus::checkpoint::{
+    checkpoint_interval, collect_checkpoint_signatures, consensus_state_hash_dbtx,
+    create_checkpoint, LocalModulePrefixes,
+};
 use crate::consensus::db::{
     AcceptedItemKey, AcceptedItemPrefix, AcceptedTransactionKey, AlephUnitsPrefix,
-    SignedSessionOutcomeKey, SignedSessionOutcomePrefix,
+    PeerEndpointsKey, PeerEndpointsPrefix, PeerStateHashKey, PeerStateHashPrefix,
+    RejectedTransaction, RejectedTransactionKey, SignedSessionOutcomeKey,
+    SignedSessionOutcomePrefix, TransactionSessionKey,
 };
 use crate::consensus::debug_fmt::FmtDbgConsensusItem;
+use crate::consensus::federation_meta::process_federation_meta;
+use crate::consensus::mempool::MempoolTracker;
+use crate::consensus::parallel::{concurrent_groups, TransactionFootprint};
+use crate::consensus::profiling::{SessionProfiler, SessionStage};
+use crate::consensus::pruning::prune_sessions;
+use crate::consensus::state_hash::{process_state_hash, store_own_state_hash};
+use crate::consensus::tls_rotation::{process_tls_cert_rotation, tls_config};
 use crate::consensus::transaction::process_transaction_with_dbtx;
+use crate::consensus::upgrade::{process_upgrade_vote, upgrade_status};
 use crate::fedimint_core::encoding::Encodable;
 use crate::metrics::{
     CONSENSUS_ITEMS_PROCESSED_TOTAL, CONSENSUS_ITEM_PROCESSING_DURATION_SECONDS,
     CONSENSUS_ITEM_PROCESSING_MODULE_AUDIT_DURATION_SECONDS,
     CONSENSUS_PEER_CONTRIBUTION_SESSION_IDX, CONSENSUS_SESSION_COUNT,
+    CONSENSUS_STATE_DIVERGENCE_COUNT, CONSENSUS_STATE_HASH_DURATION_SECONDS,
 };
 use crate::net::connect::{Connector, TlsTcpConnector};
-use crate::net::peers::{DelayCalculator, ReconnectPeerConnections};
+use crate::net::peers::{DelayCalculator, PeerConnectorLayer, ReconnectPeerConnections};
 use crate::LOG_CONSENSUS;
 
 /// Runs the main server consensus loop
 pub struct ConsensusEngine {
     pub modules: ServerModuleRegistry,
+    /// Module state that is excluded from state hashes and checkpoints
+    pub local_module_prefixes: LocalModulePrefixes,
     pub db: Database,
     pub keychain: Keychain,
     pub federation_api: DynGlobalApi,
     pub cfg: ServerConfig,
+    pub submission_sender: Sender<ConsensusItem>,
     pub submission_receiver: Receiver<ConsensusItem>,
+    pub mempool: MempoolTracker,
+    pub session_profiler: SessionProfiler,
     pub shutdown_receiver: watch::Receiver<Option<u64>>,
+    /// Set to the session after which we halted for a scheduled upgrade
+    pub upgrade_sender: watch::Sender<Option<u64>>,
     pub last_ci_by_peer: Arc<RwLock<BTreeMap<PeerId, u64>>>,
+    pub state_divergence_by_peer: Arc<RwLock<BTreeMap<PeerId, u64>>>,
     /// Just a string version of `cfg.local.identity` for performance
     pub self_id_str: String,
     /// Just a string version of peer ids for performance
     pub peer_id_str: Vec<String>,
     pub connection_status_channels: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStatus>>>,
+    /// Wraps the connector of our peer connections, only set in tests
+    pub connector_layer: Option<PeerConnectorLayer<Message>>,
     pub task_group: TaskGroup,
 }
 
@@ -94,6 +122,8 @@ impl ConsensusEngine {
             let session_start_time = std::time::Instant::now();
 
             while let Ok(item) = self.submission_receiver.recv().await {
+                self.mempool.remove(&item);
+
                 if self
                     .process_consensus_item(
                         session_index,
@@ -118,7 +148,7 @@ impl ConsensusEngine {
             };
 
             let header = session_outcome.header(session_index);
-            let signature = self.keychain.sign(&header);
+            let signature = self.keychain.try_sign(&header)?;
             let signatures = BTreeMap::from_iter([(self.cfg.local.identity, signature)]);
 
             self.complete_session(
@@ -130,8 +160,19 @@ impl ConsensusEngine {
             )
             .await;
 
+            self.session_profiler
+                .complete_session(session_index, session_start_time.elapsed());
+
             info!(target: LOG_CONSENSUS, "Session {session_index} completed");
 
+            if self.is_upgrade_scheduled(session_index).await {
+                info!(target: LOG_CONSENSUS, "Halting for the scheduled upgrade");
+
+                self.upgrade_sender.send_replace(Some(session_index));
+
+                break;
+            }
+
             if Some(session_index) == self.shutdown_receiver.borrow().to_owned() {
                 break;
             }
@@ -148,11 +189,36 @@ impl ConsensusEngine {
 
         self.confirm_server_config_consensus_hash().await?;
 
+        let mut network_config = self.cfg.network_config();
+
+        // Peers that have moved since the config was generated announced their
+        // new addresses via consensus
+        for (peer, endpoints) in self.peer_endpoints().await {
+            if let (Some(p2p_url), Some(url)) =
+                (endpoints.p2p_url, network_config.peers.get_mut(&peer))
+            {
+                *url = p2p_url;
+            }
+        }
+
+        let (tls_config_sender, tls_config_receiver) =
+            watch::channel(tls_config(&self.cfg, &self.db).await?);
+
+        let mut connector =
+            TlsTcpConnector::new_with_updates(tls_config_receiver, self.cfg.local.identity)
+                .with_max_frame_bytes(self.cfg.local.peer_rate_limit.max_message_bytes)
+                .into_dyn();
+
+        if let Some(connector_layer) = &self.connector_layer {
+            connector = connector_layer(self.cfg.local.identity, connector);
+        }
+
         // Build P2P connections for the atomic broadcast
         let connections = ReconnectPeerConnections::new(
-            self.cfg.network_config(),
-            DelayCalculator::PROD_DEFAULT,
-            TlsTcpConnector::new(self.cfg.tls_config(), self.cfg.local.identity).into_dyn(),
+            network_config,
+            DelayCalculator::from(&self.cfg.local.peer_reconnect),
+            self.cfg.local.peer_rate_limit,
+            connector,
             &self.task_group,
             Arc::clone(&self.connection_status_channels),
         )
@@ -163,10 +229,61 @@ impl ConsensusEngine {
 
             CONSENSUS_SESSION_COUNT.set(session_index as i64);
 
+            let session_start_time = std::time::Instant::now();
+
             self.run_session(connections.clone(), session_index).await?;
 
+            self.session_profiler
+                .complete_session(session_index, session_start_time.elapsed());
+
             info!(target: LOG_CONSENSUS, "Session {session_index} completed");
 
+            for (peer, endpoints) in self.peer_endpoints().await {
+                if let Some(p2p_url) = endpoints.p2p_url {
+                    connections.update_peer_address(peer, p2p_url);
+                }
+            }
+
+            // Applies certificate rotations and expires the grace window
+            tls_config_sender.send_replace(tls_config(&self.cfg, &self.db).await?);
+
+            if (session_index + 1) % checkpoint_interval() == 0 {
+                let header =
+                    create_checkpoint(&self.db, &self.local_module_prefixes, session_index).await;
+
+                let state_hash = header.state_hash;
+
+                self.task_group.spawn_cancellable(
+                    "collect checkpoint signatures",
+                    collect_checkpoint_signatures(
+                        self.db.clone(),
+                        self.federation_api.clone(),
+                        self.keychain.clone(),
+                        header,
+                    ),
+                );
+
+                self.state_hash_reporter()
+                    .announce(session_index, state_hash)
+                    .await;
+            } else {
+                self.spawn_state_hash(session_index).await;
+            }
+
+            if let Some(policy) = &self.cfg.local.session_pruning {
+                prune_sessions(&self.db, policy, session_index + 1).await;
+            }
+
+            if self.is_upgrade_scheduled(session_index).await {
+                info!(target: LOG_CONSENSUS, "Halting for the scheduled upgrade, waiting for peers to complete the session...");
+
+                sleep(Duration::from_secs(60)).await;
+
+                self.upgrade_sender.send_replace(Some(session_index));
+
+                break;
+            }
+
             if Some(session_index) == self.shutdown_receiver.borrow().to_owned() {
                 info!(target: LOG_CONSENSUS, "Initiating shutdown, waiting for peers to complete the session...");
 
@@ -181,6 +298,19 @@ impl ConsensusEngine {
         Ok(())
     }
 
+    /// Whether a threshold of guardians voted to halt for an upgrade after the
+    /// session we just completed
+    async fn is_upgrade_scheduled(&self, session_index: u64) -> bool {
+        let status = upgrade_status(
+            &mut self.db.begin_transaction_nc().await,
+            self.keychain.threshold(),
+            session_index,
+        )
+        .await;
+
+        status.scheduled_session == Some(session_index)
+    }
+
     async fn confirm_server_config_consensus_hash(&self) -> anyhow::Result<()> {
         let our_hash = self.cfg.consensus.consensus_hash();
 
@@ -206,6 +336,7 @@ impl ConsensusEngine {
         }
     }
 
+    #[instrument(target = "fm::consensus", skip(self, connections), level = "info")]
     pub async fn run_session(
         &self,
         connections: ReconnectPeerConnections<Message>,
@@ -268,7 +399,12 @@ impl ConsensusEngine {
             aleph_bft::run_session(
                 config,
                 aleph_bft::LocalIO::new(
-                    DataProvider::new(self.submission_receiver.clone(), signature_receiver),
+                    DataProvider::new(
+                        self.submission_receiver.clone(),
+                        self.mempool.clone(),
+                        self.session_profiler.clone(),
+                        signature_receiver,
+                    ),
                     FinalizationHandler::new(unit_data_sender),
                     BackupWriter::new(self.db.clone()),
                     BackupReader::new(self.db.clone()),
@@ -285,14 +421,20 @@ impl ConsensusEngine {
         // ordered every round
         let batches_per_session = expected_rounds * self.keychain.peer_count();
 
-        let signed_session_outcome = self
-            .complete_signed_session_outcome(
+        let signed_session_outcome = tokio::select! {
+            signed_session_outcome = self.complete_signed_session_outcome(
                 session_index,
                 batches_per_session,
                 unit_data_receiver,
                 signature_sender,
-            )
-            .await?;
+            ) => signed_session_outcome?,
+            failure = self.keychain.signing_failure() => {
+                terminator_sender.send(()).ok();
+                aleph_handle.await.ok();
+
+                bail!("Aborted session {session_index} after failing to sign: {failure}");
+            }
+        };
 
         // We can terminate the session instead of waiting for other peers to complete
         // it since they can always download the signed session outcome from us
@@ -326,17 +468,7 @@ impl ConsensusEngine {
                 unit_data = unit_data_receiver.recv() => {
                     if let (UnitData::Batch(bytes), peer) = unit_data? {
                         if let Ok(items) = Vec::<ConsensusItem>::consensus_decode(&mut bytes.as_slice(), &self.decoders()){
-                            for item in items {
-                                if self.process_consensus_item(
-                                    session_index,
-                                    item_index,
-                                    item.clone(),
-                                    peer
-                                ).await
-                                .is_ok() {
-                                    item_index += 1;
-                                }
-                            }
+                            item_index += self.process_batch(session_index, item_index, items, peer).await;
                         }
                         num_batches += 1;
                     }
@@ -380,7 +512,7 @@ impl ConsensusEngine {
 
         // We send our own signature to the data provider to be submitted to the atomic
         // broadcast and collected by our peers
-        signature_sender.send(Some(self.keychain.sign(&header)))?;
+        signature_sender.send(Some(self.keychain.try_sign(&header)?))?;
 
         let mut signatures = BTreeMap::new();
 
@@ -412,6 +544,47 @@ impl ConsensusEngine {
         })
     }
 
+    /// Hashes the consensus state after the session in a background task,
+    /// since reading the entire state would delay the next session. We only
+    /// wait until the task took its snapshot of the state, so the next session
+    /// can not change the state before it is hashed.
+    async fn spawn_state_hash(&self, session_index: u64) {
+        let (snapshot_sender, snapshot_receiver) = oneshot::channel();
+        let reporter = self.state_hash_reporter();
+        let local_module_prefixes = self.local_module_prefixes.clone();
+
+        self.task_group
+            .spawn_cancellable("hash consensus state", async move {
+                let db = reporter.db.clone();
+                let mut dbtx = db.begin_transaction_nc().await;
+
+                // The session loop only waits for the snapshot
+                let _ = snapshot_sender.send(());
+
+                let timer = CONSENSUS_STATE_HASH_DURATION_SECONDS.start_timer();
+                let state_hash = consensus_state_hash_dbtx(&mut dbtx, &local_module_prefixes).await;
+                timer.observe_duration();
+
+                drop(dbtx);
+
+                reporter.announce(session_index, state_hash).await;
+            });
+
+        // Only fails if the task was cancelled because we are shutting down
+        let _ = snapshot_receiver.await;
+    }
+
+    fn state_hash_reporter(&self) -> StateHashReporter {
+        StateHashReporter {
+            db: self.db.clone(),
+            mempool: self.mempool.clone(),
+            submission_sender: self.submission_sender.clone(),
+            state_divergence_by_peer: self.state_divergence_by_peer.clone(),
+            self_id_str: self.self_id_str.clone(),
+            peer_id_str: self.peer_id_str.clone(),
+        }
+    }
+
     fn decoders(&self) -> ModuleDecoderRegistry {
         self.modules.decoder_registry()
     }
@@ -427,11 +600,18 @@ impl ConsensusEngine {
             .await
     }
 
+    #[instrument(
+        target = "fm::consensus",
+        skip(self, signed_session_outcome),
+        level = "info"
+    )]
     pub async fn complete_session(
         &self,
         session_index: u64,
         signed_session_outcome: SignedSessionOutcome,
     ) {
+        let start = std::time::Instant::now();
+
         let mut dbtx = self.db.begin_transaction().await;
 
         dbtx.remove_by_prefix(&AlephUnitsPrefix).await;
@@ -452,6 +632,150 @@ impl ConsensusEngine {
         dbtx.commit_tx_result()
             .await
             .expect("This is the only place where we write to this key");
+
+        self.session_profiler
+            .record(SessionStage::Commit, start.elapsed());
+    }
+
+    /// Processes the items of an ordered batch and returns the number of
+    /// accepted items. Consecutive transactions touching disjoint sets of
+    /// modules are processed concurrently in independent database
+    /// transactions, see [`crate::consensus::parallel`].
+    async fn process_batch(
+        &self,
+        session_index: u64,
+        item_index: u64,
+        items: Vec<ConsensusItem>,
+        peer: PeerId,
+    ) -> u64 {
+        let footprints = items
+            .iter()
+            .map(TransactionFootprint::from_item)
+            .collect::<Vec<_>>();
+
+        let mut accepted = 0;
+
+        for group in concurrent_groups(&footprints) {
+            let group = items[group].to_vec();
+
+            // When recovering from a crash the items have to be matched against the
+            // previously accepted items one by one
+            let is_replay = self
+                .db
+                .begin_transaction_nc()
+                .await
+                .get_value(&AcceptedItemKey(item_index + accepted))
+                .await
+                .is_some();
+
+            if group.len() == 1 || is_replay {
+                for item in group {
+                    if self
+                        .process_consensus_item(session_index, item_index + accepted, item, peer)
+                        .await
+                        .is_ok()
+                    {
+                        accepted += 1;
+                    }
+                }
+            } else {
+                accepted += self
+                    .process_transactions_concurrently(
+                        session_index,
+                        item_index + accepted,
+                        group,
+                        peer,
+                    )
+                    .await;
+            }
+        }
+
+        accepted
+    }
+
+    /// Processes transactions with disjoint footprints concurrently and
+    /// commits the accepted ones in order. If a commit fails due to a conflict
+    /// we did not anticipate, the transaction is processed again on its own.
+    async fn process_transactions_concurrently(
+        &self,
+        session_index: u64,
+        item_index: u64,
+        items: Vec<ConsensusItem>,
+        peer: PeerId,
+    ) -> u64 {
+        self.record_contribution(session_index, peer).await;
+
+        let results = futures::future::join_all(items.iter().map(|item| async move {
+            let mut dbtx = self.db.begin_transaction().await;
+
+            dbtx.ignore_uncommitted();
+
+            let start = std::time::Instant::now();
+
+            let result = self
+                .process_consensus_item_with_db_transaction(
+                    &mut dbtx.to_ref_nc(),
+                    item.clone(),
+                    peer,
+                )
+                .await;
+
+            self.session_profiler
+                .record(SessionStage::Processing, start.elapsed());
+
+            (dbtx, result)
+        }))
+        .await;
+
+        let mut accepted = 0;
+
+        for (item, (dbtx, result)) in items.into_iter().zip(results) {
+            if let Err(error) = result {
+                if let ConsensusItem::Transaction(transaction) = &item {
+                    self.record_rejected_transaction(session_index, transaction.tx_hash(), &error)
+                        .await;
+                }
+
+                continue;
+            }
+
+            let committed = self
+                .commit_accepted_item(
+                    dbtx,
+                    session_index,
+                    item_index + accepted,
+                    item.clone(),
+                    peer,
+                )
+                .await;
+
+            if let Err(e) = committed {
+                debug!(target: LOG_CONSENSUS, "Concurrent processing conflicted, processing item again: {e}");
+
+                if self
+                    .process_consensus_item(session_index, item_index + accepted, item, peer)
+                    .await
+                    .is_err()
+                {
+                    continue;
+                }
+            }
+
+            accepted += 1;
+        }
+
+        accepted
+    }
+
+    async fn record_contribution(&self, session_index: u64, peer: PeerId) {
+        self.last_ci_by_peer
+            .write()
+            .await
+            .insert(peer, session_index);
+
+        CONSENSUS_PEER_CONTRIBUTION_SESSION_IDX
+            .with_label_values(&[&self.self_id_str, &self.peer_id_str[peer.to_usize()]])
+            .set(session_index as i64);
     }
 
     #[instrument(target = "fm::consensus", skip(self, item), level = "info")]
@@ -470,14 +794,7 @@ impl ConsensusEngine {
 
         debug!(%peer, item = ?FmtDbgConsensusItem(&item), "Processing consensus item");
 
-        self.last_ci_by_peer
-            .write()
-            .await
-            .insert(peer, session_index);
-
-        CONSENSUS_PEER_CONTRIBUTION_SESSION_IDX
-            .with_label_values(&[&self.self_id_str, peer_id_str])
-            .set(session_index as i64);
+        self.record_contribution(session_index, peer).await;
 
         let mut dbtx = self.db.begin_transaction().await;
 
@@ -497,13 +814,62 @@ impl ConsensusEngine {
             bail!("Item was discarded previously");
         }
 
-        self.process_consensus_item_with_db_transaction(&mut dbtx.to_ref_nc(), item.clone(), peer)
-            .await?;
+        let txid = match &item {
+            ConsensusItem::Transaction(transaction) => Some(transaction.tx_hash()),
+            _ => None,
+        };
+
+        let start = std::time::Instant::now();
+
+        let result = self
+            .process_consensus_item_with_db_transaction(&mut dbtx.to_ref_nc(), item.clone(), peer)
+            .await;
+
+        self.session_profiler
+            .record(SessionStage::Processing, start.elapsed());
+
+        if let Err(error) = result {
+            if let Some(txid) = txid {
+                self.record_rejected_transaction(session_index, txid, &error)
+                    .await;
+            }
+
+            return Err(error);
+        }
+
+        self.commit_accepted_item(dbtx, session_index, item_index, item, peer)
+            .await
+            .expect("Committing consensus epoch failed");
+
+        timing_prom.observe_duration();
+
+        Ok(())
+    }
+
+    /// Records a successfully processed item as accepted, audits the resulting
+    /// state and commits the database transaction the item was processed in
+    async fn commit_accepted_item(
+        &self,
+        mut dbtx: DatabaseTransaction<'_, Committable>,
+        session_index: u64,
+        item_index: u64,
+        item: ConsensusItem,
+        peer: PeerId,
+    ) -> anyhow::Result<()> {
+        let start = std::time::Instant::now();
 
         // After this point the we have to commit the database transaction since the
         // item has been fully processed without errors
         dbtx.warn_uncommitted();
 
+        if let ConsensusItem::Transaction(transaction) = &item {
+            dbtx.insert_entry(
+                &TransactionSessionKey(transaction.tx_hash()),
+                &session_index,
+            )
+            .await;
+        }
+
         dbtx.insert_entry(&AcceptedItemKey(item_index), &AcceptedItem { item, peer })
             .await;
 
@@ -533,18 +899,53 @@ impl ConsensusEngine {
             panic!("Balance sheet of the fed has gone negative, this should never happen! {audit}")
         }
 
-        dbtx.commit_tx_result()
-            .await
-            .expect("Committing consensus epoch failed");
+        let result = dbtx.commit_tx_result().await;
+
+        self.session_profiler
+            .record(SessionStage::Commit, start.elapsed());
+
+        result?;
 
         CONSENSUS_ITEMS_PROCESSED_TOTAL
-            .with_label_values(&[peer_id_str])
+            .with_label_values(&[&self.peer_id_str[peer.to_usize()]])
             .inc();
-        timing_prom.observe_duration();
 
         Ok(())
     }
 
+    /// Remembers why an ordered transaction failed to process so clients can
+    /// query it via the `transaction_status` endpoint
+    async fn record_rejected_transaction(
+        &self,
+        session_index: u64,
+        txid: TransactionId,
+        error: &anyhow::Error,
+    ) {
+        let mut dbtx = self.db.begin_transaction().await;
+
+        // A resubmission of an accepted transaction does not change its status
+        if dbtx
+            .get_value(&AcceptedTransactionKey(txid))
+            .await
+            .is_some()
+        {
+            return;
+        }
+
+        dbtx.insert_entry(
+            &RejectedTransactionKey(txid),
+            &RejectedTransaction {
+                session_index,
+                reason: error.to_string(),
+            },
+        )
+        .await;
+
+        if let Err(e) = dbtx.commit_tx_result().await {
+            warn!(target: LOG_CONSENSUS, %txid, "Failed to record rejected transaction: {e:?}");
+        }
+    }
+
     async fn process_consensus_item_with_db_transaction(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
@@ -592,6 +993,60 @@ impl ConsensusEngine {
 
                 Ok(())
             }
+            ConsensusItem::PeerEndpoints(update) => {
+                let current = dbtx
+                    .get_value(&PeerEndpointsKey(peer_id))
+                    .await
+                    .unwrap_or_default();
+
+                let endpoints = current.merge(update);
+
+                if endpoints == current {
+                    bail!("Peer endpoints are unchanged");
+                }
+
+                info!(target: LOG_CONSENSUS, peer = %peer_id, "Peer announced new endpoints");
+
+                dbtx.insert_entry(&PeerEndpointsKey(peer_id), &endpoints)
+                    .await;
+
+                Ok(())
+            }
+            ConsensusItem::TlsCertRotation(rotation) => {
+                process_tls_cert_rotation(dbtx, &self.cfg, &self.keychain, peer_id, rotation)
+                    .await?;
+
+                info!(target: LOG_CONSENSUS, peer = %peer_id, "Peer rotated its TLS certificate");
+
+                Ok(())
+            }
+            ConsensusItem::StateHash(state_hash) => {
+                if let Some(own_state_hash) = process_state_hash(dbtx, peer_id, state_hash).await? {
+                    self.state_hash_reporter()
+                        .check(peer_id, state_hash, own_state_hash)
+                        .await;
+                }
+
+                Ok(())
+            }
+            ConsensusItem::UpgradeVote(vote) => {
+                process_upgrade_vote(dbtx, peer_id, vote).await?;
+
+                info!(target: LOG_CONSENSUS, peer = %peer_id, ?vote, "Peer voted on an upgrade");
+
+                Ok(())
+            }
+            ConsensusItem::FederationMeta(meta) => {
+                let threshold = self.keychain.threshold();
+
+                if let Some(record) =
+                    process_federation_meta(dbtx, peer_id, meta, threshold).await?
+                {
+                    info!(target: LOG_CONSENSUS, revision = record.revision, "Federation meta was approved");
+                }
+
+                Ok(())
+            }
             ConsensusItem::Default { variant, .. } => {
                 warn!(
                     target: LOG_CONSENSUS,
@@ -608,7 +1063,7 @@ impl ConsensusEngine {
         index: u64,
     ) -> SignedSessionOutcome {
         let keychain = self.keychain.clone();
-        let total_peers = self.keychain.peer_count();
+        let num_peers = self.keychain.num_peers();
         let decoders = self.decoders();
 
         let filter_map = move |response: SerdeModuleEncoding<SignedSessionOutcome>| match response
@@ -641,7 +1096,7 @@ impl ConsensusEngine {
 
             let result = federation_api
                 .request_with_strategy(
-                    FilterMap::new(filter_map.clone(), total_peers),
+                    FilterMap::new(filter_map.clone(), num_peers),
                     AWAIT_SIGNED_SESSION_OUTCOME_ENDPOINT.to_string(),
                     ApiRequestErased::new(index),
                 )
@@ -656,6 +1111,17 @@ impl ConsensusEngine {
         }
     }
 
+    async fn peer_endpoints(&self) -> Vec<(PeerId, PeerEndpoints)> {
+        self.db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&PeerEndpointsPrefix)
+            .await
+            .map(|(key, endpoints)| (key.0, endpoints))
+            .collect()
+            .await
+    }
+
     /// Returns the number of sessions already saved in the database. This count
     /// **does not** include the currently running session.
     async fn get_finished_session_count(&self) -> u64 {
@@ -663,6 +1129,88 @@ impl ConsensusEngine {
     }
 }
 
+/// Announces our state hashes and compares them to the ones of our peers, can
+/// be moved into the task that hashes the state in the background
+#[derive(Clone)]
+struct StateHashReporter {
+    db: Database,
+    mempool: MempoolTracker,
+    submission_sender: Sender<ConsensusItem>,
+    state_divergence_by_peer: Arc<RwLock<BTreeMap<PeerId, u64>>>,
+    self_id_str: String,
+    peer_id_str: Vec<String>,
+}
+
+impl StateHashReporter {
+    /// Stores our state hash after the session and submits it to our peers,
+    /// see [`crate::consensus::state_hash`]
+    async fn announce(&self, session_index: u64, state_hash: sha256::Hash) {
+        store_own_state_hash(&self.db, session_index, state_hash).await;
+
+        let item = ConsensusItem::StateHash(SessionStateHash {
+            session_index,
+            state_hash,
+        });
+
+        self.mempool.insert(&item);
+
+        // The announcement is not worth stalling consensus for a full queue
+        if let Err(e) = self.submission_sender.try_send(item) {
+            self.mempool.remove(&e.into_inner());
+
+            warn!(target: LOG_CONSENSUS, session_index, "Submission queue is full, skipping state hash announcement");
+        }
+
+        // Peers that finished hashing before us have already announced their hash
+        // for this session, which we could not compare to ours at the time
+        let peer_state_hashes = self
+            .db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&PeerStateHashPrefix)
+            .await
+            .collect::<Vec<(PeerStateHashKey, SessionStateHash)>>()
+            .await;
+
+        for (PeerStateHashKey(peer), peer_state_hash) in peer_state_hashes {
+            if peer_state_hash.session_index == session_index {
+                self.check(peer, peer_state_hash, state_hash).await;
+            }
+        }
+    }
+
+    /// Raises an alert if the state hash a peer announced differs from ours
+    async fn check(
+        &self,
+        peer: PeerId,
+        state_hash: SessionStateHash,
+        own_state_hash: sha256::Hash,
+    ) {
+        let mut state_divergence_by_peer = self.state_divergence_by_peer.write().await;
+
+        if state_hash.state_hash == own_state_hash {
+            state_divergence_by_peer.remove(&peer);
+
+            return;
+        }
+
+        error!(
+            target: LOG_CONSENSUS,
+            %peer,
+            session_index = state_hash.session_index,
+            peer_state_hash = %state_hash.state_hash,
+            %own_state_hash,
+            "CONSENSUS STATE DIVERGED: the peer's state after the session differs from ours"
+        );
+
+        CONSENSUS_STATE_DIVERGENCE_COUNT
+            .with_label_values(&[&self.self_id_str, &self.peer_id_str[peer.to_usize()]])
+            .inc();
+
+        state_divergence_by_peer.insert(peer, state_hash.session_index);
+    }
+}
+
 pub async fn get_finished_session_count_static(dbtx: &mut DatabaseTransaction<'_>) -> u64 {
     dbtx.find_by_prefix_sorted_descending(&SignedSessionOutcomePrefix)
         .await
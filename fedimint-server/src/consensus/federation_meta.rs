@@ -0,0 +1,156 @@
+//! Federation metadata approved by the guardians
+//!
+//! A guardian approves metadata like the federation name or its terms of
+//! service by submitting it as [`ConsensusItem::FederationMeta`]. Once a
+//! threshold of guardians approved identical metadata it replaces the active
+//! metadata, which is served to clients and embedded in our invite codes.
+//!
+//! [`ConsensusItem::FederationMeta`]: fedimint_core::epoch::ConsensusItem::FederationMeta
+
+use std::collections::BTreeMap;
+
+use anyhow::ensure;
+use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::epoch::{FederationMeta, FederationMetaRecord};
+use fedimint_core::PeerId;
+use futures::StreamExt;
+
+use crate::consensus::db::{
+    FederationMetaKey, FederationMetaProposalKey, FederationMetaProposalPrefix,
+};
+use crate::consensus::engine::get_finished_session_count_static;
+
+/// Records the metadata a peer approved and returns the new active metadata if
+/// a threshold of peers approved it
+pub async fn process_federation_meta(
+    dbtx: &mut DatabaseTransaction<'_>,
+    peer: PeerId,
+    meta: FederationMeta,
+    threshold: usize,
+) -> anyhow::Result<Option<FederationMetaRecord>> {
+    meta.validate()?;
+
+    let active = dbtx.get_value(&FederationMetaKey).await;
+
+    ensure!(
+        active.as_ref().map(|record| &record.meta) != Some(&meta),
+        "Federation meta is already active"
+    );
+
+    let previous = dbtx
+        .insert_entry(&FederationMetaProposalKey(peer), &meta)
+        .await;
+
+    ensure!(
+        previous.as_ref() != Some(&meta),
+        "Peer already approved this federation meta"
+    );
+
+    let approvals = federation_meta_proposals(dbtx)
+        .await
+        .into_iter()
+        .filter(|(_, proposal)| *proposal == meta)
+        .map(|(peer, _)| peer)
+        .collect::<Vec<PeerId>>();
+
+    if approvals.len() < threshold {
+        return Ok(None);
+    }
+
+    for peer in approvals {
+        dbtx.remove_entry(&FederationMetaProposalKey(peer)).await;
+    }
+
+    let record = FederationMetaRecord {
+        meta,
+        revision: active.map_or(0, |record| record.revision + 1),
+        session_index: get_finished_session_count_static(dbtx).await,
+    };
+
+    dbtx.insert_entry(&FederationMetaKey, &record).await;
+
+    Ok(Some(record))
+}
+
+/// The metadata approved by peers that has not become active yet
+pub async fn federation_meta_proposals(
+    dbtx: &mut DatabaseTransaction<'_>,
+) -> BTreeMap<PeerId, FederationMeta> {
+    dbtx.find_by_prefix(&FederationMetaProposalPrefix)
+        .await
+        .map(|(key, meta)| (key.0, meta))
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::IRawDatabaseExt;
+    use fedimint_core::epoch::FederationMeta;
+    use fedimint_core::PeerId;
+
+    use super::{federation_meta_proposals, process_federation_meta};
+
+    fn meta(name: &str) -> FederationMeta {
+        FederationMeta {
+            name: Some(name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_federation_meta() {
+        let db = MemDatabase::new().into_database();
+        let mut dbtx = db.begin_transaction().await;
+
+        for peer in 0..2 {
+            assert_eq!(
+                process_federation_meta(&mut dbtx.to_ref_nc(), PeerId::from(peer), meta("a"), 3)
+                    .await
+                    .unwrap(),
+                None
+            );
+        }
+
+        // every peer can only approve the same metadata once
+        assert!(
+            process_federation_meta(&mut dbtx.to_ref_nc(), PeerId::from(0), meta("a"), 3)
+                .await
+                .is_err()
+        );
+
+        assert!(
+            process_federation_meta(&mut dbtx.to_ref_nc(), PeerId::from(3), meta(""), 3)
+                .await
+                .is_err()
+        );
+
+        process_federation_meta(&mut dbtx.to_ref_nc(), PeerId::from(3), meta("b"), 3)
+            .await
+            .unwrap();
+
+        let record = process_federation_meta(&mut dbtx.to_ref_nc(), PeerId::from(2), meta("a"), 3)
+            .await
+            .unwrap()
+            .expect("A threshold approved the metadata");
+
+        assert_eq!(record.meta, meta("a"));
+        assert_eq!(record.revision, 0);
+
+        // only the approvals of the active metadata are removed
+        assert_eq!(
+            federation_meta_proposals(&mut dbtx.to_ref_nc())
+                .await
+                .into_keys()
+                .collect::<Vec<_>>(),
+            vec![PeerId::from(3)]
+        );
+
+        assert!(
+            process_federation_meta(&mut dbtx.to_ref_nc(), PeerId::from(3), meta("a"), 3)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Invite codes scoped to a subset of guardians, an expiry and a number of uses
+//!
+//! A guardian creates the invite code and signs its [`InviteScope`] with its
+//! broadcast key, so every guardian can verify the scope without any shared
+//! state. Clients send the invite code along with their config download and
+//! every guardian counts the downloads it served for the invite code itself.
+
+use std::collections::BTreeMap;
+
+use aleph_bft::Keychain as KeychainTrait;
+use anyhow::ensure;
+use fedimint_core::admin_client::CreateInviteCodeRequest;
+use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::Encodable;
+use fedimint_core::invite_code::{InviteCode, InviteScope, SignedInviteScope};
+use fedimint_core::time::duration_since_epoch;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::PeerId;
+
+use crate::atomic_broadcast::{to_node_index, Keychain};
+use crate::config::ServerConfig;
+use crate::consensus::db::InviteUsesKey;
+
+/// Separates invite scope signatures from other signatures of the broadcast
+/// key
+const INVITE_SCOPE_TAG: &[u8] = b"fedimint-invite-scope";
+
+fn signing_message(scope: &InviteScope) -> Vec<u8> {
+    [INVITE_SCOPE_TAG, &scope.consensus_encode_to_vec()].concat()
+}
+
+/// Creates an invite code for the requested guardians with their current API
+/// URLs, signing its scope if it is restricted
+pub fn create_invite_code(
+    cfg: &ServerConfig,
+    api_endpoints: &BTreeMap<PeerId, SafeUrl>,
+    request: CreateInviteCodeRequest,
+) -> anyhow::Result<InviteCode> {
+    ensure!(
+        request
+            .peers
+            .iter()
+            .all(|peer| api_endpoints.contains_key(peer)),
+        "The federation has no such guardian"
+    );
+
+    let federation_id = cfg.get_federation_id();
+
+    let invite_code = if request.peers.is_empty() {
+        InviteCode::new_with_essential_num_guardians(api_endpoints, federation_id)
+    } else {
+        let peer_to_url_map = api_endpoints
+            .iter()
+            .filter(|(peer, _)| request.peers.contains(peer))
+            .map(|(peer, url)| (*peer, url.clone()))
+            .collect();
+
+        InviteCode::new_with_peers(&peer_to_url_map, federation_id)
+    };
+
+    if request.expires_at.is_none() && request.max_uses.is_none() {
+        return Ok(invite_code);
+    }
+
+    if let Some(expires_at) = request.expires_at {
+        ensure!(
+            duration_since_epoch().as_secs() < expires_at,
+            "The expiry has already passed"
+        );
+    }
+
+    ensure!(
+        request.max_uses != Some(0),
+        "The invite code has to allow at least one use"
+    );
+
+    let scope = InviteScope {
+        id: rand::random(),
+        issuer: cfg.local.identity,
+        expires_at: request.expires_at,
+        max_uses: request.max_uses,
+    };
+
+    let signature = Keychain::new(cfg)?.try_sign(&signing_message(&scope))?;
+
+    Ok(invite_code.with_scope(SignedInviteScope { scope, signature }))
+}
+
+/// Checks the scope of the invite code a client downloads the config with and
+/// counts the download
+pub async fn redeem_invite_code(
+    dbtx: &mut DatabaseTransaction<'_>,
+    cfg: &ServerConfig,
+    invite_code: &InviteCode,
+) -> anyhow::Result<()> {
+    ensure!(
+        invite_code.federation_id() == cfg.get_federation_id(),
+        "The invite code is for a different federation"
+    );
+
+    let Some(signed_scope) = invite_code.scope() else {
+        return Ok(());
+    };
+
+    let scope = &signed_scope.scope;
+
+    ensure!(
+        Keychain::new(cfg)?.verify(
+            &signing_message(scope),
+            &signed_scope.signature,
+            to_node_index(scope.issuer)
+        ),
+        "Invalid signature on the invite code"
+    );
+
+    let uses = dbtx
+        .get_value(&InviteUsesKey(scope.id))
+        .await
+        .unwrap_or_default();
+
+    check_invite_scope(scope, duration_since_epoch().as_secs(), uses)?;
+
+    if scope.max_uses.is_some() {
+        dbtx.insert_entry(&InviteUsesKey(scope.id), &(uses + 1))
+            .await;
+    }
+
+    Ok(())
+}
+
+fn check_invite_scope(scope: &InviteScope, now: u64, uses: u64) -> anyhow::Result<()> {
+    if let Some(expires_at) = scope.expires_at {
+        ensure!(now < expires_at, "The invite code has expired");
+    }
+
+    if let Some(max_uses) = scope.max_uses {
+        ensure!(uses < max_uses, "The invite code has been used up");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::invite_code::InviteScope;
+    use fedimint_core::PeerId;
+
+    use super::check_invite_scope;
+
+    #[test]
+    fn test_check_invite_scope() {
+        let scope = InviteScope {
+            id: 0,
+            issuer: PeerId::from(0),
+            expires_at: None,
+            max_uses: None,
+        };
+
+        assert!(check_invite_scope(&scope, u64::MAX, u64::MAX).is_ok());
+
+        let expiring = InviteScope {
+            expires_at: Some(100),
+            ..scope.clone()
+        };
+
+        assert!(check_invite_scope(&expiring, 99, 0).is_ok());
+        assert!(check_invite_scope(&expiring, 100, 0).is_err());
+
+        let limited = InviteScope {
+            max_uses: Some(2),
+            ..scope
+        };
+
+        assert!(check_invite_scope(&limited, 0, 1).is_ok());
+        assert!(check_invite_scope(&limited, 0, 2).is_err());
+    }
+}
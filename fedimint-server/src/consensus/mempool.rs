@@ -0,0 +1,157 @@
+//! Bookkeeping of the consensus items waiting in the submission queue
+//!
+//! The submission queue is a channel whose contents can not be inspected, so
+//! every item sent into it is recorded in the [`MempoolTracker`] as well and
+//! removed again once it is taken out of the queue to be proposed.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use bitcoin_hashes::sha256;
+use fedimint_core::admin_client::{MempoolModuleSummary, MempoolSummary};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::encoding::Encodable;
+use fedimint_core::epoch::ConsensusItem;
+use tokio::time::Instant;
+
+#[derive(Debug)]
+struct QueuedItem {
+    hash: sha256::Hash,
+    bytes: usize,
+    queued_at: Instant,
+    is_transaction: bool,
+    modules: BTreeMap<ModuleInstanceId, MempoolModuleSummary>,
+}
+
+impl QueuedItem {
+    fn new(item: &ConsensusItem, queued_at: Instant) -> Self {
+        let mut modules = BTreeMap::<_, MempoolModuleSummary>::new();
+
+        match item {
+            ConsensusItem::Transaction(transaction) => {
+                for input in &transaction.inputs {
+                    modules
+                        .entry(input.module_instance_id())
+                        .or_default()
+                        .inputs += 1;
+                }
+
+                for output in &transaction.outputs {
+                    modules
+                        .entry(output.module_instance_id())
+                        .or_default()
+                        .outputs += 1;
+                }
+            }
+            ConsensusItem::Module(module_item) => {
+                modules
+                    .entry(module_item.module_instance_id())
+                    .or_default()
+                    .consensus_items += 1;
+            }
+            _ => {}
+        }
+
+        QueuedItem {
+            hash: item.consensus_hash(),
+            bytes: item.consensus_encode_to_vec().len(),
+            queued_at,
+            is_transaction: matches!(item, ConsensusItem::Transaction(..)),
+            modules,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MempoolTracker(Arc<Mutex<Vec<QueuedItem>>>);
+
+impl MempoolTracker {
+    /// Has to be called before the item is sent into the submission queue
+    pub fn insert(&self, item: &ConsensusItem) {
+        let queued = QueuedItem::new(item, Instant::now());
+
+        self.0.lock().expect("Lock poisoned").push(queued);
+    }
+
+    /// Has to be called once the item was taken out of the submission queue
+    /// or failed to be sent into it
+    pub fn remove(&self, item: &ConsensusItem) {
+        let hash = item.consensus_hash::<sha256::Hash>();
+        let mut queue = self.0.lock().expect("Lock poisoned");
+
+        if let Some(index) = queue.iter().position(|queued| queued.hash == hash) {
+            queue.remove(index);
+        }
+    }
+
+    pub fn summary(&self, now: Instant) -> MempoolSummary {
+        let queue = self.0.lock().expect("Lock poisoned");
+
+        let mut summary = MempoolSummary {
+            items: queue.len(),
+            bytes: queue.iter().map(|queued| queued.bytes).sum(),
+            transactions: queue.iter().filter(|queued| queued.is_transaction).count(),
+            oldest_age_ms: queue
+                .iter()
+                .map(|queued| queued.queued_at)
+                .min()
+                .map(|oldest| now.duration_since(oldest).as_millis() as u64),
+            modules: BTreeMap::new(),
+        };
+
+        for (module_id, module_summary) in queue.iter().flat_map(|queued| &queued.modules) {
+            let entry = summary.modules.entry(*module_id).or_default();
+
+            entry.consensus_items += module_summary.consensus_items;
+            entry.inputs += module_summary.inputs;
+            entry.outputs += module_summary.outputs;
+        }
+
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fedimint_core::epoch::{ConsensusItem, PeerEndpoints};
+    use fedimint_core::util::SafeUrl;
+    use tokio::time::Instant;
+
+    use super::MempoolTracker;
+
+    fn item(port: u16) -> ConsensusItem {
+        ConsensusItem::PeerEndpoints(PeerEndpoints {
+            p2p_url: Some(SafeUrl::parse(&format!("fedimint://127.0.0.1:{port}")).unwrap()),
+            api_url: None,
+        })
+    }
+
+    #[test]
+    fn test_mempool_summary() {
+        let mempool = MempoolTracker::default();
+
+        assert_eq!(mempool.summary(Instant::now()).items, 0);
+        assert_eq!(mempool.summary(Instant::now()).oldest_age_ms, None);
+
+        mempool.insert(&item(1));
+        mempool.insert(&item(2));
+        mempool.insert(&item(2));
+
+        let summary = mempool.summary(Instant::now() + Duration::from_secs(1));
+
+        assert_eq!(summary.items, 3);
+        assert_eq!(summary.transactions, 0);
+        assert!(1000 <= summary.oldest_age_ms.unwrap());
+
+        // removes a single instance of a duplicate item
+        mempool.remove(&item(2));
+        assert_eq!(mempool.summary(Instant::now()).items, 2);
+
+        mempool.remove(&item(1));
+        mempool.remove(&item(2));
+        assert_eq!(mempool.summary(Instant::now()).items, 0);
+        assert_eq!(mempool.summary(Instant::now()).bytes, 0);
+    }
+}
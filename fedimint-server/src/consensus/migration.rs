@@ -0,0 +1,289 @@
+//! Safe application of database migrations on startup
+//!
+//! Before a new version of fedimintd migrates the database an operator can
+//! preview the changes with a dry run, which applies the pending migrations to
+//! an in-memory copy of the database. When the migrations are applied for real
+//! we first write an encrypted checkpoint of the database into the data dir
+//! and restore it if any of the migrations fails, since the global and module
+//! migrations are not applied in a single database transaction.
+
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::Context;
+use fedimint_core::config::ServerModuleInitRegistry;
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::{
+    Database, DatabaseVersion, DatabaseVersionKey, DbKeyPrefix as CoreDbKeyPrefix,
+    IDatabaseTransactionOpsCore, IDatabaseTransactionOpsCoreTyped, IRawDatabaseExt,
+    MODULE_GLOBAL_PREFIX,
+};
+use fedimint_core::encoding::Decodable;
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_logging::LOG_DB;
+use serde::Serialize;
+use strum::IntoEnumIterator;
+use tracing::{info, warn};
+
+use crate::config::ServerConfig;
+use crate::consensus::apply_consensus_migrations;
+use crate::consensus::db::{DbKeyPrefix, GLOBAL_DATABASE_VERSION};
+use crate::snapshot::DbSnapshot;
+
+/// Directory in the data dir the pre-migration checkpoints are written to
+pub const MIGRATION_CHECKPOINT_DIR: &str = "migration_checkpoints";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PendingMigration {
+    pub kind: String,
+    /// `None` for the global database
+    pub module_instance_id: Option<ModuleInstanceId>,
+    pub from: DatabaseVersion,
+    pub to: DatabaseVersion,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MigrationChanges {
+    pub added: u64,
+    pub changed: u64,
+    pub removed: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationReport {
+    pub migrations: Vec<PendingMigration>,
+    /// Changed entries per global record type and per module
+    pub changes: BTreeMap<String, MigrationChanges>,
+}
+
+/// Returns the migrations of the global database and of the configured modules
+/// that have not been applied yet
+pub async fn pending_migrations(
+    cfg: &ServerConfig,
+    db: &Database,
+    module_init_registry: &ServerModuleInitRegistry,
+) -> anyhow::Result<Vec<PendingMigration>> {
+    let mut targets = vec![("fedimint-server".to_string(), None, GLOBAL_DATABASE_VERSION)];
+
+    for (module_id, module_cfg) in &cfg.consensus.modules {
+        let module_init = module_init_registry
+            .get(&module_cfg.kind)
+            .with_context(|| {
+                format!("Detected configuration for unsupported module id: {module_id}")
+            })?;
+
+        targets.push((
+            module_init.module_kind().to_string(),
+            Some(*module_id),
+            module_init.database_version(),
+        ));
+    }
+
+    let mut dbtx = db.begin_transaction_nc().await;
+    let mut pending = vec![];
+
+    for (kind, module_instance_id, target) in targets {
+        let key = DatabaseVersionKey(module_instance_id.unwrap_or(MODULE_GLOBAL_PREFIX.into()));
+
+        // A missing version is initialized to the target version without
+        // migrating
+        if let Some(from) = dbtx.get_value(&key).await {
+            if from < target {
+                pending.push(PendingMigration {
+                    kind,
+                    module_instance_id,
+                    from,
+                    to: target,
+                });
+            }
+        }
+    }
+
+    Ok(pending)
+}
+
+/// Applies the pending migrations to an in-memory copy of `db` and reports
+/// which entries they would change. The database itself is only read from.
+pub async fn dry_run_consensus_migrations(
+    cfg: &ServerConfig,
+    db: &Database,
+    module_init_registry: &ServerModuleInitRegistry,
+) -> anyhow::Result<MigrationReport> {
+    let decoders = module_init_registry.decoders_strict(cfg.iter_module_instances())?;
+
+    let migrations = pending_migrations(cfg, db, module_init_registry).await?;
+
+    let before = DbSnapshot::take(db).await?;
+
+    let scratch_db = MemDatabase::new().into_database().with_decoders(decoders);
+
+    before.clone().restore(&scratch_db).await?;
+
+    apply_consensus_migrations(cfg, &scratch_db, module_init_registry).await?;
+
+    let after = DbSnapshot::take(&scratch_db).await?;
+
+    Ok(MigrationReport {
+        migrations,
+        changes: diff_snapshots(&before, &after),
+    })
+}
+
+/// Applies the pending migrations like [`apply_consensus_migrations`], but
+/// first writes an encrypted checkpoint of the database into
+/// `checkpoint_dir` and rolls back to it if a migration fails
+pub async fn apply_consensus_migrations_with_checkpoint(
+    cfg: &ServerConfig,
+    db: &Database,
+    module_init_registry: &ServerModuleInitRegistry,
+    checkpoint_dir: &Path,
+) -> anyhow::Result<()> {
+    let pending = pending_migrations(cfg, db, module_init_registry).await?;
+
+    if pending.is_empty() {
+        return apply_consensus_migrations(cfg, db, module_init_registry).await;
+    }
+
+    let checkpoint = DbSnapshot::take(db).await?;
+
+    let path = checkpoint
+        .encrypt(&cfg.private.api_auth.0)?
+        .write_to_dir(checkpoint_dir, checkpoint.timestamp)?;
+
+    info!(
+        target: LOG_DB,
+        ?pending,
+        path = %path.display(),
+        "Wrote database checkpoint before migrating"
+    );
+
+    if let Err(error) = apply_consensus_migrations(cfg, db, module_init_registry).await {
+        warn!(target: LOG_DB, ?error, "Database migration failed, rolling back");
+
+        rollback(db, checkpoint).await.with_context(|| {
+            format!(
+                "Rolling back the failed migration failed, the checkpoint is stored in {}",
+                path.display()
+            )
+        })?;
+
+        return Err(error.context("Database migration failed and was rolled back"));
+    }
+
+    Ok(())
+}
+
+/// Replaces all entries of `db` with the ones of the checkpoint
+async fn rollback(db: &Database, checkpoint: DbSnapshot) -> anyhow::Result<()> {
+    let mut dbtx = db.begin_transaction().await;
+
+    dbtx.raw_remove_by_prefix(&[]).await?;
+
+    for (key, value) in &checkpoint.entries {
+        dbtx.raw_insert_bytes(key, value).await?;
+    }
+
+    dbtx.commit_tx_result().await
+}
+
+fn diff_snapshots(before: &DbSnapshot, after: &DbSnapshot) -> BTreeMap<String, MigrationChanges> {
+    let before = before.entries.iter().cloned().collect::<BTreeMap<_, _>>();
+    let after = after.entries.iter().cloned().collect::<BTreeMap<_, _>>();
+
+    let mut changes = BTreeMap::<String, MigrationChanges>::new();
+
+    for (key, value) in &after {
+        match before.get(key) {
+            None => changes.entry(entry_group(key)).or_default().added += 1,
+            Some(old_value) if old_value != value => {
+                changes.entry(entry_group(key)).or_default().changed += 1;
+            }
+            Some(_) => {}
+        }
+    }
+
+    for key in before.keys().filter(|key| !after.contains_key(*key)) {
+        changes.entry(entry_group(key)).or_default().removed += 1;
+    }
+
+    changes
+}
+
+/// Name of the global record type or module the entry belongs to
+fn entry_group(key: &[u8]) -> String {
+    if key[0] == CoreDbKeyPrefix::DatabaseVersion as u8 {
+        return "DatabaseVersion".to_string();
+    }
+
+    if key[0] == MODULE_GLOBAL_PREFIX {
+        return match ModuleInstanceId::consensus_decode(
+            &mut Cursor::new(&key[1..]),
+            &ModuleDecoderRegistry::default(),
+        ) {
+            Ok(module_instance_id) => format!("Module {module_instance_id}"),
+            Err(..) => "Module".to_string(),
+        };
+    }
+
+    DbKeyPrefix::iter()
+        .find(|prefix| prefix.clone() as u8 == key[0])
+        .map_or_else(|| hex::encode(&key[..1]), |prefix| prefix.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{
+        module_instance_id_to_byte_prefix, IDatabaseTransactionOpsCore, IRawDatabaseExt,
+    };
+
+    use super::{diff_snapshots, rollback, MigrationChanges};
+    use crate::consensus::db::DbKeyPrefix;
+    use crate::snapshot::DbSnapshot;
+
+    #[tokio::test]
+    async fn test_diff_and_rollback() {
+        let db = MemDatabase::new().into_database();
+        let module_key = [module_instance_id_to_byte_prefix(3), vec![0x01]].concat();
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_insert_bytes(&[DbKeyPrefix::AcceptedItem as u8, 0x01], &[0x01])
+            .await
+            .unwrap();
+        dbtx.raw_insert_bytes(&[DbKeyPrefix::InviteUses as u8, 0x01], &[0x01])
+            .await
+            .unwrap();
+        dbtx.commit_tx().await;
+
+        let before = DbSnapshot::take(&db).await.unwrap();
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_insert_bytes(&[DbKeyPrefix::InviteUses as u8, 0x01], &[0x02])
+            .await
+            .unwrap();
+        dbtx.raw_remove_entry(&[DbKeyPrefix::AcceptedItem as u8, 0x01])
+            .await
+            .unwrap();
+        dbtx.raw_insert_bytes(&module_key, &[0x01]).await.unwrap();
+        dbtx.commit_tx().await;
+
+        let changes = diff_snapshots(&before, &DbSnapshot::take(&db).await.unwrap());
+
+        assert_eq!(
+            changes.get("AcceptedItem"),
+            Some(&MigrationChanges {
+                added: 0,
+                changed: 0,
+                removed: 1
+            })
+        );
+        assert_eq!(changes.get("InviteUses").map(|c| c.changed), Some(1));
+        assert_eq!(changes.get("Module 3").map(|c| c.added), Some(1));
+
+        rollback(&db, before.clone()).await.unwrap();
+
+        assert_eq!(DbSnapshot::take(&db).await.unwrap().entries, before.entries);
+    }
+}
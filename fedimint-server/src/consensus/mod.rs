@@ -1,43 +1,100 @@
 #![allow(clippy::let_unit_value)]
 
 pub mod api;
+pub mod checkpoint;
 pub mod db;
+pub mod db_maintenance;
+pub mod db_verify;
 pub mod debug_fmt;
+pub mod dedup;
 pub mod engine;
+pub mod federation_meta;
+pub mod invite;
+pub mod mempool;
+pub mod migration;
+pub mod parallel;
+pub mod profiling;
+pub mod proposals;
+pub mod pruning;
+pub mod replay;
+pub mod replica;
+pub mod state_hash;
+pub mod tls_rotation;
 pub mod transaction;
+pub mod upgrade;
 
 use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::bail;
-use async_channel::Sender;
-use db::{get_global_database_migrations, GLOBAL_DATABASE_VERSION};
+use checkpoint::{fast_sync, LocalModulePrefixes};
+use db::{get_global_database_migrations, PeerEndpointsPrefix, GLOBAL_DATABASE_VERSION};
 use fedimint_api_client::api::DynGlobalApi;
 use fedimint_core::config::ServerModuleInitRegistry;
 use fedimint_core::core::{ModuleInstanceId, ModuleKind};
-use fedimint_core::db::{apply_migrations, apply_migrations_server, Database};
+use fedimint_core::db::{
+    apply_migrations, apply_migrations_server, Database, IDatabaseTransactionOpsCoreTyped,
+};
 use fedimint_core::envs::is_running_in_test_env;
 use fedimint_core::epoch::ConsensusItem;
-use fedimint_core::module::registry::ModuleRegistry;
+use fedimint_core::module::registry::{ModuleRegistry, ServerModuleRegistry};
 use fedimint_core::server::DynServerModule;
 use fedimint_core::task::TaskGroup;
-use fedimint_core::NumPeers;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{runtime, NumPeers, PeerId};
 use fedimint_logging::{LOG_CONSENSUS, LOG_CORE};
+use futures::StreamExt;
 use jsonrpsee::server::ServerHandle;
+use serde::{Deserialize, Serialize};
 use tokio::sync::watch;
 use tracing::info;
 use tracing::log::warn;
 
-use crate::atomic_broadcast::Keychain;
+use crate::alerts::spawn_alert_tasks;
+use crate::atomic_broadcast::{Keychain, Message};
+use crate::backup::spawn_db_backup_task;
 use crate::config::{ServerConfig, ServerConfigLocal};
 use crate::consensus::api::ConsensusApi;
 use crate::consensus::engine::ConsensusEngine;
+use crate::consensus::mempool::MempoolTracker;
+use crate::consensus::profiling::SessionProfiler;
+use crate::consensus::proposals::ModuleProposalQueue;
 use crate::net;
 use crate::net::api::RpcHandlerCtx;
+use crate::net::peers::PeerConnectorLayer;
+
+/// Queue of consensus items submitted via the API or by our modules that have
+/// not been proposed to our peers yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubmissionQueueConfig {
+    /// How many items can be stored in memory
+    pub buffer: usize,
+    /// What happens to a submitted transaction if the queue is full
+    pub overflow: SubmissionOverflowPolicy,
+}
+
+impl Default for SubmissionQueueConfig {
+    fn default() -> Self {
+        SubmissionQueueConfig {
+            buffer: 1000,
+            overflow: SubmissionOverflowPolicy::Reject,
+        }
+    }
+}
 
-/// How many txs can be stored in memory before blocking the API
-const TRANSACTION_BUFFER: usize = 1000;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionOverflowPolicy {
+    /// The API request blocks until there is space in the queue
+    Backpressure,
+    /// The API request fails with [`ApiError::mempool_full`], so the client
+    /// can retry later
+    ///
+    /// [`ApiError::mempool_full`]: fedimint_core::module::ApiError::mempool_full
+    Reject,
+}
 
 pub async fn run(
     cfg: ServerConfig,
@@ -45,56 +102,45 @@ pub async fn run(
     module_init_registry: ServerModuleInitRegistry,
     task_group: &TaskGroup,
 ) -> anyhow::Result<()> {
-    cfg.validate_config(&cfg.local.identity, &module_init_registry)?;
-
-    apply_migrations_server(
-        &db,
-        "fedimint-server".to_string(),
-        GLOBAL_DATABASE_VERSION,
-        get_global_database_migrations(),
-    )
-    .await?;
+    run_with_connector_layer(cfg, db, module_init_registry, task_group, None).await
+}
 
-    let mut modules = BTreeMap::new();
+/// Runs the consensus like [`run`], but opens the connections to our peers
+/// through the connector returned by `connector_layer`
+pub async fn run_with_connector_layer(
+    cfg: ServerConfig,
+    db: Database,
+    module_init_registry: ServerModuleInitRegistry,
+    task_group: &TaskGroup,
+    connector_layer: Option<PeerConnectorLayer<Message>>,
+) -> anyhow::Result<()> {
+    cfg.validate_config(&cfg.local.identity, &module_init_registry)?;
 
-    for (module_id, module_cfg) in &cfg.consensus.modules {
-        match module_init_registry.get(&module_cfg.kind) {
-            Some(module_init) => {
-                info!(target: LOG_CORE, "Initialise module {module_id}");
+    apply_consensus_migrations(&cfg, &db, &module_init_registry).await?;
 
-                apply_migrations(
-                    &db,
-                    module_init.module_kind().to_string(),
-                    module_init.database_version(),
-                    module_init.get_database_migrations(),
-                    Some(*module_id),
-                )
-                .await?;
+    let local_module_prefixes = LocalModulePrefixes::new(&cfg, &module_init_registry);
 
-                let module = module_init
-                    .init(
-                        NumPeers::from(cfg.consensus.api_endpoints.len()),
-                        cfg.get_module_config(*module_id)?,
-                        db.with_prefix_module_id(*module_id),
-                        task_group,
-                        cfg.local.identity,
-                    )
-                    .await?;
+    // The modules may read their state from the database on initialization, so
+    // we have to replace it before
+    if cfg.consensus.broadcast_public_keys.len() > 1 {
+        let decoders = module_init_registry.decoders_strict(cfg.iter_module_instances())?;
 
-                modules.insert(*module_id, (module_cfg.kind.clone(), module));
-            }
-            None => bail!("Detected configuration for unsupported module id: {module_id}"),
-        };
+        fast_sync(&cfg, &db, &decoders, &local_module_prefixes).await?;
     }
 
-    let module_registry = ModuleRegistry::from(modules);
+    let module_registry = init_modules(&cfg, &db, &module_init_registry, task_group).await?;
 
     let client_cfg = cfg.consensus.to_client_config(&module_init_registry)?;
 
-    let (submission_sender, submission_receiver) = async_channel::bounded(TRANSACTION_BUFFER);
+    let mempool = MempoolTracker::default();
+    let session_profiler = SessionProfiler::default();
+    let (submission_sender, submission_receiver) =
+        async_channel::bounded(cfg.local.submission_queue.buffer.max(1));
     let (shutdown_sender, shutdown_receiver) = watch::channel(None);
+    let (upgrade_sender, upgrade_receiver) = watch::channel(None);
     let connection_status_channels = Default::default();
     let last_ci_by_peer = Default::default();
+    let state_divergence_by_peer = Default::default();
 
     let consensus_api = ConsensusApi {
         cfg: cfg.clone(),
@@ -102,21 +148,66 @@ pub async fn run(
         modules: module_registry.clone(),
         client_cfg: client_cfg.clone(),
         submission_sender: submission_sender.clone(),
+        recent_transactions: Default::default(),
+        mempool: mempool.clone(),
+        session_profiler: session_profiler.clone(),
         shutdown_sender,
+        upgrade_receiver: upgrade_receiver.clone(),
         supported_api_versions: ServerConfig::supported_api_versions_summary(
             &cfg.consensus.modules,
             &module_init_registry,
         ),
         last_ci_by_peer: Arc::clone(&last_ci_by_peer),
+        state_divergence_by_peer: Arc::clone(&state_divergence_by_peer),
         connection_status_channels: Arc::clone(&connection_status_channels),
+        task_group: task_group.clone(),
     };
 
     info!(target: LOG_CONSENSUS, "Starting Consensus Api");
 
     let api_handler = start_consensus_api(&cfg.local, consensus_api).await;
 
+    if let Some(backup_config) = cfg.local.db_backup_schedule.clone() {
+        spawn_db_backup_task(
+            task_group,
+            db.clone(),
+            backup_config,
+            cfg.private.api_auth.0.clone(),
+        );
+    }
+
+    if let Some(alert_config) = cfg.local.alerts.clone() {
+        spawn_alert_tasks(
+            task_group,
+            alert_config,
+            cfg.local.identity,
+            db.clone(),
+            Arc::clone(&connection_status_channels),
+        );
+    }
+
     info!(target: LOG_CONSENSUS, "Starting Submission of Module CI proposals");
 
+    let module_proposals = ModuleProposalQueue::new(
+        cfg.consensus
+            .modules
+            .iter()
+            .map(|(module_id, module_cfg)| {
+                let config = cfg
+                    .local
+                    .module_proposals
+                    .get(&module_cfg.kind)
+                    .copied()
+                    .unwrap_or_default();
+
+                (*module_id, config)
+            })
+            .collect(),
+        mempool.clone(),
+    );
+
+    module_proposals.spawn_dispatcher(task_group, submission_sender.clone());
+
     for (module_id, kind, module) in module_registry.iter_modules() {
         submit_module_ci_proposals(
             task_group,
@@ -124,32 +215,48 @@ pub async fn run(
             module_id,
             kind.clone(),
             module.clone(),
-            submission_sender.clone(),
+            module_proposals.clone(),
         )
         .await;
     }
 
     info!(target: LOG_CONSENSUS, "Starting Consensus Engine");
 
+    let federation_api = DynGlobalApi::from_endpoints(api_endpoints(&cfg, &db).await);
+
     ConsensusEngine {
         db,
-        keychain: Keychain::new(&cfg),
-        federation_api: DynGlobalApi::from_config(&client_cfg),
+        keychain: Keychain::new(&cfg)?,
+        federation_api,
         self_id_str: cfg.local.identity.to_string(),
         peer_id_str: (0..cfg.consensus.api_endpoints.len())
             .map(|x| x.to_string())
             .collect(),
         cfg: cfg.clone(),
         connection_status_channels,
+        submission_sender,
         submission_receiver,
+        mempool,
+        session_profiler,
         shutdown_receiver,
+        upgrade_sender,
         last_ci_by_peer,
+        state_divergence_by_peer,
         modules: module_registry,
+        local_module_prefixes,
+        connector_layer,
         task_group: task_group.clone(),
     }
     .run()
     .await?;
 
+    // Keep serving the awaiting upgrade status until the operator stops us
+    if upgrade_receiver.borrow().is_some() {
+        info!(target: LOG_CONSENSUS, "Awaiting upgrade, shut down the guardian to install it");
+
+        task_group.make_handle().make_shutdown_rx().await.await;
+    }
+
     api_handler
         .stop()
         .expect("Consensus api should still be running");
@@ -159,6 +266,95 @@ pub async fn run(
     Ok(())
 }
 
+/// Applies the migrations of the global database and of every configured
+/// module
+pub(crate) async fn apply_consensus_migrations(
+    cfg: &ServerConfig,
+    db: &Database,
+    module_init_registry: &ServerModuleInitRegistry,
+) -> anyhow::Result<()> {
+    apply_migrations_server(
+        db,
+        "fedimint-server".to_string(),
+        GLOBAL_DATABASE_VERSION,
+        get_global_database_migrations(),
+    )
+    .await?;
+
+    for (module_id, module_cfg) in &cfg.consensus.modules {
+        match module_init_registry.get(&module_cfg.kind) {
+            Some(module_init) => {
+                apply_migrations(
+                    db,
+                    module_init.module_kind().to_string(),
+                    module_init.database_version(),
+                    module_init.get_database_migrations(),
+                    Some(*module_id),
+                )
+                .await?;
+            }
+            None => bail!("Detected configuration for unsupported module id: {module_id}"),
+        };
+    }
+
+    Ok(())
+}
+
+/// Initializes the configured modules on top of their state in the database
+pub(crate) async fn init_modules(
+    cfg: &ServerConfig,
+    db: &Database,
+    module_init_registry: &ServerModuleInitRegistry,
+    task_group: &TaskGroup,
+) -> anyhow::Result<ServerModuleRegistry> {
+    let mut modules = BTreeMap::new();
+
+    for (module_id, module_cfg) in &cfg.consensus.modules {
+        match module_init_registry.get(&module_cfg.kind) {
+            Some(module_init) => {
+                info!(target: LOG_CORE, "Initialise module {module_id}");
+
+                let module = module_init
+                    .init(
+                        NumPeers::from(cfg.consensus.api_endpoints.len()),
+                        cfg.get_module_config(*module_id)?,
+                        db.with_prefix_module_id(*module_id),
+                        task_group,
+                        cfg.local.identity,
+                    )
+                    .await?;
+
+                modules.insert(*module_id, (module_cfg.kind.clone(), module));
+            }
+            None => bail!("Detected configuration for unsupported module id: {module_id}"),
+        };
+    }
+
+    Ok(ModuleRegistry::from(modules))
+}
+
+/// Returns the api endpoints of all peers, taking into account peers that
+/// announced a new api address via consensus after the config was generated
+pub(crate) async fn api_endpoints(cfg: &ServerConfig, db: &Database) -> Vec<(PeerId, SafeUrl)> {
+    let overrides = db
+        .begin_transaction_nc()
+        .await
+        .find_by_prefix(&PeerEndpointsPrefix)
+        .await
+        .filter_map(|(key, endpoints)| async move { endpoints.api_url.map(|url| (key.0, url)) })
+        .collect::<BTreeMap<_, _>>()
+        .await;
+
+    cfg.consensus
+        .api_endpoints
+        .iter()
+        .map(|(peer, endpoint)| {
+            let url = overrides.get(peer).unwrap_or(&endpoint.url).clone();
+            (*peer, url)
+        })
+        .collect()
+}
+
 async fn start_consensus_api(cfg: &ServerConfigLocal, api: ConsensusApi) -> ServerHandle {
     let mut rpc_module = RpcHandlerCtx::new_module(api.clone());
 
@@ -179,19 +375,19 @@ async fn submit_module_ci_proposals(
     module_id: ModuleInstanceId,
     kind: ModuleKind,
     module: DynServerModule,
-    submission_sender: Sender<ConsensusItem>,
+    module_proposals: ModuleProposalQueue,
 ) {
-    let mut interval = tokio::time::interval(if is_running_in_test_env() {
+    let interval = if is_running_in_test_env() {
         Duration::from_millis(100)
     } else {
         Duration::from_secs(1)
-    });
+    };
 
     task_group.spawn(
         "submit_module_ci_proposals_{module_id}",
         move |task_handle| async move {
             while !task_handle.is_shutting_down() {
-                let module_consensus_items = tokio::time::timeout(
+                let module_consensus_items = runtime::timeout(
                     CONSENSUS_PROPOSAL_TIMEOUT,
                     module.consensus_proposal(
                         &mut db
@@ -207,10 +403,7 @@ async fn submit_module_ci_proposals(
                 match module_consensus_items {
                     Ok(items) => {
                         for item in items {
-                            submission_sender
-                                .send(ConsensusItem::Module(item))
-                                .await
-                                .ok();
+                            module_proposals.submit(module_id, &kind, ConsensusItem::Module(item));
                         }
                     }
                     Err(..) => {
@@ -221,7 +414,7 @@ async fn submit_module_ci_proposals(
                     }
                 }
 
-                interval.tick().await;
+                runtime::sleep(interval).await;
             }
         },
     );
@@ -0,0 +1,128 @@
+//! Grouping of ordered consensus items for concurrent processing
+//!
+//! A transaction only reads and writes the state of the modules its inputs and
+//! outputs belong to, as well as global keys derived from its own id.
+//! Consecutive transactions with disjoint sets of modules and distinct ids can
+//! therefore not observe each other's writes, so processing them concurrently
+//! yields the same result as processing them in the order they were agreed on.
+
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::epoch::ConsensusItem;
+use fedimint_core::TransactionId;
+
+/// Modules and global keys a consensus item may touch while being processed.
+/// Only transactions have a footprint, any other item has to be processed on
+/// its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionFootprint {
+    pub txid: TransactionId,
+    pub modules: BTreeSet<ModuleInstanceId>,
+}
+
+impl TransactionFootprint {
+    pub fn from_item(item: &ConsensusItem) -> Option<Self> {
+        match item {
+            ConsensusItem::Transaction(transaction) => Some(TransactionFootprint {
+                txid: transaction.tx_hash(),
+                modules: transaction
+                    .inputs
+                    .iter()
+                    .map(|input| input.module_instance_id())
+                    .chain(
+                        transaction
+                            .outputs
+                            .iter()
+                            .map(|output| output.module_instance_id()),
+                    )
+                    .collect(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Splits the items of a batch into consecutive ranges whose items can be
+/// processed concurrently, preserving the order of the items
+pub fn concurrent_groups(footprints: &[Option<TransactionFootprint>]) -> Vec<Range<usize>> {
+    let mut groups = vec![];
+    let mut start = 0;
+    let mut modules = BTreeSet::new();
+    let mut txids = BTreeSet::new();
+
+    for (index, footprint) in footprints.iter().enumerate() {
+        let joins_group = footprint.as_ref().is_some_and(|footprint| {
+            footprint.modules.is_disjoint(&modules) && !txids.contains(&footprint.txid)
+        });
+
+        if !joins_group && start < index {
+            groups.push(start..index);
+            start = index;
+            modules.clear();
+            txids.clear();
+        }
+
+        match footprint {
+            Some(footprint) => {
+                modules.extend(footprint.modules.iter().copied());
+                txids.insert(footprint.txid);
+            }
+            None => {
+                groups.push(index..index + 1);
+                start = index + 1;
+            }
+        }
+    }
+
+    if start < footprints.len() {
+        groups.push(start..footprints.len());
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_hashes::Hash;
+    use fedimint_core::TransactionId;
+
+    use super::{concurrent_groups, TransactionFootprint};
+
+    fn tx(id: u8, modules: &[u16]) -> Option<TransactionFootprint> {
+        Some(TransactionFootprint {
+            txid: TransactionId::from_byte_array([id; 32]),
+            modules: modules.iter().copied().collect(),
+        })
+    }
+
+    #[test]
+    fn test_concurrent_groups() {
+        assert_eq!(concurrent_groups(&[]), vec![]);
+
+        // disjoint transactions form a single group
+        assert_eq!(
+            concurrent_groups(&[tx(0, &[0]), tx(1, &[1, 2]), tx(2, &[3])]),
+            vec![0..3]
+        );
+
+        // a transaction sharing a module with the group starts a new group
+        assert_eq!(
+            concurrent_groups(&[tx(0, &[0]), tx(1, &[1]), tx(2, &[1, 2]), tx(3, &[0])]),
+            vec![0..2, 2..4]
+        );
+
+        // a duplicate transaction starts a new group
+        assert_eq!(
+            concurrent_groups(&[tx(0, &[]), tx(0, &[])]),
+            vec![0..1, 1..2]
+        );
+
+        // any other item is processed on its own
+        assert_eq!(
+            concurrent_groups(&[tx(0, &[0]), None, None, tx(1, &[0]), tx(2, &[1])]),
+            vec![0..1, 1..2, 2..3, 3..5]
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Time spent in the stages of the consensus engine per session
+//!
+//! The durations of the stages are summed up over a session and reported as
+//! histograms as well as via the `session_timing` endpoint for the most recent
+//! sessions, so slow sessions can be investigated on a running guardian.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use fedimint_core::admin_client::SessionTiming;
+
+use crate::metrics::CONSENSUS_SESSION_STAGE_DURATION_SECONDS;
+
+/// Number of completed sessions we keep the timing of
+const SESSION_TIMING_HISTORY: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionStage {
+    /// Building the batches of items we propose to the atomic broadcast
+    Proposal,
+    /// Validating and applying ordered consensus items
+    Processing,
+    /// Auditing and committing the database transactions of accepted items
+    /// and completed sessions
+    Commit,
+}
+
+#[derive(Debug, Default)]
+struct SessionProfilerState {
+    proposal: Duration,
+    processing: Duration,
+    commit: Duration,
+    history: VecDeque<SessionTiming>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SessionProfiler(Arc<Mutex<SessionProfilerState>>);
+
+impl SessionProfiler {
+    pub fn record(&self, stage: SessionStage, duration: Duration) {
+        let mut state = self.0.lock().expect("Lock poisoned");
+
+        match stage {
+            SessionStage::Proposal => state.proposal += duration,
+            SessionStage::Processing => state.processing += duration,
+            SessionStage::Commit => state.commit += duration,
+        }
+    }
+
+    /// Records the timing of a completed session that took `total` and resets
+    /// the stage durations for the next session. The time not spent on
+    /// processing or committing items is attributed to the broadcast, which
+    /// includes waiting for our peers.
+    pub fn complete_session(&self, session_index: u64, total: Duration) {
+        let mut state = self.0.lock().expect("Lock poisoned");
+
+        let broadcast = total.saturating_sub(state.processing + state.commit);
+
+        let timing = SessionTiming {
+            session_index,
+            total_ms: total.as_millis() as u64,
+            proposal_ms: state.proposal.as_millis() as u64,
+            broadcast_ms: broadcast.as_millis() as u64,
+            processing_ms: state.processing.as_millis() as u64,
+            commit_ms: state.commit.as_millis() as u64,
+        };
+
+        for (stage, duration) in [
+            ("total", total),
+            ("proposal", state.proposal),
+            ("broadcast", broadcast),
+            ("processing", state.processing),
+            ("commit", state.commit),
+        ] {
+            CONSENSUS_SESSION_STAGE_DURATION_SECONDS
+                .with_label_values(&[stage])
+                .observe(duration.as_secs_f64());
+        }
+
+        state.proposal = Duration::ZERO;
+        state.processing = Duration::ZERO;
+        state.commit = Duration::ZERO;
+
+        state.history.push_back(timing);
+
+        if SESSION_TIMING_HISTORY < state.history.len() {
+            state.history.pop_front();
+        }
+    }
+
+    /// Timing of the most recent sessions in ascending order
+    pub fn recent_sessions(&self) -> Vec<SessionTiming> {
+        self.0
+            .lock()
+            .expect("Lock poisoned")
+            .history
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{SessionProfiler, SessionStage, SESSION_TIMING_HISTORY};
+
+    #[test]
+    fn test_session_profiler() {
+        let profiler = SessionProfiler::default();
+
+        profiler.record(SessionStage::Proposal, Duration::from_millis(5));
+        profiler.record(SessionStage::Processing, Duration::from_millis(10));
+        profiler.record(SessionStage::Processing, Duration::from_millis(20));
+        profiler.record(SessionStage::Commit, Duration::from_millis(30));
+
+        profiler.complete_session(0, Duration::from_millis(100));
+
+        let timing = profiler.recent_sessions()[0].clone();
+
+        assert_eq!(timing.proposal_ms, 5);
+        assert_eq!(timing.processing_ms, 30);
+        assert_eq!(timing.commit_ms, 30);
+        assert_eq!(timing.broadcast_ms, 40);
+
+        // the stages are reset for the next session
+        profiler.complete_session(1, Duration::from_millis(100));
+
+        assert_eq!(profiler.recent_sessions()[1].processing_ms, 0);
+        assert_eq!(profiler.recent_sessions()[1].broadcast_ms, 100);
+
+        for session_index in 2..200 {
+            profiler.complete_session(session_index, Duration::ZERO);
+        }
+
+        let recent = profiler.recent_sessions();
+
+        assert_eq!(recent.len(), SESSION_TIMING_HISTORY);
+        assert_eq!(recent.last().unwrap().session_index, 199);
+    }
+}
@@ -0,0 +1,422 @@
+//! Scheduling of the consensus items proposed by our modules
+//!
+//! Our modules are polled for consensus items every second. Instead of sending
+//! them straight into the submission queue, where a chatty module could delay
+//! the items of all other modules, every module has its own bounded queue. The
+//! items are taken out of these queues by weighted round robin among the
+//! modules that have items waiting and did not exceed their rate limit.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_channel::Sender;
+use fedimint_core::core::{ModuleInstanceId, ModuleKind};
+use fedimint_core::epoch::ConsensusItem;
+use fedimint_core::runtime;
+use fedimint_core::task::TaskGroup;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::consensus::mempool::MempoolTracker;
+use crate::metrics::MODULE_CI_PROPOSALS_DROPPED_COUNT;
+
+/// How long the dispatcher waits for new items before checking whether a rate
+/// limited module may submit again
+const DISPATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Limits on the consensus items proposed by the modules of a kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModuleProposalConfig {
+    /// Maximum number of items submitted per second, 0 disables the limit
+    pub max_items_per_sec: u32,
+    /// Share of the submissions the module gets relative to the other modules
+    /// while several of them have items waiting
+    pub weight: u32,
+    /// Number of items waiting to be submitted per module
+    pub buffer: usize,
+    /// Which item is dropped if the buffer is full
+    pub overflow: ProposalOverflowPolicy,
+}
+
+impl Default for ModuleProposalConfig {
+    fn default() -> Self {
+        ModuleProposalConfig {
+            max_items_per_sec: 0,
+            weight: 1,
+            buffer: 100,
+            overflow: ProposalOverflowPolicy::DropOldest,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalOverflowPolicy {
+    /// Make room for the new item, since modules usually propose the most
+    /// recent state, like the latest block height
+    DropOldest,
+    /// Keep the waiting items and discard the new one
+    DropNewest,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProposalOutcome {
+    Queued,
+    /// An identical item is already waiting
+    Duplicate,
+    /// The item was queued and the contained oldest item dropped
+    ReplacedOldest(ConsensusItem),
+    DroppedNewest,
+}
+
+#[derive(Debug)]
+struct ModuleQueue {
+    config: ModuleProposalConfig,
+    items: VecDeque<ConsensusItem>,
+    tokens: f64,
+    refilled_at: Instant,
+    current_weight: i64,
+}
+
+impl ModuleQueue {
+    fn refill(&mut self, now: Instant) {
+        let rate = f64::from(self.config.max_items_per_sec);
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.refilled_at = now;
+    }
+
+    fn is_ready(&self) -> bool {
+        !self.items.is_empty() && (self.config.max_items_per_sec == 0 || 1.0 <= self.tokens)
+    }
+
+    fn weight(&self) -> i64 {
+        i64::from(self.config.weight.max(1))
+    }
+}
+
+#[derive(Debug)]
+pub struct ProposalScheduler {
+    queues: BTreeMap<ModuleInstanceId, ModuleQueue>,
+}
+
+impl ProposalScheduler {
+    pub fn new(configs: BTreeMap<ModuleInstanceId, ModuleProposalConfig>, now: Instant) -> Self {
+        ProposalScheduler {
+            queues: configs
+                .into_iter()
+                .map(|(module_id, config)| {
+                    let queue = ModuleQueue {
+                        config,
+                        items: VecDeque::new(),
+                        tokens: f64::from(config.max_items_per_sec),
+                        refilled_at: now,
+                        current_weight: 0,
+                    };
+
+                    (module_id, queue)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn push(&mut self, module_id: ModuleInstanceId, item: ConsensusItem) -> ProposalOutcome {
+        let queue = self.queues.entry(module_id).or_insert_with(|| ModuleQueue {
+            config: ModuleProposalConfig::default(),
+            items: VecDeque::new(),
+            tokens: 0.0,
+            refilled_at: Instant::now(),
+            current_weight: 0,
+        });
+
+        if queue.items.contains(&item) {
+            return ProposalOutcome::Duplicate;
+        }
+
+        if queue.items.len() < queue.config.buffer.max(1) {
+            queue.items.push_back(item);
+
+            return ProposalOutcome::Queued;
+        }
+
+        match queue.config.overflow {
+            ProposalOverflowPolicy::DropOldest => {
+                let dropped = queue.items.pop_front().expect("The queue is full");
+
+                queue.items.push_back(item);
+
+                ProposalOutcome::ReplacedOldest(dropped)
+            }
+            ProposalOverflowPolicy::DropNewest => ProposalOutcome::DroppedNewest,
+        }
+    }
+
+    /// Takes the next item by smooth weighted round robin among the modules
+    /// that are not rate limited
+    pub fn pop(&mut self, now: Instant) -> Option<ConsensusItem> {
+        for queue in self.queues.values_mut() {
+            queue.refill(now);
+        }
+
+        let total_weight = self
+            .queues
+            .values()
+            .filter(|queue| queue.is_ready())
+            .map(ModuleQueue::weight)
+            .sum::<i64>();
+
+        for queue in self.queues.values_mut().filter(|queue| queue.is_ready()) {
+            queue.current_weight += queue.weight();
+        }
+
+        let queue = self
+            .queues
+            .values_mut()
+            .filter(|queue| queue.is_ready())
+            .reduce(|best, queue| {
+                if best.current_weight < queue.current_weight {
+                    queue
+                } else {
+                    best
+                }
+            })?;
+
+        queue.current_weight -= total_weight;
+
+        if queue.config.max_items_per_sec != 0 {
+            queue.tokens -= 1.0;
+        }
+
+        queue.items.pop_front()
+    }
+}
+
+/// Queue between the tasks polling our modules for consensus items and the
+/// submission queue
+#[derive(Debug, Clone)]
+pub struct ModuleProposalQueue {
+    scheduler: Arc<Mutex<ProposalScheduler>>,
+    notify: Arc<Notify>,
+    mempool: MempoolTracker,
+}
+
+impl ModuleProposalQueue {
+    pub fn new(
+        configs: BTreeMap<ModuleInstanceId, ModuleProposalConfig>,
+        mempool: MempoolTracker,
+    ) -> Self {
+        ModuleProposalQueue {
+            scheduler: Arc::new(Mutex::new(ProposalScheduler::new(configs, Instant::now()))),
+            notify: Arc::new(Notify::new()),
+            mempool,
+        }
+    }
+
+    pub fn submit(&self, module_id: ModuleInstanceId, kind: &ModuleKind, item: ConsensusItem) {
+        // The item has to be tracked before the dispatcher can take it out again
+        self.mempool.insert(&item);
+
+        let outcome = self
+            .scheduler
+            .lock()
+            .expect("Lock poisoned")
+            .push(module_id, item.clone());
+
+        match outcome {
+            ProposalOutcome::Queued => {
+                self.notify.notify_one();
+            }
+            ProposalOutcome::Duplicate => {
+                self.mempool.remove(&item);
+            }
+            ProposalOutcome::ReplacedOldest(dropped) => {
+                self.mempool.remove(&dropped);
+
+                MODULE_CI_PROPOSALS_DROPPED_COUNT
+                    .with_label_values(&[kind.as_str()])
+                    .inc();
+
+                self.notify.notify_one();
+            }
+            ProposalOutcome::DroppedNewest => {
+                self.mempool.remove(&item);
+
+                MODULE_CI_PROPOSALS_DROPPED_COUNT
+                    .with_label_values(&[kind.as_str()])
+                    .inc();
+            }
+        }
+    }
+
+    /// Forwards the scheduled items into the submission queue, waiting for
+    /// space in it
+    pub fn spawn_dispatcher(
+        &self,
+        task_group: &TaskGroup,
+        submission_sender: Sender<ConsensusItem>,
+    ) {
+        let queue = self.clone();
+
+        task_group.spawn(
+            "dispatch module CI proposals",
+            move |task_handle| async move {
+                while !task_handle.is_shutting_down() {
+                    let item = queue
+                        .scheduler
+                        .lock()
+                        .expect("Lock poisoned")
+                        .pop(Instant::now());
+
+                    match item {
+                        Some(item) => {
+                            if let Err(e) = submission_sender.send(item).await {
+                                queue.mempool.remove(&e.0);
+                            }
+                        }
+                        None => {
+                            runtime::timeout(DISPATCH_INTERVAL, queue.notify.notified())
+                                .await
+                                .ok();
+                        }
+                    }
+                }
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use fedimint_core::epoch::{ConsensusItem, PeerEndpoints};
+    use fedimint_core::util::SafeUrl;
+    use tokio::time::Instant;
+
+    use super::{ModuleProposalConfig, ProposalOutcome, ProposalOverflowPolicy, ProposalScheduler};
+
+    fn item(port: u16) -> ConsensusItem {
+        ConsensusItem::PeerEndpoints(PeerEndpoints {
+            p2p_url: Some(SafeUrl::parse(&format!("fedimint://127.0.0.1:{port}")).unwrap()),
+            api_url: None,
+        })
+    }
+
+    #[test]
+    fn test_weighted_round_robin() {
+        let now = Instant::now();
+
+        let mut scheduler = ProposalScheduler::new(
+            BTreeMap::from([
+                (
+                    0,
+                    ModuleProposalConfig {
+                        weight: 2,
+                        ..Default::default()
+                    },
+                ),
+                (1, ModuleProposalConfig::default()),
+            ]),
+            now,
+        );
+
+        for port in 0..6 {
+            scheduler.push(0, item(port));
+        }
+
+        scheduler.push(1, item(100));
+        scheduler.push(1, item(101));
+
+        let popped = (0..6)
+            .map(|_| scheduler.pop(now).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            popped,
+            vec![item(0), item(100), item(1), item(2), item(101), item(3)]
+        );
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let now = Instant::now();
+
+        let mut scheduler = ProposalScheduler::new(
+            BTreeMap::from([
+                (
+                    0,
+                    ModuleProposalConfig {
+                        max_items_per_sec: 2,
+                        ..Default::default()
+                    },
+                ),
+                (1, ModuleProposalConfig::default()),
+            ]),
+            now,
+        );
+
+        for port in 0..4 {
+            scheduler.push(0, item(port));
+        }
+
+        assert_eq!(scheduler.pop(now), Some(item(0)));
+        assert_eq!(scheduler.pop(now), Some(item(1)));
+        assert_eq!(scheduler.pop(now), None);
+
+        // a rate limited module does not block the others
+        scheduler.push(1, item(100));
+        assert_eq!(scheduler.pop(now), Some(item(100)));
+
+        let later = now + Duration::from_millis(500);
+
+        assert_eq!(scheduler.pop(later), Some(item(2)));
+        assert_eq!(scheduler.pop(later), None);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let now = Instant::now();
+
+        let config = ModuleProposalConfig {
+            buffer: 2,
+            ..Default::default()
+        };
+
+        let mut scheduler = ProposalScheduler::new(
+            BTreeMap::from([
+                (0, config),
+                (
+                    1,
+                    ModuleProposalConfig {
+                        overflow: ProposalOverflowPolicy::DropNewest,
+                        ..config
+                    },
+                ),
+            ]),
+            now,
+        );
+
+        assert_eq!(scheduler.push(0, item(0)), ProposalOutcome::Queued);
+        assert_eq!(scheduler.push(0, item(0)), ProposalOutcome::Duplicate);
+        assert_eq!(scheduler.push(0, item(1)), ProposalOutcome::Queued);
+        assert_eq!(
+            scheduler.push(0, item(2)),
+            ProposalOutcome::ReplacedOldest(item(0))
+        );
+
+        assert_eq!(scheduler.push(1, item(0)), ProposalOutcome::Queued);
+        assert_eq!(scheduler.push(1, item(1)), ProposalOutcome::Queued);
+        assert_eq!(scheduler.push(1, item(2)), ProposalOutcome::DroppedNewest);
+
+        assert_eq!(scheduler.pop(now), Some(item(1)));
+        assert_eq!(scheduler.pop(now), Some(item(0)));
+        assert_eq!(scheduler.pop(now), Some(item(2)));
+        assert_eq!(scheduler.pop(now), Some(item(1)));
+        assert_eq!(scheduler.pop(now), None);
+    }
+}
@@ -0,0 +1,123 @@
+//! Pruning of old session outcomes for guardians with bounded disk
+//!
+//! Guardians need the signed outcomes of past sessions only to serve them to
+//! clients and peers that are catching up. Once a threshold signed checkpoint
+//! exists, peers can fast-sync from it instead, so the outcomes of the sessions
+//! before the checkpoint can be deleted. Clients requesting a pruned session
+//! receive a distinct "gone" error and retry with the other guardians.
+//!
+//! If every guardian prunes, no guardian can serve the sessions before the
+//! pruning horizon anymore. Clients that recover by replaying the session
+//! history from the start and peers that are unable to fast-sync from a
+//! checkpoint then cannot catch up, so at least one guardian should keep the
+//! full history.
+
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_logging::LOG_CONSENSUS;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::consensus::db::{
+    SignedCheckpointHeaderKey, SignedCheckpointHeaderPrefix, SignedSessionOutcomeKey,
+    SignedSessionOutcomePrefix,
+};
+
+/// Number of session outcomes deleted per database transaction
+const PRUNING_BATCH_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SessionPruningPolicy {
+    /// Number of most recent session outcomes that are never pruned
+    pub keep_last: u64,
+}
+
+impl SessionPruningPolicy {
+    /// Returns the index of the oldest session outcome we have to keep. We
+    /// always keep the outcome of the session of our latest signed checkpoint
+    /// since peers fast-syncing from it need to download it.
+    pub fn pruning_horizon(&self, session_count: u64, signed_checkpoint: Option<u64>) -> u64 {
+        signed_checkpoint.map_or(0, |checkpoint| {
+            checkpoint.min(session_count.saturating_sub(self.keep_last))
+        })
+    }
+}
+
+/// Deletes all session outcomes before the pruning horizon as well as all but
+/// the latest signed checkpoint header
+pub async fn prune_sessions(db: &Database, policy: &SessionPruningPolicy, session_count: u64) {
+    let signed_checkpoints = db
+        .begin_transaction_nc()
+        .await
+        .find_by_prefix_sorted_descending(&SignedCheckpointHeaderPrefix)
+        .await
+        .map(|(key, _)| key.0)
+        .collect::<Vec<_>>()
+        .await;
+
+    let horizon = policy.pruning_horizon(session_count, signed_checkpoints.first().copied());
+
+    let mut pruned = 0;
+
+    loop {
+        let batch = db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&SignedSessionOutcomePrefix)
+            .await
+            .map(|(key, _)| key.0)
+            .take_while(|index| std::future::ready(*index < horizon))
+            .take(PRUNING_BATCH_SIZE)
+            .collect::<Vec<_>>()
+            .await;
+
+        if batch.is_empty() {
+            break;
+        }
+
+        let mut dbtx = db.begin_transaction().await;
+
+        for index in &batch {
+            dbtx.remove_entry(&SignedSessionOutcomeKey(*index)).await;
+        }
+
+        dbtx.commit_tx().await;
+
+        pruned += batch.len();
+    }
+
+    let mut dbtx = db.begin_transaction().await;
+
+    for session_index in signed_checkpoints.iter().skip(1) {
+        dbtx.remove_entry(&SignedCheckpointHeaderKey(*session_index))
+            .await;
+    }
+
+    dbtx.commit_tx().await;
+
+    if pruned != 0 {
+        info!(target: LOG_CONSENSUS, pruned, horizon, "Pruned session outcomes");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionPruningPolicy;
+
+    #[test]
+    fn test_pruning_horizon() {
+        let policy = SessionPruningPolicy { keep_last: 100 };
+
+        // We never prune without a signed checkpoint
+        assert_eq!(policy.pruning_horizon(1000, None), 0);
+
+        assert_eq!(policy.pruning_horizon(1000, Some(499)), 499);
+        assert_eq!(policy.pruning_horizon(1000, Some(999)), 900);
+        assert_eq!(policy.pruning_horizon(50, Some(9)), 0);
+
+        // The outcome of the checkpoint session is kept even with keep_last of zero
+        let policy = SessionPruningPolicy { keep_last: 0 };
+
+        assert_eq!(policy.pruning_horizon(1000, Some(999)), 999);
+    }
+}
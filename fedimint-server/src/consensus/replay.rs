@@ -0,0 +1,366 @@
+//! Offline replay of stored sessions for debugging state divergence
+//!
+//! Guardians that processed the same sessions must end up with the same
+//! consensus state. If their checkpoints stop being signed by a threshold the
+//! state of at least one of them diverged. To find the session that caused it
+//! a developer copies the databases of the guardians and replays the stored
+//! session outcomes of each of them into a scratch database, comparing the
+//! state hash after every session. The first session whose hashes differ
+//! between the guardians, or from a checkpoint the guardian created while
+//! running, contains the item that was processed differently.
+
+use anyhow::{bail, ensure, Context};
+use bitcoin_hashes::sha256;
+use fedimint_api_client::api::DynGlobalApi;
+use fedimint_core::config::ServerModuleInitRegistry;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCore, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::task::TaskGroup;
+use fedimint_logging::LOG_CONSENSUS;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::atomic_broadcast::Keychain;
+use crate::config::ServerConfig;
+use crate::consensus::checkpoint::{consensus_state_hash, LocalModulePrefixes};
+use crate::consensus::db::{
+    AcceptedItemPrefix, CheckpointChunkPrefix, CheckpointHeaderKey, OwnStateHashKey,
+    SignedCheckpointHeaderKey, SignedSessionOutcomeKey,
+};
+use crate::consensus::engine::{get_finished_session_count_static, ConsensusEngine};
+use crate::consensus::{apply_consensus_migrations, init_modules};
+
+/// Result of replaying a single session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayedSession {
+    pub session_index: u64,
+    /// Hash over the consensus state after the session was replayed
+    pub state_hash: sha256::Hash,
+    /// State hash the guardian computed for this session while running, if
+    /// it is still known
+    pub expected_state_hash: Option<sha256::Hash>,
+    /// Indices of the accepted items that were rejected on replay
+    pub rejected_items: Vec<u64>,
+}
+
+impl ReplayedSession {
+    pub fn is_diverged(&self) -> bool {
+        !self.rejected_items.is_empty()
+            || self
+                .expected_state_hash
+                .is_some_and(|expected| expected != self.state_hash)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayReport {
+    pub sessions: Vec<ReplayedSession>,
+    /// The first diverged session, after which the replay was stopped
+    pub divergence: Option<u64>,
+}
+
+/// Replays the session outcomes stored in `source_db` from `from_session` up
+/// to and including `to_session` into the empty `scratch_db`.
+///
+/// Unless we start at the first session the scratch database is initialized
+/// from the latest checkpoint of the source database, so `from_session` has
+/// to be the session following it. The source database is only read from.
+pub async fn replay_sessions(
+    cfg: &ServerConfig,
+    source_db: &Database,
+    scratch_db: &Database,
+    module_init_registry: &ServerModuleInitRegistry,
+    from_session: u64,
+    to_session: Option<u64>,
+    task_group: &TaskGroup,
+) -> anyhow::Result<ReplayReport> {
+    let decoders = module_init_registry.decoders_strict(cfg.iter_module_instances())?;
+
+    let source_db = source_db.with_decoders(decoders.clone());
+    let scratch_db = scratch_db.with_decoders(decoders);
+
+    let session_count =
+        get_finished_session_count_static(&mut source_db.begin_transaction_nc().await).await;
+
+    let to_session = to_session.unwrap_or(session_count.saturating_sub(1));
+
+    ensure!(
+        from_session <= to_session && to_session < session_count,
+        "The source database contains the sessions up to {session_count}"
+    );
+
+    ensure!(
+        scratch_db
+            .begin_transaction_nc()
+            .await
+            .raw_find_by_prefix(&[])
+            .await?
+            .next()
+            .await
+            .is_none(),
+        "The scratch database has to be empty"
+    );
+
+    let local_module_prefixes = LocalModulePrefixes::new(cfg, module_init_registry);
+
+    if from_session > 0 {
+        restore_checkpoint(
+            &source_db,
+            &scratch_db,
+            &local_module_prefixes,
+            from_session - 1,
+        )
+        .await?;
+    }
+
+    apply_consensus_migrations(cfg, &scratch_db, module_init_registry).await?;
+
+    let modules = init_modules(cfg, &scratch_db, module_init_registry, task_group).await?;
+
+    // The engine is only used to process items, so it is never connected to
+    // our peers or the submission queue
+    let (submission_sender, submission_receiver) = async_channel::bounded(1);
+    let (_, shutdown_receiver) = watch::channel(None);
+
+    let engine = ConsensusEngine {
+        modules,
+        local_module_prefixes: local_module_prefixes.clone(),
+        db: scratch_db.clone(),
+        keychain: Keychain::new(cfg)?,
+        federation_api: DynGlobalApi::from_endpoints(vec![]),
+        self_id_str: cfg.local.identity.to_string(),
+        peer_id_str: (0..cfg.consensus.api_endpoints.len())
+            .map(|x| x.to_string())
+            .collect(),
+        cfg: cfg.clone(),
+        submission_sender,
+        submission_receiver,
+        mempool: Default::default(),
+        session_profiler: Default::default(),
+        shutdown_receiver,
+        upgrade_sender: watch::channel(None).0,
+        last_ci_by_peer: Default::default(),
+        state_divergence_by_peer: Default::default(),
+        connection_status_channels: Default::default(),
+        connector_layer: None,
+        task_group: task_group.clone(),
+    };
+
+    let mut report = ReplayReport {
+        sessions: vec![],
+        divergence: None,
+    };
+
+    for session_index in from_session..=to_session {
+        let signed_session_outcome = source_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&SignedSessionOutcomeKey(session_index))
+            .await
+            .with_context(|| format!("Session {session_index} has been pruned"))?;
+
+        let mut rejected_items = vec![];
+        let mut item_index = 0;
+
+        for (index, accepted_item) in signed_session_outcome
+            .session_outcome
+            .items
+            .iter()
+            .enumerate()
+        {
+            let result = engine
+                .process_consensus_item(
+                    session_index,
+                    item_index,
+                    accepted_item.item.clone(),
+                    accepted_item.peer,
+                )
+                .await;
+
+            match result {
+                Ok(()) => item_index += 1,
+                Err(error) => {
+                    warn!(target: LOG_CONSENSUS, session_index, index, "Accepted item was rejected on replay: {error}");
+
+                    rejected_items.push(index as u64);
+                }
+            }
+        }
+
+        engine
+            .complete_session(session_index, signed_session_outcome)
+            .await;
+
+        let session = ReplayedSession {
+            session_index,
+            state_hash: consensus_state_hash(&scratch_db, &local_module_prefixes).await,
+            expected_state_hash: expected_state_hash(
+                &source_db,
+                &local_module_prefixes,
+                session_index,
+                session_count,
+            )
+            .await,
+            rejected_items,
+        };
+
+        info!(
+            target: LOG_CONSENSUS,
+            session_index,
+            state_hash = %session.state_hash,
+            "Replayed session"
+        );
+
+        let is_diverged = session.is_diverged();
+
+        report.sessions.push(session);
+
+        if is_diverged {
+            report.divergence = Some(session_index);
+
+            break;
+        }
+    }
+
+    Ok(report)
+}
+
+/// The state hash the guardian computed after the session while running, or
+/// the hash of its current state if the session is the last one and the next
+/// session has not accepted any items yet
+async fn expected_state_hash(
+    source_db: &Database,
+    local_prefixes: &LocalModulePrefixes,
+    session_index: u64,
+    session_count: u64,
+) -> Option<sha256::Hash> {
+    let mut dbtx = source_db.begin_transaction_nc().await;
+
+    if let Some(state_hash) = dbtx.get_value(&OwnStateHashKey(session_index)).await {
+        return Some(state_hash);
+    }
+
+    if let Some(signed_header) = dbtx
+        .get_value(&SignedCheckpointHeaderKey(session_index))
+        .await
+    {
+        return Some(signed_header.header.state_hash);
+    }
+
+    if let Some(header) = dbtx.get_value(&CheckpointHeaderKey).await {
+        if header.session_index == session_index {
+            return Some(header.state_hash);
+        }
+    }
+
+    let is_idle = dbtx
+        .find_by_prefix(&AcceptedItemPrefix)
+        .await
+        .next()
+        .await
+        .is_none();
+
+    if session_index + 1 == session_count && is_idle {
+        return Some(consensus_state_hash(source_db, local_prefixes).await);
+    }
+
+    None
+}
+
+/// Initializes the scratch database with the state of the latest checkpoint
+/// of the source database, which has to be for the given session
+async fn restore_checkpoint(
+    source_db: &Database,
+    scratch_db: &Database,
+    local_prefixes: &LocalModulePrefixes,
+    session_index: u64,
+) -> anyhow::Result<()> {
+    let mut source_dbtx = source_db.begin_transaction_nc().await;
+
+    let Some(header) = source_dbtx.get_value(&CheckpointHeaderKey).await else {
+        bail!("The source database contains no checkpoint to start the replay from");
+    };
+
+    ensure!(
+        header.session_index == session_index,
+        "The latest checkpoint is for session {}, so the replay has to start at session 0 or {}",
+        header.session_index,
+        header.session_index + 1
+    );
+
+    let chunks = source_dbtx
+        .find_by_prefix(&CheckpointChunkPrefix)
+        .await
+        .map(|(_, chunk)| chunk)
+        .collect::<Vec<_>>()
+        .await;
+
+    ensure!(
+        header.verify_chunks(&chunks),
+        "The checkpoint does not match its header"
+    );
+
+    let signed_session_outcome = source_dbtx
+        .get_value(&SignedSessionOutcomeKey(session_index))
+        .await
+        .with_context(|| format!("Session {session_index} has been pruned"))?;
+
+    let mut dbtx = scratch_db.begin_transaction().await;
+
+    for chunk in &chunks {
+        for (key, value) in &chunk.entries {
+            ensure!(
+                local_prefixes.is_consensus_key(key),
+                "The checkpoint contains a key outside of the consensus state"
+            );
+
+            dbtx.raw_insert_bytes(key, value).await?;
+        }
+    }
+
+    dbtx.insert_entry(
+        &SignedSessionOutcomeKey(session_index),
+        &signed_session_outcome,
+    )
+    .await;
+
+    dbtx.commit_tx_result().await
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_hashes::Hash;
+
+    use super::ReplayedSession;
+
+    #[test]
+    fn test_replayed_session_divergence() {
+        let session = ReplayedSession {
+            session_index: 0,
+            state_hash: bitcoin_hashes::sha256::Hash::hash(b"state"),
+            expected_state_hash: None,
+            rejected_items: vec![],
+        };
+
+        assert!(!session.is_diverged());
+
+        assert!(!ReplayedSession {
+            expected_state_hash: Some(session.state_hash),
+            ..session.clone()
+        }
+        .is_diverged());
+
+        assert!(ReplayedSession {
+            expected_state_hash: Some(bitcoin_hashes::sha256::Hash::hash(b"other")),
+            ..session.clone()
+        }
+        .is_diverged());
+
+        assert!(ReplayedSession {
+            rejected_items: vec![3],
+            ..session
+        }
+        .is_diverged());
+    }
+}
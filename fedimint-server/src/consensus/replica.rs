@@ -0,0 +1,151 @@
+//! Read-only replicas serving the client API of a guardian
+//!
+//! A replica runs next to a guardian on the same storage and follows its
+//! database read-only, so a federation can scale its client read traffic
+//! without adding consensus members. It only serves the global endpoints in
+//! [`REPLICA_ENDPOINTS`]. Module endpoints are served as well, but any of
+//! them that writes to the database fails.
+
+use std::time::Duration;
+
+use anyhow::ensure;
+use fedimint_core::config::ServerModuleInitRegistry;
+use fedimint_core::db::Database;
+use fedimint_core::endpoint_constants::{
+    AWAIT_OUTPUT_OUTCOME_ENDPOINT, AWAIT_SESSION_OUTCOME_ENDPOINT,
+    AWAIT_SIGNED_SESSION_OUTCOME_ENDPOINT, AWAIT_TRANSACTION_ENDPOINT,
+    DOWNLOAD_CHECKPOINT_ENDPOINT, FEDERATION_ID_ENDPOINT, FEDERATION_META_ENDPOINT,
+    INVITE_CODE_ENDPOINT, LATEST_CHECKPOINT_ENDPOINT, PEER_ENDPOINTS_ENDPOINT, RECOVER_ENDPOINT,
+    SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT, SESSION_COUNT_ENDPOINT, SESSION_STATUS_ENDPOINT,
+    TRANSACTION_STATUS_ENDPOINT, VERSION_ENDPOINT,
+};
+use fedimint_core::task::{sleep, TaskGroup};
+use fedimint_logging::LOG_CONSENSUS;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::config::ServerConfig;
+use crate::consensus::api::{server_endpoints, ConsensusApi};
+use crate::consensus::init_modules;
+use crate::consensus::migration::pending_migrations;
+use crate::net;
+use crate::net::api::RpcHandlerCtx;
+
+/// Global endpoints that do not modify the state of the guardian
+pub const REPLICA_ENDPOINTS: &[&str] = &[
+    VERSION_ENDPOINT,
+    AWAIT_TRANSACTION_ENDPOINT,
+    AWAIT_OUTPUT_OUTCOME_ENDPOINT,
+    INVITE_CODE_ENDPOINT,
+    FEDERATION_ID_ENDPOINT,
+    SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT,
+    SESSION_COUNT_ENDPOINT,
+    AWAIT_SESSION_OUTCOME_ENDPOINT,
+    AWAIT_SIGNED_SESSION_OUTCOME_ENDPOINT,
+    SESSION_STATUS_ENDPOINT,
+    LATEST_CHECKPOINT_ENDPOINT,
+    DOWNLOAD_CHECKPOINT_ENDPOINT,
+    FEDERATION_META_ENDPOINT,
+    TRANSACTION_STATUS_ENDPOINT,
+    PEER_ENDPOINTS_ENDPOINT,
+    RECOVER_ENDPOINT,
+];
+
+/// How often the replica picks up the changes of the guardian
+const REPLICA_CATCH_UP_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Makes the writes of the guardian visible to the replica, e.g. by catching
+/// up a RocksDB secondary instance with its primary
+pub type CatchUpFn = Box<dyn Fn() -> anyhow::Result<()> + Send + Sync>;
+
+/// Serves the read-only API on top of the database of a running guardian until
+/// the task group is shut down
+pub async fn run(
+    cfg: ServerConfig,
+    db: Database,
+    module_init_registry: ServerModuleInitRegistry,
+    catch_up: CatchUpFn,
+    task_group: &TaskGroup,
+) -> anyhow::Result<()> {
+    ensure!(
+        pending_migrations(&cfg, &db, &module_init_registry)
+            .await?
+            .is_empty(),
+        "The guardian has not migrated the database yet"
+    );
+
+    let modules = init_modules(&cfg, &db, &module_init_registry, task_group).await?;
+
+    // The replica never runs consensus, so submissions and shutdown requests
+    // have nowhere to go
+    let (submission_sender, _) = async_channel::bounded(1);
+
+    let api = ConsensusApi {
+        cfg: cfg.clone(),
+        db: db.clone(),
+        modules: modules.clone(),
+        client_cfg: cfg.consensus.to_client_config(&module_init_registry)?,
+        submission_sender,
+        recent_transactions: Default::default(),
+        mempool: Default::default(),
+        session_profiler: Default::default(),
+        shutdown_sender: watch::channel(None).0,
+        upgrade_receiver: watch::channel(None).1,
+        connection_status_channels: Default::default(),
+        last_ci_by_peer: Default::default(),
+        state_divergence_by_peer: Default::default(),
+        supported_api_versions: ServerConfig::supported_api_versions_summary(
+            &cfg.consensus.modules,
+            &module_init_registry,
+        ),
+        task_group: task_group.clone(),
+    };
+
+    let mut rpc_module = RpcHandlerCtx::new_module(api);
+
+    net::api::attach_endpoints(
+        &mut rpc_module,
+        server_endpoints()
+            .into_iter()
+            .filter(|endpoint| REPLICA_ENDPOINTS.contains(&endpoint.path))
+            .collect(),
+        None,
+    );
+
+    for (id, _, module) in modules.iter_modules() {
+        net::api::attach_endpoints(&mut rpc_module, module.api_endpoints(), Some(id));
+    }
+
+    info!(target: LOG_CONSENSUS, "Starting replica api");
+
+    let api_handler = net::api::spawn(
+        "replica",
+        &cfg.local.api_bind,
+        rpc_module,
+        cfg.local.max_connections,
+    )
+    .await;
+
+    task_group.spawn_cancellable("replica-catch-up", async move {
+        loop {
+            match catch_up() {
+                // Waiters for keys are only notified about writes of this
+                // process, so we wake all of them to check the database again
+                Ok(()) => db.notify_all().await,
+                Err(error) => warn!(target: LOG_CONSENSUS, ?error, "Replica failed to catch up"),
+            }
+
+            sleep(REPLICA_CATCH_UP_INTERVAL).await;
+        }
+    });
+
+    task_group.make_handle().make_shutdown_rx().await.await;
+
+    api_handler
+        .stop()
+        .expect("Replica api should still be running");
+
+    api_handler.stopped().await;
+
+    Ok(())
+}
@@ -0,0 +1,157 @@
+//! Exchange of consensus state hashes to detect divergence early
+//!
+//! After every session each guardian hashes its consensus state in the
+//! background and submits the hash as a [`ConsensusItem::StateHash`]. Once a
+//! peer's hash is ordered we compare it to our own hash for that session, or
+//! once we have our own hash if the peer was faster. Since all guardians
+//! process the same items their hashes have to agree, otherwise the state of
+//! at least one of them diverged, which would otherwise only be noticed once
+//! checkpoints fail to be signed. The replay tool in [`crate::consensus::replay`] helps to
+//! find the session that caused it.
+//!
+//! [`ConsensusItem::StateHash`]: fedimint_core::epoch::ConsensusItem::StateHash
+
+use anyhow::ensure;
+use bitcoin_hashes::sha256;
+use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::epoch::SessionStateHash;
+use fedimint_core::PeerId;
+
+use crate::consensus::db::{OwnStateHashKey, PeerStateHashKey};
+use crate::consensus::engine::get_finished_session_count_static;
+
+/// Number of sessions we keep our own state hash for. Peers may announce their
+/// hash a few sessions late if their submission queue is congested.
+pub const STATE_HASH_HISTORY: u64 = 10;
+
+/// Stores our own state hash after the session and forgets the hash of the
+/// session that left the history
+pub async fn store_own_state_hash(db: &Database, session_index: u64, state_hash: sha256::Hash) {
+    let mut dbtx = db.begin_transaction().await;
+
+    dbtx.insert_entry(&OwnStateHashKey(session_index), &state_hash)
+        .await;
+
+    if let Some(expired) = session_index.checked_sub(STATE_HASH_HISTORY) {
+        dbtx.remove_entry(&OwnStateHashKey(expired)).await;
+    }
+
+    dbtx.commit_tx().await;
+}
+
+/// Records the state hash announced by a peer and returns our own hash for the
+/// same session, if we still have it. A peer can only announce a hash for a
+/// completed session and only once per session.
+pub async fn process_state_hash(
+    dbtx: &mut DatabaseTransaction<'_>,
+    peer: PeerId,
+    state_hash: SessionStateHash,
+) -> anyhow::Result<Option<sha256::Hash>> {
+    let session_count = get_finished_session_count_static(dbtx).await;
+
+    ensure!(
+        state_hash.session_index < session_count,
+        "State hash is for a session that has not been completed"
+    );
+
+    if let Some(previous) = dbtx.get_value(&PeerStateHashKey(peer)).await {
+        ensure!(
+            previous.session_index < state_hash.session_index,
+            "Peer already announced a state hash for this or a later session"
+        );
+    }
+
+    dbtx.insert_entry(&PeerStateHashKey(peer), &state_hash)
+        .await;
+
+    Ok(dbtx
+        .get_value(&OwnStateHashKey(state_hash.session_index))
+        .await)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bitcoin_hashes::{sha256, Hash};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{IDatabaseTransactionOpsCoreTyped, IRawDatabaseExt};
+    use fedimint_core::epoch::SessionStateHash;
+    use fedimint_core::session_outcome::{SessionOutcome, SignedSessionOutcome};
+    use fedimint_core::PeerId;
+
+    use super::{process_state_hash, store_own_state_hash, STATE_HASH_HISTORY};
+    use crate::consensus::db::{OwnStateHashKey, SignedSessionOutcomeKey};
+
+    fn state_hash(session_index: u64, data: &[u8]) -> SessionStateHash {
+        SessionStateHash {
+            session_index,
+            state_hash: sha256::Hash::hash(data),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_state_hash() {
+        let db = MemDatabase::new().into_database();
+        let peer = PeerId::from(1);
+
+        let mut dbtx = db.begin_transaction().await;
+
+        for session_index in 0..2 {
+            dbtx.insert_entry(
+                &SignedSessionOutcomeKey(session_index),
+                &SignedSessionOutcome {
+                    session_outcome: SessionOutcome { items: vec![] },
+                    signatures: BTreeMap::new(),
+                },
+            )
+            .await;
+        }
+
+        dbtx.commit_tx().await;
+
+        store_own_state_hash(&db, 0, state_hash(0, b"state").state_hash).await;
+
+        let mut dbtx = db.begin_transaction().await;
+
+        // the session has not been completed yet
+        assert!(
+            process_state_hash(&mut dbtx.to_ref_nc(), peer, state_hash(2, b"state"))
+                .await
+                .is_err()
+        );
+
+        assert_eq!(
+            process_state_hash(&mut dbtx.to_ref_nc(), peer, state_hash(0, b"other"))
+                .await
+                .unwrap(),
+            Some(state_hash(0, b"state").state_hash)
+        );
+
+        // every session can only be announced once
+        assert!(
+            process_state_hash(&mut dbtx.to_ref_nc(), peer, state_hash(0, b"state"))
+                .await
+                .is_err()
+        );
+
+        // we have no own hash for the session
+        assert_eq!(
+            process_state_hash(&mut dbtx.to_ref_nc(), peer, state_hash(1, b"state"))
+                .await
+                .unwrap(),
+            None
+        );
+
+        dbtx.commit_tx().await;
+
+        store_own_state_hash(&db, STATE_HASH_HISTORY, state_hash(0, b"state").state_hash).await;
+
+        assert!(db
+            .begin_transaction_nc()
+            .await
+            .get_value(&OwnStateHashKey(0))
+            .await
+            .is_none());
+    }
+}
@@ -0,0 +1,257 @@
+//! Rotation of the TLS certificates used for the p2p connections
+//!
+//! A guardian generates a new certificate, signs it with its broadcast key and
+//! submits it as a [`ConsensusItem::TlsCertRotation`]. Once the item is
+//! accepted every guardian switches to the new certificate for that peer, but
+//! keeps accepting the previous one for [`TLS_CERT_ROTATION_GRACE_SESSIONS`]
+//! sessions, so peers that are still catching up on consensus can reconnect.
+//!
+//! The private key of a rotated certificate is stored in the database
+//! encrypted with the guardian password, since the private config can not be
+//! changed without regenerating the configs of all guardians.
+
+use aleph_bft::Keychain as KeychainTrait;
+use anyhow::{ensure, Context};
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_aead::{decrypt, encrypt, get_encryption_key, random_salt};
+use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::epoch::{ConsensusItem, TlsCertRotation};
+use fedimint_core::PeerId;
+use fedimint_logging::LOG_NET_PEER;
+use futures::StreamExt;
+use tokio_rustls::rustls;
+use tracing::warn;
+
+use crate::atomic_broadcast::{to_node_index, Keychain};
+use crate::config::{gen_cert_and_key, ServerConfig};
+use crate::consensus::db::{OwnTlsKeyKey, OwnTlsKeyPrefix, PeerTlsCertKey, PeerTlsCertPrefix};
+use crate::consensus::engine::get_finished_session_count_static;
+use crate::net::connect::TlsConfig;
+
+/// Number of sessions the previous certificate of a peer is still accepted
+/// after a rotation
+pub const TLS_CERT_ROTATION_GRACE_SESSIONS: u64 = 10;
+
+/// Separates rotation signatures from other signatures of the broadcast key
+const TLS_CERT_ROTATION_TAG: &[u8] = b"fedimint-tls-cert-rotation";
+
+/// Certificate of a peer that replaced the one from the config
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct PeerTlsCert {
+    /// DER encoded certificate
+    pub cert: Vec<u8>,
+    /// DER encoded certificate that was replaced by the rotation
+    pub previous_cert: Vec<u8>,
+    /// Session in which the rotation was accepted
+    pub session_index: u64,
+}
+
+/// Private key for a certificate we generated, encrypted with the guardian
+/// password. This is local state that is not part of consensus.
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct OwnTlsKey {
+    /// DER encoded certificate the key belongs to
+    pub cert: Vec<u8>,
+    pub salt: String,
+    pub ciphertext: Vec<u8>,
+}
+
+fn signing_message(cert: &[u8]) -> Vec<u8> {
+    [TLS_CERT_ROTATION_TAG, cert].concat()
+}
+
+/// Generates a new certificate and returns the consensus item announcing it.
+/// If a previous rotation has not been accepted by consensus yet, the item for
+/// its certificate is returned instead, so we never lose a private key that
+/// our peers might switch to.
+pub async fn generate_tls_cert_rotation(
+    cfg: &ServerConfig,
+    db: &Database,
+) -> anyhow::Result<ConsensusItem> {
+    // The key of a rotated certificate is kept in the database, which would
+    // defeat keeping our keys in an HSM
+    ensure!(
+        cfg.private.pkcs11.is_none(),
+        "The TLS certificate can not be rotated while our keys are held by an HSM"
+    );
+
+    let our_id = cfg.local.identity;
+    let keychain = Keychain::new(cfg)?;
+    let mut dbtx = db.begin_transaction().await;
+
+    let accepted = dbtx
+        .get_value(&PeerTlsCertKey(our_id))
+        .await
+        .map(|accepted| accepted.cert);
+
+    let own_keys = dbtx
+        .find_by_prefix(&OwnTlsKeyPrefix)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+
+    // Keys of rotated out certificates are deleted once the rotation is
+    // accepted, so any key besides the one of the current certificate is pending
+    if let Some((_, pending)) = own_keys
+        .into_iter()
+        .find(|(_, own_key)| Some(&own_key.cert) != accepted.as_ref())
+    {
+        return Ok(ConsensusItem::TlsCertRotation(TlsCertRotation {
+            signature: keychain.try_sign(&signing_message(&pending.cert))?,
+            cert: pending.cert,
+        }));
+    }
+
+    let name = &cfg
+        .local
+        .p2p_endpoints
+        .get(&our_id)
+        .context("Our own p2p endpoint is missing from the config")?
+        .name;
+
+    let (cert, private_key) = gen_cert_and_key(name)?;
+
+    let salt = random_salt();
+    let encryption_key = get_encryption_key(&cfg.private.api_auth.0, &salt)?;
+
+    dbtx.insert_entry(
+        &OwnTlsKeyKey(sha256::Hash::hash(&cert.0)),
+        &OwnTlsKey {
+            cert: cert.0.clone(),
+            ciphertext: encrypt(private_key.0, &encryption_key)?,
+            salt,
+        },
+    )
+    .await;
+
+    dbtx.commit_tx_result().await?;
+
+    Ok(ConsensusItem::TlsCertRotation(TlsCertRotation {
+        signature: keychain.try_sign(&signing_message(&cert.0))?,
+        cert: cert.0,
+    }))
+}
+
+/// Verifies and stores a certificate rotation submitted by `peer`
+pub async fn process_tls_cert_rotation(
+    dbtx: &mut DatabaseTransaction<'_>,
+    cfg: &ServerConfig,
+    keychain: &Keychain,
+    peer: PeerId,
+    rotation: TlsCertRotation,
+) -> anyhow::Result<()> {
+    ensure!(
+        keychain.verify(
+            &signing_message(&rotation.cert),
+            &rotation.signature,
+            to_node_index(peer)
+        ),
+        "Invalid signature on certificate rotation"
+    );
+
+    // An invalid certificate would make us fail to build our TLS config
+    rustls::RootCertStore::empty()
+        .add(&rustls::Certificate(rotation.cert.clone()))
+        .context("Invalid certificate")?;
+
+    let previous_cert = match dbtx.get_value(&PeerTlsCertKey(peer)).await {
+        Some(current) => current.cert,
+        None => cfg
+            .consensus
+            .tls_certs
+            .get(&peer)
+            .context("Unknown peer")?
+            .0
+            .clone(),
+    };
+
+    ensure!(previous_cert != rotation.cert, "Certificate is unchanged");
+
+    let session_index = get_finished_session_count_static(dbtx).await;
+
+    if peer == cfg.local.identity {
+        let rotated_out = dbtx
+            .find_by_prefix(&OwnTlsKeyPrefix)
+            .await
+            .filter(|(_, own_key)| std::future::ready(own_key.cert != rotation.cert))
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+            .await;
+
+        for key in rotated_out {
+            dbtx.remove_entry(&key).await;
+        }
+    }
+
+    dbtx.insert_entry(
+        &PeerTlsCertKey(peer),
+        &PeerTlsCert {
+            cert: rotation.cert,
+            previous_cert,
+            session_index,
+        },
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Returns the TLS config from the server config with all accepted
+/// certificate rotations applied
+pub async fn tls_config(cfg: &ServerConfig, db: &Database) -> anyhow::Result<TlsConfig> {
+    let mut tls_config = cfg.tls_config()?;
+    let our_id = cfg.local.identity;
+
+    let mut dbtx = db.begin_transaction_nc().await;
+
+    let session_count = get_finished_session_count_static(&mut dbtx).await;
+
+    let rotations = dbtx
+        .find_by_prefix(&PeerTlsCertPrefix)
+        .await
+        .map(|(key, cert)| (key.0, cert))
+        .collect::<Vec<_>>()
+        .await;
+
+    for (peer, rotation) in rotations {
+        if peer == our_id {
+            match own_private_key(cfg, &mut dbtx, &rotation.cert).await {
+                Ok(private_key) => tls_config.our_private_key = private_key.into(),
+                Err(e) => {
+                    warn!(target: LOG_NET_PEER, "Can not use our rotated certificate: {e:?}");
+                    continue;
+                }
+            }
+        }
+
+        tls_config
+            .peer_certs
+            .insert(peer, rustls::Certificate(rotation.cert));
+
+        if session_count < rotation.session_index + TLS_CERT_ROTATION_GRACE_SESSIONS {
+            tls_config
+                .previous_peer_certs
+                .insert(peer, rustls::Certificate(rotation.previous_cert));
+        }
+    }
+
+    Ok(tls_config)
+}
+
+async fn own_private_key(
+    cfg: &ServerConfig,
+    dbtx: &mut DatabaseTransaction<'_>,
+    cert: &[u8],
+) -> anyhow::Result<rustls::PrivateKey> {
+    let mut key = dbtx
+        .get_value(&OwnTlsKeyKey(sha256::Hash::hash(cert)))
+        .await
+        .context("Private key is missing")?;
+
+    let encryption_key = get_encryption_key(&cfg.private.api_auth.0, &key.salt)?;
+
+    Ok(rustls::PrivateKey(
+        decrypt(&mut key.ciphertext, &encryption_key)?.to_vec(),
+    ))
+}
@@ -0,0 +1,118 @@
+//! Coordinated upgrades of the guardians' software
+//!
+//! Every guardian votes via its admin API on the session after which it wants
+//! to halt for an upgrade. The votes are submitted as
+//! [`ConsensusItem::UpgradeVote`], so all guardians agree on them. Once a
+//! threshold of guardians voted for the same session, every guardian halts
+//! after completing it and waits in the
+//! [`ServerStatus::AwaitingUpgrade`] state until it is restarted. A guardian
+//! can withdraw its vote until the session is reached.
+//!
+//! [`ConsensusItem::UpgradeVote`]: fedimint_core::epoch::ConsensusItem::UpgradeVote
+//! [`ServerStatus::AwaitingUpgrade`]: fedimint_core::admin_client::ServerStatus::AwaitingUpgrade
+
+use std::collections::BTreeMap;
+
+use anyhow::{ensure, Context};
+use fedimint_core::admin_client::UpgradeStatus;
+use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::epoch::UpgradeVote;
+use fedimint_core::PeerId;
+use futures::StreamExt;
+
+use crate::consensus::db::{UpgradeVoteKey, UpgradeVotePrefix};
+use crate::consensus::engine::get_finished_session_count_static;
+
+pub async fn process_upgrade_vote(
+    dbtx: &mut DatabaseTransaction<'_>,
+    peer: PeerId,
+    vote: UpgradeVote,
+) -> anyhow::Result<()> {
+    match vote {
+        UpgradeVote::Schedule { session_index } => {
+            let session_count = get_finished_session_count_static(dbtx).await;
+
+            ensure!(
+                session_count <= session_index,
+                "Upgrade session has already been completed"
+            );
+
+            let previous = dbtx
+                .insert_entry(&UpgradeVoteKey(peer), &session_index)
+                .await;
+
+            ensure!(
+                previous != Some(session_index),
+                "Peer already voted for this session"
+            );
+        }
+        UpgradeVote::Cancel => {
+            dbtx.remove_entry(&UpgradeVoteKey(peer))
+                .await
+                .context("Peer has not voted for an upgrade")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Summarizes the votes on upcoming upgrade sessions, ignoring votes for
+/// sessions before `session_index`
+pub async fn upgrade_status(
+    dbtx: &mut DatabaseTransaction<'_>,
+    threshold: usize,
+    session_index: u64,
+) -> UpgradeStatus {
+    let votes = dbtx
+        .find_by_prefix(&UpgradeVotePrefix)
+        .await
+        .map(|(key, session_index)| (key.0, session_index))
+        .filter(|(_, vote)| std::future::ready(session_index <= *vote))
+        .collect::<BTreeMap<PeerId, u64>>()
+        .await;
+
+    UpgradeStatus {
+        scheduled_session: scheduled_session(&votes, threshold),
+        votes,
+    }
+}
+
+/// The earliest session a threshold of peers voted for
+fn scheduled_session(votes: &BTreeMap<PeerId, u64>, threshold: usize) -> Option<u64> {
+    let mut vote_count = BTreeMap::<u64, usize>::new();
+
+    for session_index in votes.values() {
+        *vote_count.entry(*session_index).or_default() += 1;
+    }
+
+    vote_count
+        .into_iter()
+        .find(|(_, count)| threshold <= *count)
+        .map(|(session_index, _)| session_index)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::PeerId;
+
+    use super::scheduled_session;
+
+    #[test]
+    fn test_scheduled_session() {
+        let votes = |votes: &[u64]| {
+            votes
+                .iter()
+                .enumerate()
+                .map(|(peer, session_index)| (PeerId::from(peer as u16), *session_index))
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        assert_eq!(scheduled_session(&votes(&[]), 3), None);
+        assert_eq!(scheduled_session(&votes(&[10, 10, 12, 12]), 3), None);
+        assert_eq!(scheduled_session(&votes(&[10, 12, 12, 12]), 3), Some(12));
+        assert_eq!(scheduled_session(&votes(&[10, 10, 10, 12]), 3), Some(10));
+        assert_eq!(scheduled_session(&votes(&[10, 10, 12, 12]), 2), Some(10));
+    }
+}
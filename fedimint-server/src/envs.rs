@@ -1,3 +1,16 @@
 /// The env var for maximum open connections the API can handle
 pub const FM_MAX_CLIENT_CONNECTIONS_ENV: &str = "FM_MAX_CLIENT_CONNECTIONS";
 pub const FM_PEER_ID_SORT_BY_URL_ENV: &str = "FM_PEER_ID_SORT_BY_URL";
+
+/// Access key id used to upload scheduled database backups to S3
+pub const FM_DB_BACKUP_S3_ACCESS_KEY_ID_ENV: &str = "FM_DB_BACKUP_S3_ACCESS_KEY_ID";
+/// Secret access key used to upload scheduled database backups to S3
+pub const FM_DB_BACKUP_S3_SECRET_ACCESS_KEY_ENV: &str = "FM_DB_BACKUP_S3_SECRET_ACCESS_KEY";
+
+/// Token used to access the secrets in HashiCorp Vault, named like the
+/// variable of the Vault CLI
+pub const VAULT_TOKEN_ENV: &str = "VAULT_TOKEN";
+/// Access key id used to access the secrets in AWS Secrets Manager
+pub const FM_SECRETS_AWS_ACCESS_KEY_ID_ENV: &str = "FM_SECRETS_AWS_ACCESS_KEY_ID";
+/// Secret access key used to access the secrets in AWS Secrets Manager
+pub const FM_SECRETS_AWS_SECRET_ACCESS_KEY_ENV: &str = "FM_SECRETS_AWS_SECRET_ACCESS_KEY";
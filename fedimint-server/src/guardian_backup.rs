@@ -0,0 +1,192 @@
+//! Passphrase-encrypted backups for moving a guardian to a new host
+//!
+//! A guardian backup contains the server config including the private keys and
+//! the few database entries of the guardian that are not part of consensus,
+//! like the private keys of rotated TLS certificates. The consensus state is
+//! left out on purpose: a restored guardian recovers it from its peers via
+//! checkpoints or by replaying sessions, while restoring stale consensus state
+//! like our own aleph units could make the guardian equivocate.
+
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::{ensure, Context};
+use fedimint_aead::{decrypt, encrypt, get_encryption_key, random_salt};
+use fedimint_core::config::ServerModuleInitRegistry;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCore};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::time::duration_since_epoch;
+use fedimint_core::util::write_new;
+use futures::StreamExt;
+
+use crate::config::io::{has_private_config, write_server_config_to, PLAINTEXT_PASSWORD};
+use crate::config::secrets::SecretsBackend;
+use crate::config::ServerConfig;
+use crate::consensus::db::DbKeyPrefix;
+use crate::snapshot::DbSnapshot;
+
+/// Database prefixes of the local state that can not be recovered from the
+/// peers
+pub const GUARDIAN_BACKUP_DB_PREFIXES: [DbKeyPrefix; 2] =
+    [DbKeyPrefix::OwnTlsKey, DbKeyPrefix::InviteUses];
+
+/// Everything a guardian needs to resume on a new host
+#[derive(Debug, Clone)]
+pub struct GuardianBackup {
+    pub config: ServerConfig,
+    /// Entries of the [`GUARDIAN_BACKUP_DB_PREFIXES`]
+    pub db: DbSnapshot,
+}
+
+/// Guardian backup as it is stored on disk
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct EncryptedGuardianBackup {
+    /// Salt used to derive the encryption key from the passphrase
+    pub salt: String,
+    /// JSON encoded config and the encoded [`DbSnapshot`] encrypted with
+    /// `fedimint_aead`
+    pub ciphertext: Vec<u8>,
+}
+
+impl GuardianBackup {
+    /// Creates a backup of a guardian that is not running
+    pub async fn create(config: ServerConfig, db: &Database) -> anyhow::Result<GuardianBackup> {
+        Ok(GuardianBackup {
+            config,
+            db: take_local_state(db).await?,
+        })
+    }
+
+    pub fn encrypt(&self, passphrase: &str) -> anyhow::Result<EncryptedGuardianBackup> {
+        let plaintext = (serde_json::to_vec(&self.config)?, self.db.clone());
+
+        let salt = random_salt();
+        let key = get_encryption_key(passphrase, &salt)?;
+
+        Ok(EncryptedGuardianBackup {
+            ciphertext: encrypt(plaintext.consensus_encode_to_vec(), &key)?,
+            salt,
+        })
+    }
+
+    /// Writes the config into `data_dir` and `secrets` and the local state
+    /// into `db`, none of which may contain a guardian yet
+    pub async fn restore(
+        self,
+        data_dir: &Path,
+        secrets: &dyn SecretsBackend,
+        db: &Database,
+        registry: &ServerModuleInitRegistry,
+        write_plaintext_password: bool,
+    ) -> anyhow::Result<()> {
+        ensure!(
+            !has_private_config(secrets).await?,
+            "The data directory already contains a guardian config"
+        );
+
+        self.db.restore(db).await?;
+
+        let password = &self.config.private.api_auth.0;
+
+        if write_plaintext_password {
+            write_new(data_dir.join(PLAINTEXT_PASSWORD), password)?;
+        }
+        write_server_config_to(
+            secrets,
+            &self.config,
+            data_dir.to_owned(),
+            password,
+            registry,
+        )
+        .await
+    }
+}
+
+impl EncryptedGuardianBackup {
+    pub fn decrypt(mut self, passphrase: &str) -> anyhow::Result<GuardianBackup> {
+        let key = get_encryption_key(passphrase, &self.salt)?;
+        let plaintext = decrypt(&mut self.ciphertext, &key)?;
+
+        let (config, db) = <(Vec<u8>, DbSnapshot)>::consensus_decode_from_finite_reader(
+            &mut Cursor::new(plaintext),
+            &ModuleDecoderRegistry::default(),
+        )
+        .context("Failed to decode guardian backup")?;
+
+        Ok(GuardianBackup {
+            config: serde_json::from_slice(&config).context("Invalid config in guardian backup")?,
+            db,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.consensus_encode_to_vec()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<EncryptedGuardianBackup> {
+        EncryptedGuardianBackup::consensus_decode_from_finite_reader(
+            &mut Cursor::new(bytes),
+            &ModuleDecoderRegistry::default(),
+        )
+        .context("Failed to decode encrypted guardian backup")
+    }
+}
+
+async fn take_local_state(db: &Database) -> anyhow::Result<DbSnapshot> {
+    let mut dbtx = db.begin_transaction_nc().await;
+    let mut entries = vec![];
+
+    for prefix in GUARDIAN_BACKUP_DB_PREFIXES {
+        entries.extend(
+            dbtx.raw_find_by_prefix(&[prefix as u8])
+                .await?
+                .collect::<Vec<_>>()
+                .await,
+        );
+    }
+
+    Ok(DbSnapshot {
+        timestamp: duration_since_epoch().as_secs(),
+        entries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{IDatabaseTransactionOpsCore, IRawDatabaseExt};
+
+    use super::take_local_state;
+    use crate::consensus::db::DbKeyPrefix;
+
+    #[tokio::test]
+    async fn test_take_local_state() {
+        let db = MemDatabase::new().into_database();
+
+        let mut dbtx = db.begin_transaction().await;
+        for prefix in [
+            DbKeyPrefix::AcceptedItem,
+            DbKeyPrefix::AlephUnits,
+            DbKeyPrefix::OwnTlsKey,
+            DbKeyPrefix::InviteUses,
+            DbKeyPrefix::Module,
+        ] {
+            dbtx.raw_insert_bytes(&[prefix as u8, 0x01], &[0x02])
+                .await
+                .unwrap();
+        }
+        dbtx.commit_tx().await;
+
+        let snapshot = take_local_state(&db).await.unwrap();
+
+        assert_eq!(
+            snapshot
+                .entries
+                .iter()
+                .map(|(key, _)| key[0])
+                .collect::<Vec<u8>>(),
+            vec![DbKeyPrefix::OwnTlsKey as u8, DbKeyPrefix::InviteUses as u8]
+        );
+    }
+}
@@ -2,21 +2,27 @@
 extern crate fedimint_core;
 
 use std::fs;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 
-use config::io::{read_server_config, PLAINTEXT_PASSWORD};
+use anyhow::Context;
+use config::io::{has_private_config, read_server_config_from, PLAINTEXT_PASSWORD};
 use config::ServerConfig;
-use fedimint_aead::random_salt;
 use fedimint_core::config::ServerModuleInitRegistry;
 use fedimint_core::db::Database;
 use fedimint_core::epoch::ConsensusItem;
 use fedimint_core::task::TaskGroup;
 use fedimint_core::util::write_new;
 use fedimint_logging::LOG_CONSENSUS;
-use tracing::info;
+use tracing::{error, info};
 
 use crate::config::api::{ConfigGenApi, ConfigGenSettings};
-use crate::config::io::{write_server_config, SALT_FILE};
+use crate::config::io::write_server_config_to;
+use crate::config::secrets::SecretsBackend;
+use crate::consensus::migration::{
+    apply_consensus_migrations_with_checkpoint, MIGRATION_CHECKPOINT_DIR,
+};
+use crate::consensus::replica::CatchUpFn;
 use crate::metrics::initialize_gauge_metrics;
 use crate::net::api::RpcHandlerCtx;
 use crate::net::connect::TlsTcpConnector;
@@ -38,19 +44,34 @@ pub mod config;
 /// Implementation of multiplexed peer connections
 pub mod multiplexed;
 
+/// Encrypted snapshots of the server database
+pub mod snapshot;
+
+/// Scheduled database backups with a retention policy
+pub mod backup;
+
+/// Encrypted backups of a guardian for moving it to a new host
+pub mod guardian_backup;
+
+/// Webhook notifications about critical events of the guardian
+pub mod alerts;
+
 pub async fn run(
     data_dir: PathBuf,
+    password: Option<String>,
+    secrets: &dyn SecretsBackend,
     settings: ConfigGenSettings,
     db: Database,
     code_version_str: String,
     module_init_registry: &ServerModuleInitRegistry,
     task_group: TaskGroup,
 ) -> anyhow::Result<()> {
-    let cfg = match get_config(&data_dir).await? {
+    let cfg = match get_config(&data_dir, password.as_deref(), secrets).await? {
         Some(cfg) => cfg,
         None => {
             run_config_gen(
-                data_dir,
+                data_dir.clone(),
+                secrets,
                 settings,
                 db.clone(),
                 code_version_str,
@@ -69,6 +90,14 @@ pub async fn run(
 
     let db = db.with_decoders(decoders);
 
+    apply_consensus_migrations_with_checkpoint(
+        &cfg,
+        &db,
+        module_init_registry,
+        &data_dir.join(MIGRATION_CHECKPOINT_DIR),
+    )
+    .await?;
+
     initialize_gauge_metrics(&db).await;
 
     consensus::run(cfg, db, module_init_registry.clone(), &task_group).await?;
@@ -80,17 +109,69 @@ pub async fn run(
     Ok(())
 }
 
-pub async fn get_config(data_dir: &Path) -> anyhow::Result<Option<ServerConfig>> {
-    // Attempt get the config with local password, otherwise start config gen
-    if let Ok(password) = fs::read_to_string(data_dir.join(PLAINTEXT_PASSWORD)) {
-        return Ok(Some(read_server_config(&password, data_dir.to_owned())?));
+/// Serves the read-only client API of the guardian in `data_dir` from a
+/// replica of its database, binding to `api_bind` instead of the address of
+/// the guardian
+#[allow(clippy::too_many_arguments)]
+pub async fn run_replica(
+    data_dir: PathBuf,
+    password: Option<String>,
+    secrets: &dyn SecretsBackend,
+    api_bind: SocketAddr,
+    db: Database,
+    catch_up: CatchUpFn,
+    module_init_registry: &ServerModuleInitRegistry,
+    task_group: TaskGroup,
+) -> anyhow::Result<()> {
+    let mut cfg = get_config(&data_dir, password.as_deref(), secrets)
+        .await?
+        .context("The data dir contains no config")?;
+
+    cfg.local.api_bind = api_bind;
+
+    let decoders = module_init_registry.decoders_strict(cfg.iter_module_instances())?;
+
+    consensus::replica::run(
+        cfg,
+        db.with_decoders(decoders),
+        module_init_registry.clone(),
+        catch_up,
+        &task_group,
+    )
+    .await?;
+
+    task_group.shutdown();
+
+    Ok(())
+}
+
+/// Reads the config with the password provided on startup or, if none was
+/// provided, with the plaintext password in the data dir. Returns `None` if
+/// the config has not been generated yet.
+pub async fn get_config(
+    data_dir: &Path,
+    password: Option<&str>,
+    secrets: &dyn SecretsBackend,
+) -> anyhow::Result<Option<ServerConfig>> {
+    if !has_private_config(secrets).await? {
+        return Ok(None);
     }
 
-    Ok(None)
+    let password = match password {
+        Some(password) => password.to_owned(),
+        None => fs::read_to_string(data_dir.join(PLAINTEXT_PASSWORD)).context(
+            "The password is required since the data dir contains no plaintext password",
+        )?,
+    };
+
+    Ok(Some(
+        read_server_config_from(secrets, &password, data_dir.to_owned()).await?,
+    ))
 }
 
 pub async fn run_config_gen(
     data_dir: PathBuf,
+    secrets: &dyn SecretsBackend,
     settings: ConfigGenSettings,
     db: Database,
     code_version_str: String,
@@ -110,6 +191,15 @@ pub async fn run_config_gen(
         code_version_str.clone(),
     );
 
+    if let Some(setup) = settings.headless_setup.clone() {
+        let config_gen = config_gen.clone();
+        task_group.spawn("headless config gen", |_handle| async move {
+            if let Err(e) = config::headless::run_headless_setup(config_gen, setup).await {
+                error!(target: LOG_CONSENSUS, "Headless config gen failed: {e:?}");
+            }
+        });
+    }
+
     let mut rpc_module = RpcHandlerCtx::new_module(config_gen);
 
     net::api::attach_endpoints(&mut rpc_module, config::api::server_endpoints(), None);
@@ -124,15 +214,19 @@ pub async fn run_config_gen(
 
     api_handler.stopped().await;
 
-    // TODO: Make writing password optional
-    write_new(data_dir.join(PLAINTEXT_PASSWORD), &cfg.private.api_auth.0)?;
-    write_new(data_dir.join(SALT_FILE), random_salt())?;
-    write_server_config(
+    if settings.write_plaintext_password {
+        write_new(data_dir.join(PLAINTEXT_PASSWORD), &cfg.private.api_auth.0)?;
+    }
+    write_server_config_to(
+        secrets,
         &cfg,
         data_dir.clone(),
         &cfg.private.api_auth.0,
         &settings.registry,
-    )?;
+    )
+    .await?;
+
+    config::api::clear_persisted_state(&db).await;
 
     Ok(cfg)
 }
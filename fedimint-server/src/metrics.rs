@@ -3,8 +3,9 @@ pub(crate) mod jsonrpsee;
 use fedimint_core::backup::ClientBackupKeyPrefix;
 use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
 use fedimint_metrics::prometheus::{
-    register_histogram_vec_with_registry, register_int_gauge_vec_with_registry,
-    register_int_gauge_with_registry, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec,
+    register_histogram_vec_with_registry, register_int_counter_with_registry,
+    register_int_gauge_vec_with_registry, register_int_gauge_with_registry, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
 };
 use fedimint_metrics::{
     histogram_opts, opts, register_histogram_with_registry, register_int_counter_vec_with_registry,
@@ -72,7 +73,7 @@ lazy_static! {
                 "jsonrpc_api_request_duration_seconds",
                 "Duration of processing an rpc request",
             ),
-            &["method"],
+            &["module", "method"],
             REGISTRY
         )
         .unwrap();
@@ -82,7 +83,28 @@ lazy_static! {
                 "jsonrpc_api_request_response_code_total",
                 "Count of response counts and types",
             ),
-            &["method", "code", "type"],
+            &["module", "method", "code", "type"],
+            REGISTRY
+        )
+        .unwrap();
+    pub(crate) static ref JSONRPC_API_REQUEST_ERRORS: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            opts!(
+                "jsonrpc_api_request_errors_total",
+                "Count of rpc requests that failed by error code",
+            ),
+            &["module", "method", "code"],
+            REGISTRY
+        )
+        .unwrap();
+    pub(crate) static ref CONSENSUS_SESSION_STAGE_DURATION_SECONDS: HistogramVec =
+        register_histogram_vec_with_registry!(
+            histogram_opts!(
+                "consensus_session_stage_duration_seconds",
+                "Time the consensus engine spent in a stage of a session",
+                vec![0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]
+            ),
+            &["stage"],
             REGISTRY
         )
         .unwrap();
@@ -113,6 +135,32 @@ lazy_static! {
         REGISTRY
     )
     .unwrap();
+    pub(crate) static ref SUBMISSION_QUEUE_DEPTH: IntGauge = register_int_gauge_with_registry!(
+        opts!(
+            "submission_queue_depth",
+            "Number of submitted consensus items waiting to be proposed",
+        ),
+        REGISTRY
+    )
+    .unwrap();
+    pub(crate) static ref SUBMISSION_QUEUE_REJECTED_COUNT: IntCounter =
+        register_int_counter_with_registry!(
+            opts!(
+                "submission_queue_rejected_total",
+                "Number of transactions rejected because the submission queue was full",
+            ),
+            REGISTRY
+        )
+        .unwrap();
+    pub(crate) static ref SUBMISSION_DEDUP_HITS_COUNT: IntCounter =
+        register_int_counter_with_registry!(
+            opts!(
+                "submission_dedup_hits_total",
+                "Number of submitted transactions skipped since they were queued recently",
+            ),
+            REGISTRY
+        )
+        .unwrap();
     pub(crate) static ref STORED_BACKUPS_COUNT: IntGauge = register_int_gauge_with_registry!(
         opts!("stored_backups_count", "Total amount of backups stored",),
         REGISTRY
@@ -135,6 +183,55 @@ lazy_static! {
             REGISTRY
         )
         .unwrap();
+    pub(crate) static ref PEER_CIRCUIT_BREAKER_TRIPS_COUNT: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            opts!(
+                "peer_circuit_breaker_trips_total",
+                "Number of times we paused reconnecting to a flapping peer",
+            ),
+            &["self_id", "peer_id"],
+            REGISTRY
+        )
+        .unwrap();
+    pub(crate) static ref CONSENSUS_STATE_DIVERGENCE_COUNT: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            opts!(
+                "consensus_state_divergence_total",
+                "Number of state hashes announced by a peer that differ from our own",
+            ),
+            &["self_id", "peer_id"],
+            REGISTRY
+        )
+        .unwrap();
+    pub(crate) static ref CONSENSUS_STATE_HASH_DURATION_SECONDS: Histogram =
+        register_histogram_with_registry!(
+            histogram_opts!(
+                "consensus_state_hash_duration_seconds",
+                "Duration of hashing the consensus state after a session",
+            ),
+            REGISTRY
+        )
+        .unwrap();
+    pub(crate) static ref MODULE_CI_PROPOSALS_DROPPED_COUNT: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            opts!(
+                "module_ci_proposals_dropped_total",
+                "Number of module consensus items dropped since the module's proposal buffer was full",
+            ),
+            &["module_kind"],
+            REGISTRY
+        )
+        .unwrap();
+    pub(crate) static ref PEER_RATE_LIMIT_VIOLATIONS_COUNT: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            opts!(
+                "peer_rate_limit_violations_total",
+                "Number of times we disconnected a peer for exceeding the rate limits",
+            ),
+            &["self_id", "peer_id", "limit"],
+            REGISTRY
+        )
+        .unwrap();
     pub(crate) static ref PEER_MESSAGES_COUNT: IntCounterVec =
         register_int_counter_vec_with_registry!(
             opts!("peer_messages_total", "Messages with the peer",),
@@ -142,6 +239,36 @@ lazy_static! {
             REGISTRY
         )
         .unwrap();
+    pub(crate) static ref PEER_MUX_MESSAGES_COUNT: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            opts!(
+                "peer_mux_messages_total",
+                "Messages with the peer per multiplexed channel",
+            ),
+            &["peer_id", "channel", "direction"],
+            REGISTRY
+        )
+        .unwrap();
+    pub(crate) static ref PEER_MUX_BYTES_COUNT: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            opts!(
+                "peer_mux_bytes_total",
+                "Encoded size of the messages with the peer per multiplexed channel",
+            ),
+            &["peer_id", "channel", "direction"],
+            REGISTRY
+        )
+        .unwrap();
+    pub(crate) static ref PEER_MUX_DROPPED_MESSAGES_COUNT: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            opts!(
+                "peer_mux_dropped_messages_total",
+                "Outgoing messages dropped because their priority lane was full",
+            ),
+            &["lane"],
+            REGISTRY
+        )
+        .unwrap();
     pub(crate) static ref PEER_BANS_COUNT: IntCounterVec = register_int_counter_vec_with_registry!(
         opts!("peer_bans_total", "Peer bans",),
         &["self_id", "peer_id"],
@@ -16,10 +16,29 @@ use jsonrpsee::types::Request;
 use jsonrpsee::MethodResponse;
 use pin_project::pin_project;
 
-use super::{JSONRPC_API_REQUEST_DURATION_SECONDS, JSONRPC_API_REQUEST_RESPONSE_CODE};
+use super::{
+    JSONRPC_API_REQUEST_DURATION_SECONDS, JSONRPC_API_REQUEST_ERRORS,
+    JSONRPC_API_REQUEST_RESPONSE_CODE,
+};
+
+/// Value of the `module` label for the endpoints of the server itself
+const GLOBAL_MODULE_LABEL: &str = "global";
+
+/// Splits the name of a module endpoint like `module_1_await_output` into the
+/// module instance id and the method, so the metrics of all modules can be
+/// filtered and aggregated by method
+fn module_and_method(method_name: &str) -> (&str, &str) {
+    method_name
+        .strip_prefix("module_")
+        .and_then(|rest| rest.split_once('_'))
+        .filter(|(module_id, _)| module_id.parse::<u16>().is_ok())
+        .unwrap_or((GLOBAL_MODULE_LABEL, method_name))
+}
 
 #[pin_project]
 pub struct ResponseFuture<F> {
+    #[pin]
+    module: String,
     #[pin]
     method: String,
     #[pin]
@@ -44,14 +63,25 @@ impl<F: Future<Output = MethodResponse>> Future for ResponseFuture<F> {
             if let Some(timer) = projected.timer.take() {
                 timer.observe_duration();
 
+                let code = if let Some(code) = res.as_error_code() {
+                    JSONRPC_API_REQUEST_ERRORS
+                        .with_label_values(&[
+                            &projected.module,
+                            &projected.method,
+                            &code.to_string(),
+                        ])
+                        .inc();
+
+                    Cow::Owned(code.to_string())
+                } else {
+                    Cow::Borrowed("0")
+                };
+
                 JSONRPC_API_REQUEST_RESPONSE_CODE
                     .with_label_values(&[
+                        &projected.module,
                         &projected.method,
-                        &if let Some(code) = res.as_error_code() {
-                            Cow::Owned(code.to_string())
-                        } else {
-                            Cow::Borrowed("0")
-                        },
+                        &code,
                         if res.is_subscription() {
                             "subscription"
                         } else if res.is_batch() {
@@ -89,14 +119,35 @@ where
     type Future = ResponseFuture<S::Future>;
 
     fn call(&self, req: Request<'a>) -> Self::Future {
+        let (module, method) = module_and_method(req.method_name());
+
         let timer = JSONRPC_API_REQUEST_DURATION_SECONDS
-            .with_label_values(&[req.method_name()])
+            .with_label_values(&[module, method])
             .start_timer();
 
         ResponseFuture {
-            method: req.method.to_string(),
+            module: module.to_string(),
+            method: method.to_string(),
             fut: self.service.call(req),
             timer: Some(timer),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::module_and_method;
+
+    #[test]
+    fn test_module_and_method() {
+        assert_eq!(module_and_method("version"), ("global", "version"));
+        assert_eq!(
+            module_and_method("module_1_await_output_outcome"),
+            ("1", "await_output_outcome")
+        );
+        assert_eq!(
+            module_and_method("module_x_method"),
+            ("global", "module_x_method")
+        );
+    }
+}
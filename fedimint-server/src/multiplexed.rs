@@ -10,10 +10,15 @@ use fedimint_core::PeerId;
 use fedimint_logging::LOG_NET_PEER;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot;
 use tracing::{debug, warn};
 
+use crate::metrics::{
+    PEER_MUX_BYTES_COUNT, PEER_MUX_DROPPED_MESSAGES_COUNT, PEER_MUX_MESSAGES_COUNT,
+};
+
 /// TODO: Use proper ModuleId after modularization is complete
 pub type ModuleId = String;
 pub type ModuleIdRef<'a> = &'a str;
@@ -24,6 +29,64 @@ pub type ModuleIdRef<'a> = &'a str;
 /// to draw the line somewhere.
 pub const MAX_PEER_OUT_OF_ORDER_MESSAGES: u64 = 10000;
 
+/// Lane a message is queued in before it is handed to the peer connections.
+/// Queued [`MessagePriority::Critical`] messages are always sent before any
+/// [`MessagePriority::Bulk`] message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessagePriority {
+    /// Messages that consensus progress depends on, like unit broadcasts and
+    /// votes
+    Critical,
+    /// Large or latency insensitive transfers, like checkpoint downloads
+    Bulk,
+}
+
+impl MessagePriority {
+    fn as_str(self) -> &'static str {
+        match self {
+            MessagePriority::Critical => "critical",
+            MessagePriority::Bulk => "bulk",
+        }
+    }
+}
+
+/// What happens to a message sent into a lane whose buffer is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LaneOverflowPolicy {
+    /// The sender waits until there is space in the buffer
+    Backpressure,
+    /// The message is dropped and the sender continues immediately
+    DropNewest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LaneConfig {
+    /// Maximum number of messages waiting in the lane
+    pub buffer: usize,
+    pub overflow: LaneOverflowPolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriorityLanesConfig {
+    pub critical: LaneConfig,
+    pub bulk: LaneConfig,
+}
+
+impl Default for PriorityLanesConfig {
+    fn default() -> Self {
+        Self {
+            critical: LaneConfig {
+                buffer: 1000,
+                overflow: LaneOverflowPolicy::Backpressure,
+            },
+            bulk: LaneConfig {
+                buffer: 100,
+                overflow: LaneOverflowPolicy::DropNewest,
+            },
+        }
+    }
+}
+
 /// A `Msg` that can target a specific destination module
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ModuleMultiplexed<MuxKey, Msg> {
@@ -59,8 +122,13 @@ impl<MuxKey, Msg> Default for ModuleMultiplexerOutOfOrder<MuxKey, Msg> {
 /// This type is thread-safe and can be cheaply cloned.
 #[derive(Clone)]
 pub struct PeerConnectionMultiplexer<MuxKey, Msg> {
-    /// Sender of send requests
-    send_requests_tx: Sender<(Vec<PeerId>, MuxKey, Msg)>,
+    /// Sender of send requests for critical messages
+    critical_tx: Sender<SendRequest<MuxKey, Msg>>,
+    /// Sender of send requests for bulk messages
+    bulk_tx: Sender<SendRequest<MuxKey, Msg>>,
+    /// Assigns every outgoing message to a lane by its key
+    classify: fn(&MuxKey) -> MessagePriority,
+    lanes: PriorityLanesConfig,
     /// Sender of receive callbacks
     receive_callbacks_tx: Sender<Callback<MuxKey, Msg>>,
     /// Sender of peer bans
@@ -69,13 +137,31 @@ pub struct PeerConnectionMultiplexer<MuxKey, Msg> {
 
 type Callback<MuxKey, Msg> = (MuxKey, oneshot::Sender<(PeerId, Msg)>);
 
+type SendRequest<MuxKey, Msg> = (Vec<PeerId>, MuxKey, Msg);
+
 impl<MuxKey, Msg> PeerConnectionMultiplexer<MuxKey, Msg>
 where
     Msg: Serialize + DeserializeOwned + Unpin + Send + Debug + 'static,
     MuxKey: Serialize + DeserializeOwned + Unpin + Send + Debug + Eq + Hash + Clone + 'static,
 {
+    /// Creates a multiplexer that treats all messages as critical
     pub fn new(connections: PeerConnections<ModuleMultiplexed<MuxKey, Msg>>) -> Self {
-        let (send_requests_tx, send_requests_rx) = channel(1000);
+        Self::with_priority_lanes(
+            connections,
+            |_| MessagePriority::Critical,
+            PriorityLanesConfig::default(),
+        )
+    }
+
+    /// Creates a multiplexer that queues outgoing messages in the lane
+    /// `classify` assigns to their key
+    pub fn with_priority_lanes(
+        connections: PeerConnections<ModuleMultiplexed<MuxKey, Msg>>,
+        classify: fn(&MuxKey) -> MessagePriority,
+        lanes: PriorityLanesConfig,
+    ) -> Self {
+        let (critical_tx, critical_rx) = channel(lanes.critical.buffer);
+        let (bulk_tx, bulk_rx) = channel(lanes.bulk.buffer);
         let (receive_callbacks_tx, receive_callbacks_rx) = channel(1000);
         let (peer_bans_tx, peer_bans_rx) = channel(1000);
 
@@ -84,14 +170,18 @@ where
             Self::run(
                 connections,
                 Default::default(),
-                send_requests_rx,
+                critical_rx,
+                bulk_rx,
                 receive_callbacks_rx,
                 peer_bans_rx,
             ),
         );
 
         Self {
-            send_requests_tx,
+            critical_tx,
+            bulk_tx,
+            classify,
+            lanes,
             receive_callbacks_tx,
             peer_bans_tx,
         }
@@ -100,17 +190,22 @@ where
     async fn run(
         mut connections: PeerConnections<ModuleMultiplexed<MuxKey, Msg>>,
         mut out_of_order: ModuleMultiplexerOutOfOrder<MuxKey, Msg>,
-        mut send_requests_rx: Receiver<(Vec<PeerId>, MuxKey, Msg)>,
+        mut critical_rx: Receiver<SendRequest<MuxKey, Msg>>,
+        mut bulk_rx: Receiver<SendRequest<MuxKey, Msg>>,
         mut receive_callbacks_rx: Receiver<Callback<MuxKey, Msg>>,
         mut peer_bans_rx: Receiver<PeerId>,
     ) -> Cancellable<()> {
         loop {
             let mut key_inserted: Option<MuxKey> = None;
             tokio::select! {
-                 // Send requests are forwarded to underlying connections
-                 send_request = send_requests_rx.recv() => {
+                // Branches are polled in order, so bulk messages are only sent
+                // once there is nothing else to do
+                biased;
+
+                // Critical send requests are forwarded to underlying connections first
+                send_request = critical_rx.recv() => {
                     let (peers, key, msg) = send_request.ok_or(Cancelled)?;
-                    connections.send(&peers, ModuleMultiplexed { key, msg }).await?;
+                    Self::send_multiplexed(&mut connections, peers, key, msg).await?;
                 }
                 // Ban requests are forwarded to underlying connections
                 peer_ban = peer_bans_rx.recv() => {
@@ -125,7 +220,9 @@ where
                 }
                 // Actual received messages are added message queue by key
                 receive = connections.receive() => {
-                    let (peer, ModuleMultiplexed { key, msg }) = receive?;
+                    let (peer, msg) = receive?;
+                    record_traffic(peer, &msg, "incoming");
+                    let ModuleMultiplexed { key, msg } = msg;
                     let peer_pending = out_of_order.peer_counts.entry(peer).or_default();
                     // We limit our messages from any given peer to avoid OOM
                     // In practice this would halt DKG
@@ -140,6 +237,11 @@ where
                         key_inserted = Some(key);
                     }
                 }
+                // Bulk send requests are forwarded to underlying connections last
+                send_request = bulk_rx.recv() => {
+                    let (peers, key, msg) = send_request.ok_or(Cancelled)?;
+                    Self::send_multiplexed(&mut connections, peers, key, msg).await?;
+                }
             }
 
             // If a key was inserted, check to see if we can fulfill a callback
@@ -157,6 +259,37 @@ where
             }
         }
     }
+
+    async fn send_multiplexed(
+        connections: &mut PeerConnections<ModuleMultiplexed<MuxKey, Msg>>,
+        peers: Vec<PeerId>,
+        key: MuxKey,
+        msg: Msg,
+    ) -> Cancellable<()> {
+        let msg = ModuleMultiplexed { key, msg };
+        for peer in &peers {
+            record_traffic(*peer, &msg, "outgoing");
+        }
+        connections.send(&peers, msg).await
+    }
+}
+
+/// Counts a message and its encoded size in the per peer and channel metrics
+fn record_traffic<MuxKey, Msg>(peer: PeerId, msg: &ModuleMultiplexed<MuxKey, Msg>, direction: &str)
+where
+    Msg: Serialize,
+    MuxKey: Serialize + Debug,
+{
+    let peer = peer.to_string();
+    let channel = format!("{:?}", msg.key);
+    let labels = [peer.as_str(), channel.as_str(), direction];
+
+    // This is the size of the frame the message is sent in, excluding the length
+    // prefix
+    let size = bincode::serialized_size(msg).unwrap_or_default();
+
+    PEER_MUX_MESSAGES_COUNT.with_label_values(&labels).inc();
+    PEER_MUX_BYTES_COUNT.with_label_values(&labels).inc_by(size);
 }
 
 #[async_trait]
@@ -167,10 +300,33 @@ where
 {
     async fn send(&self, peers: &[PeerId], key: MuxKey, msg: Msg) -> Cancellable<()> {
         debug!("Sending to {peers:?}/{key:?}, {msg:?}");
-        self.send_requests_tx
-            .send((peers.to_vec(), key, msg))
-            .await
-            .map_err(|_e| Cancelled)
+        let priority = (self.classify)(&key);
+        let (lane, lane_config) = match priority {
+            MessagePriority::Critical => (&self.critical_tx, self.lanes.critical),
+            MessagePriority::Bulk => (&self.bulk_tx, self.lanes.bulk),
+        };
+
+        match lane_config.overflow {
+            LaneOverflowPolicy::Backpressure => lane
+                .send((peers.to_vec(), key, msg))
+                .await
+                .map_err(|_e| Cancelled),
+            LaneOverflowPolicy::DropNewest => match lane.try_send((peers.to_vec(), key, msg)) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full((_, key, _))) => {
+                    warn!(
+                        target: LOG_NET_PEER,
+                        "The {} lane is full. Dropping message for {peers:?}/{key:?}.",
+                        priority.as_str()
+                    );
+                    PEER_MUX_DROPPED_MESSAGES_COUNT
+                        .with_label_values(&[priority.as_str()])
+                        .inc();
+                    Ok(())
+                }
+                Err(TrySendError::Closed(_)) => Err(Cancelled),
+            },
+        }
     }
 
     /// Await receipt of a message from any connected peer.
@@ -201,7 +357,11 @@ pub mod test {
     use rand::seq::SliceRandom;
     use rand::{thread_rng, Rng};
 
-    use crate::multiplexed::PeerConnectionMultiplexer;
+    use crate::metrics::{PEER_MUX_BYTES_COUNT, PEER_MUX_MESSAGES_COUNT};
+    use crate::multiplexed::{
+        LaneConfig, LaneOverflowPolicy, MessagePriority, PeerConnectionMultiplexer,
+        PriorityLanesConfig,
+    };
 
     /// Send over many messages a multiplexed fake link
     ///
@@ -269,4 +429,82 @@ pub mod test {
             task_group.join_all(None).await.expect("no failures");
         }
     }
+
+    #[test_log::test(tokio::test)]
+    async fn test_traffic_metrics() {
+        let task_group = TaskGroup::new();
+        let peer1 = PeerId::from(0);
+        let peer2 = PeerId::from(1);
+
+        let (conn1, conn2) = make_fake_peer_connection(peer1, peer2, 10, task_group.make_handle());
+        let (conn1, conn2) = (
+            PeerConnectionMultiplexer::new(conn1).into_dyn(),
+            PeerConnectionMultiplexer::new(conn2).into_dyn(),
+        );
+
+        let key = "traffic-metrics".to_string();
+        let channel = format!("{key:?}");
+
+        conn1.send(&[peer2], key.clone(), 42u64).await.unwrap();
+        assert_eq!(conn2.receive(key).await.unwrap(), (peer1, 42));
+
+        for (peer, direction) in [(peer2, "outgoing"), (peer1, "incoming")] {
+            let peer = peer.to_string();
+            let labels = [peer.as_str(), channel.as_str(), direction];
+
+            assert_eq!(PEER_MUX_MESSAGES_COUNT.with_label_values(&labels).get(), 1);
+            assert!(PEER_MUX_BYTES_COUNT.with_label_values(&labels).get() >= 8);
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_priority_lanes() {
+        const BULK_KEY: u64 = 0;
+        const CRITICAL_KEY: u64 = 1;
+        const NUM_MSGS: u64 = 100;
+
+        let task_group = TaskGroup::new();
+        let peer1 = PeerId::from(0);
+        let peer2 = PeerId::from(1);
+
+        let (conn1, conn2) =
+            make_fake_peer_connection(peer1, peer2, 1000, task_group.make_handle());
+
+        let lanes = PriorityLanesConfig {
+            critical: LaneConfig {
+                buffer: 10,
+                overflow: LaneOverflowPolicy::Backpressure,
+            },
+            bulk: LaneConfig {
+                buffer: 1,
+                overflow: LaneOverflowPolicy::DropNewest,
+            },
+        };
+
+        let classify = |key: &u64| {
+            if *key == BULK_KEY {
+                MessagePriority::Bulk
+            } else {
+                MessagePriority::Critical
+            }
+        };
+
+        let conn1 =
+            PeerConnectionMultiplexer::with_priority_lanes(conn1, classify, lanes).into_dyn();
+        let conn2 = PeerConnectionMultiplexer::new(conn2).into_dyn();
+
+        // Flooding the bulk lane never blocks the sender, excess messages are dropped
+        for msg in 0..NUM_MSGS {
+            conn1.send(&[peer2], BULK_KEY, msg).await.unwrap();
+        }
+
+        for msg in 0..NUM_MSGS {
+            conn1.send(&[peer2], CRITICAL_KEY, msg).await.unwrap();
+        }
+
+        // No critical message is lost
+        for msg in 0..NUM_MSGS {
+            assert_eq!(conn2.receive(CRITICAL_KEY).await.unwrap(), (peer1, msg));
+        }
+    }
 }
@@ -8,13 +8,15 @@ use anyhow::Context;
 use async_trait::async_trait;
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::module::{ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased};
+use fedimint_core::runtime;
 use fedimint_logging::LOG_NET_API;
 use futures::FutureExt;
 use jsonrpsee::server::{PingConfig, RpcServiceBuilder, ServerBuilder, ServerHandle};
 use jsonrpsee::types::ErrorObject;
 use jsonrpsee::RpcModule;
-use tracing::{error, info};
+use tracing::{error, info, info_span, Instrument};
 
+use crate::alerts::{self, AlertEvent};
 use crate::metrics;
 
 /// A state that has context for the API, passed to each rpc handler callback
@@ -117,13 +119,18 @@ pub fn attach_endpoints<State, T>(
                 // end up with an inconsistent state in theory. In practice most API functions
                 // are only reading and the few that do write anything are atomic. Lastly, this
                 // is only the last line of defense
-                AssertUnwindSafe(tokio::time::timeout(API_ENDPOINT_TIMEOUT, async {
-                    let request = serde_json::from_value(params)
-                        .map_err(|e| ApiError::bad_request(e.to_string()))?;
-                    let (state, context) = rpc_context.context(&request, module_instance_id).await;
-
-                    (handler)(state, context, request).await
-                }))
+                AssertUnwindSafe(runtime::timeout(
+                    API_ENDPOINT_TIMEOUT,
+                    async {
+                        let request = serde_json::from_value(params)
+                            .map_err(|e| ApiError::bad_request(e.to_string()))?;
+                        let (state, context) =
+                            rpc_context.context(&request, module_instance_id).await;
+
+                        (handler)(state, context, request).await
+                    }
+                    .instrument(info_span!(target: LOG_NET_API, "api_request", path)),
+                ))
                 .catch_unwind()
                 .await
                 .map_err(|_| {
@@ -131,13 +138,16 @@ pub fn attach_endpoints<State, T>(
                         target: LOG_NET_API,
                         path, "API handler panicked, DO NOT IGNORE, FIX IT!!!"
                     );
-                    ErrorObject::owned(500, "API handler panicked", None::<()>)
+                    alerts::report(AlertEvent::ApiPanic {
+                        path: path.to_string(),
+                    });
+                    ErrorObject::owned(ApiError::SERVER_ERROR, "API handler panicked", None::<()>)
                 })?
-                .map_err(|tokio::time::error::Elapsed { .. }| {
+                .map_err(|runtime::Elapsed| {
                     // TODO: find a better error for this, the error we used before:
                     // jsonrpsee::core::Error::RequestTimeout
                     // was moved to be client-side only
-                    ErrorObject::owned(-32000, "Request timeout", None::<()>)
+                    ErrorObject::owned(ApiError::TIMEOUT, "Request timeout", None::<()>)
                 })?
                 .map_err(|e| ErrorObject::owned(e.code, e.message, None::<()>))
             })
@@ -14,10 +14,13 @@ use fedimint_core::PeerId;
 use futures::Stream;
 use tokio::io::{ReadHalf, WriteHalf};
 use tokio::net::{TcpListener, TcpStream};
-use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
+use tokio::sync::watch;
+use tokio_rustls::rustls::client::ResolvesClientCert;
+use tokio_rustls::rustls::server::{AllowAnyAuthenticatedClient, ClientHello, ResolvesServerCert};
 use tokio_rustls::rustls::RootCertStore;
 use tokio_rustls::{rustls, TlsAcceptor, TlsConnector, TlsStream};
 
+use crate::config::key_store::DynKeyStore;
 use crate::net::framed::{AnyFramedTransport, BidiFramed, FramedTransport};
 
 /// Shared [`Connector`] trait object
@@ -56,24 +59,87 @@ pub trait Connector<M> {
 }
 
 /// TCP connector with encryption and authentication
+///
+/// The TLS config is read from a [`watch`] channel for every new connection,
+/// so certificates can be rotated without restarting the connector.
 #[derive(Debug)]
 pub struct TlsTcpConnector {
-    our_certificate: rustls::Certificate,
-    our_private_key: rustls::PrivateKey,
-    peer_certs: Arc<PeerCertStore>,
-    /// Copy of the certs from `peer_certs`, but in a format that `tokio_rustls`
-    /// understands
-    cert_store: RootCertStore,
-    peer_names: BTreeMap<PeerId, String>,
+    our_id: PeerId,
+    cfg: watch::Receiver<TlsConfig>,
+    /// Largest frame we accept from a peer, zero disables the limit
+    max_frame_bytes: u64,
 }
 
 #[derive(Debug, Clone)]
 pub struct TlsConfig {
-    pub our_private_key: rustls::PrivateKey,
+    pub our_private_key: TlsPrivateKey,
     pub peer_certs: BTreeMap<PeerId, rustls::Certificate>,
+    /// Certificates that were rotated out recently and are still accepted
+    /// from the respective peers in addition to `peer_certs`
+    pub previous_peer_certs: BTreeMap<PeerId, rustls::Certificate>,
     pub peer_names: BTreeMap<PeerId, String>,
 }
 
+/// Private key of our certificate
+#[derive(Debug, Clone)]
+pub enum TlsPrivateKey {
+    /// DER encoded key, as generated for the setup or a certificate rotation
+    Der(rustls::PrivateKey),
+    /// Key held by the key store of the guardian, e.g. an HSM
+    KeyStore(DynKeyStore),
+}
+
+impl From<rustls::PrivateKey> for TlsPrivateKey {
+    fn from(key: rustls::PrivateKey) -> Self {
+        TlsPrivateKey::Der(key)
+    }
+}
+
+impl TlsPrivateKey {
+    fn signing_key(&self) -> Arc<dyn rustls::sign::SigningKey> {
+        match self {
+            TlsPrivateKey::Der(key) => {
+                rustls::sign::any_supported_type(key).expect("Could not load our private key")
+            }
+            TlsPrivateKey::KeyStore(key_store) => key_store.tls_signing_key(),
+        }
+    }
+}
+
+/// Presents our certificate in every handshake, as both server and client
+struct OurCertResolver(Arc<rustls::sign::CertifiedKey>);
+
+impl ResolvesServerCert for OurCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        Some(self.0.clone())
+    }
+}
+
+impl ResolvesClientCert for OurCertResolver {
+    fn resolve(
+        &self,
+        _acceptable_issuers: &[&[u8]],
+        _sigschemes: &[rustls::SignatureScheme],
+    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        Some(self.0.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// Everything needed to open or accept a single connection, derived from the
+/// [`TlsConfig`] at the time the connection is established
+struct TlsConnectionConfig {
+    our_key: Arc<rustls::sign::CertifiedKey>,
+    peer_certs: PeerCertStore,
+    /// Copy of the certs from `peer_certs`, but in a format that `tokio_rustls`
+    /// understands
+    cert_store: RootCertStore,
+    peer_names: BTreeMap<PeerId, String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct PeerCertStore {
     peer_certificates: Vec<(PeerId, rustls::Certificate)>,
@@ -81,21 +147,67 @@ pub struct PeerCertStore {
 
 impl TlsTcpConnector {
     pub fn new(cfg: TlsConfig, our_id: PeerId) -> TlsTcpConnector {
+        TlsTcpConnector::new_with_updates(watch::channel(cfg).1, our_id)
+    }
+
+    /// Creates a connector that uses the latest [`TlsConfig`] sent on the
+    /// channel for every new connection
+    pub fn new_with_updates(cfg: watch::Receiver<TlsConfig>, our_id: PeerId) -> TlsTcpConnector {
+        TlsTcpConnector {
+            our_id,
+            cfg,
+            max_frame_bytes: 0,
+        }
+    }
+
+    /// Rejects frames larger than `max_frame_bytes` received on the
+    /// connections, see [`BidiFramed::with_max_frame_bytes`]
+    pub fn with_max_frame_bytes(mut self, max_frame_bytes: u64) -> TlsTcpConnector {
+        self.max_frame_bytes = max_frame_bytes;
+        self
+    }
+
+    fn connection_config(&self) -> TlsConnectionConfig {
+        TlsConnectionConfig::new(&self.cfg.borrow(), self.our_id)
+    }
+}
+
+impl TlsConnectionConfig {
+    fn new(cfg: &TlsConfig, our_id: PeerId) -> TlsConnectionConfig {
+        let peer_certs = PeerCertStore::new(
+            cfg.peer_certs
+                .iter()
+                .chain(cfg.previous_peer_certs.iter())
+                .map(|(peer, cert)| (*peer, cert.clone())),
+        );
+
         let mut cert_store = RootCertStore::empty();
-        for (_, cert) in cfg.peer_certs.iter() {
+        for (_, cert) in &peer_certs.peer_certificates {
             cert_store
                 .add(cert)
                 .expect("Could not add peer certificate");
         }
 
-        TlsTcpConnector {
-            our_certificate: cfg.peer_certs.get(&our_id).expect("exists").clone(),
-            our_private_key: cfg.our_private_key,
-            peer_certs: Arc::new(PeerCertStore::new(cfg.peer_certs)),
+        let our_certificate = cfg.peer_certs.get(&our_id).expect("exists").clone();
+
+        TlsConnectionConfig {
+            our_key: Arc::new(rustls::sign::CertifiedKey::new(
+                vec![our_certificate],
+                cfg.our_private_key.signing_key(),
+            )),
+            peer_certs,
             cert_store,
-            peer_names: cfg.peer_names,
+            peer_names: cfg.peer_names.clone(),
         }
     }
+
+    fn server_config(&self) -> rustls::ServerConfig {
+        let verifier = AllowAnyAuthenticatedClient::new(self.cert_store.clone());
+        rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(Arc::from(verifier))
+            .with_cert_resolver(Arc::new(OurCertResolver(self.our_key.clone())))
+    }
 }
 
 impl PeerCertStore {
@@ -130,28 +242,6 @@ impl PeerCertStore {
         self.get_peer_by_cert(received_cert)
             .ok_or_else(|| anyhow::anyhow!("Unknown certificate"))
     }
-
-    async fn accept_connection<M>(
-        &self,
-        listener: &mut TcpListener,
-        acceptor: &TlsAcceptor,
-    ) -> Result<(PeerId, AnyFramedTransport<M>), anyhow::Error>
-    where
-        M: Debug + serde::Serialize + serde::de::DeserializeOwned + Send + Unpin + 'static,
-    {
-        let (connection, _) = listener.accept().await?;
-        let tls_conn = acceptor.accept(connection).await?;
-
-        let (_, tls_session) = tls_conn.get_ref();
-        let auth_peer = self.authenticate_peer(tls_session.peer_certificates())?;
-
-        let framed =
-            BidiFramed::<_, WriteHalf<TlsStream<TcpStream>>, ReadHalf<TlsStream<TcpStream>>>::new(
-                tls_conn,
-            )
-            .into_dyn();
-        Ok((auth_peer, framed))
-    }
 }
 
 #[async_trait]
@@ -160,17 +250,15 @@ where
     M: Debug + serde::Serialize + serde::de::DeserializeOwned + Send + Unpin + 'static,
 {
     async fn connect_framed(&self, destination: SafeUrl, peer: PeerId) -> ConnectResult<M> {
+        let tls = self.connection_config();
+
         let cfg = rustls::ClientConfig::builder()
             .with_safe_defaults()
-            .with_root_certificates(self.cert_store.clone())
-            .with_client_auth_cert(
-                vec![self.our_certificate.clone()],
-                self.our_private_key.clone(),
-            )
-            .expect("Failed to create TLS config");
+            .with_root_certificates(tls.cert_store.clone())
+            .with_client_cert_resolver(Arc::new(OurCertResolver(tls.our_key.clone())));
 
         let fake_domain =
-            rustls::ServerName::try_from(dns_sanitize(&self.peer_names[&peer]).as_str())
+            rustls::ServerName::try_from(dns_sanitize(&tls.peer_names[&peer]).as_str())
                 .expect("Always a valid DNS name");
 
         let connector = TlsConnector::from(Arc::new(cfg));
@@ -182,7 +270,7 @@ where
             .await?;
 
         let (_, tls_session) = tls_conn.get_ref();
-        let auth_peer = self
+        let auth_peer = tls
             .peer_certs
             .authenticate_peer(tls_session.peer_certificates())?;
 
@@ -194,37 +282,58 @@ where
             BidiFramed::<_, WriteHalf<TlsStream<TcpStream>>, ReadHalf<TlsStream<TcpStream>>>::new(
                 tls_conn,
             )
+            .with_max_frame_bytes(self.max_frame_bytes)
             .into_dyn();
 
         Ok((peer, framed))
     }
 
     async fn listen(&self, bind_addr: SocketAddr) -> Result<ConnectionListener<M>, anyhow::Error> {
-        let verifier = AllowAnyAuthenticatedClient::new(self.cert_store.clone());
-        let config = rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_client_cert_verifier(Arc::from(verifier))
-            .with_single_cert(
-                vec![self.our_certificate.clone()],
-                self.our_private_key.clone(),
-            )
-            .unwrap();
         let listener = TcpListener::bind(bind_addr).await?;
-        let peer_certs = self.peer_certs.clone();
+        let our_id = self.our_id;
+        let max_frame_bytes = self.max_frame_bytes;
 
-        let stream = futures::stream::unfold(listener, move |mut listener| {
-            let acceptor = TlsAcceptor::from(Arc::new(config.clone()));
-            let peer_certs = peer_certs.clone();
-
-            Box::pin(async move {
-                let res = peer_certs.accept_connection(&mut listener, &acceptor).await;
-                Some((res, listener))
-            })
-        });
+        let stream =
+            futures::stream::unfold((listener, self.cfg.clone()), move |(mut listener, cfg)| {
+                Box::pin(async move {
+                    let res = accept_connection(&mut listener, &cfg, our_id, max_frame_bytes).await;
+                    Some((res, (listener, cfg)))
+                })
+            });
         Ok(Box::pin(stream))
     }
 }
 
+async fn accept_connection<M>(
+    listener: &mut TcpListener,
+    cfg: &watch::Receiver<TlsConfig>,
+    our_id: PeerId,
+    max_frame_bytes: u64,
+) -> ConnectResult<M>
+where
+    M: Debug + serde::Serialize + serde::de::DeserializeOwned + Send + Unpin + 'static,
+{
+    let (connection, _) = listener.accept().await?;
+
+    // Certificates may have been rotated while we were waiting for the connection
+    let tls = TlsConnectionConfig::new(&cfg.borrow(), our_id);
+    let acceptor = TlsAcceptor::from(Arc::new(tls.server_config()));
+    let tls_conn = acceptor.accept(connection).await?;
+
+    let (_, tls_session) = tls_conn.get_ref();
+    let auth_peer = tls
+        .peer_certs
+        .authenticate_peer(tls_session.peer_certificates())?;
+
+    let framed =
+        BidiFramed::<_, WriteHalf<TlsStream<TcpStream>>, ReadHalf<TlsStream<TcpStream>>>::new(
+            tls_conn,
+        )
+        .with_max_frame_bytes(max_frame_bytes)
+        .into_dyn();
+    Ok((auth_peer, framed))
+}
+
 /// Sanitizes name as valid domain name
 pub fn dns_sanitize(name: &str) -> String {
     let sanitized = name.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
@@ -831,6 +940,7 @@ pub mod mock {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
     use std::net::SocketAddr;
 
     use fedimint_core::runtime::spawn;
@@ -854,12 +964,13 @@ mod tests {
         peer_keys
             .iter()
             .map(|(_cert, key)| TlsConfig {
-                our_private_key: key.clone(),
+                our_private_key: key.clone().into(),
                 peer_certs: peer_keys
                     .iter()
                     .enumerate()
                     .map(|(peer, (cert, _))| (PeerId::from(peer as u16), cert.clone()))
                     .collect(),
+                previous_peer_certs: BTreeMap::new(),
                 peer_names: peer_keys
                     .iter()
                     .enumerate()
@@ -905,6 +1016,54 @@ mod tests {
         server_task.await.unwrap();
     }
 
+    #[tokio::test]
+    async fn connect_rotated_cert() {
+        let bind_addr: SocketAddr = "127.0.0.1:7002".parse().unwrap();
+        let url: SafeUrl = "ws://127.0.0.1:7002".parse().unwrap();
+        let cfg = gen_connector_config(3);
+
+        let (new_cert, new_key) = gen_cert_and_key("peer-2").unwrap();
+
+        // Peer 0 already processed the rotation of peer 2
+        let mut server_cfg = cfg[0].clone();
+        let old_cert = server_cfg
+            .peer_certs
+            .insert(PeerId::from(2), new_cert.clone());
+        server_cfg
+            .previous_peer_certs
+            .insert(PeerId::from(2), old_cert.unwrap());
+
+        let mut rotated_cfg = cfg[2].clone();
+        rotated_cfg.our_private_key = new_key.into();
+        rotated_cfg.peer_certs.insert(PeerId::from(2), new_cert);
+
+        let mut server: ConnectionListener<u64> = TlsTcpConnector::new(server_cfg, PeerId::from(0))
+            .listen(bind_addr)
+            .await
+            .unwrap();
+
+        let server_task = spawn("server next await", async move {
+            for _ in 0..2 {
+                let (peer, mut conn) = server.next().await.unwrap().unwrap();
+                assert_eq!(peer.to_usize(), 2);
+                assert_eq!(conn.next().await.unwrap().unwrap(), 42);
+            }
+        });
+
+        // Both the previous and the new certificate are accepted
+        for client_cfg in [cfg[2].clone(), rotated_cfg] {
+            let (_, mut conn): (_, AnyFramedTransport<u64>) =
+                TlsTcpConnector::new(client_cfg, PeerId::from(2))
+                    .connect_framed(url.clone(), PeerId::from(0))
+                    .await
+                    .unwrap();
+            conn.send(42).await.unwrap();
+            conn.flush().await.unwrap();
+        }
+
+        server_task.await.unwrap();
+    }
+
     #[tokio::test]
     async fn connect_reject() {
         let bind_addr: SocketAddr = "127.0.0.1:7001".parse().unwrap();
@@ -63,9 +63,20 @@ pub struct BidiFramed<T, WH, RH> {
 /// Framed codec that uses [`bincode`] to encode structs with [`serde`] support
 #[derive(Debug)]
 pub struct BincodeCodec<T> {
+    /// Largest frame we decode, zero disables the limit
+    max_frame_bytes: u64,
     _pd: PhantomData<T>,
 }
 
+/// Error returned when the length prefix of a received frame exceeds the limit
+/// of the [`BincodeCodec`]
+#[derive(Debug, thiserror::Error)]
+#[error("Frame of {length} bytes exceeds the limit of {max_frame_bytes} bytes")]
+pub struct FrameTooLarge {
+    pub length: u64,
+    pub max_frame_bytes: u64,
+}
+
 impl<T, WH, RH> BidiFramed<T, WH, RH>
 where
     WH: AsyncWrite,
@@ -95,6 +106,13 @@ where
     pub fn borrow_parts(&mut self) -> (&mut FramedSink<WH, T>, &mut FramedStream<RH, T>) {
         (&mut self.sink, &mut self.stream)
     }
+
+    /// Rejects received frames larger than `max_frame_bytes` with a
+    /// [`FrameTooLarge`] error before buffering them. Zero disables the limit.
+    pub fn with_max_frame_bytes(mut self, max_frame_bytes: u64) -> Self {
+        self.stream.decoder_mut().max_frame_bytes = max_frame_bytes;
+        self
+    }
 }
 
 impl<T> TcpBidiFramed<T>
@@ -173,6 +191,7 @@ where
 impl<T> BincodeCodec<T> {
     fn new() -> BincodeCodec<T> {
         BincodeCodec {
+            max_frame_bytes: 0,
             _pd: Default::default(),
         }
     }
@@ -222,7 +241,18 @@ where
         }
 
         let length = u64::from_be_bytes(src[0..8].try_into().expect("correct length"));
-        if src.len() < (length as usize) + 8 {
+
+        // Reject the frame before buffering it, a peer could otherwise make us
+        // allocate as much memory as it claims the frame to be long
+        if self.max_frame_bytes != 0 && self.max_frame_bytes < length {
+            return Err(FrameTooLarge {
+                length,
+                max_frame_bytes: self.max_frame_bytes,
+            }
+            .into());
+        }
+
+        if (src.len() as u64) < length.saturating_add(8) {
             trace!(length, buffern_len = src.len(), "Received partial message");
             return Ok(None);
         }
@@ -244,7 +274,7 @@ mod tests {
     use serde::{Deserialize, Serialize};
     use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
 
-    use crate::net::framed::BidiFramed;
+    use crate::net::framed::{BidiFramed, FrameTooLarge};
 
     #[tokio::test]
     async fn test_roundtrip() {
@@ -311,4 +341,29 @@ mod tests {
 
         assert!(received.is_err());
     }
+
+    #[tokio::test]
+    async fn test_reject_oversized_frame() {
+        let (mut sender, recipient) = tokio::io::duplex(1024);
+
+        let mut framed_recipient =
+            BidiFramed::<u64, WriteHalf<DuplexStream>, ReadHalf<DuplexStream>>::new(recipient)
+                .with_max_frame_bytes(100);
+
+        // Only the length prefix is sent, so the frame is rejected without
+        // waiting for its body to be buffered
+        sender.write_all(&u64::MAX.to_be_bytes()).await.unwrap();
+
+        let error = tokio::time::timeout(Duration::from_secs(1), framed_recipient.next())
+            .await
+            .expect("The frame is rejected immediately")
+            .expect("The stream is not closed")
+            .expect_err("The frame exceeds the limit");
+
+        let error = error
+            .downcast::<FrameTooLarge>()
+            .expect("Rejected for its size");
+        assert_eq!(error.length, u64::MAX);
+        assert_eq!(error.max_frame_bytes, 100);
+    }
 }
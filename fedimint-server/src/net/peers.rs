@@ -6,7 +6,7 @@
 //! details.
 
 use std::cmp::{max, min};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fmt::Debug;
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -26,16 +26,17 @@ use rand::{thread_rng, Rng};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::sync::RwLock;
+use tokio::sync::{watch, RwLock};
 use tokio::time::Instant;
 use tracing::{debug, info, instrument, trace, warn};
 
 use crate::atomic_broadcast::Recipient;
 use crate::metrics::{
-    PEER_BANS_COUNT, PEER_CONNECT_COUNT, PEER_DISCONNECT_COUNT, PEER_MESSAGES_COUNT,
+    PEER_BANS_COUNT, PEER_CIRCUIT_BREAKER_TRIPS_COUNT, PEER_CONNECT_COUNT, PEER_DISCONNECT_COUNT,
+    PEER_MESSAGES_COUNT, PEER_RATE_LIMIT_VIOLATIONS_COUNT,
 };
 use crate::net::connect::{AnyConnector, SharedAnyConnector};
-use crate::net::framed::AnyFramedTransport;
+use crate::net::framed::{AnyFramedTransport, FrameTooLarge};
 
 /// Every how many seconds to send an empty message to our peer if we sent no
 /// messages during that time. This helps with reducing the amount of messages
@@ -46,6 +47,11 @@ const PING_INTERVAL: Duration = Duration::from_secs(10);
 /// [`ReconnectPeerConnections`]
 pub type PeerConnector<M> = AnyConnector<PeerMessage<M>>;
 
+/// Wraps the [`PeerConnector`] of the given peer, which lets tests inject
+/// network faults into the connections between peers
+pub type PeerConnectorLayer<M> =
+    Arc<dyn Fn(PeerId, PeerConnector<M>) -> PeerConnector<M> + Send + Sync>;
+
 /// Connection manager that automatically reconnects to peers
 ///
 /// `ReconnectPeerConnections` is based on a
@@ -63,6 +69,7 @@ pub struct ReconnectPeerConnections<T> {
 struct PeerConnection<T> {
     outgoing: async_channel::Sender<T>,
     incoming: async_channel::Receiver<T>,
+    address: Arc<watch::Sender<SafeUrl>>,
 }
 
 /// Specifies the network configuration for federation-internal communication
@@ -90,11 +97,137 @@ struct PeerConnectionStateMachine<M> {
     state: PeerConnectionState<M>,
 }
 
+/// Policy for reconnecting to our peers as configured in the local config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PeerReconnectConfig {
+    /// Delay before the first reconnection attempt
+    pub min_delay_ms: u64,
+    /// The delay grows exponentially with every failed attempt up to this
+    pub max_delay_ms: u64,
+    /// Maximum random delay added to every delay in percent of the delay
+    pub jitter_percent: u64,
+    /// If a peer disconnects this many times within
+    /// `circuit_breaker_window_secs` we stop reconnecting to it for
+    /// `circuit_breaker_cooldown_secs`. Zero disables the circuit breaker.
+    pub circuit_breaker_disconnects: usize,
+    pub circuit_breaker_window_secs: u64,
+    pub circuit_breaker_cooldown_secs: u64,
+}
+
+impl Default for PeerReconnectConfig {
+    fn default() -> Self {
+        PeerReconnectConfig {
+            min_delay_ms: DelayCalculator::PROD_MIN_RETRY_DURATION_MS,
+            max_delay_ms: DelayCalculator::PROD_MAX_RETRY_DURATION_MS,
+            jitter_percent: DelayCalculator::DEFAULT_JITTER_PERCENT,
+            circuit_breaker_disconnects: 10,
+            circuit_breaker_window_secs: 60,
+            circuit_breaker_cooldown_secs: 60,
+        }
+    }
+}
+
+/// Limits on the traffic we accept from every peer as configured in the local
+/// config. A peer exceeding them is disconnected and may not reconnect for
+/// `violation_disconnect_secs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PeerRateLimitConfig {
+    /// Largest encoded message we accept from a peer. Zero disables the limit.
+    pub max_message_bytes: u64,
+    /// Maximum number of messages including pings we accept from a peer per
+    /// second. Zero disables the limit.
+    pub max_messages_per_sec: u32,
+    pub violation_disconnect_secs: u64,
+}
+
+impl Default for PeerRateLimitConfig {
+    fn default() -> Self {
+        PeerRateLimitConfig {
+            max_message_bytes: 32 * 1024 * 1024,
+            max_messages_per_sec: 5000,
+            violation_disconnect_secs: 60,
+        }
+    }
+}
+
+/// Which limit of the [`PeerRateLimitConfig`] a peer exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RateLimitViolation {
+    MessageSize,
+    MessageRate,
+}
+
+impl RateLimitViolation {
+    fn as_str(self) -> &'static str {
+        match self {
+            RateLimitViolation::MessageSize => "message_size",
+            RateLimitViolation::MessageRate => "message_rate",
+        }
+    }
+}
+
+/// Number of messages received from a peer in the current one second window
+#[derive(Debug, Clone, Copy)]
+struct RateWindow {
+    start: Instant,
+    messages: u32,
+}
+
+impl PeerRateLimitConfig {
+    /// Counts a received message against the message rate. The message size
+    /// is limited by the framing of the connection, see
+    /// [`crate::net::framed::FrameTooLarge`].
+    fn check(&self, window: &mut RateWindow, now: Instant) -> Result<(), RateLimitViolation> {
+        if Duration::from_secs(1) <= now.duration_since(window.start) {
+            *window = RateWindow {
+                start: now,
+                messages: 0,
+            };
+        }
+
+        window.messages += 1;
+
+        if self.max_messages_per_sec != 0 && self.max_messages_per_sec < window.messages {
+            return Err(RateLimitViolation::MessageRate);
+        }
+
+        Ok(())
+    }
+}
+
 /// Calculates delays for reconnecting to peers
 #[derive(Debug, Clone, Copy)]
 pub struct DelayCalculator {
     min_retry_duration_ms: u64,
     max_retry_duration_ms: u64,
+    jitter_percent: u64,
+    circuit_breaker: Option<CircuitBreaker>,
+}
+
+/// Stops reconnecting to a flapping peer for a while so it doesn't consume the
+/// bandwidth of our connections to healthy peers
+#[derive(Debug, Clone, Copy)]
+struct CircuitBreaker {
+    max_disconnects: usize,
+    window: Duration,
+    cooldown: Duration,
+}
+
+impl From<&PeerReconnectConfig> for DelayCalculator {
+    fn from(config: &PeerReconnectConfig) -> Self {
+        DelayCalculator {
+            min_retry_duration_ms: config.min_delay_ms,
+            max_retry_duration_ms: config.max_delay_ms,
+            jitter_percent: config.jitter_percent,
+            circuit_breaker: (config.circuit_breaker_disconnects != 0).then(|| CircuitBreaker {
+                max_disconnects: config.circuit_breaker_disconnects,
+                window: Duration::from_secs(config.circuit_breaker_window_secs),
+                cooldown: Duration::from_secs(config.circuit_breaker_cooldown_secs),
+            }),
+        }
+    }
 }
 
 impl DelayCalculator {
@@ -108,14 +241,20 @@ impl DelayCalculator {
     const TEST_MAX_RETRY_DURATION_MS: u64 = 10_000;
     const TEST_MIN_RETRY_DURATION_MS: u64 = 2_000;
 
+    const DEFAULT_JITTER_PERCENT: u64 = 10;
+
     pub const PROD_DEFAULT: Self = Self {
         min_retry_duration_ms: Self::PROD_MIN_RETRY_DURATION_MS,
         max_retry_duration_ms: Self::PROD_MAX_RETRY_DURATION_MS,
+        jitter_percent: Self::DEFAULT_JITTER_PERCENT,
+        circuit_breaker: None,
     };
 
     pub const TEST_DEFAULT: Self = Self {
         min_retry_duration_ms: Self::TEST_MIN_RETRY_DURATION_MS,
         max_retry_duration_ms: Self::TEST_MAX_RETRY_DURATION_MS,
+        jitter_percent: Self::DEFAULT_JITTER_PERCENT,
+        circuit_breaker: None,
     };
 
     const BASE_MS: u64 = 4;
@@ -129,13 +268,40 @@ impl DelayCalculator {
         let delay_ms = max(delay_ms, self.min_retry_duration_ms);
         // sets a ceiling using the max_retry_duration_ms
         let delay_ms = min(delay_ms, self.max_retry_duration_ms);
-        // add a small jitter of up to 10% to smooth out the load on the target peer if
-        // many peers are reconnecting at the same time
-        let jitter_max = delay_ms / 10;
+        // add a small jitter to smooth out the load on the target peer if many peers
+        // are reconnecting at the same time
+        let jitter_max = delay_ms.saturating_mul(self.jitter_percent) / 100;
         let jitter_ms = thread_rng().gen_range(0..max(jitter_max, 1));
         let delay_secs = delay_ms.saturating_add(jitter_ms) as f64 / 1000.0;
         Duration::from_secs_f64(delay_secs)
     }
+
+    /// Records a disconnect of the peer and returns the cooldown during which
+    /// we must not reconnect to it if it disconnected too often recently
+    fn circuit_breaker_cooldown(
+        &self,
+        recent_disconnects: &mut VecDeque<Instant>,
+        now: Instant,
+    ) -> Option<Duration> {
+        let breaker = self.circuit_breaker?;
+
+        recent_disconnects.push_back(now);
+
+        while recent_disconnects
+            .front()
+            .is_some_and(|disconnect| now.duration_since(*disconnect) > breaker.window)
+        {
+            recent_disconnects.pop_front();
+        }
+
+        if recent_disconnects.len() < breaker.max_disconnects {
+            return None;
+        }
+
+        recent_disconnects.clear();
+
+        Some(breaker.cooldown)
+    }
 }
 
 struct CommonPeerConnectionState<M> {
@@ -145,8 +311,10 @@ struct CommonPeerConnectionState<M> {
     our_id_str: String,
     peer_id: PeerId,
     peer_id_str: String,
-    peer_address: SafeUrl,
+    peer_address: watch::Receiver<SafeUrl>,
     delay_calculator: DelayCalculator,
+    recent_disconnects: VecDeque<Instant>,
+    rate_limit: PeerRateLimitConfig,
     connect: SharedAnyConnector<PeerMessage<M>>,
     incoming_connections: Receiver<AnyFramedTransport<PeerMessage<M>>>,
     status_channels: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStatus>>>,
@@ -155,11 +323,16 @@ struct CommonPeerConnectionState<M> {
 struct DisconnectedPeerConnectionState {
     reconnect_at: Instant,
     failed_reconnect_counter: u64,
+    /// Set while the circuit breaker is open or the peer is penalized for
+    /// exceeding the rate limits, we reject incoming connections from the peer
+    /// until then
+    circuit_open_until: Option<Instant>,
 }
 
 struct ConnectedPeerConnectionState<M> {
     connection: AnyFramedTransport<PeerMessage<M>>,
     next_ping: Instant,
+    rate_window: RateWindow,
 }
 
 enum PeerConnectionState<M> {
@@ -179,6 +352,7 @@ where
     pub(crate) async fn new(
         cfg: NetworkConfig,
         delay_calculator: DelayCalculator,
+        rate_limit: PeerRateLimitConfig,
         connect: PeerConnector<T>,
         task_group: &TaskGroup,
         status_channels: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStatus>>>,
@@ -197,6 +371,7 @@ where
                 *peer,
                 peer_address.clone(),
                 delay_calculator,
+                rate_limit,
                 shared_connector.clone(),
                 connection_receiver,
                 status_channels.clone(),
@@ -267,6 +442,24 @@ where
             }
         }
     }
+    /// Changes the address we connect to when reconnecting to `peer`, the
+    /// current connection is kept as long as it is alive
+    pub fn update_peer_address(&self, peer: PeerId, address: SafeUrl) {
+        if let Some(connection) = self.connections.get(&peer) {
+            connection.address.send_if_modified(|current| {
+                if *current == address {
+                    return false;
+                }
+
+                info!(target: LOG_NET_PEER, %peer, %address, "Updated peer address");
+
+                *current = address;
+
+                true
+            });
+        }
+    }
+
     pub fn send_sync(&self, msg: T, recipient: Recipient) {
         match recipient {
             Recipient::Everyone => {
@@ -335,7 +528,7 @@ where
 
 impl<M> PeerConnectionStateMachine<M>
 where
-    M: Debug + Clone,
+    M: Debug + Clone + Serialize,
 {
     async fn run(mut self, task_handle: &TaskHandle) {
         let peer = self.common.peer_id;
@@ -401,7 +594,7 @@ where
 
 impl<M> CommonPeerConnectionState<M>
 where
-    M: Debug + Clone,
+    M: Debug + Clone + Serialize,
 {
     async fn state_transition_connected(
         &mut self,
@@ -438,6 +631,10 @@ where
             Some(message_res) = connected.connection.next() => {
                 match message_res {
                     Ok(peer_message) => {
+                        if let Err(violation) = self.rate_limit.check(&mut connected.rate_window, Instant::now()) {
+                            return Some(self.disconnect_violation(violation));
+                        }
+
                         if let PeerMessage::Message(msg) = peer_message {
                             PEER_MESSAGES_COUNT.with_label_values(&[&self.our_id_str, &self.peer_id_str, "incoming"]).inc();
                             if self.incoming.try_send(msg).is_err(){
@@ -447,6 +644,9 @@ where
 
                         PeerConnectionState::Connected(connected)
                     },
+                    Err(e) if e.is::<FrameTooLarge>() => {
+                        self.disconnect_violation(RateLimitViolation::MessageSize)
+                    },
                     Err(e) => self.disconnect_err(e, 0),
                 }
             },
@@ -474,17 +674,45 @@ where
             Ok(()) => PeerConnectionState::Connected(ConnectedPeerConnectionState {
                 connection: new_connection,
                 next_ping: Instant::now(),
+                rate_window: RateWindow {
+                    start: Instant::now(),
+                    messages: 0,
+                },
             }),
             Err(e) => self.disconnect_err(e, disconnect_count),
         }
     }
 
-    fn disconnect(&self, mut disconnect_count: u64) -> PeerConnectionState<M> {
+    fn disconnect(&mut self, mut disconnect_count: u64) -> PeerConnectionState<M> {
         PEER_DISCONNECT_COUNT
             .with_label_values(&[&self.our_id_str, &self.peer_id_str])
             .inc();
         disconnect_count += 1;
 
+        let now = Instant::now();
+
+        if let Some(cooldown) = self
+            .delay_calculator
+            .circuit_breaker_cooldown(&mut self.recent_disconnects, now)
+        {
+            PEER_CIRCUIT_BREAKER_TRIPS_COUNT
+                .with_label_values(&[&self.our_id_str, &self.peer_id_str])
+                .inc();
+            warn!(
+                target: LOG_NET_PEER,
+                our_id = ?self.our_id,
+                peer = ?self.peer_id,
+                cooldown_secs = cooldown.as_secs(),
+                "Peer is flapping, pausing reconnects"
+            );
+
+            return PeerConnectionState::Disconnected(DisconnectedPeerConnectionState {
+                reconnect_at: now + cooldown,
+                failed_reconnect_counter: disconnect_count,
+                circuit_open_until: Some(now + cooldown),
+            });
+        }
+
         let reconnect_at = {
             let delay = self.delay_calculator.reconnection_delay(disconnect_count);
             let delay_secs = delay.as_secs_f64();
@@ -496,16 +724,51 @@ where
                 delay_secs,
                 "Scheduling reopening of connection"
             );
-            Instant::now() + delay
+            now + delay
         };
 
         PeerConnectionState::Disconnected(DisconnectedPeerConnectionState {
             reconnect_at,
             failed_reconnect_counter: disconnect_count,
+            circuit_open_until: None,
         })
     }
 
-    fn disconnect_err(&self, err: anyhow::Error, disconnect_count: u64) -> PeerConnectionState<M> {
+    /// Disconnects a peer that exceeded the rate limits and refuses to talk to
+    /// it for a while
+    fn disconnect_violation(&mut self, violation: RateLimitViolation) -> PeerConnectionState<M> {
+        PEER_DISCONNECT_COUNT
+            .with_label_values(&[&self.our_id_str, &self.peer_id_str])
+            .inc();
+        PEER_RATE_LIMIT_VIOLATIONS_COUNT
+            .with_label_values(&[&self.our_id_str, &self.peer_id_str, violation.as_str()])
+            .inc();
+
+        let penalty = Duration::from_secs(self.rate_limit.violation_disconnect_secs);
+
+        warn!(
+            target: LOG_NET_PEER,
+            our_id = ?self.our_id,
+            peer = ?self.peer_id,
+            violation = violation.as_str(),
+            penalty_secs = penalty.as_secs(),
+            "Peer exceeded the rate limits, disconnecting"
+        );
+
+        let until = Instant::now() + penalty;
+
+        PeerConnectionState::Disconnected(DisconnectedPeerConnectionState {
+            reconnect_at: until,
+            failed_reconnect_counter: 0,
+            circuit_open_until: Some(until),
+        })
+    }
+
+    fn disconnect_err(
+        &mut self,
+        err: anyhow::Error,
+        disconnect_count: u64,
+    ) -> PeerConnectionState<M> {
         debug!(target: LOG_NET_PEER,
             our_id = ?self.our_id,
             peer = ?self.peer_id, %err, %disconnect_count, "Peer disconnected");
@@ -513,6 +776,13 @@ where
         self.disconnect(disconnect_count)
     }
 
+    #[instrument(
+        name = "peer_send",
+        target = "fm::net::peer",
+        skip_all,
+        fields(peer = %self.peer_id),
+        level = "debug"
+    )]
     async fn send_message_connected(
         &mut self,
         mut connected: ConnectedPeerConnectionState<M>,
@@ -542,6 +812,10 @@ where
         Some(tokio::select! {
             new_connection_res = self.incoming_connections.recv() => {
                 match new_connection_res {
+                    Some(_) if disconnected.circuit_open_until.is_some_and(|until| Instant::now() < until) => {
+                        debug!(target: LOG_NET_PEER, "Rejecting incoming connection while the peer is paused");
+                        PeerConnectionState::Disconnected(disconnected)
+                    },
                     Some(new_connection) => {
                         PEER_CONNECT_COUNT.with_label_values(&[&self.our_id_str, &self.peer_id_str, "incoming"])
                         .inc();
@@ -553,7 +827,7 @@ where
                     },
                 }
             },
-            () = tokio::time::sleep_until(disconnected.reconnect_at), if self.our_id < self.peer_id => {
+            () = sleep_until(disconnected.reconnect_at), if self.our_id < self.peer_id => {
                 // to prevent "reconnection ping-pongs", only the side with lower PeerId is responsible for reconnecting
                 self.reconnect(disconnected).await
             },
@@ -590,10 +864,10 @@ where
 
     async fn try_reconnect(&self) -> Result<AnyFramedTransport<PeerMessage<M>>, anyhow::Error> {
         debug!(target: LOG_NET_PEER, our_id = ?self.our_id, peer = ?self.peer_id, "Trying to reconnect");
-        let (connected_peer, conn) = self
-            .connect
-            .connect_framed(self.peer_address.with_port_or_known_default(), self.peer_id)
-            .await?;
+        // Do not hold the borrow across the await point
+        let address = self.peer_address.borrow().with_port_or_known_default();
+
+        let (connected_peer, conn) = self.connect.connect_framed(address, self.peer_id).await?;
 
         if connected_peer == self.peer_id {
             Ok(conn)
@@ -608,7 +882,7 @@ where
 
 impl<M> PeerConnection<M>
 where
-    M: Debug + Clone + Send + Sync + 'static,
+    M: Debug + Clone + Serialize + Send + Sync + 'static,
 {
     #[allow(clippy::too_many_arguments)]
     async fn new(
@@ -616,6 +890,7 @@ where
         peer_id: PeerId,
         peer_address: SafeUrl,
         delay_calculator: DelayCalculator,
+        rate_limit: PeerRateLimitConfig,
         connect: SharedAnyConnector<PeerMessage<M>>,
         incoming_connections: Receiver<AnyFramedTransport<PeerMessage<M>>>,
         status_channels: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStatus>>>,
@@ -623,6 +898,7 @@ where
     ) -> PeerConnection<M> {
         let (outgoing_sender, outgoing_receiver) = async_channel::bounded(1024);
         let (incoming_sender, incoming_receiver) = async_channel::bounded(1024);
+        let (address_sender, address_receiver) = watch::channel(peer_address);
 
         task_group.spawn(
             format!("io-thread-peer-{peer_id}"),
@@ -632,8 +908,9 @@ where
                     outgoing_receiver,
                     our_id,
                     peer_id,
-                    peer_address,
+                    address_receiver,
                     delay_calculator,
+                    rate_limit,
                     connect,
                     incoming_connections,
                     status_channels,
@@ -646,6 +923,7 @@ where
         PeerConnection {
             outgoing: outgoing_sender,
             incoming: incoming_receiver,
+            address: Arc::new(address_sender),
         }
     }
 
@@ -672,8 +950,9 @@ where
         outgoing: async_channel::Receiver<M>,
         our_id: PeerId,
         peer_id: PeerId,
-        peer_address: SafeUrl,
+        peer_address: watch::Receiver<SafeUrl>,
         delay_calculator: DelayCalculator,
+        rate_limit: PeerRateLimitConfig,
         connect: SharedAnyConnector<PeerMessage<M>>,
         incoming_connections: Receiver<AnyFramedTransport<PeerMessage<M>>>,
         status_channels: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStatus>>>,
@@ -688,6 +967,8 @@ where
             peer_id,
             peer_address,
             delay_calculator,
+            recent_disconnects: VecDeque::new(),
+            rate_limit,
             connect,
             incoming_connections,
             status_channels,
@@ -695,6 +976,7 @@ where
         let initial_state = PeerConnectionState::Disconnected(DisconnectedPeerConnectionState {
             reconnect_at: Instant::now(),
             failed_reconnect_counter: 0,
+            circuit_open_until: None,
         });
 
         let state_machine = PeerConnectionStateMachine {
@@ -708,7 +990,7 @@ where
 
 #[cfg(test)]
 mod tests {
-    use std::collections::{BTreeMap, HashMap};
+    use std::collections::{BTreeMap, HashMap, VecDeque};
     use std::sync::Arc;
     use std::time::Duration;
 
@@ -718,8 +1000,11 @@ mod tests {
     use fedimint_core::util::retry;
     use fedimint_core::PeerId;
     use tokio::sync::RwLock;
+    use tokio::time::Instant;
 
-    use super::DelayCalculator;
+    use super::{
+        DelayCalculator, PeerRateLimitConfig, PeerReconnectConfig, RateLimitViolation, RateWindow,
+    };
     use crate::net::connect::mock::{MockNetwork, StreamReliability};
     use crate::net::connect::Connector;
     use crate::net::peers::{NetworkConfig, ReconnectPeerConnections};
@@ -759,6 +1044,7 @@ mod tests {
                 let connection = ReconnectPeerConnections::<u64>::new(
                     cfg,
                     DelayCalculator::TEST_DEFAULT,
+                    PeerRateLimitConfig::default(),
                     connect,
                     &task_group,
                     Arc::clone(&status_channels),
@@ -822,4 +1108,84 @@ mod tests {
         assert!((10..20).contains(&c.reconnection_delay(1).as_millis()));
         assert!((10000..11000).contains(&c.reconnection_delay(10).as_millis()));
     }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let c = DelayCalculator::from(&PeerReconnectConfig {
+            circuit_breaker_disconnects: 3,
+            circuit_breaker_window_secs: 10,
+            circuit_breaker_cooldown_secs: 60,
+            ..PeerReconnectConfig::default()
+        });
+
+        let start = Instant::now();
+        let mut recent_disconnects = VecDeque::new();
+
+        assert_eq!(
+            c.circuit_breaker_cooldown(&mut recent_disconnects, start),
+            None
+        );
+        assert_eq!(
+            c.circuit_breaker_cooldown(&mut recent_disconnects, start + Duration::from_secs(1)),
+            None
+        );
+
+        // The first disconnect has left the window
+        assert_eq!(
+            c.circuit_breaker_cooldown(&mut recent_disconnects, start + Duration::from_secs(11)),
+            None
+        );
+        assert_eq!(
+            c.circuit_breaker_cooldown(&mut recent_disconnects, start + Duration::from_secs(11)),
+            Some(Duration::from_secs(60))
+        );
+        assert!(recent_disconnects.is_empty());
+
+        let c = DelayCalculator::from(&PeerReconnectConfig {
+            circuit_breaker_disconnects: 0,
+            ..PeerReconnectConfig::default()
+        });
+
+        for _ in 0..100 {
+            assert_eq!(
+                c.circuit_breaker_cooldown(&mut recent_disconnects, start),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let limit = PeerRateLimitConfig {
+            max_message_bytes: 100,
+            max_messages_per_sec: 2,
+            violation_disconnect_secs: 60,
+        };
+
+        let start = Instant::now();
+        let mut window = RateWindow { start, messages: 0 };
+
+        assert_eq!(limit.check(&mut window, start), Ok(()));
+        assert_eq!(limit.check(&mut window, start), Ok(()));
+        assert_eq!(
+            limit.check(&mut window, start),
+            Err(RateLimitViolation::MessageRate)
+        );
+
+        // A new window starts after one second
+        assert_eq!(
+            limit.check(&mut window, start + Duration::from_secs(1)),
+            Ok(())
+        );
+
+        let unlimited = PeerRateLimitConfig {
+            max_message_bytes: 0,
+            max_messages_per_sec: 0,
+            violation_disconnect_secs: 60,
+        };
+
+        for _ in 0..100 {
+            assert_eq!(unlimited.check(&mut window, start), Ok(()));
+        }
+    }
 }
@@ -585,7 +585,7 @@ where
                 }
                 PeerConnectionState::Disconnected(disconnected)
             },
-            () = tokio::time::sleep_until(disconnected.reconnect_at), if self.our_id < self.peer_id => {
+            () = sleep_until(disconnected.reconnect_at), if self.our_id < self.peer_id => {
                 // to prevent "reconnection ping-pongs", only the side with lower PeerId is responsible for reconnecting
                 self.reconnect(disconnected).await
             },
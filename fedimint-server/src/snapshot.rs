@@ -0,0 +1,196 @@
+//! Encrypted point-in-time snapshots of the server database
+//!
+//! A snapshot is taken from a single read-only database transaction, so it is
+//! consistent without having to pause consensus. The raw key-value pairs are
+//! encrypted with a key derived from the guardian password, making the
+//! snapshot safe to store on untrusted media.
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use fedimint_aead::{decrypt, encrypt, get_encryption_key, random_salt};
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCore};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::time::duration_since_epoch;
+use fedimint_logging::LOG_DB;
+use futures::StreamExt;
+use tracing::info;
+
+/// File extension of encrypted database snapshots written to disk
+pub const DB_SNAPSHOT_EXT: &str = "dbsnapshot";
+
+/// Encrypted database snapshot as it is stored on disk or sent over the API
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct EncryptedDbSnapshot {
+    /// Salt used to derive the encryption key from the guardian password
+    pub salt: String,
+    /// Encoded [`DbSnapshot`] encrypted with `fedimint_aead`
+    pub ciphertext: Vec<u8>,
+}
+
+/// All key-value pairs of the database at a single point in time
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encodable, Decodable)]
+pub struct DbSnapshot {
+    /// Unix timestamp in seconds of when the snapshot was taken
+    pub timestamp: u64,
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl DbSnapshot {
+    /// Reads all entries of `db` from a single database transaction
+    pub async fn take(db: &Database) -> anyhow::Result<DbSnapshot> {
+        let mut dbtx = db.begin_transaction_nc().await;
+
+        let entries = dbtx
+            .raw_find_by_prefix(&[])
+            .await?
+            .collect::<Vec<_>>()
+            .await;
+
+        Ok(DbSnapshot {
+            timestamp: duration_since_epoch().as_secs(),
+            entries,
+        })
+    }
+
+    /// Writes all entries into `db`, which is required to be empty
+    pub async fn restore(self, db: &Database) -> anyhow::Result<()> {
+        let mut dbtx = db.begin_transaction().await;
+
+        if dbtx.raw_find_by_prefix(&[]).await?.next().await.is_some() {
+            bail!("Can only restore a database snapshot into an empty database");
+        }
+
+        for (key, value) in &self.entries {
+            dbtx.raw_insert_bytes(key, value).await?;
+        }
+
+        dbtx.commit_tx_result().await
+    }
+
+    pub fn encrypt(&self, password: &str) -> anyhow::Result<EncryptedDbSnapshot> {
+        let salt = random_salt();
+        let key = get_encryption_key(password, &salt)?;
+
+        Ok(EncryptedDbSnapshot {
+            ciphertext: encrypt(self.consensus_encode_to_vec(), &key)?,
+            salt,
+        })
+    }
+
+    /// Encrypts the snapshot on a blocking thread, since encoding and
+    /// encrypting a large database would stall the async runtime
+    pub async fn encrypt_blocking(self, password: String) -> anyhow::Result<EncryptedDbSnapshot> {
+        tokio::task::spawn_blocking(move || self.encrypt(&password)).await?
+    }
+
+    /// Encrypts the snapshot and atomically writes it into `dir` on a blocking
+    /// thread
+    pub async fn write_encrypted(self, password: String, dir: PathBuf) -> anyhow::Result<PathBuf> {
+        tokio::task::spawn_blocking(move || {
+            self.encrypt(&password)?.write_to_dir(&dir, self.timestamp)
+        })
+        .await?
+    }
+}
+
+impl EncryptedDbSnapshot {
+    pub fn decrypt(mut self, password: &str) -> anyhow::Result<DbSnapshot> {
+        let key = get_encryption_key(password, &self.salt)?;
+        let plaintext = decrypt(&mut self.ciphertext, &key)?;
+
+        // Snapshots easily exceed the default decoding limit, the plaintext is
+        // authenticated and finite so we can decode it without one
+        DbSnapshot::consensus_decode_from_finite_reader(
+            &mut Cursor::new(plaintext),
+            &ModuleDecoderRegistry::default(),
+        )
+        .context("Failed to decode database snapshot")
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.consensus_encode_to_vec()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<EncryptedDbSnapshot> {
+        EncryptedDbSnapshot::consensus_decode_from_finite_reader(
+            &mut Cursor::new(bytes),
+            &ModuleDecoderRegistry::default(),
+        )
+        .context("Failed to decode encrypted database snapshot")
+    }
+
+    /// Atomically and durably writes the snapshot into `dir`, named by its
+    /// timestamp
+    pub fn write_to_dir(&self, dir: &Path, timestamp: u64) -> anyhow::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+
+        let path = dir.join(format!("{timestamp}.{DB_SNAPSHOT_EXT}"));
+        let tmp_path = path.with_extension(format!("{DB_SNAPSHOT_EXT}.tmp"));
+
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(&self.to_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &path)?;
+
+        // Persist the rename, otherwise a crash could lose the snapshot after
+        // older ones were already deleted
+        #[cfg(unix)]
+        std::fs::File::open(dir)?.sync_all()?;
+
+        info!(target: LOG_DB, path = %path.display(), "Wrote encrypted database snapshot");
+
+        Ok(path)
+    }
+}
+
+/// Takes an encrypted snapshot of `db` and writes it into `dir`
+pub async fn write_encrypted_snapshot(
+    db: &Database,
+    password: String,
+    dir: PathBuf,
+) -> anyhow::Result<PathBuf> {
+    DbSnapshot::take(db)
+        .await?
+        .write_encrypted(password, dir)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::IRawDatabaseExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn snapshot_roundtrip() {
+        let db = MemDatabase::new().into_database();
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_insert_bytes(&[0x01, 0x02], &[0x03]).await.unwrap();
+        dbtx.raw_insert_bytes(&[0x04], &[0x05, 0x06]).await.unwrap();
+        dbtx.commit_tx().await;
+
+        let snapshot = DbSnapshot::take(&db).await.unwrap();
+        assert_eq!(snapshot.entries.len(), 2);
+
+        let encrypted = snapshot.encrypt("password").unwrap();
+        let encrypted = EncryptedDbSnapshot::from_bytes(&encrypted.to_bytes()).unwrap();
+
+        assert!(encrypted.clone().decrypt("wrong password").is_err());
+
+        let decrypted = encrypted.decrypt("password").unwrap();
+        assert_eq!(decrypted, snapshot);
+
+        let restored_db = MemDatabase::new().into_database();
+        decrypted.restore(&restored_db).await.unwrap();
+        assert_eq!(
+            DbSnapshot::take(&restored_db).await.unwrap().entries,
+            snapshot.entries
+        );
+
+        assert!(snapshot.restore(&restored_db).await.is_err());
+    }
+}
@@ -0,0 +1,725 @@
+#![allow(where_clauses_object_safety)] // https://github.com/dtolnay/async-trait/issues/228
+
+//! SQLite backed database for resource constrained guardians and clients
+//!
+//! All entries live in a single key-value table. Every transaction reads from
+//! its own snapshot of the database, which SQLite provides in WAL mode, and
+//! buffers its writes in memory. On commit the buffered writes are applied in
+//! a write transaction, failing if any of the written keys was modified since
+//! the snapshot was taken, which matches the optimistic transactions of the
+//! RocksDB backend.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{ensure, Context, Result};
+use async_trait::async_trait;
+use fedimint_core::db::{
+    Database, IDatabaseTransactionOps, IDatabaseTransactionOpsCore, IRawDatabase,
+    IRawDatabaseTransaction, PrefixStream,
+};
+use futures::{stream, StreamExt};
+pub use rusqlite;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use tracing::info;
+
+/// How long we wait for other connections to release their locks
+const BUSY_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct SqliteDb {
+    path: PathBuf,
+    read_only: bool,
+    /// Idle connections reused by new transactions
+    connections: Mutex<Vec<Connection>>,
+}
+
+/// Writes of a transaction that were not committed yet, `None` marks a removed
+/// entry
+type WriteSet = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+pub struct SqliteDbTransaction<'a> {
+    db: &'a SqliteDb,
+    /// Connection holding the read transaction of our snapshot, only `None`
+    /// while being dropped
+    connection: Option<Connection>,
+    writes: WriteSet,
+    savepoint: WriteSet,
+}
+
+impl SqliteDb {
+    pub fn open(db_path: impl AsRef<Path>) -> anyhow::Result<SqliteDb> {
+        let db = SqliteDb {
+            path: db_path.as_ref().to_owned(),
+            read_only: false,
+            connections: Mutex::new(vec![]),
+        };
+
+        let connection = db.connect()?;
+
+        // Allows transactions to read from their snapshot while others commit
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS kv (
+                key BLOB PRIMARY KEY NOT NULL,
+                value BLOB NOT NULL
+            ) WITHOUT ROWID;",
+        )?;
+
+        db.release(connection);
+
+        Ok(db)
+    }
+
+    pub fn open_read_only(db_path: impl AsRef<Path>) -> anyhow::Result<SqliteDb> {
+        let db = SqliteDb {
+            path: db_path.as_ref().to_owned(),
+            read_only: true,
+            connections: Mutex::new(vec![]),
+        };
+
+        // Fail early if the database does not exist
+        let connection = db.connect()?;
+        db.release(connection);
+
+        Ok(db)
+    }
+
+    fn connect(&self) -> anyhow::Result<Connection> {
+        let flags = if self.read_only {
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX
+        } else {
+            OpenFlags::SQLITE_OPEN_READ_WRITE
+                | OpenFlags::SQLITE_OPEN_CREATE
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX
+        };
+
+        let connection = Connection::open_with_flags(&self.path, flags)
+            .with_context(|| format!("Unable to open {}", self.path.display()))?;
+
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+
+        // Make sure we never lose data on unclean shutdown
+        connection.pragma_update(None, "synchronous", "FULL")?;
+
+        Ok(connection)
+    }
+
+    fn acquire(&self) -> anyhow::Result<Connection> {
+        match self.connections.lock().expect("Lock poisoned").pop() {
+            Some(connection) => Ok(connection),
+            None => self.connect(),
+        }
+    }
+
+    fn release(&self, connection: Connection) {
+        self.connections
+            .lock()
+            .expect("Lock poisoned")
+            .push(connection);
+    }
+}
+
+impl fmt::Debug for SqliteDb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqliteDb")
+            .field("path", &self.path)
+            .field("read_only", &self.read_only)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> fmt::Debug for SqliteDbTransaction<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SqliteDbTransaction")
+    }
+}
+
+// Will return None if there is no next prefix (i.e prefix is already the last
+// possible/max one)
+fn next_prefix(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut next_prefix = prefix.to_vec();
+
+    while let Some(last) = next_prefix.pop() {
+        if last < u8::MAX {
+            next_prefix.push(last + 1);
+            return Some(next_prefix);
+        }
+    }
+
+    None
+}
+
+#[async_trait]
+impl IRawDatabase for SqliteDb {
+    type Transaction<'a> = SqliteDbTransaction<'a>;
+    async fn begin_transaction<'a>(&'a self) -> SqliteDbTransaction<'a> {
+        let connection = fedimint_core::runtime::block_in_place(|| {
+            let connection = self.acquire()?;
+
+            // In WAL mode the snapshot is taken by the first read of a transaction
+            connection.execute_batch("BEGIN DEFERRED")?;
+            connection.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))?;
+
+            anyhow::Ok(connection)
+        })
+        .expect("Starting a SQLite transaction failed");
+
+        SqliteDbTransaction {
+            db: self,
+            connection: Some(connection),
+            writes: WriteSet::new(),
+            savepoint: WriteSet::new(),
+        }
+    }
+
+    /// SQLite can only reclaim the space of the whole database, which requires
+    /// no other write to be in progress
+    async fn compact_prefix(&self, _key_prefix: &[u8]) -> Result<()> {
+        fedimint_core::runtime::block_in_place(|| {
+            let connection = self.acquire()?;
+            connection.execute_batch("VACUUM")?;
+            self.release(connection);
+
+            Ok(())
+        })
+    }
+}
+
+impl<'a> SqliteDbTransaction<'a> {
+    fn connection(&self) -> &Connection {
+        self.connection
+            .as_ref()
+            .expect("Connection is only taken on drop")
+    }
+
+    /// Reads a value from our snapshot, ignoring our own writes
+    fn snapshot_get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .connection()
+            .query_row("SELECT value FROM kv WHERE key = ?1", params![key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.writes.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => self.snapshot_get(key),
+        }
+    }
+
+    /// Returns the entries with the prefix in ascending order of their keys,
+    /// including our own writes
+    fn find_by_prefix(&self, key_prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let upper = next_prefix(key_prefix);
+
+        let mut statement = match upper {
+            Some(_) => self.connection().prepare_cached(
+                "SELECT key, value FROM kv WHERE key >= ?1 AND key < ?2 ORDER BY key",
+            )?,
+            None => self
+                .connection()
+                .prepare_cached("SELECT key, value FROM kv WHERE key >= ?1 ORDER BY key")?,
+        };
+
+        let row_to_entry = |row: &rusqlite::Row| -> rusqlite::Result<(Vec<u8>, Option<Vec<u8>>)> {
+            Ok((row.get(0)?, Some(row.get(1)?)))
+        };
+
+        let mut entries = match &upper {
+            Some(upper) => statement.query_map(params![key_prefix, upper], row_to_entry)?,
+            None => statement.query_map(params![key_prefix], row_to_entry)?,
+        }
+        .collect::<rusqlite::Result<WriteSet>>()?;
+
+        let upper_bound = match &upper {
+            Some(upper) => Bound::Excluded(upper.clone()),
+            None => Bound::Unbounded,
+        };
+
+        for (key, value) in self
+            .writes
+            .range((Bound::Included(key_prefix.to_vec()), upper_bound))
+        {
+            entries.insert(key.clone(), value.clone());
+        }
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|value| (key, value)))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl<'a> IDatabaseTransactionOpsCore for SqliteDbTransaction<'a> {
+    async fn raw_insert_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        let old_value = fedimint_core::runtime::block_in_place(|| self.get(key))?;
+        self.writes.insert(key.to_vec(), Some(value.to_vec()));
+        Ok(old_value)
+    }
+
+    async fn raw_get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        fedimint_core::runtime::block_in_place(|| self.get(key))
+    }
+
+    async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let old_value = fedimint_core::runtime::block_in_place(|| self.get(key))?;
+        self.writes.insert(key.to_vec(), None);
+        Ok(old_value)
+    }
+
+    async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> Result<PrefixStream<'_>> {
+        let entries = fedimint_core::runtime::block_in_place(|| self.find_by_prefix(key_prefix))?;
+        Ok(Box::pin(stream::iter(entries)))
+    }
+
+    async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> anyhow::Result<()> {
+        let entries = fedimint_core::runtime::block_in_place(|| self.find_by_prefix(key_prefix))?;
+
+        for (key, _) in entries {
+            self.writes.insert(key, None);
+        }
+
+        Ok(())
+    }
+
+    async fn raw_find_by_prefix_sorted_descending(
+        &mut self,
+        key_prefix: &[u8],
+    ) -> Result<PrefixStream<'_>> {
+        let mut entries =
+            fedimint_core::runtime::block_in_place(|| self.find_by_prefix(key_prefix))?;
+        entries.reverse();
+        Ok(Box::pin(stream::iter(entries)))
+    }
+}
+
+#[async_trait]
+impl<'a> IDatabaseTransactionOps for SqliteDbTransaction<'a> {
+    async fn rollback_tx_to_savepoint(&mut self) -> Result<()> {
+        self.writes = self.savepoint.clone();
+        Ok(())
+    }
+
+    async fn set_tx_savepoint(&mut self) -> Result<()> {
+        self.savepoint = self.writes.clone();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'a> IRawDatabaseTransaction for SqliteDbTransaction<'a> {
+    async fn commit_tx(mut self) -> Result<()> {
+        let writes = std::mem::take(&mut self.writes);
+
+        if writes.is_empty() {
+            return Ok(());
+        }
+
+        fedimint_core::runtime::block_in_place(|| {
+            let snapshot_values = writes
+                .keys()
+                .map(|key| self.snapshot_get(key))
+                .collect::<Result<Vec<_>>>()?;
+
+            let connection = self.connection();
+
+            // End our snapshot and wait for all other writers
+            connection.execute_batch("COMMIT; BEGIN IMMEDIATE")?;
+
+            for ((key, value), snapshot_value) in writes.iter().zip(snapshot_values) {
+                let current_value: Option<Vec<u8>> = connection
+                    .query_row("SELECT value FROM kv WHERE key = ?1", params![key], |row| {
+                        row.get(0)
+                    })
+                    .optional()?;
+
+                ensure!(current_value == snapshot_value, "write-write conflict");
+
+                match value {
+                    Some(value) => connection.execute(
+                        "INSERT OR REPLACE INTO kv (key, value) VALUES (?1, ?2)",
+                        params![key, value],
+                    )?,
+                    None => connection.execute("DELETE FROM kv WHERE key = ?1", params![key])?,
+                };
+            }
+
+            connection.execute_batch("COMMIT")?;
+
+            Ok(())
+        })
+    }
+}
+
+impl<'a> Drop for SqliteDbTransaction<'a> {
+    fn drop(&mut self) {
+        let Some(connection) = self.connection.take() else {
+            return;
+        };
+
+        // Ends our snapshot or discards a failed commit
+        if !connection.is_autocommit() && connection.execute_batch("ROLLBACK").is_err() {
+            return;
+        }
+
+        self.db.release(connection);
+    }
+}
+
+/// Copies all entries of an existing database, e.g. one backed by RocksDB,
+/// into an empty database in a single transaction. The key-value layout is the
+/// same for all backends, so the entries are copied as they are.
+pub async fn migrate_database(source: &Database, target: &Database) -> anyhow::Result<usize> {
+    let entries = source
+        .begin_transaction_nc()
+        .await
+        .raw_find_by_prefix(&[])
+        .await?
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut dbtx = target.begin_transaction().await;
+
+    ensure!(
+        dbtx.raw_find_by_prefix(&[]).await?.next().await.is_none(),
+        "The target database is not empty"
+    );
+
+    for (key, value) in &entries {
+        dbtx.raw_insert_bytes(key, value).await?;
+    }
+
+    dbtx.commit_tx_result().await?;
+
+    info!(entries = entries.len(), "Migrated database");
+
+    Ok(entries.len())
+}
+
+/// Opens the database at `db_path`. If it does not exist yet, it is created
+/// from the entries of the legacy database returned by `legacy`, if there is
+/// one. The migration writes into a separate file that is only moved into place
+/// once complete, so an interrupted migration is simply started over.
+pub async fn open_or_migrate(
+    db_path: impl AsRef<Path>,
+    legacy: impl FnOnce() -> anyhow::Result<Option<Database>>,
+) -> anyhow::Result<SqliteDb> {
+    let db_path = db_path.as_ref();
+
+    if !db_path.exists() {
+        if let Some(legacy) = legacy()? {
+            let migration_path = db_path.with_extension("migration");
+
+            let target = Database::new(SqliteDb::open(&migration_path)?, Default::default());
+            migrate_database(&legacy, &target).await?;
+            drop(target);
+
+            // Moves the entries from the write-ahead log into the database file
+            Connection::open(&migration_path)?.pragma_update_and_check(
+                None,
+                "journal_mode",
+                "DELETE",
+                |_| Ok(()),
+            )?;
+
+            std::fs::rename(&migration_path, db_path)?;
+        }
+    }
+
+    SqliteDb::open(db_path)
+}
+
+#[cfg(test)]
+mod fedimint_sqlite_tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{IDatabaseTransactionOpsCoreTyped, IRawDatabaseExt};
+    use fedimint_core::encoding::{Decodable, Encodable};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::{impl_db_lookup, impl_db_record};
+
+    use super::*;
+
+    fn open_temp_db(temp_path: &str) -> Database {
+        let path = tempfile::Builder::new()
+            .prefix(temp_path)
+            .tempdir()
+            .unwrap();
+
+        Database::new(
+            SqliteDb::open(path.into_path().join("database.sqlite")).unwrap(),
+            ModuleDecoderRegistry::default(),
+        )
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_insert_elements() {
+        fedimint_core::db::verify_insert_elements(open_temp_db("fcb-sqlite-test-insert-elements"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_remove_nonexisting() {
+        fedimint_core::db::verify_remove_nonexisting(open_temp_db(
+            "fcb-sqlite-test-remove-nonexisting",
+        ))
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_remove_existing() {
+        fedimint_core::db::verify_remove_existing(open_temp_db("fcb-sqlite-test-remove-existing"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_read_own_writes() {
+        fedimint_core::db::verify_read_own_writes(open_temp_db("fcb-sqlite-test-read-own-writes"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_prevent_dirty_reads() {
+        fedimint_core::db::verify_prevent_dirty_reads(open_temp_db(
+            "fcb-sqlite-test-prevent-dirty-reads",
+        ))
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_find_by_prefix() {
+        fedimint_core::db::verify_find_by_prefix(open_temp_db("fcb-sqlite-test-find-by-prefix"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_commit() {
+        fedimint_core::db::verify_commit(open_temp_db("fcb-sqlite-test-commit")).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_prevent_nonrepeatable_reads() {
+        fedimint_core::db::verify_prevent_nonrepeatable_reads(open_temp_db(
+            "fcb-sqlite-test-prevent-nonrepeatable-reads",
+        ))
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_snapshot_isolation() {
+        fedimint_core::db::verify_snapshot_isolation(open_temp_db(
+            "fcb-sqlite-test-snapshot-isolation",
+        ))
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_rollback_to_savepoint() {
+        fedimint_core::db::verify_rollback_to_savepoint(open_temp_db(
+            "fcb-sqlite-test-rollback-to-savepoint",
+        ))
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_phantom_entry() {
+        fedimint_core::db::verify_phantom_entry(open_temp_db("fcb-sqlite-test-phantom-entry"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_write_conflict() {
+        fedimint_core::db::expect_write_conflict(open_temp_db("fcb-sqlite-test-write-conflict"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_remove_by_prefix() {
+        fedimint_core::db::verify_remove_by_prefix(open_temp_db(
+            "fcb-sqlite-test-remove-by-prefix",
+        ))
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_module_dbtx() {
+        fedimint_core::db::verify_module_prefix(open_temp_db("fcb-sqlite-test-module-prefix"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_module_db() {
+        let module_instance_id = 1;
+
+        fedimint_core::db::verify_module_db(
+            open_temp_db("fcb-sqlite-test-module-db"),
+            open_temp_db("fcb-sqlite-test-module-db-prefix")
+                .with_prefix_module_id(module_instance_id),
+        )
+        .await;
+    }
+
+    #[test]
+    fn test_next_prefix() {
+        assert_eq!(next_prefix(&[1, 2, 3]).unwrap(), vec![1, 2, 4]);
+        assert_eq!(next_prefix(&[1, 2, 255]).unwrap(), vec![1, 3]);
+        assert_eq!(next_prefix(&[1, 255, 255]).unwrap(), vec![2]);
+        assert!(next_prefix(&[255, 255, 255]).is_none());
+        assert_eq!(next_prefix(&[0]).unwrap(), vec![1]);
+        assert!(next_prefix(&[255]).is_none());
+        assert!(next_prefix(&[]).is_none());
+    }
+
+    #[repr(u8)]
+    #[derive(Clone)]
+    pub enum TestDbKeyPrefix {
+        Test = 254,
+        MaxTest = 255,
+    }
+
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Encodable, Decodable)]
+    pub(super) struct TestKey(pub Vec<u8>);
+
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Encodable, Decodable)]
+    pub(super) struct TestVal(pub Vec<u8>);
+
+    #[derive(Debug, Encodable, Decodable)]
+    struct DbPrefixTestPrefix;
+
+    impl_db_record!(
+        key = TestKey,
+        value = TestVal,
+        db_prefix = TestDbKeyPrefix::Test,
+        notify_on_modify = true,
+    );
+    impl_db_lookup!(key = TestKey, query_prefix = DbPrefixTestPrefix);
+
+    #[derive(Debug, Encodable, Decodable)]
+    struct DbPrefixTestPrefixMax;
+
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Encodable, Decodable)]
+    pub(super) struct TestKey2(pub Vec<u8>);
+
+    impl_db_record!(
+        key = TestKey2,
+        value = TestVal,
+        db_prefix = TestDbKeyPrefix::MaxTest, // max/last prefix
+        notify_on_modify = true,
+    );
+    impl_db_lookup!(key = TestKey2, query_prefix = DbPrefixTestPrefixMax);
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_retrieve_descending_order() {
+        let db = open_temp_db("fcb-sqlite-test-descending-order");
+
+        let mut dbtx = db.begin_transaction().await;
+        for key in [0, 254, 255] {
+            dbtx.insert_entry(&TestKey(vec![key]), &TestVal(vec![key]))
+                .await;
+            dbtx.insert_entry(&TestKey2(vec![key]), &TestVal(vec![key]))
+                .await;
+        }
+        dbtx.commit_tx().await;
+
+        let mut dbtx = db.begin_transaction().await;
+        // Uncommitted writes are merged into the results
+        dbtx.insert_entry(&TestKey(vec![1]), &TestVal(vec![1]))
+            .await;
+        dbtx.remove_entry(&TestKey2(vec![254])).await;
+
+        assert_eq!(
+            dbtx.find_by_prefix_sorted_descending(&DbPrefixTestPrefix)
+                .await
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>()
+                .await,
+            vec![
+                TestKey(vec![255]),
+                TestKey(vec![254]),
+                TestKey(vec![1]),
+                TestKey(vec![0])
+            ]
+        );
+
+        assert_eq!(
+            dbtx.find_by_prefix_sorted_descending(&DbPrefixTestPrefixMax)
+                .await
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>()
+                .await,
+            vec![TestKey2(vec![255]), TestKey2(vec![0])]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_migrate_database() {
+        let source = MemDatabase::new().into_database();
+
+        let mut dbtx = source.begin_transaction().await;
+        dbtx.insert_entry(&TestKey(vec![1]), &TestVal(vec![2]))
+            .await;
+        dbtx.insert_entry(&TestKey2(vec![3]), &TestVal(vec![4]))
+            .await;
+        dbtx.commit_tx().await;
+
+        let target = open_temp_db("fcb-sqlite-test-migrate");
+
+        assert_eq!(migrate_database(&source, &target).await.unwrap(), 2);
+
+        let mut dbtx = target.begin_transaction_nc().await;
+        assert_eq!(
+            dbtx.get_value(&TestKey(vec![1])).await,
+            Some(TestVal(vec![2]))
+        );
+        assert_eq!(
+            dbtx.get_value(&TestKey2(vec![3])).await,
+            Some(TestVal(vec![4]))
+        );
+
+        // Refuses to overwrite existing entries
+        assert!(migrate_database(&source, &target).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_open_or_migrate() {
+        let legacy = MemDatabase::new().into_database();
+
+        let mut dbtx = legacy.begin_transaction().await;
+        dbtx.insert_entry(&TestKey(vec![1]), &TestVal(vec![2]))
+            .await;
+        dbtx.commit_tx().await;
+
+        let dir = tempfile::Builder::new()
+            .prefix("fcb-sqlite-test-open-or-migrate")
+            .tempdir()
+            .unwrap();
+        let path = dir.path().join("database.sqlite");
+
+        let db = Database::new(
+            open_or_migrate(&path, || Ok(Some(legacy))).await.unwrap(),
+            ModuleDecoderRegistry::default(),
+        );
+
+        assert_eq!(
+            db.begin_transaction_nc()
+                .await
+                .get_value(&TestKey(vec![1]))
+                .await,
+            Some(TestVal(vec![2]))
+        );
+
+        drop(db);
+
+        // The legacy database is only opened if there is no database yet
+        open_or_migrate(&path, || panic!("Migrated twice"))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,43 @@
+//! Control over the time seen by the federations, gateways and clients of a
+//! test
+
+use std::time::{Duration, SystemTime};
+
+use fedimint_core::time::{advance_test_clock, enable_test_clock};
+use fedimint_logging::LOG_TEST;
+use tracing::debug;
+
+/// Moves the clock of a test forward to fast-forward through invoice expiries,
+/// timeouts and backoff schedules without waiting for them.
+///
+/// Advancing the clock moves [`fedimint_core::time::now`] forward and wakes
+/// every sleep and timeout of [`fedimint_core::runtime`] that expires within
+/// the skipped time, including the timers of the guardians like the interval
+/// of the module consensus proposals and the API request timeout.
+///
+/// The clock is shared by the whole process and can not be turned back, so it
+/// relies on every test running in a process of its own as it does under
+/// `cargo nextest`. Processes outside of the test, like the bitcoind and
+/// lightning nodes of a real test, keep their own clocks.
+#[derive(Debug, Clone, Copy)]
+pub struct TestClock {
+    _private: (),
+}
+
+impl TestClock {
+    /// Makes the runtime follow the test clock, which has to happen before
+    /// the federation of the test starts its timers
+    pub(crate) fn enable() -> Self {
+        enable_test_clock();
+        TestClock { _private: () }
+    }
+
+    pub fn now(&self) -> SystemTime {
+        fedimint_core::time::now()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        debug!(target: LOG_TEST, ?duration, "Advancing test clock");
+        advance_test_clock(duration);
+    }
+}
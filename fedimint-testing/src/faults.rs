@@ -0,0 +1,357 @@
+//! Deterministic fault injection into the connections between the peers of a
+//! [`FederationTest`](crate::federation::FederationTest)
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use fedimint_core::runtime::spawn;
+use fedimint_core::task::sleep;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::PeerId;
+use fedimint_logging::LOG_TEST;
+use fedimint_server::net::connect::{AnyConnector, ConnectResult, ConnectionListener, Connector};
+use fedimint_server::net::framed::{AnyFramedTransport, FramedTransport};
+use fedimint_server::net::peers::PeerConnectorLayer;
+use futures::channel::mpsc;
+use futures::sink::SinkMapErr;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tracing::{debug, trace};
+
+/// Probabilities with which a message sent from one peer to another is
+/// subject to each fault, at most one fault is applied to every message
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FaultSchedule {
+    pub drop_rate: f64,
+    pub delay_rate: f64,
+    /// Upper bound of a delay, which also holds back the messages sent after
+    /// the delayed one like a congested connection does
+    pub max_delay: Duration,
+    pub duplicate_rate: f64,
+    /// Probability that a message is held back and delivered after the next
+    /// message on the same connection
+    pub reorder_rate: f64,
+}
+
+impl FaultSchedule {
+    fn sample(&self, rng: &mut StdRng) -> Fault {
+        let roll = rng.gen_range(0.0..1.0);
+        let delay_bound = self.drop_rate + self.delay_rate;
+        let duplicate_bound = delay_bound + self.duplicate_rate;
+        let reorder_bound = duplicate_bound + self.reorder_rate;
+
+        if roll < self.drop_rate {
+            Fault::Drop
+        } else if roll < delay_bound {
+            Fault::Delay(rng.gen_range(Duration::ZERO..=self.max_delay))
+        } else if roll < duplicate_bound {
+            Fault::Duplicate
+        } else if roll < reorder_bound {
+            Fault::Reorder
+        } else {
+            Fault::Deliver
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fault {
+    Deliver,
+    Drop,
+    Delay(Duration),
+    Duplicate,
+    Reorder,
+}
+
+/// Injects the faults of a seeded [`FaultSchedule`] into the connections
+/// between peers and partitions the network on demand.
+///
+/// Every directed link between two peers draws its faults from its own random
+/// number generator derived from the seed, so a link sees the same sequence of
+/// faults in every run regardless of the traffic on the other links. Clones
+/// share their state, so a test can keep a clone to change the faults while
+/// the federation is running.
+#[derive(Debug, Clone)]
+pub struct NetworkFaults {
+    state: Arc<Mutex<NetworkFaultsState>>,
+}
+
+#[derive(Debug)]
+struct NetworkFaultsState {
+    seed: u64,
+    schedule: FaultSchedule,
+    /// Groups of peers that can only reach each other
+    partitions: Vec<BTreeSet<PeerId>>,
+    links: BTreeMap<(PeerId, PeerId), StdRng>,
+}
+
+impl NetworkFaults {
+    /// Creates a network without faults, which are drawn from `seed` once a
+    /// schedule is set
+    pub fn new(seed: u64) -> Self {
+        NetworkFaults {
+            state: Arc::new(Mutex::new(NetworkFaultsState {
+                seed,
+                schedule: FaultSchedule::default(),
+                partitions: vec![],
+                links: BTreeMap::new(),
+            })),
+        }
+    }
+
+    pub fn set_schedule(&self, schedule: FaultSchedule) {
+        let rates = [
+            schedule.drop_rate,
+            schedule.delay_rate,
+            schedule.duplicate_rate,
+            schedule.reorder_rate,
+        ];
+        assert!(
+            rates.iter().all(|rate| 0.0 <= *rate) && rates.iter().sum::<f64>() <= 1.0,
+            "Fault rates must not be negative and add up to at most one"
+        );
+
+        self.state.lock().expect("Lock poisoned").schedule = schedule;
+    }
+
+    /// Cuts `peers` off from the rest of the federation until [`Self::heal`]
+    /// is called, dropping every message between the two sides
+    pub fn partition(&self, peers: impl IntoIterator<Item = PeerId>) {
+        let peers = peers.into_iter().collect::<BTreeSet<_>>();
+        debug!(target: LOG_TEST, ?peers, "Partitioning network");
+        self.state
+            .lock()
+            .expect("Lock poisoned")
+            .partitions
+            .push(peers);
+    }
+
+    /// Cuts `peer` off from all other peers
+    pub fn isolate(&self, peer: PeerId) {
+        self.partition([peer]);
+    }
+
+    /// Removes all partitions
+    pub fn heal(&self) {
+        debug!(target: LOG_TEST, "Healing network partitions");
+        self.state.lock().expect("Lock poisoned").partitions.clear();
+    }
+
+    /// Returns the layer that injects the faults into the peer connections of
+    /// a guardian
+    pub fn connector_layer<M>(&self) -> PeerConnectorLayer<M>
+    where
+        M: Clone + Debug + Send + Unpin + 'static,
+    {
+        let faults = self.clone();
+        Arc::new(move |our_id, connector| {
+            FaultyConnector {
+                inner: connector,
+                our_id,
+                faults: faults.clone(),
+            }
+            .into_dyn()
+        })
+    }
+
+    fn next_fault(&self, from: PeerId, to: PeerId) -> Fault {
+        let mut state = self.state.lock().expect("Lock poisoned");
+
+        if state
+            .partitions
+            .iter()
+            .any(|peers| peers.contains(&from) != peers.contains(&to))
+        {
+            return Fault::Drop;
+        }
+
+        let NetworkFaultsState {
+            seed,
+            schedule,
+            links,
+            ..
+        } = &mut *state;
+
+        let rng = links.entry((from, to)).or_insert_with(|| {
+            let link = (u64::from(u16::from(from)) << 16) | u64::from(u16::from(to));
+            StdRng::seed_from_u64(*seed ^ link)
+        });
+
+        schedule.sample(rng)
+    }
+
+    /// Routes the messages we send to `peer` over `connection` through a task
+    /// that applies the faults, faults of the messages we receive are applied
+    /// by the peer
+    fn wrap<M>(
+        &self,
+        our_id: PeerId,
+        peer: PeerId,
+        connection: AnyFramedTransport<M>,
+    ) -> AnyFramedTransport<M>
+    where
+        M: Clone + Debug + Send + Unpin + 'static,
+    {
+        let (outgoing_sender, outgoing_receiver) = mpsc::unbounded();
+        let (incoming_sender, incoming_receiver) = mpsc::unbounded();
+
+        spawn(
+            "faulty peer connection",
+            self.clone()
+                .forward(our_id, peer, connection, outgoing_receiver, incoming_sender),
+        );
+
+        FaultyTransport {
+            outgoing: outgoing_sender.sink_map_err(anyhow::Error::from as fn(_) -> _),
+            incoming: incoming_receiver,
+        }
+        .into_dyn()
+    }
+
+    async fn forward<M>(
+        self,
+        our_id: PeerId,
+        peer: PeerId,
+        mut connection: AnyFramedTransport<M>,
+        mut outgoing: mpsc::UnboundedReceiver<M>,
+        incoming: mpsc::UnboundedSender<anyhow::Result<M>>,
+    ) where
+        M: Clone + Debug + Send + Unpin + 'static,
+    {
+        let (sink, stream) = connection.borrow_split();
+
+        let send = async {
+            let mut held = None;
+
+            while let Some(message) = outgoing.next().await {
+                let fault = self.next_fault(our_id, peer);
+                trace!(target: LOG_TEST, %our_id, %peer, ?fault, "Sending peer message");
+
+                let mut messages = vec![];
+                match fault {
+                    Fault::Deliver => messages.push(message),
+                    Fault::Drop => {}
+                    Fault::Delay(delay) => {
+                        sleep(delay).await;
+                        messages.push(message);
+                    }
+                    Fault::Duplicate => messages.extend([message.clone(), message]),
+                    Fault::Reorder => messages.extend(held.replace(message)),
+                }
+
+                if fault != Fault::Reorder {
+                    messages.extend(held.take());
+                }
+
+                for message in messages {
+                    sink.send(message).await?;
+                }
+            }
+
+            anyhow::Ok(())
+        };
+
+        let receive = async {
+            while let Some(message) = stream.next().await {
+                if incoming.unbounded_send(message).is_err() {
+                    break;
+                }
+            }
+        };
+
+        // Dropping the channels once either direction fails closes the
+        // connection for the peer connection state machine
+        tokio::select! {
+            result = send => {
+                if let Err(e) = result {
+                    debug!(target: LOG_TEST, %our_id, %peer, "Faulty connection closed: {e}");
+                }
+            }
+            () = receive => {}
+        }
+    }
+}
+
+struct FaultyConnector<M> {
+    inner: AnyConnector<M>,
+    our_id: PeerId,
+    faults: NetworkFaults,
+}
+
+#[async_trait]
+impl<M> Connector<M> for FaultyConnector<M>
+where
+    M: Clone + Debug + Send + Unpin + 'static,
+{
+    async fn connect_framed(&self, destination: SafeUrl, peer: PeerId) -> ConnectResult<M> {
+        let (peer, connection) = self.inner.connect_framed(destination, peer).await?;
+        Ok((peer, self.faults.wrap(self.our_id, peer, connection)))
+    }
+
+    async fn listen(&self, bind_addr: SocketAddr) -> Result<ConnectionListener<M>, anyhow::Error> {
+        let our_id = self.our_id;
+        let faults = self.faults.clone();
+        let listener = self.inner.listen(bind_addr).await?;
+
+        Ok(Box::pin(listener.map(move |connection| {
+            let (peer, connection) = connection?;
+            Ok((peer, faults.wrap(our_id, peer, connection)))
+        })))
+    }
+}
+
+/// Connection whose messages are passed to and from the task forwarding them
+/// over the wrapped connection
+struct FaultyTransport<M> {
+    outgoing: SinkMapErr<mpsc::UnboundedSender<M>, fn(mpsc::SendError) -> anyhow::Error>,
+    incoming: mpsc::UnboundedReceiver<anyhow::Result<M>>,
+}
+
+impl<M> Sink<M> for FaultyTransport<M> {
+    type Error = anyhow::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: M) -> Result<(), Self::Error> {
+        Pin::new(&mut self.outgoing).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_close(cx)
+    }
+}
+
+impl<M> Stream for FaultyTransport<M> {
+    type Item = anyhow::Result<M>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.incoming.poll_next_unpin(cx)
+    }
+}
+
+impl<M> FramedTransport<M> for FaultyTransport<M>
+where
+    M: Send + 'static,
+{
+    fn borrow_split(
+        &mut self,
+    ) -> (
+        &'_ mut (dyn Sink<M, Error = anyhow::Error> + Send + Unpin),
+        &'_ mut (dyn Stream<Item = Result<M, anyhow::Error>> + Send + Unpin),
+    ) {
+        (&mut self.outgoing, &mut self.incoming)
+    }
+}
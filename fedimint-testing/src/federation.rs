@@ -28,6 +28,8 @@ use fedimint_server::net::connect::parse_host_port;
 use tokio_rustls::rustls;
 use tracing::info;
 
+use crate::faults::NetworkFaults;
+
 /// Test fixture for a running fedimint federation
 #[derive(Clone)]
 pub struct FederationTest {
@@ -138,6 +140,7 @@ pub struct FederationTestBuilder {
     params: ServerModuleConfigGenParamsRegistry,
     server_init: ServerModuleInitRegistry,
     client_init: ClientModuleInitRegistry,
+    network_faults: Option<NetworkFaults>,
 }
 
 impl FederationTestBuilder {
@@ -157,6 +160,7 @@ impl FederationTestBuilder {
             params,
             server_init,
             client_init,
+            network_faults: None,
         }
     }
 
@@ -185,6 +189,13 @@ impl FederationTestBuilder {
         self
     }
 
+    /// Injects `network_faults` into the connections between the peers, keep
+    /// a clone to change the faults while the federation is running
+    pub fn network_faults(mut self, network_faults: NetworkFaults) -> FederationTestBuilder {
+        self.network_faults = Some(network_faults);
+        self
+    }
+
     pub async fn build(self) -> FederationTest {
         let num_offline = self.num_offline;
         assert!(
@@ -209,11 +220,21 @@ impl FederationTestBuilder {
             let db = Database::new(MemDatabase::new(), decoders);
             let module_init_registry = self.server_init.clone();
             let subgroup = task_group.make_subgroup();
+            let connector_layer = self
+                .network_faults
+                .as_ref()
+                .map(NetworkFaults::connector_layer);
 
             task_group.spawn("fedimintd", move |_| async move {
-                consensus::run(config.clone(), db.clone(), module_init_registry, &subgroup)
-                    .await
-                    .expect("Could not initialise consensus");
+                consensus::run_with_connector_layer(
+                    config.clone(),
+                    db.clone(),
+                    module_init_registry,
+                    &subgroup,
+                    connector_layer,
+                )
+                .await
+                .expect("Could not initialise consensus");
             });
         }
 
@@ -22,6 +22,7 @@ use tempfile::TempDir;
 use crate::btc::mock::FakeBitcoinFactory;
 use crate::btc::real::RealBitcoinTest;
 use crate::btc::BitcoinTest;
+use crate::clock::TestClock;
 use crate::envs::{
     FM_PORT_ESPLORA_ENV, FM_TEST_BITCOIND_RPC_ENV, FM_TEST_DIR_ENV, FM_TEST_USE_REAL_DAEMONS_ENV,
 };
@@ -40,6 +41,7 @@ pub struct Fixtures {
     bitcoin_rpc: BitcoinRpcConfig,
     bitcoin: Arc<dyn BitcoinTest>,
     dyn_bitcoin_rpc: DynBitcoindRpc,
+    clock: TestClock,
     id: ModuleInstanceId,
 }
 
@@ -51,6 +53,7 @@ impl Fixtures {
     ) -> Self {
         // Ensure tracing has been set once
         let _ = TracingSetup::default().init();
+        let clock = TestClock::enable();
         let real_testing = Fixtures::is_real_test();
         let task_group = TaskGroup::new();
         let (dyn_bitcoin_rpc, bitcoin, config): (
@@ -80,6 +83,7 @@ impl Fixtures {
             bitcoin_rpc: config,
             bitcoin,
             dyn_bitcoin_rpc,
+            clock,
             id: 0,
         }
         .with_module(client, server, params)
@@ -203,6 +207,11 @@ impl Fixtures {
     pub fn dyn_bitcoin_rpc(&self) -> DynBitcoindRpc {
         self.dyn_bitcoin_rpc.clone()
     }
+
+    /// Get the clock of the test
+    pub fn clock(&self) -> TestClock {
+        self.clock
+    }
 }
 
 /// If `FM_TEST_DIR` is set, use it as a base, otherwise use a tempdir
@@ -1,7 +1,10 @@
 pub mod btc;
+pub mod clock;
 pub mod db;
 pub mod envs;
+pub mod faults;
 pub mod federation;
 pub mod fixtures;
 pub mod gateway;
 pub mod ln;
+pub mod load;
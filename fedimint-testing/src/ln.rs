@@ -16,8 +16,9 @@ use lightning_invoice::{
 };
 use ln_gateway::gateway_lnrpc::{
     self, CloseChannelsWithPeerResponse, CreateInvoiceRequest, CreateInvoiceResponse,
-    EmptyResponse, GetFundingAddressResponse, GetNodeInfoResponse, GetRouteHintsResponse,
-    InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
+    EmptyResponse, GetFundingAddressResponse, GetNodeInfoResponse, GetOnchainBalanceResponse,
+    GetRouteHintsResponse, InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
+    SendOnchainRequest, SendOnchainResponse,
 };
 use ln_gateway::lightning::cln::{HtlcResult, RouteHtlcStream};
 use ln_gateway::lightning::{ChannelInfo, ILnRpcClient, LightningRpcError};
@@ -80,6 +81,25 @@ impl FakeLightningTest {
             .unwrap())
     }
 
+    /// Creates an invoice with a random payment hash, so unlike the invoices
+    /// of [`Self::invoice`] each one can be paid by the same client
+    pub fn unique_invoice(&self, amount: Amount) -> Bolt11Invoice {
+        let ctx = bitcoin::secp256k1::Secp256k1::new();
+        let preimage: [u8; 32] = rand::random();
+
+        // `FakeLightningTest` reveals the payment secret as preimage
+        InvoiceBuilder::new(Currency::Regtest)
+            .description("".to_string())
+            .payment_hash(sha256::Hash::hash(&preimage))
+            .current_timestamp()
+            .min_final_cltv_expiry_delta(0)
+            .payment_secret(PaymentSecret(preimage))
+            .amount_milli_satoshis(amount.msats)
+            .expiry_time(Duration::from_secs(DEFAULT_EXPIRY_TIME))
+            .build_signed(|m| ctx.sign_ecdsa_recoverable(m, &self.gateway_node_sec_key))
+            .expect("Invoice creation failed")
+    }
+
     /// Creates an invoice that is not payable
     ///
     /// * Mocks use hard-coded invoice description to fail the payment
@@ -119,7 +139,7 @@ impl ILnRpcClient for FakeLightningTest {
             alias: "FakeLightningNode".to_string(),
             network: "regtest".to_string(),
             block_height: 0,
-            synced_to_chain: false,
+            synced_to_chain: true,
         })
     }
 
@@ -150,8 +170,16 @@ impl ILnRpcClient for FakeLightningTest {
             });
         }
 
+        // Invoices of `unique_invoice` use their payment secret as preimage
+        let payment_secret = invoice.payment_secret().0;
+        let preimage = if sha256::Hash::hash(&payment_secret) == *invoice.payment_hash() {
+            payment_secret
+        } else {
+            [0; 32]
+        };
+
         Ok(PayInvoiceResponse {
-            preimage: [0; 32].to_vec(),
+            preimage: preimage.to_vec(),
         })
     }
 
@@ -236,4 +264,15 @@ impl ILnRpcClient for FakeLightningTest {
     async fn list_active_channels(&self) -> Result<Vec<ChannelInfo>, LightningRpcError> {
         unimplemented!("FakeLightningTest does not support listing active channels")
     }
+
+    async fn get_onchain_balance(&self) -> Result<GetOnchainBalanceResponse, LightningRpcError> {
+        unimplemented!("FakeLightningTest does not support getting the on-chain balance")
+    }
+
+    async fn send_onchain(
+        &self,
+        _request: SendOnchainRequest,
+    ) -> Result<SendOnchainResponse, LightningRpcError> {
+        unimplemented!("FakeLightningTest does not support sending on-chain")
+    }
 }
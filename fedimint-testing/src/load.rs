@@ -0,0 +1,528 @@
+//! Load generation against the clients of a
+//! [`FederationTest`](crate::federation::FederationTest) that reports the
+//! throughput and latencies of the operations it drives
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context};
+use fedimint_client::ClientHandleArc;
+use fedimint_core::runtime::sleep_until;
+use fedimint_core::secp256k1::PublicKey;
+use fedimint_core::Amount;
+use fedimint_ln_client::{LightningClientModule, LnPayState, PayType};
+use fedimint_logging::LOG_TEST;
+use fedimint_mint_client::{MintClientModule, OOBNotes, ReissueExternalNotesState, SpendOOBState};
+use fedimint_wallet_client::{DepositState, WalletClientModule};
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+use crate::btc::BitcoinTest;
+use crate::ln::FakeLightningTest;
+
+/// How long the notes of a spend can wait to be reissued before the spender
+/// takes them back
+const SPEND_CANCEL_AFTER: Duration = Duration::from_secs(3600);
+
+/// How long the deposit address of a peg-in stays valid
+const PEG_IN_TIMEOUT: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LoadOperation {
+    PegIn,
+    Spend,
+    Reissue,
+    LnPayment,
+}
+
+impl Display for LoadOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadOperation::PegIn => write!(f, "peg_in"),
+            LoadOperation::Spend => write!(f, "spend"),
+            LoadOperation::Reissue => write!(f, "reissue"),
+            LoadOperation::LnPayment => write!(f, "ln_payment"),
+        }
+    }
+}
+
+impl FromStr for LoadOperation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "peg_in" => Ok(LoadOperation::PegIn),
+            "spend" => Ok(LoadOperation::Spend),
+            "reissue" => Ok(LoadOperation::Reissue),
+            "ln_payment" => Ok(LoadOperation::LnPayment),
+            _ => Err(format!("Invalid value for LoadOperation: {s}")),
+        }
+    }
+}
+
+/// Relative weights with which the operations of a load are drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LoadMix {
+    pub peg_ins: u32,
+    pub spends: u32,
+    pub reissues: u32,
+    pub ln_payments: u32,
+}
+
+impl LoadMix {
+    fn weights(&self) -> [(LoadOperation, u32); 4] {
+        [
+            (LoadOperation::PegIn, self.peg_ins),
+            (LoadOperation::Spend, self.spends),
+            (LoadOperation::Reissue, self.reissues),
+            (LoadOperation::LnPayment, self.ln_payments),
+        ]
+    }
+
+    fn total_weight(&self) -> u64 {
+        self.weights()
+            .iter()
+            .map(|(_, weight)| u64::from(*weight))
+            .sum()
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> LoadOperation {
+        let mut roll = rng.gen_range(0..self.total_weight());
+
+        for (operation, weight) in self.weights() {
+            if roll < u64::from(weight) {
+                return operation;
+            }
+            roll -= u64::from(weight);
+        }
+
+        unreachable!("The roll is below the total weight")
+    }
+}
+
+/// Parses a mix like `spend=2,reissue=2,ln_payment=1`, operations that are
+/// not listed are never drawn
+impl FromStr for LoadMix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mix = LoadMix::default();
+
+        for entry in s.split(',') {
+            let (operation, weight) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected <operation>=<weight>, got {entry}"))?;
+            let weight = weight
+                .trim()
+                .parse()
+                .map_err(|e| format!("Invalid weight for {operation}: {e}"))?;
+
+            match operation.trim().parse()? {
+                LoadOperation::PegIn => mix.peg_ins = weight,
+                LoadOperation::Spend => mix.spends = weight,
+                LoadOperation::Reissue => mix.reissues = weight,
+                LoadOperation::LnPayment => mix.ln_payments = weight,
+            }
+        }
+
+        Ok(mix)
+    }
+}
+
+/// Outcomes of all operations of one kind
+#[derive(Debug, Clone, Default)]
+pub struct OperationStats {
+    pub succeeded: u64,
+    pub failed: u64,
+    latencies: Vec<Duration>,
+}
+
+impl OperationStats {
+    /// Latencies of the successful operations, fastest first
+    pub fn latencies(&self) -> &[Duration] {
+        &self.latencies
+    }
+
+    /// Latency within which `percentile` percent of the successful operations
+    /// completed, `None` if none did
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        assert!(
+            (0.0..=100.0).contains(&percentile),
+            "Percentile must be between 0 and 100"
+        );
+
+        let rank = (percentile / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies.get(rank.saturating_sub(1)).copied()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadReport {
+    /// Time from the start of the first operation until the last one finished
+    pub elapsed: Duration,
+    /// Operations that were due while the maximum number of operations was
+    /// still in flight and therefore never started
+    pub skipped: u64,
+    pub operations: BTreeMap<LoadOperation, OperationStats>,
+}
+
+impl LoadReport {
+    pub fn succeeded(&self) -> u64 {
+        self.operations.values().map(|stats| stats.succeeded).sum()
+    }
+
+    pub fn failed(&self) -> u64 {
+        self.operations.values().map(|stats| stats.failed).sum()
+    }
+
+    /// Successful operations per second
+    pub fn throughput(&self) -> f64 {
+        self.succeeded() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    fn record(&mut self, operation: LoadOperation, result: anyhow::Result<Duration>) {
+        let stats = self.operations.entry(operation).or_default();
+
+        match result {
+            Ok(latency) => {
+                stats.succeeded += 1;
+                stats.latencies.push(latency);
+            }
+            Err(e) => {
+                debug!(target: LOG_TEST, %operation, "Load operation failed: {e:#}");
+                stats.failed += 1;
+            }
+        }
+    }
+}
+
+impl Display for LoadReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fn millis(latency: Option<Duration>) -> String {
+            latency.map_or("-".to_string(), |latency| {
+                format!("{}ms", latency.as_millis())
+            })
+        }
+
+        writeln!(
+            f,
+            "{:<12} {:>9} {:>7} {:>8} {:>9} {:>9} {:>9} {:>9}",
+            "operation", "succeeded", "failed", "ops/s", "p50", "p90", "p99", "max"
+        )?;
+
+        for (operation, stats) in &self.operations {
+            writeln!(
+                f,
+                "{:<12} {:>9} {:>7} {:>8.2} {:>9} {:>9} {:>9} {:>9}",
+                operation.to_string(),
+                stats.succeeded,
+                stats.failed,
+                stats.succeeded as f64 / self.elapsed.as_secs_f64(),
+                millis(stats.percentile(50.0)),
+                millis(stats.percentile(90.0)),
+                millis(stats.percentile(99.0)),
+                millis(stats.latencies.last().copied()),
+            )?;
+        }
+
+        write!(
+            f,
+            "{} succeeded, {} failed and {} skipped in {:.1}s ({:.2} ops/s)",
+            self.succeeded(),
+            self.failed(),
+            self.skipped,
+            self.elapsed.as_secs_f64(),
+            self.throughput()
+        )
+    }
+}
+
+/// Drives a [`LoadMix`] of operations at a target rate through the clients of
+/// a federation and reports their throughput and latencies.
+///
+/// The load is open, operations are started at the target rate regardless of
+/// how long the earlier ones take, up to a maximum number of operations in
+/// flight. The operations are drawn from a seeded random number generator and
+/// assigned to the clients in turn, so a run with the same seed issues the
+/// same operations.
+///
+/// Spends, reissues and LN payments need the clients to be funded with
+/// e-cash, reissues take the notes of earlier spends and spend notes first
+/// if there are none.
+pub struct LoadGenerator {
+    clients: Vec<ClientHandleArc>,
+    mix: LoadMix,
+    rate: f64,
+    duration: Duration,
+    max_in_flight: usize,
+    amount: Amount,
+    seed: u64,
+    bitcoin: Option<(Arc<dyn BitcoinTest>, u64)>,
+    gateway_id: Option<PublicKey>,
+    lightning: FakeLightningTest,
+    /// Notes of earlier spends that are waiting to be reissued
+    spent_notes: Mutex<Vec<OOBNotes>>,
+}
+
+impl LoadGenerator {
+    pub fn new(clients: Vec<ClientHandleArc>, mix: LoadMix) -> Self {
+        LoadGenerator {
+            clients,
+            mix,
+            rate: 10.0,
+            duration: Duration::from_secs(10),
+            max_in_flight: 100,
+            amount: Amount::from_sats(100),
+            seed: 0,
+            bitcoin: None,
+            gateway_id: None,
+            lightning: FakeLightningTest::new(),
+            spent_notes: Mutex::new(vec![]),
+        }
+    }
+
+    /// Operations started per second
+    pub fn rate(mut self, rate: f64) -> Self {
+        assert!(rate > 0.0, "Rate must be positive");
+        self.rate = rate;
+        self
+    }
+
+    /// How long operations are started for, the run ends once the operations
+    /// in flight at the end finish
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight;
+        self
+    }
+
+    /// Amount moved by every operation
+    pub fn amount(mut self, amount: Amount) -> Self {
+        self.amount = amount;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Enables peg-ins, which are confirmed by mining `finality_delay` blocks
+    /// on `bitcoin`
+    pub fn peg_ins(mut self, bitcoin: Arc<dyn BitcoinTest>, finality_delay: u64) -> Self {
+        self.bitcoin = Some((bitcoin, finality_delay));
+        self
+    }
+
+    /// Enables LN payments, which are sent through the gateway with
+    /// `gateway_id`
+    pub fn ln_payments(mut self, gateway_id: PublicKey) -> Self {
+        self.gateway_id = Some(gateway_id);
+        self
+    }
+
+    pub async fn run(&self) -> anyhow::Result<LoadReport> {
+        ensure!(!self.clients.is_empty(), "Load needs at least one client");
+        ensure!(self.mix.total_weight() > 0, "Load mix has no operations");
+        ensure!(
+            self.mix.peg_ins == 0 || self.bitcoin.is_some(),
+            "Peg-ins need a bitcoin fixture"
+        );
+        ensure!(
+            self.mix.ln_payments == 0 || self.gateway_id.is_some(),
+            "LN payments need a gateway"
+        );
+
+        info!(
+            target: LOG_TEST,
+            rate = self.rate,
+            duration = ?self.duration,
+            mix = ?self.mix,
+            "Starting load"
+        );
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let interval = Duration::from_secs_f64(1.0 / self.rate);
+        let mut report = LoadReport {
+            elapsed: Duration::ZERO,
+            skipped: 0,
+            operations: BTreeMap::new(),
+        };
+        let mut in_flight = FuturesUnordered::new();
+        let mut issued = 0;
+
+        // nosemgrep: ban-instant-now
+        let start = Instant::now();
+        let mut next = start;
+
+        while next < start + self.duration {
+            tokio::select! {
+                () = sleep_until(next) => {
+                    let operation = self.mix.sample(&mut rng);
+                    if in_flight.len() < self.max_in_flight {
+                        let client = &self.clients[issued % self.clients.len()];
+                        in_flight.push(self.run_operation(operation, client));
+                    } else {
+                        report.skipped += 1;
+                    }
+                    issued += 1;
+                    next += interval;
+                }
+                Some((operation, result)) = in_flight.next() => report.record(operation, result),
+            }
+        }
+
+        while let Some((operation, result)) = in_flight.next().await {
+            report.record(operation, result);
+        }
+
+        report.elapsed = start.elapsed();
+        for stats in report.operations.values_mut() {
+            stats.latencies.sort();
+        }
+
+        info!(target: LOG_TEST, "Finished load\n{report}");
+
+        Ok(report)
+    }
+
+    async fn run_operation(
+        &self,
+        operation: LoadOperation,
+        client: &ClientHandleArc,
+    ) -> (LoadOperation, anyhow::Result<Duration>) {
+        let result = match operation {
+            LoadOperation::PegIn => self.peg_in(client).await,
+            LoadOperation::Spend => self.spend(client).await,
+            LoadOperation::Reissue => self.reissue(client).await,
+            LoadOperation::LnPayment => self.ln_payment(client).await,
+        };
+
+        (operation, result)
+    }
+
+    async fn peg_in(&self, client: &ClientHandleArc) -> anyhow::Result<Duration> {
+        let (bitcoin, finality_delay) = self.bitcoin.as_ref().expect("Checked before the run");
+        // nosemgrep: ban-instant-now
+        let start = Instant::now();
+
+        let wallet = client.get_first_module::<WalletClientModule>();
+        let valid_until = fedimint_core::time::now() + PEG_IN_TIMEOUT;
+        let (operation_id, address) = wallet.get_deposit_address(valid_until, ()).await?;
+
+        let amount = self.amount + wallet.get_fee_consensus().peg_in_abs;
+        bitcoin
+            .send_and_mine_block(&address, bitcoin::Amount::from_sat(amount.msats / 1000))
+            .await;
+        bitcoin.mine_blocks(*finality_delay).await;
+
+        let mut updates = wallet
+            .subscribe_deposit_updates(operation_id)
+            .await?
+            .into_stream();
+        while let Some(update) = updates.next().await {
+            match update {
+                DepositState::Claimed(_) => return Ok(start.elapsed()),
+                DepositState::Failed(e) => bail!("Peg-in failed: {e}"),
+                _ => {}
+            }
+        }
+
+        bail!("Peg-in updates ended before the deposit was claimed")
+    }
+
+    async fn spend(&self, client: &ClientHandleArc) -> anyhow::Result<Duration> {
+        // nosemgrep: ban-instant-now
+        let start = Instant::now();
+        let notes = spend_notes(client, self.amount).await?;
+        let latency = start.elapsed();
+
+        self.spent_notes.lock().expect("Lock poisoned").push(notes);
+
+        Ok(latency)
+    }
+
+    async fn reissue(&self, client: &ClientHandleArc) -> anyhow::Result<Duration> {
+        let spent_notes = self.spent_notes.lock().expect("Lock poisoned").pop();
+        let notes = match spent_notes {
+            Some(notes) => notes,
+            None => spend_notes(client, self.amount).await?,
+        };
+
+        // nosemgrep: ban-instant-now
+        let start = Instant::now();
+
+        let mint = client.get_first_module::<MintClientModule>();
+        let operation_id = mint.reissue_external_notes(notes, ()).await?;
+        let mut updates = mint
+            .subscribe_reissue_external_notes(operation_id)
+            .await?
+            .into_stream();
+        while let Some(update) = updates.next().await {
+            match update {
+                ReissueExternalNotesState::Done => return Ok(start.elapsed()),
+                ReissueExternalNotesState::Failed(e) => bail!("Reissue failed: {e}"),
+                _ => {}
+            }
+        }
+
+        bail!("Reissue updates ended before the notes were issued")
+    }
+
+    async fn ln_payment(&self, client: &ClientHandleArc) -> anyhow::Result<Duration> {
+        let gateway_id = self.gateway_id.expect("Checked before the run");
+        let invoice = self.lightning.unique_invoice(self.amount);
+        // nosemgrep: ban-instant-now
+        let start = Instant::now();
+
+        let ln = client.get_first_module::<LightningClientModule>();
+        let gateway = ln
+            .select_gateway(&gateway_id)
+            .await
+            .context("Gateway is not registered with the federation")?;
+        let payment = ln.pay_bolt11_invoice(Some(gateway), invoice, ()).await?;
+        let PayType::Lightning(operation_id) = payment.payment_type else {
+            bail!("Invoice was paid within the federation");
+        };
+
+        let mut updates = ln.subscribe_ln_pay(operation_id).await?.into_stream();
+        while let Some(update) = updates.next().await {
+            match update {
+                LnPayState::Success { .. } => return Ok(start.elapsed()),
+                LnPayState::Canceled
+                | LnPayState::Refunded { .. }
+                | LnPayState::UnexpectedError { .. } => bail!("LN payment failed: {update:?}"),
+                _ => {}
+            }
+        }
+
+        bail!("LN payment updates ended before the payment succeeded")
+    }
+}
+
+async fn spend_notes(client: &ClientHandleArc, amount: Amount) -> anyhow::Result<OOBNotes> {
+    let mint = client.get_first_module::<MintClientModule>();
+    let (operation_id, notes) = mint
+        .spend_notes(amount, SPEND_CANCEL_AFTER, false, ())
+        .await?;
+
+    let mut updates = mint
+        .subscribe_spend_notes(operation_id)
+        .await?
+        .into_stream();
+    match updates.next().await {
+        Some(SpendOOBState::Created) => Ok(notes),
+        state => bail!("Spend failed: {state:?}"),
+    }
+}
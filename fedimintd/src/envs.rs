@@ -31,6 +31,35 @@ pub const FM_BITCOIN_NETWORK_ENV: &str = "FM_BITCOIN_NETWORK";
 // Env variable to TODO
 pub const FM_FINALITY_DELAY_ENV: &str = "FM_FINALITY_DELAY";
 
+// Env variable to set the file the federation setup is read from
+pub const FM_SETUP_FILE_ENV: &str = "FM_SETUP_FILE";
+
+// Env variable to set the passphrase guardian backups are encrypted with
+pub const FM_BACKUP_PASSPHRASE_ENV: &str = "FM_BACKUP_PASSPHRASE";
+
+// Env variable to select where the salt and the encrypted private config are
+// stored
+pub const FM_SECRETS_BACKEND_ENV: &str = "FM_SECRETS_BACKEND";
+
+// Env variable to select the database backend
+pub const FM_DB_BACKEND_ENV: &str = "FM_DB_BACKEND";
+
+// Env variable to run as a read-only replica of the guardian
+pub const FM_REPLICA_ENV: &str = "FM_REPLICA";
+
+// Env variable to write the logs as JSON
+pub const FM_LOG_JSON_ENV: &str = "FM_LOG_JSON";
+
+// Env variable to export traces to an OpenTelemetry collector via OTLP
+pub const FM_OTLP_ENDPOINT_ENV: &str = "FM_OTLP_ENDPOINT";
+
+// Env variable to not write the password into the data dir
+pub const FM_NO_PLAINTEXT_PASSWORD_ENV: &str = "FM_NO_PLAINTEXT_PASSWORD";
+
+// Env variable set by systemd to the directory containing the credentials of
+// the service
+pub const CREDENTIALS_DIRECTORY_ENV: &str = "CREDENTIALS_DIRECTORY";
+
 // Env variable to TODO
 pub const FM_BIND_METRICS_API_ENV: &str = "FM_BIND_METRICS_API";
 
@@ -1,23 +1,27 @@
 mod metrics;
+mod status;
 
 use std::collections::BTreeMap;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use anyhow::{format_err, Context};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use fedimint_core::admin_client::ConfigGenParamsRequest;
 use fedimint_core::config::{
     ModuleInitParams, ServerModuleConfigGenParamsRegistry, ServerModuleInitRegistry,
 };
 use fedimint_core::core::ModuleKind;
-use fedimint_core::db::Database;
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::{Database, IRawDatabaseExt};
 use fedimint_core::envs::{is_env_var_set, BitcoinRpcConfig, FM_USE_UNKNOWN_MODULE_ENV};
-use fedimint_core::module::{ServerApiVersionsSummary, ServerDbVersionsSummary, ServerModuleInit};
+use fedimint_core::module::{
+    ApiAuth, ServerApiVersionsSummary, ServerDbVersionsSummary, ServerModuleInit,
+};
 use fedimint_core::task::TaskGroup;
 use fedimint_core::timing;
-use fedimint_core::util::{handle_version_hash_command, write_overwrite, SafeUrl};
+use fedimint_core::util::{handle_version_hash_command, write_new, write_overwrite, SafeUrl};
 use fedimint_ln_common::config::{
     LightningGenParams, LightningGenParamsConsensus, LightningGenParamsLocal,
 };
@@ -27,8 +31,16 @@ use fedimint_meta_server::{MetaGenParams, MetaInit};
 use fedimint_mint_server::common::config::{MintGenParams, MintGenParamsConsensus};
 use fedimint_mint_server::MintInit;
 use fedimint_server::config::api::ConfigGenSettings;
-use fedimint_server::config::io::{DB_FILE, PLAINTEXT_PASSWORD};
+use fedimint_server::config::headless::HeadlessSetup;
+use fedimint_server::config::io::{write_private_config, DB_FILE, PLAINTEXT_PASSWORD};
+use fedimint_server::config::key_store::Pkcs11Config;
+use fedimint_server::config::secrets::{open_secrets_backend, DynSecretsBackend};
 use fedimint_server::config::ServerConfig;
+use fedimint_server::consensus::db_verify::{verify_db, DbVerifyReport};
+use fedimint_server::consensus::migration::{dry_run_consensus_migrations, MigrationReport};
+use fedimint_server::consensus::replay::{replay_sessions, ReplayReport};
+use fedimint_server::consensus::replica::CatchUpFn;
+use fedimint_server::guardian_backup::{EncryptedGuardianBackup, GuardianBackup};
 use fedimint_unknown_common::config::UnknownGenParams;
 use fedimint_unknown_server::UnknownInit;
 use fedimint_wallet_server::common::config::{
@@ -36,15 +48,19 @@ use fedimint_wallet_server::common::config::{
 };
 use fedimint_wallet_server::WalletInit;
 use futures::FutureExt;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 use crate::default_esplora_server;
 use crate::envs::{
-    FM_API_URL_ENV, FM_BIND_API_ENV, FM_BIND_METRICS_API_ENV, FM_BIND_P2P_ENV,
-    FM_BITCOIN_NETWORK_ENV, FM_DATA_DIR_ENV, FM_DISABLE_META_MODULE_ENV, FM_EXTRA_DKG_META_ENV,
-    FM_FINALITY_DELAY_ENV, FM_P2P_URL_ENV, FM_PASSWORD_ENV, FM_TOKIO_CONSOLE_BIND_ENV,
+    CREDENTIALS_DIRECTORY_ENV, FM_API_URL_ENV, FM_BACKUP_PASSPHRASE_ENV, FM_BIND_API_ENV,
+    FM_BIND_METRICS_API_ENV, FM_BIND_P2P_ENV, FM_BITCOIN_NETWORK_ENV, FM_DATA_DIR_ENV,
+    FM_DB_BACKEND_ENV, FM_DISABLE_META_MODULE_ENV, FM_EXTRA_DKG_META_ENV, FM_FINALITY_DELAY_ENV,
+    FM_LOG_JSON_ENV, FM_NO_PLAINTEXT_PASSWORD_ENV, FM_OTLP_ENDPOINT_ENV, FM_P2P_URL_ENV,
+    FM_PASSWORD_ENV, FM_REPLICA_ENV, FM_SECRETS_BACKEND_ENV, FM_SETUP_FILE_ENV,
+    FM_TOKIO_CONSOLE_BIND_ENV,
 };
 use crate::fedimintd::metrics::APP_START_TS;
+use crate::fedimintd::status::guardian_status;
 
 /// Time we will wait before forcefully shutting down tasks
 const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
@@ -60,12 +76,41 @@ pub struct ServerOpts {
     // the API
     #[arg(long, env = FM_PASSWORD_ENV)]
     pub password: Option<String>,
+    /// Read the password from stdin on startup
+    #[arg(long, default_value = "false")]
+    pub password_prompt: bool,
+    /// Where the salt and the encrypted private config are stored, e.g.
+    /// `vault+https://vault.example.com:8200/secret/fedimint/guardian-0` or
+    /// `aws-secretsmanager://us-east-1/fedimint/guardian-0`. Defaults to the
+    /// data dir.
+    #[arg(long, env = FM_SECRETS_BACKEND_ENV)]
+    pub secrets_backend: Option<String>,
+    /// Do not write the password into the data dir. It then has to be
+    /// provided on every start via `--password`, `--password-prompt` or the
+    /// `fedimintd-password` systemd credential.
+    #[arg(long, env = FM_NO_PLAINTEXT_PASSWORD_ENV, default_value = "false")]
+    pub no_plaintext_password: bool,
+    /// Database backend, switching from `rocksdb` to `sqlite` migrates the
+    /// existing database on the next start
+    #[arg(long, env = FM_DB_BACKEND_ENV, value_enum, default_value = "rocksdb")]
+    pub db_backend: DbBackend,
+    /// Serve the read-only client API on `--bind-api` from the database of the
+    /// guardian running on the same data dir, without joining consensus
+    #[arg(long, env = FM_REPLICA_ENV, default_value = "false")]
+    pub replica: bool,
     /// Enable tokio console logging
     #[arg(long, env = FM_TOKIO_CONSOLE_BIND_ENV)]
     pub tokio_console_bind: Option<SocketAddr>,
     /// Enable telemetry logging
     #[arg(long, default_value = "false")]
     pub with_telemetry: bool,
+    /// Write the logs as one JSON object per line
+    #[arg(long, env = FM_LOG_JSON_ENV, default_value = "false")]
+    pub log_json: bool,
+    /// Export traces of the API requests, sessions and peer messages to an
+    /// OpenTelemetry collector, e.g. `http://localhost:4317`
+    #[arg(long, env = FM_OTLP_ENDPOINT_ENV)]
+    pub otlp_endpoint: Option<String>,
 
     /// Address we bind to for federation communication
     #[arg(long, env = FM_BIND_P2P_ENV, default_value = "127.0.0.1:8173")]
@@ -94,6 +139,11 @@ pub struct ServerOpts {
     #[arg(long, env = FM_EXTRA_DKG_META_ENV, value_parser = parse_map, default_value="")]
     extra_dkg_meta: BTreeMap<String, String>,
 
+    /// Path to a JSON file describing the federation setup, which is then run
+    /// without any interactive API calls
+    #[arg(long, env = FM_SETUP_FILE_ENV)]
+    setup_file: Option<PathBuf>,
+
     #[clap(subcommand)]
     subcommand: Option<ServerSubcommand>,
 }
@@ -103,6 +153,47 @@ enum ServerSubcommand {
     /// Development-related commands
     #[clap(subcommand)]
     Dev(DevSubcommand),
+    /// Write the config, private keys and the local database state into a
+    /// passphrase-encrypted backup file and exit. The server must not be
+    /// running.
+    ExportGuardianBackup {
+        /// File the backup is written to
+        #[arg(long)]
+        out: PathBuf,
+        /// Passphrase the backup is encrypted with
+        #[arg(long, env = FM_BACKUP_PASSPHRASE_ENV)]
+        passphrase: String,
+    },
+    /// Restore a guardian backup into an empty data directory and exit. The
+    /// guardian recovers the consensus state from its peers once started.
+    RestoreGuardianBackup {
+        /// Backup file written by `export-guardian-backup`
+        #[arg(long)]
+        backup: PathBuf,
+        /// Passphrase the backup was encrypted with
+        #[arg(long, env = FM_BACKUP_PASSPHRASE_ENV)]
+        passphrase: String,
+    },
+    /// Remove the TLS and broadcast secret keys from the private config and
+    /// use the ones held by an HSM instead, then exit. The keys have to be
+    /// imported into the HSM beforehand. Requires the `pkcs11` feature.
+    MoveKeysToHsm {
+        /// JSON file with the PKCS#11 config of the HSM
+        #[arg(long)]
+        pkcs11_config: PathBuf,
+    },
+    /// Check that all database entries can be decoded and that the module
+    /// states are consistent, print a report and exit. Fails if any problem
+    /// was found. The server must not be running.
+    VerifyDb,
+    /// Apply the pending database migrations to an in-memory copy of the
+    /// database, print the changes they make and exit. The server must not be
+    /// running.
+    DryRunMigrations,
+    /// Print the consensus health, peer connectivity, session, mempool and
+    /// module summaries of the running guardian at `--api-url` and exit. The
+    /// mempool and modules are only shown if the password is set.
+    Status,
 }
 
 #[derive(Subcommand)]
@@ -111,6 +202,165 @@ enum DevSubcommand {
     ListApiVersions,
     /// List supported server database versions and exit
     ListDbVersions,
+    /// Replay the stored sessions into a scratch database, print the state
+    /// hash after every session and exit. Fails if the replay diverges from
+    /// the checkpoints in the database. The server must not be running.
+    ReplaySessions {
+        /// Session to start from, either 0 or the one following the latest
+        /// checkpoint
+        #[arg(long, default_value = "0")]
+        from_session: u64,
+        /// Last session to replay, defaults to the latest finished session
+        #[arg(long)]
+        to_session: Option<u64>,
+        /// Path of the scratch database, which has to be empty. Replays in
+        /// memory if not set.
+        #[arg(long)]
+        scratch_db: Option<PathBuf>,
+    },
+}
+
+/// Storage engine of the guardian database
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DbBackend {
+    Rocksdb,
+    Sqlite,
+}
+
+/// File name of the SQLite database in the data dir, the RocksDB database uses
+/// [`DB_FILE`]
+const SQLITE_DB_FILE: &str = "database.sqlite";
+
+/// Directory in the data dir holding the state of the RocksDB secondary
+/// instances of the replicas
+const REPLICA_DIR: &str = "replica";
+
+impl DbBackend {
+    /// Opens the database in the data dir. The SQLite database is migrated
+    /// from the RocksDB database on first use.
+    async fn open(self, data_dir: &Path) -> anyhow::Result<Database> {
+        Ok(match self {
+            DbBackend::Rocksdb => Database::new(
+                fedimint_rocksdb::RocksDb::open(data_dir.join(DB_FILE))?,
+                Default::default(),
+            ),
+            DbBackend::Sqlite => Database::new(
+                fedimint_sqlite::open_or_migrate(data_dir.join(SQLITE_DB_FILE), || {
+                    let rocksdb_path = data_dir.join(DB_FILE);
+
+                    if !rocksdb_path.exists() {
+                        return Ok(None);
+                    }
+
+                    info!("Migrating the RocksDB database to SQLite");
+
+                    Ok(Some(Database::new(
+                        fedimint_rocksdb::RocksDbReadOnly::open_read_only(rocksdb_path)?,
+                        Default::default(),
+                    )))
+                })
+                .await?,
+                Default::default(),
+            ),
+        })
+    }
+
+    fn open_read_only(self, data_dir: &Path) -> anyhow::Result<Database> {
+        Ok(match self {
+            DbBackend::Rocksdb => Database::new(
+                fedimint_rocksdb::RocksDbReadOnly::open_read_only(data_dir.join(DB_FILE))?,
+                Default::default(),
+            ),
+            DbBackend::Sqlite => Database::new(
+                fedimint_sqlite::SqliteDb::open_read_only(data_dir.join(SQLITE_DB_FILE))?,
+                Default::default(),
+            ),
+        })
+    }
+
+    /// Opens the database of a guardian running on the same data dir, which
+    /// the returned function makes the latest writes of visible
+    fn open_replica(
+        self,
+        data_dir: &Path,
+        api_bind: SocketAddr,
+    ) -> anyhow::Result<(Database, CatchUpFn)> {
+        Ok(match self {
+            DbBackend::Rocksdb => {
+                // Every secondary instance needs its own directory, replicas on the same
+                // host are told apart by their API port
+                let db = fedimint_rocksdb::RocksDbReadOnly::open_secondary(
+                    data_dir.join(DB_FILE),
+                    data_dir.join(REPLICA_DIR).join(api_bind.port().to_string()),
+                )?;
+
+                (
+                    Database::new(db.clone(), Default::default()),
+                    Box::new(move || db.catch_up_with_primary()),
+                )
+            }
+            // Every read transaction of SQLite sees the latest commit
+            DbBackend::Sqlite => (
+                Database::new(
+                    fedimint_sqlite::SqliteDb::open_read_only(data_dir.join(SQLITE_DB_FILE))?,
+                    Default::default(),
+                ),
+                Box::new(|| Ok(())),
+            ),
+        })
+    }
+
+    /// Opens a database at an arbitrary path without any migration
+    fn open_path(self, path: &Path) -> anyhow::Result<Database> {
+        Ok(match self {
+            DbBackend::Rocksdb => {
+                Database::new(fedimint_rocksdb::RocksDb::open(path)?, Default::default())
+            }
+            DbBackend::Sqlite => {
+                Database::new(fedimint_sqlite::SqliteDb::open(path)?, Default::default())
+            }
+        })
+    }
+}
+
+/// Name of the systemd credential the password is read from, see
+/// `LoadCredential=` in `systemd.exec(5)`
+const SYSTEMD_PASSWORD_CREDENTIAL: &str = "fedimintd-password";
+
+impl ServerOpts {
+    fn secrets_backend(&self, data_dir: &Path) -> anyhow::Result<DynSecretsBackend> {
+        open_secrets_backend(self.secrets_backend.as_deref(), data_dir)
+    }
+
+    /// The password provided on startup, which takes precedence over the
+    /// plaintext password in the data dir
+    fn startup_password(&self) -> anyhow::Result<Option<String>> {
+        if let Some(password) = &self.password {
+            return Ok(Some(password.clone()));
+        }
+
+        if let Some(dir) = std::env::var_os(CREDENTIALS_DIRECTORY_ENV) {
+            let path = PathBuf::from(dir).join(SYSTEMD_PASSWORD_CREDENTIAL);
+
+            if path.exists() {
+                let password = std::fs::read_to_string(&path)
+                    .context("Unable to read the password credential")?;
+                return Ok(Some(password.trim_end_matches(['\r', '\n']).to_owned()));
+            }
+        }
+
+        if self.password_prompt {
+            eprint!("Guardian password: ");
+
+            let mut password = String::new();
+            std::io::stdin()
+                .read_line(&mut password)
+                .context("Unable to read the password from stdin")?;
+            return Ok(Some(password.trim_end_matches(['\r', '\n']).to_owned()));
+        }
+
+        Ok(None)
+    }
 }
 
 fn parse_map(s: &str) -> anyhow::Result<BTreeMap<String, String>> {
@@ -206,6 +456,8 @@ impl Fedimintd {
         TracingSetup::default()
             .tokio_console_bind(opts.tokio_console_bind)
             .with_jaeger(opts.with_telemetry)
+            .with_json(opts.log_json)
+            .with_otlp(opts.otlp_endpoint.clone())
             .init()
             .unwrap();
 
@@ -335,6 +587,90 @@ impl Fedimintd {
                     println!("{db_versions}");
                     std::process::exit(0);
                 }
+                ServerSubcommand::Dev(DevSubcommand::ReplaySessions {
+                    from_session,
+                    to_session,
+                    scratch_db,
+                }) => {
+                    let report = match self
+                        .replay_sessions(*from_session, *to_session, scratch_db.clone())
+                        .await
+                    {
+                        Ok(report) => report,
+                        Err(error) => {
+                            error!(?error, "Replaying the sessions failed");
+                            std::process::exit(1);
+                        }
+                    };
+                    let report_json = serde_json::to_string_pretty(&report)
+                        .expect("Replay report struct is serializable");
+                    println!("{report_json}");
+                    std::process::exit(if report.divergence.is_some() { 1 } else { 0 });
+                }
+                ServerSubcommand::ExportGuardianBackup { out, passphrase } => {
+                    if let Err(error) = self.export_guardian_backup(out, passphrase).await {
+                        error!(?error, "Exporting the guardian backup failed");
+                        std::process::exit(1);
+                    }
+                    std::process::exit(0);
+                }
+                ServerSubcommand::RestoreGuardianBackup { backup, passphrase } => {
+                    if let Err(error) = self.restore_guardian_backup(backup, passphrase).await {
+                        error!(?error, "Restoring the guardian backup failed");
+                        std::process::exit(1);
+                    }
+                    std::process::exit(0);
+                }
+                ServerSubcommand::MoveKeysToHsm { pkcs11_config } => {
+                    if let Err(error) = self.move_keys_to_hsm(pkcs11_config).await {
+                        error!(?error, "Moving the keys to the HSM failed");
+                        std::process::exit(1);
+                    }
+                    std::process::exit(0);
+                }
+                ServerSubcommand::VerifyDb => {
+                    let report = match self.verify_db().await {
+                        Ok(report) => report,
+                        Err(error) => {
+                            error!(?error, "Verifying the database failed");
+                            std::process::exit(1);
+                        }
+                    };
+                    let report_json = serde_json::to_string_pretty(&report)
+                        .expect("Database verification report is serializable");
+                    println!("{report_json}");
+                    std::process::exit(if report.is_ok() { 0 } else { 1 });
+                }
+                ServerSubcommand::Status => {
+                    let auth = match self.opts.startup_password() {
+                        Ok(password) => password.map(ApiAuth),
+                        Err(error) => {
+                            error!(?error, "Reading the password failed");
+                            std::process::exit(1);
+                        }
+                    };
+                    match guardian_status(self.opts.api_url.clone(), auth).await {
+                        Ok(status) => print!("{status}"),
+                        Err(error) => {
+                            error!(?error, "Querying the guardian status failed");
+                            std::process::exit(1);
+                        }
+                    }
+                    std::process::exit(0);
+                }
+                ServerSubcommand::DryRunMigrations => {
+                    let report = match self.dry_run_migrations().await {
+                        Ok(report) => report,
+                        Err(error) => {
+                            error!(?error, "Dry run of the database migrations failed");
+                            std::process::exit(1);
+                        }
+                    };
+                    let report_json = serde_json::to_string_pretty(&report)
+                        .expect("Migration report is serializable");
+                    println!("{report_json}");
+                    std::process::exit(0);
+                }
             }
         }
 
@@ -410,6 +746,189 @@ impl Fedimintd {
         }
     }
 
+    async fn export_guardian_backup(&self, out: &Path, passphrase: &str) -> anyhow::Result<()> {
+        let data_dir = self
+            .opts
+            .data_dir
+            .clone()
+            .context("data-dir option is not present")?;
+
+        let cfg = fedimint_server::get_config(
+            &data_dir,
+            self.opts.startup_password()?.as_deref(),
+            self.opts.secrets_backend(&data_dir)?.as_ref(),
+        )
+        .await?
+        .context("The data dir contains no config")?;
+
+        let db = self.opts.db_backend.open_read_only(&data_dir)?;
+
+        let backup = GuardianBackup::create(cfg, &db)
+            .await?
+            .encrypt(passphrase)?;
+
+        write_new(out, backup.to_bytes())?;
+
+        info!(path = %out.display(), "Wrote guardian backup");
+
+        Ok(())
+    }
+
+    async fn restore_guardian_backup(&self, backup: &Path, passphrase: &str) -> anyhow::Result<()> {
+        let data_dir = self
+            .opts
+            .data_dir
+            .clone()
+            .context("data-dir option is not present")?;
+
+        let backup =
+            EncryptedGuardianBackup::from_bytes(&std::fs::read(backup)?)?.decrypt(passphrase)?;
+
+        std::fs::create_dir_all(&data_dir)?;
+
+        let db = self.opts.db_backend.open(&data_dir).await?;
+
+        backup
+            .restore(
+                &data_dir,
+                self.opts.secrets_backend(&data_dir)?.as_ref(),
+                &db,
+                &self.server_gens,
+                !self.opts.no_plaintext_password,
+            )
+            .await?;
+
+        info!(data_dir = %data_dir.display(), "Restored guardian backup");
+
+        Ok(())
+    }
+
+    async fn move_keys_to_hsm(&self, pkcs11_config: &Path) -> anyhow::Result<()> {
+        let data_dir = self
+            .opts
+            .data_dir
+            .clone()
+            .context("data-dir option is not present")?;
+
+        let secrets = self.opts.secrets_backend(&data_dir)?;
+
+        let mut cfg = fedimint_server::get_config(
+            &data_dir,
+            self.opts.startup_password()?.as_deref(),
+            secrets.as_ref(),
+        )
+        .await?
+        .context("The data dir contains no config")?;
+
+        let pkcs11_config: Pkcs11Config = serde_json::from_slice(&std::fs::read(pkcs11_config)?)
+            .context("Unable to parse the PKCS#11 config")?;
+
+        cfg.private.pkcs11 = Some(pkcs11_config);
+
+        // Fails unless the HSM holds our broadcast secret key
+        cfg.open_key_store()?;
+
+        cfg.private.tls_key = None;
+        cfg.private.broadcast_secret_key = None;
+
+        write_private_config(secrets.as_ref(), &cfg.private, &cfg.private.api_auth.0).await?;
+
+        info!("Moved the keys to the HSM, existing backups of the data dir still contain them");
+
+        Ok(())
+    }
+
+    async fn replay_sessions(
+        &self,
+        from_session: u64,
+        to_session: Option<u64>,
+        scratch_db: Option<PathBuf>,
+    ) -> anyhow::Result<ReplayReport> {
+        let data_dir = self
+            .opts
+            .data_dir
+            .clone()
+            .context("data-dir option is not present")?;
+
+        let cfg = fedimint_server::get_config(
+            &data_dir,
+            self.opts.startup_password()?.as_deref(),
+            self.opts.secrets_backend(&data_dir)?.as_ref(),
+        )
+        .await?
+        .context("The data dir contains no config")?;
+
+        let source_db = self.opts.db_backend.open_read_only(&data_dir)?;
+
+        let scratch_db = match scratch_db {
+            Some(path) => self.opts.db_backend.open_path(&path)?,
+            None => MemDatabase::new().into_database(),
+        };
+
+        let task_group = TaskGroup::new();
+
+        let report = replay_sessions(
+            &cfg,
+            &source_db,
+            &scratch_db,
+            &self.server_gens,
+            from_session,
+            to_session,
+            &task_group,
+        )
+        .await;
+
+        task_group.shutdown();
+
+        report
+    }
+
+    async fn verify_db(&self) -> anyhow::Result<DbVerifyReport> {
+        let data_dir = self
+            .opts
+            .data_dir
+            .clone()
+            .context("data-dir option is not present")?;
+
+        let cfg = fedimint_server::get_config(
+            &data_dir,
+            self.opts.startup_password()?.as_deref(),
+            self.opts.secrets_backend(&data_dir)?.as_ref(),
+        )
+        .await?
+        .context("The data dir contains no config")?;
+
+        let db = self.opts.db_backend.open(&data_dir).await?;
+
+        let task_group = TaskGroup::new();
+
+        let report = verify_db(&cfg, &db, &self.server_gens, &task_group).await;
+
+        task_group.shutdown();
+
+        report
+    }
+
+    async fn dry_run_migrations(&self) -> anyhow::Result<MigrationReport> {
+        let data_dir = self
+            .opts
+            .data_dir
+            .clone()
+            .context("data-dir option is not present")?;
+
+        let cfg = fedimint_server::get_config(
+            &data_dir,
+            self.opts.startup_password()?.as_deref(),
+            self.opts.secrets_backend(&data_dir)?.as_ref(),
+        )
+        .await?
+        .context("The data dir contains no config")?;
+
+        let db = self.opts.db_backend.open_read_only(&data_dir)?;
+
+        dry_run_consensus_migrations(&cfg, &db, &self.server_gens).await
+    }
+
     fn get_server_db_versions(&self) -> ServerDbVersionsSummary {
         ServerDbVersionsSummary {
             modules: self
@@ -444,12 +963,37 @@ async fn run(
 
     let data_dir = opts.data_dir.context("data-dir option is not present")?;
 
+    let password = opts.startup_password()?;
+
+    let secrets = opts.secrets_backend(&data_dir)?;
+
+    if opts.replica {
+        let (db, catch_up) = opts.db_backend.open_replica(&data_dir, opts.bind_api)?;
+
+        return fedimint_server::run_replica(
+            data_dir,
+            password,
+            secrets.as_ref(),
+            opts.bind_api,
+            db,
+            catch_up,
+            &module_inits,
+            task_group.clone(),
+        )
+        .await;
+    }
+
     // TODO: Fedimintd should use the config gen API
     // on each run we want to pass the currently passed password, so we need to
     // overwrite
-    if let Some(password) = opts.password {
+    if opts.no_plaintext_password {
+        if data_dir.join(PLAINTEXT_PASSWORD).exists() {
+            warn!("The data dir still contains a plaintext password, remove it to keep the password off the disk");
+        }
+    } else if let Some(password) = &password {
         write_overwrite(data_dir.join(PLAINTEXT_PASSWORD), password)?;
-    };
+    }
+
     let default_params = ConfigGenParamsRequest {
         meta: opts.extra_dkg_meta.clone(),
         modules: module_inits_params.clone(),
@@ -464,15 +1008,21 @@ async fn run(
         default_params,
         max_connections: fedimint_server::config::max_connections(),
         registry: module_inits.clone(),
+        headless_setup: None,
+        write_plaintext_password: !opts.no_plaintext_password,
+    };
+
+    let settings = match opts.setup_file {
+        Some(path) => settings.with_headless_setup(HeadlessSetup::read(&path)?),
+        None => settings,
     };
 
-    let db = Database::new(
-        fedimint_rocksdb::RocksDb::open(data_dir.join(DB_FILE))?,
-        Default::default(),
-    );
+    let db = opts.db_backend.open(&data_dir).await?;
 
     fedimint_server::run(
         data_dir,
+        password,
+        secrets.as_ref(),
         settings,
         db,
         code_version_str,
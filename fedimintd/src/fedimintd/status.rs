@@ -0,0 +1,138 @@
+//! Human readable status of a running guardian, queried via its admin API
+
+use std::fmt::Write;
+
+use fedimint_api_client::api::{DynGlobalApi, PeerConnectionStatus, StatusResponse};
+use fedimint_core::admin_client::MempoolSummary;
+use fedimint_core::module::audit::AuditSummary;
+use fedimint_core::module::ApiAuth;
+use fedimint_core::util::SafeUrl;
+
+/// Queries the guardian at `api_url` and returns its status. The mempool and
+/// the audit are only included if the admin password is known.
+pub async fn guardian_status(api_url: SafeUrl, auth: Option<ApiAuth>) -> anyhow::Result<String> {
+    let api = DynGlobalApi::from_pre_peer_id_admin_endpoint(api_url.clone());
+
+    let status = api.status().await?;
+
+    let (mempool, audit) = match auth {
+        Some(auth) => (
+            Some(api.mempool(auth.clone()).await?),
+            Some(api.audit(auth).await?),
+        ),
+        None => (None, None),
+    };
+
+    Ok(format_status(
+        &api_url,
+        &status,
+        mempool.as_ref(),
+        audit.as_ref(),
+    ))
+}
+
+fn format_status(
+    api_url: &SafeUrl,
+    status: &StatusResponse,
+    mempool: Option<&MempoolSummary>,
+    audit: Option<&AuditSummary>,
+) -> String {
+    let mut out = String::new();
+
+    // Writing into a string can't fail
+    let _ = writeln!(out, "Guardian:     {api_url}");
+    let _ = writeln!(out, "Server:       {:?}", status.server);
+
+    let Some(federation) = &status.federation else {
+        let _ = writeln!(out, "Consensus:    not running");
+        return out;
+    };
+
+    let health = if federation.peers_diverged > 0 {
+        "DIVERGED"
+    } else if federation.peers_flagged > 0 {
+        "degraded"
+    } else {
+        "healthy"
+    };
+
+    let _ = writeln!(out, "Consensus:    {health}");
+    let _ = writeln!(out, "Session:      {}", federation.session_count);
+    let _ = writeln!(
+        out,
+        "Peers:        {} online, {} offline, {} flagged, {} diverged",
+        federation.peers_online,
+        federation.peers_offline,
+        federation.peers_flagged,
+        federation.peers_diverged
+    );
+
+    let mut peers = federation.status_by_peer.iter().collect::<Vec<_>>();
+    peers.sort_by_key(|(peer, _)| **peer);
+
+    for (peer, peer_status) in peers {
+        let connection = match peer_status.connection_status {
+            PeerConnectionStatus::Connected => "connected",
+            PeerConnectionStatus::Disconnected => "disconnected",
+        };
+
+        let _ = write!(out, "  peer {peer}:     {connection}");
+
+        if let Some(lag) = peer_status.session_lag {
+            let _ = write!(out, ", {lag} sessions behind");
+        }
+
+        if peer_status.flagged {
+            let _ = write!(out, ", flagged");
+        }
+
+        if let Some(session) = peer_status.state_divergence {
+            let _ = write!(out, ", diverged after session {session}");
+        }
+
+        let _ = writeln!(out);
+    }
+
+    if let Some(mempool) = mempool {
+        let _ = write!(
+            out,
+            "Mempool:      {} transactions, {} items, {} bytes",
+            mempool.transactions, mempool.items, mempool.bytes
+        );
+
+        if let Some(age_ms) = mempool.oldest_age_ms {
+            let _ = write!(out, ", oldest queued {}s ago", age_ms / 1000);
+        }
+
+        let _ = writeln!(out);
+    }
+
+    if let Some(audit) = audit {
+        let _ = writeln!(out, "Net assets:   {} msat", audit.net_assets);
+
+        let mut modules = audit.module_summaries.iter().collect::<Vec<_>>();
+        modules.sort_by_key(|(module_instance_id, _)| **module_instance_id);
+
+        for (module_instance_id, summary) in modules {
+            let _ = write!(
+                out,
+                "  module {module_instance_id} ({}): {} msat",
+                summary.kind, summary.net_assets
+            );
+
+            if let Some(module_mempool) =
+                mempool.and_then(|mempool| mempool.modules.get(module_instance_id))
+            {
+                let _ = write!(
+                    out,
+                    ", {} queued items, {} queued inputs, {} queued outputs",
+                    module_mempool.consensus_items, module_mempool.inputs, module_mempool.outputs
+                );
+            }
+
+            let _ = writeln!(out);
+        }
+    }
+
+    out
+}
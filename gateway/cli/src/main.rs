@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use std::time::Duration;
 
 use anyhow::bail;
@@ -5,15 +6,24 @@ use bitcoin::address::NetworkUnchecked;
 use bitcoin::Address;
 use clap::{CommandFactory, Parser, Subcommand};
 use fedimint_core::config::FederationId;
+use fedimint_core::secp256k1::PublicKey;
 use fedimint_core::util::{retry, ConstantBackoff, SafeUrl};
-use fedimint_core::{fedimint_build_code_version_env, BitcoinAmountOrAll};
+use fedimint_core::{fedimint_build_code_version_env, Amount, BitcoinAmountOrAll};
 use fedimint_logging::TracingSetup;
-use ln_gateway::rpc::rpc_client::GatewayRpcClient;
+use lightning_invoice::Bolt11Invoice;
+use ln_gateway::lightning::ChannelInfo;
+use ln_gateway::rpc::rpc_client::{GatewayRpcClient, GatewayRpcError};
 use ln_gateway::rpc::{
-    BackupPayload, BalancePayload, CloseChannelsWithPeerPayload, ConfigPayload, ConnectFedPayload,
-    ConnectToPeerPayload, DepositAddressPayload, FederationRoutingFees, GetFundingAddressPayload,
-    LeaveFedPayload, OpenChannelPayload, RestorePayload, SetConfigurationPayload, WithdrawPayload,
-    V1_API_ENDPOINT,
+    ApiScope, BackupPayload, BalancePayload, CloseChannelsWithPeerPayload, ConfigPayload,
+    ConnectFedPayload, ConnectToPeerPayload, CreateApiKeyPayload, CreateOfferPayload,
+    DepositAddressPayload, DrainPayload, ExportConfigPayload, FederationLimits,
+    FederationRoutingFees, GetFundingAddressPayload, GetOfferPayload, HtlcFilterConfig,
+    ImportConfigPayload, LeaveFedPayload, ListPaymentsPayload, ListSweepsPayload, LnurlAccount,
+    OpenChannelPayload, PayInvoiceForOperatorPayload, PaymentRecord, PaymentStatus,
+    RebalancePayload, RestorePayload, RevokeApiKeyPayload, SendOnchainPayload,
+    SetConfigurationPayload, SetFederationLimitsPayload, SetHtlcFilterPayload,
+    SetLnurlAccountPayload, SetSweepPolicyPayload, ShutdownPayload, SweepPayload, SweepPolicy,
+    WithdrawPayload, V1_API_ENDPOINT,
 };
 use serde::Serialize;
 
@@ -28,9 +38,14 @@ struct Cli {
     address: SafeUrl,
     #[command(subcommand)]
     command: Commands,
+    /// The gateway password or an API key.
     /// WARNING: Passing in a password from the command line may be less secure!
     #[clap(long)]
     rpcpassword: Option<String>,
+    /// Print JSON instead of a table for commands that list payments or
+    /// channels
+    #[clap(long, global = true)]
+    json: bool,
 }
 
 #[derive(Subcommand)]
@@ -108,8 +123,241 @@ pub enum Commands {
         #[clap(long)]
         per_federation_routing_fees: Option<Vec<PerFederationRoutingFees>>,
     },
+    /// Override the spend and exposure limits of a federation. Limits that
+    /// are not given are removed.
+    SetFederationLimits {
+        #[clap(long)]
+        federation_id: FederationId,
+        /// Largest single outgoing payment or withdrawal
+        #[clap(long)]
+        max_payment: Option<Amount>,
+        /// Largest sum of outgoing payments and withdrawals per UTC day
+        #[clap(long)]
+        max_daily_outflow: Option<Amount>,
+        /// Largest e-cash balance the gateway accumulates by paying invoices
+        #[clap(long)]
+        max_ecash_balance: Option<Amount>,
+        /// Reset the federation to the gateway's default limits
+        #[clap(long, conflicts_with_all = ["max_payment", "max_daily_outflow", "max_ecash_balance"])]
+        reset: bool,
+    },
+    /// Stop accepting new payments and wait for the payments in flight to
+    /// resolve, so the gateway can be shut down safely. The gateway keeps
+    /// rejecting new payments until it is restarted.
+    Drain {
+        /// Seconds to wait for the payments in flight
+        #[clap(long)]
+        timeout_secs: Option<u64>,
+    },
+    /// Shut down a drained gateway
+    Shutdown {
+        /// Shut down even if the gateway is not drained, which might leave
+        /// HTLCs stuck
+        #[clap(long)]
+        force: bool,
+    },
+    /// Export the gateway's settings, keys and federation clients as an
+    /// encrypted archive, to migrate the gateway to another machine
+    ExportConfig {
+        /// Password to encrypt the archive with
+        #[clap(long)]
+        password: String,
+        /// File to write the archive to
+        #[clap(long)]
+        output: PathBuf,
+    },
+    /// Import an archive created by `export-config` into a gateway that is not
+    /// connected to any federation. The gateway connects to the federations
+    /// of the archive once it is restarted, after which their e-cash can be
+    /// recovered with `restore`.
+    ImportConfig {
+        /// Password the archive was encrypted with
+        #[clap(long)]
+        password: String,
+        /// File to read the archive from
+        #[clap(long)]
+        archive: PathBuf,
+    },
+    /// Create a BOLT12 offer for a federation, if the lightning node supports
+    /// offers
+    CreateOffer {
+        #[clap(long)]
+        federation_id: FederationId,
+        /// Amount the offer requests, the payer chooses the amount if it is
+        /// not set
+        #[clap(long)]
+        amount: Option<Amount>,
+        #[clap(long)]
+        description: String,
+        /// Seconds the offer can be paid for, it does not expire if not set
+        #[clap(long)]
+        expiry_secs: Option<u64>,
+    },
+    /// Show an offer created by the gateway and whether it has been paid
+    GetOffer {
+        #[clap(long)]
+        offer_id: String,
+    },
+    /// List the payments the gateway routed, newest first
+    ListPayments {
+        #[command(flatten)]
+        filter: PaymentFilter,
+        /// Number of payments to skip
+        #[clap(long, default_value_t = 0)]
+        offset: usize,
+        /// Maximum number of payments to list
+        #[clap(long)]
+        limit: Option<usize>,
+    },
+    /// Export the payments the gateway routed as CSV
+    ExportPayments {
+        #[command(flatten)]
+        filter: PaymentFilter,
+    },
     #[command(subcommand)]
     Lightning(LightningCommands),
+    #[command(subcommand)]
+    ApiKey(ApiKeyCommands),
+    #[command(subcommand)]
+    Sweep(SweepCommands),
+    #[command(subcommand)]
+    Lnurl(LnurlCommands),
+    #[command(subcommand)]
+    HtlcFilter(HtlcFilterCommands),
+}
+
+/// Manage API keys, which can be passed instead of the password to access the
+/// routes of their scope
+#[derive(Subcommand)]
+pub enum ApiKeyCommands {
+    /// Create an API key, which is only shown once
+    Create {
+        #[clap(long)]
+        name: String,
+        /// One of `info`, `payments` or `admin`
+        #[clap(long)]
+        scope: ApiScope,
+    },
+    /// List the names and scopes of the API keys
+    List,
+    /// Revoke an API key
+    Revoke {
+        #[clap(long)]
+        name: String,
+    },
+}
+
+/// Peg out the e-cash the gateway holds in a federation to the lightning
+/// node's on-chain wallet
+#[derive(Subcommand)]
+pub enum SweepCommands {
+    /// Sweep a federation's e-cash now
+    Run {
+        #[clap(long)]
+        federation_id: FederationId,
+        /// E-cash to leave in the federation, defaults to the float of its
+        /// sweep policy
+        #[clap(long)]
+        float: Option<Amount>,
+    },
+    /// Sweep a federation automatically whenever its e-cash exceeds a
+    /// threshold
+    SetPolicy {
+        #[clap(long)]
+        federation_id: FederationId,
+        /// E-cash balance above which the federation is swept
+        #[clap(long, required_unless_present = "disable")]
+        threshold: Option<Amount>,
+        /// E-cash balance left in the federation by a sweep
+        #[clap(long, default_value_t = Amount::ZERO)]
+        float: Amount,
+        /// Stop sweeping the federation automatically
+        #[clap(long, conflicts_with_all = ["threshold", "float"])]
+        disable: bool,
+    },
+    /// List past sweeps, newest first
+    History {
+        #[clap(long)]
+        federation_id: Option<FederationId>,
+    },
+}
+
+/// Manage the lightning addresses the gateway hosts for federation users,
+/// which are served if the gateway runs with `--enable-lnurl`
+#[derive(Subcommand)]
+pub enum LnurlCommands {
+    /// Register a lightning address paying into a federation account, or
+    /// update an existing one
+    Register {
+        #[clap(long)]
+        username: String,
+        #[clap(long)]
+        federation_id: FederationId,
+        /// Static public key of the user's LNv2 client
+        #[clap(long)]
+        recipient_pk: PublicKey,
+        #[clap(long, default_value_t = Amount::from_sats(1))]
+        min_sendable: Amount,
+        #[clap(long, default_value_t = Amount::from_sats(1_000_000))]
+        max_sendable: Amount,
+        /// Shown to payers, defaults to "Payment to <username>"
+        #[clap(long)]
+        description: Option<String>,
+    },
+    /// Remove a lightning address
+    Remove {
+        #[clap(long)]
+        username: String,
+    },
+    /// List the lightning addresses by username
+    List,
+}
+
+/// Manage the rules the gateway applies to HTLCs intercepted for its
+/// federations, the first matching rule decides whether an HTLC is accepted
+#[derive(Subcommand)]
+pub enum HtlcFilterCommands {
+    /// Show the current rules
+    Show,
+    /// Replace the rules
+    Set {
+        /// JSON file with the list of rules, in order of precedence
+        #[clap(long)]
+        rules: PathBuf,
+        /// Only log the HTLCs the rules would reject
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Remove all rules, accepting every HTLC
+    Clear,
+}
+
+#[derive(clap::Args)]
+pub struct PaymentFilter {
+    #[clap(long)]
+    federation_id: Option<FederationId>,
+    /// Only payments created at or after this unix timestamp in seconds
+    #[clap(long)]
+    start: Option<u64>,
+    /// Only payments created before this unix timestamp in seconds
+    #[clap(long)]
+    end: Option<u64>,
+    /// One of `pending`, `succeeded` or `failed`
+    #[clap(long)]
+    status: Option<PaymentStatus>,
+}
+
+impl From<PaymentFilter> for ListPaymentsPayload {
+    fn from(filter: PaymentFilter) -> Self {
+        ListPaymentsPayload {
+            federation_id: filter.federation_id,
+            start: filter.start,
+            end: filter.end,
+            status: filter.status,
+            offset: 0,
+            limit: None,
+        }
+    }
 }
 
 /// This API is intentionally kept very minimal, as its main purpose is to
@@ -127,6 +375,22 @@ pub enum LightningCommands {
     },
     /// Get a Bitcoin address to fund the gateway
     GetFundingAddress,
+    /// Get the balance of the lightning node's on-chain wallet
+    GetOnchainBalance,
+    /// Send funds from the lightning node's on-chain wallet
+    SendOnchain {
+        /// The address to send the funds to
+        #[clap(long)]
+        address: Address<NetworkUnchecked>,
+
+        /// The amount to send
+        #[clap(long)]
+        amount_sats: u64,
+
+        /// The fee rate of the transaction
+        #[clap(long)]
+        fee_rate_sats_per_vbyte: u64,
+    },
     /// Open a channel with another lightning node
     OpenChannel {
         /// The public key of the node to open a channel with
@@ -149,7 +413,41 @@ pub enum LightningCommands {
         pubkey: bitcoin::secp256k1::PublicKey,
     },
     /// List active channels
+    #[command(alias = "list-channels")]
     ListActiveChannels,
+    /// Pay an invoice from the lightning node's funds, without using the
+    /// e-cash of any federation
+    PayInvoice {
+        #[clap(long)]
+        invoice: Bolt11Invoice,
+
+        /// The maximum routing fee to pay
+        #[clap(long)]
+        max_fee: Amount,
+
+        /// The maximum number of blocks the payment can lock funds for
+        #[clap(long, default_value_t = 1008)]
+        max_delay: u64,
+    },
+    /// Move outbound liquidity from one channel to the channel with another
+    /// peer by paying the lightning node itself
+    Rebalance {
+        /// The short channel id of the channel to move liquidity out of
+        #[clap(long)]
+        outgoing_short_channel_id: u64,
+
+        /// The public key of the peer whose channel receives the liquidity
+        #[clap(long)]
+        last_hop_pubkey: bitcoin::secp256k1::PublicKey,
+
+        /// The amount to move
+        #[clap(long)]
+        amount: Amount,
+
+        /// The maximum routing fee to pay
+        #[clap(long)]
+        max_fee: Amount,
+    },
     /// Wait for the lightning node to be synced with the blockchain
     WaitForChainSync {
         /// The block height to wait for
@@ -211,6 +509,9 @@ async fn main() -> anyhow::Result<()> {
             // FIXME: deprecated >= 0.3.0
             let response = match client().get_info().await {
                 Ok(res) => res,
+                Err(e @ (GatewayRpcError::Unauthorized | GatewayRpcError::Network(_))) => {
+                    return Err(e.into())
+                }
                 Err(_) => client().get_info_legacy().await?,
             };
 
@@ -270,6 +571,73 @@ async fn main() -> anyhow::Result<()> {
         Commands::Restore { federation_id } => {
             client().restore(RestorePayload { federation_id }).await?;
         }
+        Commands::CreateOffer {
+            federation_id,
+            amount,
+            description,
+            expiry_secs,
+        } => {
+            let response = client()
+                .create_offer(CreateOfferPayload {
+                    federation_id,
+                    amount,
+                    description,
+                    expiry_secs,
+                })
+                .await?;
+
+            print_response(response);
+        }
+        Commands::GetOffer { offer_id } => {
+            let response = client().get_offer(GetOfferPayload { offer_id }).await?;
+
+            print_response(response);
+        }
+        Commands::ListPayments {
+            filter,
+            offset,
+            limit,
+        } => {
+            let response = client()
+                .list_payments(ListPaymentsPayload {
+                    offset,
+                    limit,
+                    ..filter.into()
+                })
+                .await?;
+
+            if cli.json {
+                print_response(response);
+            } else {
+                print_payments(response);
+            }
+        }
+        Commands::Drain { timeout_secs } => {
+            let response = client().drain(DrainPayload { timeout_secs }).await?;
+
+            print_response(response);
+        }
+        Commands::Shutdown { force } => {
+            client().shutdown(ShutdownPayload { force }).await?;
+        }
+        Commands::ExportConfig { password, output } => {
+            let archive = client()
+                .export_config(ExportConfigPayload { password })
+                .await?;
+
+            std::fs::write(output, serde_json::to_string_pretty(&archive)?)?;
+        }
+        Commands::ImportConfig { password, archive } => {
+            let archive = serde_json::from_str(&std::fs::read_to_string(archive)?)?;
+            client()
+                .import_config(ImportConfigPayload { password, archive })
+                .await?;
+        }
+        Commands::ExportPayments { filter } => {
+            let csv = client().export_payments_csv(filter.into()).await?;
+
+            println!("{csv}");
+        }
         Commands::Completion { shell } => {
             clap_complete::generate(
                 shell,
@@ -297,6 +665,27 @@ async fn main() -> anyhow::Result<()> {
                 })
                 .await?;
         }
+        Commands::SetFederationLimits {
+            federation_id,
+            max_payment,
+            max_daily_outflow,
+            max_ecash_balance,
+            reset,
+        } => {
+            let limits = (!reset).then_some(FederationLimits {
+                max_payment,
+                max_daily_outflow,
+                max_ecash_balance,
+            });
+            let response = client()
+                .set_federation_limits(SetFederationLimitsPayload {
+                    federation_id,
+                    limits,
+                })
+                .await?;
+
+            print_response(response);
+        }
 
         Commands::Lightning(lightning_command) => match lightning_command {
             LightningCommands::ConnectToPeer { pubkey, host } => {
@@ -311,6 +700,24 @@ async fn main() -> anyhow::Result<()> {
                     .assume_checked();
                 println!("{response}");
             }
+            LightningCommands::GetOnchainBalance => {
+                let response = client().get_onchain_balance().await?;
+                print_response(response);
+            }
+            LightningCommands::SendOnchain {
+                address,
+                amount_sats,
+                fee_rate_sats_per_vbyte,
+            } => {
+                let txid = client()
+                    .send_onchain(SendOnchainPayload {
+                        address,
+                        amount_sats,
+                        fee_rate_sats_per_vbyte,
+                    })
+                    .await?;
+                print_response(txid);
+            }
             LightningCommands::OpenChannel {
                 pubkey,
                 channel_size_sats,
@@ -332,7 +739,41 @@ async fn main() -> anyhow::Result<()> {
             }
             LightningCommands::ListActiveChannels => {
                 let response = client().list_active_channels().await?;
-                print_response(response);
+                if cli.json {
+                    print_response(response);
+                } else {
+                    print_channels(response);
+                }
+            }
+            LightningCommands::PayInvoice {
+                invoice,
+                max_fee,
+                max_delay,
+            } => {
+                let preimage = client()
+                    .pay_invoice_for_operator(PayInvoiceForOperatorPayload {
+                        invoice,
+                        max_fee,
+                        max_delay,
+                    })
+                    .await?;
+                print_response(preimage);
+            }
+            LightningCommands::Rebalance {
+                outgoing_short_channel_id,
+                last_hop_pubkey,
+                amount,
+                max_fee,
+            } => {
+                let fee = client()
+                    .rebalance(RebalancePayload {
+                        outgoing_short_channel_id,
+                        last_hop_pubkey,
+                        amount,
+                        max_fee,
+                    })
+                    .await?;
+                print_response(fee);
             }
             LightningCommands::WaitForChainSync {
                 block_height,
@@ -361,6 +802,120 @@ async fn main() -> anyhow::Result<()> {
                 .map_err(|_| anyhow::anyhow!("Timed out waiting for chain sync"))?;
             }
         },
+        Commands::ApiKey(api_key_command) => match api_key_command {
+            ApiKeyCommands::Create { name, scope } => {
+                let key = client()
+                    .create_api_key(CreateApiKeyPayload { name, scope })
+                    .await?;
+                println!("{key}");
+            }
+            ApiKeyCommands::List => {
+                let response = client().list_api_keys().await?;
+                print_response(response);
+            }
+            ApiKeyCommands::Revoke { name } => {
+                client()
+                    .revoke_api_key(RevokeApiKeyPayload { name })
+                    .await?;
+            }
+        },
+        Commands::Sweep(sweep_command) => match sweep_command {
+            SweepCommands::Run {
+                federation_id,
+                float,
+            } => {
+                let response = client()
+                    .sweep(SweepPayload {
+                        federation_id,
+                        float,
+                    })
+                    .await?;
+                print_response(response);
+            }
+            SweepCommands::SetPolicy {
+                federation_id,
+                threshold,
+                float,
+                disable,
+            } => {
+                let policy = threshold
+                    .filter(|_| !disable)
+                    .map(|threshold| SweepPolicy { threshold, float });
+                let response = client()
+                    .set_sweep_policy(SetSweepPolicyPayload {
+                        federation_id,
+                        policy,
+                    })
+                    .await?;
+                print_response(response);
+            }
+            SweepCommands::History { federation_id } => {
+                let response = client()
+                    .list_sweeps(ListSweepsPayload { federation_id })
+                    .await?;
+                print_response(response);
+            }
+        },
+        Commands::Lnurl(lnurl_command) => match lnurl_command {
+            LnurlCommands::Register {
+                username,
+                federation_id,
+                recipient_pk,
+                min_sendable,
+                max_sendable,
+                description,
+            } => {
+                let description = description.unwrap_or_else(|| format!("Payment to {username}"));
+                let response = client()
+                    .set_lnurl_account(SetLnurlAccountPayload {
+                        username,
+                        account: Some(LnurlAccount {
+                            federation_id,
+                            recipient_pk,
+                            min_sendable,
+                            max_sendable,
+                            description,
+                        }),
+                    })
+                    .await?;
+                print_response(response);
+            }
+            LnurlCommands::Remove { username } => {
+                client()
+                    .set_lnurl_account(SetLnurlAccountPayload {
+                        username,
+                        account: None,
+                    })
+                    .await?;
+            }
+            LnurlCommands::List => {
+                let response = client().list_lnurl_accounts().await?;
+                print_response(response);
+            }
+        },
+        Commands::HtlcFilter(htlc_filter_command) => match htlc_filter_command {
+            HtlcFilterCommands::Show => {
+                let response = client().get_htlc_filter().await?;
+                print_response(response);
+            }
+            HtlcFilterCommands::Set { rules, dry_run } => {
+                let rules = serde_json::from_str(&std::fs::read_to_string(rules)?)?;
+                let response = client()
+                    .set_htlc_filter(SetHtlcFilterPayload {
+                        filter: HtlcFilterConfig { rules, dry_run },
+                    })
+                    .await?;
+                print_response(response);
+            }
+            HtlcFilterCommands::Clear => {
+                let response = client()
+                    .set_htlc_filter(SetHtlcFilterPayload {
+                        filter: HtlcFilterConfig::default(),
+                    })
+                    .await?;
+                print_response(response);
+            }
+        },
     }
 
     Ok(())
@@ -372,3 +927,85 @@ pub fn print_response<T: Serialize>(val: T) {
         serde_json::to_string_pretty(&val).expect("Cannot serialize")
     )
 }
+
+fn print_payments(payments: Vec<PaymentRecord>) {
+    print_table(
+        [
+            "CREATED",
+            "FEDERATION",
+            "DIRECTION",
+            "AMOUNT",
+            "FEE",
+            "STATUS",
+            "PAYMENT HASH",
+        ],
+        payments.into_iter().map(|payment| {
+            [
+                payment.created_at.to_string(),
+                payment.federation_id.to_string(),
+                serialized_name(&payment.direction),
+                payment.amount.to_string(),
+                payment.fee.to_string(),
+                serialized_name(&payment.status),
+                payment.payment_hash.to_string(),
+            ]
+        }),
+    );
+}
+
+fn print_channels(channels: Vec<ChannelInfo>) {
+    print_table(
+        [
+            "PEER",
+            "SHORT CHANNEL ID",
+            "SIZE (SATS)",
+            "OUTBOUND (SATS)",
+            "INBOUND (SATS)",
+        ],
+        channels.into_iter().map(|channel| {
+            [
+                channel.remote_pubkey,
+                channel.short_channel_id.to_string(),
+                channel.channel_size_sats.to_string(),
+                channel.outbound_liquidity_sats.to_string(),
+                channel.inbound_liquidity_sats.to_string(),
+            ]
+        }),
+    );
+}
+
+/// Prints the rows below the headers, with each column padded to its widest
+/// cell
+fn print_table<const N: usize>(headers: [&str; N], rows: impl Iterator<Item = [String; N]>) {
+    let rows = rows.collect::<Vec<_>>();
+
+    let mut widths = headers.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let print_row = |cells: Vec<&str>| {
+        let line = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+
+    print_row(headers.to_vec());
+    for row in &rows {
+        print_row(row.iter().map(String::as_str).collect());
+    }
+}
+
+/// Returns the name a unit enum variant is serialized as
+fn serialized_name<T: Serialize>(val: &T) -> String {
+    match serde_json::to_value(val) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
@@ -29,10 +29,11 @@ use ln_gateway::gateway_lnrpc::intercept_htlc_response::{Action, Cancel, Forward
 use ln_gateway::gateway_lnrpc::list_active_channels_response::ChannelInfo;
 use ln_gateway::gateway_lnrpc::{
     CloseChannelsWithPeerRequest, CloseChannelsWithPeerResponse, ConnectToPeerRequest,
-    CreateInvoiceRequest, CreateInvoiceResponse, EmptyRequest, EmptyResponse,
-    GetFundingAddressResponse, GetNodeInfoResponse, GetRouteHintsRequest, GetRouteHintsResponse,
-    InterceptHtlcRequest, InterceptHtlcResponse, ListActiveChannelsResponse, OpenChannelRequest,
-    PayInvoiceRequest, PayInvoiceResponse,
+    CreateInvoiceRequest, CreateInvoiceResponse, CreateOfferRequest, EmptyRequest, EmptyResponse,
+    GetFundingAddressResponse, GetNodeInfoResponse, GetOfferRequest, GetOnchainBalanceResponse,
+    GetRouteHintsRequest, GetRouteHintsResponse, InterceptHtlcRequest, InterceptHtlcResponse,
+    ListActiveChannelsResponse, OfferResponse, OpenChannelRequest, PayInvoiceRequest,
+    PayInvoiceResponse, SendOnchainRequest, SendOnchainResponse,
 };
 use rand::rngs::OsRng;
 use rand::Rng;
@@ -112,6 +113,49 @@ struct HtlcAccepted {
     onion: Onion,
 }
 
+// Offers are not covered by the typed requests of `cln_rpc` yet, so they are
+// requested by method name. Before v24.11 lightningd needs to be started with
+// `--experimental-offers`.
+// See: https://docs.corelightning.org/reference/lightning-offer
+#[derive(Clone, Serialize, Debug)]
+struct ClnOfferRequest {
+    // An amount in millisatoshis suffixed with `msat`, or `any`
+    amount: String,
+    description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    absolute_expiry: Option<u64>,
+}
+
+// See: https://docs.corelightning.org/reference/lightning-listoffers
+#[derive(Clone, Serialize, Debug)]
+struct ClnListOffersRequest {
+    offer_id: String,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+struct ClnListOffersResponse {
+    offers: Vec<ClnOffer>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+struct ClnOffer {
+    offer_id: String,
+    bolt12: String,
+    active: bool,
+    used: bool,
+}
+
+impl From<ClnOffer> for OfferResponse {
+    fn from(offer: ClnOffer) -> Self {
+        OfferResponse {
+            offer_id: offer.offer_id,
+            bolt12: offer.bolt12,
+            active: offer.active,
+            used: offer.used,
+        }
+    }
+}
+
 #[allow(dead_code)]
 struct ClnRpcService {
     socket: PathBuf,
@@ -838,6 +882,154 @@ impl GatewayLightning for ClnRpcService {
             channels,
         }))
     }
+
+    async fn get_onchain_balance(
+        &self,
+        _request: tonic::Request<EmptyRequest>,
+    ) -> Result<tonic::Response<GetOnchainBalanceResponse>, Status> {
+        let outputs = self
+            .rpc_client()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .call(cln_rpc::Request::ListFunds(
+                model::requests::ListfundsRequest { spent: Some(false) },
+            ))
+            .await
+            .map(|response| match response {
+                cln_rpc::Response::ListFunds(model::responses::ListfundsResponse {
+                    outputs,
+                    ..
+                }) => Ok(outputs),
+                _ => Err(ClnExtensionError::RpcWrongResponse),
+            })
+            .map_err(|e| {
+                error!("cln listfunds rpc returned error {:?}", e);
+                tonic::Status::internal(e.to_string())
+            })?
+            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+
+        let mut balance = GetOnchainBalanceResponse {
+            confirmed_balance_sats: 0,
+            unconfirmed_balance_sats: 0,
+        };
+        for output in outputs {
+            let amount_sats = output.amount_msat.msat() / 1000;
+            match output.status {
+                model::responses::ListfundsOutputsStatus::CONFIRMED => {
+                    balance.confirmed_balance_sats += amount_sats;
+                }
+                model::responses::ListfundsOutputsStatus::UNCONFIRMED
+                | model::responses::ListfundsOutputsStatus::IMMATURE => {
+                    balance.unconfirmed_balance_sats += amount_sats;
+                }
+                model::responses::ListfundsOutputsStatus::SPENT => {}
+            }
+        }
+
+        Ok(tonic::Response::new(balance))
+    }
+
+    async fn send_onchain(
+        &self,
+        request: tonic::Request<SendOnchainRequest>,
+    ) -> Result<tonic::Response<SendOnchainResponse>, Status> {
+        let SendOnchainRequest {
+            address,
+            amount_sats,
+            fee_rate_sats_per_vbyte,
+        } = request.into_inner();
+
+        let feerate_per_kb = (fee_rate_sats_per_vbyte * 1000)
+            .try_into()
+            .map_err(|_| Status::invalid_argument("fee rate is too high"))?;
+
+        let txid = self
+            .rpc_client()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .call(cln_rpc::Request::Withdraw(
+                model::requests::WithdrawRequest {
+                    destination: address,
+                    satoshi: cln_rpc::primitives::AmountOrAll::Amount(
+                        cln_rpc::primitives::Amount::from_sat(amount_sats),
+                    ),
+                    feerate: Some(cln_rpc::primitives::Feerate::PerKb(feerate_per_kb)),
+                    minconf: None,
+                    utxos: None,
+                },
+            ))
+            .await
+            .map(|response| match response {
+                cln_rpc::Response::Withdraw(model::responses::WithdrawResponse {
+                    txid, ..
+                }) => Ok(txid),
+                _ => Err(ClnExtensionError::RpcWrongResponse),
+            })
+            .map_err(|e| {
+                error!("cln withdraw rpc returned error {:?}", e);
+                tonic::Status::internal(e.to_string())
+            })?
+            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+
+        Ok(tonic::Response::new(SendOnchainResponse { txid }))
+    }
+
+    async fn create_offer(
+        &self,
+        request: tonic::Request<CreateOfferRequest>,
+    ) -> Result<tonic::Response<OfferResponse>, Status> {
+        let CreateOfferRequest {
+            amount_msat,
+            description,
+            absolute_expiry,
+        } = request.into_inner();
+
+        let offer: ClnOffer = self
+            .rpc_client()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .call_raw(
+                "offer",
+                &ClnOfferRequest {
+                    amount: amount_msat.map_or("any".to_string(), |msat| format!("{msat}msat")),
+                    description,
+                    absolute_expiry,
+                },
+            )
+            .await
+            .map_err(|e| {
+                error!("cln offer rpc returned error {:?}", e);
+                tonic::Status::internal(format!("{e:?}"))
+            })?;
+
+        Ok(tonic::Response::new(offer.into()))
+    }
+
+    async fn get_offer(
+        &self,
+        request: tonic::Request<GetOfferRequest>,
+    ) -> Result<tonic::Response<OfferResponse>, Status> {
+        let GetOfferRequest { offer_id } = request.into_inner();
+
+        let response: ClnListOffersResponse = self
+            .rpc_client()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .call_raw("listoffers", &ClnListOffersRequest { offer_id })
+            .await
+            .map_err(|e| {
+                error!("cln listoffers rpc returned error {:?}", e);
+                tonic::Status::internal(format!("{e:?}"))
+            })?;
+
+        let offer = response
+            .offers
+            .into_iter()
+            .next()
+            .ok_or_else(|| Status::not_found("No offer with this id"))?;
+
+        Ok(tonic::Response::new(offer.into()))
+    }
 }
 
 #[derive(Debug, Error)]
@@ -3,30 +3,62 @@ use std::fmt::Debug;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use anyhow::Context;
+use fedimint_aead::LessSafeKey;
 use fedimint_client::module::init::ClientModuleInitRegistry;
 use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
 use fedimint_client::Client;
+use fedimint_core::config::FederationId;
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::db::{
-    Committable, Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped,
+    Committable, Database, DatabaseTransaction, IDatabaseTransactionOpsCore,
+    IDatabaseTransactionOpsCoreTyped, IRawDatabase,
 };
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use futures::StreamExt;
 use rand::thread_rng;
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::db::{FederationConfig, FederationIdKey, FederationIdKeyPrefix};
+use crate::encrypted_db::EncryptedDatabase;
 use crate::gateway_module_v2::GatewayClientInitV2;
 use crate::state_machine::GatewayClientInit;
 use crate::{Gateway, GatewayError, Result};
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct GatewayClientBuilder {
     work_dir: PathBuf,
     registry: ClientModuleInitRegistry,
     primary_module: ModuleInstanceId,
+    /// Key the client databases are encrypted with, if enabled
+    db_key: Option<Arc<LessSafeKey>>,
+    /// Postgres instance the client databases are stored in instead of the
+    /// work dir, if set
+    postgres_uri: Option<String>,
 }
 
+impl Debug for GatewayClientBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The Postgres URI usually contains credentials
+        f.debug_struct("GatewayClientBuilder")
+            .field("work_dir", &self.work_dir)
+            .field("registry", &self.registry)
+            .field("primary_module", &self.primary_module)
+            .field("encrypted", &self.is_encrypted())
+            .field("postgres", &self.postgres_uri.is_some())
+            .finish()
+    }
+}
+
+/// Salt of the key the client databases are encrypted with
+const CLIENT_DB_SALT_FILE: &str = "client_db.salt";
+
+/// Constant encrypted with the key of the client databases, used to reject a
+/// wrong password before any database is opened with it
+const CLIENT_DB_CHECK_FILE: &str = "client_db.check";
+
+const CLIENT_DB_CHECK_PLAINTEXT: &[u8] = b"gatewayd client databases";
+
 impl GatewayClientBuilder {
     pub fn new(
         work_dir: PathBuf,
@@ -37,8 +69,54 @@ impl GatewayClientBuilder {
             work_dir,
             registry,
             primary_module,
+            db_key: None,
+            postgres_uri: None,
         }
     }
+
+    /// Stores the client databases in the Postgres instance at `uri`, one
+    /// table per federation. Existing client databases in the work dir are
+    /// not migrated.
+    pub fn with_postgres(mut self, uri: String) -> Self {
+        self.postgres_uri = Some(uri);
+        self
+    }
+
+    /// Encrypts the values of the client databases with a key derived from
+    /// `password`. Databases that were created unencrypted stay unencrypted.
+    pub fn with_encryption(mut self, password: &str) -> anyhow::Result<Self> {
+        let salt_path = self.work_dir.join(CLIENT_DB_SALT_FILE);
+        let check_path = self.work_dir.join(CLIENT_DB_CHECK_FILE);
+
+        let key = if salt_path.exists() {
+            let salt = std::fs::read_to_string(&salt_path)?;
+            let key = fedimint_aead::get_encryption_key(password, salt.trim())?;
+            let check = fedimint_aead::encrypted_read(&key, check_path)
+                .context("Wrong password for the encrypted client databases")?;
+            anyhow::ensure!(
+                check == CLIENT_DB_CHECK_PLAINTEXT,
+                "Wrong password for the encrypted client databases"
+            );
+            key
+        } else {
+            let salt = fedimint_aead::random_salt();
+            let key = fedimint_aead::get_encryption_key(password, &salt)?;
+            if check_path.exists() {
+                std::fs::remove_file(&check_path)?;
+            }
+            fedimint_aead::encrypted_write(CLIENT_DB_CHECK_PLAINTEXT.to_vec(), &key, check_path)?;
+            // The salt is written last, so a partial setup is redone on the next start
+            std::fs::write(&salt_path, salt)?;
+            key
+        };
+
+        self.db_key = Some(Arc::new(key));
+        Ok(self)
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.db_key.is_some()
+    }
 }
 
 impl GatewayClientBuilder {
@@ -64,31 +142,30 @@ impl GatewayClientBuilder {
         });
         registry.attach(GatewayClientInitV2 { gateway });
 
-        let db_path = self.work_dir.join(format!("{federation_id}.db"));
-
-        let rocksdb = fedimint_rocksdb::RocksDb::open(db_path.clone()).map_err(|e| {
-            GatewayError::DatabaseError(anyhow::anyhow!("Error opening rocksdb: {e:?}"))
-        })?;
-        let db = Database::new(rocksdb, ModuleDecoderRegistry::default());
+        let db = self.open_database(federation_id).await?;
 
         let mut client_builder = Client::builder(db);
         client_builder.with_module_inits(registry);
         client_builder.with_primary_module(self.primary_module);
 
-        let client_secret =
-            match Client::load_decodable_client_secret::<[u8; 64]>(client_builder.db_no_decoders())
-                .await
-            {
-                Ok(secret) => secret,
-                Err(_) => {
-                    info!("Generating secret and writing to client storage");
-                    let secret = PlainRootSecretStrategy::random(&mut thread_rng());
-                    Client::store_encodable_client_secret(client_builder.db_no_decoders(), secret)
-                        .await
-                        .map_err(GatewayError::ClientStateMachineError)?;
-                    secret
-                }
-            };
+        // Only generate a new secret if there is none, a secret that can not be
+        // read must never be overwritten
+        let client_secret = match Client::load_decodable_client_secret_opt::<[u8; 64]>(
+            client_builder.db_no_decoders(),
+        )
+        .await
+        .map_err(GatewayError::DatabaseError)?
+        {
+            Some(secret) => secret,
+            None => {
+                info!("Generating secret and writing to client storage");
+                let secret = PlainRootSecretStrategy::random(&mut thread_rng());
+                Client::store_encodable_client_secret(client_builder.db_no_decoders(), secret)
+                    .await
+                    .map_err(GatewayError::ClientStateMachineError)?;
+                secret
+            }
+        };
 
         let root_secret = PlainRootSecretStrategy::to_root_secret(&client_secret);
         if Client::is_initialized(client_builder.db_no_decoders()).await {
@@ -108,6 +185,99 @@ impl GatewayClientBuilder {
         .map_err(GatewayError::ClientStateMachineError)
     }
 
+    /// Stores the root secret of a client that has not been built yet, so it
+    /// is built with `secret` instead of a random one
+    pub async fn store_client_secret(
+        &self,
+        federation_id: FederationId,
+        secret: [u8; 64],
+    ) -> Result<()> {
+        let db = self.open_database(federation_id).await?;
+        if Client::load_decodable_client_secret_opt::<[u8; 64]>(&db)
+            .await
+            .map_err(GatewayError::DatabaseError)?
+            .is_some()
+        {
+            return Err(GatewayError::FederationAlreadyConnected);
+        }
+
+        Client::store_encodable_client_secret(&db, secret)
+            .await
+            .map_err(GatewayError::DatabaseError)
+    }
+
+    async fn open_database(&self, federation_id: FederationId) -> Result<Database> {
+        match &self.postgres_uri {
+            Some(uri) => {
+                // Half of the id keeps the table name within the limits of Postgres
+                let name = format!("client_{}", &federation_id.to_string()[..32]);
+                let db = fedimint_postgres::PostgresDb::open(uri.as_str(), &name)
+                    .await
+                    .map_err(|e| {
+                        GatewayError::DatabaseError(anyhow::anyhow!(
+                            "Error opening postgres: {e:?}"
+                        ))
+                    })?;
+                self.wrap_database(federation_id, db).await
+            }
+            None => {
+                let db_path = self.work_dir.join(format!("{federation_id}.db"));
+                let rocksdb = fedimint_rocksdb::RocksDb::open(db_path).map_err(|e| {
+                    GatewayError::DatabaseError(anyhow::anyhow!("Error opening rocksdb: {e:?}"))
+                })?;
+                self.wrap_database(federation_id, rocksdb).await
+            }
+        }
+    }
+
+    /// Encrypts the client database of a federation if it is new or was
+    /// created encrypted
+    async fn wrap_database(
+        &self,
+        federation_id: FederationId,
+        db: impl IRawDatabase,
+    ) -> Result<Database> {
+        let marker_path = self.work_dir.join(format!("{federation_id}.db.encrypted"));
+
+        let Some(key) = &self.db_key else {
+            if marker_path.exists() {
+                return Err(GatewayError::DatabaseError(anyhow::anyhow!(
+                    "Client database of federation {federation_id} is encrypted, start the \
+                     gateway with its password and client database encryption enabled"
+                )));
+            }
+            return Ok(Database::new(db, ModuleDecoderRegistry::default()));
+        };
+
+        if !marker_path.exists() {
+            let is_empty = db
+                .begin_transaction()
+                .await
+                .raw_find_by_prefix(&[])
+                .await
+                .map_err(GatewayError::DatabaseError)?
+                .next()
+                .await
+                .is_none();
+
+            if !is_empty {
+                warn!("Client database of federation {federation_id} is not encrypted");
+                return Ok(Database::new(db, ModuleDecoderRegistry::default()));
+            }
+
+            std::fs::File::create(&marker_path).map_err(|e| {
+                GatewayError::DatabaseError(anyhow::anyhow!(
+                    "Error marking client database as encrypted: {e:?}"
+                ))
+            })?;
+        }
+
+        Ok(Database::new(
+            EncryptedDatabase::new(db, key.clone()),
+            ModuleDecoderRegistry::default(),
+        ))
+    }
+
     pub async fn save_config(
         &self,
         config: FederationConfig,
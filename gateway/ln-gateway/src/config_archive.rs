@@ -0,0 +1,76 @@
+use anyhow::Context;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::secp256k1::KeyPair;
+
+use crate::db::{FederationConfig, GatewayConfiguration};
+use crate::rpc::{EncryptedConfigArchive, FederationLimits};
+
+/// Everything a gateway needs to take over from another gateway without
+/// reconnecting to its federations
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct GatewayConfigArchive {
+    pub gateway_config: Option<GatewayConfiguration>,
+    /// Keypair the gateway registers with the federations under
+    pub keypair: KeyPair,
+    pub federations: Vec<FederationArchive>,
+}
+
+/// The config and client secret of a connected federation
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct FederationArchive {
+    pub config: FederationConfig,
+    /// Root secret of the gateway's client, from which the e-cash and the
+    /// LNv2 keys of the gateway are derived
+    pub client_secret: [u8; 64],
+    pub limits: Option<FederationLimits>,
+}
+
+impl GatewayConfigArchive {
+    /// Encrypts the archive with a key derived from `password`
+    pub fn encrypt(&self, password: &str) -> anyhow::Result<EncryptedConfigArchive> {
+        let salt = fedimint_aead::random_salt();
+        let key = fedimint_aead::get_encryption_key(password, &salt)?;
+        let ciphertext = fedimint_aead::encrypt(self.consensus_encode_to_vec(), &key)?;
+
+        Ok(EncryptedConfigArchive {
+            salt,
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    pub fn decrypt(archive: &EncryptedConfigArchive, password: &str) -> anyhow::Result<Self> {
+        let key = fedimint_aead::get_encryption_key(password, &archive.salt)?;
+        let mut ciphertext = hex::decode(&archive.ciphertext)?;
+        let plaintext = fedimint_aead::decrypt(&mut ciphertext, &key)
+            .context("Wrong password or corrupted archive")?;
+
+        Ok(Self::consensus_decode_vec(
+            plaintext.to_vec(),
+            &ModuleDecoderRegistry::default(),
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::secp256k1::{KeyPair, Secp256k1};
+    use rand::rngs::OsRng;
+
+    use super::GatewayConfigArchive;
+
+    #[test]
+    fn archive_roundtrips_only_with_password() {
+        let archive = GatewayConfigArchive {
+            gateway_config: None,
+            keypair: KeyPair::new(&Secp256k1::new(), &mut OsRng),
+            federations: vec![],
+        };
+
+        let encrypted = archive.encrypt("correct horse").unwrap();
+        let decrypted = GatewayConfigArchive::decrypt(&encrypted, "correct horse").unwrap();
+        assert_eq!(decrypted.keypair, archive.keypair);
+
+        assert!(GatewayConfigArchive::decrypt(&encrypted, "battery staple").is_err());
+    }
+}
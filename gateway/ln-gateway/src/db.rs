@@ -3,14 +3,16 @@ use std::collections::BTreeMap;
 use bitcoin::Network;
 use bitcoin_hashes::sha256;
 use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
 use fedimint_core::db::{
     DatabaseTransaction, DatabaseVersion, IDatabaseTransactionOpsCoreTyped, ServerMigrationFn,
 };
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::invite_code::InviteCode;
-use fedimint_core::{impl_db_lookup, impl_db_record, secp256k1};
+use fedimint_core::{impl_db_lookup, impl_db_record, secp256k1, Amount};
 use fedimint_ln_common::serde_routing_fees;
 use fedimint_lnv2_client::CreateInvoicePayload;
+use fedimint_lnv2_common::contracts::IncomingContract;
 use futures::FutureExt;
 use lightning_invoice::RoutingFees;
 use rand::Rng;
@@ -18,6 +20,10 @@ use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 
 use crate::rpc::rpc_server::hash_password;
+use crate::rpc::{
+    ApiScope, FederationLimits, HtlcFilterConfig, LnurlAccount, PaymentDirection, PaymentRecord,
+    SweepPolicy, SweepRecord,
+};
 
 pub const GATEWAYD_DATABASE_VERSION: DatabaseVersion = DatabaseVersion(1);
 
@@ -29,6 +35,17 @@ pub enum DbKeyPrefix {
     GatewayConfiguration = 0x07,
     PreimageAuthentication = 0x08,
     CreateInvoicePayload = 0x09,
+    Payment = 0x0a,
+    FederationLimits = 0x0b,
+    DailyOutflow = 0x0c,
+    ApiKey = 0x0d,
+    InterceptedHtlc = 0x0e,
+    SweepPolicy = 0x0f,
+    Sweep = 0x10,
+    Offer = 0x11,
+    LnurlAccount = 0x12,
+    LnurlContract = 0x13,
+    HtlcFilter = 0x14,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -162,6 +179,254 @@ impl_db_record!(
     db_prefix = DbKeyPrefix::CreateInvoicePayload,
 );
 
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct PaymentKey {
+    pub payment_hash: sha256::Hash,
+    pub direction: PaymentDirection,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PaymentKeyPrefix;
+
+impl_db_record!(
+    key = PaymentKey,
+    value = PaymentRecord,
+    db_prefix = DbKeyPrefix::Payment,
+);
+
+impl_db_lookup!(key = PaymentKey, query_prefix = PaymentKeyPrefix);
+
+/// Limits of a federation that override the gateway's default limits
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct FederationLimitsKey {
+    pub id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FederationLimitsKeyPrefix;
+
+impl_db_record!(
+    key = FederationLimitsKey,
+    value = FederationLimits,
+    db_prefix = DbKeyPrefix::FederationLimits,
+);
+
+impl_db_lookup!(
+    key = FederationLimitsKey,
+    query_prefix = FederationLimitsKeyPrefix
+);
+
+/// Sum of the outgoing payments and withdrawals of a federation on a UTC day,
+/// counted in days since the unix epoch
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct DailyOutflowKey {
+    pub federation_id: FederationId,
+    pub day: u64,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct DailyOutflowKeyPrefix;
+
+impl_db_record!(
+    key = DailyOutflowKey,
+    value = Amount,
+    db_prefix = DbKeyPrefix::DailyOutflow,
+);
+
+impl_db_lookup!(key = DailyOutflowKey, query_prefix = DailyOutflowKeyPrefix);
+
+/// API keys start with a random id they are stored under, so a key is only
+/// checked against a single stored hash
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct ApiKeyKey {
+    pub key_id: String,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ApiKeyKeyPrefix;
+
+/// API credential that grants access to the routes of its scope. Only a hash
+/// of the key is stored, like the gateway's password.
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct ApiKeyRecord {
+    pub name: String,
+    pub hashed_key: sha256::Hash,
+    pub salt: [u8; 16],
+    pub scope: ApiScope,
+    /// Seconds since the unix epoch
+    pub created_at: u64,
+}
+
+impl_db_record!(
+    key = ApiKeyKey,
+    value = ApiKeyRecord,
+    db_prefix = DbKeyPrefix::ApiKey,
+);
+
+impl_db_lookup!(key = ApiKeyKey, query_prefix = ApiKeyKeyPrefix);
+
+/// An HTLC held by the lightning node, identified like in
+/// `InterceptHtlcResponse`
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct InterceptedHtlcKey {
+    pub incoming_chan_id: u64,
+    pub htlc_id: u64,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct InterceptedHtlcKeyPrefix;
+
+/// An HTLC paying into one of the gateway's federations that has not been
+/// settled or cancelled yet, so it can be resolved after a restart
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct InterceptedHtlc {
+    pub federation_id: FederationId,
+    pub payment_hash: sha256::Hash,
+    pub short_channel_id: Option<u64>,
+    pub incoming_amount_msat: u64,
+    pub outgoing_amount_msat: u64,
+    /// Whether the HTLC pays a LNv2 invoice instead of a legacy one
+    pub lnv2: bool,
+    pub stage: InterceptedHtlcStage,
+    /// Seconds since the unix epoch
+    pub updated_at: u64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub enum InterceptedHtlcStage {
+    /// The gateway is handing the HTLC to the client of the federation
+    Intercepted,
+    /// The client funds the incoming contract and settles or cancels the HTLC
+    /// once the contract is decrypted
+    Funding(OperationId),
+}
+
+impl_db_record!(
+    key = InterceptedHtlcKey,
+    value = InterceptedHtlc,
+    db_prefix = DbKeyPrefix::InterceptedHtlc,
+);
+
+impl_db_lookup!(
+    key = InterceptedHtlcKey,
+    query_prefix = InterceptedHtlcKeyPrefix
+);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct SweepPolicyKey {
+    pub id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct SweepPolicyKeyPrefix;
+
+impl_db_record!(
+    key = SweepPolicyKey,
+    value = SweepPolicy,
+    db_prefix = DbKeyPrefix::SweepPolicy,
+);
+
+impl_db_lookup!(key = SweepPolicyKey, query_prefix = SweepPolicyKeyPrefix);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct SweepKey {
+    pub federation_id: FederationId,
+    pub txid: bitcoin::Txid,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct SweepKeyPrefix;
+
+impl_db_record!(
+    key = SweepKey,
+    value = SweepRecord,
+    db_prefix = DbKeyPrefix::Sweep,
+);
+
+impl_db_lookup!(key = SweepKey, query_prefix = SweepKeyPrefix);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct OfferKey {
+    pub offer_id: String,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct OfferKeyPrefix;
+
+/// BOLT12 offer the gateway created for a federation, its state is tracked by
+/// the lightning node
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct OfferRecord {
+    pub federation_id: FederationId,
+    pub bolt12: String,
+    pub amount: Option<Amount>,
+    pub description: String,
+    /// Seconds since the unix epoch
+    pub created_at: u64,
+}
+
+impl_db_record!(
+    key = OfferKey,
+    value = OfferRecord,
+    db_prefix = DbKeyPrefix::Offer,
+);
+
+impl_db_lookup!(key = OfferKey, query_prefix = OfferKeyPrefix);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct LnurlAccountKey {
+    pub username: String,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LnurlAccountKeyPrefix;
+
+impl_db_record!(
+    key = LnurlAccountKey,
+    value = LnurlAccount,
+    db_prefix = DbKeyPrefix::LnurlAccount,
+);
+
+impl_db_lookup!(key = LnurlAccountKey, query_prefix = LnurlAccountKeyPrefix);
+
+/// Incoming contract of an invoice the gateway issued for a lightning address,
+/// kept until it expires so that the user can claim it
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct LnurlContractKey {
+    pub username: String,
+    pub payment_hash: sha256::Hash,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LnurlContractKeyPrefix;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LnurlContractUsernamePrefix {
+    pub username: String,
+}
+
+impl_db_record!(
+    key = LnurlContractKey,
+    value = IncomingContract,
+    db_prefix = DbKeyPrefix::LnurlContract,
+);
+
+impl_db_lookup!(
+    key = LnurlContractKey,
+    query_prefix = LnurlContractKeyPrefix,
+    query_prefix = LnurlContractUsernamePrefix
+);
+
+/// Rules the gateway applies to intercepted HTLCs
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct HtlcFilterKey;
+
+impl_db_record!(
+    key = HtlcFilterKey,
+    value = HtlcFilterConfig,
+    db_prefix = DbKeyPrefix::HtlcFilter,
+);
+
 #[cfg(test)]
 mod fedimint_migration_tests {
     use std::str::FromStr;
@@ -289,7 +554,18 @@ mod fedimint_migration_tests {
                             ensure!(gateway_configuration.is_some(), "validate_migrations was not able to read GatewayConfiguration");
                             info!("Validated GatewayConfiguration");
                         }
-                        DbKeyPrefix::CreateInvoicePayload => {}
+                        DbKeyPrefix::CreateInvoicePayload
+                        | DbKeyPrefix::Payment
+                        | DbKeyPrefix::FederationLimits
+                        | DbKeyPrefix::DailyOutflow
+                        | DbKeyPrefix::ApiKey
+                        | DbKeyPrefix::InterceptedHtlc
+                        | DbKeyPrefix::SweepPolicy
+                        | DbKeyPrefix::Sweep
+                        | DbKeyPrefix::Offer
+                        | DbKeyPrefix::LnurlAccount
+                        | DbKeyPrefix::LnurlContract
+                        | DbKeyPrefix::HtlcFilter => {}
                     }
                 }
                 Ok(())
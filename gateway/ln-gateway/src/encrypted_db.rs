@@ -0,0 +1,202 @@
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::Context;
+use fedimint_aead::LessSafeKey;
+use fedimint_core::db::{
+    IDatabaseTransactionOps, IDatabaseTransactionOpsCore, IRawDatabase, IRawDatabaseTransaction,
+    PrefixStream,
+};
+use fedimint_core::{apply, async_trait_maybe_send};
+use futures::{stream, StreamExt};
+
+/// Database that encrypts and authenticates the values it stores in the
+/// wrapped database, so that the client secret and the e-cash notes of a
+/// federation can not be read from a stolen data dir.
+///
+/// Keys are stored in plaintext since prefix queries depend on their order.
+pub struct EncryptedDatabase<DB> {
+    inner: DB,
+    key: Arc<LessSafeKey>,
+}
+
+impl<DB> EncryptedDatabase<DB> {
+    pub fn new(inner: DB, key: Arc<LessSafeKey>) -> Self {
+        Self { inner, key }
+    }
+}
+
+impl<DB: fmt::Debug> fmt::Debug for EncryptedDatabase<DB> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedDatabase")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<DB> IRawDatabase for EncryptedDatabase<DB>
+where
+    DB: IRawDatabase,
+{
+    type Transaction<'a> = EncryptedTransaction<DB::Transaction<'a>>;
+
+    async fn begin_transaction<'a>(&'a self) -> Self::Transaction<'a> {
+        EncryptedTransaction {
+            inner: self.inner.begin_transaction().await,
+            key: self.key.clone(),
+        }
+    }
+
+    async fn compact_prefix(&self, key_prefix: &[u8]) -> anyhow::Result<()> {
+        self.inner.compact_prefix(key_prefix).await
+    }
+}
+
+pub struct EncryptedTransaction<Tx> {
+    inner: Tx,
+    key: Arc<LessSafeKey>,
+}
+
+impl<Tx: fmt::Debug> fmt::Debug for EncryptedTransaction<Tx> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedTransaction")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+fn decrypt(key: &LessSafeKey, mut value: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    Ok(fedimint_aead::decrypt(&mut value, key)
+        .context("Failed to decrypt database value, it was encrypted with another key")?
+        .to_vec())
+}
+
+fn decrypt_opt(key: &LessSafeKey, value: Option<Vec<u8>>) -> anyhow::Result<Option<Vec<u8>>> {
+    value.map(|value| decrypt(key, value)).transpose()
+}
+
+/// Decrypts the values of `entries`, failing on the first value that does not
+/// authenticate instead of handing out a stream that can not report it
+async fn decrypt_entries(
+    key: &LessSafeKey,
+    entries: PrefixStream<'_>,
+) -> anyhow::Result<PrefixStream<'static>> {
+    let entries = entries
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .map(|(k, value)| Ok((k, decrypt(key, value)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(Box::pin(stream::iter(entries)))
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<Tx> IDatabaseTransactionOpsCore for EncryptedTransaction<Tx>
+where
+    Tx: IDatabaseTransactionOpsCore,
+{
+    async fn raw_insert_bytes(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let ciphertext = fedimint_aead::encrypt(value.to_vec(), &self.key)?;
+        let old_value = self.inner.raw_insert_bytes(key, &ciphertext).await?;
+        decrypt_opt(&self.key, old_value)
+    }
+
+    async fn raw_get_bytes(&mut self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let value = self.inner.raw_get_bytes(key).await?;
+        decrypt_opt(&self.key, value)
+    }
+
+    async fn raw_remove_entry(&mut self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let old_value = self.inner.raw_remove_entry(key).await?;
+        decrypt_opt(&self.key, old_value)
+    }
+
+    async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> anyhow::Result<PrefixStream<'_>> {
+        let entries = self.inner.raw_find_by_prefix(key_prefix).await?;
+        decrypt_entries(&self.key, entries).await
+    }
+
+    async fn raw_find_by_prefix_sorted_descending(
+        &mut self,
+        key_prefix: &[u8],
+    ) -> anyhow::Result<PrefixStream<'_>> {
+        let entries = self
+            .inner
+            .raw_find_by_prefix_sorted_descending(key_prefix)
+            .await?;
+        decrypt_entries(&self.key, entries).await
+    }
+
+    async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> anyhow::Result<()> {
+        self.inner.raw_remove_by_prefix(key_prefix).await
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<Tx> IDatabaseTransactionOps for EncryptedTransaction<Tx>
+where
+    Tx: IDatabaseTransactionOps,
+{
+    async fn set_tx_savepoint(&mut self) -> anyhow::Result<()> {
+        self.inner.set_tx_savepoint().await
+    }
+
+    async fn rollback_tx_to_savepoint(&mut self) -> anyhow::Result<()> {
+        self.inner.rollback_tx_to_savepoint().await
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<Tx> IRawDatabaseTransaction for EncryptedTransaction<Tx>
+where
+    Tx: IRawDatabaseTransaction,
+{
+    async fn commit_tx(self) -> anyhow::Result<()> {
+        self.inner.commit_tx().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{IDatabaseTransactionOpsCore, IRawDatabase, IRawDatabaseTransaction};
+
+    use super::EncryptedDatabase;
+
+    fn key(password: &str) -> Arc<fedimint_aead::LessSafeKey> {
+        Arc::new(fedimint_aead::get_encryption_key(password, "client-db-test-salt").unwrap())
+    }
+
+    #[tokio::test]
+    async fn values_are_encrypted_at_rest() {
+        let db = EncryptedDatabase::new(MemDatabase::new(), key("password"));
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_insert_bytes(b"note", b"secret").await.unwrap();
+        dbtx.commit_tx().await.unwrap();
+
+        let mut dbtx = db.begin_transaction().await;
+        assert_eq!(
+            dbtx.raw_get_bytes(b"note").await.unwrap(),
+            Some(b"secret".to_vec())
+        );
+        drop(dbtx);
+
+        let mut raw_dbtx = db.inner.begin_transaction().await;
+        let stored = raw_dbtx.raw_get_bytes(b"note").await.unwrap().unwrap();
+        assert_ne!(stored, b"secret".to_vec());
+        drop(raw_dbtx);
+
+        let db = EncryptedDatabase::new(db.inner, key("wrong password"));
+        let mut dbtx = db.begin_transaction().await;
+        assert!(dbtx.raw_get_bytes(b"note").await.is_err());
+    }
+}
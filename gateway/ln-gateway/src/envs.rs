@@ -33,3 +33,49 @@ pub const FM_LND_MACAROON_ENV: &str = "FM_LND_MACAROON";
 
 // Env variable to TODO
 pub const FM_GATEWAY_LIGHTNING_ADDR_ENV: &str = "FM_GATEWAY_LIGHTNING_ADDR";
+
+// Env variable to set the Esplora server the embedded LDK node syncs from
+pub const FM_LDK_ESPLORA_SERVER_URL_ENV: &str = "FM_LDK_ESPLORA_SERVER_URL";
+
+// Env variable to set the bitcoin network of the embedded LDK node
+pub const FM_LDK_NETWORK_ENV: &str = "FM_LDK_NETWORK";
+
+// Env variable to set the port the embedded LDK node accepts peers on
+pub const FM_LDK_LIGHTNING_PORT_ENV: &str = "FM_LDK_LIGHTNING_PORT";
+
+// Env variable to set the url of the Eclair HTTP API
+pub const FM_ECLAIR_API_URL_ENV: &str = "FM_ECLAIR_API_URL";
+
+// Env variable to set the password of the Eclair HTTP API
+pub const FM_ECLAIR_PASSWORD_ENV: &str = "FM_ECLAIR_PASSWORD";
+
+// Env variable to set the Nostr Wallet Connect uri of the lightning wallet
+pub const FM_NWC_URI_ENV: &str = "FM_NWC_URI";
+
+// Env variable to set the lightning backends payments fail over to
+pub const FM_GATEWAY_FALLBACK_LIGHTNING_ENV: &str = "FM_GATEWAY_FALLBACK_LIGHTNING";
+
+// Env variable to set the inbound liquidity below which the gateway warns
+pub const FM_GATEWAY_MIN_INBOUND_LIQUIDITY_ENV: &str = "FM_GATEWAY_MIN_INBOUND_LIQUIDITY";
+
+// Env variable to set the outbound liquidity below which the gateway warns
+pub const FM_GATEWAY_MIN_OUTBOUND_LIQUIDITY_ENV: &str = "FM_GATEWAY_MIN_OUTBOUND_LIQUIDITY";
+
+// Env variable to set the default limit on a single outgoing payment
+pub const FM_GATEWAY_MAX_PAYMENT_ENV: &str = "FM_GATEWAY_MAX_PAYMENT";
+
+// Env variable to set the default limit on the daily outflow of a federation
+pub const FM_GATEWAY_MAX_DAILY_OUTFLOW_ENV: &str = "FM_GATEWAY_MAX_DAILY_OUTFLOW";
+
+// Env variable to set the default limit on the e-cash balance in a federation
+pub const FM_GATEWAY_MAX_ECASH_BALANCE_ENV: &str = "FM_GATEWAY_MAX_ECASH_BALANCE";
+
+// Env variable to serve the lightning addresses registered with the gateway
+pub const FM_GATEWAY_ENABLE_LNURL_ENV: &str = "FM_GATEWAY_ENABLE_LNURL";
+
+// Env variable to encrypt the client databases of newly connected federations
+pub const FM_GATEWAY_ENCRYPT_CLIENT_DBS_ENV: &str = "FM_GATEWAY_ENCRYPT_CLIENT_DBS";
+
+// Env variable to store the client databases in Postgres instead of the data
+// dir
+pub const FM_GATEWAY_CLIENT_DB_POSTGRES_URI_ENV: &str = "FM_GATEWAY_CLIENT_DB_POSTGRES_URI";
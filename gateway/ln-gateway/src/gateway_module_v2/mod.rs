@@ -304,11 +304,11 @@ impl GatewayClientModuleV2 {
         incoming_chan_id: u64,
         htlc_id: u64,
         payload: CreateInvoicePayload,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<OperationId> {
         let operation_id = OperationId::from_encodable(&payload.clone());
 
         if self.client_ctx.operation_exists(operation_id).await {
-            return Ok(());
+            return Ok(operation_id);
         }
 
         let refund_keypair = self.keypair;
@@ -351,7 +351,21 @@ impl GatewayClientModuleV2 {
             )
             .await?;
 
-        Ok(())
+        Ok(operation_id)
+    }
+
+    /// Waits until the incoming HTLC relayed by the operation has been settled
+    /// or cancelled on the lightning node
+    pub async fn subscribe_completion(&self, operation_id: OperationId) {
+        let mut stream = self.notifier.subscribe(operation_id).await;
+
+        loop {
+            if let Some(GatewayClientStateMachinesV2::Complete(state)) = stream.next().await {
+                if state.state == CompleteSMState::Completed {
+                    return;
+                }
+            }
+        }
     }
 
     pub async fn relay_direct_swap(
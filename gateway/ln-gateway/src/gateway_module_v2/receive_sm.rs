@@ -185,7 +185,7 @@ impl ReceiveStateMachine {
                 .request_with_strategy(
                     FilterMapThreshold::new(
                         verify_decryption_share.clone(),
-                        global_context.api().all_peers().total(),
+                        global_context.api().all_peers().to_num_peers(),
                     ),
                     AWAIT_OUTPUT_OUTCOME_ENDPOINT.to_owned(),
                     ApiRequestErased::new(out_point),
@@ -7,6 +7,7 @@ use fedimint_client::DynGlobalClientContext;
 use fedimint_core::core::OperationId;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::secp256k1::KeyPair;
+use fedimint_core::time::duration_since_epoch;
 use fedimint_core::{Amount, OutPoint};
 use fedimint_ln_common::PrunedInvoice;
 use fedimint_lnv2_client::LightningClientStateMachines;
@@ -17,6 +18,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::gateway_lnrpc::PayInvoiceRequest;
 use crate::gateway_module_v2::{GatewayClientContextV2, GatewayClientModuleV2};
+use crate::lightning::MAX_PAYMENT_PARTS;
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
 pub struct SendStateMachine {
@@ -131,7 +133,7 @@ impl SendStateMachine {
         // The following three checks may fail in edge cases since they have inherent
         // timing assumptions. Therefore, they may only be checked after we have created
         // the state machine such that we can cancel the contract.
-        if invoice.is_expired() {
+        if invoice.would_expire(duration_since_epoch()) {
             return Err(Cancelled::InvoiceExpired);
         }
 
@@ -176,6 +178,7 @@ impl SendStateMachine {
                     PrunedInvoice::try_from(invoice).expect("Invoice has amount"),
                     max_delay,
                     max_fee,
+                    MAX_PAYMENT_PARTS,
                 )
                 .await
         } else {
@@ -189,14 +192,14 @@ impl SendStateMachine {
                 })
                 .await
         }
-        .map(|response| {
+        .map_err(|e| Cancelled::LightningRpcError(e.to_string()))
+        .and_then(|response| {
             response
                 .preimage
                 .as_slice()
                 .try_into()
-                .expect("Preimage is 32 bytes")
+                .map_err(|_| Cancelled::LightningRpcError("Preimage is not 32 bytes".to_string()))
         })
-        .map_err(|e| Cancelled::LightningRpcError(e.to_string()))
     }
 
     async fn transition_send_payment(
@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use fedimint_core::config::FederationId;
+use fedimint_core::Amount;
+use tracing::info;
+
+use crate::rpc::{HtlcFilterConfig, HtlcRuleAction};
+
+/// Window over which the `max_per_minute` of a rule is enforced
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Applies the operator's `HtlcFilterConfig` to the HTLCs intercepted for the
+/// connected federations before the gateway accepts them.
+#[derive(Debug, Default)]
+pub struct HtlcFilter {
+    state: Mutex<HtlcFilterState>,
+}
+
+#[derive(Debug, Default)]
+struct HtlcFilterState {
+    config: HtlcFilterConfig,
+    /// Times at which each rate limited rule accepted an HTLC within the
+    /// current window, oldest first
+    accepted: BTreeMap<String, VecDeque<SystemTime>>,
+}
+
+impl HtlcFilter {
+    pub fn new(config: HtlcFilterConfig) -> Self {
+        HtlcFilter {
+            state: Mutex::new(HtlcFilterState {
+                config,
+                accepted: BTreeMap::new(),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> HtlcFilterConfig {
+        self.state.lock().expect("Lock poisoned").config.clone()
+    }
+
+    /// Replaces the rules, which also resets their rate limits
+    pub fn set_config(&self, config: HtlcFilterConfig) {
+        *self.state.lock().expect("Lock poisoned") = HtlcFilterState {
+            config,
+            accepted: BTreeMap::new(),
+        };
+    }
+
+    /// Returns why an HTLC of `amount` to `federation_id` over
+    /// `short_channel_id` has to be rejected, or `None` if it can be accepted
+    pub fn check(
+        &self,
+        short_channel_id: Option<u64>,
+        federation_id: FederationId,
+        amount: Amount,
+    ) -> Option<String> {
+        self.check_at(
+            short_channel_id,
+            federation_id,
+            amount,
+            fedimint_core::time::now(),
+        )
+    }
+
+    fn check_at(
+        &self,
+        short_channel_id: Option<u64>,
+        federation_id: FederationId,
+        amount: Amount,
+        now: SystemTime,
+    ) -> Option<String> {
+        let mut state = self.state.lock().expect("Lock poisoned");
+        let HtlcFilterState { config, accepted } = &mut *state;
+
+        let rule = config
+            .rules
+            .iter()
+            .find(|rule| rule.matches(short_channel_id, federation_id, amount))?;
+
+        let denial = match (rule.action, rule.max_per_minute) {
+            (HtlcRuleAction::Deny, _) => Some(format!("HTLC denied by rule {}", rule.name)),
+            (HtlcRuleAction::Allow, None) => None,
+            (HtlcRuleAction::Allow, Some(max_per_minute)) => {
+                let window = accepted.entry(rule.name.clone()).or_default();
+                while window.front().is_some_and(|accepted_at| {
+                    now.duration_since(*accepted_at).unwrap_or_default() >= RATE_WINDOW
+                }) {
+                    window.pop_front();
+                }
+
+                if window.len() < max_per_minute as usize {
+                    window.push_back(now);
+                    None
+                } else {
+                    Some(format!(
+                        "HTLC exceeds the rate of {max_per_minute} per minute of rule {}",
+                        rule.name
+                    ))
+                }
+            }
+        };
+
+        match denial {
+            Some(reason) if config.dry_run => {
+                info!("Dry run, accepting HTLC to federation {federation_id}: {reason}");
+                None
+            }
+            denial => denial,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fedimint_core::config::FederationId;
+    use fedimint_core::Amount;
+
+    use super::HtlcFilter;
+    use crate::rpc::{HtlcFilterConfig, HtlcRule, HtlcRuleAction};
+
+    fn rule(name: &str, action: HtlcRuleAction) -> HtlcRule {
+        HtlcRule {
+            name: name.to_string(),
+            action,
+            short_channel_id: None,
+            federation_id: None,
+            min_amount: None,
+            max_amount: None,
+            max_per_minute: None,
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let federation_id = FederationId::dummy();
+        let filter = HtlcFilter::new(HtlcFilterConfig {
+            rules: vec![
+                HtlcRule {
+                    short_channel_id: Some(7),
+                    ..rule("trusted channel", HtlcRuleAction::Allow)
+                },
+                HtlcRule {
+                    min_amount: Some(Amount::from_sats(1_000)),
+                    max_amount: Some(Amount::from_sats(2_000)),
+                    ..rule("mid range", HtlcRuleAction::Deny)
+                },
+            ],
+            dry_run: false,
+        });
+
+        assert!(filter
+            .check(Some(1), federation_id, Amount::from_sats(999))
+            .is_none());
+        assert!(filter
+            .check(Some(1), federation_id, Amount::from_sats(2_000))
+            .is_some());
+        assert!(filter
+            .check(Some(7), federation_id, Amount::from_sats(1_500))
+            .is_none());
+    }
+
+    #[test]
+    fn allow_rules_are_rate_limited() {
+        let federation_id = FederationId::dummy();
+        let filter = HtlcFilter::new(HtlcFilterConfig {
+            rules: vec![HtlcRule {
+                max_per_minute: Some(2),
+                ..rule("limited", HtlcRuleAction::Allow)
+            }],
+            dry_run: false,
+        });
+
+        let start = fedimint_core::time::now();
+        let check = |now| filter.check_at(None, federation_id, Amount::from_sats(1), now);
+        assert!(check(start).is_none());
+        assert!(check(start + Duration::from_secs(30)).is_none());
+        assert!(check(start + Duration::from_secs(59)).is_some());
+        assert!(check(start + Duration::from_secs(60)).is_none());
+    }
+
+    #[test]
+    fn dry_run_only_logs_denials() {
+        let filter = HtlcFilter::new(HtlcFilterConfig {
+            rules: vec![rule("everything", HtlcRuleAction::Deny)],
+            dry_run: true,
+        });
+
+        assert!(filter
+            .check(None, FederationId::dummy(), Amount::from_sats(1))
+            .is_none());
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let valid = HtlcRule {
+            max_per_minute: Some(10),
+            ..rule("limited", HtlcRuleAction::Allow)
+        };
+        let config = |rules| HtlcFilterConfig {
+            rules,
+            dry_run: false,
+        };
+
+        assert!(config(vec![valid.clone()]).validate().is_ok());
+        assert!(config(vec![valid.clone(), valid.clone()])
+            .validate()
+            .is_err());
+        assert!(config(vec![HtlcRule {
+            max_per_minute: Some(10),
+            ..rule("limited", HtlcRuleAction::Deny)
+        }])
+        .validate()
+        .is_err());
+        assert!(config(vec![HtlcRule {
+            min_amount: Some(Amount::from_sats(2)),
+            max_amount: Some(Amount::from_sats(1)),
+            ..rule("empty range", HtlcRuleAction::Deny)
+        }])
+        .validate()
+        .is_err());
+    }
+}
@@ -1,8 +1,12 @@
 pub mod client;
+mod config_archive;
 mod db;
+mod encrypted_db;
 pub mod envs;
 pub mod gateway_module_v2;
+mod htlc_filter;
 pub mod lightning;
+pub mod liquidity;
 pub mod rpc;
 pub mod state_machine;
 mod types;
@@ -19,26 +23,29 @@ use std::net::SocketAddr;
 use std::ops::ControlFlow;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, bail};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
+use bitcoin::address::NetworkUnchecked;
 use bitcoin::{Address, Network, Txid};
 use bitcoin_hashes::sha256;
 use clap::Parser;
 use client::GatewayClientBuilder;
+use config_archive::{FederationArchive, GatewayConfigArchive};
 use db::{
     DbKeyPrefix, FederationIdKey, GatewayConfiguration, GatewayConfigurationKey, GatewayPublicKey,
     GATEWAYD_DATABASE_VERSION,
 };
 use fedimint_api_client::api::FederationError;
 use fedimint_client::module::init::ClientModuleInitRegistry;
-use fedimint_client::ClientHandleArc;
+use fedimint_client::{Client, ClientHandleArc};
 use fedimint_core::config::FederationId;
 use fedimint_core::core::{
-    ModuleInstanceId, ModuleKind, LEGACY_HARDCODED_INSTANCE_ID_MINT,
+    ModuleInstanceId, ModuleKind, OperationId, LEGACY_HARDCODED_INSTANCE_ID_MINT,
     LEGACY_HARDCODED_INSTANCE_ID_WALLET,
 };
 use fedimint_core::db::{
@@ -57,55 +64,74 @@ use fedimint_core::{
     fedimint_build_code_version_env, push_db_pair_items, Amount, BitcoinAmountOrAll, BitcoinHash,
 };
 use fedimint_ln_client::pay::PayInvoicePayload;
-use fedimint_ln_common::config::{GatewayFee, LightningClientConfig};
+use fedimint_ln_common::config::{FeeToAmount, GatewayFee, LightningClientConfig};
 use fedimint_ln_common::contracts::Preimage;
 use fedimint_ln_common::route_hints::RouteHint;
 use fedimint_ln_common::LightningCommonInit;
 use fedimint_lnv2_client::{
-    Bolt11InvoiceDescription, CreateInvoicePayload, PaymentFee, PaymentInfo, SendPaymentPayload,
+    create_incoming_contract, Bolt11InvoiceDescription, CreateInvoicePayload, PaymentFee,
+    PaymentInfo, SendPaymentPayload,
 };
+use fedimint_lnv2_common::contracts::IncomingContract;
 use fedimint_mint_client::{MintClientInit, MintCommonInit};
 use fedimint_wallet_client::{
-    WalletClientInit, WalletClientModule, WalletCommonInit, WithdrawState,
+    PegOutFees, WalletClientInit, WalletClientModule, WalletCommonInit, WithdrawState,
 };
 use futures::stream::StreamExt;
 use gateway_lnrpc::intercept_htlc_response::Action;
 use gateway_lnrpc::{
-    CloseChannelsWithPeerResponse, GetNodeInfoResponse, GetRouteHintsResponse,
-    InterceptHtlcResponse,
+    CloseChannelsWithPeerResponse, GetNodeInfoResponse, GetOnchainBalanceResponse,
+    GetRouteHintsResponse, InterceptHtlcRequest, InterceptHtlcResponse, SendOnchainRequest,
 };
 use hex::ToHex;
+use htlc_filter::HtlcFilter;
 use lightning::{ILnRpcClient, LightningBuilder, LightningMode, LightningRpcError};
 use lightning_invoice::{Bolt11Invoice, RoutingFees};
+use liquidity::{FederationLiquidity, LiquidityThresholds, LIQUIDITY_CHECK_INTERVAL};
 use rand::rngs::OsRng;
 use rand::Rng;
 use rpc::{
-    CloseChannelsWithPeerPayload, ConnectToPeerPayload, FederationInfo, GatewayFedConfig,
-    GatewayInfo, LeaveFedPayload, OpenChannelPayload, SetConfigurationPayload, V1_API_ENDPOINT,
+    ApiKeyInfo, ApiScope, CloseChannelsWithPeerPayload, ConnectToPeerPayload, CreateApiKeyPayload,
+    CreateOfferPayload, DrainPayload, DrainStatus, EncryptedConfigArchive, ExportConfigPayload,
+    FederationConnectionState, FederationInfo, FederationLimits, GatewayFedConfig, GatewayInfo,
+    GatewayStatus, GetOfferPayload, HtlcFilterConfig, ImportConfigPayload, LeaveFedPayload,
+    ListSweepsPayload, LnurlAccount, LnurlInvoiceResponse, LnurlPayResponse, OfferInfo,
+    OpenChannelPayload, PayInvoiceForOperatorPayload, RebalancePayload, RevokeApiKeyPayload,
+    SendOnchainPayload, SetConfigurationPayload, SetFederationLimitsPayload, SetHtlcFilterPayload,
+    SetLnurlAccountPayload, SetSweepPolicyPayload, ShutdownPayload, SweepPayload, SweepPolicy,
+    SweepRecord, V1_API_ENDPOINT,
 };
 use state_machine::pay::OutgoingPaymentError;
-use state_machine::GatewayClientModule;
+use state_machine::{GatewayClientModule, Htlc};
 use strum::IntoEnumIterator;
 use thiserror::Error;
 use tokio::sync::{Mutex, MutexGuard, RwLock};
 use tracing::{debug, error, info, info_span, warn, Instrument};
 
 use crate::db::{
-    get_gatewayd_database_migrations, CreateInvoicePayloadKey, FederationConfig,
-    FederationIdKeyPrefix,
+    get_gatewayd_database_migrations, ApiKeyKey, ApiKeyKeyPrefix, ApiKeyRecord,
+    CreateInvoicePayloadKey, DailyOutflowKey, DailyOutflowKeyPrefix, FederationConfig,
+    FederationIdKeyPrefix, FederationLimitsKey, FederationLimitsKeyPrefix, HtlcFilterKey,
+    InterceptedHtlc, InterceptedHtlcKey, InterceptedHtlcKeyPrefix, InterceptedHtlcStage,
+    LnurlAccountKey, LnurlAccountKeyPrefix, LnurlContractKey, LnurlContractKeyPrefix,
+    LnurlContractUsernamePrefix, OfferKey, OfferKeyPrefix, OfferRecord, PaymentKey,
+    PaymentKeyPrefix, SweepKey, SweepKeyPrefix, SweepPolicyKey, SweepPolicyKeyPrefix,
 };
 use crate::gateway_lnrpc::create_invoice_request::Description;
-use crate::gateway_lnrpc::intercept_htlc_response::Forward;
-use crate::gateway_lnrpc::CreateInvoiceRequest;
+use crate::gateway_lnrpc::intercept_htlc_response::{Cancel, Forward};
+use crate::gateway_lnrpc::{
+    CreateInvoiceRequest, CreateOfferRequest, OfferResponse, PayInvoiceRequest,
+};
 use crate::gateway_module_v2::GatewayClientModuleV2;
 use crate::lightning::cln::RouteHtlcStream;
 use crate::lightning::GatewayLightningBuilder;
 use crate::rpc::rpc_server::{hash_password, run_webserver};
 use crate::rpc::{
-    BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, RestorePayload,
-    WithdrawPayload,
+    validate_lnurl_username, BackupPayload, BalancePayload, ConnectFedPayload,
+    DepositAddressPayload, ListPaymentsPayload, PaymentAttempt, PaymentDirection, PaymentRecord,
+    PaymentStatus, RestorePayload, WithdrawPayload,
 };
-use crate::state_machine::GatewayExtPayStates;
+use crate::state_machine::{GatewayExtPayStates, GatewayExtReceiveStates};
 
 /// This initial SCID is considered invalid by LND HTLC interceptor,
 /// So we should always increment the value before assigning a new SCID.
@@ -114,6 +140,17 @@ const INITIAL_SCID: u64 = 0;
 /// How long a gateway announcement stays valid
 const GW_ANNOUNCEMENT_TTL: Duration = Duration::from_secs(600);
 
+/// Share of `GW_ANNOUNCEMENT_TTL` after which the gateway refreshes its
+/// registration with the federations
+const GW_REGISTRATION_REFRESH_FRACTION: f32 = 0.85;
+
+/// How long the gateway waits before retrying a failed registration
+const GW_REGISTRATION_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Maximum share of a registration delay it is randomly shifted by, so
+/// gateways don't all contact the federations at the same time
+const GW_REGISTRATION_JITTER_FRACTION: f32 = 0.1;
+
 /// The default number of route hints that the legacy gateway provides for
 /// invoice creation.
 const DEFAULT_NUM_ROUTE_HINTS: u32 = 1;
@@ -134,6 +171,27 @@ pub const DEFAULT_FEES: RoutingFees = RoutingFees {
 /// LNv2 CLTV Delta in blocks
 const EXPIRATION_DELTA_MINIMUM_V2: u64 = 144;
 
+/// How long a drain waits for in-flight payments to resolve unless the request
+/// specifies a timeout
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How often a drain checks whether the in-flight payments have resolved
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the gateway checks the e-cash balances against the sweep policies
+/// of the federations
+const SWEEP_INTERVAL: Duration = Duration::from_secs(600);
+
+/// How long invoices issued for lightning addresses can be paid
+const LNURL_INVOICE_EXPIRY_SECS: u32 = 3600;
+
+/// How long the incoming contract of an invoice issued for a lightning address
+/// is kept after the invoice expired, giving its user time to claim it
+const LNURL_CONTRACT_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Separates the id an API key is stored under from its secret part
+const API_KEY_ID_SEPARATOR: char = '_';
+
 pub type Result<T> = std::result::Result<T, GatewayError>;
 
 /// Name of the gateway's database that is used for metadata and configuration
@@ -146,6 +204,12 @@ const DEFAULT_MODULE_KINDS: [(ModuleInstanceId, &ModuleKind); 2] = [
     (LEGACY_HARDCODED_INSTANCE_ID_WALLET, &WalletCommonInit::KIND),
 ];
 
+/// Day since the unix epoch that an outflow at `secs` since the epoch counts
+/// towards in the daily outflow limit
+fn outflow_day(secs: u64) -> u64 {
+    secs / (24 * 60 * 60)
+}
+
 /// Command line parameters for starting the gateway. `mode`, `data_dir`,
 /// `listen`, and `api_addr` are all required.
 #[derive(Parser)]
@@ -186,6 +250,68 @@ struct GatewayOpts {
         default_value_t = DEFAULT_NUM_ROUTE_HINTS
     )]
     pub num_route_hints: u32,
+
+    /// Lightning backends outgoing payments fail over to when the primary one
+    /// is unhealthy, in order of priority. Each is a JSON encoded
+    /// `LightningMode`, e.g. `{"Lnd":{"lnd_rpc_addr":...}}`, separated by `;`
+    #[arg(
+        long = "fallback-lightning",
+        env = envs::FM_GATEWAY_FALLBACK_LIGHTNING_ENV,
+        value_delimiter = ';',
+        value_parser = parse_lightning_mode
+    )]
+    pub fallback_lightning: Vec<LightningMode>,
+
+    /// Warn when the liquidity for receiving lightning payments into a
+    /// federation falls below this amount
+    #[arg(long = "min-inbound-liquidity", env = envs::FM_GATEWAY_MIN_INBOUND_LIQUIDITY_ENV)]
+    pub min_inbound_liquidity: Option<Amount>,
+
+    /// Warn when the liquidity for paying lightning invoices out of a
+    /// federation falls below this amount
+    #[arg(long = "min-outbound-liquidity", env = envs::FM_GATEWAY_MIN_OUTBOUND_LIQUIDITY_ENV)]
+    pub min_outbound_liquidity: Option<Amount>,
+
+    /// Default limit on a single outgoing payment or withdrawal of a
+    /// federation
+    #[arg(long = "max-payment", env = envs::FM_GATEWAY_MAX_PAYMENT_ENV)]
+    pub max_payment: Option<Amount>,
+
+    /// Default limit on the sum of outgoing payments and withdrawals of a
+    /// federation per UTC day
+    #[arg(long = "max-daily-outflow", env = envs::FM_GATEWAY_MAX_DAILY_OUTFLOW_ENV)]
+    pub max_daily_outflow: Option<Amount>,
+
+    /// Default limit on the e-cash balance the gateway accumulates in a
+    /// federation by paying invoices
+    #[arg(long = "max-ecash-balance", env = envs::FM_GATEWAY_MAX_ECASH_BALANCE_ENV)]
+    pub max_ecash_balance: Option<Amount>,
+
+    /// Serve the lightning addresses registered with the gateway under
+    /// `/.well-known/lnurlp/<username>`
+    #[arg(long = "enable-lnurl", env = envs::FM_GATEWAY_ENABLE_LNURL_ENV)]
+    pub enable_lnurl: bool,
+
+    /// Encrypt the client databases of newly connected federations with a
+    /// key derived from `--password`, which can then no longer be changed
+    #[arg(
+        long = "encrypt-client-dbs",
+        env = envs::FM_GATEWAY_ENCRYPT_CLIENT_DBS_ENV,
+        requires = "password"
+    )]
+    pub encrypt_client_dbs: bool,
+
+    /// Postgres instance to store the client databases of the federations
+    /// in, instead of the data dir
+    #[arg(
+        long = "client-db-postgres-uri",
+        env = envs::FM_GATEWAY_CLIENT_DB_POSTGRES_URI_ENV
+    )]
+    pub client_db_postgres_uri: Option<String>,
+}
+
+fn parse_lightning_mode(s: &str) -> anyhow::Result<LightningMode> {
+    Ok(serde_json::from_str(s)?)
 }
 
 impl GatewayOpts {
@@ -205,6 +331,16 @@ impl GatewayOpts {
             network: self.network,
             num_route_hints: self.num_route_hints,
             fees: self.fees.clone(),
+            liquidity_thresholds: LiquidityThresholds {
+                min_inbound: self.min_inbound_liquidity,
+                min_outbound: self.min_outbound_liquidity,
+            },
+            default_limits: FederationLimits {
+                max_payment: self.max_payment,
+                max_daily_outflow: self.max_daily_outflow,
+                max_ecash_balance: self.max_ecash_balance,
+            },
+            lnurl_enabled: self.enable_lnurl,
         })
     }
 }
@@ -223,6 +359,9 @@ pub struct GatewayParameters {
     network: Option<Network>,
     num_route_hints: u32,
     fees: Option<GatewayFee>,
+    liquidity_thresholds: LiquidityThresholds,
+    default_limits: FederationLimits,
+    lnurl_enabled: bool,
 }
 
 #[cfg_attr(doc, aquamarine::aquamarine)]
@@ -321,6 +460,56 @@ pub struct Gateway {
 
     // The socket the gateway listens on.
     listen: SocketAddr,
+
+    // Liquidity thresholds below which the gateway warns the operator.
+    liquidity_thresholds: LiquidityThresholds,
+
+    // Outcome of the latest registrations with each connected federation.
+    registrations: Arc<RwLock<BTreeMap<FederationId, FederationRegistration>>>,
+
+    // Limits of federations that have not been given their own limits.
+    default_limits: FederationLimits,
+
+    // Set once the gateway is draining, after which it rejects new payments until
+    // it restarts.
+    draining: Arc<AtomicBool>,
+
+    // Sweeps are serialized so that concurrent sweeps do not peg out the same
+    // e-cash twice.
+    sweep_lock: Arc<Mutex<()>>,
+
+    // Operator rules for the HTLCs intercepted for the connected federations.
+    htlc_filter: Arc<HtlcFilter>,
+
+    // Whether the webserver serves the lightning addresses registered with the
+    // gateway.
+    pub lnurl_enabled: bool,
+}
+
+/// Tracks whether the gateway is currently announced to a federation
+#[derive(Debug, Clone, Default)]
+struct FederationRegistration {
+    last_success: Option<SystemTime>,
+    consecutive_failures: u32,
+}
+
+impl FederationRegistration {
+    /// Returns true if the last successful announcement has expired, which
+    /// removes the gateway from the federation's list of gateways
+    fn is_expired(&self) -> bool {
+        self.last_success.map_or(true, |last_success| {
+            now()
+                .duration_since(last_success)
+                .map_or(false, |elapsed| elapsed >= GW_ANNOUNCEMENT_TTL)
+        })
+    }
+}
+
+/// Randomly shifts `delay` by up to `GW_REGISTRATION_JITTER_FRACTION`
+fn jittered(delay: Duration) -> Duration {
+    delay.mul_f32(
+        1.0 + OsRng.gen_range(-GW_REGISTRATION_JITTER_FRACTION..=GW_REGISTRATION_JITTER_FRACTION),
+    )
 }
 
 impl std::fmt::Debug for Gateway {
@@ -365,6 +554,9 @@ impl Gateway {
                 num_route_hints,
                 fees: Some(GatewayFee(fees)),
                 network,
+                liquidity_thresholds: LiquidityThresholds::default(),
+                default_limits: FederationLimits::default(),
+                lnurl_enabled: false,
             },
             gateway_db,
             client_builder,
@@ -390,11 +582,20 @@ impl Gateway {
             decoders.clone(),
         );
 
-        let client_builder = GatewayClientBuilder::new(
+        let mut client_builder = GatewayClientBuilder::new(
             opts.data_dir.clone(),
             registry.clone(),
             LEGACY_HARDCODED_INSTANCE_ID_MINT,
         );
+        if let Some(uri) = &opts.client_db_postgres_uri {
+            client_builder = client_builder.with_postgres(uri.clone());
+        }
+        if opts.encrypt_client_dbs {
+            let Some(password) = &opts.password else {
+                bail!("Encrypting the client databases requires a password");
+            };
+            client_builder = client_builder.with_encryption(password)?;
+        }
 
         info!(
             "Starting gatewayd (version: {})",
@@ -404,6 +605,8 @@ impl Gateway {
         Gateway::new(
             Arc::new(GatewayLightningBuilder {
                 lightning_mode: opts.mode.clone(),
+                fallback_modes: opts.fallback_lightning.clone(),
+                data_dir: opts.data_dir.clone(),
             }),
             opts.to_gateway_parameters()?,
             gateway_db,
@@ -435,6 +638,13 @@ impl Gateway {
         let gateway_config =
             Self::get_gateway_configuration(gateway_db.clone(), &gateway_parameters).await;
 
+        let htlc_filter = gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&HtlcFilterKey)
+            .await
+            .unwrap_or_default();
+
         Ok(Self {
             lightning_builder,
             max_used_scid: Arc::new(Mutex::new(INITIAL_SCID)),
@@ -448,6 +658,13 @@ impl Gateway {
             client_joining_lock: Arc::new(Mutex::new(ClientsJoinLock)),
             versioned_api: gateway_parameters.versioned_api,
             listen: gateway_parameters.listen,
+            liquidity_thresholds: gateway_parameters.liquidity_thresholds,
+            registrations: Arc::new(RwLock::new(BTreeMap::new())),
+            default_limits: gateway_parameters.default_limits,
+            draining: Arc::new(AtomicBool::new(false)),
+            sweep_lock: Arc::new(Mutex::new(())),
+            htlc_filter: Arc::new(HtlcFilter::new(htlc_filter)),
+            lnurl_enabled: gateway_parameters.lnurl_enabled,
         })
     }
 
@@ -504,6 +721,111 @@ impl Gateway {
                             .insert("Gateway Public Key".to_string(), Box::new(public_key));
                     }
                 }
+                DbKeyPrefix::Payment => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PaymentKeyPrefix,
+                        PaymentKey,
+                        PaymentRecord,
+                        gateway_items,
+                        "Payments"
+                    );
+                }
+                DbKeyPrefix::FederationLimits => {
+                    push_db_pair_items!(
+                        dbtx,
+                        FederationLimitsKeyPrefix,
+                        FederationLimitsKey,
+                        FederationLimits,
+                        gateway_items,
+                        "Federation Limits"
+                    );
+                }
+                DbKeyPrefix::ApiKey => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ApiKeyKeyPrefix,
+                        ApiKeyKey,
+                        ApiKeyRecord,
+                        gateway_items,
+                        "API Keys"
+                    );
+                }
+                DbKeyPrefix::DailyOutflow => {
+                    push_db_pair_items!(
+                        dbtx,
+                        DailyOutflowKeyPrefix,
+                        DailyOutflowKey,
+                        Amount,
+                        gateway_items,
+                        "Daily Outflows"
+                    );
+                }
+                DbKeyPrefix::InterceptedHtlc => {
+                    push_db_pair_items!(
+                        dbtx,
+                        InterceptedHtlcKeyPrefix,
+                        InterceptedHtlcKey,
+                        InterceptedHtlc,
+                        gateway_items,
+                        "Intercepted HTLCs"
+                    );
+                }
+                DbKeyPrefix::SweepPolicy => {
+                    push_db_pair_items!(
+                        dbtx,
+                        SweepPolicyKeyPrefix,
+                        SweepPolicyKey,
+                        SweepPolicy,
+                        gateway_items,
+                        "Sweep Policies"
+                    );
+                }
+                DbKeyPrefix::Sweep => {
+                    push_db_pair_items!(
+                        dbtx,
+                        SweepKeyPrefix,
+                        SweepKey,
+                        SweepRecord,
+                        gateway_items,
+                        "Sweeps"
+                    );
+                }
+                DbKeyPrefix::Offer => {
+                    push_db_pair_items!(
+                        dbtx,
+                        OfferKeyPrefix,
+                        OfferKey,
+                        OfferRecord,
+                        gateway_items,
+                        "Offers"
+                    );
+                }
+                DbKeyPrefix::LnurlAccount => {
+                    push_db_pair_items!(
+                        dbtx,
+                        LnurlAccountKeyPrefix,
+                        LnurlAccountKey,
+                        LnurlAccount,
+                        gateway_items,
+                        "LNURL Accounts"
+                    );
+                }
+                DbKeyPrefix::LnurlContract => {
+                    push_db_pair_items!(
+                        dbtx,
+                        LnurlContractKeyPrefix,
+                        LnurlContractKey,
+                        IncomingContract,
+                        gateway_items,
+                        "LNURL Contracts"
+                    );
+                }
+                DbKeyPrefix::HtlcFilter => {
+                    if let Some(htlc_filter) = dbtx.get_value(&HtlcFilterKey).await {
+                        gateway_items.insert("HTLC Filter".to_string(), Box::new(htlc_filter));
+                    }
+                }
                 _ => {}
             }
         }
@@ -517,6 +839,8 @@ impl Gateway {
     /// service requests.
     pub async fn run(mut self, tg: &mut TaskGroup) -> anyhow::Result<TaskShutdownToken> {
         self.register_clients_timer(tg).await;
+        self.liquidity_check_timer(tg);
+        self.sweep_timer(tg);
         self.load_clients().await;
         self.start_gateway(tg).await?;
         // start webserver last to avoid handling requests before fully initialized
@@ -532,6 +856,7 @@ impl Gateway {
         let mut self_copy = self.clone();
         let tg = task_group.clone();
         task_group.spawn("Subscribe to intercepted HTLCs in stream", move |handle| async move {
+            let mut htlcs_reconciled = false;
             loop {
                 if handle.is_shutting_down() {
                     info!("Gateway HTLC handler loop is shutting down");
@@ -580,6 +905,13 @@ impl Gateway {
                                 }
 
                                 info!("Successfully loaded Gateway clients.");
+                                // HTLCs intercepted before the gateway restarted need to be resolved
+                                // before new ones are handled, which might be replays of them
+                                if !htlcs_reconciled {
+                                    self_copy.reconcile_intercepted_htlcs(ln_client.clone(), &tg).await;
+                                    htlcs_reconciled = true;
+                                }
+
                                 let lightning_context = LightningContext {
                                     lnrpc: ln_client,
                                     lightning_public_key,
@@ -591,7 +923,7 @@ impl Gateway {
                                 }).await;
 
                                 // Blocks until the connection to the lightning node breaks or we receive the shutdown signal
-                                match handle.cancel_on_shutdown(self_copy.handle_htlc_stream(stream, handle.clone())).await {
+                                match handle.cancel_on_shutdown(self_copy.handle_htlc_stream(stream, handle.clone(), &tg)).await {
                                     Ok(_) => {
                                         warn!("HTLC Stream Lightning connection broken. Gateway is disconnected");
                                     },
@@ -635,7 +967,12 @@ impl Gateway {
     /// Spawns a state machine to either forward, cancel, or complete the
     /// HTLC depending on if the gateway is able to acquire the preimage from
     /// the federation.
-    pub async fn handle_htlc_stream(&self, mut stream: RouteHtlcStream<'_>, handle: TaskHandle) {
+    pub async fn handle_htlc_stream(
+        &self,
+        mut stream: RouteHtlcStream<'_>,
+        handle: TaskHandle,
+        task_group: &TaskGroup,
+    ) {
         let GatewayState::Running { lightning_context } = self.state.read().await.clone() else {
             panic!("Gateway isn't in a running state")
         };
@@ -650,6 +987,51 @@ impl Gateway {
                         break;
                     }
 
+                    // The lightning node replays HTLCs it still holds when the stream is
+                    // re-established, those already handed to a client are completed by it
+                    if self.is_funding_intercepted_htlc(&htlc_request).await {
+                        info!(
+                            "HTLC {} is already being funded, skipping",
+                            PrettyInterceptHtlcRequest(&htlc_request)
+                        );
+                        continue;
+                    }
+
+                    // A draining gateway is about to shut down, so it does not take on
+                    // new payments to its federations that it might not be able to finish
+                    let federation_id = self.federation_of_htlc(&htlc_request).await;
+                    if self.is_draining() && federation_id.is_some() {
+                        Self::cancel_htlc(
+                            &lightning_context,
+                            &htlc_request,
+                            "Gateway is shutting down",
+                        )
+                        .await;
+                        continue;
+                    }
+
+                    // The operator's rules are only applied to payments into the federations,
+                    // forwarded HTLCs are left to the lightning node
+                    if let Some(federation_id) = federation_id {
+                        if let Some(reason) = self.htlc_filter.check(
+                            htlc_request.short_channel_id,
+                            federation_id,
+                            Amount::from_msats(htlc_request.outgoing_amount_msat),
+                        ) {
+                            info!(
+                                "Rejecting HTLC {}: {reason}",
+                                PrettyInterceptHtlcRequest(&htlc_request)
+                            );
+                            Self::cancel_htlc(
+                                &lightning_context,
+                                &htlc_request,
+                                "HTLC rejected by gateway",
+                            )
+                            .await;
+                            continue;
+                        }
+                    }
+
                     // If `payment_hash` has been registered as a LNv2 payment, we try to complete
                     // the payment by getting the preimage from the federation
                     // using the LNv2 protocol. If the `payment_hash` is not registered,
@@ -666,7 +1048,11 @@ impl Gateway {
                         )
                         .await
                     {
-                        if let Err(error) = client
+                        let htlc_key = self
+                            .record_intercepted_htlc(&htlc_request, payload.federation_id, true)
+                            .await;
+
+                        match client
                             .get_first_module::<GatewayClientModuleV2>()
                             .relay_incoming_htlc(
                                 htlc_request.incoming_chan_id,
@@ -675,7 +1061,18 @@ impl Gateway {
                             )
                             .await
                         {
-                            error!("Error relaying incoming HTLC: {error:?}");
+                            Ok(operation_id) => {
+                                self.track_intercepted_htlc_v2(
+                                    client,
+                                    htlc_key,
+                                    operation_id,
+                                    task_group,
+                                )
+                                .await;
+                            }
+                            Err(error) => {
+                                error!("Error relaying incoming HTLC: {error:?}");
+                            }
                         }
 
                         continue;
@@ -695,18 +1092,24 @@ impl Gateway {
                             // Just forward the HTLC if we do not have a client that
                             // corresponds to the federation id
                             if let Some(client) = client {
+                                let htlc_key = self
+                                    .record_intercepted_htlc(&htlc_request, *federation_id, false)
+                                    .await;
                                 let cf = client
                                     .borrow()
                                     .with(|client| async {
-                                        let htlc = htlc_request.clone().try_into();
+                                        let htlc: anyhow::Result<Htlc> = htlc_request.clone().try_into();
                                         if let Ok(htlc) = htlc {
                                             match client
                                                 .get_first_module::<GatewayClientModule>()
-                                                .gateway_handle_intercepted_htlc(htlc)
+                                                .gateway_handle_intercepted_htlc(htlc.clone())
                                                 .await
                                             {
-                                                Ok(_) => {
-                                                    return Some(ControlFlow::<(), ()>::Continue(()))
+                                                Ok(operation_id) => {
+                                                    return Some(ControlFlow::<(), _>::Continue((
+                                                        operation_id,
+                                                        htlc,
+                                                    )))
                                                 }
                                                 Err(e) => {
                                                     info!(
@@ -720,9 +1123,20 @@ impl Gateway {
                                         None
                                     })
                                     .await;
-                                if let Some(ControlFlow::Continue(())) = cf {
+                                if let Some(ControlFlow::Continue((operation_id, htlc))) = cf {
+                                    self.record_incoming_payment(
+                                        client.value().clone(),
+                                        *federation_id,
+                                        htlc,
+                                        operation_id,
+                                        task_group,
+                                    )
+                                    .await;
                                     continue;
                                 }
+
+                                // The HTLC is forwarded instead
+                                self.remove_intercepted_htlc(&htlc_key).await;
                             } else {
                                 info!("Got no client result")
                             }
@@ -781,6 +1195,11 @@ impl Gateway {
                         .await,
                 );
             }
+            let connections = federations
+                .iter()
+                .map(|federation| federation.connection.clone())
+                .collect::<Vec<_>>();
+            let lightning_backends = lightning_context.lnrpc.backend_health();
 
             return Ok(GatewayInfo {
                 federations,
@@ -791,10 +1210,14 @@ impl Gateway {
                 fees: Some(gateway_config.routing_fees),
                 route_hints,
                 gateway_id: self.gateway_id,
-                gateway_state: self.state.read().await.to_string(),
+                gateway_state: self.status(|| {
+                    GatewayStatus::from_running(node_info.4, &lightning_backends, &connections)
+                }),
                 network: Some(gateway_config.network),
                 block_height: Some(node_info.3),
                 synced_to_chain: node_info.4,
+                lightning_backends,
+                supports_offers: lightning_context.lnrpc.supports_offers(),
             });
         }
 
@@ -807,13 +1230,47 @@ impl Gateway {
             fees: None,
             route_hints: vec![],
             gateway_id: self.gateway_id,
-            gateway_state: self.state.read().await.to_string(),
+            gateway_state: match *self.state.read().await {
+                GatewayState::Configuring => self.status(|| GatewayStatus::Configuring),
+                GatewayState::Disconnected => self.status(|| GatewayStatus::Degraded {
+                    reason: "Disconnected from the lightning node".to_string(),
+                }),
+                _ => self.status(|| GatewayStatus::Initializing),
+            },
             network: None,
             block_height: None,
             synced_to_chain: false,
+            lightning_backends: vec![],
+            supports_offers: false,
         })
     }
 
+    /// Returns `Draining` if the gateway is draining, otherwise the status
+    /// derived by `status`
+    fn status(&self, status: impl FnOnce() -> GatewayStatus) -> GatewayStatus {
+        if self.is_draining() {
+            return GatewayStatus::Draining;
+        }
+        status()
+    }
+
+    /// Returns whether the gateway is announced to `federation_id`
+    async fn federation_connection_state(
+        &self,
+        federation_id: FederationId,
+    ) -> FederationConnectionState {
+        match self.registrations.read().await.get(&federation_id) {
+            None => FederationConnectionState::Registering,
+            Some(registration) if registration.consecutive_failures == 0 => {
+                FederationConnectionState::Registered
+            }
+            Some(registration) => FederationConnectionState::Failing {
+                consecutive_failures: registration.consecutive_failures,
+                announced: !registration.is_expired(),
+            },
+        }
+    }
+
     /// If the Gateway is connected to the Lightning node, returns the
     /// `ClientConfig` for each federation that the Gateway is connected to.
     pub async fn handle_get_federation_config(
@@ -904,12 +1361,53 @@ impl Gateway {
             ),
         };
 
+        // Withdrawals only lower the e-cash balance, so they can never exceed
+        // the maximum e-cash balance and skip that check
+        let day = outflow_day(duration_since_epoch().as_secs());
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        self.charge_outflow(
+            &mut dbtx.to_ref_nc(),
+            federation_id,
+            day,
+            amount.into(),
+            None,
+        )
+        .await?;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        match Self::peg_out(&wallet_module, address, amount, fees).await {
+            Ok(txid) => Ok(txid),
+            // Only give back the outflow if the federation can not broadcast the
+            // withdrawal anymore
+            Err(PegOutError::NotSubmitted(e)) => {
+                let mut dbtx = self.gateway_db.begin_transaction().await;
+                Self::release_outflow(&mut dbtx.to_ref_nc(), federation_id, day, amount.into())
+                    .await;
+                dbtx.commit_tx().await;
+                Err(e)
+            }
+            Err(PegOutError::Unknown(e)) => Err(e),
+        }
+    }
+
+    /// Pegs out `amount` to `address` and waits for the federation to
+    /// broadcast the transaction
+    async fn peg_out(
+        wallet_module: &WalletClientModule,
+        address: Address<NetworkUnchecked>,
+        amount: bitcoin::Amount,
+        fees: PegOutFees,
+    ) -> std::result::Result<Txid, PegOutError> {
         let operation_id = wallet_module
             .withdraw(address.clone(), amount, fees, ())
-            .await?;
+            .await
+            .map_err(|e| PegOutError::NotSubmitted(e.into()))?;
         let mut updates = wallet_module
             .subscribe_withdraw_updates(operation_id)
-            .await?
+            .await
+            .map_err(|e| PegOutError::Unknown(e.into()))?
             .into_stream();
 
         while let Some(update) = updates.next().await {
@@ -921,16 +1419,17 @@ impl Gateway {
                     );
                     return Ok(txid);
                 }
+                // The federation rejected the withdrawal transaction
                 WithdrawState::Failed(e) => {
-                    return Err(GatewayError::UnexpectedState(e));
+                    return Err(PegOutError::NotSubmitted(GatewayError::UnexpectedState(e)));
                 }
                 _ => {}
             }
         }
 
-        Err(GatewayError::UnexpectedState(
+        Err(PegOutError::Unknown(GatewayError::UnexpectedState(
             "Ran out of state updates while withdrawing".to_string(),
-        ))
+        )))
     }
 
     /// Requests the gateway to pay an outgoing LN invoice on behalf of a
@@ -938,10 +1437,45 @@ impl Gateway {
     async fn handle_pay_invoice_msg(&self, payload: PayInvoicePayload) -> Result<Preimage> {
         if let GatewayState::Running { .. } = self.state.read().await.clone() {
             debug!("Handling pay invoice message: {payload:?}");
+            if self.is_draining() {
+                return Err(GatewayError::Draining);
+            }
+
             let client = self.select_client(payload.federation_id).await?;
             let contract_id = payload.contract_id;
+            let federation_id = payload.federation_id;
+            let payment_hash = payload.payment_data.payment_hash();
+            let amount = payload.payment_data.amount().unwrap_or(Amount::ZERO);
             let gateway_module = &client.value().get_first_module::<GatewayClientModule>();
-            let operation_id = gateway_module.gateway_pay_bolt11_invoice(payload).await?;
+
+            // Recorded before the payment starts so its attempts can be added
+            let fee = self
+                .gateway_db
+                .begin_transaction_nc()
+                .await
+                .get_value(&FederationIdKey { id: federation_id })
+                .await
+                .map_or(Amount::ZERO, |config| config.fees.to_amount(&amount));
+
+            // The gateway is reimbursed for the payment and its fee in e-cash
+            let ecash_balance = client.value().get_balance().await + amount + fee;
+            self.start_outgoing_payment(federation_id, payment_hash, amount, fee, ecash_balance)
+                .await?;
+
+            let operation_id = match gateway_module.gateway_pay_bolt11_invoice(payload).await {
+                Ok(operation_id) => operation_id,
+                Err(e) => {
+                    self.update_payment_status(
+                        payment_hash,
+                        PaymentDirection::Outgoing,
+                        PaymentStatus::Failed,
+                        Some(e.to_string()),
+                    )
+                    .await;
+                    return Err(e.into());
+                }
+            };
+
             let mut updates = gateway_module
                 .gateway_subscribe_ln_pay(operation_id)
                 .await?
@@ -950,6 +1484,13 @@ impl Gateway {
                 match update {
                     GatewayExtPayStates::Success { preimage, .. } => {
                         debug!("Successfully paid invoice: {contract_id}");
+                        self.update_payment_status(
+                            payment_hash,
+                            PaymentDirection::Outgoing,
+                            PaymentStatus::Succeeded,
+                            None,
+                        )
+                        .await;
                         return Ok(preimage);
                     }
                     GatewayExtPayStates::Fail {
@@ -957,10 +1498,24 @@ impl Gateway {
                         error_message,
                     } => {
                         error!("{error_message} while paying invoice: {contract_id}");
+                        self.update_payment_status(
+                            payment_hash,
+                            PaymentDirection::Outgoing,
+                            PaymentStatus::Failed,
+                            Some(error_message),
+                        )
+                        .await;
                         return Err(GatewayError::OutgoingPaymentError(Box::new(error)));
                     }
                     GatewayExtPayStates::Canceled { error } => {
                         error!("Cancelled with {error} while paying invoice: {contract_id}");
+                        self.update_payment_status(
+                            payment_hash,
+                            PaymentDirection::Outgoing,
+                            PaymentStatus::Failed,
+                            Some(error.to_string()),
+                        )
+                        .await;
                         return Err(GatewayError::OutgoingPaymentError(Box::new(error)));
                     }
                     GatewayExtPayStates::Created => {
@@ -1042,6 +1597,10 @@ impl Gateway {
                 config: client.get_config().clone(),
                 channel_id: Some(mint_channel_id),
                 routing_fees: Some(gateway_config.routing_fees.into()),
+                limits: self.federation_limits(federation_id).await,
+                sweep_policy: self.sweep_policy(federation_id).await,
+                // Connecting fails unless the registration below succeeds
+                connection: FederationConnectionState::Registered,
             };
 
             self.check_federation_network(&federation_info, gateway_config.network)
@@ -1057,6 +1616,7 @@ impl Gateway {
                     lightning_context,
                 )
                 .await?;
+            self.record_registration(federation_id, Ok(())).await;
 
             // no need to enter span earlier, because connect-fed has a span
             self.clients.write().await.insert(
@@ -1144,89 +1704,548 @@ impl Gateway {
         unimplemented!("Restore is not currently supported");
     }
 
-    /// Handle a request to change a connected federation's configuration or
-    /// gateway metadata. If `num_route_hints` is changed, the Gateway
-    /// will re-register with all connected federations. If
-    /// `per_federation_routing_fees` is changed, the Gateway will only
-    /// re-register with the specified federation.
-    pub async fn handle_set_configuration_msg(
+    /// Returns the payments of the ledger matching the filters of the
+    /// `payload`, newest first.
+    pub async fn handle_list_payments_msg(
         &self,
-        SetConfigurationPayload {
-            password,
-            network,
-            num_route_hints,
-            routing_fees,
-            per_federation_routing_fees,
-        }: SetConfigurationPayload,
-    ) -> Result<()> {
-        let gw_state = self.state.read().await.clone();
-        let lightning_network = match gw_state {
-            GatewayState::Running { lightning_context } => {
-                if network.is_some() && network != Some(lightning_context.lightning_network) {
-                    return Err(GatewayError::GatewayConfigurationError(
-                        "Cannot change network while connected to a lightning node".to_string(),
-                    ));
-                }
-                lightning_context.lightning_network
-            }
-            // In the case the gateway is not yet running and not yet connected to a lightning node,
-            // we start off with a default network configuration. This default gets replaced later
-            // when the gateway connects to a lightning node, or when a user sets a different
-            // configuration
-            _ => DEFAULT_NETWORK,
-        };
-
-        let mut dbtx = self.gateway_db.begin_transaction().await;
-
-        let prev_gateway_config = self.gateway_config.read().await.clone();
-        let new_gateway_config = if let Some(mut prev_config) = prev_gateway_config {
-            if let Some(password) = password {
-                let hashed_password = hash_password(password, prev_config.password_salt);
-                prev_config.hashed_password = hashed_password;
-            }
+        payload: ListPaymentsPayload,
+    ) -> Result<Vec<PaymentRecord>> {
+        let mut payments = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&PaymentKeyPrefix)
+            .await
+            .map(|(_, payment)| payment)
+            .filter(|payment| std::future::ready(payload.matches(payment)))
+            .collect::<Vec<_>>()
+            .await;
 
-            if let Some(network) = network {
-                if self.clients.read().await.len() > 0 {
-                    return Err(GatewayError::GatewayConfigurationError(
-                        "Cannot change network while connected to a federation".to_string(),
-                    ));
-                }
-                prev_config.network = network;
-            }
+        payments.sort_by(|a, b| b.created_at.cmp(&a.created_at));
 
-            if let Some(num_route_hints) = num_route_hints {
-                prev_config.num_route_hints = num_route_hints;
-            }
+        Ok(payments
+            .into_iter()
+            .skip(payload.offset)
+            .take(payload.limit.unwrap_or(usize::MAX))
+            .collect())
+    }
 
-            // Using this routing fee config as a default for all federation that has none
-            // routing fees specified.
-            if let Some(fees) = routing_fees.clone() {
-                let routing_fees = GatewayFee(fees.into()).0;
-                prev_config.routing_fees = routing_fees;
-            }
+    /// Exports the payments of the ledger matching the filters of the
+    /// `payload` as CSV for accounting.
+    pub async fn handle_export_payments_csv_msg(
+        &self,
+        payload: ListPaymentsPayload,
+    ) -> Result<String> {
+        let payments = self.handle_list_payments_msg(payload).await?;
+
+        let mut csv = PaymentRecord::CSV_HEADER.to_string();
+        for payment in payments {
+            csv.push('\n');
+            csv.push_str(&payment.to_csv_row());
+        }
 
-            prev_config
-        } else {
-            let password = password.ok_or(GatewayError::GatewayConfigurationError(
-                "The password field is required when initially configuring the gateway".to_string(),
-            ))?;
-            let password_salt: [u8; 16] = rand::thread_rng().gen();
-            let hashed_password = hash_password(password, password_salt);
+        Ok(csv)
+    }
 
-            GatewayConfiguration {
-                hashed_password,
-                network: lightning_network,
-                num_route_hints: DEFAULT_NUM_ROUTE_HINTS,
-                routing_fees: DEFAULT_FEES,
-                password_salt,
-            }
-        };
-        dbtx.insert_entry(&GatewayConfigurationKey, &new_gateway_config)
-            .await;
+    /// Adds a pending payment to the payments ledger.
+    async fn record_payment(
+        &self,
+        federation_id: FederationId,
+        payment_hash: sha256::Hash,
+        direction: PaymentDirection,
+        amount: Amount,
+        fee: Amount,
+    ) {
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        Self::insert_pending_payment(
+            &mut dbtx.to_ref_nc(),
+            federation_id,
+            payment_hash,
+            direction,
+            amount,
+            fee,
+            duration_since_epoch().as_secs(),
+        )
+        .await;
+        dbtx.commit_tx().await;
+    }
 
-        let mut register_federations: Vec<(FederationId, FederationConfig)> = Vec::new();
-        if let Some(per_federation_routing_fees) = per_federation_routing_fees {
-            for (federation_id, routing_fees) in per_federation_routing_fees.iter() {
+    /// Counts an outgoing payment towards the limits of its federation and
+    /// adds it to the payments ledger as pending. `ecash_balance` is the
+    /// gateway's balance once it has been paid for the payment. The outflow is
+    /// released again if the payment fails, see [`Self::update_payment_status`].
+    async fn start_outgoing_payment(
+        &self,
+        federation_id: FederationId,
+        payment_hash: sha256::Hash,
+        amount: Amount,
+        fee: Amount,
+        ecash_balance: Amount,
+    ) -> Result<()> {
+        let now = duration_since_epoch().as_secs();
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        self.charge_outflow(
+            &mut dbtx.to_ref_nc(),
+            federation_id,
+            outflow_day(now),
+            amount,
+            Some(ecash_balance),
+        )
+        .await?;
+        Self::insert_pending_payment(
+            &mut dbtx.to_ref_nc(),
+            federation_id,
+            payment_hash,
+            PaymentDirection::Outgoing,
+            amount,
+            fee,
+            now,
+        )
+        .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)
+    }
+
+    async fn insert_pending_payment(
+        dbtx: &mut DatabaseTransaction<'_>,
+        federation_id: FederationId,
+        payment_hash: sha256::Hash,
+        direction: PaymentDirection,
+        amount: Amount,
+        fee: Amount,
+        now: u64,
+    ) {
+        dbtx.insert_entry(
+            &PaymentKey {
+                payment_hash,
+                direction,
+            },
+            &PaymentRecord {
+                federation_id,
+                payment_hash,
+                direction,
+                amount,
+                fee,
+                status: PaymentStatus::Pending,
+                created_at: now,
+                updated_at: now,
+                error: None,
+                attempts: vec![],
+            },
+        )
+        .await;
+    }
+
+    /// Records the final status of a payment in the payments ledger.
+    async fn update_payment_status(
+        &self,
+        payment_hash: sha256::Hash,
+        direction: PaymentDirection,
+        status: PaymentStatus,
+        error: Option<String>,
+    ) {
+        let key = PaymentKey {
+            payment_hash,
+            direction,
+        };
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        if let Some(mut payment) = dbtx.get_value(&key).await {
+            // The outflow of an outgoing payment is charged when it starts
+            if direction == PaymentDirection::Outgoing
+                && payment.status == PaymentStatus::Pending
+                && status == PaymentStatus::Failed
+            {
+                Self::release_outflow(
+                    &mut dbtx.to_ref_nc(),
+                    payment.federation_id,
+                    outflow_day(payment.created_at),
+                    payment.amount,
+                )
+                .await;
+            }
+
+            payment.status = status;
+            payment.error = error;
+            payment.updated_at = duration_since_epoch().as_secs();
+            dbtx.insert_entry(&key, &payment).await;
+        }
+        dbtx.commit_tx().await;
+    }
+
+    /// Adds an attempt to pay an outgoing payment over lightning to the
+    /// payments ledger.
+    pub async fn record_payment_attempt(
+        &self,
+        payment_hash: sha256::Hash,
+        attempt: PaymentAttempt,
+    ) {
+        let key = PaymentKey {
+            payment_hash,
+            direction: PaymentDirection::Outgoing,
+        };
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        if let Some(mut payment) = dbtx.get_value(&key).await {
+            payment.attempts.push(attempt);
+            payment.updated_at = duration_since_epoch().as_secs();
+            dbtx.insert_entry(&key, &payment).await;
+        }
+        dbtx.commit_tx().await;
+    }
+
+    /// Adds an intercepted HTLC to the payments ledger and spawns a task that
+    /// records its final status once the client's receive completes.
+    async fn record_incoming_payment(
+        &self,
+        client: ClientHandleArc,
+        federation_id: FederationId,
+        htlc: Htlc,
+        operation_id: OperationId,
+        task_group: &TaskGroup,
+    ) {
+        let payment_hash = htlc.payment_hash;
+        self.record_payment(
+            federation_id,
+            payment_hash,
+            PaymentDirection::Incoming,
+            htlc.outgoing_amount_msat,
+            htlc.incoming_amount_msat
+                .saturating_sub(htlc.outgoing_amount_msat),
+        )
+        .await;
+
+        let htlc_key = InterceptedHtlcKey {
+            incoming_chan_id: htlc.incoming_chan_id,
+            htlc_id: htlc.htlc_id,
+        };
+        self.track_incoming_payment(client, payment_hash, operation_id, htlc_key, task_group)
+            .await;
+    }
+
+    /// Spawns a task that records the final status of an incoming payment
+    /// once the client's receive completes and forgets the intercepted HTLC.
+    async fn track_incoming_payment(
+        &self,
+        client: ClientHandleArc,
+        payment_hash: sha256::Hash,
+        operation_id: OperationId,
+        htlc_key: InterceptedHtlcKey,
+        task_group: &TaskGroup,
+    ) {
+        self.set_intercepted_htlc_stage(&htlc_key, InterceptedHtlcStage::Funding(operation_id))
+            .await;
+
+        let gateway = self.clone();
+        task_group.spawn_cancellable("track incoming payment", async move {
+            let updates = match client
+                .get_first_module::<GatewayClientModule>()
+                .gateway_subscribe_ln_receive(operation_id)
+                .await
+            {
+                Ok(updates) => updates,
+                Err(e) => {
+                    warn!("Failed to subscribe to incoming payment {payment_hash}: {e:?}");
+                    return;
+                }
+            };
+
+            let mut updates = updates.into_stream();
+            while let Some(update) = updates.next().await {
+                let (status, error) = match update {
+                    GatewayExtReceiveStates::Funding => continue,
+                    GatewayExtReceiveStates::Preimage(_) => (PaymentStatus::Succeeded, None),
+                    GatewayExtReceiveStates::RefundSuccess { error, .. }
+                    | GatewayExtReceiveStates::FundingFailed { error } => {
+                        (PaymentStatus::Failed, Some(error.to_string()))
+                    }
+                    GatewayExtReceiveStates::RefundError { error_message, .. } => {
+                        (PaymentStatus::Failed, Some(error_message))
+                    }
+                };
+
+                gateway
+                    .update_payment_status(payment_hash, PaymentDirection::Incoming, status, error)
+                    .await;
+                gateway.remove_intercepted_htlc(&htlc_key).await;
+                break;
+            }
+        });
+    }
+
+    /// Spawns a task that forgets an intercepted HTLC relayed to a federation
+    /// using LNv2 once the client has settled or cancelled it.
+    async fn track_intercepted_htlc_v2(
+        &self,
+        client: ClientHandleArc,
+        htlc_key: InterceptedHtlcKey,
+        operation_id: OperationId,
+        task_group: &TaskGroup,
+    ) {
+        self.set_intercepted_htlc_stage(&htlc_key, InterceptedHtlcStage::Funding(operation_id))
+            .await;
+
+        let gateway = self.clone();
+        task_group.spawn_cancellable("track intercepted HTLC", async move {
+            client
+                .get_first_module::<GatewayClientModuleV2>()
+                .subscribe_completion(operation_id)
+                .await;
+            gateway.remove_intercepted_htlc(&htlc_key).await;
+        });
+    }
+
+    /// Persists an HTLC paying into a federation before it is handed to the
+    /// federation's client, so it can be resolved if the gateway restarts.
+    async fn record_intercepted_htlc(
+        &self,
+        htlc_request: &InterceptHtlcRequest,
+        federation_id: FederationId,
+        lnv2: bool,
+    ) -> InterceptedHtlcKey {
+        let key = InterceptedHtlcKey {
+            incoming_chan_id: htlc_request.incoming_chan_id,
+            htlc_id: htlc_request.htlc_id,
+        };
+        let htlc = InterceptedHtlc {
+            federation_id,
+            payment_hash: sha256::Hash::from_slice(&htlc_request.payment_hash)
+                .expect("Lightning node reported invalid payment hash"),
+            short_channel_id: htlc_request.short_channel_id,
+            incoming_amount_msat: htlc_request.incoming_amount_msat,
+            outgoing_amount_msat: htlc_request.outgoing_amount_msat,
+            lnv2,
+            stage: InterceptedHtlcStage::Intercepted,
+            updated_at: duration_since_epoch().as_secs(),
+        };
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(&key, &htlc).await;
+        dbtx.commit_tx().await;
+        key
+    }
+
+    async fn set_intercepted_htlc_stage(
+        &self,
+        key: &InterceptedHtlcKey,
+        stage: InterceptedHtlcStage,
+    ) {
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        if let Some(mut htlc) = dbtx.get_value(key).await {
+            htlc.stage = stage;
+            htlc.updated_at = duration_since_epoch().as_secs();
+            dbtx.insert_entry(key, &htlc).await;
+        }
+        dbtx.commit_tx().await;
+    }
+
+    async fn remove_intercepted_htlc(&self, key: &InterceptedHtlcKey) {
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.remove_entry(key).await;
+        dbtx.commit_tx().await;
+    }
+
+    /// Whether `htlc_request` has already been handed to the client of its
+    /// federation, which settles or cancels it
+    async fn is_funding_intercepted_htlc(&self, htlc_request: &InterceptHtlcRequest) -> bool {
+        let key = InterceptedHtlcKey {
+            incoming_chan_id: htlc_request.incoming_chan_id,
+            htlc_id: htlc_request.htlc_id,
+        };
+        matches!(
+            self.gateway_db
+                .begin_transaction_nc()
+                .await
+                .get_value(&key)
+                .await,
+            Some(InterceptedHtlc {
+                stage: InterceptedHtlcStage::Funding(_),
+                ..
+            })
+        )
+    }
+
+    /// Returns the operation of the federation's client that funds the
+    /// incoming contract of `htlc`, if the gateway got that far
+    async fn intercepted_htlc_operation_id(&self, htlc: &InterceptedHtlc) -> Option<OperationId> {
+        match htlc.stage {
+            InterceptedHtlcStage::Funding(operation_id) => Some(operation_id),
+            // The gateway might have stopped after handing the HTLC to the client but
+            // before recording it, so the operation is derived like the client does
+            InterceptedHtlcStage::Intercepted if htlc.lnv2 => self
+                .gateway_db
+                .begin_transaction_nc()
+                .await
+                .get_value(&CreateInvoicePayloadKey(htlc.payment_hash.to_byte_array()))
+                .await
+                .map(|payload| OperationId::from_encodable(&payload)),
+            InterceptedHtlcStage::Intercepted => {
+                Some(OperationId(htlc.payment_hash.to_byte_array()))
+            }
+        }
+    }
+
+    /// Resolves the HTLCs that were intercepted before the gateway restarted.
+    /// HTLCs whose incoming contract is being funded are left to the client of
+    /// their federation, which settles or cancels them. All others are
+    /// cancelled since the gateway has not paid for them.
+    async fn reconcile_intercepted_htlcs(
+        &self,
+        lnrpc: Arc<dyn ILnRpcClient>,
+        task_group: &TaskGroup,
+    ) {
+        let htlcs = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&InterceptedHtlcKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+
+        for (key, htlc) in htlcs {
+            let client = self
+                .clients
+                .read()
+                .await
+                .get(&htlc.federation_id)
+                .map(|client| client.value().clone());
+
+            if let (Some(client), Some(operation_id)) =
+                (client, self.intercepted_htlc_operation_id(&htlc).await)
+            {
+                if client.operation_exists(operation_id).await {
+                    info!(
+                        "Resuming intercepted HTLC {} for federation {}",
+                        htlc.payment_hash, htlc.federation_id
+                    );
+                    if htlc.lnv2 {
+                        self.track_intercepted_htlc_v2(client, key, operation_id, task_group)
+                            .await;
+                    } else {
+                        self.track_incoming_payment(
+                            client,
+                            htlc.payment_hash,
+                            operation_id,
+                            key,
+                            task_group,
+                        )
+                        .await;
+                    }
+                    continue;
+                }
+            }
+
+            warn!(
+                "Cancelling intercepted HTLC {} for federation {}",
+                htlc.payment_hash, htlc.federation_id
+            );
+            let outcome = InterceptHtlcResponse {
+                action: Some(Action::Cancel(Cancel {
+                    reason: "Gateway restarted before handling the HTLC".to_string(),
+                })),
+                incoming_chan_id: key.incoming_chan_id,
+                htlc_id: key.htlc_id,
+            };
+
+            // The lightning node might have already failed the HTLC while the gateway
+            // was down
+            if let Err(error) = lnrpc.complete_htlc(outcome).await {
+                warn!("Failed to cancel intercepted HTLC: {error:?}");
+            }
+            self.remove_intercepted_htlc(&key).await;
+        }
+    }
+
+    /// Handle a request to change a connected federation's configuration or
+    /// gateway metadata. If `num_route_hints` is changed, the Gateway
+    /// will re-register with all connected federations. If
+    /// `per_federation_routing_fees` is changed, the Gateway will only
+    /// re-register with the specified federation.
+    pub async fn handle_set_configuration_msg(
+        &self,
+        SetConfigurationPayload {
+            password,
+            network,
+            num_route_hints,
+            routing_fees,
+            per_federation_routing_fees,
+        }: SetConfigurationPayload,
+    ) -> Result<()> {
+        let gw_state = self.state.read().await.clone();
+        let lightning_network = match gw_state {
+            GatewayState::Running { lightning_context } => {
+                if network.is_some() && network != Some(lightning_context.lightning_network) {
+                    return Err(GatewayError::GatewayConfigurationError(
+                        "Cannot change network while connected to a lightning node".to_string(),
+                    ));
+                }
+                lightning_context.lightning_network
+            }
+            // In the case the gateway is not yet running and not yet connected to a lightning node,
+            // we start off with a default network configuration. This default gets replaced later
+            // when the gateway connects to a lightning node, or when a user sets a different
+            // configuration
+            _ => DEFAULT_NETWORK,
+        };
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+
+        let prev_gateway_config = self.gateway_config.read().await.clone();
+        let new_gateway_config = if let Some(mut prev_config) = prev_gateway_config {
+            if let Some(password) = password {
+                if self.client_builder.is_encrypted() {
+                    return Err(GatewayError::GatewayConfigurationError(
+                        "Cannot change the password while the client databases are encrypted \
+                         with it"
+                            .to_string(),
+                    ));
+                }
+                let hashed_password = hash_password(password, prev_config.password_salt);
+                prev_config.hashed_password = hashed_password;
+            }
+
+            if let Some(network) = network {
+                if self.clients.read().await.len() > 0 {
+                    return Err(GatewayError::GatewayConfigurationError(
+                        "Cannot change network while connected to a federation".to_string(),
+                    ));
+                }
+                prev_config.network = network;
+            }
+
+            if let Some(num_route_hints) = num_route_hints {
+                prev_config.num_route_hints = num_route_hints;
+            }
+
+            // Using this routing fee config as a default for all federation that has none
+            // routing fees specified.
+            if let Some(fees) = routing_fees.clone() {
+                let routing_fees = GatewayFee(fees.into()).0;
+                prev_config.routing_fees = routing_fees;
+            }
+
+            prev_config
+        } else {
+            let password = password.ok_or(GatewayError::GatewayConfigurationError(
+                "The password field is required when initially configuring the gateway".to_string(),
+            ))?;
+            let password_salt: [u8; 16] = rand::thread_rng().gen();
+            let hashed_password = hash_password(password, password_salt);
+
+            GatewayConfiguration {
+                hashed_password,
+                network: lightning_network,
+                num_route_hints: DEFAULT_NUM_ROUTE_HINTS,
+                routing_fees: DEFAULT_FEES,
+                password_salt,
+            }
+        };
+        dbtx.insert_entry(&GatewayConfigurationKey, &new_gateway_config)
+            .await;
+
+        let mut register_federations: Vec<(FederationId, FederationConfig)> = Vec::new();
+        if let Some(per_federation_routing_fees) = per_federation_routing_fees {
+            for (federation_id, routing_fees) in per_federation_routing_fees.iter() {
                 let federation_key = FederationIdKey { id: *federation_id };
                 if let Some(mut federation_config) = dbtx.get_value(&federation_key).await {
                     federation_config.fees = routing_fees.clone().into();
@@ -1256,115 +2275,943 @@ impl Gateway {
 
         dbtx.commit_tx().await;
 
-        let mut curr_gateway_config = self.gateway_config.write().await;
-        *curr_gateway_config = Some(new_gateway_config.clone());
+        let mut curr_gateway_config = self.gateway_config.write().await;
+        *curr_gateway_config = Some(new_gateway_config.clone());
+
+        info!("Set GatewayConfiguration successfully.");
+
+        Ok(())
+    }
+
+    /// Instructs the Gateway's Lightning node to connect to a peer specified by
+    /// `pubkey` and `host`.
+    pub async fn handle_connect_to_peer_msg(
+        &self,
+        ConnectToPeerPayload { pubkey, host }: ConnectToPeerPayload,
+    ) -> Result<()> {
+        let context = self.get_lightning_context().await?;
+        context.lnrpc.connect_to_peer(pubkey, host).await?;
+        Ok(())
+    }
+
+    /// Instructs the Gateway's Lightning node to retrieve an onchain funding
+    /// Bitcoin address.
+    pub async fn handle_get_funding_address_msg(&self) -> Result<Address> {
+        let context = self.get_lightning_context().await?;
+        let response = context.lnrpc.get_funding_address().await?;
+        Address::from_str(&response.address)
+            .map(|address| address.assume_checked())
+            .map_err(|e| GatewayError::LightningResponseParseError(e.into()))
+    }
+
+    /// Creates a BOLT12 offer on the lightning node for a connected
+    /// federation. Payments to the offer are received by the lightning node.
+    pub async fn handle_create_offer_msg(
+        &self,
+        CreateOfferPayload {
+            federation_id,
+            amount,
+            description,
+            expiry_secs,
+        }: CreateOfferPayload,
+    ) -> Result<OfferInfo> {
+        self.select_client(federation_id).await?;
+        let context = self.get_lightning_context().await?;
+
+        let offer = context
+            .lnrpc
+            .create_offer(CreateOfferRequest {
+                amount_msat: amount.map(|amount| amount.msats),
+                description: description.clone(),
+                absolute_expiry: expiry_secs
+                    .map(|expiry_secs| duration_since_epoch().as_secs() + expiry_secs),
+            })
+            .await?;
+
+        let record = OfferRecord {
+            federation_id,
+            bolt12: offer.bolt12.clone(),
+            amount,
+            description,
+            created_at: duration_since_epoch().as_secs(),
+        };
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(
+            &OfferKey {
+                offer_id: offer.offer_id.clone(),
+            },
+            &record,
+        )
+        .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        info!(
+            "Created offer {} for federation {federation_id}",
+            offer.offer_id
+        );
+        Ok(Self::make_offer_info(offer, record))
+    }
+
+    /// Returns an offer the gateway created with its current state on the
+    /// lightning node, or `None` if the gateway did not create it.
+    pub async fn handle_get_offer_msg(
+        &self,
+        GetOfferPayload { offer_id }: GetOfferPayload,
+    ) -> Result<Option<OfferInfo>> {
+        let Some(record) = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&OfferKey {
+                offer_id: offer_id.clone(),
+            })
+            .await
+        else {
+            return Ok(None);
+        };
+
+        let context = self.get_lightning_context().await?;
+        Ok(context
+            .lnrpc
+            .get_offer(offer_id)
+            .await?
+            .map(|offer| Self::make_offer_info(offer, record)))
+    }
+
+    fn make_offer_info(offer: OfferResponse, record: OfferRecord) -> OfferInfo {
+        OfferInfo {
+            offer_id: offer.offer_id,
+            federation_id: record.federation_id,
+            bolt12: offer.bolt12,
+            amount: record.amount,
+            description: record.description,
+            active: offer.active,
+            used: offer.used,
+            created_at: record.created_at,
+        }
+    }
+
+    /// Instructs the Gateway's Lightning node to open a channel to a peer
+    /// specified by `pubkey`.
+    pub async fn handle_open_channel_msg(
+        &self,
+        OpenChannelPayload {
+            pubkey,
+            channel_size_sats,
+            push_amount_sats,
+        }: OpenChannelPayload,
+    ) -> Result<()> {
+        let context = self.get_lightning_context().await?;
+        context
+            .lnrpc
+            .open_channel(pubkey, channel_size_sats, push_amount_sats)
+            .await?;
+        Ok(())
+    }
+
+    /// Instructs the Gateway's Lightning node to close all channels with a peer
+    /// specified by `pubkey`.
+    pub async fn handle_close_channels_with_peer_msg(
+        &self,
+        CloseChannelsWithPeerPayload { pubkey }: CloseChannelsWithPeerPayload,
+    ) -> Result<CloseChannelsWithPeerResponse> {
+        let context = self.get_lightning_context().await?;
+        let response = context.lnrpc.close_channels_with_peer(pubkey).await?;
+        Ok(response)
+    }
+
+    /// Returns a list of Lightning network channels from the Gateway's
+    /// Lightning node.
+    pub async fn handle_list_active_channels_msg(&self) -> Result<Vec<lightning::ChannelInfo>> {
+        let context = self.get_lightning_context().await?;
+        let channels = context.lnrpc.list_active_channels().await?;
+        Ok(channels)
+    }
+
+    /// Returns the balance of the Gateway's Lightning node's on-chain wallet.
+    pub async fn handle_get_onchain_balance_msg(&self) -> Result<GetOnchainBalanceResponse> {
+        let context = self.get_lightning_context().await?;
+        let balance = context.lnrpc.get_onchain_balance().await?;
+        Ok(balance)
+    }
+
+    /// Instructs the Gateway's Lightning node to send funds from its on-chain
+    /// wallet to `address`.
+    pub async fn handle_send_onchain_msg(
+        &self,
+        SendOnchainPayload {
+            address,
+            amount_sats,
+            fee_rate_sats_per_vbyte,
+        }: SendOnchainPayload,
+    ) -> Result<Txid> {
+        let context = self.get_lightning_context().await?;
+        let address = address
+            .require_network(context.lightning_network)
+            .map_err(|e| LightningRpcError::FailedToSendOnchain {
+                failure_reason: e.to_string(),
+            })?;
+
+        let response = context
+            .lnrpc
+            .send_onchain(SendOnchainRequest {
+                address: address.to_string(),
+                amount_sats,
+                fee_rate_sats_per_vbyte,
+            })
+            .await?;
+        info!("Sent {amount_sats} sats on-chain to {address}");
+        Txid::from_str(&response.txid)
+            .map_err(|e| GatewayError::LightningResponseParseError(e.into()))
+    }
+
+    /// Instructs the Gateway's Lightning node to move liquidity between two of
+    /// its channels. Returns the routing fee that was paid.
+    pub async fn handle_rebalance_msg(
+        &self,
+        RebalancePayload {
+            outgoing_short_channel_id,
+            last_hop_pubkey,
+            amount,
+            max_fee,
+        }: RebalancePayload,
+    ) -> Result<Amount> {
+        let context = self.get_lightning_context().await?;
+        let fee = context
+            .lnrpc
+            .rebalance(outgoing_short_channel_id, last_hop_pubkey, amount, max_fee)
+            .await?;
+        Ok(fee)
+    }
+
+    /// Pays an invoice from the funds of the lightning node, without involving
+    /// the e-cash of any federation
+    pub async fn handle_pay_invoice_for_operator_msg(
+        &self,
+        PayInvoiceForOperatorPayload {
+            invoice,
+            max_fee,
+            max_delay,
+        }: PayInvoiceForOperatorPayload,
+    ) -> Result<Preimage> {
+        if self.is_draining() {
+            return Err(GatewayError::Draining);
+        }
+
+        let context = self.get_lightning_context().await?;
+        let response = context
+            .lnrpc
+            .pay(PayInvoiceRequest {
+                invoice: invoice.to_string(),
+                max_delay,
+                max_fee_msat: max_fee.msats,
+                payment_hash: invoice.payment_hash().to_byte_array().to_vec(),
+            })
+            .await?;
+
+        let preimage = response.preimage.as_slice().try_into().map_err(|_| {
+            GatewayError::LightningResponseParseError(anyhow!("Preimage is not 32 bytes"))
+        })?;
+        Ok(Preimage(preimage))
+    }
+
+    /// Registers the gateway with each specified federation.
+    async fn register_federations(
+        &self,
+        gateway_config: &GatewayConfiguration,
+        federations: &[(FederationId, FederationConfig)],
+    ) -> Result<()> {
+        if let Ok(lightning_context) = self.get_lightning_context().await {
+            let route_hints = Self::fetch_lightning_route_hints(
+                lightning_context.lnrpc.clone(),
+                gateway_config.num_route_hints,
+            )
+            .await;
+            if route_hints.is_empty() {
+                warn!("Gateway did not retrieve any route hints, may reduce receive success rate.");
+            }
+
+            // A federation that fails to register must not keep the gateway from
+            // being announced to the remaining ones
+            let mut failed_federations = Vec::new();
+            for (federation_id, federation_config) in federations {
+                let Some(client) = self.clients.read().await.get(federation_id).cloned() else {
+                    continue;
+                };
+
+                let result = async {
+                    client
+                        .value()
+                        .get_first_module::<GatewayClientModule>()
+                        .register_with_federation(
+                            route_hints.clone(),
+                            GW_ANNOUNCEMENT_TTL,
+                            federation_config.fees,
+                            lightning_context.clone(),
+                        )
+                        .await
+                }
+                .instrument(client.span())
+                .await;
+
+                if result.is_err() {
+                    failed_federations.push(*federation_id);
+                }
+                self.record_registration(*federation_id, result).await;
+            }
+
+            if !failed_federations.is_empty() {
+                return Err(GatewayError::FederationError(FederationError::general(
+                    REGISTER_GATEWAY_ENDPOINT,
+                    serde_json::Value::Null,
+                    anyhow::anyhow!("Error registering federations {failed_federations:?}"),
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the limits of `federation_id`, which are the gateway's default
+    /// limits unless they have been overridden.
+    async fn federation_limits(&self, federation_id: FederationId) -> FederationLimits {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&FederationLimitsKey { id: federation_id })
+            .await
+            .unwrap_or_else(|| self.default_limits.clone())
+    }
+
+    /// Checks that `amount` can leave the gateway on behalf of
+    /// `federation_id` and counts it towards the federation's outflow on
+    /// `day`. `ecash_balance` is the gateway's balance after the outflow, if
+    /// the gateway is paid for it in e-cash.
+    async fn charge_outflow(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        federation_id: FederationId,
+        day: u64,
+        amount: Amount,
+        ecash_balance: Option<Amount>,
+    ) -> Result<()> {
+        let limits = self.federation_limits(federation_id).await;
+        let key = DailyOutflowKey { federation_id, day };
+
+        let daily_outflow = dbtx.get_value(&key).await.unwrap_or(Amount::ZERO);
+        if let Err(e) = limits.check(amount, daily_outflow, ecash_balance) {
+            warn!("Rejected outflow from federation {federation_id}: {e}");
+            return Err(GatewayError::FederationLimitExceeded(e));
+        }
+
+        dbtx.insert_entry(&key, &(daily_outflow + amount)).await;
+        Ok(())
+    }
+
+    /// Gives back the `amount` charged on `day` for an outflow that failed
+    async fn release_outflow(
+        dbtx: &mut DatabaseTransaction<'_>,
+        federation_id: FederationId,
+        day: u64,
+        amount: Amount,
+    ) {
+        let key = DailyOutflowKey { federation_id, day };
+
+        if let Some(daily_outflow) = dbtx.get_value(&key).await {
+            dbtx.insert_entry(&key, &daily_outflow.saturating_sub(amount))
+                .await;
+        }
+    }
+
+    /// Overrides the limits of a connected federation, or resets them to the
+    /// gateway's default limits.
+    pub async fn handle_set_federation_limits_msg(
+        &self,
+        SetFederationLimitsPayload {
+            federation_id,
+            limits,
+        }: SetFederationLimitsPayload,
+    ) -> Result<FederationLimits> {
+        self.select_client(federation_id).await?;
+
+        let key = FederationLimitsKey { id: federation_id };
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        match &limits {
+            Some(limits) => {
+                dbtx.insert_entry(&key, limits).await;
+            }
+            None => {
+                dbtx.remove_entry(&key).await;
+            }
+        }
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        info!("Set limits of federation {federation_id} to {limits:?}");
+        Ok(limits.unwrap_or_else(|| self.default_limits.clone()))
+    }
+
+    /// Returns the sweep policy of `federation_id`, if its e-cash is swept
+    /// automatically
+    async fn sweep_policy(&self, federation_id: FederationId) -> Option<SweepPolicy> {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&SweepPolicyKey { id: federation_id })
+            .await
+    }
+
+    /// Sets the policy by which the e-cash of a connected federation is swept
+    /// to the lightning node's on-chain wallet, or stops sweeping it.
+    pub async fn handle_set_sweep_policy_msg(
+        &self,
+        SetSweepPolicyPayload {
+            federation_id,
+            policy,
+        }: SetSweepPolicyPayload,
+    ) -> Result<Option<SweepPolicy>> {
+        self.select_client(federation_id).await?;
+
+        let key = SweepPolicyKey { id: federation_id };
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        match &policy {
+            Some(policy) => {
+                policy
+                    .validate()
+                    .map_err(GatewayError::InvalidSweepPolicy)?;
+                dbtx.insert_entry(&key, policy).await;
+            }
+            None => {
+                dbtx.remove_entry(&key).await;
+            }
+        }
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        info!("Set sweep policy of federation {federation_id} to {policy:?}");
+        Ok(policy)
+    }
+
+    /// Returns the rules the gateway applies to intercepted HTLCs
+    pub fn handle_get_htlc_filter_msg(&self) -> HtlcFilterConfig {
+        self.htlc_filter.config()
+    }
+
+    /// Replaces the rules the gateway applies to intercepted HTLCs, an empty
+    /// list of rules accepts every HTLC.
+    pub async fn handle_set_htlc_filter_msg(
+        &self,
+        SetHtlcFilterPayload { filter }: SetHtlcFilterPayload,
+    ) -> Result<HtlcFilterConfig> {
+        filter.validate().map_err(GatewayError::InvalidHtlcFilter)?;
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(&HtlcFilterKey, &filter).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        self.htlc_filter.set_config(filter.clone());
+        info!(
+            "Set HTLC filter with {} rules, dry run: {}",
+            filter.rules.len(),
+            filter.dry_run
+        );
+        Ok(filter)
+    }
+
+    /// Sweeps the e-cash of a connected federation above the requested float
+    /// to the lightning node's on-chain wallet. Returns `None` if there was
+    /// not enough e-cash to cover the peg-out fees.
+    pub async fn handle_sweep_msg(
+        &self,
+        SweepPayload {
+            federation_id,
+            float,
+        }: SweepPayload,
+    ) -> Result<Option<SweepRecord>> {
+        let float = match float {
+            Some(float) => float,
+            None => self
+                .sweep_policy(federation_id)
+                .await
+                .map_or(Amount::ZERO, |policy| policy.float),
+        };
+
+        self.sweep(federation_id, float, false).await
+    }
+
+    /// Returns the sweeps of the gateway, newest first
+    pub async fn handle_list_sweeps_msg(
+        &self,
+        ListSweepsPayload { federation_id }: ListSweepsPayload,
+    ) -> Result<Vec<SweepRecord>> {
+        let mut sweeps = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&SweepKeyPrefix)
+            .await
+            .map(|(_, sweep)| sweep)
+            .filter(|sweep| {
+                std::future::ready(federation_id.map_or(true, |id| sweep.federation_id == id))
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        sweeps.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(sweeps)
+    }
+
+    /// Pegs out the e-cash of `federation_id` above `float` to the lightning
+    /// node's on-chain wallet. Sweeps move funds between wallets of the
+    /// operator, so they are not subject to the federation's limits.
+    async fn sweep(
+        &self,
+        federation_id: FederationId,
+        float: Amount,
+        automatic: bool,
+    ) -> Result<Option<SweepRecord>> {
+        let _sweep_lock = self.sweep_lock.lock().await;
+
+        let client = self.select_client(federation_id).await?;
+        let balance = client.value().get_balance().await;
+        let excess = bitcoin::Amount::from_sat(balance.saturating_sub(float).msats / 1000);
+        if excess == bitcoin::Amount::ZERO {
+            return Ok(None);
+        }
+
+        let context = self.get_lightning_context().await?;
+        let response = context.lnrpc.get_funding_address().await?;
+        let address = Address::from_str(&response.address)
+            .map_err(|e| GatewayError::LightningResponseParseError(e.into()))?;
+
+        let wallet_module = client.value().get_first_module::<WalletClientModule>();
+        let fees = wallet_module
+            .get_withdraw_fees(address.clone(), excess)
+            .await?;
+        let Some(amount) = excess.checked_sub(fees.amount()) else {
+            debug!("E-cash of federation {federation_id} above the float does not cover the peg-out fees");
+            return Ok(None);
+        };
+
+        let txid = Self::peg_out(&wallet_module, address, amount, fees).await?;
+        let sweep = SweepRecord {
+            federation_id,
+            txid,
+            address: response.address,
+            amount: amount.into(),
+            fee: fees.amount().into(),
+            automatic,
+            created_at: duration_since_epoch().as_secs(),
+        };
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(
+            &SweepKey {
+                federation_id,
+                txid,
+            },
+            &sweep,
+        )
+        .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        info!("Swept {amount} from federation {federation_id} in transaction {txid}");
+        Ok(Some(sweep))
+    }
+
+    /// Spawns a task that periodically sweeps the federations whose e-cash
+    /// balance exceeds the threshold of their sweep policy.
+    fn sweep_timer(&self, task_group: &mut TaskGroup) {
+        let gateway = self.clone();
+        task_group.spawn_cancellable("sweep federations", async move {
+            loop {
+                sleep(SWEEP_INTERVAL).await;
+
+                let policies = gateway
+                    .gateway_db
+                    .begin_transaction_nc()
+                    .await
+                    .find_by_prefix(&SweepPolicyKeyPrefix)
+                    .await
+                    .collect::<Vec<_>>()
+                    .await;
+
+                for (SweepPolicyKey { id: federation_id }, policy) in policies {
+                    let Ok(client) = gateway.select_client(federation_id).await else {
+                        continue;
+                    };
+                    if !policy.should_sweep(client.value().get_balance().await) {
+                        continue;
+                    }
+
+                    if let Err(e) = gateway.sweep(federation_id, policy.float, true).await {
+                        warn!("Failed to sweep federation {federation_id}: {e}");
+                    }
+                }
+            }
+        });
+    }
+
+    fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Returns the connected federation `htlc_request` pays into, or `None`
+    /// if it is forwarded
+    async fn federation_of_htlc(
+        &self,
+        htlc_request: &InterceptHtlcRequest,
+    ) -> Option<FederationId> {
+        if let Ok(payment_hash) = <[u8; 32]>::try_from(htlc_request.payment_hash.as_slice()) {
+            if let Some(payload) = self
+                .gateway_db
+                .begin_transaction_nc()
+                .await
+                .get_value(&CreateInvoicePayloadKey(payment_hash))
+                .await
+            {
+                return Some(payload.federation_id);
+            }
+        }
+
+        let short_channel_id = htlc_request.short_channel_id?;
+        self.scid_to_federation
+            .read()
+            .await
+            .get(&short_channel_id)
+            .copied()
+    }
+
+    /// Fails `htlc_request` back to the sender
+    async fn cancel_htlc(
+        lightning_context: &LightningContext,
+        htlc_request: &InterceptHtlcRequest,
+        reason: &str,
+    ) {
+        let outcome = InterceptHtlcResponse {
+            action: Some(Action::Cancel(Cancel {
+                reason: reason.to_string(),
+            })),
+            incoming_chan_id: htlc_request.incoming_chan_id,
+            htlc_id: htlc_request.htlc_id,
+        };
+
+        if let Err(error) = lightning_context.lnrpc.complete_htlc(outcome).await {
+            error!("Error sending HTLC response to lightning node: {error:?}");
+        }
+    }
+
+    /// Returns the payments that have not succeeded or failed yet
+    async fn in_flight_payments(&self) -> Vec<PaymentRecord> {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&PaymentKeyPrefix)
+            .await
+            .filter_map(|(_, payment)| async move {
+                (payment.status == PaymentStatus::Pending).then_some(payment)
+            })
+            .collect()
+            .await
+    }
+
+    /// Stops the gateway from accepting new payments and waits until the
+    /// payments that are in flight have resolved or the timeout has passed.
+    /// The gateway keeps draining until it restarts.
+    pub async fn handle_drain_msg(
+        &self,
+        DrainPayload { timeout_secs }: DrainPayload,
+    ) -> Result<DrainStatus> {
+        if !self.draining.swap(true, Ordering::SeqCst) {
+            info!("Draining gateway, new payments are rejected");
+        }
+
+        let timeout = timeout_secs.map_or(DEFAULT_DRAIN_TIMEOUT, Duration::from_secs);
+        let deadline = now() + timeout;
+        loop {
+            let in_flight = self.in_flight_payments().await;
+            if in_flight.is_empty() {
+                info!("Gateway is drained");
+                return Ok(DrainStatus { in_flight });
+            }
+
+            if now() >= deadline {
+                warn!(
+                    "{} payments are still in flight after draining for {timeout:?}",
+                    in_flight.len()
+                );
+                return Ok(DrainStatus { in_flight });
+            }
+
+            sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Shuts the gateway down once it is drained, or right away if `force` is
+    /// set.
+    pub async fn handle_shutdown_msg(
+        &self,
+        ShutdownPayload { force }: ShutdownPayload,
+        task_group: &TaskGroup,
+    ) -> Result<()> {
+        if !force {
+            if !self.is_draining() {
+                return Err(GatewayError::NotDrained(
+                    "the gateway is not draining".to_string(),
+                ));
+            }
+
+            let in_flight = self.in_flight_payments().await.len();
+            if in_flight > 0 {
+                return Err(GatewayError::NotDrained(format!(
+                    "{in_flight} payments are in flight"
+                )));
+            }
+        }
+
+        warn!("Shutting down gateway, forced: {force}");
+        task_group.shutdown();
+        Ok(())
+    }
+
+    /// Returns the scope of the API key `key`, if it is valid
+    pub async fn api_key_scope(&self, key: &str) -> Option<ApiScope> {
+        let (key_id, _) = key.split_once(API_KEY_ID_SEPARATOR)?;
+
+        let record = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&ApiKeyKey {
+                key_id: key_id.to_string(),
+            })
+            .await?;
+
+        let hashed_key = hash_password(key.to_string(), record.salt);
 
-        info!("Set GatewayConfiguration successfully.");
+        bitcoin_hashes::cmp::fixed_time_eq(&hashed_key[..], &record.hashed_key[..])
+            .then_some(record.scope)
+    }
 
-        Ok(())
+    /// Returns the id the API key with the given name is stored under
+    async fn find_api_key_id(dbtx: &mut DatabaseTransaction<'_>, name: &str) -> Option<String> {
+        dbtx.find_by_prefix(&ApiKeyKeyPrefix)
+            .await
+            .filter_map(|(ApiKeyKey { key_id }, record)| {
+                std::future::ready((record.name == name).then_some(key_id))
+            })
+            .next()
+            .await
     }
 
-    /// Instructs the Gateway's Lightning node to connect to a peer specified by
-    /// `pubkey` and `host`.
-    pub async fn handle_connect_to_peer_msg(
+    /// Creates a random API key with the given scope. The key is only
+    /// returned here, the gateway stores a hash of it.
+    pub async fn handle_create_api_key_msg(
         &self,
-        ConnectToPeerPayload { pubkey, host }: ConnectToPeerPayload,
-    ) -> Result<()> {
-        let context = self.get_lightning_context().await?;
-        context.lnrpc.connect_to_peer(pubkey, host).await?;
-        Ok(())
+        CreateApiKeyPayload { name, scope }: CreateApiKeyPayload,
+    ) -> Result<String> {
+        let key_id = hex::encode(OsRng.gen::<[u8; 8]>());
+        let key = format!(
+            "{key_id}{API_KEY_ID_SEPARATOR}{}",
+            hex::encode(OsRng.gen::<[u8; 32]>())
+        );
+        let salt = OsRng.gen::<[u8; 16]>();
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        if Self::find_api_key_id(&mut dbtx.to_ref_nc(), &name)
+            .await
+            .is_some()
+        {
+            return Err(GatewayError::GatewayConfigurationError(format!(
+                "API key {name} already exists"
+            )));
+        }
+        let record = ApiKeyRecord {
+            name: name.clone(),
+            hashed_key: hash_password(key.clone(), salt),
+            salt,
+            scope,
+            created_at: duration_since_epoch().as_secs(),
+        };
+        dbtx.insert_new_entry(&ApiKeyKey { key_id }, &record).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        info!("Created API key {name} with scope {scope:?}");
+        Ok(key)
     }
 
-    /// Instructs the Gateway's Lightning node to retrieve an onchain funding
-    /// Bitcoin address.
-    pub async fn handle_get_funding_address_msg(&self) -> Result<Address> {
-        let context = self.get_lightning_context().await?;
-        let response = context.lnrpc.get_funding_address().await?;
-        Address::from_str(&response.address)
-            .map(|address| address.assume_checked())
-            .map_err(|e| GatewayError::LightningResponseParseError(e.into()))
+    pub async fn handle_list_api_keys_msg(&self) -> Vec<ApiKeyInfo> {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&ApiKeyKeyPrefix)
+            .await
+            .map(|(_, record)| ApiKeyInfo {
+                name: record.name,
+                scope: record.scope,
+                created_at: record.created_at,
+            })
+            .collect()
+            .await
     }
 
-    /// Instructs the Gateway's Lightning node to open a channel to a peer
-    /// specified by `pubkey`.
-    pub async fn handle_open_channel_msg(
+    pub async fn handle_revoke_api_key_msg(
         &self,
-        OpenChannelPayload {
-            pubkey,
-            channel_size_sats,
-            push_amount_sats,
-        }: OpenChannelPayload,
+        RevokeApiKeyPayload { name }: RevokeApiKeyPayload,
     ) -> Result<()> {
-        let context = self.get_lightning_context().await?;
-        context
-            .lnrpc
-            .open_channel(pubkey, channel_size_sats, push_amount_sats)
-            .await?;
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        let Some(key_id) = Self::find_api_key_id(&mut dbtx.to_ref_nc(), &name).await else {
+            return Err(GatewayError::GatewayConfigurationError(format!(
+                "API key {name} does not exist"
+            )));
+        };
+        dbtx.remove_entry(&ApiKeyKey { key_id }).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        info!("Revoked API key {name}");
         Ok(())
     }
 
-    /// Instructs the Gateway's Lightning node to close all channels with a peer
-    /// specified by `pubkey`.
-    pub async fn handle_close_channels_with_peer_msg(
+    /// Exports the gateway's settings and keys together with the config and
+    /// client secret of every connected federation as an archive encrypted
+    /// with `password`.
+    pub async fn handle_export_config_msg(
         &self,
-        CloseChannelsWithPeerPayload { pubkey }: CloseChannelsWithPeerPayload,
-    ) -> Result<CloseChannelsWithPeerResponse> {
-        let context = self.get_lightning_context().await?;
-        let response = context.lnrpc.close_channels_with_peer(pubkey).await?;
-        Ok(response)
-    }
+        ExportConfigPayload { password }: ExportConfigPayload,
+    ) -> Result<EncryptedConfigArchive> {
+        let mut dbtx = self.gateway_db.begin_transaction_nc().await;
+        let keypair = dbtx
+            .get_value(&GatewayPublicKey)
+            .await
+            .expect("Gateway keypair does not exist");
+        let configs = self
+            .client_builder
+            .load_configs(self.gateway_db.begin_transaction_nc().await)
+            .await;
 
-    /// Returns a list of Lightning network channels from the Gateway's
-    /// Lightning node.
-    pub async fn handle_list_active_channels_msg(&self) -> Result<Vec<lightning::ChannelInfo>> {
-        let context = self.get_lightning_context().await?;
-        let channels = context.lnrpc.list_active_channels().await?;
-        Ok(channels)
+        let mut federations = Vec::with_capacity(configs.len());
+        for config in configs {
+            let federation_id = config.invite_code.federation_id();
+            let client = self.select_client(federation_id).await?;
+            let client_secret =
+                Client::load_decodable_client_secret::<[u8; 64]>(client.value().db()).await?;
+            let limits = dbtx
+                .get_value(&FederationLimitsKey { id: federation_id })
+                .await;
+
+            federations.push(FederationArchive {
+                config,
+                client_secret,
+                limits,
+            });
+        }
+
+        let archive = GatewayConfigArchive {
+            gateway_config: dbtx.get_value(&GatewayConfigurationKey).await,
+            keypair,
+            federations,
+        };
+        info!(
+            "Exporting config of {} federations",
+            archive.federations.len()
+        );
+        Ok(archive.encrypt(&password)?)
     }
 
-    /// Registers the gateway with each specified federation.
-    async fn register_federations(
+    /// Imports an archive created by `handle_export_config_msg`. The gateway
+    /// must not be connected to any federation, since its keys are replaced by
+    /// the ones in the archive. The federations are connected once the gateway
+    /// restarts.
+    pub async fn handle_import_config_msg(
         &self,
-        gateway_config: &GatewayConfiguration,
-        federations: &[(FederationId, FederationConfig)],
+        ImportConfigPayload { password, archive }: ImportConfigPayload,
     ) -> Result<()> {
-        if let Ok(lightning_context) = self.get_lightning_context().await {
-            let route_hints = Self::fetch_lightning_route_hints(
-                lightning_context.lnrpc.clone(),
-                gateway_config.num_route_hints,
-            )
-            .await;
-            if route_hints.is_empty() {
-                warn!("Gateway did not retrieve any route hints, may reduce receive success rate.");
+        let archive = GatewayConfigArchive::decrypt(&archive, &password)?;
+
+        let _join_federation = self.client_joining_lock.lock().await;
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        if !self.clients.read().await.is_empty()
+            || dbtx
+                .find_by_prefix(&FederationIdKeyPrefix)
+                .await
+                .next()
+                .await
+                .is_some()
+        {
+            return Err(GatewayError::FederationAlreadyConnected);
+        }
+
+        for FederationArchive {
+            config,
+            client_secret,
+            limits,
+        } in archive.federations
+        {
+            let federation_id = config.invite_code.federation_id();
+            self.client_builder
+                .store_client_secret(federation_id, client_secret)
+                .await?;
+            dbtx.insert_entry(&FederationIdKey { id: federation_id }, &config)
+                .await;
+            if let Some(limits) = limits {
+                dbtx.insert_entry(&FederationLimitsKey { id: federation_id }, &limits)
+                    .await;
             }
+        }
 
-            for (federation_id, federation_config) in federations {
-                if let Some(client) = self.clients.read().await.get(federation_id) {
-                    if let Err(e) = async {
-                        client
-                            .value()
-                            .get_first_module::<GatewayClientModule>()
-                            .register_with_federation(
-                                route_hints.clone(),
-                                GW_ANNOUNCEMENT_TTL,
-                                federation_config.fees,
-                                lightning_context.clone(),
-                            )
-                            .await
-                    }
-                    .instrument(client.span())
-                    .await
-                    {
-                        Err(GatewayError::FederationError(FederationError::general(
-                            REGISTER_GATEWAY_ENDPOINT,
-                            serde_json::Value::Null,
-                            anyhow::anyhow!("Error registering federation {federation_id}: {e:?}"),
-                        )))?
-                    }
+        if let Some(gateway_config) = archive.gateway_config {
+            dbtx.insert_entry(&GatewayConfigurationKey, &gateway_config)
+                .await;
+        }
+        dbtx.insert_entry(&GatewayPublicKey, &archive.keypair).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        info!("Imported gateway config, restart the gateway to connect to its federations");
+        Ok(())
+    }
+
+    /// Keeps track of the registrations with `federation_id` and alerts the
+    /// operator once failures have caused the announcement to expire.
+    async fn record_registration(&self, federation_id: FederationId, result: anyhow::Result<()>) {
+        let mut registrations = self.registrations.write().await;
+        let registration = registrations.entry(federation_id).or_default();
+        match result {
+            Ok(()) => {
+                if registration.consecutive_failures > 0 {
+                    info!(
+                        "Registered with federation {federation_id} again after {} failed attempts",
+                        registration.consecutive_failures
+                    );
+                }
+                registration.last_success = Some(now());
+                registration.consecutive_failures = 0;
+            }
+            Err(e) => {
+                registration.consecutive_failures += 1;
+                if registration.is_expired() {
+                    error!(
+                        "Gateway is no longer announced to federation {federation_id} after {} failed registrations: {e:?}",
+                        registration.consecutive_failures
+                    );
+                } else {
+                    warn!("Failed to refresh registration with federation {federation_id}: {e:?}");
                 }
             }
         }
-        Ok(())
     }
 
     /// This function will return a `GatewayConfiguration` one of two
@@ -1424,9 +3271,7 @@ impl Gateway {
             .write()
             .await
             .remove(&federation_id)
-            .ok_or(GatewayError::InvalidMetadata(format!(
-                "No federation with id {federation_id}"
-            )))?
+            .ok_or(GatewayError::FederationNotConnected(federation_id))?
             .into_value();
 
         if let Some(client) = Arc::into_inner(client) {
@@ -1440,6 +3285,7 @@ impl Gateway {
             .write()
             .await
             .retain(|_, fid| *fid != federation_id);
+        self.registrations.write().await.remove(&federation_id);
         Ok(())
     }
 
@@ -1454,9 +3300,7 @@ impl Gateway {
             .await
             .get(&federation_id)
             .cloned()
-            .ok_or(GatewayError::InvalidMetadata(format!(
-                "No federation with id {federation_id}"
-            )))
+            .ok_or(GatewayError::FederationNotConnected(federation_id))
     }
 
     /// Reads the connected federation client configs from the Gateway's
@@ -1499,9 +3343,10 @@ impl Gateway {
 
     /// Legacy mechanism for registering the Gateway with connected federations.
     /// This will spawn a task that will re-register the Gateway with
-    /// connected federations every 8.5 mins. Only registers the Gateway if it
-    /// has successfully connected to the Lightning node, so that it can
-    /// include route hints in the registration.
+    /// connected federations roughly every 8.5 mins, randomly shifted to spread
+    /// the load on the federations. Only registers the Gateway if it has
+    /// successfully connected to the Lightning node, so that it can include
+    /// route hints in the registration.
     async fn register_clients_timer(&mut self, task_group: &mut TaskGroup) {
         let gateway = self.clone();
         task_group.spawn_cancellable("register clients", async move {
@@ -1527,19 +3372,53 @@ impl Gateway {
                 }
 
                 let registration_delay: Duration = if let Some(Err(GatewayError::FederationError(_))) = registration_result {
-                    // Retry to register gateway with federations in 10 seconds since it failed
-                    Duration::from_secs(10)
+                    // Retry to register gateway with federations soon since it failed
+                    GW_REGISTRATION_RETRY_DELAY
                 } else {
-                    // Allow a 15% buffer of the TTL before the re-registering gateway
-                    // with the federations.
-                    GW_ANNOUNCEMENT_TTL.mul_f32(0.85)
+                    // Leave a buffer of the TTL before re-registering the gateway
+                    // with the federations, so the announcement never lapses.
+                    GW_ANNOUNCEMENT_TTL.mul_f32(GW_REGISTRATION_REFRESH_FRACTION)
                 };
 
-                sleep(registration_delay).await;
+                sleep(jittered(registration_delay)).await;
+            }
+        });
+    }
+
+    /// Spawns a task that periodically warns about connected federations whose
+    /// liquidity falls below the configured thresholds.
+    fn liquidity_check_timer(&self, task_group: &mut TaskGroup) {
+        if !self.liquidity_thresholds.is_enabled() {
+            return;
+        }
+
+        let gateway = self.clone();
+        task_group.spawn_cancellable("check liquidity", async move {
+            loop {
+                if let Err(e) = gateway.check_liquidity().await {
+                    debug!("Skipping liquidity check: {e}");
+                }
+                sleep(LIQUIDITY_CHECK_INTERVAL).await;
             }
         });
     }
 
+    async fn check_liquidity(&self) -> Result<()> {
+        let context = self.get_lightning_context().await?;
+        let channels = context.lnrpc.list_active_channels().await?;
+
+        let clients = self.clients.read().await.clone();
+        for (federation_id, client) in clients {
+            let ecash_balance = client.value().get_balance().await;
+            self.liquidity_thresholds.check(&FederationLiquidity::new(
+                federation_id,
+                &channels,
+                ecash_balance,
+            ));
+        }
+        Ok(())
+    }
+
     /// Retrieve route hints from the Lightning node, capped at
     /// `num_route_hints`. The route hints should be ordered based on liquidity
     /// of incoming channels.
@@ -1597,6 +3476,9 @@ impl Gateway {
             config,
             channel_id,
             routing_fees,
+            limits: self.federation_limits(federation_id).await,
+            sweep_policy: self.sweep_policy(federation_id).await,
+            connection: self.federation_connection_state(federation_id).await,
         }
     }
 
@@ -1719,6 +3601,10 @@ impl Gateway {
         &self,
         payload: SendPaymentPayload,
     ) -> anyhow::Result<std::result::Result<[u8; 32], Signature>> {
+        if self.is_draining() {
+            return Err(GatewayError::Draining.into());
+        }
+
         let clients = self.clients.read().await;
 
         let client = clients
@@ -1726,10 +3612,35 @@ impl Gateway {
             .ok_or(anyhow!("Federation client not available"))?
             .value();
 
-        client
+        let federation_id = payload.federation_id;
+        let payment_hash = payload.contract.payment_hash;
+        let amount = payload
+            .invoice
+            .amount_milli_satoshis()
+            .map_or(Amount::ZERO, Amount::from_msats);
+        let fee = payload.contract.amount.saturating_sub(amount);
+
+        let ecash_balance = client.get_balance().await + payload.contract.amount;
+        self.start_outgoing_payment(federation_id, payment_hash, amount, fee, ecash_balance)
+            .await?;
+
+        let result = client
             .get_first_module::<GatewayClientModuleV2>()
             .send_payment(payload)
-            .await
+            .await;
+
+        let (status, error) = match &result {
+            Ok(Ok(_)) => (PaymentStatus::Succeeded, None),
+            Ok(Err(_)) => (
+                PaymentStatus::Failed,
+                Some("Lightning payment failed".to_string()),
+            ),
+            Err(e) => (PaymentStatus::Failed, Some(e.to_string())),
+        };
+        self.update_payment_status(payment_hash, PaymentDirection::Outgoing, status, error)
+            .await;
+
+        result
     }
 
     /// For the LNv2 protocol, this will create an invoice by fetching it from
@@ -1740,6 +3651,10 @@ impl Gateway {
         &self,
         payload: CreateInvoicePayload,
     ) -> anyhow::Result<Bolt11Invoice> {
+        if self.is_draining() {
+            return Err(GatewayError::Draining.into());
+        }
+
         if !payload.contract.verify() {
             bail!("The contract is invalid")
         }
@@ -1866,6 +3781,178 @@ impl Gateway {
     }
 }
 
+// LNURL-pay implementation
+impl Gateway {
+    /// Registers `username` as a lightning address paying into the federation
+    /// account, or removes the lightning address if `account` is `None`
+    pub async fn handle_set_lnurl_account_msg(
+        &self,
+        SetLnurlAccountPayload { username, account }: SetLnurlAccountPayload,
+    ) -> Result<Option<LnurlAccount>> {
+        validate_lnurl_username(&username).map_err(GatewayError::InvalidLnurlAccount)?;
+
+        let key = LnurlAccountKey {
+            username: username.clone(),
+        };
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        match &account {
+            Some(account) => {
+                account
+                    .validate()
+                    .map_err(GatewayError::InvalidLnurlAccount)?;
+                self.select_client(account.federation_id).await?;
+                dbtx.insert_entry(&key, account).await;
+            }
+            None => {
+                dbtx.remove_entry(&key).await;
+            }
+        }
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        info!("Set lightning address {username} to {account:?}");
+        Ok(account)
+    }
+
+    /// Returns the lightning addresses hosted by the gateway by username
+    pub async fn handle_list_lnurl_accounts_msg(&self) -> Result<BTreeMap<String, LnurlAccount>> {
+        Ok(self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&LnurlAccountKeyPrefix)
+            .await
+            .map(|(key, account)| (key.username, account))
+            .collect()
+            .await)
+    }
+
+    async fn lnurl_account(&self, username: &str) -> anyhow::Result<LnurlAccount> {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&LnurlAccountKey {
+                username: username.to_string(),
+            })
+            .await
+            .ok_or(anyhow!("Unknown lightning address"))
+    }
+
+    /// Answers a payer resolving the lightning address of `username`
+    pub async fn lnurl_pay_request(&self, username: String) -> anyhow::Result<LnurlPayResponse> {
+        let account = self.lnurl_account(&username).await?;
+
+        Ok(LnurlPayResponse {
+            callback: self
+                .versioned_api
+                .join(&format!("lnurlp/{username}/callback"))?,
+            min_sendable: account.min_sendable.msats,
+            max_sendable: account.max_sendable.msats,
+            metadata: account.metadata(),
+            tag: "payRequest".to_string(),
+        })
+    }
+
+    /// Issues an invoice paying `amount` to the lightning address of
+    /// `username`. The invoice is backed by an LNv2 incoming contract that
+    /// the gateway funds once the invoice is paid and that only the user of
+    /// the lightning address can claim.
+    pub async fn lnurl_invoice(
+        &self,
+        username: String,
+        amount: Amount,
+    ) -> anyhow::Result<LnurlInvoiceResponse> {
+        let account = self.lnurl_account(&username).await?;
+
+        if amount < account.min_sendable || account.max_sendable < amount {
+            bail!(
+                "The amount must be between {} and {}",
+                account.min_sendable,
+                account.max_sendable
+            );
+        }
+
+        let tpe_agg_pk = self
+            .select_client(account.federation_id)
+            .await?
+            .value()
+            .get_first_module::<GatewayClientModuleV2>()
+            .cfg
+            .tpe_agg_pk;
+
+        let payment_info = self
+            .payment_info_v2(&account.federation_id)
+            .await
+            .ok_or(anyhow!("Payment Info not available"))?;
+
+        let (contract, _) = create_incoming_contract(
+            tpe_agg_pk,
+            account.recipient_pk,
+            payment_info.public_key,
+            payment_info.receive_fee.subtract_fee(amount.msats),
+            duration_since_epoch().as_secs() + u64::from(LNURL_INVOICE_EXPIRY_SECS),
+        );
+
+        let invoice = self
+            .create_invoice_v2(CreateInvoicePayload {
+                federation_id: account.federation_id,
+                contract: contract.clone(),
+                invoice_amount: amount,
+                description: Bolt11InvoiceDescription::Hash(sha256::Hash::hash(
+                    account.metadata().as_bytes(),
+                )),
+                expiry_time: LNURL_INVOICE_EXPIRY_SECS,
+            })
+            .await?;
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(
+            &LnurlContractKey {
+                username,
+                payment_hash: contract.commitment.payment_hash,
+            },
+            &contract,
+        )
+        .await;
+        dbtx.commit_tx_result().await?;
+
+        Ok(LnurlInvoiceResponse {
+            pr: invoice,
+            routes: vec![],
+        })
+    }
+
+    /// Returns the incoming contracts of the invoices issued for the lightning
+    /// address of `username`, which its user claims with
+    /// `receive_external_contract`. Contracts past their retention are
+    /// removed.
+    pub async fn lnurl_contracts(&self, username: String) -> anyhow::Result<Vec<IncomingContract>> {
+        let retained_since = duration_since_epoch()
+            .saturating_sub(LNURL_CONTRACT_RETENTION)
+            .as_secs();
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        let contracts = dbtx
+            .find_by_prefix(&LnurlContractUsernamePrefix { username })
+            .await
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut retained = vec![];
+        for (key, contract) in contracts {
+            if contract.commitment.expiration < retained_since {
+                dbtx.remove_entry(&key).await;
+            } else {
+                retained.push(contract);
+            }
+        }
+        dbtx.commit_tx_result().await?;
+
+        Ok(retained)
+    }
+}
+
 /// Errors that can occur while processing incoming HTLC's, making outgoing
 /// payments, registering with connected federations, or responding to webserver
 /// requests.
@@ -1897,6 +3984,20 @@ pub enum GatewayError {
     FederationAlreadyConnected,
     #[error("Error parsing response: {}", OptStacktrace(.0))]
     LightningResponseParseError(anyhow::Error),
+    #[error("Federation limit exceeded: {0}")]
+    FederationLimitExceeded(String),
+    #[error("Federation {0} is not connected")]
+    FederationNotConnected(FederationId),
+    #[error("The gateway is draining")]
+    Draining,
+    #[error("The gateway is not drained: {0}")]
+    NotDrained(String),
+    #[error("Invalid sweep policy: {0}")]
+    InvalidSweepPolicy(String),
+    #[error("Invalid lightning address: {0}")]
+    InvalidLnurlAccount(String),
+    #[error("Invalid HTLC filter: {0}")]
+    InvalidHtlcFilter(String),
 }
 
 impl IntoResponse for GatewayError {
@@ -1914,6 +4015,37 @@ impl IntoResponse for GatewayError {
                 "The gateway is disconnected from the Lightning Node".to_string(),
                 StatusCode::NOT_FOUND,
             ),
+            GatewayError::FederationLimitExceeded(_) => (
+                "The payment exceeds the gateway's limits for this federation".to_string(),
+                StatusCode::BAD_REQUEST,
+            ),
+            GatewayError::FederationNotConnected(_) => (
+                "The gateway is not connected to this federation".to_string(),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            GatewayError::Draining => (
+                "The gateway is shutting down".to_string(),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            GatewayError::NotDrained(e) => (
+                format!("The gateway must be drained before it shuts down: {e}"),
+                StatusCode::CONFLICT,
+            ),
+            GatewayError::InvalidSweepPolicy(e) => (
+                format!("Invalid sweep policy: {e}"),
+                StatusCode::BAD_REQUEST,
+            ),
+            GatewayError::InvalidLnurlAccount(e) => (
+                format!("Invalid lightning address: {e}"),
+                StatusCode::BAD_REQUEST,
+            ),
+            GatewayError::InvalidHtlcFilter(e) => {
+                (format!("Invalid HTLC filter: {e}"), StatusCode::BAD_REQUEST)
+            }
+            GatewayError::LightningRpcError(_) => (
+                "The Lightning Node failed to process the request".to_string(),
+                StatusCode::BAD_GATEWAY,
+            ),
             _ => (
                 "An internal gateway error occurred".to_string(),
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -1925,6 +4057,22 @@ impl IntoResponse for GatewayError {
     }
 }
 
+/// Error of a peg-out, tells whether the withdrawal might still be broadcast
+enum PegOutError {
+    /// The federation never accepted the withdrawal transaction
+    NotSubmitted(GatewayError),
+    /// The federation might have accepted and broadcast the withdrawal
+    Unknown(GatewayError),
+}
+
+impl From<PegOutError> for GatewayError {
+    fn from(error: PegOutError) -> Self {
+        match error {
+            PegOutError::NotSubmitted(e) | PegOutError::Unknown(e) => e,
+        }
+    }
+}
+
 /// Utility struct for formatting an intercepted HTLC. Useful for debugging.
 struct PrettyInterceptHtlcRequest<'a>(&'a crate::gateway_lnrpc::InterceptHtlcRequest);
 
@@ -15,10 +15,11 @@ use super::{ChannelInfo, ILnRpcClient, LightningRpcError};
 use crate::gateway_lnrpc::gateway_lightning_client::GatewayLightningClient;
 use crate::gateway_lnrpc::{
     CloseChannelsWithPeerRequest, CloseChannelsWithPeerResponse, ConnectToPeerRequest,
-    CreateInvoiceRequest, CreateInvoiceResponse, EmptyRequest, EmptyResponse,
-    GetFundingAddressResponse, GetNodeInfoResponse, GetRouteHintsRequest, GetRouteHintsResponse,
-    InterceptHtlcRequest, InterceptHtlcResponse, OpenChannelRequest, PayInvoiceRequest,
-    PayInvoiceResponse,
+    CreateInvoiceRequest, CreateInvoiceResponse, CreateOfferRequest, EmptyRequest, EmptyResponse,
+    GetFundingAddressResponse, GetNodeInfoResponse, GetOfferRequest, GetOnchainBalanceResponse,
+    GetRouteHintsRequest, GetRouteHintsResponse, InterceptHtlcRequest, InterceptHtlcResponse,
+    OfferResponse, OpenChannelRequest, PayInvoiceRequest, PayInvoiceResponse, SendOnchainRequest,
+    SendOnchainResponse,
 };
 use crate::lightning::MAX_LIGHTNING_RETRIES;
 pub type HtlcResult = std::result::Result<InterceptHtlcRequest, tonic::Status>;
@@ -102,13 +103,19 @@ impl ILnRpcClient for NetworkLnRpcClient {
     ) -> Result<PayInvoiceResponse, LightningRpcError> {
         let req = Request::new(invoice);
         let mut client = self.connect().await?;
-        let res =
-            client
-                .pay_invoice(req)
-                .await
-                .map_err(|status| LightningRpcError::FailedPayment {
+        let res = client.pay_invoice(req).await.map_err(|status| {
+            // The extension reports errors of CLN's pay command as internal,
+            // any other status means we lost track of the request
+            if status.code() == tonic::Code::Internal {
+                LightningRpcError::FailedPayment {
                     failure_reason: status.message().to_string(),
-                })?;
+                }
+            } else {
+                LightningRpcError::PaymentStateUnknown {
+                    failure_reason: status.message().to_string(),
+                }
+            }
+        })?;
         Ok(res.into_inner())
     }
 
@@ -242,4 +249,59 @@ impl ILnRpcClient for NetworkLnRpcClient {
             })
             .collect())
     }
+
+    async fn get_onchain_balance(&self) -> Result<GetOnchainBalanceResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let res = client
+            .get_onchain_balance(EmptyRequest {})
+            .await
+            .map_err(|status| LightningRpcError::FailedToGetOnchainBalance {
+                failure_reason: status.message().to_string(),
+            })?;
+        Ok(res.into_inner())
+    }
+
+    async fn send_onchain(
+        &self,
+        request: SendOnchainRequest,
+    ) -> Result<SendOnchainResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let res = client.send_onchain(request).await.map_err(|status| {
+            LightningRpcError::FailedToSendOnchain {
+                failure_reason: status.message().to_string(),
+            }
+        })?;
+        Ok(res.into_inner())
+    }
+
+    async fn create_offer(
+        &self,
+        request: CreateOfferRequest,
+    ) -> Result<OfferResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let res = client.create_offer(request).await.map_err(|status| {
+            LightningRpcError::FailedToCreateOffer {
+                failure_reason: status.message().to_string(),
+            }
+        })?;
+        Ok(res.into_inner())
+    }
+
+    async fn get_offer(
+        &self,
+        offer_id: String,
+    ) -> Result<Option<OfferResponse>, LightningRpcError> {
+        let mut client = self.connect().await?;
+        match client.get_offer(GetOfferRequest { offer_id }).await {
+            Ok(res) => Ok(Some(res.into_inner())),
+            Err(status) if status.code() == tonic::Code::NotFound => Ok(None),
+            Err(status) => Err(LightningRpcError::FailedToGetOffer {
+                failure_reason: status.message().to_string(),
+            }),
+        }
+    }
+
+    fn supports_offers(&self) -> bool {
+        true
+    }
 }
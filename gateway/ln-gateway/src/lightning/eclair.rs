@@ -0,0 +1,539 @@
+use std::fmt;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use base64::Engine as _;
+use fedimint_core::secp256k1;
+use fedimint_core::task::TaskGroup;
+use fedimint_core::util::SafeUrl;
+use futures::StreamExt;
+use secp256k1::PublicKey;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tracing::{debug, info, warn};
+
+use super::cln::{HtlcResult, RouteHtlcStream};
+use super::{verify_preimage, ChannelInfo, ILnRpcClient, LightningRpcError};
+use crate::gateway_lnrpc::{
+    CloseChannelsWithPeerResponse, CreateInvoiceRequest, CreateInvoiceResponse, EmptyResponse,
+    GetFundingAddressResponse, GetNodeInfoResponse, GetOnchainBalanceResponse,
+    GetRouteHintsResponse, InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
+    SendOnchainRequest, SendOnchainResponse,
+};
+
+/// An `ILnRpcClient` that talks to an Eclair node via its HTTP API.
+///
+/// Eclair does not expose HTLC interception over its API, so the gateway can
+/// only pay invoices with it. The event websocket is only used to notice when
+/// the node becomes unreachable, which ends the HTLC stream and makes the
+/// gateway reconnect.
+#[derive(Clone)]
+pub struct GatewayEclairClient {
+    api_url: SafeUrl,
+    password: String,
+    client: reqwest::Client,
+}
+
+impl GatewayEclairClient {
+    pub fn new(api_url: SafeUrl, password: String) -> Self {
+        info!(
+            "Gateway configured to connect to Eclair at \n api url: {}",
+            api_url
+        );
+        GatewayEclairClient {
+            api_url,
+            password,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Calls the API `method` with form encoded `params`, which is how Eclair
+    /// expects them
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &[(&str, String)],
+    ) -> Result<T, String> {
+        self.try_call(method, params)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Like [`GatewayEclairClient::call`], but tells apart calls Eclair
+    /// rejected from calls it might have executed
+    async fn try_call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &[(&str, String)],
+    ) -> Result<T, EclairCallError> {
+        let url = self
+            .api_url
+            .join(method)
+            .map_err(|e| EclairCallError::Rejected(e.to_string()))?;
+
+        let response = self
+            .client
+            .post(url.to_unsafe())
+            .basic_auth("", Some(&self.password))
+            .form(params)
+            .send()
+            .await
+            .map_err(|e| EclairCallError::Transport(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error = response.text().await.unwrap_or_default();
+            let error = format!("Eclair returned {status}: {error}");
+
+            return Err(if status.is_client_error() {
+                EclairCallError::Rejected(error)
+            } else {
+                EclairCallError::Transport(error)
+            });
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| EclairCallError::Transport(e.to_string()))
+    }
+}
+
+/// Error of a call to the Eclair API
+#[derive(Debug)]
+enum EclairCallError {
+    /// Eclair refused the request, so it was not executed
+    Rejected(String),
+    /// The request or its response got lost or could not be read, Eclair might
+    /// have executed it
+    Transport(String),
+}
+
+impl fmt::Display for EclairCallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EclairCallError::Rejected(error) | EclairCallError::Transport(error) => {
+                f.write_str(error)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for GatewayEclairClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EclairClient")
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EclairInfo {
+    node_id: PublicKey,
+    alias: String,
+    network: String,
+    block_height: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum EclairPaymentEvent {
+    #[serde(rename_all = "camelCase")]
+    PaymentSent {
+        payment_preimage: String,
+    },
+    PaymentFailed {
+        failures: serde_json::Value,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EclairChannel {
+    node_id: PublicKey,
+    channel_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EclairUsableBalance {
+    remote_node_id: PublicKey,
+    short_ids: EclairShortIds,
+    can_send: u64,
+    can_receive: u64,
+}
+
+/// Balances in sats
+#[derive(Deserialize)]
+struct EclairOnchainBalance {
+    confirmed: u64,
+    unconfirmed: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EclairShortIds {
+    /// Absent until the funding transaction is confirmed
+    real: Option<EclairRealShortId>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EclairRealShortId {
+    /// Formatted as `<block>x<tx index>x<output index>`
+    real_scid: Option<String>,
+}
+
+/// Converts a short channel id in Eclair's `<block>x<tx>x<output>` format to
+/// its integer representation
+fn parse_short_channel_id(scid: &str) -> Option<u64> {
+    let mut parts = scid.split('x').map(str::parse::<u64>);
+    let block = parts.next()?.ok()?;
+    let tx_index = parts.next()?.ok()?;
+    let output_index = parts.next()?.ok()?;
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some((block << 40) | (tx_index << 16) | output_index)
+}
+
+#[async_trait]
+impl ILnRpcClient for GatewayEclairClient {
+    async fn info(&self) -> Result<GetNodeInfoResponse, LightningRpcError> {
+        let info: EclairInfo = self.call("getinfo", &[]).await.map_err(|e| {
+            LightningRpcError::FailedToGetNodeInfo {
+                failure_reason: format!("Failed to get node info {e}"),
+            }
+        })?;
+
+        Ok(GetNodeInfoResponse {
+            pub_key: info.node_id.serialize().to_vec(),
+            alias: info.alias,
+            network: info.network,
+            block_height: info.block_height,
+            // Eclair only serves its API once it is synced to the chain
+            synced_to_chain: true,
+        })
+    }
+
+    /// Route hints are only needed to intercept LNv1 payments, which Eclair
+    /// does not support
+    async fn routehints(
+        &self,
+        _num_route_hints: usize,
+    ) -> Result<GetRouteHintsResponse, LightningRpcError> {
+        Ok(GetRouteHintsResponse {
+            route_hints: vec![],
+        })
+    }
+
+    async fn pay(
+        &self,
+        invoice: PayInvoiceRequest,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        let event: EclairPaymentEvent = self
+            .try_call(
+                "payinvoice",
+                &[
+                    ("invoice", invoice.invoice),
+                    ("maxFeeFlatSat", (invoice.max_fee_msat / 1000).to_string()),
+                    ("blocking", "true".to_string()),
+                ],
+            )
+            .await
+            .map_err(|e| match e {
+                EclairCallError::Rejected(failure_reason) => {
+                    LightningRpcError::FailedPayment { failure_reason }
+                }
+                // The payment might be in flight, so it must not be retried
+                EclairCallError::Transport(failure_reason) => {
+                    LightningRpcError::PaymentStateUnknown { failure_reason }
+                }
+            })?;
+
+        match event {
+            EclairPaymentEvent::PaymentSent { payment_preimage } => {
+                let preimage = hex::decode(payment_preimage).map_err(|e| {
+                    LightningRpcError::FailedPayment {
+                        failure_reason: format!("Invalid preimage {e:?}"),
+                    }
+                })?;
+
+                verify_preimage(&preimage, &invoice.payment_hash)?;
+
+                Ok(PayInvoiceResponse { preimage })
+            }
+            EclairPaymentEvent::PaymentFailed { failures } => {
+                Err(LightningRpcError::FailedPayment {
+                    failure_reason: failures.to_string(),
+                })
+            }
+        }
+    }
+
+    async fn route_htlcs<'a>(
+        self: Box<Self>,
+        task_group: &mut TaskGroup,
+    ) -> Result<(RouteHtlcStream<'a>, Arc<dyn ILnRpcClient>), LightningRpcError> {
+        let mut ws_url = self.api_url.clone().to_unsafe();
+        let scheme = if ws_url.scheme() == "https" {
+            "wss"
+        } else {
+            "ws"
+        };
+        ws_url
+            .set_scheme(scheme)
+            .map_err(|()| LightningRpcError::FailedToRouteHtlcs {
+                failure_reason: "Invalid Eclair api url".to_string(),
+            })?;
+        ws_url.set_path("ws");
+
+        let mut request = ws_url.as_str().into_client_request().map_err(|e| {
+            LightningRpcError::FailedToRouteHtlcs {
+                failure_reason: e.to_string(),
+            }
+        })?;
+        let credentials =
+            base64::engine::general_purpose::STANDARD.encode(format!(":{}", self.password));
+        request.headers_mut().insert(
+            "Authorization",
+            HeaderValue::from_str(&format!("Basic {credentials}"))
+                .expect("Base64 is a valid header value"),
+        );
+
+        let (mut events, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .map_err(|e| LightningRpcError::FailedToRouteHtlcs {
+                failure_reason: format!("Failed to connect to Eclair websocket {e:?}"),
+            })?;
+
+        // Eclair never sends intercepted HTLCs, the stream ends once the
+        // websocket closes
+        let (gateway_sender, gateway_receiver) = mpsc::channel::<HtlcResult>(1);
+
+        task_group.spawn("Eclair event websocket", |handle| async move {
+            let _gateway_sender = gateway_sender;
+
+            while let Ok(Some(event)) = handle.cancel_on_shutdown(events.next()).await {
+                match event {
+                    Ok(event) => debug!("Eclair event: {event}"),
+                    Err(e) => {
+                        warn!("Eclair websocket failed: {e:?}");
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok((
+            Box::pin(ReceiverStream::new(gateway_receiver)),
+            Arc::new(*self),
+        ))
+    }
+
+    async fn complete_htlc(
+        &self,
+        _htlc: InterceptHtlcResponse,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToCompleteHtlc {
+            failure_reason: "Eclair does not support HTLC interception".to_string(),
+        })
+    }
+
+    async fn create_invoice(
+        &self,
+        _create_invoice_request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        // The gateway needs invoices for a payment hash it does not know the
+        // preimage of, but Eclair can only create invoices from preimages
+        Err(LightningRpcError::FailedToGetInvoice {
+            failure_reason: "Eclair does not support invoices for a given payment hash".to_string(),
+        })
+    }
+
+    async fn connect_to_peer(
+        &self,
+        pubkey: PublicKey,
+        host: String,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        self.call::<serde_json::Value>("connect", &[("uri", format!("{pubkey}@{host}"))])
+            .await
+            .map_err(|e| LightningRpcError::FailedToConnectToPeer {
+                failure_reason: format!("Failed to connect to peer {e}"),
+            })?;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn get_funding_address(&self) -> Result<GetFundingAddressResponse, LightningRpcError> {
+        let address: String = self
+            .call("getnewaddress", &[])
+            .await
+            .map_err(|e| LightningRpcError::FailedToGetFundingAddress { failure_reason: e })?;
+
+        Ok(GetFundingAddressResponse { address })
+    }
+
+    async fn open_channel(
+        &self,
+        pubkey: PublicKey,
+        channel_size_sats: u64,
+        push_amount_sats: u64,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        self.call::<serde_json::Value>(
+            "open",
+            &[
+                ("nodeId", pubkey.to_string()),
+                ("fundingSatoshis", channel_size_sats.to_string()),
+                ("pushMsat", (push_amount_sats * 1000).to_string()),
+            ],
+        )
+        .await
+        .map_err(|e| LightningRpcError::FailedToOpenChannel { failure_reason: e })?;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn close_channels_with_peer(
+        &self,
+        pubkey: PublicKey,
+    ) -> Result<CloseChannelsWithPeerResponse, LightningRpcError> {
+        let channels: Vec<EclairChannel> = self
+            .call("channels", &[("nodeId", pubkey.to_string())])
+            .await
+            .map_err(|e| LightningRpcError::FailedToCloseChannelsWithPeer { failure_reason: e })?;
+
+        let channel_ids = channels
+            .into_iter()
+            .filter(|channel| channel.node_id == pubkey)
+            .map(|channel| channel.channel_id)
+            .collect::<Vec<_>>();
+
+        if channel_ids.is_empty() {
+            return Ok(CloseChannelsWithPeerResponse {
+                num_channels_closed: 0,
+            });
+        }
+
+        self.call::<serde_json::Value>("close", &[("channelIds", channel_ids.join(","))])
+            .await
+            .map_err(|e| LightningRpcError::FailedToCloseChannelsWithPeer { failure_reason: e })?;
+
+        Ok(CloseChannelsWithPeerResponse {
+            num_channels_closed: channel_ids.len() as u32,
+        })
+    }
+
+    async fn list_active_channels(&self) -> Result<Vec<ChannelInfo>, LightningRpcError> {
+        let balances: Vec<EclairUsableBalance> = self
+            .call("usablebalances", &[])
+            .await
+            .map_err(|e| LightningRpcError::FailedToListActiveChannels { failure_reason: e })?;
+
+        Ok(balances
+            .into_iter()
+            .map(|balance| {
+                let short_channel_id = balance
+                    .short_ids
+                    .real
+                    .and_then(|real| real.real_scid)
+                    .and_then(|scid| parse_short_channel_id(&scid))
+                    .unwrap_or_default();
+
+                ChannelInfo {
+                    remote_pubkey: balance.remote_node_id.to_string(),
+                    // Eclair only reports the usable balances, which exclude
+                    // the channel reserves
+                    channel_size_sats: (balance.can_send + balance.can_receive) / 1000,
+                    outbound_liquidity_sats: balance.can_send / 1000,
+                    inbound_liquidity_sats: balance.can_receive / 1000,
+                    short_channel_id,
+                }
+            })
+            .collect())
+    }
+
+    async fn get_onchain_balance(&self) -> Result<GetOnchainBalanceResponse, LightningRpcError> {
+        let balance: EclairOnchainBalance = self
+            .call("onchainbalance", &[])
+            .await
+            .map_err(|e| LightningRpcError::FailedToGetOnchainBalance { failure_reason: e })?;
+
+        Ok(GetOnchainBalanceResponse {
+            confirmed_balance_sats: balance.confirmed,
+            unconfirmed_balance_sats: balance.unconfirmed,
+        })
+    }
+
+    async fn send_onchain(
+        &self,
+        request: SendOnchainRequest,
+    ) -> Result<SendOnchainResponse, LightningRpcError> {
+        let txid: String = self
+            .call(
+                "sendonchain",
+                &[
+                    ("address", request.address),
+                    ("amountSatoshis", request.amount_sats.to_string()),
+                    (
+                        "feeRatePerByte",
+                        request.fee_rate_sats_per_vbyte.to_string(),
+                    ),
+                ],
+            )
+            .await
+            .map_err(|e| LightningRpcError::FailedToSendOnchain { failure_reason: e })?;
+
+        Ok(SendOnchainResponse { txid })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::util::SafeUrl;
+
+    use super::{parse_short_channel_id, GatewayEclairClient};
+    use crate::gateway_lnrpc::PayInvoiceRequest;
+    use crate::lightning::{ILnRpcClient, LightningRpcError};
+    use crate::state_machine::pay::is_retryable;
+
+    #[test]
+    fn parses_eclair_short_channel_ids() {
+        assert_eq!(
+            parse_short_channel_id("539268x845x1"),
+            Some((539268 << 40) | (845 << 16) | 1)
+        );
+        assert_eq!(parse_short_channel_id("539268x845"), None);
+        assert_eq!(parse_short_channel_id("539268x845x1x0"), None);
+        assert_eq!(parse_short_channel_id("invalid"), None);
+    }
+
+    #[tokio::test]
+    async fn transport_error_is_not_retried() {
+        // Nothing listens on the port, so the request never gets an answer
+        let client = GatewayEclairClient::new(
+            SafeUrl::parse("http://127.0.0.1:1").expect("Valid url"),
+            "password".to_string(),
+        );
+
+        let result = client
+            .pay(PayInvoiceRequest {
+                invoice: "lnbcrt1".to_string(),
+                max_delay: 144,
+                max_fee_msat: 1_000,
+                payment_hash: vec![0; 32],
+            })
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(LightningRpcError::PaymentStateUnknown { .. })
+        ));
+        assert!(!is_retryable(&result));
+    }
+}
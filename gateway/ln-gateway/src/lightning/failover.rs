@@ -0,0 +1,347 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use bitcoin_hashes::Hash;
+use fedimint_core::task::TaskGroup;
+use fedimint_core::time::now;
+use fedimint_core::{secp256k1, Amount};
+use fedimint_ln_common::PrunedInvoice;
+use secp256k1::PublicKey;
+use tracing::warn;
+
+use super::cln::RouteHtlcStream;
+use super::{ChannelInfo, ILnRpcClient, LightningBackendHealth, LightningRpcError};
+use crate::gateway_lnrpc::{
+    CloseChannelsWithPeerResponse, CreateInvoiceRequest, CreateInvoiceResponse, CreateOfferRequest,
+    EmptyResponse, GetFundingAddressResponse, GetNodeInfoResponse, GetOnchainBalanceResponse,
+    GetRouteHintsResponse, InterceptHtlcResponse, OfferResponse, PayInvoiceRequest,
+    PayInvoiceResponse, SendOnchainRequest, SendOnchainResponse,
+};
+
+/// The backend that intercepts HTLCs, which can only be shared once
+/// `route_htlcs` has been called
+#[derive(Debug)]
+enum PrimaryBackend {
+    Unrouted(Box<dyn ILnRpcClient>),
+    Routed(Arc<dyn ILnRpcClient>),
+}
+
+impl PrimaryBackend {
+    fn client(&self) -> &dyn ILnRpcClient {
+        match self {
+            PrimaryBackend::Unrouted(client) => client.as_ref(),
+            PrimaryBackend::Routed(client) => client.as_ref(),
+        }
+    }
+}
+
+/// An `ILnRpcClient` that pays invoices through the highest priority healthy
+/// lightning backend, so the gateway keeps serving outgoing payments while
+/// its primary node is unreachable.
+///
+/// Invoices, offers, HTLC interception and channel management always use the primary
+/// backend since they are tied to the node the route hints point to.
+///
+/// The backend is selected by probing the health of each backend right before
+/// the first attempt to pay an invoice. All later attempts for the same
+/// payment hash go to that backend, since an earlier attempt might still
+/// succeed on it and another backend would pay the invoice twice.
+#[derive(Debug)]
+pub struct FailoverLnRpcClient {
+    primary: PrimaryBackend,
+    fallbacks: Vec<Arc<dyn ILnRpcClient>>,
+    /// Health of the primary followed by the fallbacks, in order of priority
+    health: Arc<Mutex<Vec<LightningBackendHealth>>>,
+    /// Priority of the backend each payment hash was first handed to and when
+    payment_backends: Arc<Mutex<BTreeMap<Vec<u8>, (usize, SystemTime)>>>,
+}
+
+/// How long the backend of a payment is remembered after its first attempt,
+/// which exceeds the time the gateway keeps retrying a payment
+const PAYMENT_BACKEND_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl FailoverLnRpcClient {
+    /// Creates a client from the primary backend and the fallbacks in order
+    /// of priority, each paired with the name of its kind
+    pub fn new(
+        primary: (&'static str, Box<dyn ILnRpcClient>),
+        fallbacks: Vec<(&'static str, Arc<dyn ILnRpcClient>)>,
+    ) -> Self {
+        let health = std::iter::once(primary.0)
+            .chain(fallbacks.iter().map(|(kind, _)| *kind))
+            .enumerate()
+            .map(|(priority, kind)| LightningBackendHealth {
+                priority,
+                kind: kind.to_string(),
+                healthy: true,
+                last_error: None,
+            })
+            .collect();
+
+        FailoverLnRpcClient {
+            primary: PrimaryBackend::Unrouted(primary.1),
+            fallbacks: fallbacks.into_iter().map(|(_, client)| client).collect(),
+            health: Arc::new(Mutex::new(health)),
+            payment_backends: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    fn backends(&self) -> impl Iterator<Item = (usize, &dyn ILnRpcClient)> {
+        std::iter::once(self.primary.client())
+            .chain(self.fallbacks.iter().map(|client| client.as_ref()))
+            .enumerate()
+    }
+
+    fn record_health(&self, priority: usize, error: Option<&LightningRpcError>) {
+        let mut health = self.health.lock().expect("Lock poisoned");
+        health[priority].healthy = error.is_none();
+        health[priority].last_error = error.map(ToString::to_string);
+    }
+
+    /// Returns the backend a previous attempt to pay `payment_hash` was handed
+    /// to, or else the highest priority backend that responds to an info
+    /// request and supports private payments if `private` is set
+    async fn payment_backend(
+        &self,
+        payment_hash: &[u8],
+        private: bool,
+    ) -> Result<&dyn ILnRpcClient, LightningRpcError> {
+        let pinned = self
+            .payment_backends
+            .lock()
+            .expect("Lock poisoned")
+            .get(payment_hash)
+            .map(|(priority, _)| *priority);
+
+        if let Some(priority) = pinned {
+            let (_, backend) = self
+                .backends()
+                .nth(priority)
+                .expect("Backends are never removed");
+
+            return Ok(backend);
+        }
+
+        let (priority, backend) = self.select_payment_backend(private).await?;
+
+        let mut payment_backends = self.payment_backends.lock().expect("Lock poisoned");
+
+        let attempted_at = now();
+        payment_backends.retain(|_, (_, first_attempt)| {
+            attempted_at
+                .duration_since(*first_attempt)
+                .unwrap_or_default()
+                < PAYMENT_BACKEND_RETENTION
+        });
+        payment_backends.insert(payment_hash.to_vec(), (priority, attempted_at));
+
+        Ok(backend)
+    }
+
+    /// Returns the highest priority backend that responds to an info request
+    /// and supports private payments if `private` is set
+    async fn select_payment_backend(
+        &self,
+        private: bool,
+    ) -> Result<(usize, &dyn ILnRpcClient), LightningRpcError> {
+        for (priority, backend) in self.backends() {
+            if backend.supports_private_payments() != private {
+                continue;
+            }
+
+            match backend.info().await {
+                Ok(_) => {
+                    self.record_health(priority, None);
+                    return Ok((priority, backend));
+                }
+                Err(error) => {
+                    warn!("Lightning backend {priority} is unhealthy: {error}");
+                    self.record_health(priority, Some(&error));
+                }
+            }
+        }
+
+        Err(LightningRpcError::FailedPayment {
+            failure_reason: "No healthy lightning backend available".to_string(),
+        })
+    }
+
+    /// Records the health of the primary backend from the `result` of a
+    /// request to it
+    fn on_primary<T>(&self, result: Result<T, LightningRpcError>) -> Result<T, LightningRpcError> {
+        self.record_health(0, result.as_ref().err());
+        result
+    }
+}
+
+#[async_trait]
+impl ILnRpcClient for FailoverLnRpcClient {
+    async fn info(&self) -> Result<GetNodeInfoResponse, LightningRpcError> {
+        self.on_primary(self.primary.client().info().await)
+    }
+
+    async fn routehints(
+        &self,
+        num_route_hints: usize,
+    ) -> Result<GetRouteHintsResponse, LightningRpcError> {
+        self.on_primary(self.primary.client().routehints(num_route_hints).await)
+    }
+
+    async fn pay(
+        &self,
+        invoice: PayInvoiceRequest,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        let payment_hash = invoice.payment_hash.clone();
+
+        self.payment_backend(&payment_hash, false)
+            .await?
+            .pay(invoice)
+            .await
+    }
+
+    async fn pay_private(
+        &self,
+        invoice: PrunedInvoice,
+        max_delay: u64,
+        max_fee: Amount,
+        max_parts: u32,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        self.payment_backend(&invoice.payment_hash.to_byte_array(), true)
+            .await?
+            .pay_private(invoice, max_delay, max_fee, max_parts)
+            .await
+    }
+
+    fn supports_private_payments(&self) -> bool {
+        self.primary.client().supports_private_payments()
+    }
+
+    async fn route_htlcs<'a>(
+        self: Box<Self>,
+        task_group: &mut TaskGroup,
+    ) -> Result<(RouteHtlcStream<'a>, Arc<dyn ILnRpcClient>), LightningRpcError> {
+        let PrimaryBackend::Unrouted(primary) = self.primary else {
+            return Err(LightningRpcError::FailedToRouteHtlcs {
+                failure_reason: "HTLCs are already routed".to_string(),
+            });
+        };
+
+        let (stream, primary) = primary.route_htlcs(task_group).await?;
+
+        Ok((
+            stream,
+            Arc::new(FailoverLnRpcClient {
+                primary: PrimaryBackend::Routed(primary),
+                fallbacks: self.fallbacks,
+                health: self.health,
+                payment_backends: self.payment_backends,
+            }),
+        ))
+    }
+
+    async fn complete_htlc(
+        &self,
+        htlc: InterceptHtlcResponse,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        self.on_primary(self.primary.client().complete_htlc(htlc).await)
+    }
+
+    async fn create_invoice(
+        &self,
+        create_invoice_request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        self.on_primary(
+            self.primary
+                .client()
+                .create_invoice(create_invoice_request)
+                .await,
+        )
+    }
+
+    async fn connect_to_peer(
+        &self,
+        pubkey: PublicKey,
+        host: String,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        self.on_primary(self.primary.client().connect_to_peer(pubkey, host).await)
+    }
+
+    async fn get_funding_address(&self) -> Result<GetFundingAddressResponse, LightningRpcError> {
+        self.on_primary(self.primary.client().get_funding_address().await)
+    }
+
+    async fn open_channel(
+        &self,
+        pubkey: PublicKey,
+        channel_size_sats: u64,
+        push_amount_sats: u64,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        self.on_primary(
+            self.primary
+                .client()
+                .open_channel(pubkey, channel_size_sats, push_amount_sats)
+                .await,
+        )
+    }
+
+    async fn close_channels_with_peer(
+        &self,
+        pubkey: PublicKey,
+    ) -> Result<CloseChannelsWithPeerResponse, LightningRpcError> {
+        self.on_primary(self.primary.client().close_channels_with_peer(pubkey).await)
+    }
+
+    async fn list_active_channels(&self) -> Result<Vec<ChannelInfo>, LightningRpcError> {
+        self.on_primary(self.primary.client().list_active_channels().await)
+    }
+
+    async fn get_onchain_balance(&self) -> Result<GetOnchainBalanceResponse, LightningRpcError> {
+        self.on_primary(self.primary.client().get_onchain_balance().await)
+    }
+
+    async fn send_onchain(
+        &self,
+        request: SendOnchainRequest,
+    ) -> Result<SendOnchainResponse, LightningRpcError> {
+        self.on_primary(self.primary.client().send_onchain(request).await)
+    }
+
+    async fn rebalance(
+        &self,
+        outgoing_short_channel_id: u64,
+        last_hop_pubkey: PublicKey,
+        amount: Amount,
+        max_fee: Amount,
+    ) -> Result<Amount, LightningRpcError> {
+        self.on_primary(
+            self.primary
+                .client()
+                .rebalance(outgoing_short_channel_id, last_hop_pubkey, amount, max_fee)
+                .await,
+        )
+    }
+
+    async fn create_offer(
+        &self,
+        request: CreateOfferRequest,
+    ) -> Result<OfferResponse, LightningRpcError> {
+        self.on_primary(self.primary.client().create_offer(request).await)
+    }
+
+    async fn get_offer(
+        &self,
+        offer_id: String,
+    ) -> Result<Option<OfferResponse>, LightningRpcError> {
+        self.on_primary(self.primary.client().get_offer(offer_id).await)
+    }
+
+    fn supports_offers(&self) -> bool {
+        self.primary.client().supports_offers()
+    }
+
+    fn backend_health(&self) -> Vec<LightningBackendHealth> {
+        self.health.lock().expect("Lock poisoned").clone()
+    }
+}
@@ -0,0 +1,483 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bitcoin::Network;
+use fedimint_core::task::{sleep, TaskGroup};
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{secp256k1, Amount};
+use ldk_node::lightning::ln::msgs::SocketAddress;
+use ldk_node::lightning::ln::{PaymentHash, PaymentPreimage};
+use ldk_node::lightning_invoice::Bolt11Invoice;
+use ldk_node::payment::{PaymentKind, PaymentStatus};
+use ldk_node::{Event, Node};
+use secp256k1::PublicKey;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{error, info, warn};
+
+use super::cln::{HtlcResult, RouteHtlcStream};
+use super::{ChannelInfo, ILnRpcClient, LightningRpcError};
+use crate::gateway_lnrpc::create_invoice_request::Description;
+use crate::gateway_lnrpc::intercept_htlc_response::Action;
+use crate::gateway_lnrpc::{
+    CloseChannelsWithPeerResponse, CreateInvoiceRequest, CreateInvoiceResponse, EmptyResponse,
+    GetFundingAddressResponse, GetNodeInfoResponse, GetOnchainBalanceResponse,
+    GetRouteHintsResponse, InterceptHtlcRequest, InterceptHtlcResponse, PayInvoiceRequest,
+    PayInvoiceResponse, SendOnchainRequest, SendOnchainResponse,
+};
+
+/// How often the status of an outgoing payment is polled
+const PAYMENT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A payment to an invoice of the node that is waiting to be claimed or
+/// failed by the gateway
+#[derive(Debug, Clone, Copy)]
+struct ClaimablePayment {
+    payment_hash: PaymentHash,
+    amount_msat: u64,
+}
+
+/// An `ILnRpcClient` that runs an embedded lightning node with ldk-node, so the
+/// gateway does not depend on an external lightning node.
+///
+/// The node can not intercept HTLCs to the fake short channel ids of LNv1
+/// route hints, so it only receives payments to invoices it created itself.
+/// These are held until the gateway completes them with `complete_htlc`.
+pub struct GatewayLdkClient {
+    node: Arc<Node>,
+    network: Network,
+    /// Payments awaiting `complete_htlc`, keyed by the `htlc_id` they were
+    /// sent to the gateway with
+    claimable_payments: Arc<Mutex<BTreeMap<u64, ClaimablePayment>>>,
+}
+
+impl GatewayLdkClient {
+    /// Starts the lightning node, which stores its keys and state in
+    /// `data_dir` and syncs the chain from the esplora server.
+    pub fn new(
+        data_dir: &Path,
+        esplora_server_url: &SafeUrl,
+        network: Network,
+        lightning_port: u16,
+    ) -> anyhow::Result<Self> {
+        info!(
+            "Gateway configured to run an embedded LDK node at \n data dir: {},\n esplora server: {},\n lightning port: {} ",
+            data_dir.display(),
+            esplora_server_url,
+            lightning_port
+        );
+
+        let mut builder = ldk_node::Builder::from_config(ldk_node::Config {
+            storage_dir_path: data_dir.to_string_lossy().to_string(),
+            network,
+            listening_addresses: Some(vec![SocketAddress::TcpIpV4 {
+                addr: [0, 0, 0, 0],
+                port: lightning_port,
+            }]),
+            ..Default::default()
+        });
+        builder.set_esplora_server(esplora_server_url.to_string());
+        builder.set_gossip_source_p2p();
+
+        let node = builder.build()?;
+        node.start()?;
+
+        Ok(GatewayLdkClient {
+            node: Arc::new(node),
+            network,
+            claimable_payments: Arc::new(Mutex::new(BTreeMap::new())),
+        })
+    }
+
+    /// Forwards payments to the invoices of the node to the gateway until the
+    /// task group shuts down, then stops the node.
+    fn spawn_event_handler(
+        &self,
+        task_group: &mut TaskGroup,
+        gateway_sender: mpsc::Sender<HtlcResult>,
+    ) {
+        let node = self.node.clone();
+        let claimable_payments = self.claimable_payments.clone();
+
+        task_group.spawn("LDK node event handler", |handle| async move {
+            let mut next_htlc_id = 0;
+
+            while let Ok(event) = handle.cancel_on_shutdown(node.next_event_async()).await {
+                if let Event::PaymentClaimable {
+                    payment_hash,
+                    claimable_amount_msat,
+                    claim_deadline,
+                    ..
+                } = event
+                {
+                    let htlc_id = next_htlc_id;
+                    next_htlc_id += 1;
+
+                    claimable_payments.lock().expect("Lock poisoned").insert(
+                        htlc_id,
+                        ClaimablePayment {
+                            payment_hash,
+                            amount_msat: claimable_amount_msat,
+                        },
+                    );
+
+                    let htlc = InterceptHtlcRequest {
+                        payment_hash: payment_hash.0.to_vec(),
+                        incoming_amount_msat: claimable_amount_msat,
+                        outgoing_amount_msat: claimable_amount_msat,
+                        incoming_expiry: claim_deadline.unwrap_or_default(),
+                        short_channel_id: None,
+                        incoming_chan_id: 0,
+                        htlc_id,
+                    };
+
+                    if let Err(e) = gateway_sender.send(Ok(htlc)).await {
+                        error!("Failed to send intercepted HTLC to gateway: {e:?}");
+                        break;
+                    }
+                }
+
+                node.event_handled();
+            }
+
+            info!("Stopping LDK node");
+            if let Err(e) = node.stop() {
+                warn!("Failed to stop LDK node: {e:?}");
+            }
+        });
+    }
+}
+
+impl fmt::Debug for GatewayLdkClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LdkClient")
+    }
+}
+
+#[async_trait]
+impl ILnRpcClient for GatewayLdkClient {
+    async fn info(&self) -> Result<GetNodeInfoResponse, LightningRpcError> {
+        let status = self.node.status();
+
+        Ok(GetNodeInfoResponse {
+            pub_key: self.node.node_id().serialize().to_vec(),
+            // ldk-node does not support node aliases yet
+            alias: String::new(),
+            network: self.network.to_string(),
+            block_height: status.current_best_block.height,
+            synced_to_chain: status.latest_wallet_sync_timestamp.is_some(),
+        })
+    }
+
+    /// Route hints are only needed to intercept LNv1 payments, which the
+    /// embedded node does not support
+    async fn routehints(
+        &self,
+        _num_route_hints: usize,
+    ) -> Result<GetRouteHintsResponse, LightningRpcError> {
+        Ok(GetRouteHintsResponse {
+            route_hints: vec![],
+        })
+    }
+
+    async fn pay(
+        &self,
+        invoice: PayInvoiceRequest,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        let invoice = Bolt11Invoice::from_str(&invoice.invoice).map_err(|e| {
+            LightningRpcError::FailedPayment {
+                failure_reason: format!("Failed to parse invoice {e:?}"),
+            }
+        })?;
+
+        // ldk-node limits the routing fee with its default config, it can not
+        // be set per payment yet
+        let payment_id = self.node.bolt11_payment().send(&invoice).map_err(|e| {
+            LightningRpcError::FailedPayment {
+                failure_reason: format!("Failed to send payment {e:?}"),
+            }
+        })?;
+
+        loop {
+            if let Some(payment) = self.node.payment(&payment_id) {
+                match payment.status {
+                    PaymentStatus::Pending => {}
+                    PaymentStatus::Succeeded => {
+                        if let PaymentKind::Bolt11 {
+                            preimage: Some(preimage),
+                            ..
+                        } = payment.kind
+                        {
+                            return Ok(PayInvoiceResponse {
+                                preimage: preimage.0.to_vec(),
+                            });
+                        }
+
+                        return Err(LightningRpcError::FailedPayment {
+                            failure_reason: "Payment succeeded without a preimage".to_string(),
+                        });
+                    }
+                    PaymentStatus::Failed => {
+                        return Err(LightningRpcError::FailedPayment {
+                            failure_reason: "LDK payment failed".to_string(),
+                        });
+                    }
+                }
+            }
+
+            sleep(PAYMENT_POLL_INTERVAL).await;
+        }
+    }
+
+    async fn route_htlcs<'a>(
+        self: Box<Self>,
+        task_group: &mut TaskGroup,
+    ) -> Result<(RouteHtlcStream<'a>, Arc<dyn ILnRpcClient>), LightningRpcError> {
+        const CHANNEL_SIZE: usize = 100;
+
+        let (gateway_sender, gateway_receiver) = mpsc::channel::<HtlcResult>(CHANNEL_SIZE);
+
+        self.spawn_event_handler(task_group, gateway_sender);
+
+        Ok((
+            Box::pin(ReceiverStream::new(gateway_receiver)),
+            Arc::new(*self),
+        ))
+    }
+
+    async fn complete_htlc(
+        &self,
+        htlc: InterceptHtlcResponse,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let ClaimablePayment {
+            payment_hash,
+            amount_msat,
+        } = self
+            .claimable_payments
+            .lock()
+            .expect("Lock poisoned")
+            .remove(&htlc.htlc_id)
+            .ok_or_else(|| LightningRpcError::FailedToCompleteHtlc {
+                failure_reason: format!("Unknown HTLC {}", htlc.htlc_id),
+            })?;
+
+        let result = match htlc.action {
+            Some(Action::Settle(settle)) => {
+                let preimage: [u8; 32] = settle.preimage.try_into().map_err(|_| {
+                    LightningRpcError::FailedToCompleteHtlc {
+                        failure_reason: "Invalid preimage length".to_string(),
+                    }
+                })?;
+
+                self.node.bolt11_payment().claim_for_hash(
+                    payment_hash,
+                    amount_msat,
+                    PaymentPreimage(preimage),
+                )
+            }
+            // The node only receives payments to its own invoices, so there is
+            // nothing to forward them to
+            Some(Action::Cancel(_) | Action::Forward(_)) | None => {
+                self.node.bolt11_payment().fail_for_hash(payment_hash)
+            }
+        };
+
+        result.map_err(|e| LightningRpcError::FailedToCompleteHtlc {
+            failure_reason: format!("{e:?}"),
+        })?;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn create_invoice(
+        &self,
+        create_invoice_request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        let payment_hash: [u8; 32] =
+            create_invoice_request
+                .payment_hash
+                .try_into()
+                .map_err(|_| LightningRpcError::FailedToGetInvoice {
+                    failure_reason: "Invalid payment hash length".to_string(),
+                })?;
+
+        let description = match create_invoice_request.description {
+            Some(Description::Direct(description)) => description,
+            Some(Description::Hash(_)) => {
+                return Err(LightningRpcError::FailedToGetInvoice {
+                    failure_reason: "LDK does not support description hashes".to_string(),
+                })
+            }
+            None => {
+                return Err(LightningRpcError::FailedToGetInvoice {
+                    failure_reason: "Description or description hash was not provided".to_string(),
+                })
+            }
+        };
+
+        let invoice = self
+            .node
+            .bolt11_payment()
+            .receive_for_hash(
+                create_invoice_request.amount_msat,
+                &description,
+                create_invoice_request.expiry,
+                PaymentHash(payment_hash),
+            )
+            .map_err(|e| LightningRpcError::FailedToGetInvoice {
+                failure_reason: format!("{e:?}"),
+            })?;
+
+        Ok(CreateInvoiceResponse {
+            invoice: invoice.to_string(),
+        })
+    }
+
+    async fn connect_to_peer(
+        &self,
+        pubkey: PublicKey,
+        host: String,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let address = SocketAddress::from_str(&host).map_err(|e| {
+            LightningRpcError::FailedToConnectToPeer {
+                failure_reason: format!("Invalid peer address {e:?}"),
+            }
+        })?;
+
+        self.node.connect(pubkey, address, true).map_err(|e| {
+            LightningRpcError::FailedToConnectToPeer {
+                failure_reason: format!("Failed to connect to peer {e:?}"),
+            }
+        })?;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn get_funding_address(&self) -> Result<GetFundingAddressResponse, LightningRpcError> {
+        let address = self.node.onchain_payment().new_address().map_err(|e| {
+            LightningRpcError::FailedToGetFundingAddress {
+                failure_reason: format!("{e:?}"),
+            }
+        })?;
+
+        Ok(GetFundingAddressResponse {
+            address: address.to_string(),
+        })
+    }
+
+    async fn open_channel(
+        &self,
+        pubkey: PublicKey,
+        channel_size_sats: u64,
+        push_amount_sats: u64,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        // The node has to be connected to the peer with `connect_to_peer` first
+        let address = self
+            .node
+            .list_peers()
+            .into_iter()
+            .find(|peer| peer.node_id == pubkey)
+            .map(|peer| peer.address)
+            .ok_or_else(|| LightningRpcError::FailedToOpenChannel {
+                failure_reason: format!("Not connected to peer {pubkey}"),
+            })?;
+
+        self.node
+            .connect_open_channel(
+                pubkey,
+                address,
+                channel_size_sats,
+                Some(Amount::from_sats(push_amount_sats).msats),
+                None,
+                true,
+            )
+            .map_err(|e| LightningRpcError::FailedToOpenChannel {
+                failure_reason: format!("{e:?}"),
+            })?;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn close_channels_with_peer(
+        &self,
+        pubkey: PublicKey,
+    ) -> Result<CloseChannelsWithPeerResponse, LightningRpcError> {
+        let mut num_channels_closed = 0;
+
+        for channel in self
+            .node
+            .list_channels()
+            .into_iter()
+            .filter(|channel| channel.counterparty_node_id == pubkey)
+        {
+            self.node
+                .close_channel(&channel.user_channel_id, pubkey)
+                .map_err(|e| LightningRpcError::FailedToCloseChannelsWithPeer {
+                    failure_reason: format!("{e:?}"),
+                })?;
+
+            num_channels_closed += 1;
+        }
+
+        Ok(CloseChannelsWithPeerResponse {
+            num_channels_closed,
+        })
+    }
+
+    async fn list_active_channels(&self) -> Result<Vec<ChannelInfo>, LightningRpcError> {
+        Ok(self
+            .node
+            .list_channels()
+            .into_iter()
+            .filter(|channel| channel.is_usable)
+            .map(|channel| ChannelInfo {
+                remote_pubkey: channel.counterparty_node_id.to_string(),
+                channel_size_sats: channel.channel_value_sats,
+                outbound_liquidity_sats: channel.outbound_capacity_msat / 1000,
+                inbound_liquidity_sats: channel.inbound_capacity_msat / 1000,
+                short_channel_id: channel.short_channel_id.unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn get_onchain_balance(&self) -> Result<GetOnchainBalanceResponse, LightningRpcError> {
+        let balances = self.node.list_balances();
+
+        // LDK only distinguishes the spendable part of the balance, which
+        // excludes unconfirmed outputs and the reserve for anchor channels
+        Ok(GetOnchainBalanceResponse {
+            confirmed_balance_sats: balances.spendable_onchain_balance_sats,
+            unconfirmed_balance_sats: balances.total_onchain_balance_sats
+                - balances.spendable_onchain_balance_sats,
+        })
+    }
+
+    async fn send_onchain(
+        &self,
+        request: SendOnchainRequest,
+    ) -> Result<SendOnchainResponse, LightningRpcError> {
+        let address = bitcoin::Address::from_str(&request.address)
+            .and_then(|address| address.require_network(self.network))
+            .map_err(|e| LightningRpcError::FailedToSendOnchain {
+                failure_reason: format!("Invalid address {e:?}"),
+            })?;
+
+        // The wallet of the LDK node estimates the fee rate itself
+        let txid = self
+            .node
+            .onchain_payment()
+            .send_to_address(&address, request.amount_sats)
+            .map_err(|e| LightningRpcError::FailedToSendOnchain {
+                failure_reason: format!("{e:?}"),
+            })?;
+
+        Ok(SendOnchainResponse {
+            txid: txid.to_string(),
+        })
+    }
+}
@@ -19,7 +19,8 @@ use tonic_lnd::lnrpc::failure::FailureCode;
 use tonic_lnd::lnrpc::payment::PaymentStatus;
 use tonic_lnd::lnrpc::{
     ChanInfoRequest, ChannelPoint, CloseChannelRequest, ConnectPeerRequest, GetInfoRequest,
-    LightningAddress, ListChannelsRequest, OpenChannelRequest,
+    Invoice, LightningAddress, ListChannelsRequest, OpenChannelRequest, SendCoinsRequest,
+    WalletBalanceRequest,
 };
 use tonic_lnd::routerrpc::{
     CircuitKey, ForwardHtlcInterceptResponse, ResolveHoldForwardAction, SendPaymentRequest,
@@ -37,8 +38,9 @@ use crate::gateway_lnrpc::get_route_hints_response::{RouteHint, RouteHintHop};
 use crate::gateway_lnrpc::intercept_htlc_response::{Action, Cancel, Forward, Settle};
 use crate::gateway_lnrpc::{
     CloseChannelsWithPeerResponse, CreateInvoiceRequest, CreateInvoiceResponse, EmptyResponse,
-    GetFundingAddressResponse, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcRequest,
-    InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
+    GetFundingAddressResponse, GetNodeInfoResponse, GetOnchainBalanceResponse,
+    GetRouteHintsResponse, InterceptHtlcRequest, InterceptHtlcResponse, PayInvoiceRequest,
+    PayInvoiceResponse, SendOnchainRequest, SendOnchainResponse,
 };
 
 type HtlcSubscriptionSender = mpsc::Sender<Result<InterceptHtlcRequest, Status>>;
@@ -252,7 +254,7 @@ impl GatewayLndClient {
                     // Block until LND returns the completed payment
                     if let Some(payment) =
                         payments.into_inner().message().await.map_err(|status| {
-                            LightningRpcError::FailedPayment {
+                            LightningRpcError::PaymentStateUnknown {
                                 failure_reason: status.message().to_string(),
                             }
                         })?
@@ -261,8 +263,14 @@ impl GatewayLndClient {
                             return Ok(Some(payment.payment_preimage));
                         }
 
+                        // A failed payment is final, so it can be attempted
+                        // again with new limits
+                        if payment.status() == PaymentStatus::Failed {
+                            return Ok(None);
+                        }
+
                         let failure_reason = payment.failure_reason();
-                        return Err(LightningRpcError::FailedPayment {
+                        return Err(LightningRpcError::PaymentStateUnknown {
                             failure_reason: format!("{failure_reason:?}"),
                         });
                     }
@@ -411,6 +419,7 @@ impl ILnRpcClient for GatewayLndClient {
         invoice: PrunedInvoice,
         max_delay: u64,
         max_fee: Amount,
+        max_parts: u32,
     ) -> Result<PayInvoiceResponse, LightningRpcError> {
         info!("LND Paying invoice {invoice:?}");
         let mut client = self.connect().await?;
@@ -479,13 +488,25 @@ impl ILnRpcClient for GatewayLndClient {
                     no_inflight_updates: false,
                     timeout_seconds: LND_PAYMENT_TIMEOUT_SECONDS,
                     fee_limit_msat,
+                    // All parts share the same preimage and are bounded by
+                    // `cltv_limit`, so they settle or fail together. LND falls
+                    // back to its own default if this is not set.
+                    max_parts,
                     ..Default::default()
                 })
                 .await
                 .map_err(|status| {
                     info!("LND payment request failed for invoice {invoice:?} with {status:?}");
-                    LightningRpcError::FailedPayment {
-                        failure_reason: format!("Failed to make outgoing payment {status:?}"),
+                    // The request might have reached LND before the connection
+                    // broke, so only a rejected request is a definite failure
+                    if status.code() == Code::InvalidArgument {
+                        LightningRpcError::FailedPayment {
+                            failure_reason: format!("Failed to make outgoing payment {status:?}"),
+                        }
+                    } else {
+                        LightningRpcError::PaymentStateUnknown {
+                            failure_reason: format!("Failed to make outgoing payment {status:?}"),
+                        }
                     }
                 })?;
 
@@ -494,12 +515,11 @@ impl ILnRpcClient for GatewayLndClient {
             );
             let mut messages = payments.into_inner();
             loop {
-                match messages
-                    .message()
-                    .await
-                    .map_err(|error| LightningRpcError::FailedPayment {
+                match messages.message().await.map_err(|error| {
+                    LightningRpcError::PaymentStateUnknown {
                         failure_reason: format!("Failed to get payment status {error:?}"),
-                    }) {
+                    }
+                }) {
                     Ok(Some(payment)) if payment.status() == PaymentStatus::Succeeded => {
                         info!("LND payment succeeded for invoice {invoice:?}");
                         break hex::FromHex::from_hex(payment.payment_preimage.as_str()).map_err(
@@ -521,7 +541,7 @@ impl ILnRpcClient for GatewayLndClient {
                     }
                     Ok(None) => {
                         info!("LND payment failed for invoice {invoice:?} with no payment status");
-                        return Err(LightningRpcError::FailedPayment {
+                        return Err(LightningRpcError::PaymentStateUnknown {
                             failure_reason: format!(
                                 "Failed to get payment status for payment hash {:?}",
                                 invoice.payment_hash
@@ -857,6 +877,142 @@ impl ILnRpcClient for GatewayLndClient {
             }),
         }
     }
+
+    async fn get_onchain_balance(&self) -> Result<GetOnchainBalanceResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+
+        let balance = client
+            .lightning()
+            .wallet_balance(WalletBalanceRequest::default())
+            .await
+            .map_err(|e| LightningRpcError::FailedToGetOnchainBalance {
+                failure_reason: format!("Failed to get wallet balance {e:?}"),
+            })?
+            .into_inner();
+
+        Ok(GetOnchainBalanceResponse {
+            confirmed_balance_sats: balance.confirmed_balance.try_into().expect("i64 -> u64"),
+            unconfirmed_balance_sats: balance.unconfirmed_balance.try_into().expect("i64 -> u64"),
+        })
+    }
+
+    async fn send_onchain(
+        &self,
+        request: SendOnchainRequest,
+    ) -> Result<SendOnchainResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+
+        let amount = request.amount_sats.try_into().map_err(|error| {
+            LightningRpcError::FailedToSendOnchain {
+                failure_reason: format!("amount exceeds valid LND amount ranges {error:?}"),
+            }
+        })?;
+
+        let response = client
+            .lightning()
+            .send_coins(SendCoinsRequest {
+                addr: request.address,
+                amount,
+                sat_per_vbyte: request.fee_rate_sats_per_vbyte,
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| LightningRpcError::FailedToSendOnchain {
+                failure_reason: format!("Failed to send coins {e:?}"),
+            })?
+            .into_inner();
+
+        Ok(SendOnchainResponse {
+            txid: response.txid,
+        })
+    }
+
+    async fn rebalance(
+        &self,
+        outgoing_short_channel_id: u64,
+        last_hop_pubkey: PublicKey,
+        amount: Amount,
+        max_fee: Amount,
+    ) -> Result<Amount, LightningRpcError> {
+        let mut client = self.connect().await?;
+
+        let value_msat =
+            amount
+                .msats
+                .try_into()
+                .map_err(|error| LightningRpcError::FailedToRebalance {
+                    failure_reason: format!("amount exceeds valid LND amount ranges {error:?}"),
+                })?;
+        let fee_limit_msat =
+            max_fee
+                .msats
+                .try_into()
+                .map_err(|error| LightningRpcError::FailedToRebalance {
+                    failure_reason: format!(
+                        "max_fee_msat exceeds valid LND fee limit ranges {error:?}"
+                    ),
+                })?;
+
+        let payment_request = client
+            .lightning()
+            .add_invoice(Invoice {
+                memo: "Gateway channel rebalance".to_string(),
+                value_msat,
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| LightningRpcError::FailedToRebalance {
+                failure_reason: format!("Failed to create invoice {e:?}"),
+            })?
+            .into_inner()
+            .payment_request;
+
+        info!("LND rebalancing {amount} from channel {outgoing_short_channel_id} to peer {last_hop_pubkey}");
+        let mut payments = client
+            .router()
+            .send_payment_v2(SendPaymentRequest {
+                payment_request,
+                outgoing_chan_ids: vec![outgoing_short_channel_id],
+                last_hop_pubkey: last_hop_pubkey.serialize().to_vec(),
+                allow_self_payment: true,
+                timeout_seconds: LND_PAYMENT_TIMEOUT_SECONDS,
+                fee_limit_msat,
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| LightningRpcError::FailedToRebalance {
+                failure_reason: format!("Failed to make rebalancing payment {e:?}"),
+            })?
+            .into_inner();
+
+        loop {
+            match payments.message().await {
+                Ok(Some(payment)) if payment.status() == PaymentStatus::Succeeded => {
+                    let fee_msat = payment.fee_msat.try_into().expect("i64 -> u64");
+                    return Ok(Amount::from_msats(fee_msat));
+                }
+                Ok(Some(payment)) if payment.status() == PaymentStatus::InFlight => {
+                    continue;
+                }
+                Ok(Some(payment)) => {
+                    let failure_reason = payment.failure_reason();
+                    return Err(LightningRpcError::FailedToRebalance {
+                        failure_reason: format!("{failure_reason:?}"),
+                    });
+                }
+                Ok(None) => {
+                    return Err(LightningRpcError::FailedToRebalance {
+                        failure_reason: "Payment stream ended without a final status".to_string(),
+                    });
+                }
+                Err(e) => {
+                    return Err(LightningRpcError::FailedToRebalance {
+                        failure_reason: format!("Failed to get payment status {e:?}"),
+                    });
+                }
+            }
+        }
+    }
 }
 
 fn route_hints_to_lnd(
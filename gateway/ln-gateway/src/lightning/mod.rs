@@ -1,10 +1,17 @@
 pub mod cln;
+pub mod eclair;
+pub mod failover;
+pub mod ldk;
 pub mod lnd;
+pub mod nwc;
 
 use std::fmt::Debug;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use bitcoin::Network;
+use bitcoin_hashes::{sha256, Hash as BitcoinHash};
 use clap::Subcommand;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::task::TaskGroup;
@@ -15,18 +22,52 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use self::cln::{NetworkLnRpcClient, RouteHtlcStream};
+use self::eclair::GatewayEclairClient;
+use self::failover::FailoverLnRpcClient;
+use self::ldk::GatewayLdkClient;
 use self::lnd::GatewayLndClient;
+use self::nwc::GatewayNwcClient;
 use crate::envs::{
-    FM_GATEWAY_LIGHTNING_ADDR_ENV, FM_LND_MACAROON_ENV, FM_LND_RPC_ADDR_ENV, FM_LND_TLS_CERT_ENV,
+    FM_ECLAIR_API_URL_ENV, FM_ECLAIR_PASSWORD_ENV, FM_GATEWAY_LIGHTNING_ADDR_ENV,
+    FM_LDK_ESPLORA_SERVER_URL_ENV, FM_LDK_LIGHTNING_PORT_ENV, FM_LDK_NETWORK_ENV,
+    FM_LND_MACAROON_ENV, FM_LND_RPC_ADDR_ENV, FM_LND_TLS_CERT_ENV, FM_NWC_URI_ENV,
 };
 use crate::gateway_lnrpc::{
-    CloseChannelsWithPeerResponse, CreateInvoiceRequest, CreateInvoiceResponse, EmptyResponse,
-    GetFundingAddressResponse, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcResponse,
-    PayInvoiceRequest, PayInvoiceResponse,
+    CloseChannelsWithPeerResponse, CreateInvoiceRequest, CreateInvoiceResponse, CreateOfferRequest,
+    EmptyResponse, GetFundingAddressResponse, GetNodeInfoResponse, GetOnchainBalanceResponse,
+    GetRouteHintsResponse, InterceptHtlcResponse, OfferResponse, PayInvoiceRequest,
+    PayInvoiceResponse, SendOnchainRequest, SendOnchainResponse,
 };
 
 pub const MAX_LIGHTNING_RETRIES: u32 = 10;
 
+/// Maximum number of parts a multi-part payment can be split into, matches the
+/// default of LND
+pub const MAX_PAYMENT_PARTS: u32 = 16;
+
+/// Checks that a preimage reported by a lightning backend is 32 bytes long and
+/// hashes to the payment hash, so a faulty remote wallet or node can not make
+/// the gateway claim a contract with an invalid preimage
+pub fn verify_preimage(preimage: &[u8], payment_hash: &[u8]) -> Result<(), LightningRpcError> {
+    if preimage.len() != 32 {
+        return Err(LightningRpcError::FailedPayment {
+            failure_reason: format!("Preimage is {} bytes instead of 32", preimage.len()),
+        });
+    }
+
+    if sha256::Hash::hash(preimage).to_byte_array().as_slice() != payment_hash {
+        return Err(LightningRpcError::FailedPayment {
+            failure_reason: "Preimage does not match the payment hash".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Subdirectory of the gateway data directory the embedded LDK node stores
+/// its keys and state in
+const LDK_NODE_DIR: &str = "ldk_node";
+
 #[derive(
     Error, Debug, Serialize, Deserialize, Encodable, Decodable, Clone, Eq, PartialEq, Hash,
 )]
@@ -57,6 +98,20 @@ pub enum LightningRpcError {
     FailedToListActiveChannels { failure_reason: String },
     #[error("Failed to wait for chain sync: {failure_reason}")]
     FailedToWaitForChainSync { failure_reason: String },
+    #[error("Failed to rebalance channels: {failure_reason}")]
+    FailedToRebalance { failure_reason: String },
+    #[error("Failed to get on-chain balance: {failure_reason}")]
+    FailedToGetOnchainBalance { failure_reason: String },
+    #[error("Failed to send on-chain: {failure_reason}")]
+    FailedToSendOnchain { failure_reason: String },
+    #[error("Failed to create offer: {failure_reason}")]
+    FailedToCreateOffer { failure_reason: String },
+    #[error("Failed to get offer: {failure_reason}")]
+    FailedToGetOffer { failure_reason: String },
+    /// The payment might still be in flight or have succeeded, so it must not
+    /// be attempted again
+    #[error("Payment state is unknown: {failure_reason}")]
+    PaymentStateUnknown { failure_reason: String },
 }
 
 /// A trait that the gateway uses to interact with a lightning node. This allows
@@ -73,7 +128,9 @@ pub trait ILnRpcClient: Debug + Send + Sync {
         num_route_hints: usize,
     ) -> Result<GetRouteHintsResponse, LightningRpcError>;
 
-    /// Attempt to pay an invoice using the lightning node
+    /// Attempt to pay an invoice using the lightning node. The node splits
+    /// the payment into multiple parts on its own if a single path can not
+    /// carry the amount, the number of parts can not be limited.
     async fn pay(
         &self,
         invoice: PayInvoiceRequest,
@@ -81,12 +138,14 @@ pub trait ILnRpcClient: Debug + Send + Sync {
 
     /// Attempt to pay an invoice using the lightning node using a
     /// [`PrunedInvoice`], increasing the user's privacy by not sending the
-    /// invoice description to the gateway.
+    /// invoice description to the gateway. The payment is split into at most
+    /// `max_parts` parts if a single path can not carry the amount.
     async fn pay_private(
         &self,
         _invoice: PrunedInvoice,
         _max_delay: u64,
         _max_fee: Amount,
+        _max_parts: u32,
     ) -> Result<PayInvoiceResponse, LightningRpcError> {
         Err(LightningRpcError::FailedPayment {
             failure_reason: "Private payments not supported".to_string(),
@@ -156,6 +215,65 @@ pub trait ILnRpcClient: Debug + Send + Sync {
     ) -> Result<CloseChannelsWithPeerResponse, LightningRpcError>;
 
     async fn list_active_channels(&self) -> Result<Vec<ChannelInfo>, LightningRpcError>;
+
+    /// Returns the balance of the lightning node's on-chain wallet
+    async fn get_onchain_balance(&self) -> Result<GetOnchainBalanceResponse, LightningRpcError>;
+
+    /// Sends funds from the lightning node's on-chain wallet
+    async fn send_onchain(
+        &self,
+        request: SendOnchainRequest,
+    ) -> Result<SendOnchainResponse, LightningRpcError>;
+
+    /// Moves `amount` of outbound liquidity from the channel with
+    /// `outgoing_short_channel_id` to the channel with the peer
+    /// `last_hop_pubkey` by paying an invoice of the lightning node to itself.
+    /// Returns the routing fee that was paid.
+    async fn rebalance(
+        &self,
+        _outgoing_short_channel_id: u64,
+        _last_hop_pubkey: secp256k1::PublicKey,
+        _amount: Amount,
+        _max_fee: Amount,
+    ) -> Result<Amount, LightningRpcError> {
+        Err(LightningRpcError::FailedToRebalance {
+            failure_reason: "Rebalancing is not supported by this lightning backend".to_string(),
+        })
+    }
+
+    /// Creates a BOLT12 offer the lightning node answers invoice requests for
+    async fn create_offer(
+        &self,
+        _request: CreateOfferRequest,
+    ) -> Result<OfferResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToCreateOffer {
+            failure_reason: "Offers are not supported by this lightning backend".to_string(),
+        })
+    }
+
+    /// Looks up a BOLT12 offer created by the lightning node, returns `None`
+    /// if the node has no offer with `offer_id`
+    async fn get_offer(
+        &self,
+        _offer_id: String,
+    ) -> Result<Option<OfferResponse>, LightningRpcError> {
+        Err(LightningRpcError::FailedToGetOffer {
+            failure_reason: "Offers are not supported by this lightning backend".to_string(),
+        })
+    }
+
+    /// Returns true if the lightning backend can create BOLT12 offers. If
+    /// this returns true, then [`ILnRpcClient::create_offer`] and
+    /// [`ILnRpcClient::get_offer`] have to be implemented.
+    fn supports_offers(&self) -> bool {
+        false
+    }
+
+    /// Health of the lightning backends in order of priority, empty if the
+    /// gateway only has a single backend
+    fn backend_health(&self) -> Vec<LightningBackendHealth> {
+        vec![]
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -167,6 +285,16 @@ pub struct ChannelInfo {
     pub short_channel_id: u64,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LightningBackendHealth {
+    /// 0 for the primary backend, fallbacks are used in ascending order
+    pub priority: usize,
+    pub kind: String,
+    /// Whether the last request to the backend succeeded
+    pub healthy: bool,
+    pub last_error: Option<String>,
+}
+
 #[derive(Debug, Clone, Subcommand, Serialize, Deserialize)]
 pub enum LightningMode {
     #[clap(name = "lnd")]
@@ -188,6 +316,51 @@ pub enum LightningMode {
         #[arg(long = "cln-extension-addr", env = FM_GATEWAY_LIGHTNING_ADDR_ENV)]
         cln_extension_addr: SafeUrl,
     },
+    #[clap(name = "eclair")]
+    Eclair {
+        /// Eclair HTTP API url
+        #[arg(long = "eclair-api-url", env = FM_ECLAIR_API_URL_ENV)]
+        eclair_api_url: SafeUrl,
+
+        /// Eclair HTTP API password
+        #[arg(long = "eclair-password", env = FM_ECLAIR_PASSWORD_ENV)]
+        eclair_password: String,
+    },
+    /// Runs an embedded lightning node in the gateway process
+    #[clap(name = "ldk")]
+    Ldk {
+        /// Esplora server the node syncs the chain from
+        #[arg(long = "ldk-esplora-server-url", env = FM_LDK_ESPLORA_SERVER_URL_ENV)]
+        esplora_server_url: SafeUrl,
+
+        /// Bitcoin network of the node
+        #[arg(long = "ldk-network", env = FM_LDK_NETWORK_ENV)]
+        network: Network,
+
+        /// Port the node accepts lightning peer connections on
+        #[arg(long = "ldk-lightning-port", env = FM_LDK_LIGHTNING_PORT_ENV, default_value_t = 9735)]
+        lightning_port: u16,
+    },
+    /// Pays invoices from a remote wallet via Nostr Wallet Connect, receiving
+    /// is not supported
+    #[clap(name = "nwc")]
+    Nwc {
+        /// Connection uri of the wallet, `nostr+walletconnect://...`
+        #[arg(long = "nwc-uri", env = FM_NWC_URI_ENV)]
+        nwc_uri: String,
+    },
+}
+
+impl LightningMode {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LightningMode::Lnd { .. } => "lnd",
+            LightningMode::Cln { .. } => "cln",
+            LightningMode::Eclair { .. } => "eclair",
+            LightningMode::Ldk { .. } => "ldk",
+            LightningMode::Nwc { .. } => "nwc",
+        }
+    }
 }
 
 #[async_trait]
@@ -198,12 +371,17 @@ pub trait LightningBuilder {
 #[derive(Clone)]
 pub struct GatewayLightningBuilder {
     pub lightning_mode: LightningMode,
+    /// Backends outgoing payments fail over to when `lightning_mode` is
+    /// unhealthy, in order of priority
+    pub fallback_modes: Vec<LightningMode>,
+    /// Data directory of the gateway, embedded lightning nodes store their
+    /// state in a subdirectory
+    pub data_dir: PathBuf,
 }
 
-#[async_trait]
-impl LightningBuilder for GatewayLightningBuilder {
-    async fn build(&self) -> Box<dyn ILnRpcClient> {
-        match self.lightning_mode.clone() {
+impl GatewayLightningBuilder {
+    async fn build_backend(&self, lightning_mode: LightningMode) -> Box<dyn ILnRpcClient> {
+        match lightning_mode {
             LightningMode::Cln { cln_extension_addr } => {
                 Box::new(NetworkLnRpcClient::new(cln_extension_addr).await)
             }
@@ -214,6 +392,77 @@ impl LightningBuilder for GatewayLightningBuilder {
             } => Box::new(
                 GatewayLndClient::new(lnd_rpc_addr, lnd_tls_cert, lnd_macaroon, None).await,
             ),
+            LightningMode::Eclair {
+                eclair_api_url,
+                eclair_password,
+            } => Box::new(GatewayEclairClient::new(eclair_api_url, eclair_password)),
+            LightningMode::Ldk {
+                esplora_server_url,
+                network,
+                lightning_port,
+            } => Box::new(
+                GatewayLdkClient::new(
+                    &self.data_dir.join(LDK_NODE_DIR),
+                    &esplora_server_url,
+                    network,
+                    lightning_port,
+                )
+                .expect("Failed to start LDK node"),
+            ),
+            LightningMode::Nwc { nwc_uri } => {
+                Box::new(GatewayNwcClient::new(&nwc_uri).expect("Invalid NWC uri"))
+            }
         }
     }
 }
+
+#[async_trait]
+impl LightningBuilder for GatewayLightningBuilder {
+    async fn build(&self) -> Box<dyn ILnRpcClient> {
+        let primary = self.build_backend(self.lightning_mode.clone()).await;
+
+        if self.fallback_modes.is_empty() {
+            return primary;
+        }
+
+        let mut fallbacks = Vec::new();
+        for mode in &self.fallback_modes {
+            let backend: Arc<dyn ILnRpcClient> = self.build_backend(mode.clone()).await.into();
+            fallbacks.push((mode.kind(), backend));
+        }
+
+        Box::new(FailoverLnRpcClient::new(
+            (self.lightning_mode.kind(), primary),
+            fallbacks,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_hashes::{sha256, Hash as BitcoinHash};
+
+    use super::{verify_preimage, LightningRpcError};
+
+    #[test]
+    fn verifies_preimage_against_payment_hash() {
+        let preimage = [1; 32];
+        let payment_hash = sha256::Hash::hash(&preimage).to_byte_array();
+
+        assert_eq!(verify_preimage(&preimage, &payment_hash), Ok(()));
+
+        assert!(matches!(
+            verify_preimage(&[2; 32], &payment_hash),
+            Err(LightningRpcError::FailedPayment { .. })
+        ));
+
+        let long_preimage = [1; 33];
+        assert!(matches!(
+            verify_preimage(
+                &long_preimage,
+                &sha256::Hash::hash(&long_preimage).to_byte_array()
+            ),
+            Err(LightningRpcError::FailedPayment { .. })
+        ));
+    }
+}
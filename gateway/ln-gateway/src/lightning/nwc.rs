@@ -0,0 +1,219 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use fedimint_core::secp256k1;
+use fedimint_core::task::TaskGroup;
+use nwc::prelude::{nip47, NostrWalletConnectURI, NWC};
+use secp256k1::PublicKey;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::info;
+
+use super::cln::{HtlcResult, RouteHtlcStream};
+use super::{verify_preimage, ChannelInfo, ILnRpcClient, LightningRpcError};
+use crate::gateway_lnrpc::{
+    CloseChannelsWithPeerResponse, CreateInvoiceRequest, CreateInvoiceResponse, EmptyResponse,
+    GetFundingAddressResponse, GetNodeInfoResponse, GetOnchainBalanceResponse,
+    GetRouteHintsResponse, InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
+    SendOnchainRequest, SendOnchainResponse,
+};
+
+/// An `ILnRpcClient` that pays invoices from a remote wallet via Nostr Wallet
+/// Connect (NIP-47), so a gateway can offer outgoing payments without running
+/// a lightning node.
+///
+/// The wallet can not hold incoming payments for the gateway, so receiving
+/// and all channel management are unsupported.
+pub struct GatewayNwcClient {
+    nwc: Arc<NWC>,
+}
+
+impl GatewayNwcClient {
+    pub fn new(uri: &str) -> anyhow::Result<Self> {
+        let uri = NostrWalletConnectURI::from_str(uri)?;
+
+        info!(
+            "Gateway configured to pay via Nostr Wallet Connect at \n relay: {}",
+            uri.relay_url
+        );
+
+        Ok(GatewayNwcClient {
+            nwc: Arc::new(NWC::new(uri)),
+        })
+    }
+}
+
+impl fmt::Debug for GatewayNwcClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NwcClient")
+    }
+}
+
+fn unsupported(operation: &str) -> String {
+    format!("{operation} is not supported by Nostr Wallet Connect")
+}
+
+#[async_trait]
+impl ILnRpcClient for GatewayNwcClient {
+    async fn info(&self) -> Result<GetNodeInfoResponse, LightningRpcError> {
+        let info =
+            self.nwc
+                .get_info()
+                .await
+                .map_err(|e| LightningRpcError::FailedToGetNodeInfo {
+                    failure_reason: format!("Failed to get wallet info {e:?}"),
+                })?;
+
+        let pub_key = PublicKey::from_str(&info.pubkey).map_err(|e| {
+            LightningRpcError::FailedToGetNodeInfo {
+                failure_reason: format!("Failed to parse public key {e:?}"),
+            }
+        })?;
+
+        Ok(GetNodeInfoResponse {
+            pub_key: pub_key.serialize().to_vec(),
+            alias: info.alias,
+            network: info.network,
+            block_height: info.block_height,
+            // The wallet does not report its sync state, it is responsible for
+            // only accepting payments once it is synced
+            synced_to_chain: true,
+        })
+    }
+
+    async fn routehints(
+        &self,
+        _num_route_hints: usize,
+    ) -> Result<GetRouteHintsResponse, LightningRpcError> {
+        Ok(GetRouteHintsResponse {
+            route_hints: vec![],
+        })
+    }
+
+    async fn pay(
+        &self,
+        invoice: PayInvoiceRequest,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        // NIP-47 has no way to limit the routing fee, the wallet applies its
+        // own limits
+        let preimage = self
+            .nwc
+            .pay_invoice(invoice.invoice)
+            .await
+            .map_err(|e| match e {
+                // Only an error response of the wallet shows the payment failed
+                nwc::Error::NIP47(nip47::Error::ErrorCode(_)) => LightningRpcError::FailedPayment {
+                    failure_reason: format!("{e:?}"),
+                },
+                // The request or the response got lost on the relay, so the
+                // payment might be in flight and must not be retried
+                _ => LightningRpcError::PaymentStateUnknown {
+                    failure_reason: format!("{e:?}"),
+                },
+            })?;
+
+        let preimage = hex::decode(preimage).map_err(|e| LightningRpcError::FailedPayment {
+            failure_reason: format!("Invalid preimage {e:?}"),
+        })?;
+
+        verify_preimage(&preimage, &invoice.payment_hash)?;
+
+        Ok(PayInvoiceResponse { preimage })
+    }
+
+    async fn route_htlcs<'a>(
+        self: Box<Self>,
+        task_group: &mut TaskGroup,
+    ) -> Result<(RouteHtlcStream<'a>, Arc<dyn ILnRpcClient>), LightningRpcError> {
+        // No HTLCs are ever intercepted, keep the stream open until shutdown so
+        // the gateway stays connected
+        let (gateway_sender, gateway_receiver) = mpsc::channel::<HtlcResult>(1);
+
+        task_group.spawn("NWC HTLC stream", |handle| async move {
+            let _gateway_sender = gateway_sender;
+            handle.make_shutdown_rx().await.await;
+        });
+
+        Ok((
+            Box::pin(ReceiverStream::new(gateway_receiver)),
+            Arc::new(*self),
+        ))
+    }
+
+    async fn complete_htlc(
+        &self,
+        _htlc: InterceptHtlcResponse,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToCompleteHtlc {
+            failure_reason: unsupported("Receiving payments"),
+        })
+    }
+
+    async fn create_invoice(
+        &self,
+        _create_invoice_request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToGetInvoice {
+            failure_reason: unsupported("Receiving payments"),
+        })
+    }
+
+    async fn connect_to_peer(
+        &self,
+        _pubkey: PublicKey,
+        _host: String,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToConnectToPeer {
+            failure_reason: unsupported("Connecting to peers"),
+        })
+    }
+
+    async fn get_funding_address(&self) -> Result<GetFundingAddressResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToGetFundingAddress {
+            failure_reason: unsupported("Funding the wallet on-chain"),
+        })
+    }
+
+    async fn open_channel(
+        &self,
+        _pubkey: PublicKey,
+        _channel_size_sats: u64,
+        _push_amount_sats: u64,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToOpenChannel {
+            failure_reason: unsupported("Opening channels"),
+        })
+    }
+
+    async fn close_channels_with_peer(
+        &self,
+        _pubkey: PublicKey,
+    ) -> Result<CloseChannelsWithPeerResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToCloseChannelsWithPeer {
+            failure_reason: unsupported("Closing channels"),
+        })
+    }
+
+    async fn list_active_channels(&self) -> Result<Vec<ChannelInfo>, LightningRpcError> {
+        Err(LightningRpcError::FailedToListActiveChannels {
+            failure_reason: unsupported("Listing channels"),
+        })
+    }
+
+    async fn get_onchain_balance(&self) -> Result<GetOnchainBalanceResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToGetOnchainBalance {
+            failure_reason: unsupported("Getting the on-chain balance"),
+        })
+    }
+
+    async fn send_onchain(
+        &self,
+        _request: SendOnchainRequest,
+    ) -> Result<SendOnchainResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToSendOnchain {
+            failure_reason: unsupported("Sending on-chain"),
+        })
+    }
+}
@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use fedimint_core::config::FederationId;
+use fedimint_core::Amount;
+use tracing::warn;
+
+use crate::lightning::ChannelInfo;
+
+/// How often the gateway checks its liquidity against the configured
+/// thresholds
+pub const LIQUIDITY_CHECK_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Minimum liquidity the gateway should have available for each connected
+/// federation. Thresholds that are not set are not checked.
+#[derive(Debug, Clone, Default)]
+pub struct LiquidityThresholds {
+    /// Minimum liquidity for receiving lightning payments into a federation
+    pub min_inbound: Option<Amount>,
+    /// Minimum liquidity for paying lightning invoices out of a federation
+    pub min_outbound: Option<Amount>,
+}
+
+impl LiquidityThresholds {
+    pub fn is_enabled(&self) -> bool {
+        self.min_inbound.is_some() || self.min_outbound.is_some()
+    }
+
+    /// Warns about every threshold `liquidity` falls below
+    pub fn check(&self, liquidity: &FederationLiquidity) {
+        for violation in self.violations(liquidity) {
+            warn!(
+                federation_id = %liquidity.federation_id,
+                "{violation}, consider opening a channel or rebalancing"
+            );
+        }
+    }
+
+    fn violations(&self, liquidity: &FederationLiquidity) -> Vec<String> {
+        let mut violations = vec![];
+        if let Some(min_inbound) = self.min_inbound {
+            if liquidity.inbound < min_inbound {
+                violations.push(format!(
+                    "Inbound liquidity {} is below the threshold of {min_inbound}",
+                    liquidity.inbound
+                ));
+            }
+        }
+        if let Some(min_outbound) = self.min_outbound {
+            if liquidity.outbound < min_outbound {
+                violations.push(format!(
+                    "Outbound liquidity {} is below the threshold of {min_outbound}",
+                    liquidity.outbound
+                ));
+            }
+        }
+        violations
+    }
+}
+
+/// Liquidity the gateway has available for a connected federation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FederationLiquidity {
+    pub federation_id: FederationId,
+    /// Receiving a lightning payment into the federation requires inbound
+    /// liquidity on the lightning node and ecash to fund the incoming contract
+    pub inbound: Amount,
+    /// Paying an invoice out of the federation only requires outbound
+    /// liquidity on the lightning node, since the gateway receives ecash
+    pub outbound: Amount,
+}
+
+impl FederationLiquidity {
+    pub fn new(
+        federation_id: FederationId,
+        channels: &[ChannelInfo],
+        ecash_balance: Amount,
+    ) -> Self {
+        let inbound_sats = channels
+            .iter()
+            .map(|channel| channel.inbound_liquidity_sats)
+            .sum();
+        let outbound_sats = channels
+            .iter()
+            .map(|channel| channel.outbound_liquidity_sats)
+            .sum();
+
+        FederationLiquidity {
+            federation_id,
+            inbound: Amount::from_sats(inbound_sats).min(ecash_balance),
+            outbound: Amount::from_sats(outbound_sats),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::config::FederationId;
+    use fedimint_core::Amount;
+
+    use super::{FederationLiquidity, LiquidityThresholds};
+    use crate::lightning::ChannelInfo;
+
+    fn channel(outbound_liquidity_sats: u64, inbound_liquidity_sats: u64) -> ChannelInfo {
+        ChannelInfo {
+            remote_pubkey: String::new(),
+            channel_size_sats: outbound_liquidity_sats + inbound_liquidity_sats,
+            outbound_liquidity_sats,
+            inbound_liquidity_sats,
+            short_channel_id: 0,
+        }
+    }
+
+    #[test]
+    fn inbound_liquidity_is_limited_by_ecash() {
+        let federation_id = FederationId::dummy();
+        let channels = [channel(1_000, 5_000), channel(2_000, 0)];
+        let liquidity =
+            FederationLiquidity::new(federation_id, &channels, Amount::from_sats(4_000));
+
+        assert_eq!(liquidity.inbound, Amount::from_sats(4_000));
+        assert_eq!(liquidity.outbound, Amount::from_sats(3_000));
+
+        let thresholds = LiquidityThresholds {
+            min_inbound: Some(Amount::from_sats(4_000)),
+            min_outbound: Some(Amount::from_sats(3_001)),
+        };
+        assert_eq!(thresholds.violations(&liquidity).len(), 1);
+        assert!(LiquidityThresholds::default()
+            .violations(&liquidity)
+            .is_empty());
+    }
+}
@@ -1,17 +1,23 @@
 pub mod rpc_client;
 pub mod rpc_server;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::str::FromStr;
 
 use bitcoin::address::NetworkUnchecked;
-use bitcoin::{Address, Network};
+use bitcoin::{Address, Network, Txid};
+use bitcoin_hashes::sha256;
 use fedimint_core::config::{ClientConfig, FederationId, JsonClientConfig};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::util::SafeUrl;
 use fedimint_core::{secp256k1, Amount, BitcoinAmountOrAll};
 use fedimint_ln_common::config::parse_routing_fees;
 use fedimint_ln_common::{route_hints, serde_option_routing_fees};
-use lightning_invoice::RoutingFees;
+use lightning_invoice::{Bolt11Invoice, RoutingFees};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::lightning::LightningBackendHealth;
 
 pub const V1_API_ENDPOINT: &str = "v1";
 
@@ -68,6 +74,467 @@ pub struct FederationInfo {
     pub config: ClientConfig,
     pub channel_id: Option<u64>,
     pub routing_fees: Option<FederationRoutingFees>,
+    #[serde(default)]
+    pub limits: FederationLimits,
+    #[serde(default)]
+    pub sweep_policy: Option<SweepPolicy>,
+    #[serde(default)]
+    pub connection: FederationConnectionState,
+}
+
+/// Whether the gateway is announced to a connected federation, which lets the
+/// federation's clients route payments through it
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum FederationConnectionState {
+    /// The gateway has not attempted to register with the federation yet
+    #[default]
+    Registering,
+    Registered,
+    /// The latest registrations with the federation failed
+    Failing {
+        consecutive_failures: u32,
+        /// Whether the last successful announcement is still valid
+        announced: bool,
+    },
+}
+
+/// State of the gateway as reported by `GatewayInfo`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum GatewayStatus {
+    /// Connecting to the lightning node and loading the federation clients
+    Initializing,
+    /// Waiting for the gateway's configuration to be set
+    Configuring,
+    /// Waiting for the lightning node to sync to the chain or for the first
+    /// registrations with the federations
+    Syncing,
+    /// Routing payments for its federations
+    Connected { federations: usize },
+    /// Running, but unable to route some payments
+    Degraded { reason: String },
+    /// Rejecting new payments before shutting down
+    Draining,
+}
+
+impl GatewayStatus {
+    /// Derives the status of a gateway that is connected to its lightning
+    /// node from the health of its lightning backends and its federations
+    pub fn from_running(
+        synced_to_chain: bool,
+        lightning_backends: &[LightningBackendHealth],
+        federations: &[FederationConnectionState],
+    ) -> Self {
+        if let Some(backend) = lightning_backends.iter().find(|backend| !backend.healthy) {
+            return GatewayStatus::Degraded {
+                reason: format!(
+                    "Lightning backend {} ({}) is unhealthy",
+                    backend.priority, backend.kind
+                ),
+            };
+        }
+
+        let failing = federations
+            .iter()
+            .filter(|state| matches!(state, FederationConnectionState::Failing { .. }))
+            .count();
+        if failing > 0 {
+            return GatewayStatus::Degraded {
+                reason: format!("Failing to register with {failing} federation(s)"),
+            };
+        }
+
+        if !synced_to_chain || federations.contains(&FederationConnectionState::Registering) {
+            return GatewayStatus::Syncing;
+        }
+
+        GatewayStatus::Connected {
+            federations: federations.len(),
+        }
+    }
+}
+
+/// Limits on the funds a federation can move out of the gateway, protecting
+/// the operator from a malicious or buggy federation draining the node.
+/// Limits that are not set are not enforced.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct FederationLimits {
+    /// Largest single outgoing payment or withdrawal
+    pub max_payment: Option<Amount>,
+    /// Largest sum of outgoing payments and withdrawals per UTC day
+    pub max_daily_outflow: Option<Amount>,
+    /// Largest e-cash balance the gateway accumulates by paying invoices for
+    /// the federation
+    pub max_ecash_balance: Option<Amount>,
+}
+
+impl FederationLimits {
+    /// Checks whether `amount` can leave the gateway if `daily_outflow` has
+    /// already left it today. `ecash_balance` is the balance the gateway
+    /// holds after the outflow, if the outflow is paid for with e-cash.
+    pub fn check(
+        &self,
+        amount: Amount,
+        daily_outflow: Amount,
+        ecash_balance: Option<Amount>,
+    ) -> Result<(), String> {
+        if let Some(max_payment) = self.max_payment {
+            if amount > max_payment {
+                return Err(format!(
+                    "{amount} exceeds the maximum payment of {max_payment}"
+                ));
+            }
+        }
+
+        if let Some(max_daily_outflow) = self.max_daily_outflow {
+            if daily_outflow + amount > max_daily_outflow {
+                return Err(format!(
+                    "{amount} exceeds the remaining daily outflow of {}",
+                    max_daily_outflow.saturating_sub(daily_outflow)
+                ));
+            }
+        }
+
+        if let (Some(max_ecash_balance), Some(ecash_balance)) =
+            (self.max_ecash_balance, ecash_balance)
+        {
+            if ecash_balance > max_ecash_balance {
+                return Err(format!(
+                    "E-cash balance of {ecash_balance} would exceed the maximum of {max_ecash_balance}"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetFederationLimitsPayload {
+    pub federation_id: FederationId,
+    /// `None` resets the federation to the gateway's default limits
+    pub limits: Option<FederationLimits>,
+}
+
+/// Policy for pegging out the e-cash the gateway accumulates in a federation
+/// to the lightning node's on-chain wallet, reducing how much the gateway
+/// has at stake in any single federation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct SweepPolicy {
+    /// E-cash balance above which the federation is swept
+    pub threshold: Amount,
+    /// E-cash balance left in the federation by a sweep, to keep funding
+    /// incoming payments
+    pub float: Amount,
+}
+
+impl SweepPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.threshold < self.float {
+            return Err(format!(
+                "Threshold {} is below the float of {}",
+                self.threshold, self.float
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether the policy sweeps a federation with `ecash_balance`
+    pub fn should_sweep(&self, ecash_balance: Amount) -> bool {
+        ecash_balance > self.threshold
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetSweepPolicyPayload {
+    pub federation_id: FederationId,
+    /// `None` stops sweeping the federation automatically
+    pub policy: Option<SweepPolicy>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SweepPayload {
+    pub federation_id: FederationId,
+    /// E-cash balance to leave in the federation, defaults to the float of the
+    /// federation's sweep policy or nothing if it has none
+    pub float: Option<Amount>,
+}
+
+/// Peg-out of a federation's e-cash to the lightning node's on-chain wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct SweepRecord {
+    pub federation_id: FederationId,
+    pub txid: Txid,
+    pub address: String,
+    /// Amount received on-chain
+    pub amount: Amount,
+    /// Peg-out fee paid with e-cash
+    pub fee: Amount,
+    /// Whether the sweep was triggered by the federation's sweep policy
+    pub automatic: bool,
+    /// Seconds since the unix epoch
+    pub created_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListSweepsPayload {
+    pub federation_id: Option<FederationId>,
+}
+
+/// Whether an HTLC matching an `HtlcRule` is accepted by the gateway
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+#[serde(rename_all = "snake_case")]
+pub enum HtlcRuleAction {
+    Allow,
+    Deny,
+}
+
+/// Rule matching HTLCs that are intercepted for the connected federations.
+/// Conditions that are not set match every HTLC.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct HtlcRule {
+    pub name: String,
+    pub action: HtlcRuleAction,
+    #[serde(default)]
+    pub short_channel_id: Option<u64>,
+    #[serde(default)]
+    pub federation_id: Option<FederationId>,
+    /// Smallest matching amount, inclusive
+    #[serde(default)]
+    pub min_amount: Option<Amount>,
+    /// Largest matching amount, inclusive
+    #[serde(default)]
+    pub max_amount: Option<Amount>,
+    /// Number of matching HTLCs an allow rule accepts per minute, further
+    /// matching HTLCs are denied
+    #[serde(default)]
+    pub max_per_minute: Option<u32>,
+}
+
+impl HtlcRule {
+    pub fn matches(
+        &self,
+        short_channel_id: Option<u64>,
+        federation_id: FederationId,
+        amount: Amount,
+    ) -> bool {
+        self.short_channel_id
+            .into_iter()
+            .all(|scid| short_channel_id == Some(scid))
+            && self.federation_id.into_iter().all(|id| id == federation_id)
+            && self.min_amount.into_iter().all(|min| min <= amount)
+            && self.max_amount.into_iter().all(|max| amount <= max)
+    }
+}
+
+/// Rules the gateway applies to intercepted HTLCs before accepting them. The
+/// first matching rule decides, HTLCs that match no rule are accepted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct HtlcFilterConfig {
+    pub rules: Vec<HtlcRule>,
+    /// Only logs the HTLCs the rules would deny instead of cancelling them
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl HtlcFilterConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let mut names = BTreeSet::new();
+        for rule in &self.rules {
+            if rule.name.is_empty() {
+                return Err("Rule names must not be empty".to_string());
+            }
+            if !names.insert(&rule.name) {
+                return Err(format!("Duplicate rule {}", rule.name));
+            }
+            if let (Some(min), Some(max)) = (rule.min_amount, rule.max_amount) {
+                if max < min {
+                    return Err(format!(
+                        "Rule {} has a max amount {max} below its min amount {min}",
+                        rule.name
+                    ));
+                }
+            }
+            match (rule.action, rule.max_per_minute) {
+                (HtlcRuleAction::Deny, Some(_)) => {
+                    return Err(format!(
+                        "Rule {} denies HTLCs and can not be rate limited",
+                        rule.name
+                    ));
+                }
+                (HtlcRuleAction::Allow, Some(0)) => {
+                    return Err(format!(
+                        "Rule {} must allow at least one HTLC per minute",
+                        rule.name
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetHtlcFilterPayload {
+    pub filter: HtlcFilterConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateOfferPayload {
+    pub federation_id: FederationId,
+    /// Amount the offer requests, the payer chooses the amount if it is not
+    /// set
+    pub amount: Option<Amount>,
+    pub description: String,
+    /// Seconds the offer can be paid for, it does not expire if not set
+    pub expiry_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GetOfferPayload {
+    pub offer_id: String,
+}
+
+/// BOLT12 offer the gateway's lightning node answers invoice requests for on
+/// behalf of a federation
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct OfferInfo {
+    pub offer_id: String,
+    pub federation_id: FederationId,
+    /// The offer encoded as a bech32 `lno` string
+    pub bolt12: String,
+    pub amount: Option<Amount>,
+    pub description: String,
+    /// Whether the offer can still be paid
+    pub active: bool,
+    /// Whether the offer has been paid at least once
+    pub used: bool,
+    /// Seconds since the unix epoch
+    pub created_at: u64,
+}
+
+/// Federation account that payments to a lightning address hosted by the
+/// gateway settle into
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct LnurlAccount {
+    pub federation_id: FederationId,
+    /// Static public key of the user's LNv2 client, the incoming contracts of
+    /// payments to the address can only be claimed with its secret key
+    pub recipient_pk: secp256k1::PublicKey,
+    pub min_sendable: Amount,
+    pub max_sendable: Amount,
+    /// Shown to payers by their wallets
+    pub description: String,
+}
+
+impl LnurlAccount {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_sendable < Amount::from_sats(1) {
+            return Err("The minimum amount is below 1 sat".to_string());
+        }
+        if self.max_sendable < self.min_sendable {
+            return Err(format!(
+                "The maximum amount {} is below the minimum amount {}",
+                self.max_sendable, self.min_sendable
+            ));
+        }
+        Ok(())
+    }
+
+    /// LUD-06 metadata of the address, the invoices commit to its hash
+    pub fn metadata(&self) -> String {
+        json!([["text/plain", self.description]]).to_string()
+    }
+}
+
+/// Checks that `username` is a valid name of a lightning address as defined
+/// by LUD-16
+pub fn validate_lnurl_username(username: &str) -> Result<(), String> {
+    let valid = !username.is_empty()
+        && username
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-_.".contains(c));
+
+    if !valid {
+        return Err(format!(
+            "Username {username:?} may only contain a-z, 0-9, '-', '_' and '.'"
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetLnurlAccountPayload {
+    pub username: String,
+    /// `None` removes the lightning address
+    pub account: Option<LnurlAccount>,
+}
+
+/// LUD-06 response to a payer resolving a lightning address
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlPayResponse {
+    pub callback: SafeUrl,
+    /// Millisatoshis
+    pub min_sendable: u64,
+    /// Millisatoshis
+    pub max_sendable: u64,
+    pub metadata: String,
+    pub tag: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LnurlCallbackParams {
+    /// Millisatoshis
+    pub amount: u64,
+}
+
+/// LUD-06 response to a payer requesting an invoice
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LnurlInvoiceResponse {
+    pub pr: Bolt11Invoice,
+    pub routes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DrainPayload {
+    /// Seconds to wait for in-flight payments to resolve
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DrainStatus {
+    /// Payments that were still in flight when the drain stopped waiting, the
+    /// gateway is drained if there are none
+    pub in_flight: Vec<PaymentRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ShutdownPayload {
+    /// Shut down even if the gateway is not drained
+    pub force: bool,
+}
+
+/// Gateway settings, federation configs and client secrets, encrypted with a
+/// password chosen when exporting them
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EncryptedConfigArchive {
+    pub salt: String,
+    /// Hex encoded
+    pub ciphertext: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExportConfigPayload {
+    /// Password the archive is encrypted with
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportConfigPayload {
+    /// Password the archive was encrypted with
+    pub password: String,
+    pub archive: EncryptedConfigArchive,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -81,7 +548,7 @@ pub struct GatewayInfo {
     pub fees: Option<RoutingFees>,
     pub route_hints: Vec<route_hints::RouteHint>,
     pub gateway_id: secp256k1::PublicKey,
-    pub gateway_state: String,
+    pub gateway_state: GatewayStatus,
     pub network: Option<Network>,
     // TODO: This is here to allow for backwards compatibility with old versions of this struct. We
     // should be able to remove it once 0.4.0 is released.
@@ -91,6 +558,13 @@ pub struct GatewayInfo {
     // should be able to remove it once 0.4.0 is released.
     #[serde(default)]
     pub synced_to_chain: bool,
+    /// Health of each lightning backend if the gateway fails over between
+    /// several of them
+    #[serde(default)]
+    pub lightning_backends: Vec<LightningBackendHealth>,
+    /// Whether the lightning backend can create BOLT12 offers
+    #[serde(default)]
+    pub supports_offers: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -163,3 +637,362 @@ pub struct OpenChannelPayload {
 pub struct CloseChannelsWithPeerPayload {
     pub pubkey: secp256k1::PublicKey,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SendOnchainPayload {
+    pub address: Address<NetworkUnchecked>,
+    pub amount_sats: u64,
+    pub fee_rate_sats_per_vbyte: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PayInvoiceForOperatorPayload {
+    pub invoice: Bolt11Invoice,
+    /// The maximum routing fee to pay
+    pub max_fee: Amount,
+    /// The maximum number of blocks the payment can lock funds for
+    pub max_delay: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RebalancePayload {
+    /// The channel the liquidity is moved out of
+    pub outgoing_short_channel_id: u64,
+    /// The peer whose channel the liquidity is moved into
+    pub last_hop_pubkey: secp256k1::PublicKey,
+    pub amount: Amount,
+    pub max_fee: Amount,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    Encodable,
+    Decodable,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentDirection {
+    /// The gateway paid a lightning invoice on behalf of a federation client
+    Outgoing,
+    /// The gateway intercepted an HTLC to a federation client
+    Incoming,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+impl FromStr for PaymentStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(PaymentStatus::Pending),
+            "succeeded" => Ok(PaymentStatus::Succeeded),
+            "failed" => Ok(PaymentStatus::Failed),
+            other => anyhow::bail!("Unknown payment status {other}"),
+        }
+    }
+}
+
+/// Access granted by an API key. Each scope includes the routes of the scopes
+/// before it, the gateway's password always has the `Admin` scope.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Encodable, Decodable,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    /// Reading information and balances of the gateway
+    Info,
+    /// Moving funds into or within the gateway
+    Payments,
+    /// Everything, including spending funds and changing the configuration
+    Admin,
+}
+
+impl FromStr for ApiScope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "info" => Ok(ApiScope::Info),
+            "payments" => Ok(ApiScope::Payments),
+            "admin" => Ok(ApiScope::Admin),
+            other => anyhow::bail!("Unknown API scope {other}"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateApiKeyPayload {
+    pub name: String,
+    pub scope: ApiScope,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RevokeApiKeyPayload {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ApiKeyInfo {
+    pub name: String,
+    pub scope: ApiScope,
+    /// Seconds since the unix epoch
+    pub created_at: u64,
+}
+
+/// Entry of the payments ledger of the gateway
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct PaymentRecord {
+    pub federation_id: FederationId,
+    pub payment_hash: sha256::Hash,
+    pub direction: PaymentDirection,
+    /// Amount received by the payee
+    pub amount: Amount,
+    /// Fee earned by the gateway
+    pub fee: Amount,
+    pub status: PaymentStatus,
+    /// Seconds since the unix epoch
+    pub created_at: u64,
+    /// Seconds since the unix epoch
+    pub updated_at: u64,
+    pub error: Option<String>,
+    /// Attempts to pay an outgoing payment over lightning, oldest first
+    pub attempts: Vec<PaymentAttempt>,
+}
+
+/// Attempt of the gateway to pay an invoice over lightning within the given
+/// limits
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct PaymentAttempt {
+    pub max_fee: Amount,
+    pub max_delay: u64,
+    /// `None` if the attempt succeeded
+    pub error: Option<String>,
+    /// Seconds since the unix epoch
+    pub finished_at: u64,
+}
+
+impl PaymentRecord {
+    pub const CSV_HEADER: &'static str =
+        "created_at,updated_at,federation_id,direction,payment_hash,amount_msat,fee_msat,status,attempts,error";
+
+    /// Formats the record as a row of [`Self::CSV_HEADER`]
+    pub fn to_csv_row(&self) -> String {
+        let direction = match self.direction {
+            PaymentDirection::Outgoing => "outgoing",
+            PaymentDirection::Incoming => "incoming",
+        };
+
+        let status = match self.status {
+            PaymentStatus::Pending => "pending",
+            PaymentStatus::Succeeded => "succeeded",
+            PaymentStatus::Failed => "failed",
+        };
+
+        // Errors are the only free form field, so only they need quoting
+        let error = self
+            .error
+            .as_ref()
+            .map(|error| format!("\"{}\"", error.replace('"', "\"\"")))
+            .unwrap_or_default();
+
+        format!(
+            "{},{},{},{},{},{},{},{},{},{}",
+            self.created_at,
+            self.updated_at,
+            self.federation_id,
+            direction,
+            self.payment_hash,
+            self.amount.msats,
+            self.fee.msats,
+            status,
+            self.attempts.len(),
+            error
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ListPaymentsPayload {
+    pub federation_id: Option<FederationId>,
+    /// Only payments created at or after this time in seconds since the unix
+    /// epoch
+    pub start: Option<u64>,
+    /// Only payments created before this time in seconds since the unix epoch
+    pub end: Option<u64>,
+    pub status: Option<PaymentStatus>,
+    /// Number of matching payments to skip, newest first
+    #[serde(default)]
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl ListPaymentsPayload {
+    pub fn matches(&self, payment: &PaymentRecord) -> bool {
+        self.federation_id
+            .map_or(true, |federation_id| payment.federation_id == federation_id)
+            && self.start.map_or(true, |start| start <= payment.created_at)
+            && self.end.map_or(true, |end| payment.created_at < end)
+            && self.status.map_or(true, |status| payment.status == status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::Amount;
+    use fedimint_ln_common::gateway_endpoint_constants::{
+        GET_FUNDING_ADDRESS_ENDPOINT, REBALANCE_ENDPOINT, WITHDRAW_ENDPOINT,
+    };
+
+    use super::rpc_server::routes_for_scope;
+    use super::{
+        validate_lnurl_username, ApiScope, FederationConnectionState, FederationLimits,
+        GatewayStatus, SweepPolicy,
+    };
+    use crate::lightning::LightningBackendHealth;
+
+    #[test]
+    fn api_scopes_include_lower_scopes() {
+        assert!(ApiScope::Admin > ApiScope::Payments);
+        assert!(ApiScope::Payments > ApiScope::Info);
+        assert_eq!("payments".parse::<ApiScope>().unwrap(), ApiScope::Payments);
+        assert!("withdraw".parse::<ApiScope>().is_err());
+
+        let required_scope = |endpoint| {
+            [ApiScope::Info, ApiScope::Payments, ApiScope::Admin]
+                .into_iter()
+                .find(|scope| {
+                    routes_for_scope(*scope)
+                        .iter()
+                        .any(|(path, _)| *path == endpoint)
+                })
+                .expect("Endpoint is always authenticated")
+        };
+
+        assert_eq!(
+            required_scope(GET_FUNDING_ADDRESS_ENDPOINT),
+            ApiScope::Payments
+        );
+        // A payments key must not be able to move funds out of the gateway
+        for endpoint in [REBALANCE_ENDPOINT, WITHDRAW_ENDPOINT] {
+            assert!(ApiScope::Payments < required_scope(endpoint));
+        }
+    }
+
+    #[test]
+    fn federation_limits_are_enforced() {
+        let limits = FederationLimits {
+            max_payment: Some(Amount::from_sats(100)),
+            max_daily_outflow: Some(Amount::from_sats(250)),
+            max_ecash_balance: Some(Amount::from_sats(1_000)),
+        };
+
+        assert!(limits
+            .check(Amount::from_sats(100), Amount::from_sats(150), None)
+            .is_ok());
+        assert!(limits
+            .check(Amount::from_sats(101), Amount::ZERO, None)
+            .is_err());
+        assert!(limits
+            .check(Amount::from_sats(100), Amount::from_sats(151), None)
+            .is_err());
+        assert!(limits
+            .check(
+                Amount::from_sats(10),
+                Amount::ZERO,
+                Some(Amount::from_sats(1_001))
+            )
+            .is_err());
+        assert!(FederationLimits::default()
+            .check(
+                Amount::from_sats(1_000_000),
+                Amount::from_sats(1_000_000),
+                None
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn sweep_policy_sweeps_above_threshold() {
+        let policy = SweepPolicy {
+            threshold: Amount::from_sats(10_000),
+            float: Amount::from_sats(2_000),
+        };
+
+        assert!(policy.validate().is_ok());
+        assert!(!policy.should_sweep(Amount::from_sats(10_000)));
+        assert!(policy.should_sweep(Amount::from_sats(10_001)));
+        assert!(SweepPolicy {
+            threshold: Amount::from_sats(1_000),
+            float: Amount::from_sats(2_000),
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn gateway_status_reflects_backends_and_federations() {
+        let registered = vec![FederationConnectionState::Registered; 2];
+        assert_eq!(
+            GatewayStatus::from_running(true, &[], &registered),
+            GatewayStatus::Connected { federations: 2 }
+        );
+        assert_eq!(
+            GatewayStatus::from_running(false, &[], &registered),
+            GatewayStatus::Syncing
+        );
+        assert_eq!(
+            GatewayStatus::from_running(
+                true,
+                &[],
+                &[
+                    FederationConnectionState::Registered,
+                    FederationConnectionState::Registering
+                ]
+            ),
+            GatewayStatus::Syncing
+        );
+
+        let failing = FederationConnectionState::Failing {
+            consecutive_failures: 3,
+            announced: false,
+        };
+        assert!(matches!(
+            GatewayStatus::from_running(true, &[], &[failing]),
+            GatewayStatus::Degraded { .. }
+        ));
+
+        let unhealthy = LightningBackendHealth {
+            priority: 1,
+            kind: "lnd".to_string(),
+            healthy: false,
+            last_error: Some("connection refused".to_string()),
+        };
+        assert!(matches!(
+            GatewayStatus::from_running(true, &[unhealthy], &registered),
+            GatewayStatus::Degraded { .. }
+        ));
+    }
+
+    #[test]
+    fn lnurl_usernames_are_validated() {
+        assert!(validate_lnurl_username("satoshi.nakamoto_21").is_ok());
+        assert!(validate_lnurl_username("").is_err());
+        assert!(validate_lnurl_username("Satoshi").is_err());
+        assert!(validate_lnurl_username("../admin").is_err());
+    }
+}
@@ -1,28 +1,53 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
 use bitcoin::address::NetworkUnchecked;
-use bitcoin::Address;
+use bitcoin::{Address, Txid};
+use fedimint_core::runtime::sleep;
 use fedimint_core::util::SafeUrl;
 use fedimint_core::{Amount, TransactionId};
 use fedimint_ln_common::gateway_endpoint_constants::{
     BACKUP_ENDPOINT, BALANCE_ENDPOINT, CLOSE_CHANNELS_WITH_PEER_ENDPOINT, CONFIGURATION_ENDPOINT,
-    CONNECT_FED_ENDPOINT, CONNECT_TO_PEER_ENDPOINT, GATEWAY_INFO_ENDPOINT,
-    GATEWAY_INFO_POST_ENDPOINT, GET_FUNDING_ADDRESS_ENDPOINT, LEAVE_FED_ENDPOINT,
-    LIST_ACTIVE_CHANNELS_ENDPOINT, OPEN_CHANNEL_ENDPOINT, RESTORE_ENDPOINT,
-    SET_CONFIGURATION_ENDPOINT, WITHDRAW_ENDPOINT,
+    CONNECT_FED_ENDPOINT, CONNECT_TO_PEER_ENDPOINT, CREATE_API_KEY_ENDPOINT, CREATE_OFFER_ENDPOINT,
+    DRAIN_ENDPOINT, EXPORT_CONFIG_ENDPOINT, EXPORT_PAYMENTS_CSV_ENDPOINT, GATEWAY_INFO_ENDPOINT,
+    GATEWAY_INFO_POST_ENDPOINT, GET_FUNDING_ADDRESS_ENDPOINT, GET_HTLC_FILTER_ENDPOINT,
+    GET_OFFER_ENDPOINT, GET_ONCHAIN_BALANCE_ENDPOINT, IMPORT_CONFIG_ENDPOINT, LEAVE_FED_ENDPOINT,
+    LIST_ACTIVE_CHANNELS_ENDPOINT, LIST_API_KEYS_ENDPOINT, LIST_LNURL_ACCOUNTS_ENDPOINT,
+    LIST_PAYMENTS_ENDPOINT, LIST_SWEEPS_ENDPOINT, OPEN_CHANNEL_ENDPOINT,
+    PAY_INVOICE_FOR_OPERATOR_ENDPOINT, REBALANCE_ENDPOINT, RESTORE_ENDPOINT,
+    REVOKE_API_KEY_ENDPOINT, SEND_ONCHAIN_ENDPOINT, SET_CONFIGURATION_ENDPOINT,
+    SET_FEDERATION_LIMITS_ENDPOINT, SET_HTLC_FILTER_ENDPOINT, SET_LNURL_ACCOUNT_ENDPOINT,
+    SET_SWEEP_POLICY_ENDPOINT, SHUTDOWN_ENDPOINT, SWEEP_ENDPOINT, WITHDRAW_ENDPOINT,
 };
 use reqwest::{Method, StatusCode};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use thiserror::Error;
+use tracing::debug;
 
 use super::{
-    BackupPayload, BalancePayload, CloseChannelsWithPeerPayload, ConfigPayload, ConnectFedPayload,
-    ConnectToPeerPayload, DepositAddressPayload, FederationInfo, GatewayFedConfig, GatewayInfo,
-    GetFundingAddressPayload, LeaveFedPayload, OpenChannelPayload, RestorePayload,
-    SetConfigurationPayload, WithdrawPayload,
+    ApiKeyInfo, BackupPayload, BalancePayload, CloseChannelsWithPeerPayload, ConfigPayload,
+    ConnectFedPayload, ConnectToPeerPayload, CreateApiKeyPayload, CreateOfferPayload,
+    DepositAddressPayload, DrainPayload, DrainStatus, EncryptedConfigArchive, ExportConfigPayload,
+    FederationInfo, FederationLimits, GatewayFedConfig, GatewayInfo, GetFundingAddressPayload,
+    GetOfferPayload, HtlcFilterConfig, ImportConfigPayload, LeaveFedPayload, ListPaymentsPayload,
+    ListSweepsPayload, LnurlAccount, OfferInfo, OpenChannelPayload, PayInvoiceForOperatorPayload,
+    PaymentRecord, RebalancePayload, RestorePayload, RevokeApiKeyPayload, SendOnchainPayload,
+    SetConfigurationPayload, SetFederationLimitsPayload, SetHtlcFilterPayload,
+    SetLnurlAccountPayload, SetSweepPolicyPayload, ShutdownPayload, SweepPayload, SweepPolicy,
+    SweepRecord, WithdrawPayload,
 };
+use crate::gateway_lnrpc::GetOnchainBalanceResponse;
 use crate::lightning::ChannelInfo;
 use crate::CloseChannelsWithPeerResponse;
 
+/// Number of times an idempotent request is retried if the gateway could not
+/// be reached
+const MAX_RETRIES: u32 = 3;
+
+/// Delay before the first retry, which grows with each retry
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
 pub struct GatewayRpcClient {
     /// Base URL to gateway web server
     /// This should include an applicable API version, e.g. http://localhost:8080/v1
@@ -60,7 +85,7 @@ impl GatewayRpcClient {
             .base_url
             .join(GATEWAY_INFO_POST_ENDPOINT)
             .expect("invalid base url");
-        self.call_post(url, ()).await
+        self.call_post_idempotent(url, ()).await
     }
 
     pub async fn get_config(&self, payload: ConfigPayload) -> GatewayRpcResult<GatewayFedConfig> {
@@ -68,7 +93,7 @@ impl GatewayRpcClient {
             .base_url
             .join(CONFIGURATION_ENDPOINT)
             .expect("invalid base url");
-        self.call_post(url, payload).await
+        self.call_post_idempotent(url, payload).await
     }
 
     pub async fn get_balance(&self, payload: BalancePayload) -> GatewayRpcResult<Amount> {
@@ -76,7 +101,7 @@ impl GatewayRpcClient {
             .base_url
             .join(BALANCE_ENDPOINT)
             .expect("invalid base url");
-        self.call_post(url, payload).await
+        self.call_post_idempotent(url, payload).await
     }
 
     pub async fn get_deposit_address(
@@ -190,6 +215,223 @@ impl GatewayRpcClient {
         self.call_get(url).await
     }
 
+    pub async fn get_onchain_balance(&self) -> GatewayRpcResult<GetOnchainBalanceResponse> {
+        let url = self
+            .base_url
+            .join(GET_ONCHAIN_BALANCE_ENDPOINT)
+            .expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn send_onchain(&self, payload: SendOnchainPayload) -> GatewayRpcResult<Txid> {
+        let url = self
+            .base_url
+            .join(SEND_ONCHAIN_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn rebalance(&self, payload: RebalancePayload) -> GatewayRpcResult<Amount> {
+        let url = self
+            .base_url
+            .join(REBALANCE_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    /// Returns the hex encoded preimage of the paid invoice
+    pub async fn pay_invoice_for_operator(
+        &self,
+        payload: PayInvoiceForOperatorPayload,
+    ) -> GatewayRpcResult<String> {
+        let url = self
+            .base_url
+            .join(PAY_INVOICE_FOR_OPERATOR_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn set_federation_limits(
+        &self,
+        payload: SetFederationLimitsPayload,
+    ) -> GatewayRpcResult<FederationLimits> {
+        let url = self
+            .base_url
+            .join(SET_FEDERATION_LIMITS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post_idempotent(url, payload).await
+    }
+
+    pub async fn create_offer(&self, payload: CreateOfferPayload) -> GatewayRpcResult<OfferInfo> {
+        let url = self
+            .base_url
+            .join(CREATE_OFFER_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    /// Returns an offer created by the gateway, `None` if there is none with
+    /// the id
+    pub async fn get_offer(&self, payload: GetOfferPayload) -> GatewayRpcResult<Option<OfferInfo>> {
+        let url = self
+            .base_url
+            .join(GET_OFFER_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post_idempotent(url, payload).await
+    }
+
+    pub async fn set_sweep_policy(
+        &self,
+        payload: SetSweepPolicyPayload,
+    ) -> GatewayRpcResult<Option<SweepPolicy>> {
+        let url = self
+            .base_url
+            .join(SET_SWEEP_POLICY_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post_idempotent(url, payload).await
+    }
+
+    pub async fn get_htlc_filter(&self) -> GatewayRpcResult<HtlcFilterConfig> {
+        let url = self
+            .base_url
+            .join(GET_HTLC_FILTER_ENDPOINT)
+            .expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn set_htlc_filter(
+        &self,
+        payload: SetHtlcFilterPayload,
+    ) -> GatewayRpcResult<HtlcFilterConfig> {
+        let url = self
+            .base_url
+            .join(SET_HTLC_FILTER_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post_idempotent(url, payload).await
+    }
+
+    /// Sweeps a federation's e-cash to the lightning node's on-chain wallet,
+    /// returns `None` if there was nothing to sweep
+    pub async fn sweep(&self, payload: SweepPayload) -> GatewayRpcResult<Option<SweepRecord>> {
+        let url = self
+            .base_url
+            .join(SWEEP_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn list_sweeps(
+        &self,
+        payload: ListSweepsPayload,
+    ) -> GatewayRpcResult<Vec<SweepRecord>> {
+        let url = self
+            .base_url
+            .join(LIST_SWEEPS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post_idempotent(url, payload).await
+    }
+
+    pub async fn set_lnurl_account(
+        &self,
+        payload: SetLnurlAccountPayload,
+    ) -> GatewayRpcResult<Option<LnurlAccount>> {
+        let url = self
+            .base_url
+            .join(SET_LNURL_ACCOUNT_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post_idempotent(url, payload).await
+    }
+
+    pub async fn list_lnurl_accounts(&self) -> GatewayRpcResult<BTreeMap<String, LnurlAccount>> {
+        let url = self
+            .base_url
+            .join(LIST_LNURL_ACCOUNTS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    /// Creates an API key and returns it, it can not be retrieved later
+    pub async fn create_api_key(&self, payload: CreateApiKeyPayload) -> GatewayRpcResult<String> {
+        let url = self
+            .base_url
+            .join(CREATE_API_KEY_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn list_api_keys(&self) -> GatewayRpcResult<Vec<ApiKeyInfo>> {
+        let url = self
+            .base_url
+            .join(LIST_API_KEYS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn revoke_api_key(&self, payload: RevokeApiKeyPayload) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join(REVOKE_API_KEY_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn drain(&self, payload: DrainPayload) -> GatewayRpcResult<DrainStatus> {
+        let url = self
+            .base_url
+            .join(DRAIN_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn shutdown(&self, payload: ShutdownPayload) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join(SHUTDOWN_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn export_config(
+        &self,
+        payload: ExportConfigPayload,
+    ) -> GatewayRpcResult<EncryptedConfigArchive> {
+        let url = self
+            .base_url
+            .join(EXPORT_CONFIG_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post_idempotent(url, payload).await
+    }
+
+    pub async fn import_config(&self, payload: ImportConfigPayload) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join(IMPORT_CONFIG_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn list_payments(
+        &self,
+        payload: ListPaymentsPayload,
+    ) -> GatewayRpcResult<Vec<PaymentRecord>> {
+        let url = self
+            .base_url
+            .join(LIST_PAYMENTS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post_idempotent(url, payload).await
+    }
+
+    pub async fn export_payments_csv(
+        &self,
+        payload: ListPaymentsPayload,
+    ) -> GatewayRpcResult<String> {
+        let url = self
+            .base_url
+            .join(EXPORT_PAYMENTS_CSV_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post_idempotent(url, payload).await
+    }
+
     async fn call<P: Serialize, T: DeserializeOwned>(
         &self,
         method: Method,
@@ -205,16 +447,40 @@ impl GatewayRpcClient {
                 .json(&payload)
                 .header(reqwest::header::CONTENT_TYPE, "application/json")
         }
-        let response = builder.send().await?;
+        let response = builder.send().await.map_err(GatewayRpcError::from)?;
 
         match response.status() {
-            StatusCode::OK => Ok(response.json().await?),
-            status => Err(GatewayRpcError::BadStatus(status)),
+            StatusCode::OK => response.json().await.map_err(GatewayRpcError::RequestError),
+            status => Err(GatewayRpcError::from(status)),
+        }
+    }
+
+    /// Calls an endpoint that can safely be called more than once, retrying
+    /// up to `MAX_RETRIES` times if the gateway could not be reached
+    async fn call_idempotent<P: Serialize + Clone, T: DeserializeOwned>(
+        &self,
+        method: Method,
+        url: SafeUrl,
+        payload: Option<P>,
+    ) -> Result<T, GatewayRpcError> {
+        let mut retries = 0;
+        loop {
+            match self
+                .call(method.clone(), url.clone(), payload.clone())
+                .await
+            {
+                Err(e) if e.is_transient() && retries < MAX_RETRIES => {
+                    retries += 1;
+                    debug!("Retrying {url} after transient error: {e}");
+                    sleep(RETRY_DELAY * retries).await;
+                }
+                result => return result,
+            }
         }
     }
 
     async fn call_get<T: DeserializeOwned>(&self, url: SafeUrl) -> Result<T, GatewayRpcError> {
-        self.call(Method::GET, url, None::<()>).await
+        self.call_idempotent(Method::GET, url, None::<()>).await
     }
 
     async fn call_post<P: Serialize, T: DeserializeOwned>(
@@ -224,14 +490,107 @@ impl GatewayRpcClient {
     ) -> Result<T, GatewayRpcError> {
         self.call(Method::POST, url, Some(payload)).await
     }
+
+    /// Like `call_post`, for endpoints that only read state or set it to a
+    /// given value
+    async fn call_post_idempotent<P: Serialize + Clone, T: DeserializeOwned>(
+        &self,
+        url: SafeUrl,
+        payload: P,
+    ) -> Result<T, GatewayRpcError> {
+        self.call_idempotent(Method::POST, url, Some(payload)).await
+    }
 }
 
 pub type GatewayRpcResult<T> = Result<T, GatewayRpcError>;
 
 #[derive(Error, Debug)]
 pub enum GatewayRpcError {
+    #[error("The gateway password is missing or wrong")]
+    Unauthorized,
+    #[error("The API key's scope does not include this request")]
+    Forbidden,
+    #[error("The gateway is not connected to the federation")]
+    FederationNotConnected,
+    #[error("Lightning failure, the gateway returned {0}")]
+    LightningFailure(StatusCode),
     #[error("Bad status returned {0}")]
     BadStatus(StatusCode),
+    #[error("Could not reach the gateway: {0}")]
+    Network(reqwest::Error),
     #[error(transparent)]
-    RequestError(#[from] reqwest::Error),
+    RequestError(reqwest::Error),
+}
+
+impl GatewayRpcError {
+    /// Returns the status the gateway responded with, if it responded
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            GatewayRpcError::Unauthorized => Some(StatusCode::UNAUTHORIZED),
+            GatewayRpcError::Forbidden => Some(StatusCode::FORBIDDEN),
+            GatewayRpcError::FederationNotConnected => Some(StatusCode::UNPROCESSABLE_ENTITY),
+            GatewayRpcError::LightningFailure(status) | GatewayRpcError::BadStatus(status) => {
+                Some(*status)
+            }
+            GatewayRpcError::Network(_) | GatewayRpcError::RequestError(_) => None,
+        }
+    }
+
+    /// Whether the request might succeed if it is sent again
+    pub fn is_transient(&self) -> bool {
+        matches!(self, GatewayRpcError::Network(_))
+    }
+}
+
+impl From<StatusCode> for GatewayRpcError {
+    fn from(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => GatewayRpcError::Unauthorized,
+            StatusCode::FORBIDDEN => GatewayRpcError::Forbidden,
+            StatusCode::UNPROCESSABLE_ENTITY => GatewayRpcError::FederationNotConnected,
+            // The gateway responds with `NOT_FOUND` while it is disconnected from its
+            // lightning node
+            StatusCode::BAD_GATEWAY | StatusCode::NOT_FOUND => {
+                GatewayRpcError::LightningFailure(status)
+            }
+            status => GatewayRpcError::BadStatus(status),
+        }
+    }
+}
+
+impl From<reqwest::Error> for GatewayRpcError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_connect() || error.is_timeout() || error.is_request() {
+            GatewayRpcError::Network(error)
+        } else {
+            GatewayRpcError::RequestError(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::GatewayRpcError;
+
+    #[test]
+    fn classifies_gateway_statuses() {
+        assert!(matches!(
+            GatewayRpcError::from(StatusCode::UNAUTHORIZED),
+            GatewayRpcError::Unauthorized
+        ));
+        assert!(matches!(
+            GatewayRpcError::from(StatusCode::UNPROCESSABLE_ENTITY),
+            GatewayRpcError::FederationNotConnected
+        ));
+        assert!(matches!(
+            GatewayRpcError::from(StatusCode::BAD_GATEWAY),
+            GatewayRpcError::LightningFailure(StatusCode::BAD_GATEWAY)
+        ));
+
+        let error = GatewayRpcError::from(StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!error.is_transient());
+    }
 }
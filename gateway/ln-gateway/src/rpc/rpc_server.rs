@@ -1,22 +1,31 @@
-use axum::extract::Request;
+use axum::extract::{Path, Query, Request, State};
 use axum::http::{header, StatusCode};
 use axum::middleware::{self, Next};
 use axum::response::IntoResponse;
-use axum::routing::{get, post};
+use axum::routing::{get, post, MethodRouter};
 use axum::{Extension, Json, Router};
 use axum_macros::debug_handler;
 use bitcoin::consensus::Encodable;
 use bitcoin_hashes::{sha256, Hash};
 use fedimint_core::config::FederationId;
 use fedimint_core::task::TaskGroup;
+use fedimint_core::Amount;
 use fedimint_ln_client::pay::PayInvoicePayload;
 use fedimint_ln_common::gateway_endpoint_constants::{
     ADDRESS_ENDPOINT, BACKUP_ENDPOINT, BALANCE_ENDPOINT, CLOSE_CHANNELS_WITH_PEER_ENDPOINT,
     CONFIGURATION_ENDPOINT, CONNECT_FED_ENDPOINT, CONNECT_TO_PEER_ENDPOINT,
-    CREATE_INVOICE_V2_ENDPOINT, GATEWAY_INFO_ENDPOINT, GATEWAY_INFO_POST_ENDPOINT,
-    GET_FUNDING_ADDRESS_ENDPOINT, GET_GATEWAY_ID_ENDPOINT, LEAVE_FED_ENDPOINT,
-    LIST_ACTIVE_CHANNELS_ENDPOINT, OPEN_CHANNEL_ENDPOINT, PAYMENT_INFO_V2_ENDPOINT,
-    PAY_INVOICE_ENDPOINT, RESTORE_ENDPOINT, SEND_PAYMENT_V2_ENDPOINT, SET_CONFIGURATION_ENDPOINT,
+    CREATE_API_KEY_ENDPOINT, CREATE_INVOICE_V2_ENDPOINT, CREATE_OFFER_ENDPOINT, DRAIN_ENDPOINT,
+    EXPORT_CONFIG_ENDPOINT, EXPORT_PAYMENTS_CSV_ENDPOINT, GATEWAY_INFO_ENDPOINT,
+    GATEWAY_INFO_POST_ENDPOINT, GET_FUNDING_ADDRESS_ENDPOINT, GET_GATEWAY_ID_ENDPOINT,
+    GET_HTLC_FILTER_ENDPOINT, GET_OFFER_ENDPOINT, GET_ONCHAIN_BALANCE_ENDPOINT,
+    IMPORT_CONFIG_ENDPOINT, LEAVE_FED_ENDPOINT, LIST_ACTIVE_CHANNELS_ENDPOINT,
+    LIST_API_KEYS_ENDPOINT, LIST_LNURL_ACCOUNTS_ENDPOINT, LIST_PAYMENTS_ENDPOINT,
+    LIST_SWEEPS_ENDPOINT, LNURLP_CALLBACK_ENDPOINT, LNURLP_CONTRACTS_ENDPOINT, LNURLP_ENDPOINT,
+    OPEN_CHANNEL_ENDPOINT, PAYMENT_INFO_V2_ENDPOINT, PAY_INVOICE_ENDPOINT,
+    PAY_INVOICE_FOR_OPERATOR_ENDPOINT, REBALANCE_ENDPOINT, RESTORE_ENDPOINT,
+    REVOKE_API_KEY_ENDPOINT, SEND_ONCHAIN_ENDPOINT, SEND_PAYMENT_V2_ENDPOINT,
+    SET_CONFIGURATION_ENDPOINT, SET_FEDERATION_LIMITS_ENDPOINT, SET_HTLC_FILTER_ENDPOINT,
+    SET_LNURL_ACCOUNT_ENDPOINT, SET_SWEEP_POLICY_ENDPOINT, SHUTDOWN_ENDPOINT, SWEEP_ENDPOINT,
     WITHDRAW_ENDPOINT,
 };
 use fedimint_lnv2_client::{CreateInvoicePayload, SendPaymentPayload};
@@ -27,10 +36,14 @@ use tower_http::cors::CorsLayer;
 use tracing::{error, info, instrument};
 
 use super::{
-    BackupPayload, BalancePayload, CloseChannelsWithPeerPayload, ConnectFedPayload,
-    ConnectToPeerPayload, DepositAddressPayload, GetFundingAddressPayload, InfoPayload,
-    LeaveFedPayload, OpenChannelPayload, RestorePayload, SetConfigurationPayload, WithdrawPayload,
-    V1_API_ENDPOINT,
+    ApiScope, BackupPayload, BalancePayload, CloseChannelsWithPeerPayload, ConnectFedPayload,
+    ConnectToPeerPayload, CreateApiKeyPayload, CreateOfferPayload, DepositAddressPayload,
+    DrainPayload, ExportConfigPayload, GetFundingAddressPayload, GetOfferPayload,
+    ImportConfigPayload, InfoPayload, LeaveFedPayload, ListPaymentsPayload, ListSweepsPayload,
+    LnurlCallbackParams, OpenChannelPayload, PayInvoiceForOperatorPayload, RebalancePayload,
+    RestorePayload, RevokeApiKeyPayload, SendOnchainPayload, SetConfigurationPayload,
+    SetFederationLimitsPayload, SetHtlcFilterPayload, SetLnurlAccountPayload,
+    SetSweepPolicyPayload, ShutdownPayload, SweepPayload, WithdrawPayload, V1_API_ENDPOINT,
 };
 use crate::rpc::ConfigPayload;
 use crate::{Gateway, GatewayError};
@@ -41,7 +54,8 @@ pub async fn run_webserver(gateway: Gateway, task_group: &mut TaskGroup) -> anyh
     let api_v1 = Router::new()
         .nest(&format!("/{V1_API_ENDPOINT}"), v1_routes.clone())
         // Backwards compatibility: Continue supporting gateway APIs without versioning
-        .merge(v1_routes);
+        .merge(v1_routes)
+        .layer(Extension(task_group.clone()));
 
     let handle = task_group.make_handle();
     let shutdown_rx = handle.make_shutdown_rx().await;
@@ -79,9 +93,10 @@ fn extract_bearer_token(request: &Request) -> Result<String, StatusCode> {
 }
 
 /// Middleware to authenticate an incoming request. Routes that are
-/// authenticated with this middleware always require a Bearer token to be
-/// supplied in the Authorization header.
+/// authenticated with this middleware always require a Bearer token with at
+/// least the given scope to be supplied in the Authorization header.
 async fn auth_middleware(
+    State(scope): State<ApiScope>,
     Extension(gateway): Extension<Gateway>,
     request: Request,
     next: Next,
@@ -95,14 +110,24 @@ async fn auth_middleware(
         .ok_or(StatusCode::NOT_FOUND)?;
     let gateway_hashed_password = gateway_config.hashed_password;
     let password_salt = gateway_config.password_salt;
-    authenticate(gateway_hashed_password, password_salt, request, next).await
+    authenticate(
+        &gateway,
+        scope,
+        gateway_hashed_password,
+        password_salt,
+        request,
+        next,
+    )
+    .await
 }
 
 /// Middleware to authenticate an incoming request. Routes that are
 /// authenticated with this middleware are un-authenticated if the gateway has
 /// not yet been configured. After the gateway is configured, this middleware
-/// enforces that a Bearer token must be supplied in the Authorization header.
+/// enforces that a Bearer token with at least the given scope must be supplied
+/// in the Authorization header.
 async fn auth_after_config_middleware(
+    State(scope): State<ApiScope>,
     Extension(gateway): Extension<Gateway>,
     request: Request,
     next: Next,
@@ -119,36 +144,51 @@ async fn auth_after_config_middleware(
     let gateway_config = gateway_config.expect("Already validated the gateway config is not none");
     let gateway_hashed_password = gateway_config.hashed_password;
     let password_salt = gateway_config.password_salt;
-    authenticate(gateway_hashed_password, password_salt, request, next).await
+    authenticate(
+        &gateway,
+        scope,
+        gateway_hashed_password,
+        password_salt,
+        request,
+        next,
+    )
+    .await
 }
 
-/// Validate that the Bearer token matches the gateway's hashed password
+/// Validate that the Bearer token matches the gateway's hashed password, which
+/// grants every scope, or an API key with at least `scope`
 async fn authenticate(
+    gateway: &Gateway,
+    scope: ApiScope,
     gateway_hashed_password: sha256::Hash,
     password_salt: [u8; 16],
     request: Request,
     next: Next,
 ) -> Result<axum::response::Response, StatusCode> {
     let token = extract_bearer_token(&request)?;
-    let hashed_password = hash_password(token, password_salt);
+    let hashed_password = hash_password(token.clone(), password_salt);
     if gateway_hashed_password == hashed_password {
         return Ok(next.run(request).await);
     }
 
-    Err(StatusCode::UNAUTHORIZED)
+    match gateway.api_key_scope(&token).await {
+        Some(key_scope) if key_scope >= scope => Ok(next.run(request).await),
+        Some(_) => Err(StatusCode::FORBIDDEN),
+        None => Err(StatusCode::UNAUTHORIZED),
+    }
 }
 
 /// Gateway Webserver Routes. The gateway supports three types of routes
 /// - Always Authenticated: these routes always require a Bearer token. Used by
-///   gateway administrators.
+///   gateway administrators and by API keys with the route's scope.
 /// - Authenticated after config: these routes are unauthenticated before
 ///   configuring the gateway to allow the user
 /// to set a password. After setting the password, they become authenticated.
 /// - Un-authenticated: anyone can request these routes. Used by fedimint
-///   clients.
+///   clients, and by payers of lightning addresses if LNURL is enabled.
 fn v1_routes(gateway: Gateway) -> Router {
     // Public routes on gateway webserver
-    let public_routes = Router::new()
+    let mut public_routes = Router::new()
         .route(PAY_INVOICE_ENDPOINT, post(pay_invoice))
         .route(GET_GATEWAY_ID_ENDPOINT, get(get_gateway_id))
         // These routes are for next generation lightning
@@ -156,43 +196,108 @@ fn v1_routes(gateway: Gateway) -> Router {
         .route(SEND_PAYMENT_V2_ENDPOINT, post(send_payment_v2))
         .route(CREATE_INVOICE_V2_ENDPOINT, post(create_invoice_v2));
 
-    // Authenticated, public routes used for gateway administration
-    let always_authenticated_routes = Router::new()
-        .route(BALANCE_ENDPOINT, post(balance))
-        .route(ADDRESS_ENDPOINT, post(address))
-        .route(WITHDRAW_ENDPOINT, post(withdraw))
-        .route(CONNECT_FED_ENDPOINT, post(connect_fed))
-        .route(LEAVE_FED_ENDPOINT, post(leave_fed))
-        .route(BACKUP_ENDPOINT, post(backup))
-        .route(RESTORE_ENDPOINT, post(restore))
-        .route(CONNECT_TO_PEER_ENDPOINT, post(connect_to_peer))
-        .route(GET_FUNDING_ADDRESS_ENDPOINT, post(get_funding_address))
-        .route(OPEN_CHANNEL_ENDPOINT, post(open_channel))
-        .route(
-            CLOSE_CHANNELS_WITH_PEER_ENDPOINT,
-            post(close_channels_with_peer),
-        )
-        .route(LIST_ACTIVE_CHANNELS_ENDPOINT, get(list_active_channels))
-        .layer(middleware::from_fn(auth_middleware));
+    if gateway.lnurl_enabled {
+        public_routes = public_routes
+            .route(LNURLP_ENDPOINT, get(lnurlp))
+            .route(LNURLP_CALLBACK_ENDPOINT, get(lnurlp_callback))
+            .route(LNURLP_CONTRACTS_ENDPOINT, get(lnurlp_contracts));
+    }
+
+    // Authenticated, public routes used for gateway administration, grouped by
+    // the scope an API key needs to access them
+    let mut scoped_routes = Router::new();
+    for scope in [ApiScope::Info, ApiScope::Payments, ApiScope::Admin] {
+        let routes = routes_for_scope(scope)
+            .into_iter()
+            .fold(Router::new(), |routes, (path, method)| {
+                routes.route(path, method)
+            });
+        scoped_routes = scoped_routes
+            .merge(routes.layer(middleware::from_fn_with_state(scope, auth_middleware)));
+    }
 
     // Routes that are un-authenticated before gateway configuration, then become
     // authenticated after a password has been set.
-    let authenticated_after_config_routes = Router::new()
-        .route(SET_CONFIGURATION_ENDPOINT, post(set_configuration))
+    let info_after_config_routes = Router::new()
         .route(CONFIGURATION_ENDPOINT, get(configuration))
         // FIXME: deprecated >= 0.3.0
         .route(GATEWAY_INFO_POST_ENDPOINT, post(handle_post_info))
         .route(GATEWAY_INFO_ENDPOINT, get(info))
-        .layer(middleware::from_fn(auth_after_config_middleware));
+        .layer(middleware::from_fn_with_state(
+            ApiScope::Info,
+            auth_after_config_middleware,
+        ));
+
+    let admin_after_config_routes = Router::new()
+        .route(SET_CONFIGURATION_ENDPOINT, post(set_configuration))
+        .layer(middleware::from_fn_with_state(
+            ApiScope::Admin,
+            auth_after_config_middleware,
+        ));
 
     Router::new()
         .merge(public_routes)
-        .merge(always_authenticated_routes)
-        .merge(authenticated_after_config_routes)
+        .merge(scoped_routes)
+        .merge(info_after_config_routes)
+        .merge(admin_after_config_routes)
         .layer(Extension(gateway))
         .layer(CorsLayer::permissive())
 }
 
+/// Always authenticated routes that require an API key with at least `scope`
+pub(crate) fn routes_for_scope(scope: ApiScope) -> Vec<(&'static str, MethodRouter)> {
+    match scope {
+        ApiScope::Info => vec![
+            (BALANCE_ENDPOINT, post(balance)),
+            (LIST_ACTIVE_CHANNELS_ENDPOINT, get(list_active_channels)),
+            (GET_ONCHAIN_BALANCE_ENDPOINT, get(get_onchain_balance)),
+            (LIST_PAYMENTS_ENDPOINT, post(list_payments)),
+            (EXPORT_PAYMENTS_CSV_ENDPOINT, post(export_payments_csv)),
+            (LIST_SWEEPS_ENDPOINT, post(list_sweeps)),
+            (GET_OFFER_ENDPOINT, post(get_offer)),
+            (LIST_LNURL_ACCOUNTS_ENDPOINT, get(list_lnurl_accounts)),
+            (GET_HTLC_FILTER_ENDPOINT, get(get_htlc_filter)),
+        ],
+        ApiScope::Payments => vec![
+            (ADDRESS_ENDPOINT, post(address)),
+            (GET_FUNDING_ADDRESS_ENDPOINT, post(get_funding_address)),
+        ],
+        ApiScope::Admin => vec![
+            (WITHDRAW_ENDPOINT, post(withdraw)),
+            (
+                PAY_INVOICE_FOR_OPERATOR_ENDPOINT,
+                post(pay_invoice_for_operator),
+            ),
+            // Rebalancing moves funds out of a federation, so it is a spend
+            (REBALANCE_ENDPOINT, post(rebalance)),
+            (CREATE_OFFER_ENDPOINT, post(create_offer)),
+            (CONNECT_FED_ENDPOINT, post(connect_fed)),
+            (LEAVE_FED_ENDPOINT, post(leave_fed)),
+            (BACKUP_ENDPOINT, post(backup)),
+            (RESTORE_ENDPOINT, post(restore)),
+            (CONNECT_TO_PEER_ENDPOINT, post(connect_to_peer)),
+            (OPEN_CHANNEL_ENDPOINT, post(open_channel)),
+            (
+                CLOSE_CHANNELS_WITH_PEER_ENDPOINT,
+                post(close_channels_with_peer),
+            ),
+            (SEND_ONCHAIN_ENDPOINT, post(send_onchain)),
+            (SET_FEDERATION_LIMITS_ENDPOINT, post(set_federation_limits)),
+            (SET_SWEEP_POLICY_ENDPOINT, post(set_sweep_policy)),
+            (SWEEP_ENDPOINT, post(sweep)),
+            (SET_HTLC_FILTER_ENDPOINT, post(set_htlc_filter)),
+            (SET_LNURL_ACCOUNT_ENDPOINT, post(set_lnurl_account)),
+            (EXPORT_CONFIG_ENDPOINT, post(export_config)),
+            (IMPORT_CONFIG_ENDPOINT, post(import_config)),
+            (CREATE_API_KEY_ENDPOINT, post(create_api_key)),
+            (LIST_API_KEYS_ENDPOINT, get(list_api_keys)),
+            (REVOKE_API_KEY_ENDPOINT, post(revoke_api_key)),
+            (DRAIN_ENDPOINT, post(drain)),
+            (SHUTDOWN_ENDPOINT, post(shutdown)),
+        ],
+    }
+}
+
 /// Creates a password hash by appending a 4 byte salt to the plaintext
 /// password.
 pub fn hash_password(plaintext_password: String, salt: [u8; 16]) -> sha256::Hash {
@@ -374,6 +479,194 @@ async fn list_active_channels(
     Ok(Json(json!(channels)))
 }
 
+#[instrument(skip_all, err)]
+async fn get_onchain_balance(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let balance = gateway.handle_get_onchain_balance_msg().await?;
+    Ok(Json(json!(balance)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn send_onchain(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SendOnchainPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let txid = gateway.handle_send_onchain_msg(payload).await?;
+    Ok(Json(json!(txid)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn rebalance(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<RebalancePayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let fee = gateway.handle_rebalance_msg(payload).await?;
+    Ok(Json(json!(fee)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn pay_invoice_for_operator(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<PayInvoiceForOperatorPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let preimage = gateway.handle_pay_invoice_for_operator_msg(payload).await?;
+    Ok(Json(json!(preimage.0.encode_hex::<String>())))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn set_federation_limits(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetFederationLimitsPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let limits = gateway.handle_set_federation_limits_msg(payload).await?;
+    Ok(Json(json!(limits)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn create_offer(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<CreateOfferPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let offer = gateway.handle_create_offer_msg(payload).await?;
+    Ok(Json(json!(offer)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn get_offer(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<GetOfferPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let offer = gateway.handle_get_offer_msg(payload).await?;
+    Ok(Json(json!(offer)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn set_sweep_policy(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetSweepPolicyPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let policy = gateway.handle_set_sweep_policy_msg(payload).await?;
+    Ok(Json(json!(policy)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn sweep(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SweepPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let sweep = gateway.handle_sweep_msg(payload).await?;
+    Ok(Json(json!(sweep)))
+}
+
+#[instrument(skip_all, err)]
+async fn get_htlc_filter(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let filter = gateway.handle_get_htlc_filter_msg();
+    Ok(Json(json!(filter)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn set_htlc_filter(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetHtlcFilterPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let filter = gateway.handle_set_htlc_filter_msg(payload).await?;
+    Ok(Json(json!(filter)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn list_sweeps(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<ListSweepsPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let sweeps = gateway.handle_list_sweeps_msg(payload).await?;
+    Ok(Json(json!(sweeps)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn create_api_key(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<CreateApiKeyPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let key = gateway.handle_create_api_key_msg(payload).await?;
+    Ok(Json(json!(key)))
+}
+
+#[instrument(skip_all, err)]
+async fn list_api_keys(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let keys = gateway.handle_list_api_keys_msg().await;
+    Ok(Json(json!(keys)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn revoke_api_key(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<RevokeApiKeyPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_revoke_api_key_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn drain(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<DrainPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let status = gateway.handle_drain_msg(payload).await?;
+    Ok(Json(json!(status)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn shutdown(
+    Extension(gateway): Extension<Gateway>,
+    Extension(task_group): Extension<TaskGroup>,
+    Json(payload): Json<ShutdownPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_shutdown_msg(payload, &task_group).await?;
+    Ok(Json(json!(())))
+}
+
+// The payloads contain the archive password, so they are not logged
+#[instrument(skip_all, err)]
+async fn export_config(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<ExportConfigPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let archive = gateway.handle_export_config_msg(payload).await?;
+    Ok(Json(json!(archive)))
+}
+
+#[instrument(skip_all, err)]
+async fn import_config(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<ImportConfigPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_import_config_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn list_payments(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<ListPaymentsPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let payments = gateway.handle_list_payments_msg(payload).await?;
+    Ok(Json(json!(payments)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn export_payments_csv(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<ListPaymentsPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let csv = gateway.handle_export_payments_csv_msg(payload).await?;
+    Ok(Json(json!(csv)))
+}
+
 #[instrument(skip_all, err)]
 async fn get_gateway_id(
     Extension(gateway): Extension<Gateway>,
@@ -407,3 +700,55 @@ async fn create_invoice_v2(
         .await
         .map_err(|e| e.to_string())))
 }
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn set_lnurl_account(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetLnurlAccountPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let account = gateway.handle_set_lnurl_account_msg(payload).await?;
+    Ok(Json(json!(account)))
+}
+
+#[instrument(skip_all, err)]
+async fn list_lnurl_accounts(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let accounts = gateway.handle_list_lnurl_accounts_msg().await?;
+    Ok(Json(json!(accounts)))
+}
+
+/// Responds to LNURL requests as defined by LUD-06, which reports errors with
+/// an `ERROR` status instead of the HTTP status code
+fn lnurl_response<T: serde::Serialize>(result: anyhow::Result<T>) -> Json<Value> {
+    match result {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({ "status": "ERROR", "reason": e.to_string() })),
+    }
+}
+
+async fn lnurlp(
+    Extension(gateway): Extension<Gateway>,
+    Path(username): Path<String>,
+) -> Json<Value> {
+    lnurl_response(gateway.lnurl_pay_request(username).await)
+}
+
+async fn lnurlp_callback(
+    Extension(gateway): Extension<Gateway>,
+    Path(username): Path<String>,
+    Query(params): Query<LnurlCallbackParams>,
+) -> Json<Value> {
+    lnurl_response(
+        gateway
+            .lnurl_invoice(username, Amount::from_msats(params.amount))
+            .await,
+    )
+}
+
+async fn lnurlp_contracts(
+    Extension(gateway): Extension<Gateway>,
+    Path(username): Path<String>,
+) -> Json<Value> {
+    lnurl_response(gateway.lnurl_contracts(username).await)
+}
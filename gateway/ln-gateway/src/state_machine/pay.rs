@@ -9,6 +9,7 @@ use fedimint_core::config::FederationId;
 use fedimint_core::core::OperationId;
 use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
 use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::time::duration_since_epoch;
 use fedimint_core::util::Spanned;
 use fedimint_core::{secp256k1, Amount, OutPoint, TransactionId};
 use fedimint_ln_client::api::LnFederationApi;
@@ -26,7 +27,8 @@ use tracing::{debug, error, info, warn, Instrument};
 use super::{GatewayClientContext, GatewayClientStateMachines, GatewayExtReceiveStates};
 use crate::db::{FederationIdKey, PreimageAuthentication};
 use crate::gateway_lnrpc::{PayInvoiceRequest, PayInvoiceResponse};
-use crate::lightning::LightningRpcError;
+use crate::lightning::{LightningRpcError, MAX_PAYMENT_PARTS};
+use crate::rpc::PaymentAttempt;
 use crate::state_machine::GatewayClientModule;
 use crate::{GatewayState, RoutingFees};
 
@@ -179,6 +181,43 @@ pub struct GatewayPayInvoice {
     pub pay_invoice_payload: PayInvoicePayload,
 }
 
+/// Share of the contract's routing fee budget, in percent, that each attempt
+/// to pay an invoice over lightning may spend. Cheap routes are tried first
+/// so the gateway keeps the unspent part of the fee.
+const FEE_BUDGET_SCHEDULE_PERCENT: [u64; 3] = [25, 50, 100];
+
+/// Limits of a single attempt to pay an invoice over lightning
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AttemptLimits {
+    max_fee: Amount,
+    max_delay: u64,
+}
+
+/// Returns the limits of each attempt to pay an invoice within the limits of
+/// the outgoing contract. The delay grows with the fee so later attempts can
+/// consider longer routes. Every attempt may split the payment into up to
+/// [`MAX_PAYMENT_PARTS`] parts in case no single path can carry the amount.
+/// Every part is bounded by the same delay, so all of them time out before the
+/// contract.
+fn attempt_limits(max_fee: Amount, max_delay: u64) -> Vec<AttemptLimits> {
+    let attempts = FEE_BUDGET_SCHEDULE_PERCENT.len() as u64;
+    FEE_BUDGET_SCHEDULE_PERCENT
+        .iter()
+        .zip(1..)
+        .map(|(percent, attempt)| AttemptLimits {
+            max_fee: Amount::from_msats(max_fee.msats * percent / 100),
+            max_delay: max_delay * attempt / attempts,
+        })
+        .collect()
+}
+
+/// Only a payment the lightning node reported as failed is safe to retry.
+/// Other errors, and `PaymentStateUnknown` in particular, might leave the
+/// previous attempt in flight.
+pub(crate) fn is_retryable(result: &Result<PayInvoiceResponse, LightningRpcError>) -> bool {
+    matches!(result, Err(LightningRpcError::FailedPayment { .. }))
+}
+
 impl GatewayPayInvoice {
     fn transitions(
         &self,
@@ -403,30 +442,71 @@ impl GatewayPayInvoice {
             }
         };
 
-        let payment_result = match buy_preimage.payment_data {
-            PaymentData::Invoice(invoice) => {
-                lightning_context
-                    .lnrpc
-                    .pay(PayInvoiceRequest {
-                        invoice: invoice.to_string(),
-                        max_delay,
-                        max_fee_msat: max_fee.msats,
-                        payment_hash: payment_data.payment_hash().to_byte_array().to_vec(),
-                    })
-                    .await
+        let payment_hash = payment_data.payment_hash();
+        let mut payment_result = Err(LightningRpcError::FailedPayment {
+            failure_reason: "No payment attempt was made".to_string(),
+        });
+        for limits in attempt_limits(max_fee, max_delay) {
+            if payment_data.is_expired() {
+                break;
             }
-            PaymentData::PrunedInvoice(invoice) => {
-                lightning_context
-                    .lnrpc
-                    .pay_private(invoice, buy_preimage.max_delay, max_fee)
-                    .await
+
+            payment_result = match &buy_preimage.payment_data {
+                PaymentData::Invoice(invoice) => {
+                    lightning_context
+                        .lnrpc
+                        .pay(PayInvoiceRequest {
+                            invoice: invoice.to_string(),
+                            max_delay: limits.max_delay,
+                            max_fee_msat: limits.max_fee.msats,
+                            payment_hash: payment_hash.to_byte_array().to_vec(),
+                        })
+                        .await
+                }
+                PaymentData::PrunedInvoice(invoice) => {
+                    lightning_context
+                        .lnrpc
+                        .pay_private(
+                            invoice.clone(),
+                            limits.max_delay,
+                            limits.max_fee,
+                            MAX_PAYMENT_PARTS,
+                        )
+                        .await
+                }
+            };
+
+            context
+                .gateway
+                .record_payment_attempt(
+                    payment_hash,
+                    PaymentAttempt {
+                        max_fee: limits.max_fee,
+                        max_delay: limits.max_delay,
+                        error: payment_result.as_ref().err().map(ToString::to_string),
+                        finished_at: duration_since_epoch().as_secs(),
+                    },
+                )
+                .await;
+
+            if !is_retryable(&payment_result) {
+                break;
             }
-        };
+
+            if let Err(error) = &payment_result {
+                info!("Payment attempt with {limits:?} failed for contract {contract:?}: {error}");
+            }
+        }
 
         match payment_result {
             Ok(PayInvoiceResponse { preimage, .. }) => {
                 debug!("Preimage received for contract {contract:?}");
-                let slice: [u8; 32] = preimage.try_into().expect("Failed to parse preimage");
+                let Ok(slice) = <[u8; 32]>::try_from(preimage) else {
+                    let error = LightningRpcError::FailedPayment {
+                        failure_reason: "Preimage is not 32 bytes".to_string(),
+                    };
+                    return Self::gateway_pay_cancel_contract(error, contract, common);
+                };
                 GatewayPayStateMachine {
                     common,
                     state: GatewayPayStates::ClaimOutgoingContract(Box::new(
@@ -924,3 +1004,32 @@ impl GatewayPayCancelContract {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::Amount;
+
+    use super::{attempt_limits, AttemptLimits};
+
+    #[test]
+    fn attempt_limits_stay_within_contract_limits() {
+        let limits = attempt_limits(Amount::from_msats(1_000), 144);
+        assert_eq!(
+            limits,
+            vec![
+                AttemptLimits {
+                    max_fee: Amount::from_msats(250),
+                    max_delay: 48,
+                },
+                AttemptLimits {
+                    max_fee: Amount::from_msats(500),
+                    max_delay: 96,
+                },
+                AttemptLimits {
+                    max_fee: Amount::from_msats(1_000),
+                    max_delay: 144,
+                },
+            ]
+        );
+    }
+}
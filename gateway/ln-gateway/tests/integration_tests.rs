@@ -44,10 +44,10 @@ use fedimint_unknown_common::config::UnknownGenParams;
 use fedimint_unknown_server::UnknownInit;
 use futures::Future;
 use lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescription, Description, RoutingFees};
-use ln_gateway::rpc::rpc_client::{GatewayRpcClient, GatewayRpcError, GatewayRpcResult};
+use ln_gateway::rpc::rpc_client::{GatewayRpcClient, GatewayRpcResult};
 use ln_gateway::rpc::rpc_server::hash_password;
 use ln_gateway::rpc::{
-    BalancePayload, ConnectFedPayload, FederationRoutingFees, LeaveFedPayload,
+    BalancePayload, ConnectFedPayload, FederationRoutingFees, GatewayStatus, LeaveFedPayload,
     SetConfigurationPayload,
 };
 use ln_gateway::state_machine::pay::{
@@ -964,7 +964,7 @@ async fn test_gateway_configuration() -> anyhow::Result<()> {
 
     // Verify that the gateway's state is "Configuring"
     let gw_info = verify_gateway_rpc_success("get_info", || initial_rpc_client.get_info()).await;
-    assert_eq!(gw_info.gateway_state, "Configuring".to_string());
+    assert_eq!(gw_info.gateway_state, GatewayStatus::Configuring);
 
     // Verify that the gateway's fees, and network are `None`
     assert_eq!(gw_info.fees, None);
@@ -1011,7 +1011,10 @@ async fn test_gateway_configuration() -> anyhow::Result<()> {
     let gw_info =
         verify_gateway_rpc_success("get_info", || initial_rpc_client_with_password.get_info())
             .await;
-    assert_eq!(gw_info.gateway_state, "Running".to_string());
+    assert!(matches!(
+        gw_info.gateway_state,
+        GatewayStatus::Connected { .. }
+    ));
     assert_eq!(gw_info.fees, Some(DEFAULT_FEES));
     assert_eq!(gw_info.network, Some(DEFAULT_NETWORK));
 
@@ -1036,7 +1039,10 @@ async fn test_gateway_configuration() -> anyhow::Result<()> {
     let gw_info =
         verify_gateway_rpc_success("get_info", || new_password_rpc_client.get_info()).await;
 
-    assert_eq!(gw_info.gateway_state, "Running".to_string());
+    assert!(matches!(
+        gw_info.gateway_state,
+        GatewayStatus::Connected { .. }
+    ));
     assert_eq!(gw_info.fees, Some(GatewayFee(federation_fee.into()).0));
     assert_eq!(gw_info.network, Some(DEFAULT_NETWORK));
 
@@ -1413,10 +1419,12 @@ where
 {
     match func().await {
         Ok(ret) => ret,
-        Err(GatewayRpcError::RequestError(e)) => panic!("RequestError during {name}: {e:?}"),
-        Err(GatewayRpcError::BadStatus(status)) => {
-            panic!("{name} returned error code {status} when success was expected")
-        }
+        Err(e) => match e.status() {
+            Some(status) => {
+                panic!("{name} returned error code {status} when success was expected")
+            }
+            None => panic!("RequestError during {name}: {e:?}"),
+        },
     }
 }
 
@@ -1430,13 +1438,13 @@ async fn verify_gateway_rpc_failure<Fut, T>(
 {
     match func().await {
         Ok(_) => panic!("{name} returned success, expected {status_code}"),
-        Err(GatewayRpcError::RequestError(e)) => panic!("RequestError during {name}: {e:?}"),
-        Err(GatewayRpcError::BadStatus(status)) => {
-            assert_eq!(
+        Err(e) => match e.status() {
+            Some(status) => assert_eq!(
                 status, status_code,
                 "Unexpected status code returned. Expected: {status_code}, found {status}"
-            )
-        }
+            ),
+            None => panic!("RequestError during {name}: {e:?}"),
+        },
     }
 }
 
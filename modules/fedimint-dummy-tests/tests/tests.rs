@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::bail;
 use fedimint_client::transaction::{ClientInput, ClientOutput, TransactionBuilder};
@@ -7,12 +8,13 @@ use fedimint_core::core::{IntoDynInstance, ModuleKind, OperationId};
 use fedimint_core::db::mem_impl::MemDatabase;
 use fedimint_core::module::ModuleConsensusVersion;
 use fedimint_core::secp256k1::Secp256k1;
-use fedimint_core::{sats, Amount, OutPoint};
+use fedimint_core::{sats, Amount, OutPoint, PeerId};
 use fedimint_dummy_client::states::DummyStateMachine;
 use fedimint_dummy_client::{DummyClientInit, DummyClientModule};
 use fedimint_dummy_common::config::{DummyClientConfig, DummyGenParams};
 use fedimint_dummy_common::{broken_fed_key_pair, DummyInput, DummyOutput, KIND};
 use fedimint_dummy_server::DummyInit;
+use fedimint_testing::faults::{FaultSchedule, NetworkFaults};
 use fedimint_testing::fixtures::Fixtures;
 
 fn fixtures() -> Fixtures {
@@ -39,6 +41,39 @@ async fn can_print_and_send_money() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn federation_tolerates_network_faults() -> anyhow::Result<()> {
+    let faults = NetworkFaults::new(42);
+    faults.set_schedule(FaultSchedule {
+        drop_rate: 0.05,
+        delay_rate: 0.1,
+        max_delay: Duration::from_millis(50),
+        duplicate_rate: 0.05,
+        reorder_rate: 0.05,
+    });
+    faults.isolate(PeerId::from(3));
+
+    let fed = fixtures()
+        .new_fed_builder()
+        .await
+        .num_offline(0)
+        .network_faults(faults.clone())
+        .build()
+        .await;
+    let client = fed.new_client().await;
+    let dummy_module = client.get_first_module::<DummyClientModule>();
+
+    let (_, outpoint) = dummy_module.print_money(sats(1000)).await?;
+    dummy_module.receive_money(outpoint).await?;
+
+    faults.heal();
+
+    let (_, outpoint) = dummy_module.print_money(sats(500)).await?;
+    dummy_module.receive_money(outpoint).await?;
+    assert_eq!(client.get_balance().await, sats(1500));
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn client_ignores_unknown_module() {
     let fed = fixtures().new_default_fed().await;
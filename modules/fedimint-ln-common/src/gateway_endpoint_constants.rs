@@ -6,18 +6,44 @@ pub const BALANCE_ENDPOINT: &str = "/balance";
 pub const CONFIGURATION_ENDPOINT: &str = "/config";
 pub const CONNECT_FED_ENDPOINT: &str = "/connect-fed"; // uses `-` for backwards compatibility
 pub const CONNECT_TO_PEER_ENDPOINT: &str = "/connect_to_peer";
+pub const CREATE_API_KEY_ENDPOINT: &str = "/create_api_key";
 pub const CREATE_INVOICE_V2_ENDPOINT: &str = "/create_invoice";
+pub const CREATE_OFFER_ENDPOINT: &str = "/create_offer";
+pub const DRAIN_ENDPOINT: &str = "/drain";
+pub const EXPORT_CONFIG_ENDPOINT: &str = "/export_config";
+pub const EXPORT_PAYMENTS_CSV_ENDPOINT: &str = "/export_payments_csv";
 pub const GATEWAY_INFO_ENDPOINT: &str = "/info";
 pub const GET_GATEWAY_ID_ENDPOINT: &str = "/id";
 pub const GATEWAY_INFO_POST_ENDPOINT: &str = "/info";
 pub const GET_FUNDING_ADDRESS_ENDPOINT: &str = "/get_funding_address";
+pub const GET_OFFER_ENDPOINT: &str = "/get_offer";
+pub const GET_ONCHAIN_BALANCE_ENDPOINT: &str = "/get_onchain_balance";
+pub const GET_HTLC_FILTER_ENDPOINT: &str = "/get_htlc_filter";
+pub const IMPORT_CONFIG_ENDPOINT: &str = "/import_config";
 pub const LEAVE_FED_ENDPOINT: &str = "/leave-fed"; // uses `-` for backwards compatibility
 pub const LIST_ACTIVE_CHANNELS_ENDPOINT: &str = "/list_active_channels";
+pub const LIST_API_KEYS_ENDPOINT: &str = "/list_api_keys";
+pub const LIST_LNURL_ACCOUNTS_ENDPOINT: &str = "/list_lnurl_accounts";
+pub const LIST_PAYMENTS_ENDPOINT: &str = "/list_payments";
+pub const LIST_SWEEPS_ENDPOINT: &str = "/list_sweeps";
+pub const LNURLP_CALLBACK_ENDPOINT: &str = "/lnurlp/:username/callback";
+pub const LNURLP_CONTRACTS_ENDPOINT: &str = "/lnurlp/:username/contracts";
+pub const LNURLP_ENDPOINT: &str = "/.well-known/lnurlp/:username";
 pub const OPEN_CHANNEL_ENDPOINT: &str = "/open_channel";
 pub const CLOSE_CHANNELS_WITH_PEER_ENDPOINT: &str = "/close_channels_with_peer";
 pub const PAYMENT_INFO_V2_ENDPOINT: &str = "/payment_info";
 pub const PAY_INVOICE_ENDPOINT: &str = "/pay_invoice";
+pub const PAY_INVOICE_FOR_OPERATOR_ENDPOINT: &str = "/pay_invoice_for_operator";
+pub const REBALANCE_ENDPOINT: &str = "/rebalance";
 pub const RESTORE_ENDPOINT: &str = "/restore";
+pub const REVOKE_API_KEY_ENDPOINT: &str = "/revoke_api_key";
+pub const SEND_ONCHAIN_ENDPOINT: &str = "/send_onchain";
 pub const SEND_PAYMENT_V2_ENDPOINT: &str = "/send_payment";
 pub const SET_CONFIGURATION_ENDPOINT: &str = "/set_configuration";
+pub const SET_FEDERATION_LIMITS_ENDPOINT: &str = "/set_federation_limits";
+pub const SET_HTLC_FILTER_ENDPOINT: &str = "/set_htlc_filter";
+pub const SET_LNURL_ACCOUNT_ENDPOINT: &str = "/set_lnurl_account";
+pub const SET_SWEEP_POLICY_ENDPOINT: &str = "/set_sweep_policy";
+pub const SHUTDOWN_ENDPOINT: &str = "/shutdown";
+pub const SWEEP_ENDPOINT: &str = "/sweep";
 pub const WITHDRAW_ENDPOINT: &str = "/withdraw";
@@ -304,6 +304,15 @@ impl ServerModuleInit for LightningInit {
             network: config.network,
         })
     }
+
+    fn local_db_prefixes(&self) -> Vec<u8> {
+        // Our own decryption shares are only proposed and the gateways register
+        // with every guardian via the API
+        vec![
+            DbKeyPrefix::ProposeDecryptionShare as u8,
+            DbKeyPrefix::LightningGateway as u8,
+        ]
+    }
 }
 /// The lightning module implements an account system. It does not have the
 /// privacy guarantees of the e-cash mint module but instead allows for smart
@@ -38,7 +38,7 @@ use lightning_invoice::Bolt11Invoice;
 use secp256k1::{ecdh, KeyPair, PublicKey, Scalar, SecretKey};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tpe::{derive_agg_decryption_key, AggregateDecryptionKey};
+use tpe::{derive_agg_decryption_key, AggregateDecryptionKey, AggregatePublicKey};
 
 use crate::api::LnFederationApi;
 use crate::receive_sm::{ReceiveSMCommon, ReceiveSMState, ReceiveStateMachine};
@@ -287,6 +287,47 @@ fn generate_ephemeral_tweak(static_pk: PublicKey) -> ([u8; 32], PublicKey) {
     (ephemeral_tweak, ephemeral_keypair.public_key())
 }
 
+/// Creates an incoming contract over `amount` that can only be claimed by the
+/// owner of `recipient_static_pk` and is refunded to `refund_pk` after
+/// `expiration`. Returns the contract together with its preimage.
+pub fn create_incoming_contract(
+    tpe_agg_pk: AggregatePublicKey,
+    recipient_static_pk: PublicKey,
+    refund_pk: PublicKey,
+    amount: Amount,
+    expiration: u64,
+) -> (IncomingContract, [u8; 32]) {
+    let (ephemeral_tweak, ephemeral_pk) = generate_ephemeral_tweak(recipient_static_pk);
+
+    let encryption_seed = ephemeral_tweak
+        .consensus_hash::<sha256::Hash>()
+        .to_byte_array();
+
+    let preimage = encryption_seed
+        .consensus_hash::<sha256::Hash>()
+        .to_byte_array();
+
+    let claim_pk = recipient_static_pk
+        .mul_tweak(
+            secp256k1::SECP256K1,
+            &Scalar::from_be_bytes(ephemeral_tweak).expect("Within curve order"),
+        )
+        .expect("Tweak is valid");
+
+    let contract = IncomingContract::new(
+        tpe_agg_pk,
+        encryption_seed,
+        preimage,
+        amount,
+        expiration,
+        claim_pk,
+        refund_pk,
+        ephemeral_pk,
+    );
+
+    (contract, preimage)
+}
+
 impl LightningClientModule {
     pub async fn fetch_payment_info(
         &self,
@@ -333,7 +374,7 @@ impl LightningClientModule {
             .amount_milli_satoshis()
             .ok_or(SendPaymentError::InvoiceMissingAmount)?;
 
-        if invoice.is_expired() {
+        if invoice.would_expire(duration_since_epoch()) {
             return Err(SendPaymentError::InvoiceExpired);
         }
 
@@ -585,16 +626,6 @@ impl LightningClientModule {
         description: Bolt11InvoiceDescription,
         payment_fee_limit: PaymentFee,
     ) -> Result<(IncomingContract, [u8; 32], Bolt11Invoice), FetchInvoiceError> {
-        let (ephemeral_tweak, ephemeral_pk) = generate_ephemeral_tweak(recipient_static_pk);
-
-        let encryption_seed = ephemeral_tweak
-            .consensus_hash::<sha256::Hash>()
-            .to_byte_array();
-
-        let preimage = encryption_seed
-            .consensus_hash::<sha256::Hash>()
-            .to_byte_array();
-
         let payment_info = self
             .fetch_payment_info(gateway_api.clone())
             .await
@@ -613,22 +644,12 @@ impl LightningClientModule {
             .as_secs()
             .saturating_add(expiry_time as u64);
 
-        let claim_pk = recipient_static_pk
-            .mul_tweak(
-                secp256k1::SECP256K1,
-                &Scalar::from_be_bytes(ephemeral_tweak).expect("Within curve order"),
-            )
-            .expect("Tweak is valid");
-
-        let contract = IncomingContract::new(
+        let (contract, preimage) = create_incoming_contract(
             self.cfg.tpe_agg_pk,
-            encryption_seed,
-            preimage,
+            recipient_static_pk,
+            payment_info.public_key,
             contract_amount,
             expiration,
-            claim_pk,
-            payment_info.public_key,
-            ephemeral_pk,
         );
 
         let payload = CreateInvoicePayload {
@@ -275,6 +275,11 @@ impl ServerModuleInit for LightningInit {
             network: config.network,
         })
     }
+
+    fn local_db_prefixes(&self) -> Vec<u8> {
+        // The gateways are added by every guardian via the admin API
+        vec![DbKeyPrefix::Gateway as u8]
+    }
 }
 
 fn dealer_keygen(
@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use fedimint_client::ClientHandle;
 use fedimint_core::config::FederationId;
@@ -162,6 +163,38 @@ async fn refund_unpayable_invoice() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_invoice_once_clock_passed_expiry() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_default_fed().await;
+    let gateway_test = gateway(&fixtures, &fed).await;
+    let gateway_api = gateway_test.gateway.versioned_api.clone();
+
+    let other_ln = FakeLightningTest::new();
+    let invoice = other_ln.invoice(Amount::from_sats(100), Some(3600)).await?;
+
+    let client = fed.new_client().await;
+
+    let (op, outpoint) = client
+        .get_first_module::<DummyClientModule>()
+        .print_money(sats(1000))
+        .await?;
+
+    client.await_primary_module_output(op, outpoint).await?;
+
+    fixtures.clock().advance(Duration::from_secs(3601));
+
+    let send_result = client
+        .get_first_module::<LightningClientModule>()
+        .send(gateway_api, invoice)
+        .await;
+
+    assert_eq!(Err(SendPaymentError::InvoiceExpired), send_result);
+    assert_eq!(client.get_balance().await, sats(1000));
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn can_make_self_payment_exactly_once() -> anyhow::Result<()> {
     let fixtures = fixtures();
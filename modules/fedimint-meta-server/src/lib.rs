@@ -200,6 +200,10 @@ impl ServerModuleInit for MetaInit {
     fn get_database_migrations(&self) -> BTreeMap<DatabaseVersion, ServerMigrationFn> {
         BTreeMap::new()
     }
+
+    fn local_db_prefixes(&self) -> Vec<u8> {
+        vec![DbKeyPrefix::Desired as u8]
+    }
 }
 
 /// Meta module
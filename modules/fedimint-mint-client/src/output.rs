@@ -196,7 +196,7 @@ impl MintOutputStatesCreated {
                         move |peer, outcome| {
                             verify_blind_share(peer, outcome, amount, message, &decoder, &pks)
                         },
-                        global_context.api().all_peers().total(),
+                        global_context.api().all_peers().to_num_peers(),
                     ),
                     AWAIT_OUTPUT_OUTCOME_ENDPOINT.to_owned(),
                     ApiRequestErased::new(common.out_point),
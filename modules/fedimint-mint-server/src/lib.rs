@@ -307,6 +307,11 @@ impl ServerModuleInit for MintInit {
             max_notes_per_denomination: config.max_notes_per_denomination,
         })
     }
+
+    fn local_db_prefixes(&self) -> Vec<u8> {
+        // Backups are uploaded to every guardian via the API
+        vec![DbKeyPrefix::EcashBackup as u8]
+    }
 }
 
 fn dealer_keygen(
@@ -488,6 +493,34 @@ impl ServerModule for Mint {
             .await;
     }
 
+    async fn verify(&self, dbtx: &mut DatabaseTransaction<'_>) -> Vec<String> {
+        let mut redemptions = Amount::from_sats(0);
+        let mut issuances = Amount::from_sats(0);
+
+        dbtx.find_by_prefix(&MintAuditItemKeyPrefix)
+            .await
+            .for_each(|(key, amount)| {
+                match key {
+                    MintAuditItemKey::Issuance(_) | MintAuditItemKey::IssuanceTotal => {
+                        issuances += amount;
+                    }
+                    MintAuditItemKey::Redemption(_) | MintAuditItemKey::RedemptionTotal => {
+                        redemptions += amount;
+                    }
+                }
+                async {}
+            })
+            .await;
+
+        if redemptions > issuances {
+            return vec![format!(
+                "Redeemed {redemptions} in ecash but only issued {issuances}"
+            )];
+        }
+
+        vec![]
+    }
+
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
         vec![
             api_endpoint! {
@@ -16,6 +16,7 @@ use fedimint_mint_client::{
 use fedimint_mint_common::config::{FeeConsensus, MintGenParams, MintGenParamsConsensus};
 use fedimint_mint_server::MintInit;
 use fedimint_testing::fixtures::{Fixtures, TIMEOUT};
+use fedimint_testing::load::{LoadGenerator, LoadMix};
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use tracing::{debug, info};
@@ -174,6 +175,43 @@ async fn sends_ecash_oob_highly_parallel() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn load_generator_reports_ecash_operations() -> anyhow::Result<()> {
+    let fed = fixtures().new_default_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+    for client in [&client1, &client2] {
+        let (op, outpoint) = client
+            .get_first_module::<DummyClientModule>()
+            .print_money(sats(1000))
+            .await?;
+        client.await_primary_module_output(op, outpoint).await?;
+    }
+
+    let mix = LoadMix {
+        spends: 1,
+        reissues: 1,
+        ..LoadMix::default()
+    };
+    let report = LoadGenerator::new(vec![client1, client2], mix)
+        .rate(2.0)
+        .duration(Duration::from_secs(5))
+        .max_in_flight(2)
+        .amount(sats(10))
+        .seed(7)
+        .run()
+        .await?;
+    info!("Load report:\n{report}");
+
+    assert_eq!(report.failed(), 0);
+    assert_eq!(report.succeeded() + report.skipped, 10);
+    for stats in report.operations.values() {
+        assert_eq!(stats.latencies().len() as u64, stats.succeeded);
+        assert!(stats.percentile(50.0) <= stats.percentile(99.0));
+    }
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn backup_encode_decode_roundtrip() -> anyhow::Result<()> {
     // Print notes for client
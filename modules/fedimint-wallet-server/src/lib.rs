@@ -86,6 +86,9 @@ use crate::metrics::WALLET_BLOCK_COUNT;
 
 mod metrics;
 
+/// Finality delay below which deposits on mainnet are at risk of being reorged
+const MIN_MAINNET_FINALITY_DELAY: u32 = 6;
+
 #[derive(Debug, Clone)]
 pub struct WalletInit;
 
@@ -235,6 +238,26 @@ impl ServerModuleInit for WalletInit {
         .into())
     }
 
+    fn params_warnings(&self, params: &Self::Params) -> Vec<String> {
+        let network = params.consensus.network;
+        let finality_delay = params.consensus.finality_delay;
+
+        if network != Network::Bitcoin {
+            return vec![format!(
+                "The federation will run on {network}, not on mainnet"
+            )];
+        }
+
+        if finality_delay < MIN_MAINNET_FINALITY_DELAY {
+            return vec![format!(
+                "A finality delay of {finality_delay} blocks risks accepting deposits that are \
+                 reorged, at least {MIN_MAINNET_FINALITY_DELAY} blocks are recommended"
+            )];
+        }
+
+        vec![]
+    }
+
     fn trusted_dealer_gen(
         &self,
         peers: &[PeerId],
@@ -334,6 +357,11 @@ impl ServerModuleInit for WalletInit {
             default_bitcoin_rpc: config.client_default_bitcoin_rpc,
         })
     }
+
+    fn local_db_prefixes(&self) -> Vec<u8> {
+        // Our own peg-out signatures are only proposed
+        vec![DbKeyPrefix::PegOutTxSigCi as u8]
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
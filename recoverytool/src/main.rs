@@ -226,6 +226,11 @@ async fn main() -> anyhow::Result<()> {
                             .filter_map(|item| match item.item {
                                 ConsensusItem::Transaction(tx) => Some(tx),
                                 ConsensusItem::Module(_) => None,
+                                ConsensusItem::PeerEndpoints(_) => None,
+                                ConsensusItem::TlsCertRotation(_) => None,
+                                ConsensusItem::StateHash(_) => None,
+                                ConsensusItem::UpgradeVote(_) => None,
+                                ConsensusItem::FederationMeta(_) => None,
                                 ConsensusItem::Default { .. } => None,
                             })
                             .collect();